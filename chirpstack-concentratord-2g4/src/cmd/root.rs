@@ -1,20 +1,27 @@
 use std::collections::HashMap;
-use std::sync::mpsc::{Receiver, Sender};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread;
 
 use anyhow::Result;
 use libconcentratord::signals;
 use libconcentratord::signals::Signal;
-use libconcentratord::{commands, events, jitqueue, reset};
+use libconcentratord::{
+    blacklist, commands, drift, events, forwarder, health, history, jitqueue, joinflood, metered,
+    oversize, region, remoteconfig, reset, shadow, soaktest, state, stats, storage, systemd,
+    timesource, timesync, uplinkfilter,
+};
 use libloragw_2g4::hal;
 
 use super::super::{concentrator, config, handler, wrapper};
 
 pub fn run(
     config: &config::Configuration,
+    reset_reason: Option<reset::Reason>,
     stop_send: Sender<Signal>,
     stop_receive: Arc<Receiver<Signal>>,
+    queue: &Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
 ) -> Result<Signal> {
     info!(
         "Starting Concentratord 2g4 (version: {}, docs: {})",
@@ -22,14 +29,139 @@ pub fn run(
         "https://www.chirpstack.io/concentratord/"
     );
 
-    // reset concentrator
-    reset::reset().expect("concentrator reset failed");
+    // setup event socket, this is done first so that the state transitions are published
+    events::bind_socket(
+        &config.concentratord.api.event_bind,
+        config.concentratord.api.event_sequence,
+        events::Compression::from_str(&config.concentratord.api.event_compression)
+            .expect("invalid event_compression"),
+        &hex::decode(&config.concentratord.api.event_encryption_key)
+            .expect("invalid event_encryption_key"),
+    )
+    .expect("bind event socket error");
+    events::set_uplink_filter(
+        events::UplinkMode::from_str(&config.concentratord.api.uplink_mode)
+            .expect("invalid uplink_mode"),
+        config.concentratord.api.uplink_sampling,
+    );
+    if config.concentratord.join_flood.enabled {
+        let conf = joinflood::Configuration {
+            key: joinflood::Key::from_str(&config.concentratord.join_flood.key)
+                .expect("invalid join_flood key"),
+            prefix_bits: config.concentratord.join_flood.prefix_bits,
+            max_join_requests: config.concentratord.join_flood.max_join_requests,
+            interval: config.concentratord.join_flood.interval,
+            limit: config.concentratord.join_flood.limit,
+        };
+        joinflood::validate(&conf).expect("invalid join_flood configuration");
+        joinflood::setup(Some(conf));
+    } else {
+        joinflood::setup(None);
+    }
+    if config.concentratord.blacklist.enabled {
+        let conf = blacklist::Configuration {
+            max_fcnt_gap: config.concentratord.blacklist.max_fcnt_gap,
+            max_malformed: config.concentratord.blacklist.max_malformed,
+            interval: config.concentratord.blacklist.interval,
+            expiry: config.concentratord.blacklist.expiry,
+            limit: config.concentratord.blacklist.limit,
+            max_uplinks: config.concentratord.blacklist.max_uplinks,
+        };
+        blacklist::validate(&conf).expect("invalid blacklist configuration");
+        blacklist::setup(Some(conf));
+    } else {
+        blacklist::setup(None);
+    }
+    drift::setup(drift::Configuration {
+        threshold_ppm: config.concentratord.clock_drift.threshold_ppm,
+        baseline: config.concentratord.clock_drift.baseline,
+    });
+    oversize::setup(oversize::Configuration {
+        action: oversize::Action::from_str(&config.concentratord.oversized_uplinks.action)
+            .expect("invalid oversized_uplinks action"),
+        region: Some(region::Region::ISM2400),
+    });
+    uplinkfilter::setup(uplinkfilter::Configuration {
+        net_id_allow: uplinkfilter::parse_list(&config.concentratord.uplink_filter.net_id_allow)
+            .expect("invalid uplink_filter net_id_allow"),
+        net_id_deny: uplinkfilter::parse_list(&config.concentratord.uplink_filter.net_id_deny)
+            .expect("invalid uplink_filter net_id_deny"),
+        join_eui_allow: uplinkfilter::parse_list(
+            &config.concentratord.uplink_filter.join_eui_allow,
+        )
+        .expect("invalid uplink_filter join_eui_allow"),
+        join_eui_deny: uplinkfilter::parse_list(&config.concentratord.uplink_filter.join_eui_deny)
+            .expect("invalid uplink_filter join_eui_deny"),
+        thresholds: config
+            .concentratord
+            .uplink_filter
+            .thresholds
+            .iter()
+            .map(|(k, v)| {
+                (
+                    uplinkfilter::Modulation::from_str(k)
+                        .expect("invalid uplink_filter thresholds modulation"),
+                    uplinkfilter::Threshold {
+                        min_snr: v.min_snr,
+                        min_rssi: v.min_rssi,
+                    },
+                )
+            })
+            .collect(),
+        chain: uplinkfilter::parse_chain(
+            &config
+                .concentratord
+                .uplink_filter
+                .chain
+                .iter()
+                .map(|v| uplinkfilter::FilterConfig {
+                    name: v.name.clone(),
+                    filter: v.filter.clone(),
+                    action: v.action.clone(),
+                    values: v.values.clone(),
+                    modulation: v.modulation.clone(),
+                    min: v.min,
+                    max: v.max,
+                    window: v.window,
+                })
+                .collect::<Vec<uplinkfilter::FilterConfig>>(),
+        )
+        .expect("invalid uplink_filter chain"),
+        debug: config.concentratord.uplink_filter.debug,
+    });
+    let storage_conf = match config.concentratord.storage.backend.as_ref() {
+        "" => None,
+        _ => Some(storage::Configuration {
+            backend: storage::BackendType::from_str(&config.concentratord.storage.backend)
+                .expect("invalid storage backend"),
+            path: config.concentratord.storage.path.clone(),
+            fsync: storage::FsyncPolicy::from_str(&config.concentratord.storage.fsync)
+                .expect("invalid storage fsync policy"),
+        }),
+    };
+    storage::setup(storage_conf.as_ref()).expect("setup storage error");
+    history::set_size(config.concentratord.downlink_history.size);
+    state::set(state::State::Initializing, "configuring concentrator");
+
+    // The concentrator is kept running on a configuration reload which does not require
+    // re-initializing the concentrator, in which case there is no reset reason.
+    if let Some(reset_reason) = reset_reason {
+        // reset concentrator
+        if let Err(err) = reset::reset(reset_reason) {
+            state::fail(state::Failure::Reset, &err);
+        }
 
-    // setup concentrator
-    concentrator::board_setconf(&config)?;
-    concentrator::rx_setconf(&config)?;
-    concentrator::tx_setconf(&config)?;
-    concentrator::start()?;
+        // setup concentrator
+        if let Err(err) = setup_concentrator(&config) {
+            state::fail(state::Failure::Configuration, &err);
+        }
+        state::set(state::State::Calibrating, "starting concentrator");
+        if let Err(err) = concentrator::start() {
+            state::fail(state::Failure::Start, &err);
+        }
+    } else {
+        info!("Keeping the concentrator running");
+    }
 
     // setup static location
     handler::gps::set_static_gps_coords(
@@ -39,19 +171,28 @@ pub fn run(
     );
 
     // get concentrator eui
-    let gateway_id = concentrator::get_eui().unwrap();
+    let gateway_id =
+        concentrator::get_eui().unwrap_or_else(|err| state::fail(state::Failure::GatewayId, &err));
+    state::set_gateway_id(&gateway_id);
 
     info!(
         "Gateway ID retrieved, gateway_id: {:x?}",
         hex::encode(gateway_id)
     );
 
-    // setup jit queue
-    let queue: jitqueue::Queue<wrapper::TxPacket> = jitqueue::Queue::new(32);
-    let queue = Arc::new(Mutex::new(queue));
+    // setup jit queue, the queued downlinks are kept across a configuration reload which does
+    // not require re-initializing the concentrator
+    let strategy = jitqueue::Strategy::from_str(&config.concentratord.jit_scheduler)
+        .expect("invalid jit_scheduler");
+    {
+        let mut queue = queue.lock().unwrap();
+        if reset_reason.is_some() {
+            queue.reset();
+        }
+        queue.set_strategy(strategy);
+    }
 
-    // setup zeromq
-    events::bind_socket(&config.concentratord.api.event_bind).expect("bind event socket error");
+    // setup command socket
     let rep_sock = commands::get_socket(&config.concentratord.api.command_bind)
         .expect("bind command socket error");
 
@@ -69,38 +210,68 @@ pub fn run(
         }
     }));
 
+    // The GPS timed TX offset is not measured, as there is no GPS time reference (see gps).
+    stats::set_metadata("gps_tx_offset_supported", "false");
+
     // jit thread
     threads.push(thread::spawn({
+        let gateway_id = gateway_id.clone();
         let queue = Arc::clone(&queue);
-        let antenna_gain = config.gateway.antenna_gain;
+        let antenna_gain = config.gateway.get_effective_antenna_gain();
+        let stop_receive = signal_pool.new_receiver();
+
+        move || {
+            handler::jit::jit_loop(&gateway_id, queue, antenna_gain, stop_receive);
+        }
+    }));
+
+    // tx test thread
+    let (txtest_send, txtest_receive) = mpsc::channel();
+    threads.push(thread::spawn({
+        let gateway_id = gateway_id.clone();
+        let antenna_gain = config.gateway.get_effective_antenna_gain();
         let stop_receive = signal_pool.new_receiver();
 
         move || {
-            handler::jit::jit_loop(queue, antenna_gain, stop_receive);
+            handler::txtest::txtest_loop(&gateway_id, antenna_gain, txtest_receive, stop_receive);
         }
     }));
 
     // command thread
     threads.push(thread::spawn({
-        let vendor_config = config.gateway.model_config.clone();
+        let queue = Arc::clone(&queue);
+        let config = config.clone();
         let gateway_id = gateway_id.clone();
         let stop_receive = signal_pool.new_receiver();
         let stop_send = stop_send.clone();
-        let lorawan_public = config.gateway.lorawan_public;
 
         move || {
             handler::command::handle_loop(
-                lorawan_public,
-                &vendor_config,
+                &config,
                 &gateway_id,
                 queue,
                 rep_sock,
                 stop_receive,
                 stop_send,
+                txtest_send,
             );
         }
     }));
 
+    // beacon thread
+    if !config.gateway.beacon.frequencies.is_empty() {
+        threads.push(thread::spawn({
+            let beacon_config = config.gateway.beacon.clone();
+            let lorawan_public = config.gateway.lorawan_public;
+            let queue = Arc::clone(&queue);
+            let stop_receive = signal_pool.new_receiver();
+
+            move || {
+                handler::beacon::beacon_loop(&beacon_config, lorawan_public, queue, stop_receive);
+            }
+        }));
+    }
+
     // stats thead
     threads.push(thread::spawn({
         let gateway_id = gateway_id.clone();
@@ -116,21 +287,240 @@ pub fn run(
             config::VERSION.to_string(),
         );
         metadata.insert("model".to_string(), config.gateway.model.clone());
+        metadata.insert(
+            "antenna_gain".to_string(),
+            config.gateway.antenna_gain.to_string(),
+        );
+        metadata.insert(
+            "cable_loss".to_string(),
+            config.gateway.cable_loss.to_string(),
+        );
         metadata.insert("hal_version".to_string(), hal::version_info());
 
         move || {
-            handler::stats::stats_loop(&gateway_id, &stats_interval, stop_receive, &metadata);
+            handler::stats::stats_loop(&gateway_id, &stats_interval, stop_receive, metadata);
         }
     }));
 
+    if !config.forwarder.udp.server.is_empty() {
+        // udp packet-forwarder thread
+        let conf = forwarder::Configuration {
+            server: config.forwarder.udp.server.clone(),
+            port_up: config.forwarder.udp.port_up,
+            port_down: config.forwarder.udp.port_down,
+            keepalive_interval: config.forwarder.udp.keepalive_interval,
+        };
+        let gateway_id = gateway_id.clone();
+
+        threads.push(thread::spawn({
+            let config = config.clone();
+            let queue = Arc::clone(&queue);
+            let stop_receive = signal_pool.new_receiver();
+
+            move || {
+                let handle_downlink = |pl: &chirpstack_api::gw::DownlinkFrame| {
+                    handler::command::enqueue_downlink(
+                        config.gateway.lorawan_public,
+                        &config.gateway.model_config,
+                        &gateway_id,
+                        &queue,
+                        pl,
+                    )
+                };
+                forwarder::pull_loop(&conf, &gateway_id, handle_downlink, stop_receive);
+            }
+        }));
+    } else {
+        forwarder::close();
+    }
+
+    if config.gateway.soak_test.enabled {
+        // downlink soak test thread
+        let soak_test = &config.gateway.soak_test;
+        let conf = soaktest::Configuration {
+            interval: soak_test.interval,
+            payload_size: soak_test.payload_size,
+            tx_power: soak_test.tx_power,
+            frequencies: match soak_test.frequencies.is_empty() {
+                true => config
+                    .gateway
+                    .concentrator
+                    .channels
+                    .iter()
+                    .map(|v| v.frequency)
+                    .filter(|v| *v != 0)
+                    .collect(),
+                false => soak_test.frequencies.clone(),
+            },
+            bandwidth: soak_test.bandwidth,
+            spreading_factor: soak_test.spreading_factor,
+        };
+        soaktest::validate(&conf).expect("invalid soak_test configuration");
+
+        threads.push(thread::spawn({
+            let config = config.clone();
+            let gateway_id = gateway_id.clone();
+            let queue = Arc::clone(&queue);
+            let stop_receive = signal_pool.new_receiver();
+
+            move || {
+                let handle_downlink = |pl: &chirpstack_api::gw::DownlinkFrame| {
+                    handler::command::enqueue_downlink(
+                        config.gateway.lorawan_public,
+                        &config.gateway.model_config,
+                        &gateway_id,
+                        &queue,
+                        pl,
+                    )
+                };
+                soaktest::soak_loop(&conf, &gateway_id, handle_downlink, stop_receive);
+            }
+        }));
+    }
+
+    if !config.concentratord.remote_config.url.is_empty() {
+        // remote configuration thread
+        threads.push(thread::spawn({
+            let conf = remoteconfig::Configuration {
+                url: config.concentratord.remote_config.url.clone(),
+                public_key: hex::decode(&config.concentratord.remote_config.public_key)
+                    .expect("invalid remote_config public_key"),
+                interval: config.concentratord.remote_config.interval,
+            };
+            let gateway_id = gateway_id.clone();
+            let stop_receive = signal_pool.new_receiver();
+            let stop_send = stop_send.clone();
+
+            move || {
+                remoteconfig::fetch_loop(&conf, &gateway_id, stop_send, stop_receive);
+            }
+        }));
+    }
+
+    if !config.concentratord.metered.file.is_empty() || config.concentratord.metered.network_manager
+    {
+        // metered backhaul monitor thread
+        threads.push(thread::spawn({
+            let conf = metered::Configuration {
+                file: config.concentratord.metered.file.clone(),
+                network_manager: config.concentratord.metered.network_manager,
+                interval: config.concentratord.metered.interval,
+            };
+            let stop_receive = signal_pool.new_receiver();
+
+            move || {
+                metered::monitor_loop(&conf, stop_receive);
+            }
+        }));
+    }
+
+    if config.concentratord.health.enabled {
+        // concentrator health monitor thread
+        threads.push(thread::spawn({
+            let conf = health::Configuration {
+                max_hal_errors: config.concentratord.health.max_hal_errors,
+                rx_timeout: config.concentratord.health.rx_timeout,
+                counter_stall_timeout: config.concentratord.health.counter_stall_timeout,
+            };
+            let stop_receive = signal_pool.new_receiver();
+            let stop_send = stop_send.clone();
+
+            move || {
+                health::monitor_loop(&conf, hal::get_instcnt, stop_send, stop_receive);
+            }
+        }));
+    }
+
+    if config.concentratord.time_sync.enabled {
+        // use the host clock, corrected by the network server
+        timesource::set(Arc::new(timesync::NetworkClock));
+
+        // time-sync request thread
+        threads.push(thread::spawn({
+            let conf = timesync::Configuration {
+                interval: config.concentratord.time_sync.interval,
+                max_rtt: config.concentratord.time_sync.max_rtt,
+            };
+            let gateway_id = gateway_id.clone();
+            let stop_receive = signal_pool.new_receiver();
+
+            move || {
+                timesync::request_loop(&conf, &gateway_id, hal::get_instcnt, stop_receive);
+            }
+        }));
+    }
+
+    if !config.concentratord.shadow.path.is_empty() {
+        // shadow state file thread
+        threads.push(thread::spawn({
+            let conf = shadow::Configuration {
+                path: config.concentratord.shadow.path.clone(),
+                interval: config.concentratord.shadow.interval,
+                config_hash: config.concentratord.config_hash.clone(),
+                config_version: config.gateway.config_version.clone(),
+            };
+            let queue = Arc::clone(&queue);
+            let stop_receive = signal_pool.new_receiver();
+
+            move || {
+                let status = || handler::command::get_status(&queue);
+                shadow::shadow_loop(&conf, status, stop_receive);
+            }
+        }));
+    }
+
+    state::set(state::State::Running, "concentrator started");
+    systemd::ready();
+    if reset_reason == Some(reset::Reason::Recovery) {
+        health::send_restarted();
+    }
+
     let stop_signal = stop_receive.recv().unwrap();
+    state::set(
+        state::State::Resetting,
+        match stop_signal {
+            Signal::Stop => "stop signal received",
+            Signal::Configuration(_) => "configuration update",
+            Signal::Reload => "configuration reload",
+            Signal::Restart => "health check failed",
+        },
+    );
     signal_pool.send_signal(stop_signal.clone());
 
     for t in threads {
         t.join().unwrap();
     }
 
-    concentrator::stop()?;
+    // preserve the pending downlinks when the concentrator is re-initialized. On a
+    // configuration reload, these are saved by the caller when the concentrator must be
+    // re-initialized.
+    if let Signal::Configuration(_) | Signal::Restart = stop_signal {
+        handler::jit::save_pending(queue);
+    }
+
+    // On a configuration reload, the concentrator is stopped by the caller if required. On a
+    // restart, the concentrator might be hanging such that stopping fails, in which case it is
+    // recovered by the reset.
+    match stop_signal {
+        Signal::Reload => {}
+        Signal::Restart => {
+            if let Err(err) = concentrator::stop() {
+                warn!(
+                    "Stop concentrator error, continuing with reset, error: {}",
+                    err
+                );
+            }
+        }
+        _ => concentrator::stop()?,
+    }
 
     Ok(stop_signal)
 }
+
+fn setup_concentrator(config: &config::Configuration) -> Result<()> {
+    concentrator::board_setconf(config)?;
+    concentrator::rx_setconf(config)?;
+    concentrator::tx_setconf(config)?;
+
+    Ok(())
+}
@@ -1,4 +1,8 @@
+use std::collections::HashMap;
 use std::fs;
+use std::str::FromStr;
+
+use libconcentratord::{reset, shadow};
 
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
@@ -7,53 +11,547 @@ pub mod vendor;
 
 pub const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Default, Serialize, Deserialize, Clone)]
 #[serde(default = "example_configuration")]
 pub struct Configuration {
     pub concentratord: Concentratord,
     pub gateway: Gateway,
+    #[serde(default)]
+    pub forwarder: Forwarder,
+    #[serde(default)]
+    pub metrics: Metrics,
+    #[serde(default)]
+    pub logging: Logging,
 }
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Forwarder {
+    pub udp: ForwarderUdp,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ForwarderUdp {
+    pub server: String,
+    pub port_up: u16,
+    pub port_down: u16,
+    #[serde(with = "humantime_serde")]
+    pub keepalive_interval: Duration,
+}
+
+impl Default for ForwarderUdp {
+    fn default() -> Self {
+        ForwarderUdp {
+            server: "".to_string(),
+            port_up: 1700,
+            port_down: 1700,
+            keepalive_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
 pub struct Concentratord {
     pub log_level: String,
     #[serde(default)]
     pub log_to_syslog: bool,
+    #[serde(default)]
+    pub systemd_notify: bool,
     #[serde(with = "humantime_serde")]
     pub stats_interval: Duration,
+    #[serde(default)]
+    pub jit_scheduler: String,
     pub api: API,
+    #[serde(default)]
+    pub remote_config: RemoteConfig,
+    #[serde(default)]
+    pub audit_log: AuditLog,
+    #[serde(default)]
+    pub capture: Capture,
+    #[serde(default)]
+    pub stats_metadata: StatsMetadata,
+    #[serde(default)]
+    pub latency_probe: LatencyProbe,
+    #[serde(default)]
+    pub metered: Metered,
+    #[serde(default)]
+    pub health: Health,
+    #[serde(default)]
+    pub clock_drift: ClockDrift,
+    #[serde(default)]
+    pub time_sync: TimeSync,
+    #[serde(default)]
+    pub shadow: Shadow,
+    #[serde(default)]
+    pub join_flood: JoinFlood,
+    #[serde(default)]
+    pub blacklist: Blacklist,
+    #[serde(default)]
+    pub oversized_uplinks: OversizedUplinks,
+    #[serde(default)]
+    pub uplink_filter: UplinkFilter,
+    #[serde(default)]
+    pub storage: Storage,
+    #[serde(default)]
+    pub downlink_history: DownlinkHistory,
+    #[serde(default)]
+    pub diag_bundle: DiagBundle,
+    // Hash of the configuration file(s), this is set when loading the configuration.
+    #[serde(skip)]
+    pub config_hash: String,
 }
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Default, Serialize, Deserialize, Clone)]
 pub struct API {
     pub event_bind: String,
     pub command_bind: String,
+    #[serde(default)]
+    pub event_sequence: bool,
+    #[serde(default)]
+    pub event_compression: String,
+    #[serde(default)]
+    pub event_encryption_key: String,
+    #[serde(default)]
+    pub curve_secret_key: String,
+    #[serde(default)]
+    pub curve_client_keys: Vec<String>,
+    #[serde(default)]
+    pub allowed_commands: Vec<String>,
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default)]
+    pub log_event_level: String,
+    #[serde(default)]
+    pub uplink_mode: String,
+    #[serde(default)]
+    pub uplink_sampling: u64,
+    #[serde(default)]
+    pub rate_limits: HashMap<String, RateLimit>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RateLimit {
+    pub rate: f64,
+    pub burst: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct RemoteConfig {
+    pub url: String,
+    pub public_key: String,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+}
+
+impl Default for RemoteConfig {
+    fn default() -> Self {
+        RemoteConfig {
+            url: "".to_string(),
+            public_key: "".to_string(),
+            interval: Duration::from_secs(60 * 60),
+        }
+    }
 }
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct AuditLog {
+    pub path: String,
+    pub max_size: u64,
+    pub max_files: usize,
+}
+
+impl Default for AuditLog {
+    fn default() -> Self {
+        AuditLog {
+            path: "".to_string(),
+            max_size: 10 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Capture {
+    pub path: String,
+    pub max_size: u64,
+    pub max_files: usize,
+}
+
+impl Default for Capture {
+    fn default() -> Self {
+        Capture {
+            path: "".to_string(),
+            max_size: 10 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct StatsMetadata {
+    pub commands: HashMap<String, Vec<String>>,
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+}
+
+impl Default for StatsMetadata {
+    fn default() -> Self {
+        StatsMetadata {
+            commands: HashMap::new(),
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct LatencyProbe {
+    pub method: String,
+    pub target: String,
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+}
+
+impl Default for LatencyProbe {
+    fn default() -> Self {
+        LatencyProbe {
+            method: "TCP".to_string(),
+            target: "".to_string(),
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Metered {
+    pub file: String,
+    pub network_manager: bool,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+}
+
+impl Default for Metered {
+    fn default() -> Self {
+        Metered {
+            file: "".to_string(),
+            network_manager: false,
+            interval: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Health {
+    pub enabled: bool,
+    pub max_hal_errors: u32,
+    #[serde(with = "humantime_serde")]
+    pub rx_timeout: Duration,
+    #[serde(with = "humantime_serde")]
+    pub counter_stall_timeout: Duration,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Health {
+            enabled: false,
+            max_hal_errors: 10,
+            rx_timeout: Duration::ZERO,
+            counter_stall_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ClockDrift {
+    pub threshold_ppm: f64,
+    #[serde(with = "humantime_serde")]
+    pub baseline: Duration,
+}
+
+impl Default for ClockDrift {
+    fn default() -> Self {
+        ClockDrift {
+            threshold_ppm: 10.0,
+            baseline: Duration::from_secs(3600),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct TimeSync {
+    pub enabled: bool,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    #[serde(with = "humantime_serde")]
+    pub max_rtt: Duration,
+}
+
+impl Default for TimeSync {
+    fn default() -> Self {
+        TimeSync {
+            enabled: false,
+            interval: Duration::from_secs(600),
+            max_rtt: Duration::from_secs(2),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Shadow {
+    pub path: String,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+}
+
+impl Default for Shadow {
+    fn default() -> Self {
+        Shadow {
+            path: "".to_string(),
+            interval: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct JoinFlood {
+    pub enabled: bool,
+    pub key: String,
+    pub prefix_bits: u32,
+    pub max_join_requests: u32,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    pub limit: bool,
+}
+
+impl Default for JoinFlood {
+    fn default() -> Self {
+        JoinFlood {
+            enabled: false,
+            key: "DEV_EUI".to_string(),
+            prefix_bits: 64,
+            max_join_requests: 10,
+            interval: Duration::from_secs(60),
+            limit: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Blacklist {
+    pub enabled: bool,
+    pub max_fcnt_gap: u32,
+    pub max_malformed: u32,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    #[serde(with = "humantime_serde")]
+    pub expiry: Duration,
+    pub limit: bool,
+    pub max_uplinks: u32,
+}
+
+impl Default for Blacklist {
+    fn default() -> Self {
+        Blacklist {
+            enabled: false,
+            max_fcnt_gap: 16384,
+            max_malformed: 10,
+            interval: Duration::from_secs(600),
+            expiry: Duration::from_secs(3600),
+            limit: false,
+            max_uplinks: 0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct OversizedUplinks {
+    pub action: String,
+}
+
+impl Default for OversizedUplinks {
+    fn default() -> Self {
+        OversizedUplinks {
+            action: "FORWARD".to_string(),
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct UplinkFilter {
+    pub net_id_allow: Vec<String>,
+    pub net_id_deny: Vec<String>,
+    pub join_eui_allow: Vec<String>,
+    pub join_eui_deny: Vec<String>,
+    pub thresholds: HashMap<String, UplinkThreshold>,
+    pub chain: Vec<UplinkFilterRule>,
+    pub debug: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UplinkThreshold {
+    pub min_snr: f32,
+    pub min_rssi: i32,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct UplinkFilterRule {
+    pub name: String,
+    pub filter: String,
+    pub action: String,
+    pub values: Vec<String>,
+    pub modulation: String,
+    pub min: f64,
+    pub max: f64,
+    #[serde(with = "humantime_serde")]
+    pub window: Duration,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Storage {
+    pub backend: String,
+    pub path: String,
+    pub fsync: String,
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Storage {
+            backend: "".to_string(),
+            path: "".to_string(),
+            fsync: "NEVER".to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct DownlinkHistory {
+    pub size: usize,
+}
+
+impl Default for DownlinkHistory {
+    fn default() -> Self {
+        DownlinkHistory { size: 256 }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct DiagBundle {
+    pub path: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Gpio {
+    pub shared: bool,
+    #[serde(with = "humantime_serde")]
+    pub busy_timeout: Duration,
+}
+
+impl Default for Gpio {
+    fn default() -> Self {
+        Gpio {
+            shared: false,
+            busy_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
 pub struct Gateway {
     #[serde(default)]
     pub antenna_gain: i8,
     #[serde(default)]
+    pub cable_loss: i8,
+    #[serde(default)]
     pub lorawan_public: bool,
     pub model: String,
     #[serde(default)]
     pub model_flags: Vec<String>,
+    #[serde(default)]
+    pub shutdown_commands: Vec<Vec<String>>,
+    #[serde(default)]
+    pub reset_sequence: Vec<String>,
+    #[serde(default)]
+    pub gpio: Gpio,
     pub concentrator: Concentrator,
     #[serde(default)]
+    pub beacon: Beacon,
+    #[serde(default)]
     pub location: Location,
+    #[serde(default)]
+    pub status_led: StatusLed,
+    #[serde(default)]
+    pub first_uplink: FirstUplink,
+    #[serde(default)]
+    pub soak_test: SoakTest,
     #[serde(skip)]
     pub model_config: vendor::Configuration,
     #[serde(skip)]
     pub config_version: String,
 }
 
-#[derive(Default, Serialize, Deserialize, Debug, PartialEq)]
+impl Gateway {
+    /// Returns the antenna gain minus the cable loss (dB). This is used to convert between
+    /// the EIRP of the downlink and the conducted power of the TX gain table.
+    pub fn get_effective_antenna_gain(&self) -> i8 {
+        self.antenna_gain - self.cable_loss
+    }
+
+    /// Returns the shutdown commands as (command, args).
+    pub fn get_shutdown_commands(&self) -> Option<Vec<(String, Vec<String>)>> {
+        if self.shutdown_commands.is_empty() {
+            return None;
+        }
+
+        Some(
+            self.shutdown_commands
+                .iter()
+                .filter(|v| !v.is_empty())
+                .map(|v| (v[0].clone(), v[1..].to_vec()))
+                .collect(),
+        )
+    }
+
+    /// Returns the reset sequence. The configured sequence overrides the sequence of the model,
+    /// when neither is set the default sequence is used (see reset::Configuration).
+    pub fn get_reset_sequence(&self) -> Option<Vec<reset::Step>> {
+        if self.reset_sequence.is_empty() {
+            return self.model_config.reset_sequence.clone();
+        }
+
+        Some(
+            self.reset_sequence
+                .iter()
+                .map(|v| reset::Step::from_str(v).expect("invalid reset_sequence step"))
+                .collect(),
+        )
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Concentrator {
     pub channels: [Channel; 3],
 }
 
-#[derive(Default, Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Default, Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Channel {
     pub frequency: u32,
     pub bandwidth: u32,
@@ -62,13 +560,126 @@ pub struct Channel {
     pub rssi_offset: f32,
 }
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Beacon {
+    pub compulsory_rfu_size: usize,
+    pub optional_rfu_size: usize,
+    pub frequencies: Vec<u32>,
+    pub spreading_factor: u32,
+    pub bandwidth: u32,
+    pub code_rate: String,
+    pub tx_power: u32,
+}
+
+impl Default for Beacon {
+    fn default() -> Self {
+        Beacon {
+            compulsory_rfu_size: 2,
+            optional_rfu_size: 0,
+            frequencies: vec![],
+            spreading_factor: 12,
+            bandwidth: 812000,
+            code_rate: "4/8LI".to_string(),
+            tx_power: 10,
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct StatusLed {
+    pub chip: String,
+    pub line: u32,
+    pub led: String,
+    pub active_low: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct FirstUplink {
+    pub chip: String,
+    pub line: u32,
+    pub active_low: bool,
+    #[serde(with = "humantime_serde")]
+    pub duration: Duration,
+    pub command: Vec<String>,
+}
+
+impl Default for FirstUplink {
+    fn default() -> Self {
+        FirstUplink {
+            chip: "".to_string(),
+            line: 0,
+            active_low: false,
+            duration: Duration::from_secs(1),
+            command: Vec::new(),
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
 pub struct Location {
     pub latitude: f64,
     pub longitude: f64,
     pub altitude: i16,
 }
 
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SoakTest {
+    pub enabled: bool,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    pub payload_size: usize,
+    pub tx_power: i32,
+    pub frequencies: Vec<u32>,
+    pub bandwidth: u32,
+    pub spreading_factor: u32,
+}
+
+impl Default for SoakTest {
+    fn default() -> Self {
+        SoakTest {
+            enabled: false,
+            interval: Duration::from_secs(10),
+            payload_size: 51,
+            tx_power: 14,
+            frequencies: Vec::new(),
+            bandwidth: 812000,
+            spreading_factor: 7,
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Metrics {
+    pub bind: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Logging {
+    pub output: String,
+    pub format: String,
+    pub path: String,
+    pub max_size: u64,
+    pub max_files: usize,
+}
+
+impl Default for Logging {
+    fn default() -> Self {
+        Logging {
+            output: "".to_string(),
+            format: "TEXT".to_string(),
+            path: "".to_string(),
+            max_size: 10 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
+}
+
 fn example_configuration() -> Configuration {
     Configuration {
         concentratord: Concentratord {
@@ -77,6 +688,7 @@ fn example_configuration() -> Configuration {
             api: API {
                 event_bind: "ipc:///tmp/concentratord_event".to_string(),
                 command_bind: "ipc:///tmp/concentratord_command".to_string(),
+                ..Default::default()
             },
             ..Default::default()
         },
@@ -107,6 +719,9 @@ fn example_configuration() -> Configuration {
             },
             ..Default::default()
         },
+        forwarder: Default::default(),
+        metrics: Default::default(),
+        logging: Default::default(),
     }
 }
 
@@ -118,6 +733,7 @@ pub fn get(filenames: Vec<String>) -> Configuration {
     }
 
     let mut config: Configuration = toml::from_str(&content).expect("Error parsing config file");
+    config.concentratord.config_hash = shadow::config_hash(&content);
 
     // get model configuration
     config.gateway.model_config = match config.gateway.model.as_ref() {
@@ -127,6 +743,33 @@ pub fn get(filenames: Vec<String>) -> Configuration {
     };
 
     debug!("Antenna gain {} dBi", config.gateway.antenna_gain);
+    debug!("Cable loss {} dB", config.gateway.cable_loss);
 
     return config;
 }
+
+/// Returns true when the concentrator must be re-initialized to apply the new configuration,
+/// e.g. on a configuration reload. The gateway settings which are applied by the handlers
+/// (e.g. the location and TX power) do not require a restart of the concentrator.
+pub fn requires_restart(current: &Configuration, new: &Configuration) -> bool {
+    match (radio_config(&current.gateway), radio_config(&new.gateway)) {
+        (Some(current), Some(new)) => current != new,
+        _ => true,
+    }
+}
+
+// Returns the gateway configuration, without the settings that are applied by the handlers.
+fn radio_config(gateway: &Gateway) -> Option<toml::Value> {
+    let mut gateway = gateway.clone();
+    gateway.antenna_gain = 0;
+    gateway.cable_loss = 0;
+    gateway.shutdown_commands = Vec::new();
+    gateway.reset_sequence = Vec::new();
+    gateway.beacon = Default::default();
+    gateway.location = Default::default();
+    gateway.status_led = Default::default();
+    gateway.first_uplink = Default::default();
+    gateway.soak_test = Default::default();
+
+    toml::Value::try_from(gateway).ok()
+}
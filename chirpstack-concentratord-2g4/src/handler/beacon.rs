@@ -0,0 +1,339 @@
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+
+use anyhow::Result;
+use libconcentratord::signals::Signal;
+use libconcentratord::{api, jitqueue, timesource};
+use libloragw_2g4::hal;
+use rand::Rng;
+
+use super::super::{config, wrapper};
+
+const PERIOD: u64 = 128;
+const MARGIN: Duration = Duration::from_secs(5);
+const GW_SPECIFIC_SIZE: usize = 7;
+
+// Seconds between the Unix epoch and the GPS epoch (1980-01-06).
+const GPS_EPOCH_OFFSET: u64 = 315964800;
+
+// GPS - UTC offset in seconds (leap seconds since 2017-01-01).
+const LEAP_SECONDS: u64 = 18;
+
+// Max. time since the last PPS pulse, the PPS pulse is expected every second.
+const PPS_MAX_AGE: Duration = Duration::from_millis(1100);
+
+lazy_static! {
+    static ref PAYLOAD_OVERRIDE: Mutex<api::BeaconPayload> = Mutex::new(Default::default());
+}
+
+// Time reference of the last PPS pulse.
+#[derive(Clone, Copy)]
+struct PpsReference {
+    // GPS epoch time of the PPS pulse.
+    gps_epoch: Duration,
+    // Concentrator counter captured on the PPS pulse.
+    count_us: u32,
+}
+
+pub fn set_payload_override(pl: api::BeaconPayload) -> Result<()> {
+    if !pl.gw_specific.is_empty() && pl.gw_specific.len() != GW_SPECIFIC_SIZE {
+        return Err(anyhow!(
+            "gw_specific must be exactly {} bytes",
+            GW_SPECIFIC_SIZE
+        ));
+    }
+
+    if pl.payload.len() > 255 {
+        return Err(anyhow!("payload must not exceed 255 bytes"));
+    }
+
+    info!(
+        "Beacon payload override set, gw_specific: {}, payload: {}",
+        hex::encode(&pl.gw_specific),
+        hex::encode(&pl.payload)
+    );
+
+    *PAYLOAD_OVERRIDE.lock().unwrap() = pl;
+
+    Ok(())
+}
+
+pub fn beacon_loop(
+    conf: &config::Beacon,
+    lorawan_public: bool,
+    queue: Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
+    stop_receive: Receiver<Signal>,
+) {
+    debug!("Starting beacon loop");
+
+    loop {
+        // Instead of a MARGIN sleep, we receive from the stop channel with a
+        // timeout of MARGIN seconds.
+        match stop_receive.recv_timeout(MARGIN) {
+            Ok(v) => {
+                debug!("Received stop signal, signal: {}", v);
+                break;
+            }
+            _ => {}
+        };
+
+        let gps_epoch = match get_pps_reference() {
+            Ok(v) => v.gps_epoch,
+            Err(err) => {
+                debug!("Get PPS reference error, error: {}", err);
+                thread::sleep(Duration::from_secs(1));
+                continue;
+            }
+        };
+
+        let next_beacon_time =
+            Duration::from_secs(gps_epoch.as_secs() - (gps_epoch.as_secs() % PERIOD) + PERIOD);
+        let sleep_time = match next_beacon_time.checked_sub(gps_epoch + MARGIN) {
+            Some(v) => v,
+            None => continue,
+        };
+
+        // Instead of a sleep_time sleep, we receive from the stop channel with a
+        // timeout of sleep_time.
+        match stop_receive.recv_timeout(sleep_time) {
+            Ok(v) => {
+                debug!("Received stop signal, signal: {}", v);
+                break;
+            }
+            _ => {}
+        };
+
+        match send_beacon(conf, lorawan_public, next_beacon_time, &queue) {
+            Ok(_) => info!(
+                "Beacon enqueued, beacon_time_gps_epoch: {:?}",
+                next_beacon_time
+            ),
+            Err(err) => warn!("Enqueue beacon failed, error: {}", err),
+        }
+    }
+
+    debug!("Beacon loop ended");
+}
+
+fn send_beacon(
+    conf: &config::Beacon,
+    lorawan_public: bool,
+    beacon_time: Duration,
+    queue: &Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
+) -> Result<()> {
+    let mut rng = rand::thread_rng();
+
+    let mut beacon_pl = {
+        let payload_override = PAYLOAD_OVERRIDE.lock().unwrap();
+        if !payload_override.payload.is_empty() {
+            payload_override.payload.clone()
+        } else {
+            let mut b = get_beacon(conf.compulsory_rfu_size, beacon_time);
+            if !payload_override.gw_specific.is_empty() {
+                b.extend_from_slice(&get_beacon_gw_specific(
+                    &payload_override.gw_specific,
+                    conf.optional_rfu_size,
+                ));
+            }
+            b
+        }
+    };
+    let data_size = beacon_pl.len();
+
+    let mut data: [u8; 256] = [0; 256];
+    beacon_pl.resize(data.len(), 0);
+    data.copy_from_slice(&beacon_pl);
+
+    // The beacon is sent as timestamped packet, using the concentrator counter captured on the
+    // PPS pulse.
+    let tx_freq = get_beacon_frequency(&conf.frequencies, beacon_time);
+    let tx_packet = hal::TxPacket {
+        freq_hz: tx_freq,
+        tx_mode: hal::TxMode::Timestamped,
+        count_us: epoch2cnt(&get_pps_reference()?, beacon_time)?,
+        rf_power: conf.tx_power as i8,
+        bandwidth: conf.bandwidth,
+        datarate: match conf.spreading_factor {
+            5 => hal::DataRate::SF5,
+            6 => hal::DataRate::SF6,
+            7 => hal::DataRate::SF7,
+            8 => hal::DataRate::SF8,
+            9 => hal::DataRate::SF9,
+            10 => hal::DataRate::SF10,
+            11 => hal::DataRate::SF11,
+            12 => hal::DataRate::SF12,
+            _ => return Err(anyhow!("invalid spreading-factor configured")),
+        },
+        coderate: match conf.code_rate.as_ref() {
+            "4/5" => hal::CodeRate::LoRa4_5,
+            "4/6" => hal::CodeRate::LoRa4_6,
+            "4/7" => hal::CodeRate::LoRa4_7,
+            "4/8" => hal::CodeRate::LoRa4_8,
+            "4/5LI" => hal::CodeRate::LoRaLi4_5,
+            "4/6LI" => hal::CodeRate::LoRaLi4_6,
+            "4/8LI" => hal::CodeRate::LoRaLi4_8,
+            _ => return Err(anyhow!("invalid code-rate configured")),
+        },
+        invert_pol: false,
+        preamble: 10,
+        sync_word: match lorawan_public {
+            true => 0x21,
+            false => 0x12,
+        },
+        no_crc: true,
+        no_header: true,
+        size: data_size as u16,
+        payload: data,
+    };
+    let tx_packet = wrapper::TxPacket::new(rng.gen(), tx_packet);
+
+    match queue
+        .lock()
+        .unwrap()
+        .enqueue_beacon(hal::get_instcnt()?, tx_packet)
+    {
+        Ok(_) => Ok(()),
+        Err(status) => Err(anyhow!("{:?}", status)),
+    }
+}
+
+// Returns the time reference of the last PPS pulse. The concentrator captures its counter on
+// the PPS pulse of the GNSS module, the second of this pulse is derived from the host clock,
+// which therefore must be synchronized (e.g. using NTP or gpsd) within half a second.
+fn get_pps_reference() -> Result<PpsReference> {
+    let count_us = hal::get_trigcnt()?;
+    let inst_cnt = hal::get_instcnt()?;
+    let now = timesource::now().duration_since(UNIX_EPOCH)?;
+
+    let pps_age = Duration::from_micros(inst_cnt.wrapping_sub(count_us).into());
+    if pps_age > PPS_MAX_AGE {
+        return Err(anyhow!("no recent pps pulse, pps_age: {:?}", pps_age));
+    }
+
+    Ok(PpsReference {
+        gps_epoch: unix2gps(now.checked_sub(pps_age).unwrap_or_default())?,
+        count_us,
+    })
+}
+
+// Converts the Unix time of a PPS pulse into the GPS epoch time. The PPS pulse is aligned to the
+// full second, the time is rounded to the nearest second.
+fn unix2gps(unix_time: Duration) -> Result<Duration> {
+    let secs = (unix_time + Duration::from_millis(500)).as_secs();
+    let secs = secs
+        .checked_sub(GPS_EPOCH_OFFSET)
+        .ok_or_else(|| anyhow!("time is before the gps epoch"))?;
+
+    Ok(Duration::from_secs(secs + LEAP_SECONDS))
+}
+
+// Returns the concentrator counter at the given GPS epoch time, using the PPS reference.
+fn epoch2cnt(pps: &PpsReference, gps_epoch: Duration) -> Result<u32> {
+    let diff = gps_epoch
+        .checked_sub(pps.gps_epoch)
+        .ok_or_else(|| anyhow!("gps epoch is before the pps reference"))?;
+
+    Ok(pps.count_us.wrapping_add(diff.as_micros() as u32))
+}
+
+// The beacon channel is incremented every beacon period.
+fn get_beacon_frequency(frequencies: &[u32], beacon_time: Duration) -> u32 {
+    let channel = ((beacon_time.as_secs() % (1 << 32)) / PERIOD) % frequencies.len() as u64;
+    frequencies[channel as usize]
+}
+
+fn get_beacon(rfu_size: usize, beacon_time: Duration) -> Vec<u8> {
+    // [N: RFU | 4: TIME | 2: CRC]
+    let mut b: Vec<u8> = vec![0; rfu_size + 6];
+    let beacon_time = beacon_time.as_secs();
+
+    let time_bytes = ((beacon_time % (1 << 32)) as u32).to_le_bytes();
+    b[rfu_size..4 + rfu_size].copy_from_slice(&time_bytes);
+
+    let crc_bytes = crc16(&b[..rfu_size + 4]).to_le_bytes();
+    b[rfu_size + 4..rfu_size + 6].copy_from_slice(&crc_bytes);
+
+    b
+}
+
+fn get_beacon_gw_specific(gw_specific: &[u8], rfu_size: usize) -> Vec<u8> {
+    // [7: GwSpecific | N: RFU | 2: CRC]
+    let mut b: Vec<u8> = vec![0; gw_specific.len() + rfu_size + 2];
+    b[..gw_specific.len()].copy_from_slice(gw_specific);
+
+    let crc_bytes = crc16(&b[..gw_specific.len() + rfu_size]).to_le_bytes();
+    b[gw_specific.len() + rfu_size..].copy_from_slice(&crc_bytes);
+
+    b
+}
+
+fn crc16(b: &[u8]) -> u16 {
+    let poly: u16 = 0x1021;
+    let mut x: u16 = 0;
+
+    for v in b {
+        x ^= (*v as u16) << 8;
+        for _j in 0..8 {
+            if x & 0x8000 != 0 {
+                x = (x << 1) ^ poly;
+            } else {
+                x = x << 1;
+            }
+        }
+    }
+
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_beacon() {
+        let beacon_time = Duration::from_secs(0xcc020000);
+        let beacon = get_beacon(2, beacon_time);
+
+        assert_eq!(vec![0x00, 0x00, 0x00, 0x00, 0x02, 0xcc, 0xa2, 0x7e], beacon);
+    }
+
+    #[test]
+    fn test_unix2gps() {
+        // 2020-01-01T00:00:00Z
+        let unix_time = Duration::from_secs(1577836800);
+        assert_eq!(
+            Duration::from_secs(1261872018),
+            unix2gps(unix_time).unwrap()
+        );
+        assert_eq!(
+            Duration::from_secs(1261872018),
+            unix2gps(unix_time - Duration::from_millis(300)).unwrap()
+        );
+        assert_eq!(
+            Duration::from_secs(1261872018),
+            unix2gps(unix_time + Duration::from_millis(300)).unwrap()
+        );
+        assert!(unix2gps(Duration::from_secs(0)).is_err());
+    }
+
+    #[test]
+    fn test_epoch2cnt() {
+        let pps = PpsReference {
+            gps_epoch: Duration::from_secs(1000),
+            count_us: u32::MAX - 999_999,
+        };
+
+        assert_eq!(
+            u32::MAX - 999_999,
+            epoch2cnt(&pps, Duration::from_secs(1000)).unwrap()
+        );
+        // counter wraps around
+        assert_eq!(
+            4_000_000,
+            epoch2cnt(&pps, Duration::from_secs(1005)).unwrap()
+        );
+        assert!(epoch2cnt(&pps, Duration::from_secs(999)).is_err());
+    }
+}
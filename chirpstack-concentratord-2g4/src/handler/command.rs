@@ -1,72 +1,195 @@
+use std::str::FromStr;
 use std::sync::mpsc::{Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use anyhow::Result;
 use libconcentratord::signals::Signal;
-use libconcentratord::{commands, jitqueue, stats};
+use libconcentratord::{
+    api, audit, blacklist, commands, diag, events, history, jitqueue, region, shadow, stats,
+    timesync, txtest,
+};
 use libloragw_2g4::hal;
 use prost::Message;
 
-use super::super::config::vendor;
+use super::super::config::{self, vendor};
 use super::super::wrapper;
+use super::beacon;
 
 pub fn handle_loop(
-    lorawan_public: bool,
-    vendor_config: &vendor::Configuration,
+    config: &config::Configuration,
     gateway_id: &[u8],
     queue: Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
     rep_sock: zmq::Socket,
     stop_receive: Receiver<Signal>,
     stop_send: Sender<Signal>,
+    txtest_send: Sender<api::TxTestRequest>,
 ) {
     debug!("Starting command handler loop");
 
+    let rate_limits = config
+        .concentratord
+        .api
+        .rate_limits
+        .iter()
+        .map(|(k, v)| {
+            (
+                k.clone(),
+                commands::RateLimit {
+                    rate: v.rate,
+                    burst: v.burst,
+                },
+            )
+        })
+        .collect();
+
     // A timeout is used so that we can consume from the stop signal.
-    let reader = commands::Reader::new(&rep_sock, Duration::from_millis(100));
+    let mut reader = commands::Reader::new(&rep_sock, Duration::from_millis(100))
+        .with_filter(commands::Filter {
+            allowed_commands: config.concentratord.api.allowed_commands.clone(),
+            read_only: config.concentratord.api.read_only,
+        })
+        .with_rate_limits(rate_limits);
 
-    for cmd in reader {
+    while let Some(cmd) = reader.next() {
         match stop_receive.recv_timeout(Duration::from_millis(0)) {
             Ok(v) => {
                 debug!("Received stop signal, signal: {}", v);
+
+                // The command socket is re-used after a configuration reload, a received
+                // command must be replied to, to keep the socket usable.
+                if !matches!(cmd, commands::Command::Timeout) {
+                    let _ = rep_sock.send(Vec::new(), 0);
+                }
                 break;
             }
             _ => {}
         };
 
-        let resp = match cmd {
+        let cmd_name = cmd.to_string();
+        let (resp, outcome) = match cmd {
             commands::Command::Timeout => {
                 continue;
             }
             commands::Command::Downlink(pl) => {
-                match handle_downlink(lorawan_public, vendor_config, gateway_id, &queue, &pl) {
-                    Ok(v) => v,
-                    Err(_) => Vec::new(),
+                match handle_downlink(
+                    config.gateway.lorawan_public,
+                    &config.gateway.model_config,
+                    gateway_id,
+                    &queue,
+                    &pl,
+                ) {
+                    Ok(v) => (v, "OK"),
+                    Err(_) => (Vec::new(), "ERROR"),
+                }
+            }
+            commands::Command::GatewayID => (gateway_id.to_vec(), "OK"),
+            commands::Command::GetConfiguration => (
+                super::config::get_configuration(config, gateway_id).encode_to_vec(),
+                "OK",
+            ),
+            commands::Command::Capabilities => (events::get_capabilities().encode_to_vec(), "OK"),
+            commands::Command::Status => {
+                (shadow::snapshot(&get_status(&queue)).encode_to_vec(), "OK")
+            }
+            commands::Command::Beacon(pl) => match beacon::set_payload_override(pl) {
+                Ok(_) => (Vec::new(), "OK"),
+                Err(err) => {
+                    error!("Set beacon payload override error, error: {}", err);
+                    (Vec::new(), "ERROR")
                 }
+            },
+            commands::Command::SyncTx(_) => {
+                warn!("Synchronized TX is not supported by this concentratord");
+                (Vec::new(), "NOT_SUPPORTED")
             }
-            commands::Command::GatewayID => gateway_id.to_vec(),
+            commands::Command::TxTest(pl) => match handle_tx_test(&txtest_send, pl) {
+                Ok(_) => (Vec::new(), "OK"),
+                Err(err) => {
+                    error!("TX test request error, error: {}", err);
+                    (Vec::new(), "ERROR")
+                }
+            },
             commands::Command::Configuration(pl) => {
-                match handle_configuration(stop_send.clone(), pl) {
-                    Ok(v) => v,
-                    Err(_) => Vec::new(),
+                match handle_configuration(config, stop_send.clone(), pl) {
+                    Ok(v) => (v, "OK"),
+                    Err(_) => (Vec::new(), "ERROR"),
+                }
+            }
+            commands::Command::Reload => {
+                stop_send.send(Signal::Reload).unwrap();
+                (Vec::new(), "OK")
+            }
+            commands::Command::TimeSync(pl) => match timesync::handle_answer(&pl) {
+                Ok(_) => (Vec::new(), "OK"),
+                Err(err) => {
+                    error!("Time-sync answer error, error: {}", err);
+                    (Vec::new(), "ERROR")
                 }
+            },
+            commands::Command::SpectralScan(_) => {
+                warn!("Spectral scan is not supported by this concentratord");
+                (Vec::new(), "NOT_SUPPORTED")
             }
+            commands::Command::DownlinkHistory(pl) => {
+                (history::get(gateway_id, &pl).encode_to_vec(), "OK")
+            }
+            commands::Command::Blacklist => (blacklist::get(gateway_id).encode_to_vec(), "OK"),
+            commands::Command::DiagBundle(pl) => {
+                match handle_diag_bundle(config, gateway_id, &queue, pl) {
+                    Ok(v) => (v, "OK"),
+                    Err(err) => {
+                        error!("Diagnostic bundle request error, error: {}", err);
+                        (Vec::new(), "ERROR")
+                    }
+                }
+            }
+            commands::Command::Tap(pl) => match events::start_tap(&pl) {
+                Ok(_) => (Vec::new(), "OK"),
+                Err(err) => {
+                    error!("Tap request error, error: {}", err);
+                    (Vec::new(), "ERROR")
+                }
+            },
             commands::Command::Error(err) => {
                 error!("Read command error, error: {}", err);
-                Vec::new()
+                (Vec::new(), "ERROR")
+            }
+            commands::Command::Rejected(command) => {
+                warn!("Command rejected, command: {}", command);
+                (Vec::new(), "REJECTED")
+            }
+            commands::Command::Throttled(command) => {
+                // This is not logged as warning, as that would amplify a flood of commands
+                // into a flood of log events.
+                debug!("Command throttled, command: {}", command);
+                (Vec::new(), "THROTTLED")
             }
             commands::Command::Unknown(command, _) => {
                 warn!("Unknown command received, command: {}", command);
-                Vec::new()
+                (Vec::new(), "UNKNOWN")
             }
         };
 
+        audit::log(&cmd_name, reader.user_id(), outcome);
         rep_sock.send(resp, 0).unwrap();
     }
 
+    commands::release_socket(&config.concentratord.api.command_bind, rep_sock);
+
     debug!("Command loop ended");
 }
 
+/// Returns the daemon specific status, as included in the shadow state and the status command.
+pub fn get_status(queue: &Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>) -> shadow::Status {
+    let queue = queue.lock().unwrap();
+    shadow::Status {
+        gps: None,
+        queue_depth: queue.count(),
+        queue_capacity: queue.size(),
+    }
+}
+
 fn handle_downlink(
     lorawan_public: bool,
     vendor_config: &vendor::Configuration,
@@ -74,6 +197,38 @@ fn handle_downlink(
     queue: &Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
     pl: &chirpstack_api::gw::DownlinkFrame,
 ) -> Result<Vec<u8>> {
+    let (tx_ack, details) =
+        schedule_downlink(lorawan_public, vendor_config, gateway_id, queue, pl)?;
+
+    // The rejection details are appended to the TX acknowledgement, see
+    // api::DownlinkTxAckDetails.
+    let mut b = tx_ack.encode_to_vec();
+    details.encode(&mut b)?;
+    Ok(b)
+}
+
+/// Enqueues the downlink and returns the TX acknowledgement. This is also used for the
+/// downlinks received by the UDP packet-forwarder.
+pub fn enqueue_downlink(
+    lorawan_public: bool,
+    vendor_config: &vendor::Configuration,
+    gateway_id: &[u8],
+    queue: &Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
+    pl: &chirpstack_api::gw::DownlinkFrame,
+) -> Result<chirpstack_api::gw::DownlinkTxAck> {
+    let (tx_ack, _) = schedule_downlink(lorawan_public, vendor_config, gateway_id, queue, pl)?;
+    Ok(tx_ack)
+}
+
+// Enqueues the first possible downlink item. Returns the TX acknowledgement and the rejection
+// details of the items rejected by the JIT queue.
+fn schedule_downlink(
+    lorawan_public: bool,
+    vendor_config: &vendor::Configuration,
+    gateway_id: &[u8],
+    queue: &Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
+    pl: &chirpstack_api::gw::DownlinkFrame,
+) -> Result<(chirpstack_api::gw::DownlinkTxAck, api::DownlinkTxAckDetails)> {
     stats::inc_tx_packets_received();
 
     let mut tx_ack = chirpstack_api::gw::DownlinkTxAck {
@@ -82,9 +237,39 @@ fn handle_downlink(
         items: vec![Default::default(); pl.items.len()],
         ..Default::default()
     };
+    let mut details: api::DownlinkTxAckDetails = Default::default();
     let mut stats_tx_status = chirpstack_api::gw::TxAckStatus::Ignored;
 
     for (i, item) in pl.items.iter().enumerate() {
+        // the downlinks are suspended during a certification TX test
+        if txtest::certification_active() {
+            warn!(
+                "Rejecting downlink, certification TX test is running, downlink_id: {}",
+                pl.downlink_id
+            );
+            tx_ack.items[i].set_status(chirpstack_api::gw::TxAckStatus::CollisionPacket);
+
+            // try next
+            continue;
+        }
+
+        // validate payload size
+        if let Err(err) = region::Region::ISM2400.validate_phy_payload_size(item) {
+            error!(
+                "Validate downlink payload size error, downlink_id: {}, error: {}",
+                pl.downlink_id, err
+            );
+            // There is no payload size status, the PAYLOAD_SIZE rejection details contain the
+            // max. PHYPayload size of the data-rate.
+            if let Some(rejection) = err.to_proto(i) {
+                details.rejections.push(rejection);
+            }
+            tx_ack.items[i].set_status(chirpstack_api::gw::TxAckStatus::InternalError);
+
+            // try next
+            continue;
+        }
+
         // convert protobuf to hal struct
         let tx_packet = match wrapper::downlink_from_proto(lorawan_public, item) {
             Ok(v) => v,
@@ -119,22 +304,79 @@ fn handle_downlink(
                 // break out of for loop
                 break;
             }
-            Err(status) => {
-                tx_ack.items[i].set_status(status);
-                stats_tx_status = status;
+            Err(rejection) => {
+                warn!(
+                    "Downlink rejected by JIT queue, downlink_id: {}, reason: {}",
+                    pl.downlink_id, rejection
+                );
+                details.rejections.push(rejection.to_proto(i));
+                tx_ack.items[i].set_status(rejection.status());
+                stats_tx_status = rejection.status();
             }
         };
     }
 
     stats::inc_tx_status_count(stats_tx_status);
+    stats::inc_tx_rejection_counts(&tx_ack);
+    history::record_tx_ack(pl, &tx_ack);
+
+    Ok((tx_ack, details))
+}
+
+fn handle_tx_test(txtest_send: &Sender<api::TxTestRequest>, pl: api::TxTestRequest) -> Result<()> {
+    txtest::validate(&pl)?;
+
+    // Only the certification modes are supported.
+    if !pl.stop && txtest::Mode::from_str(&pl.mode)? == txtest::Mode::Sweep {
+        return Err(anyhow!(
+            "TX test sweep is not supported by this concentratord"
+        ));
+    }
+
+    txtest_send.send(pl)?;
+    Ok(())
+}
 
-    Ok(tx_ack.encode_to_vec())
+fn handle_diag_bundle(
+    config: &config::Configuration,
+    gateway_id: &[u8],
+    queue: &Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
+    pl: api::DiagBundleRequest,
+) -> Result<Vec<u8>> {
+    // The secret keys are not included in the bundle.
+    let mut config = config.clone();
+    config.concentratord.api.event_encryption_key = "".to_string();
+    config.concentratord.api.curve_secret_key = "".to_string();
+
+    let status = format!("{:#?}", shadow::snapshot(&get_status(queue)));
+    let queue = diag::format_queue(&queue.lock().unwrap().packets());
+    let history = format!("{:#?}", history::get(gateway_id, &Default::default()));
+
+    let files = vec![
+        (
+            "config.toml".to_string(),
+            toml::Value::try_from(&config)?.to_string().into_bytes(),
+        ),
+        ("status.txt".to_string(), status.into_bytes()),
+        ("queue.txt".to_string(), queue.into_bytes()),
+        ("downlink_history.txt".to_string(), history.into_bytes()),
+    ];
+
+    Ok(diag::start(gateway_id, &pl, files)?.encode_to_vec())
 }
 
 fn handle_configuration(
+    config: &config::Configuration,
     stop_send: Sender<Signal>,
     pl: chirpstack_api::gw::GatewayConfiguration,
 ) -> Result<Vec<u8>> {
+    // Validate the new configuration, so that an invalid channel-plan is rejected instead of
+    // failing the concentrator restart.
+    if let Err(err) = super::config::update_configuration(&mut config.clone(), &pl) {
+        error!("Invalid gateway configuration, error: {}", err);
+        return Err(err);
+    }
+
     stop_send.send(Signal::Configuration(pl)).unwrap();
     Ok(Vec::new())
 }
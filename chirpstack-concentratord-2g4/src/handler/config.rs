@@ -47,6 +47,38 @@ pub fn update_configuration(
     Ok(())
 }
 
+/// Returns the active channel configuration, in the format accepted by update_configuration.
+pub fn get_configuration(
+    config: &Configuration,
+    gateway_id: &[u8],
+) -> chirpstack_api::gw::GatewayConfiguration {
+    chirpstack_api::gw::GatewayConfiguration {
+        gateway_id: hex::encode(gateway_id),
+        version: config.gateway.config_version.clone(),
+        channels: config
+            .gateway
+            .concentrator
+            .channels
+            .iter()
+            .filter(|c| c.frequency != 0)
+            .map(|c| chirpstack_api::gw::ChannelConfiguration {
+                frequency: c.frequency,
+                modulation_config: Some(
+                    chirpstack_api::gw::channel_configuration::ModulationConfig::LoraModulationConfig(
+                        chirpstack_api::gw::LoraModulationConfig {
+                            bandwidth: c.bandwidth,
+                            spreading_factors: vec![c.spreading_factor],
+                            ..Default::default()
+                        },
+                    ),
+                ),
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::super::config::Channel;
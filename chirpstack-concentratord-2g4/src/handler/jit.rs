@@ -1,21 +1,57 @@
 use std::sync::mpsc::Receiver;
 use std::sync::{Arc, Mutex};
+use std::thread;
 use std::time::Duration;
 
+use anyhow::Result;
 use libconcentratord::jitqueue::TxPacket;
 use libconcentratord::signals::Signal;
-use libconcentratord::{jitqueue, stats};
+use libconcentratord::{api, events, history, jitqueue, stats, timesource, txtest};
 use libloragw_2g4::hal;
 
 use super::super::wrapper;
 
+lazy_static! {
+    static ref PENDING: Mutex<Vec<(wrapper::TxPacket, jitqueue::PendingOffset)>> =
+        Mutex::new(Vec::new());
+}
+
+/// Saves the pending downlinks, so that these are re-scheduled by the JIT loop once the
+/// concentrator has been re-initialized. As the concentrator counter is reset on
+/// re-initialization, the downlinks are saved with their offset relative to the host monotonic
+/// time. Downlinks of which the TX time has passed are dropped.
+pub fn save_pending(queue: &Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>) {
+    let mut pending = PENDING.lock().unwrap();
+    let concentrator_count = hal::get_instcnt().ok();
+    let now = timesource::monotonic();
+
+    for tx_packet in queue.lock().unwrap().drain() {
+        match concentrator_count
+            .and_then(|v| jitqueue::PendingOffset::new(tx_packet.get_count_us(), v, now))
+        {
+            Some(v) => pending.push((tx_packet, v)),
+            None => drop_pending(&tx_packet, "TX time has passed or is unknown"),
+        }
+    }
+
+    if !pending.is_empty() {
+        info!(
+            "Saved pending downlinks for re-scheduling, count: {}",
+            pending.len()
+        );
+    }
+}
+
 pub fn jit_loop(
+    gateway_id: &[u8],
     queue: Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
     antenna_gain: i8,
     stop_receive: Receiver<Signal>,
 ) {
     debug!("Start JIT queue loop");
 
+    restore_pending(&queue);
+
     loop {
         // Instead of a 10ms sleep, we receive from the stop channel with a
         // timeout of 10ms.
@@ -27,6 +63,11 @@ pub fn jit_loop(
             _ => {}
         };
 
+        // The downlinks are suspended during a certification TX test.
+        if txtest::certification_active() {
+            continue;
+        }
+
         let tx_packet = match get_tx_packet(&queue) {
             Some(v) => v,
             None => continue,
@@ -34,9 +75,10 @@ pub fn jit_loop(
 
         let downlink_id = tx_packet.get_id();
         let mut tx_packet = tx_packet.tx_packet();
+        let eirp = tx_packet.rf_power;
         tx_packet.rf_power = tx_packet.rf_power - antenna_gain;
 
-        match hal::send(&tx_packet) {
+        match send(&tx_packet, downlink_id) {
             Ok(_) => {
                 info!("Scheduled packet for TX, downlink_id: {}, count_us: {}, freq: {}, bw: {}, mod: {:?}, dr: {:?}",
                     downlink_id,
@@ -50,9 +92,25 @@ pub fn jit_loop(
                 if let Ok(tx_info) = wrapper::downlink_to_tx_info_proto(&tx_packet) {
                     stats::inc_tx_counts(&tx_info);
                 }
+
+                events::send_tx_done(&api::TxDone {
+                    gateway_id: hex::encode(gateway_id),
+                    downlink_id,
+                    frequency: tx_packet.freq_hz,
+                    power: eirp as i32,
+                    count_us: tx_packet.count_us,
+                    ..Default::default()
+                })
+                .unwrap();
             }
             Err(err) => {
                 error!("Schedule packet for tx error, error: {}", err);
+                history::record_tx_failed(
+                    downlink_id,
+                    tx_packet.freq_hz,
+                    tx_packet.count_us,
+                    &err.to_string(),
+                );
             }
         }
     }
@@ -60,10 +118,88 @@ pub fn jit_loop(
     debug!("JIT loop ended");
 }
 
+// Interval between TX attempts when the concentrator is busy.
+const TX_RETRY_INTERVAL: Duration = Duration::from_millis(1);
+
+// TX is no longer retried when the scheduled TX time is within this margin (us).
+const TX_RETRY_MARGIN: u32 = 1500;
+
+// When the concentrator is still busy (scheduled or emitting) at the moment the packet is
+// handed over to the HAL, the TX is retried until the remaining margin is exhausted.
+fn send(tx_packet: &hal::TxPacket, downlink_id: u32) -> Result<()> {
+    loop {
+        let err = match hal::send(tx_packet) {
+            Ok(_) => return Ok(()),
+            Err(err) => err,
+        };
+
+        match hal::status(hal::StatusSelect::Tx) {
+            Ok(hal::StatusReturn::Tx(hal::TxStatus::Scheduled))
+            | Ok(hal::StatusReturn::Tx(hal::TxStatus::Emitting)) => {}
+            _ => return Err(err),
+        };
+
+        // The remaining time wraps around when the scheduled time has passed.
+        let remaining = tx_packet.count_us.wrapping_sub(hal::get_instcnt()?);
+        if matches!(tx_packet.tx_mode, hal::TxMode::OnGPS)
+            || remaining < TX_RETRY_MARGIN
+            || remaining > (i32::MAX as u32)
+        {
+            stats::inc_counter("tx_busy_failed");
+            return Err(anyhow!("concentrator busy"));
+        }
+
+        warn!(
+            "Concentrator busy, retrying TX, downlink_id: {}, remaining_us: {}",
+            downlink_id, remaining
+        );
+        stats::inc_counter("tx_busy_retries");
+        thread::sleep(TX_RETRY_INTERVAL);
+    }
+}
+
+// Re-schedules the pending downlinks, see save_pending.
+fn restore_pending(queue: &Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>) {
+    let now = timesource::monotonic();
+    let mut queue = queue.lock().unwrap();
+    let concentrator_count = hal::get_instcnt().expect("get concentrator count error");
+
+    for (mut tx_packet, offset) in PENDING.lock().unwrap().drain(..) {
+        let count_us = match offset.count_us(concentrator_count, now) {
+            Some(v) => v,
+            None => {
+                drop_pending(&tx_packet, "TX time has passed");
+                continue;
+            }
+        };
+        tx_packet.set_count_us(count_us);
+
+        match queue.enqueue(concentrator_count, tx_packet) {
+            Ok(_) => {
+                info!(
+                    "Re-scheduled pending downlink, downlink_id: {}, count_us: {}",
+                    tx_packet.get_id(),
+                    count_us
+                );
+            }
+            Err(rejection) => drop_pending(&tx_packet, &rejection.to_string()),
+        }
+    }
+}
+
+fn drop_pending(tx_packet: &wrapper::TxPacket, reason: &str) {
+    warn!(
+        "Dropping pending downlink, downlink_id: {}, reason: {}",
+        tx_packet.get_id(),
+        reason
+    );
+    stats::inc_counter("jit_restart_dropped");
+}
+
 fn get_tx_packet(
     queue: &Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
 ) -> Option<wrapper::TxPacket> {
     let mut queue = queue.lock().unwrap();
     let concentrator_count = hal::get_instcnt().expect("get concentrator count error");
-    return queue.pop(concentrator_count);
+    queue.pop(concentrator_count)
 }
@@ -1,6 +1,8 @@
+pub mod beacon;
 pub mod command;
 pub mod config;
 pub mod gps;
 pub mod jit;
 pub mod stats;
+pub mod txtest;
 pub mod uplink;
@@ -3,7 +3,8 @@ use std::sync::mpsc::Receiver;
 use std::time::Duration;
 
 use libconcentratord::signals::Signal;
-use libconcentratord::stats;
+use libconcentratord::{drift, stats};
+use libloragw_2g4::hal;
 
 use super::gps;
 
@@ -11,10 +12,13 @@ pub fn stats_loop(
     gateway_id: &[u8],
     stats_interval: &Duration,
     stop_receive: Receiver<Signal>,
-    metadata: &HashMap<String, String>,
+    mut metadata: HashMap<String, String>,
 ) {
     debug!("Starting stats loop, stats_interval: {:?}", stats_interval);
 
+    let mut drift = drift::Drift::new();
+    sample_drift(&mut drift, &mut metadata);
+
     loop {
         // Instead of a 'stats interval' sleep, we receive from the stop channel with a
         // timeout equal to the 'stats interval'.
@@ -42,8 +46,25 @@ pub fn stats_loop(
             None => None,
         };
 
-        stats::send_and_reset(gateway_id, loc, metadata).expect("sending stats failed");
+        // sample the concentrator clock drift.
+        sample_drift(&mut drift, &mut metadata);
+
+        stats::send_and_reset(gateway_id, loc, &metadata).expect("sending stats failed");
     }
 
     debug!("Stats loop ended");
 }
+
+fn sample_drift(drift: &mut drift::Drift, metadata: &mut HashMap<String, String>) {
+    match hal::get_instcnt() {
+        Ok(v) => {
+            if let Some(ppm) = drift.sample(v) {
+                metadata.insert("concentrator_drift_ppm".to_string(), format!("{:.2}", ppm));
+            }
+        }
+        Err(err) => {
+            metadata.remove(&"concentrator_drift_ppm".to_string());
+            error!("Get concentrator count error, error: {}", err);
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::str::FromStr;
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+use anyhow::Result;
+use libconcentratord::signals::Signal;
+use libconcentratord::{api, txtest};
+use libloragw_2g4::hal;
+use rand::Rng;
+
+pub fn txtest_loop(
+    gateway_id: &[u8],
+    antenna_gain: i8,
+    request_receive: Receiver<api::TxTestRequest>,
+    stop_receive: Receiver<Signal>,
+) {
+    debug!("Starting TX test loop");
+
+    loop {
+        // Instead of a 100ms sleep, we receive from the stop channel with a timeout of 100ms.
+        if let Ok(v) = stop_receive.recv_timeout(Duration::from_millis(100)) {
+            debug!("Received stop signal, signal: {}", v);
+            break;
+        }
+
+        let req = match request_receive.try_recv() {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+
+        let completed = match txtest::Mode::from_str(&req.mode) {
+            _ if req.stop => {
+                warn!("No certification TX test is running");
+                true
+            }
+            Ok(txtest::Mode::Sweep) => {
+                warn!("TX test sweep is not supported by this concentratord");
+                true
+            }
+            _ => txtest::run_certification(
+                gateway_id,
+                &Transmitter { antenna_gain },
+                &req,
+                &request_receive,
+                &stop_receive,
+            ),
+        };
+
+        if !completed {
+            break;
+        }
+    }
+
+    debug!("TX test loop ended");
+}
+
+// Transmits the certification test directly through the HAL, as the JIT queue is suspended
+// during the test.
+struct Transmitter {
+    antenna_gain: i8,
+}
+
+impl txtest::Transmitter for Transmitter {
+    fn start(&self, mode: txtest::Mode, req: &api::TxTestRequest) -> Result<()> {
+        let mut tx_packet = hal::TxPacket {
+            freq_hz: req.frequency,
+            tx_mode: hal::TxMode::Immediate,
+            rf_power: (req.tx_power - self.antenna_gain as i32) as i8,
+            ..Default::default()
+        };
+
+        match mode {
+            txtest::Mode::ContinuousWave => {
+                tx_packet.tx_mode = hal::TxMode::CWOn;
+            }
+            _ => {
+                tx_packet.bandwidth = 812000;
+                tx_packet.datarate = hal::DataRate::SF7;
+                tx_packet.coderate = hal::CodeRate::LoRa4_5;
+                tx_packet.preamble = 8;
+                tx_packet.size = match req.payload_size {
+                    0 => 255,
+                    v => v as u16,
+                };
+                rand::thread_rng().fill(&mut tx_packet.payload[..]);
+            }
+        }
+
+        hal::send(&tx_packet)
+    }
+
+    fn busy(&self) -> Result<bool> {
+        Ok(matches!(
+            hal::status(hal::StatusSelect::Tx)?,
+            hal::StatusReturn::Tx(hal::TxStatus::Scheduled)
+                | hal::StatusReturn::Tx(hal::TxStatus::Emitting)
+        ))
+    }
+
+    fn abort(&self) -> Result<()> {
+        // The continuous wave is only stopped by a CW off request.
+        hal::send(&hal::TxPacket {
+            tx_mode: hal::TxMode::CWOff,
+            ..Default::default()
+        })?;
+        hal::abort_tx()
+    }
+}
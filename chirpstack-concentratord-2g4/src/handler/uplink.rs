@@ -3,7 +3,7 @@ use std::thread;
 use std::time::Duration;
 
 use libconcentratord::signals::Signal;
-use libconcentratord::{events, stats};
+use libconcentratord::{airtime, capture, events, health, metrics, stats, systemd};
 use libloragw_2g4::hal;
 
 use super::super::wrapper;
@@ -22,14 +22,28 @@ pub fn handle_loop(gateway_id: &[u8], stop_receive: Receiver<Signal>) {
 
         match hal::receive() {
             Ok(frames) => {
+                // A hanging or failing receive stops the pings, see systemd::watchdog.
+                systemd::watchdog();
+                health::receive_ok(frames.len());
+
                 for frame in frames {
                     stats::inc_rx_packets_received();
+                    metrics::inc_counter(
+                        "rx_packets_total",
+                        &[
+                            ("channel", &frame.channel.to_string()),
+                            ("crc", &format!("{:?}", frame.status)),
+                        ],
+                    );
                     if frame.status != hal::CRC::CRCOk {
                         debug!("Frame received with invalid CRC");
+                        if events::tap_active() {
+                            send_tap_invalid_crc(gateway_id, &frame);
+                        }
                         continue;
                     }
 
-                    let proto = match wrapper::uplink_to_proto(gateway_id.clone(), &frame) {
+                    let mut proto = match wrapper::uplink_to_proto(gateway_id.clone(), &frame) {
                         Ok(v) => v,
                         Err(err) => {
                             error!("Convert uplink frame to protobuf error, error: {}", err);
@@ -37,6 +51,8 @@ pub fn handle_loop(gateway_id: &[u8], stop_receive: Receiver<Signal>) {
                         }
                     };
 
+                    airtime::annotate(&mut proto);
+
                     let rx_info = proto.rx_info.as_ref().unwrap();
 
                     info!(
@@ -50,10 +66,14 @@ pub fn handle_loop(gateway_id: &[u8], stop_receive: Receiver<Signal>) {
                     );
 
                     stats::inc_rx_counts(&proto);
+                    capture::write_uplink(&proto);
                     events::send_uplink(&proto).unwrap();
                 }
             }
-            Err(_) => error!("Receive error"),
+            Err(_) => {
+                error!("Receive error");
+                health::hal_error();
+            }
         };
 
         thread::sleep(Duration::from_millis(10));
@@ -61,3 +81,22 @@ pub fn handle_loop(gateway_id: &[u8], stop_receive: Receiver<Signal>) {
 
     debug!("Uplink loop ended");
 }
+
+// Frames with an invalid CRC are only published by the live frame tap.
+fn send_tap_invalid_crc(gateway_id: &[u8], frame: &hal::RxPacket) {
+    let mut proto = match wrapper::uplink_to_proto(gateway_id, frame) {
+        Ok(v) => v,
+        Err(err) => {
+            debug!("Convert tapped frame to protobuf error, error: {}", err);
+            return;
+        }
+    };
+
+    if let Some(rx_info) = proto.rx_info.as_mut() {
+        rx_info
+            .metadata
+            .insert("crc_status".to_string(), format!("{:?}", frame.status));
+    }
+
+    events::send_tap(&proto);
+}
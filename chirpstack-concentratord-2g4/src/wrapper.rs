@@ -53,6 +53,10 @@ impl jitqueue::TxPacket for TxPacket {
     fn get_id(&self) -> u32 {
         self.1
     }
+
+    fn get_frequency(&self) -> u32 {
+        self.0.freq_hz
+    }
 }
 
 pub fn uplink_to_proto(gateway_id: &[u8], packet: &hal::RxPacket) -> Result<gw::UplinkFrame> {
@@ -99,6 +103,13 @@ pub fn uplink_to_proto(gateway_id: &[u8], packet: &hal::RxPacket) -> Result<gw::
             gateway_id: hex::encode(gateway_id),
             rssi: packet.rssi as i32,
             snr: packet.snr as f32,
+            metadata: vec![
+                ("board".to_string(), "0".to_string()),
+                ("rf_chain".to_string(), "0".to_string()),
+                ("if_chain".to_string(), packet.channel.to_string()),
+            ]
+            .into_iter()
+            .collect(),
             ..Default::default()
         }),
         ..Default::default()
@@ -0,0 +1,402 @@
+//! Hardware-in-the-loop tests.
+//!
+//! These tests are run against a Concentratord instance running on real hardware (e.g. on a
+//! bench), using its ZeroMQ API. They are gated by the CONCENTRATORD_HIL_COMMAND_URL
+//! environment variable, see Configuration::from_env for the other options.
+#[macro_use]
+extern crate anyhow;
+
+use std::env;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use chirpstack_api::gw;
+use libconcentratord::{api, events};
+use prost::Message;
+use serde::Serialize;
+
+// Timeout of a command request.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Max. time it takes to re-initialize the concentrator after a configuration update.
+const RESET_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Max. time it takes to complete the TX test.
+const TX_TIMEOUT: Duration = Duration::from_secs(120);
+
+pub struct Configuration {
+    // Event PUB socket URL (CONCENTRATORD_HIL_EVENT_URL).
+    pub event_url: String,
+    // HEX encoded key of the encrypted event payloads (CONCENTRATORD_HIL_EVENT_KEY).
+    pub event_key: Vec<u8>,
+    // Command REP socket URL (CONCENTRATORD_HIL_COMMAND_URL).
+    pub command_url: String,
+    // TX power (EIRP) of the test transmissions (CONCENTRATORD_HIL_TX_POWER).
+    pub tx_power: i32,
+    // Time to wait for an uplink, transmitted by a node on the bench
+    // (CONCENTRATORD_HIL_RX_TIMEOUT, seconds).
+    pub rx_timeout: Duration,
+    // Comma separated multi-SF channels (Hz) which are applied to test the re-initialization of
+    // the concentrator (CONCENTRATORD_HIL_CHANNELS). When empty, this test is skipped.
+    pub channels: Vec<u32>,
+    // Test that the uplinks are GPS timestamped (CONCENTRATORD_HIL_GPS).
+    pub gps: bool,
+}
+
+impl Configuration {
+    /// Returns the configuration, or None when CONCENTRATORD_HIL_COMMAND_URL is not set.
+    pub fn from_env() -> Result<Option<Configuration>> {
+        let command_url = match env::var("CONCENTRATORD_HIL_COMMAND_URL") {
+            Ok(v) => v,
+            Err(_) => return Ok(None),
+        };
+
+        Ok(Some(Configuration {
+            event_url: env::var("CONCENTRATORD_HIL_EVENT_URL")
+                .unwrap_or_else(|_| "ipc:///tmp/concentratord_event".to_string()),
+            event_key: match env::var("CONCENTRATORD_HIL_EVENT_KEY") {
+                Ok(v) => hex::decode(v)?,
+                Err(_) => Vec::new(),
+            },
+            command_url,
+            tx_power: match env::var("CONCENTRATORD_HIL_TX_POWER") {
+                Ok(v) => v.parse()?,
+                Err(_) => 14,
+            },
+            rx_timeout: match env::var("CONCENTRATORD_HIL_RX_TIMEOUT") {
+                Ok(v) => Duration::from_secs(v.parse()?),
+                Err(_) => Duration::from_secs(60),
+            },
+            channels: match env::var("CONCENTRATORD_HIL_CHANNELS") {
+                Ok(v) => v
+                    .split(',')
+                    .map(|s| s.trim().parse())
+                    .collect::<Result<Vec<u32>, _>>()?,
+                Err(_) => Vec::new(),
+            },
+            gps: env::var("CONCENTRATORD_HIL_GPS").map_or(false, |v| v == "1" || v == "true"),
+        }))
+    }
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Outcome {
+    Passed,
+    Failed,
+    Skipped,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Passed => write!(f, "PASSED"),
+            Outcome::Failed => write!(f, "FAILED"),
+            Outcome::Skipped => write!(f, "SKIPPED"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Check {
+    pub name: String,
+    pub outcome: Outcome,
+    pub duration_ms: u128,
+    pub detail: String,
+}
+
+#[derive(Serialize)]
+pub struct Report {
+    pub gateway_id: String,
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    /// Returns true when none of the checks failed.
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|c| c.outcome != Outcome::Failed)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    fn add(&mut self, name: &str, start: Instant, result: Result<Option<String>>) {
+        let (outcome, detail) = match result {
+            Ok(Some(v)) => (Outcome::Passed, v),
+            Ok(None) => (Outcome::Skipped, String::new()),
+            Err(err) => (Outcome::Failed, err.to_string()),
+        };
+
+        self.checks.push(Check {
+            name: name.to_string(),
+            outcome,
+            duration_ms: start.elapsed().as_millis(),
+            detail,
+        });
+    }
+}
+
+/// Run the init, reset, TX, RX and GPS checks and return the report. The checks following a
+/// failed init check are skipped.
+pub fn run(conf: &Configuration) -> Result<Report> {
+    let mut client = Client::connect(conf)?;
+    let mut report = Report {
+        gateway_id: String::new(),
+        checks: Vec::new(),
+    };
+
+    let start = Instant::now();
+    let init = check_init(&mut client);
+    if let Ok(Some(gateway_id)) = &init {
+        report.gateway_id = gateway_id.clone();
+    }
+    let init_ok = init.is_ok();
+    report.add("init", start, init);
+
+    if !init_ok {
+        for name in ["reset", "tx", "rx", "gps"] {
+            report.add(name, Instant::now(), Ok(None));
+        }
+        return Ok(report);
+    }
+
+    let start = Instant::now();
+    let reset = check_reset(&mut client, conf);
+    report.add("reset", start, reset);
+
+    let start = Instant::now();
+    let tx = check_tx(&mut client, conf);
+    report.add("tx", start, tx);
+
+    let start = Instant::now();
+    let rx = check_rx(&mut client, conf);
+    report.add("rx", start, rx);
+
+    let start = Instant::now();
+    let gps = check_gps(&mut client, conf);
+    report.add("gps", start, gps);
+
+    Ok(report)
+}
+
+// Reads the gateway ID and capabilities.
+fn check_init(client: &mut Client) -> Result<Option<String>> {
+    let gateway_id = client.command("gateway_id", &[])?;
+    if gateway_id.len() != 8 {
+        return Err(anyhow!(
+            "gateway_id must be 8 bytes, received: {}",
+            gateway_id.len()
+        ));
+    }
+
+    let capabilities = api::Capabilities::decode(client.command("capabilities", &[])?.as_slice())?;
+    client.compression = capabilities.event_compression;
+
+    Ok(Some(hex::encode(gateway_id)))
+}
+
+// Applies the configured channels and waits until the concentrator is running again.
+fn check_reset(client: &mut Client, conf: &Configuration) -> Result<Option<String>> {
+    if conf.channels.is_empty() {
+        return Ok(None);
+    }
+
+    let pl = gw::GatewayConfiguration {
+        version: "hil".to_string(),
+        channels: conf
+            .channels
+            .iter()
+            .map(|f| gw::ChannelConfiguration {
+                frequency: *f,
+                modulation_config: Some(
+                    gw::channel_configuration::ModulationConfig::LoraModulationConfig(
+                        gw::LoraModulationConfig {
+                            bandwidth: 125000,
+                            spreading_factors: vec![7, 8, 9, 10, 11, 12],
+                            ..Default::default()
+                        },
+                    ),
+                ),
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    };
+    client.command("config", &pl.encode_to_vec())?;
+
+    let mut states = Vec::new();
+    client.wait_event(RESET_TIMEOUT, |topic, b| {
+        if topic != "state" {
+            return Ok(false);
+        }
+
+        let state = api::StateEvent::decode(b)?;
+        states.push(state.state.clone());
+        Ok(state.state == "RUNNING")
+    })?;
+
+    Ok(Some(format!("states: {}", states.join(" > "))))
+}
+
+// Runs a TX test (one transmission per channel) and waits until it has completed.
+fn check_tx(client: &mut Client, conf: &Configuration) -> Result<Option<String>> {
+    let pl = api::TxTestRequest {
+        tx_power: conf.tx_power,
+        repetitions: 1,
+        interval_ms: 1000,
+        payload_size: 16,
+        ..Default::default()
+    };
+    client.command("txtest", &pl.encode_to_vec())?;
+
+    let mut errors = Vec::new();
+    let mut total = 0;
+    client.wait_event(TX_TIMEOUT, |topic, b| {
+        if topic != "txtest" {
+            return Ok(false);
+        }
+
+        let progress = api::TxTestProgress::decode(b)?;
+        if !progress.error.is_empty() {
+            errors.push(format!("{}: {}", progress.frequency, progress.error));
+        }
+        total = progress.total;
+        Ok(progress.sent == progress.total)
+    })?;
+
+    if !errors.is_empty() {
+        return Err(anyhow!("tx errors: {}", errors.join(", ")));
+    }
+
+    Ok(Some(format!("transmissions: {}", total)))
+}
+
+// Waits for an uplink.
+fn check_rx(client: &mut Client, conf: &Configuration) -> Result<Option<String>> {
+    let mut detail = String::new();
+    client.wait_event(conf.rx_timeout, |topic, b| {
+        if topic != "up" {
+            return Ok(false);
+        }
+
+        let frame = gw::UplinkFrame::decode(b)?;
+        let tx_info = frame.tx_info.unwrap_or_default();
+        let rx_info = frame.rx_info.unwrap_or_default();
+        detail = format!(
+            "frequency: {}, rssi: {}, snr: {}",
+            tx_info.frequency, rx_info.rssi, rx_info.snr
+        );
+        Ok(true)
+    })?;
+
+    Ok(Some(detail))
+}
+
+// Waits for a GPS timestamped uplink.
+fn check_gps(client: &mut Client, conf: &Configuration) -> Result<Option<String>> {
+    if !conf.gps {
+        return Ok(None);
+    }
+
+    let mut detail = String::new();
+    client.wait_event(conf.rx_timeout, |topic, b| {
+        if topic != "up" {
+            return Ok(false);
+        }
+
+        let rx_info = gw::UplinkFrame::decode(b)?.rx_info.unwrap_or_default();
+        match rx_info.time_since_gps_epoch {
+            Some(v) => {
+                detail = format!("time_since_gps_epoch: {}s", v.seconds);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    })?;
+
+    Ok(Some(detail))
+}
+
+struct Client {
+    event_sock: zmq::Socket,
+    command_sock: zmq::Socket,
+    compression: String,
+    event_key: Vec<u8>,
+}
+
+impl Client {
+    fn connect(conf: &Configuration) -> Result<Client> {
+        let ctx = zmq::Context::new();
+
+        let event_sock = ctx.socket(zmq::SUB)?;
+        event_sock.connect(&conf.event_url)?;
+        event_sock.set_subscribe(b"")?;
+
+        let command_sock = ctx.socket(zmq::REQ)?;
+        command_sock.connect(&conf.command_url)?;
+
+        Ok(Client {
+            event_sock,
+            command_sock,
+            compression: String::new(),
+            event_key: conf.event_key.clone(),
+        })
+    }
+
+    fn command(&mut self, command: &str, pl: &[u8]) -> Result<Vec<u8>> {
+        self.command_sock.send(command, zmq::SNDMORE)?;
+        self.command_sock.send(pl, 0)?;
+
+        let mut items = [self.command_sock.as_poll_item(zmq::POLLIN)];
+        zmq::poll(&mut items, COMMAND_TIMEOUT.as_millis() as i64)?;
+        if !items[0].is_readable() {
+            return Err(anyhow!("{} command timeout", command));
+        }
+
+        Ok(self.command_sock.recv_bytes(0)?)
+    }
+
+    // Receives the events until f returns true, or returns an error on timeout.
+    fn wait_event<F>(&mut self, timeout: Duration, mut f: F) -> Result<()>
+    where
+        F: FnMut(&str, &[u8]) -> Result<bool>,
+    {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(anyhow!("timeout after {:?}", timeout));
+            }
+
+            let mut items = [self.event_sock.as_poll_item(zmq::POLLIN)];
+            zmq::poll(&mut items, remaining.as_millis() as i64)?;
+            if !items[0].is_readable() {
+                continue;
+            }
+
+            // The optional third frame contains the sequence number.
+            let msg = self.event_sock.recv_multipart(0)?;
+            if msg.len() < 2 {
+                continue;
+            }
+
+            let topic = String::from_utf8(msg[0].clone())?;
+            let b = if self.event_key.is_empty() {
+                msg[1].clone()
+            } else {
+                events::decrypt(&self.event_key, &topic, &msg[1])?
+            };
+            let b = match self.compression.as_ref() {
+                "ZSTD" => zstd::decode_all(&b[..])?,
+                _ => b,
+            };
+
+            if f(&topic, &b)? {
+                return Ok(());
+            }
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use std::fs;
+
+use chirpstack_concentratord_hil::{run, Configuration};
+
+// This test requires a running Concentratord instance on real hardware and is skipped unless
+// CONCENTRATORD_HIL_COMMAND_URL is set. The report is written as JSON to the path set by
+// CONCENTRATORD_HIL_REPORT, or to stdout.
+#[test]
+fn hardware() {
+    let conf = match Configuration::from_env().expect("read configuration error") {
+        Some(v) => v,
+        None => {
+            eprintln!("CONCENTRATORD_HIL_COMMAND_URL is not set, skipping hardware tests");
+            return;
+        }
+    };
+
+    let report = run(&conf).expect("run hardware tests error");
+    let json = report.to_json().unwrap();
+
+    match std::env::var("CONCENTRATORD_HIL_REPORT") {
+        Ok(path) => fs::write(&path, &json).expect("write report error"),
+        Err(_) => println!("{}", json),
+    }
+
+    for check in &report.checks {
+        eprintln!(
+            "{}: {} ({} ms) {}",
+            check.name, check.outcome, check.duration_ms, check.detail
+        );
+    }
+
+    assert!(report.passed());
+}
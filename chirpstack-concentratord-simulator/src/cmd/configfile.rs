@@ -0,0 +1,110 @@
+use handlebars::Handlebars;
+
+use super::super::config;
+
+pub fn run(config: &config::Configuration) {
+    let template = r#"
+# Concentratord simulator configuration.
+#
+# The simulator implements the Concentratord event and command API without
+# concentrator hardware. It publishes the uplinks defined by the scenario,
+# accepts downlinks through a JIT queue driven by a simulated concentrator
+# counter and publishes the stats. Multiple configuration files can be given
+# (e.g. a separate scenario file), these are merged.
+[concentratord]
+  # Log level.
+  #
+  # Valid options are:
+  #   * TRACE
+  #   * DEBUG
+  #   * INFO
+  #   * WARN
+  #   * ERROR
+  #   * OFF
+  log_level="{{ concentratord.log_level }}"
+
+  # Statistics interval.
+  stats_interval="{{ concentratord.stats_interval }}"
+
+  # Configuration for the (ZeroMQ based) API.
+  [concentratord.api]
+    # Event PUB socket bind.
+    event_bind="{{ concentratord.api.event_bind }}"
+
+    # Command REP socket bind.
+    command_bind="{{ concentratord.api.command_bind }}"
+
+
+# Simulated gateway configuration.
+[gateway]
+  # Gateway ID (HEX encoded EUI64).
+  gateway_id="{{ gateway.gateway_id }}"
+
+  # LoRaWAN public network.
+  lorawan_public={{ gateway.lorawan_public }}
+
+  # Min. and max. downlink frequency (Hz), downlinks outside this range are
+  # rejected with TX_FREQ.
+  min_tx_frequency={{ gateway.min_tx_frequency }}
+  max_tx_frequency={{ gateway.max_tx_frequency }}
+
+  # JIT queue size.
+  queue_size={{ gateway.queue_size }}
+
+
+# Scenario.
+#
+# Each [[uplinks]] section defines a (simulated) device, which periodically
+# transmits an uplink. The payloads (HEX encoded PHYPayloads) are sent
+# round-robin and are published as-is. Example:
+#
+# [[uplinks]]
+#   # Name, used for logging.
+#   name="device-1"
+#
+#   # Frequency (Hz), bandwidth (Hz), spreading-factor and code rate.
+#   frequency=868100000
+#   bandwidth=125000
+#   spreading_factor=7
+#   code_rate="4/5"
+#
+#   # Payloads.
+#   payloads=["40010000000000010001a1a2a3a4"]
+#
+#   # Interval between two uplinks.
+#   interval="10s"
+#
+#   # Delay of the first uplink.
+#   offset="0s"
+#
+#   # Number of uplinks, 0 for unlimited.
+#   count=0
+#
+#   # RSSI, SNR and channel of the uplinks.
+#   rssi=-50
+#   snr=10.0
+#   channel=0
+{{#each uplinks}}
+[[uplinks]]
+  name="{{ this.name }}"
+  frequency={{ this.frequency }}
+  bandwidth={{ this.bandwidth }}
+  spreading_factor={{ this.spreading_factor }}
+  code_rate="{{ this.code_rate }}"
+  payloads=[{{#each this.payloads}}"{{ this }}",{{/each}}]
+  interval="{{ this.interval }}"
+  offset="{{ this.offset }}"
+  count={{ this.count }}
+  rssi={{ this.rssi }}
+  snr={{ this.snr }}
+  channel={{ this.channel }}
+{{/each}}
+"#;
+
+    let reg = Handlebars::new();
+    println!(
+        "{}",
+        reg.render_template(template, config)
+            .expect("render configfile error")
+    );
+}
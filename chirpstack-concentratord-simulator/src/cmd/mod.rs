@@ -0,0 +1,2 @@
+pub mod configfile;
+pub mod root;
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use anyhow::Result;
+use libconcentratord::signals;
+use libconcentratord::signals::Signal;
+use libconcentratord::{commands, events, jitqueue, state};
+
+use super::super::{concentrator, config, handler, wrapper};
+
+pub fn run(config: &config::Configuration, stop_receive: Receiver<Signal>) -> Result<()> {
+    info!(
+        "Starting Concentratord simulator (version: {}, docs: {})",
+        config::VERSION,
+        "https://www.chirpstack.io/concentratord/"
+    );
+
+    // setup event socket, this is done first so that the state transitions are published
+    events::bind_socket(
+        &config.concentratord.api.event_bind,
+        false,
+        events::Compression::None,
+        &[],
+    )?;
+    state::set(state::State::Initializing, "configuring simulator");
+
+    let gateway_id = concentrator::get_eui(config)?;
+    state::set_gateway_id(&gateway_id);
+
+    info!(
+        "Gateway ID configured, gateway_id: {}",
+        hex::encode(gateway_id)
+    );
+
+    // setup jit queue
+    let queue: jitqueue::Queue<wrapper::TxPacket> = jitqueue::Queue::new(config.gateway.queue_size);
+    let queue = Arc::new(Mutex::new(queue));
+
+    // setup command socket
+    let rep_sock = commands::get_socket(&config.concentratord.api.command_bind)?;
+
+    // setup threads
+    let mut signal_pool = signals::SignalPool::new();
+    let mut threads: Vec<thread::JoinHandle<()>> = vec![];
+
+    // uplink thread
+    threads.push(thread::spawn({
+        let gateway_id = gateway_id.clone();
+        let uplinks = config.uplinks.clone();
+        let stop_receive = signal_pool.new_receiver();
+
+        move || {
+            handler::uplink::handle_loop(&gateway_id, &uplinks, stop_receive);
+        }
+    }));
+
+    // jit thread
+    threads.push(thread::spawn({
+        let gateway_id = gateway_id.clone();
+        let queue = Arc::clone(&queue);
+        let stop_receive = signal_pool.new_receiver();
+
+        move || {
+            handler::jit::jit_loop(&gateway_id, queue, stop_receive);
+        }
+    }));
+
+    // command thread
+    threads.push(thread::spawn({
+        let queue = Arc::clone(&queue);
+        let config = config.clone();
+        let gateway_id = gateway_id.clone();
+        let stop_receive = signal_pool.new_receiver();
+
+        move || {
+            handler::command::handle_loop(&config, &gateway_id, queue, rep_sock, stop_receive);
+        }
+    }));
+
+    // stats thead
+    threads.push(thread::spawn({
+        let gateway_id = gateway_id.clone();
+        let stats_interval = config.concentratord.stats_interval;
+        let stop_receive = signal_pool.new_receiver();
+        let mut metadata = HashMap::new();
+        metadata.insert(
+            "concentratord_version".to_string(),
+            config::VERSION.to_string(),
+        );
+        metadata.insert("model".to_string(), "simulator".to_string());
+
+        move || {
+            handler::stats::stats_loop(&gateway_id, &stats_interval, stop_receive, metadata);
+        }
+    }));
+
+    state::set(state::State::Running, "simulator started");
+
+    let stop_signal = stop_receive.recv().unwrap();
+    state::set(state::State::Resetting, "stop signal received");
+    signal_pool.send_signal(stop_signal);
+
+    for t in threads {
+        t.join().unwrap();
+    }
+
+    Ok(())
+}
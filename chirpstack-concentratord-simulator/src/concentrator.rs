@@ -0,0 +1,27 @@
+use std::time::Instant;
+
+use anyhow::Result;
+
+use super::config;
+
+lazy_static! {
+    // Start of the simulated concentrator counter.
+    static ref START: Instant = Instant::now();
+}
+
+/// Returns the simulated concentrator counter (us). Like the concentrator counter, it starts at
+/// 0 and wraps around every ~71 minutes.
+pub fn get_instcnt() -> u32 {
+    START.elapsed().as_micros() as u32
+}
+
+pub fn get_eui(config: &config::Configuration) -> Result<[u8; 8]> {
+    let b = hex::decode(&config.gateway.gateway_id)?;
+    if b.len() != 8 {
+        return Err(anyhow!("gateway_id must be exactly 8 bytes"));
+    }
+
+    let mut eui = [0; 8];
+    eui.copy_from_slice(&b);
+    Ok(eui)
+}
@@ -0,0 +1,149 @@
+use std::fs;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::wrapper;
+
+pub const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default = "example_configuration")]
+pub struct Configuration {
+    pub concentratord: Concentratord,
+    pub gateway: Gateway,
+    #[serde(default)]
+    pub uplinks: Vec<Uplink>,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+pub struct Concentratord {
+    pub log_level: String,
+    #[serde(with = "humantime_serde")]
+    pub stats_interval: Duration,
+    pub api: API,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+pub struct API {
+    pub event_bind: String,
+    pub command_bind: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Gateway {
+    pub gateway_id: String,
+    pub lorawan_public: bool,
+    pub min_tx_frequency: u32,
+    pub max_tx_frequency: u32,
+    pub queue_size: usize,
+}
+
+impl Default for Gateway {
+    fn default() -> Self {
+        Gateway {
+            gateway_id: "0000000000000000".to_string(),
+            lorawan_public: true,
+            min_tx_frequency: 863000000,
+            max_tx_frequency: 870000000,
+            queue_size: 32,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Uplink {
+    pub name: String,
+    pub frequency: u32,
+    pub bandwidth: u32,
+    pub spreading_factor: u32,
+    pub code_rate: String,
+    pub payloads: Vec<String>,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    #[serde(with = "humantime_serde")]
+    pub offset: Duration,
+    pub count: u64,
+    pub rssi: i32,
+    pub snr: f32,
+    pub channel: u32,
+}
+
+impl Default for Uplink {
+    fn default() -> Self {
+        Uplink {
+            name: "".to_string(),
+            frequency: 868100000,
+            bandwidth: 125000,
+            spreading_factor: 7,
+            code_rate: "4/5".to_string(),
+            payloads: Vec::new(),
+            interval: Duration::from_secs(10),
+            offset: Duration::from_secs(0),
+            count: 0,
+            rssi: -50,
+            snr: 10.0,
+            channel: 0,
+        }
+    }
+}
+
+fn example_configuration() -> Configuration {
+    Configuration {
+        concentratord: Concentratord {
+            log_level: "INFO".to_string(),
+            stats_interval: Duration::from_secs(30),
+            api: API {
+                event_bind: "ipc:///tmp/concentratord_event".to_string(),
+                command_bind: "ipc:///tmp/concentratord_command".to_string(),
+            },
+        },
+        gateway: Default::default(),
+        uplinks: Vec::new(),
+    }
+}
+
+pub fn get(filenames: Vec<String>) -> Configuration {
+    let mut content: String = String::new();
+
+    for file_name in &filenames {
+        content.push_str(&fs::read_to_string(&file_name).expect("Error reading config file"));
+    }
+
+    let config: Configuration = toml::from_str(&content).expect("Error parsing config file");
+
+    match hex::decode(&config.gateway.gateway_id) {
+        Ok(v) if v.len() == 8 => {}
+        _ => panic!("gateway_id must be a HEX encoded 8 byte EUI"),
+    }
+
+    for uplink in &config.uplinks {
+        if !(5..=12).contains(&uplink.spreading_factor) {
+            panic!(
+                "uplink spreading_factor must be between 5 and 12, uplink: {}",
+                uplink.name
+            );
+        }
+        if uplink.interval.is_zero() {
+            panic!("uplink interval must not be 0, uplink: {}", uplink.name);
+        }
+        if uplink.payloads.is_empty() {
+            panic!("uplink payloads must not be empty, uplink: {}", uplink.name);
+        }
+        for payload in &uplink.payloads {
+            if hex::decode(payload).is_err() {
+                panic!(
+                    "uplink payloads must be HEX encoded, uplink: {}",
+                    uplink.name
+                );
+            }
+        }
+        if let Err(err) = wrapper::code_rate_from_str(&uplink.code_rate) {
+            panic!("{}, uplink: {}", err, uplink.name);
+        }
+    }
+
+    config
+}
@@ -0,0 +1,181 @@
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use libconcentratord::signals::Signal;
+use libconcentratord::{api, blacklist, commands, events, history, jitqueue, shadow, stats};
+use prost::Message;
+
+use super::super::{concentrator, config, wrapper};
+
+pub fn handle_loop(
+    config: &config::Configuration,
+    gateway_id: &[u8],
+    queue: Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
+    rep_sock: zmq::Socket,
+    stop_receive: Receiver<Signal>,
+) {
+    debug!("Starting command handler loop");
+
+    // A timeout is used so that we can consume from the stop signal.
+    let reader = commands::Reader::new(&rep_sock, Duration::from_millis(100));
+
+    for cmd in reader {
+        match stop_receive.recv_timeout(Duration::from_millis(0)) {
+            Ok(v) => {
+                debug!("Received stop signal, signal: {}", v);
+                break;
+            }
+            _ => {}
+        };
+
+        let resp = match cmd {
+            commands::Command::Timeout => {
+                continue;
+            }
+            commands::Command::Downlink(pl) => {
+                match handle_downlink(config, gateway_id, &queue, &pl) {
+                    Ok(v) => v,
+                    Err(_) => Vec::new(),
+                }
+            }
+            commands::Command::GatewayID => gateway_id.to_vec(),
+            commands::Command::Capabilities => events::get_capabilities().encode_to_vec(),
+            commands::Command::Status => shadow::snapshot(&get_status(&queue)).encode_to_vec(),
+            commands::Command::DownlinkHistory(pl) => history::get(gateway_id, &pl).encode_to_vec(),
+            commands::Command::Blacklist => blacklist::get(gateway_id).encode_to_vec(),
+            commands::Command::Tap(pl) => {
+                if let Err(err) = events::start_tap(&pl) {
+                    error!("Tap request error, error: {}", err);
+                }
+                Vec::new()
+            }
+            commands::Command::Error(err) => {
+                error!("Read command error, error: {}", err);
+                Vec::new()
+            }
+            commands::Command::Unknown(command, _) => {
+                warn!("Unknown command received, command: {}", command);
+                Vec::new()
+            }
+            cmd => {
+                warn!(
+                    "Command is not supported by the simulator, command: {}",
+                    cmd
+                );
+                Vec::new()
+            }
+        };
+
+        rep_sock.send(resp, 0).unwrap();
+    }
+
+    debug!("Command loop ended");
+}
+
+fn get_status(queue: &Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>) -> shadow::Status {
+    let queue = queue.lock().unwrap();
+    shadow::Status {
+        gps: None,
+        queue_depth: queue.count(),
+        queue_capacity: queue.size(),
+    }
+}
+
+fn handle_downlink(
+    config: &config::Configuration,
+    gateway_id: &[u8],
+    queue: &Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
+    pl: &chirpstack_api::gw::DownlinkFrame,
+) -> Result<Vec<u8>> {
+    let (tx_ack, details) = schedule_downlink(config, gateway_id, queue, pl)?;
+
+    // The rejection details are appended to the TX acknowledgement, see
+    // api::DownlinkTxAckDetails.
+    let mut b = tx_ack.encode_to_vec();
+    details.encode(&mut b)?;
+    Ok(b)
+}
+
+// Enqueues the first possible downlink item. Returns the TX acknowledgement and the rejection
+// details of the items rejected by the JIT queue.
+fn schedule_downlink(
+    config: &config::Configuration,
+    gateway_id: &[u8],
+    queue: &Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
+    pl: &chirpstack_api::gw::DownlinkFrame,
+) -> Result<(chirpstack_api::gw::DownlinkTxAck, api::DownlinkTxAckDetails)> {
+    stats::inc_tx_packets_received();
+
+    let mut tx_ack = chirpstack_api::gw::DownlinkTxAck {
+        gateway_id: hex::encode(gateway_id),
+        downlink_id: pl.downlink_id,
+        items: vec![Default::default(); pl.items.len()],
+        ..Default::default()
+    };
+    let mut details: api::DownlinkTxAckDetails = Default::default();
+    let mut stats_tx_status = chirpstack_api::gw::TxAckStatus::Ignored;
+
+    for (i, item) in pl.items.iter().enumerate() {
+        let tx_packet = match wrapper::downlink_from_proto(pl.downlink_id, item) {
+            Ok(v) => v,
+            Err(err) => {
+                error!(
+                    "Convert downlink protobuf error, downlink_id: {}, error: {}",
+                    pl.downlink_id, err,
+                );
+                tx_ack.items[i].set_status(chirpstack_api::gw::TxAckStatus::InternalError);
+                stats_tx_status = chirpstack_api::gw::TxAckStatus::InternalError;
+
+                // try next
+                continue;
+            }
+        };
+
+        // validate frequency range
+        if tx_packet.frequency < config.gateway.min_tx_frequency
+            || tx_packet.frequency > config.gateway.max_tx_frequency
+        {
+            error!(
+                "Frequency is not within min/max gateway frequency, downlink_id: {}, min_freq: {}, max_freq: {}",
+                pl.downlink_id, config.gateway.min_tx_frequency, config.gateway.max_tx_frequency
+            );
+            tx_ack.items[i].set_status(chirpstack_api::gw::TxAckStatus::TxFreq);
+            stats_tx_status = chirpstack_api::gw::TxAckStatus::TxFreq;
+
+            // try next
+            continue;
+        }
+
+        // try enqueue
+        match queue
+            .lock()
+            .unwrap()
+            .enqueue(concentrator::get_instcnt(), tx_packet)
+        {
+            Ok(_) => {
+                tx_ack.items[i].set_status(chirpstack_api::gw::TxAckStatus::Ok);
+                stats_tx_status = chirpstack_api::gw::TxAckStatus::Ok;
+
+                // break out of for loop
+                break;
+            }
+            Err(rejection) => {
+                warn!(
+                    "Downlink rejected by JIT queue, downlink_id: {}, reason: {}",
+                    pl.downlink_id, rejection
+                );
+                details.rejections.push(rejection.to_proto(i));
+                tx_ack.items[i].set_status(rejection.status());
+                stats_tx_status = rejection.status();
+            }
+        };
+    }
+
+    stats::inc_tx_status_count(stats_tx_status);
+    stats::inc_tx_rejection_counts(&tx_ack);
+    history::record_tx_ack(pl, &tx_ack);
+
+    Ok((tx_ack, details))
+}
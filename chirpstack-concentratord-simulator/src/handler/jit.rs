@@ -0,0 +1,66 @@
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use libconcentratord::signals::Signal;
+use libconcentratord::{api, events, jitqueue, stats};
+
+use super::super::{concentrator, wrapper};
+
+pub fn jit_loop(
+    gateway_id: &[u8],
+    queue: Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
+    stop_receive: Receiver<Signal>,
+) {
+    debug!("Start JIT queue loop");
+
+    loop {
+        // Instead of a 10ms sleep, we receive from the stop channel with a
+        // timeout of 10ms.
+        match stop_receive.recv_timeout(Duration::from_millis(10)) {
+            Ok(v) => {
+                debug!("Received stop signal, signal: {}", v);
+                break;
+            }
+            _ => {}
+        };
+
+        let tx_packet = match queue.lock().unwrap().pop(concentrator::get_instcnt()) {
+            Some(v) => v,
+            None => continue,
+        };
+
+        info!(
+            "Scheduled packet for simulated TX, downlink_id: {}, count_us: {}, freq: {}, bw: {}, sf: {}, time_on_air: {:?}",
+            tx_packet.id,
+            tx_packet.count_us,
+            tx_packet.frequency,
+            tx_packet.bandwidth,
+            tx_packet.spreading_factor,
+            tx_packet.time_on_air,
+        );
+
+        // Like the concentrator, the packet is handed over before its scheduled time. The
+        // emission is simulated by waiting until the scheduled time has passed, the queue
+        // accounts for the time on air.
+        let remaining = tx_packet.count_us.wrapping_sub(concentrator::get_instcnt());
+        if remaining < (i32::MAX as u32) {
+            thread::sleep(Duration::from_micros(remaining as u64));
+        }
+
+        stats::inc_tx_counts(&wrapper::downlink_to_tx_info_proto(&tx_packet));
+
+        events::send_tx_done(&api::TxDone {
+            gateway_id: hex::encode(gateway_id),
+            downlink_id: tx_packet.id,
+            frequency: tx_packet.frequency,
+            power: tx_packet.power,
+            count_us: tx_packet.count_us,
+            ..Default::default()
+        })
+        .unwrap();
+    }
+
+    debug!("JIT loop ended");
+}
@@ -0,0 +1,4 @@
+pub mod command;
+pub mod jit;
+pub mod stats;
+pub mod uplink;
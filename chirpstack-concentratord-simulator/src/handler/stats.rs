@@ -0,0 +1,31 @@
+use std::collections::HashMap;
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+use libconcentratord::signals::Signal;
+use libconcentratord::stats;
+
+pub fn stats_loop(
+    gateway_id: &[u8],
+    stats_interval: &Duration,
+    stop_receive: Receiver<Signal>,
+    metadata: HashMap<String, String>,
+) {
+    debug!("Starting stats loop, stats_interval: {:?}", stats_interval);
+
+    loop {
+        // Instead of a 'stats interval' sleep, we receive from the stop channel with a
+        // timeout equal to the 'stats interval'.
+        match stop_receive.recv_timeout(*stats_interval) {
+            Ok(v) => {
+                debug!("Received stop signal, signal: {}", v);
+                break;
+            }
+            _ => {}
+        };
+
+        stats::send_and_reset(gateway_id, None, &metadata).expect("sending stats failed");
+    }
+
+    debug!("Stats loop ended");
+}
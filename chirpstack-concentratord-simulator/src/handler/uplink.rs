@@ -0,0 +1,88 @@
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+use libconcentratord::signals::Signal;
+use libconcentratord::{airtime, events, stats};
+
+use super::super::{concentrator, config, wrapper};
+
+// State of a scenario uplink.
+struct Generator {
+    uplink: config::Uplink,
+    payloads: Vec<Vec<u8>>,
+    next: Instant,
+    sent: u64,
+}
+
+pub fn handle_loop(gateway_id: &[u8], uplinks: &[config::Uplink], stop_receive: Receiver<Signal>) {
+    debug!("Starting uplink handle loop");
+
+    let start = Instant::now();
+    let mut generators: Vec<Generator> = uplinks
+        .iter()
+        .map(|v| Generator {
+            uplink: v.clone(),
+            // The payloads are validated when loading the configuration.
+            payloads: v.payloads.iter().map(|v| hex::decode(v).unwrap()).collect(),
+            next: start + v.offset,
+            sent: 0,
+        })
+        .collect();
+
+    loop {
+        // Instead of a 10ms sleep, we receive from the stop channel with a
+        // timeout of 10ms.
+        match stop_receive.recv_timeout(Duration::from_millis(10)) {
+            Ok(v) => {
+                debug!("Received stop signal, signal: {}", v);
+                break;
+            }
+            _ => {}
+        };
+
+        let now = Instant::now();
+        for g in generators.iter_mut() {
+            if (g.uplink.count != 0 && g.sent >= g.uplink.count) || g.next > now {
+                continue;
+            }
+
+            // The payloads are sent round-robin.
+            let payload = &g.payloads[(g.sent % g.payloads.len() as u64) as usize];
+            g.sent += 1;
+            g.next += g.uplink.interval;
+
+            send_uplink(gateway_id, &g.uplink, payload);
+        }
+    }
+
+    debug!("Uplink loop ended");
+}
+
+fn send_uplink(gateway_id: &[u8], uplink: &config::Uplink, payload: &[u8]) {
+    let count_us = concentrator::get_instcnt();
+    let mut proto = match wrapper::uplink_to_proto(gateway_id, uplink, payload, count_us) {
+        Ok(v) => v,
+        Err(err) => {
+            error!("Convert uplink frame to protobuf error, error: {}", err);
+            return;
+        }
+    };
+
+    airtime::annotate(&mut proto);
+
+    let rx_info = proto.rx_info.as_ref().unwrap();
+
+    info!(
+        "Simulated frame received, name: {}, uplink_id: {}, count_us: {}, freq: {}, bw: {}, sf: {}",
+        uplink.name,
+        rx_info.uplink_id,
+        count_us,
+        uplink.frequency,
+        uplink.bandwidth,
+        uplink.spreading_factor,
+    );
+
+    stats::inc_rx_packets_received();
+    stats::inc_rx_counts(&proto);
+    events::send_uplink(&proto).unwrap();
+}
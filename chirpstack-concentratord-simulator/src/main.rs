@@ -0,0 +1,83 @@
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate anyhow;
+extern crate simple_logger;
+
+use std::process;
+use std::str::FromStr;
+use std::sync::mpsc::channel;
+use std::thread;
+
+use clap::{Parser, Subcommand};
+use signal_hook::consts::signal::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use simple_logger::SimpleLogger;
+
+use libconcentratord::signals::Signal;
+use libconcentratord::{logging, state};
+
+mod cmd;
+mod concentrator;
+mod config;
+mod handler;
+mod wrapper;
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    #[arg(short, long, value_name = "FILE")]
+    config: Vec<String>,
+
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Print the configuration template
+    Configfile {},
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let config = config::get(cli.config.clone());
+
+    if let Some(Commands::Configfile {}) = &cli.command {
+        cmd::configfile::run(&config);
+        process::exit(0);
+    }
+
+    let log_level = log::Level::from_str(&config.concentratord.log_level)
+        .unwrap()
+        .to_level_filter();
+    // The level is filtered by the logging wrapper, see libconcentratord::logging.
+    let logger = Box::new(
+        SimpleLogger::new()
+            .with_utc_timestamps()
+            .with_level(log::LevelFilter::Trace),
+    );
+    logging::init(logger, log_level, log::LevelFilter::Off).expect("setup logger error");
+
+    let mut signals = Signals::new(&[SIGINT, SIGTERM]).expect("error registering channels");
+    let (stop_send, stop_receive) = channel();
+
+    thread::spawn(move || {
+        let mut stopping = false;
+        for _ in signals.forever() {
+            if stopping {
+                warn!("Received stop signal, terminating simulator immediately");
+                process::exit(0);
+            }
+
+            warn!("Received stop signal, stopping simulator");
+            stop_send.send(Signal::Stop).unwrap();
+            stopping = true;
+        }
+    });
+
+    cmd::root::run(&config, stop_receive).expect("run simulator error");
+    state::shutdown();
+}
@@ -0,0 +1,204 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use chirpstack_api::gw;
+use libconcentratord::{airtime, jitqueue};
+use rand::Rng;
+
+use super::config;
+
+/// Simulated downlink. Unlike the HAL packets, this only contains the fields which are needed
+/// for scheduling and for the TX acknowledgement.
+#[derive(Copy, Clone)]
+pub struct TxPacket {
+    pub id: u32,
+    pub tx_mode: jitqueue::TxMode,
+    pub count_us: u32,
+    pub frequency: u32,
+    pub power: i32,
+    pub bandwidth: u32,
+    pub spreading_factor: u32,
+    pub code_rate: gw::CodeRate,
+    pub time_on_air: Duration,
+}
+
+impl jitqueue::TxPacket for TxPacket {
+    fn get_time_on_air(&self) -> Result<Duration> {
+        Ok(self.time_on_air)
+    }
+
+    fn get_tx_mode(&self) -> jitqueue::TxMode {
+        self.tx_mode
+    }
+
+    fn set_tx_mode(&mut self, tx_mode: jitqueue::TxMode) {
+        self.tx_mode = tx_mode;
+    }
+
+    fn get_count_us(&self) -> u32 {
+        self.count_us
+    }
+
+    fn set_count_us(&mut self, count_us: u32) {
+        self.count_us = count_us;
+    }
+
+    fn get_id(&self) -> u32 {
+        self.id
+    }
+
+    fn get_frequency(&self) -> u32 {
+        self.frequency
+    }
+}
+
+/// Parses the code rate, e.g. 4/5.
+pub fn code_rate_from_str(s: &str) -> Result<gw::CodeRate> {
+    Ok(match s {
+        "4/5" => gw::CodeRate::Cr45,
+        "4/6" => gw::CodeRate::Cr46,
+        "4/7" => gw::CodeRate::Cr47,
+        "4/8" => gw::CodeRate::Cr48,
+        _ => return Err(anyhow!("unexpected code_rate: {}", s)),
+    })
+}
+
+// Returns the code rate as used by the time on air calculation.
+fn code_rate_to_int(code_rate: gw::CodeRate) -> Result<u32> {
+    Ok(match code_rate {
+        gw::CodeRate::Cr45 | gw::CodeRate::CrLi45 => 1,
+        gw::CodeRate::Cr46 | gw::CodeRate::CrLi46 => 2,
+        gw::CodeRate::Cr47 => 3,
+        gw::CodeRate::Cr48 | gw::CodeRate::CrLi48 => 4,
+        _ => return Err(anyhow!("unexpected code_rate: {:?}", code_rate)),
+    })
+}
+
+pub fn uplink_to_proto(
+    gateway_id: &[u8],
+    uplink: &config::Uplink,
+    payload: &[u8],
+    count_us: u32,
+) -> Result<gw::UplinkFrame> {
+    let mut rng = rand::thread_rng();
+    let time = SystemTime::now().duration_since(UNIX_EPOCH)?;
+
+    Ok(gw::UplinkFrame {
+        phy_payload: payload.to_vec(),
+        tx_info: Some(gw::UplinkTxInfo {
+            frequency: uplink.frequency,
+            modulation: Some(gw::Modulation {
+                parameters: Some(gw::modulation::Parameters::Lora(gw::LoraModulationInfo {
+                    bandwidth: uplink.bandwidth,
+                    spreading_factor: uplink.spreading_factor,
+                    code_rate: code_rate_from_str(&uplink.code_rate)?.into(),
+                    ..Default::default()
+                })),
+            }),
+        }),
+        rx_info: Some(gw::UplinkRxInfo {
+            uplink_id: rng.gen(),
+            context: count_us.to_be_bytes().to_vec(),
+            gateway_id: hex::encode(gateway_id),
+            rssi: uplink.rssi,
+            snr: uplink.snr,
+            channel: uplink.channel,
+            time: Some(pbjson_types::Timestamp {
+                seconds: time.as_secs() as i64,
+                nanos: time.subsec_nanos() as i32,
+            }),
+            metadata: vec![("simulated".to_string(), "true".to_string())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        }),
+        ..Default::default()
+    })
+}
+
+pub fn downlink_from_proto(downlink_id: u32, df: &gw::DownlinkFrameItem) -> Result<TxPacket> {
+    let tx_info = match df.tx_info.as_ref() {
+        Some(v) => v,
+        None => return Err(anyhow!("tx_info must not be blank")),
+    };
+
+    let (bandwidth, spreading_factor, code_rate) = match tx_info
+        .modulation
+        .as_ref()
+        .and_then(|v| v.parameters.as_ref())
+    {
+        Some(gw::modulation::Parameters::Lora(v)) => {
+            (v.bandwidth, v.spreading_factor, v.code_rate())
+        }
+        _ => return Err(anyhow!("only LORA modulation is implemented")),
+    };
+    if !(5..=12).contains(&spreading_factor) {
+        return Err(anyhow!("unexpected spreading-factor"));
+    }
+    if bandwidth == 0 {
+        return Err(anyhow!("bandwidth must not be 0"));
+    }
+
+    let (tx_mode, count_us) = match tx_info.timing.as_ref().and_then(|v| v.parameters.as_ref()) {
+        Some(gw::timing::Parameters::Immediately(_)) => (jitqueue::TxMode::Immediate, 0),
+        Some(gw::timing::Parameters::Delay(v)) => {
+            let ctx = &tx_info.context;
+            if ctx.len() != 4 {
+                return Err(anyhow!("context must be exactly 4 bytes"));
+            }
+
+            let delay = match &v.delay {
+                Some(v) => {
+                    Duration::from_secs(v.seconds as u64) + Duration::from_nanos(v.nanos as u64)
+                }
+                None => return Err(anyhow!("delay must not be null")),
+            };
+
+            let mut array = [0; 4];
+            array.copy_from_slice(ctx);
+            (
+                jitqueue::TxMode::Timestamped,
+                u32::from_be_bytes(array).wrapping_add(delay.as_micros() as u32),
+            )
+        }
+        Some(gw::timing::Parameters::GpsEpoch(_)) => {
+            return Err(anyhow!("gps epoch timing is not implemented"));
+        }
+        None => return Err(anyhow!("timing must not be blank")),
+    };
+
+    Ok(TxPacket {
+        id: downlink_id,
+        tx_mode,
+        count_us,
+        frequency: tx_info.frequency,
+        power: tx_info.power,
+        bandwidth,
+        spreading_factor,
+        code_rate,
+        time_on_air: airtime::lora(
+            df.phy_payload.len(),
+            bandwidth,
+            spreading_factor,
+            code_rate_to_int(code_rate)?,
+            airtime::LORA_PREAMBLE_SYMBOLS,
+            false,
+        ),
+    })
+}
+
+pub fn downlink_to_tx_info_proto(packet: &TxPacket) -> gw::DownlinkTxInfo {
+    gw::DownlinkTxInfo {
+        frequency: packet.frequency,
+        power: packet.power,
+        modulation: Some(gw::Modulation {
+            parameters: Some(gw::modulation::Parameters::Lora(gw::LoraModulationInfo {
+                bandwidth: packet.bandwidth,
+                spreading_factor: packet.spreading_factor,
+                code_rate: packet.code_rate.into(),
+                ..Default::default()
+            })),
+        }),
+        ..Default::default()
+    }
+}
@@ -5,6 +5,10 @@ use super::super::config;
 pub fn run(config: &config::Configuration) {
     let template = r#"
 # Concentratord configuration.
+#
+# The configuration is reloaded on SIGHUP or on the reload command. The
+# concentrator is only re-initialized when the radio configuration has
+# changed, the API sockets are kept open.
 [concentratord]
   # Log level.
   #
@@ -20,11 +24,40 @@ pub fn run(config: &config::Configuration) {
   # Log to syslog.
   #
   # When set to true, log messages are being written to syslog instead of stdout.
+  # This is ignored when logging.output is set.
   log_to_syslog={{ concentratord.log_to_syslog }}
 
+  # systemd notifications.
+  #
+  # When set to true, Concentratord notifies systemd (Type=notify) once the
+  # concentrator is started and the API sockets are bound. When the unit has
+  # WatchdogSec set, the watchdog is pinged from the uplink loop, such that a
+  # hanging concentrator (e.g. on SPI errors) results in a restart by systemd.
+  # Note that the watchdog timeout must allow for the concentrator
+  # (re-)initialization.
+  systemd_notify={{ concentratord.systemd_notify }}
+
   # Statistics interval.
+  #
+  # The offset between the requested GPS time and the TX start of GPS timed
+  # downlinks is not measured by this Concentratord, the
+  # gps_tx_offset_supported meta-data value is set to false.
   stats_interval="{{ concentratord.stats_interval }}"
 
+  # JIT queue scheduler.
+  #
+  # Options are:
+  #   FIFO           - Downlinks are scheduled in the order they are received
+  #                    and a downlink overlapping a queued downlink is rejected
+  #                    (default).
+  #   PRIORITY_EVICT - A timestamped downlink (e.g. class-A) overlapping a queued
+  #                    immediate downlink evicts the latter, which is re-planned
+  #                    after it.
+  #   DUTY_CYCLE     - Immediate downlinks exceeding the duty-cycle budget are
+  #                    delayed until the budget allows these, instead of being
+  #                    rejected.
+  jit_scheduler="{{ concentratord.jit_scheduler }}"
+
   # Configuration for the (ZeroMQ based) API.
   [concentratord.api]
     # Event PUB socket bind.
@@ -33,6 +66,579 @@ pub fn run(config: &config::Configuration) {
     # Command REP socket bind.
     command_bind="{{ concentratord.api.command_bind }}"
 
+    # Event sequence numbers.
+    #
+    # When enabled, each event is published with an additional frame containing
+    # a monotonically increasing sequence number (64 bit, big endian). This can
+    # be used by subscribers to detect dropped events.
+    event_sequence={{ concentratord.api.event_sequence }}
+
+    # Event compression.
+    #
+    # Compression applied to the event payloads, this can reduce the bandwidth
+    # when the events are consumed over a constrained link. Subscribers can
+    # retrieve the used compression using the 'capabilities' command. Options:
+    #   NONE - No compression
+    #   ZSTD - Zstandard compression
+    event_compression="{{ concentratord.api.event_compression }}"
+
+    # Event encryption key.
+    #
+    # When set (HEX encoded, 32 bytes), the event payloads are encrypted using
+    # AES-256-GCM with this pre-shared key, after compression. The encrypted
+    # payload contains the 12 byte nonce, followed by the ciphertext and tag.
+    # The event topic is used as associated data. This is intended for
+    # deployments where the event socket crosses an untrusted network segment
+    # and CURVE can't be used.
+    event_encryption_key="{{ concentratord.api.event_encryption_key }}"
+
+    # CURVE secret key.
+    #
+    # When set (Z85 encoded), the event and command sockets are bound as ZeroMQ
+    # CURVE server, such that the connections are authenticated and encrypted.
+    # The clients must be configured with the matching server public key. A key
+    # pair can be generated using the --generate-keys flag. Changing this
+    # setting requires a restart.
+    curve_secret_key="{{ concentratord.api.curve_secret_key }}"
+
+    # CURVE client keys.
+    #
+    # The Z85 encoded public keys of the clients that are allowed to connect.
+    # When empty, every client that knows the server public key can connect.
+    curve_client_keys=[{{#each concentratord.api.curve_client_keys}}"{{ this }}",{{/each}}]
+
+    # Allowed commands.
+    #
+    # When set, only the listed commands are accepted on the command socket
+    # (e.g. ["gateway_id", "capabilities"]). When empty, all commands are
+    # accepted.
+    allowed_commands=[{{#each concentratord.api.allowed_commands}}"{{ this }}",{{/each}}]
+
+    # Read-only mode.
+    #
+    # When enabled, the commands changing the state of the concentrator (down,
+    # config, beacon, txtest, sync_tx, reload, timesync, diag_bundle and
+    # spectral_scan) are rejected. This is intended for gateways deployed as
+    # listen-only sensors.
+    read_only={{ concentratord.api.read_only }}
+
+    # Log event level.
+    #
+    # When set (ERROR, WARN, INFO, DEBUG or TRACE), the log messages up to this
+    # level are also published as 'log' events. Events are published with the
+    # event type as ZeroMQ topic (up, stats, txdone, state, error, txtest and
+    # log), so that subscribers can subscribe to a subset of the events.
+    log_event_level="{{ concentratord.api.log_event_level }}"
+
+    # Uplink event mode.
+    #
+    # This can be used to reduce the bandwidth of gateways with a constrained
+    # backhaul (e.g. satellite). Options:
+    #   FULL          - Publish the complete uplink frames
+    #   METADATA_ONLY - Publish the uplink frames without PHYPayload
+    uplink_mode="{{ concentratord.api.uplink_mode }}"
+
+    # Uplink event sampling.
+    #
+    # When set to N (> 1), only one out of every N uplinks is published. The
+    # uplink statistics are not affected. Use the capture file to retain all
+    # the uplink frames locally.
+    uplink_sampling={{ concentratord.api.uplink_sampling }}
+
+    # NMEA passthrough socket.
+    #
+    # When set, the raw NMEA sentences read from the GPS are re-served on this
+    # Unix socket (e.g. /tmp/concentratord_nmea), so that other services can
+    # consume the GNSS data while Concentratord owns the GPS tty.
+    nmea_socket="{{ concentratord.api.nmea_socket }}"
+
+    # Command rate limits.
+    #
+    # Token-bucket rate limit per command, to protect Concentratord against
+    # misbehaving clients. The rate is the number of commands per second, the
+    # burst is the max. number of commands that can be received at once.
+    # Commands exceeding the rate limit are answered with an empty response
+    # (logged as THROTTLED in the audit log). Commands without rate limit are
+    # not throttled. Example:
+    #
+    # [concentratord.api.rate_limits]
+    #   down={ rate=10.0, burst=20 }
+    [concentratord.api.rate_limits]
+{{#each concentratord.api.rate_limits}}
+      {{ @key }}={ rate={{ this.rate }}, burst={{ this.burst }} }
+{{/each}}
+
+  # Remote configuration.
+  #
+  # When an url is configured, Concentratord periodically fetches a signed
+  # configuration bundle from this url. The gateway ID is added to the url as
+  # gateway_id query parameter. The bundle can contain the channel plan and the
+  # log level and is only applied when its version differs from the applied
+  # version. The applied version is reported in the stats meta-data as
+  # remote_config_version.
+  [concentratord.remote_config]
+    # HTTPS url of the configuration bundle.
+    url="{{ concentratord.remote_config.url }}"
+
+    # Public key (HEX encoded Ed25519 public key) to validate the signature of
+    # the configuration bundle.
+    public_key="{{ concentratord.remote_config.public_key }}"
+
+    # Fetch interval.
+    interval="{{ concentratord.remote_config.interval }}"
+
+  # Audit log.
+  #
+  # When a path is configured, every command received on the command socket
+  # is written to this file, together with its outcome (OK, ERROR, REJECTED,
+  # UNKNOWN or NOT_SUPPORTED). When CURVE is enabled, the public key of the
+  # client is logged as identity.
+  [concentratord.audit_log]
+    # Path of the audit log file.
+    path="{{ concentratord.audit_log.path }}"
+
+    # Max. size (bytes) of the audit log file.
+    #
+    # Once this size is exceeded, the file is rotated (e.g. audit.log is
+    # renamed to audit.log.1). Set this to 0 to disable rotation.
+    max_size={{ concentratord.audit_log.max_size }}
+
+    # Number of rotated audit log files to keep.
+    max_files={{ concentratord.audit_log.max_files }}
+
+
+  # Uplink capture file.
+  #
+  # When the path is set, every received uplink frame is written to this file,
+  # regardless of the uplink event mode and sampling. Frames are stored as
+  # length-delimited Protobuf UplinkFrame messages.
+  [concentratord.capture]
+    # Path of the capture file.
+    path="{{ concentratord.capture.path }}"
+
+    # Max. size (bytes) of the capture file.
+    #
+    # Once this size is exceeded, the file is rotated (e.g. uplink.cap is
+    # renamed to uplink.cap.1). Set this to 0 to disable rotation.
+    max_size={{ concentratord.capture.max_size }}
+
+    # Number of rotated capture files to keep.
+    max_files={{ concentratord.capture.max_files }}
+
+
+  # Stats meta-data commands.
+  #
+  # The stdout of each command is merged into the stats meta-data on every
+  # stats interval. The output must be a JSON object or key=value lines. The
+  # keys are prefixed by the command name, e.g. a modem command printing
+  # signal_quality=80 results in modem_signal_quality=80. Example:
+  #
+  # [concentratord.stats_metadata.commands]
+  #   modem=["/usr/local/bin/modem-signal", "--modem", "0"]
+  [concentratord.stats_metadata]
+    # Max. time to wait for a command, before it is killed.
+    timeout="{{ concentratord.stats_metadata.timeout }}"
+
+    [concentratord.stats_metadata.commands]
+{{#each concentratord.stats_metadata.commands}}
+      {{ @key }}=[{{#each this}}"{{ this }}",{{/each}}]
+{{/each}}
+
+
+  # Backhaul latency probe.
+  #
+  # When a target is set, the round-trip latency to the target is measured on
+  # every stats interval and reported in the stats meta-data as
+  # backhaul_latency_ms. When the probe fails, the error is reported as
+  # backhaul_latency_error. This helps to correlate downlinks which are
+  # rejected as too late with a degraded backhaul.
+  [concentratord.latency_probe]
+    # Probe method.
+    #
+    # Valid options are:
+    #   * TCP:  Measure the time to connect to the target (host:port).
+    #   * ICMP: Measure the time of an echo request to the target (host),
+    #           using the system ping command.
+    method="{{ concentratord.latency_probe.method }}"
+
+    # Probe target, e.g. the ChirpStack MQTT broker (example.com:8883).
+    target="{{ concentratord.latency_probe.target }}"
+
+    # Max. time to wait for the probe to complete.
+    timeout="{{ concentratord.latency_probe.timeout }}"
+
+
+  # Metered backhaul.
+  #
+  # When the backhaul is metered (e.g. cellular), the optional events are
+  # suppressed to reduce the data usage: the log events and the output of the
+  # stats meta-data commands. Uplinks and all other events are always
+  # published. The state is reported in the stats meta-data as
+  # backhaul_metered.
+  [concentratord.metered]
+    # Metered flag file.
+    #
+    # When set, the backhaul is metered when this file contains 1, true or
+    # yes. A missing file means not metered.
+    file="{{ concentratord.metered.file }}"
+
+    # Read the metered state from NetworkManager.
+    #
+    # When enabled, the Metered property of NetworkManager is read using
+    # busctl. The backhaul is metered when this property is yes or guess-yes.
+    network_manager={{ concentratord.metered.network_manager }}
+
+    # Interval at which the metered state is checked.
+    interval="{{ concentratord.metered.interval }}"
+
+
+  # Concentrator health monitoring.
+  #
+  # When enabled, the concentrator is automatically restarted (stop, reset
+  # sequence and start) when one of the below thresholds is exceeded. On
+  # restart, a restart event is published and the restarts are reported in
+  # the stats meta-data (health_restart_count and the health_restart_*
+  # counters).
+  [concentratord.health]
+    # Enable health monitoring.
+    enabled={{ concentratord.health.enabled }}
+
+    # Max. number of consecutive HAL errors.
+    #
+    # Set to 0 to disable.
+    max_hal_errors={{ concentratord.health.max_hal_errors }}
+
+    # Max. duration without any received frame.
+    #
+    # Only use this in a busy band, in which frames are expected to be
+    # received continuously. Set to 0s to disable.
+    rx_timeout="{{ concentratord.health.rx_timeout }}"
+
+    # Max. duration without the concentrator counter advancing.
+    #
+    # The SX1301 counter is latched on the GPS PPS, this detects a PPS signal
+    # that stopped. Only enable this when the gateway has a GPS. Set to 0s to
+    # disable.
+    counter_stall_timeout="{{ concentratord.health.counter_stall_timeout }}"
+
+
+  # Concentrator clock drift.
+  #
+  # The drift between the concentrator counter and the host clock is reported
+  # in the stats meta-data (concentrator_drift_ppm).
+  [concentratord.clock_drift]
+    # Drift (ppm) above which a warning is logged.
+    #
+    # This is also the max. drift that is assumed when extrapolating the last
+    # known GPS time reference.
+    threshold_ppm={{ concentratord.clock_drift.threshold_ppm }}
+
+    # Duration over which the drift is estimated.
+    #
+    # The drift is estimated using a linear regression over the samples within
+    # this duration. A short baseline is dominated by the jitter of reading the
+    # concentrator counter.
+    baseline="{{ concentratord.clock_drift.baseline }}"
+
+
+  # Network server assisted time-sync.
+  #
+  # For gateways with neither a GPS nor NTP. When enabled, a timesync event
+  # containing the concentrator counter and the host time is periodically
+  # published. The network server answers this event using the timesync
+  # command, containing its receive and transmit time, from which the offset of
+  # the host clock is calculated. The corrected host clock is then used for
+  # the uplink time (when the uplink_time source is SYSTEM) and the stats and
+  # event timestamps. When the gateway has a GPS, the GPS time is used for the
+  # latter instead.
+  [concentratord.time_sync]
+    # Enable network server assisted time-sync.
+    enabled={{ concentratord.time_sync.enabled }}
+
+    # Interval between two timesync events.
+    interval="{{ concentratord.time_sync.interval }}"
+
+    # Max. round-trip time.
+    #
+    # Answers with a larger round-trip time (excluding the processing time of
+    # the network server) are rejected, as the accuracy of the offset is half
+    # the round-trip time.
+    max_rtt="{{ concentratord.time_sync.max_rtt }}"
+
+
+  # Shadow state file.
+  #
+  # When the path is set, a compact JSON document with the current state of
+  # the gateway (state, configuration hash, last uplink, GPS, JIT queue depth
+  # and the last stats) is periodically written to this file. Use a tmpfs path
+  # (e.g. /tmp/concentratord_shadow.json) to avoid flash wear.
+  [concentratord.shadow]
+    # Path of the shadow state file.
+    path="{{ concentratord.shadow.path }}"
+
+    # Interval at which the shadow state file is written.
+    interval="{{ concentratord.shadow.interval }}"
+
+
+  # Join-request flood detection.
+  #
+  # When enabled, the join-requests are grouped by the DevEUI or JoinEUI
+  # prefix and the number of join-requests per prefix is limited to
+  # max_join_requests per interval. Join-requests exceeding this rate are
+  # counted (join_requests_excess) and the join_flood stats meta-data flag is
+  # set. This protects the backhaul and the network server against a
+  # misbehaving or malicious device.
+  [concentratord.join_flood]
+    # Enable join-request flood detection.
+    enabled={{ concentratord.join_flood.enabled }}
+
+    # Key by which the join-requests are grouped.
+    #
+    # Valid options are:
+    #   * DEV_EUI
+    #   * JOIN_EUI
+    key="{{ concentratord.join_flood.key }}"
+
+    # Number of (most significant) bits of the EUI prefix.
+    #
+    # Use 64 to track each EUI individually.
+    prefix_bits={{ concentratord.join_flood.prefix_bits }}
+
+    # Max. number of join-requests per prefix within the interval.
+    max_join_requests={{ concentratord.join_flood.max_join_requests }}
+
+    # Interval.
+    interval="{{ concentratord.join_flood.interval }}"
+
+    # Rate-limit the join-requests.
+    #
+    # When enabled, the join-requests exceeding the rate are not forwarded,
+    # these are counted as join_requests_dropped.
+    limit={{ concentratord.join_flood.limit }}
+
+
+  # Transmitter blacklist.
+  #
+  # When enabled, the data uplinks are tracked by DevAddr and checked for
+  # malformed frames, without validating the MIC: an invalid MHDR, a frame
+  # which is too short for its FHDR, both FOpts and FPort 0, or an FCnt jump
+  # exceeding max_fcnt_gap. A DevAddr sending max_malformed malformed frames
+  # within the interval is blacklisted (warning log), the number of
+  # blacklisted DevAddrs is reported as blacklist_size stats meta-data and
+  # the blacklist can be retrieved using the blacklist command. As a DevAddr
+  # is not unique, devices sharing a DevAddr might cause FCnt jumps.
+  [concentratord.blacklist]
+    # Enable the transmitter blacklist.
+    enabled={{ concentratord.blacklist.enabled }}
+
+    # Max. FCnt increment between two uplinks of the same DevAddr.
+    #
+    # Only the 16 LSB of the FCnt are sent by the device.
+    max_fcnt_gap={{ concentratord.blacklist.max_fcnt_gap }}
+
+    # Number of malformed uplinks within the interval after which the DevAddr
+    # is blacklisted.
+    max_malformed={{ concentratord.blacklist.max_malformed }}
+
+    # Interval.
+    interval="{{ concentratord.blacklist.interval }}"
+
+    # Expiry.
+    #
+    # A DevAddr is removed from the blacklist when it did not send a malformed
+    # uplink for this duration.
+    expiry="{{ concentratord.blacklist.expiry }}"
+
+    # Rate-limit the uplinks of blacklisted DevAddrs.
+    #
+    # When enabled, only max_uplinks uplinks per interval are forwarded for a
+    # blacklisted DevAddr, the other uplinks are counted as
+    # uplinks_blacklisted_dropped.
+    limit={{ concentratord.blacklist.limit }}
+
+    # Max. number of forwarded uplinks per interval of a blacklisted DevAddr.
+    max_uplinks={{ concentratord.blacklist.max_uplinks }}
+
+
+  # Oversized uplinks.
+  #
+  # Uplinks which exceed the max. PHYPayload size for their data-rate are
+  # usually interference artifacts reported by the HAL. The max. size of the
+  # gateway.region is used, or the LoRaWAN max. of 255 bytes when the region
+  # is not known. These are counted
+  # (rx_oversized) in the stats meta-data.
+  [concentratord.oversized_uplinks]
+
+    # Action.
+    #
+    # Valid options are:
+    #   * FORWARD: the uplink is forwarded as-is
+    #   * TRUNCATE: the PHYPayload is truncated to the max. size, the original
+    #     size is added to the uplink meta-data (phy_payload_truncated). These
+    #     are counted as rx_oversized_truncated.
+    #   * DROP: the uplink is not forwarded, these are counted as
+    #     rx_oversized_dropped.
+    #
+    # Note that private networks (lorawan_public=false) might use payloads
+    # exceeding the LoRaWAN max. size.
+    action="{{ concentratord.oversized_uplinks.action }}"
+
+
+  # Uplink filter.
+  #
+  # When set, the uplinks belonging to other networks are not published (and
+  # not forwarded by the packet-forwarder compatibility). The data frames are
+  # filtered by the NetID of their DevAddr, the join-requests by their JoinEUI.
+  # The filtered frames are counted (uplink_filtered_net_id and
+  # uplink_filtered_join_eui) in the stats meta-data. Example:
+  #
+  # net_id_allow=["000013"]
+  # join_eui_deny=["0000000000000000-00000000000000ff", "0102030405060708"]
+  [concentratord.uplink_filter]
+    # When not empty, only the data frames of these NetIDs are published.
+    net_id_allow=[{{#each concentratord.uplink_filter.net_id_allow}}"{{ this }}",{{/each}}]
+
+    # The data frames of these NetIDs are not published.
+    net_id_deny=[{{#each concentratord.uplink_filter.net_id_deny}}"{{ this }}",{{/each}}]
+
+    # When not empty, only the join-requests of these JoinEUI ranges are
+    # published.
+    join_eui_allow=[{{#each concentratord.uplink_filter.join_eui_allow}}"{{ this }}",{{/each}}]
+
+    # The join-requests of these JoinEUI ranges are not published.
+    join_eui_deny=[{{#each concentratord.uplink_filter.join_eui_deny}}"{{ this }}",{{/each}}]
+
+    # Debug mode.
+    #
+    # When enabled, the frames are published instead of dropped, annotated with
+    # the name of the filter by which they would have been dropped (uplink_filter
+    # meta-data). The lists and thresholds are named net_id, join_eui and
+    # thresholds. The frames are still counted in the stats meta-data.
+    debug={{ concentratord.uplink_filter.debug }}
+
+    # Publish thresholds.
+    #
+    # Per modulation (LORA, FSK or LR_FHSS), frames with an SNR (dB) below
+    # min_snr or an RSSI (dBm) below min_rssi are counted, but not published.
+    # This can be used to suppress the noise-floor frames e.g. received on the
+    # FSK channel. The suppressed frames are counted (uplink_suppressed_snr and
+    # uplink_suppressed_rssi) in the stats meta-data. Example:
+    #
+    # [concentratord.uplink_filter.thresholds]
+    #   FSK={ min_snr=0.0, min_rssi=-110 }
+    [concentratord.uplink_filter.thresholds]
+{{#each concentratord.uplink_filter.thresholds}}
+      {{ @key }}={ min_snr={{ this.min_snr }}, min_rssi={{ this.min_rssi }} }
+{{/each}}
+
+    # Filter chain.
+    #
+    # The filters are applied in order after the above lists and thresholds, a
+    # frame is dropped by the first filter that does not allow it. Each filter
+    # has a unique name, the dropped frames are counted per filter
+    # (uplink_filtered_<name>) in the stats meta-data. Filters:
+    #
+    #   NET_ID     Data frames of which the DevAddr belongs to one of the NetIDs
+    #              (values).
+    #   DEV_ADDR   Data frames of which the DevAddr matches one of the prefixes
+    #              (values, e.g. 26000000/7).
+    #   JOIN_EUI   Join-requests of which the JoinEUI is within one of the
+    #              ranges (values).
+    #   SIZE       Frames of which the PHYPayload size is within min and max
+    #              (max is not used when 0).
+    #   SNR        Frames with an SNR (dB) of at least min.
+    #   RSSI       Frames with an RSSI (dBm) of at least min.
+    #   DEDUP      Frames of which the same PHYPayload was already received within
+    #              the window.
+    #
+    # The SNR and RSSI filters can be limited to a modulation (LORA, FSK or
+    # LR_FHSS). With the ALLOW action, the frames to which the filter applies but
+    # which do not match are dropped. With the DENY action, the matching frames
+    # are dropped. This can be repeated for each filter, e.g.:
+    #
+    # [[concentratord.uplink_filter.chain]]
+    #   name="own_devices"
+    #   filter="DEV_ADDR"
+    #   action="ALLOW"
+    #   values=["26000000/7"]
+    #
+    # [[concentratord.uplink_filter.chain]]
+    #   name="duplicates"
+    #   filter="DEDUP"
+    #   action="DENY"
+    #   window="200ms"
+{{#each concentratord.uplink_filter.chain}}
+    [[concentratord.uplink_filter.chain]]
+      name="{{ this.name }}"
+      filter="{{ this.filter }}"
+      action="{{ this.action }}"
+      values=[{{#each this.values}}"{{ this }}",{{/each}}]
+      modulation="{{ this.modulation }}"
+      min={{ this.min }}
+      max={{ this.max }}
+      window="{{ this.window }}"
+{{/each}}
+
+
+  # Persistent storage.
+  #
+  # The storage is used to persist state across restarts, e.g. the lifetime
+  # counters which are reported in the stats meta-data (lifetime_rx_received,
+  # lifetime_rx_received_ok, lifetime_tx_received and lifetime_tx_emitted).
+  # The last known GNSS fix is also persisted, this is reported in the stats
+  # after a restart while waiting for a new fix, flagged as stale by the
+  # gnss_fix_stale=true meta-data.
+  # When the backend is empty, nothing is persisted.
+  [concentratord.storage]
+    # Backend.
+    #
+    # Valid options are:
+    #   * FILE: each key is stored as a file within the path directory
+    #   * SQLITE: the keys are stored in the path SQLite database (this
+    #     requires concentratord to be compiled with the sqlite feature)
+    backend="{{ concentratord.storage.backend }}"
+
+    # Path (directory for FILE, database file for SQLITE).
+    path="{{ concentratord.storage.path }}"
+
+    # Fsync policy.
+    #
+    # Valid options are:
+    #   * ALWAYS: every write is flushed to the storage device, this survives
+    #     a power loss at the cost of flash wear
+    #   * NEVER: flushing is left to the OS
+    fsync="{{ concentratord.storage.fsync }}"
+
+    # Persist the JIT queue.
+    #
+    # When enabled, the queued downlinks are persisted on shutdown and are
+    # re-scheduled on the next startup, once the GPS time reference is valid.
+    # Downlinks that can not be converted to GPS time, or of which the TX time
+    # has passed, are dropped. This requires a GPS and a storage backend.
+    persist_jit_queue={{ concentratord.storage.persist_jit_queue }}
+
+  # Downlink history.
+  #
+  # A bounded in-memory history of the recent downlink scheduling decisions
+  # (enqueue status, TX done, TX failed and dropped downlinks) is kept, which
+  # can be retrieved using the downlink_history command.
+  [concentratord.downlink_history]
+    # Max. number of history entries. Set to 0 to disable the history.
+    size={{ concentratord.downlink_history.size }}
+
+  # Diagnostic bundles.
+  #
+  # The diag_bundle command raises the log level for a bounded time (max. 10
+  # minutes), capturing the log records and the received frames. At the end, a
+  # zstd compressed tar archive (diag_<gateway_id>_<time>.tar.zst) is written
+  # to the configured directory. This contains the captured log records and
+  # frames, and a dump of the configuration (without the secret keys),
+  # status, JIT queue and downlink history. When the path is empty, the
+  # diag_bundle command is rejected.
+  [concentratord.diag_bundle]
+    # Directory to which the archives are written.
+    path="{{ concentratord.diag_bundle.path }}"
+
 
 # LoRa gateway configuration.
 [gateway]
@@ -40,6 +646,12 @@ pub fn run(config: &config::Configuration) {
   # Antenna gain (dB).
   antenna_gain={{ gateway.antenna_gain }}
 
+  # Cable loss (dB).
+  #
+  # The antenna gain minus the cable loss is used to calculate the conducted TX
+  # power from the requested EIRP.
+  cable_loss={{ gateway.cable_loss }}
+
   # Public LoRaWAN network.
   lorawan_public={{ gateway.lorawan_public }}
 
@@ -49,6 +661,13 @@ pub fn run(config: &config::Configuration) {
   # frequency and TX gain table.
   model="{{ gateway.model }}"
 
+  # Region.
+  #
+  # This is used to validate the downlink payload size against the max. payload
+  # size of the requested data-rate. When left blank, the region is derived
+  # from the gateway model name (e.g. rak_2247_eu868).
+  region="{{ gateway.region }}"
+
   # Gateway vendor / model flags.
   #
   # Flag can be used to configure additional vendor / model features. The
@@ -62,10 +681,89 @@ pub fn run(config: &config::Configuration) {
   #     AP2  - Module is in AP2 slot
   model_flags=[{{#each gateway.model_flags}}"{{ this }}",{{/each}}]
 
+  # Reset pin.
+  #
+  # When set, this overrides the reset pin (GPIO number) of the gateway model,
+  # e.g. 25 for an iC880A mounted on the Raspberry Pi backplane (the
+  # imst_ic880a models default to GPIO 5, as used by the IMST Lite Gateway).
+  reset_pin={{ gateway.reset_pin }}
+
+  # GPIO chip.
+  #
+  # When set, this overrides the GPIO chip of the reset pin(s) of the gateway
+  # model. This can be a path (e.g. /dev/gpiochip0) or the chip label, which
+  # is stable across boards and kernel versions (e.g. pinctrl-rp1 on the
+  # Raspberry Pi 5, pinctrl-bcm2711 on the Raspberry Pi 4). For boards which
+  # route the reset pin(s) through an I2C GPIO expander, this can also be
+  # <model>:<bus>:<address> (e.g. pca9555:1:0x20 or mcp23017:1:0x21). The
+  # i2c model is an alias for pca9555. Pins 8 - 15 are on the second port.
+  gpio_chip="{{ gateway.gpio_chip }}"
+
+  # Reset GPIO line name.
+  #
+  # When set, the reset pin is looked up by its GPIO line name (as shown by
+  # gpioinfo) across all GPIO chips on startup. This overrides the reset pin
+  # of the gateway model. An I2C GPIO expander line can be set as
+  # <model>:<bus>:<address>:<pin> (e.g. i2c:1:0x20:3), see gpio_chip.
+  reset_line="{{ gateway.reset_line }}"
+
+  # Shutdown commands.
+  #
+  # Commands that are executed on shutdown (SIGINT or SIGTERM), after the
+  # concentrator has been stopped and its reset and power enable pins have
+  # been deasserted, e.g. to switch off a power rail that is not managed by
+  # Concentratord. Example:
+  #
+  # shutdown_commands=[["/usr/bin/gpioset", "gpiochip0", "23=0"]]
+  shutdown_commands=[{{#each gateway.shutdown_commands}}[{{#each this}}"{{ this }}",{{/each}}],{{/each}}]
+
+  # Reset sequence.
+  #
+  # When set, this overrides the reset sequence of the gateway model. By
+  # default, the reset pin(s) of the gateway model are pulsed, using the
+  # reset timing, after which the reset commands are executed. Steps:
+  #
+  #   SET <PIN> <0|1>, sets the pin to the given value, PIN is
+  #     SX130X_RESET.
+  #   WAIT <MS>, waits the given number of milliseconds.
+  #   COMMAND <COMMAND> [ARG ...], executes the given command.
+  #
+  # Only the pins configured for the gateway model can be set. Example:
+  #
+  # reset_sequence=["SET SX130X_RESET 1", "WAIT 100", "SET SX130X_RESET 0", "WAIT 100"]
+  reset_sequence=[{{#each gateway.reset_sequence}}"{{ this }}",{{/each}}]
+
+  # Radio TX notch filter frequency (Hz).
+  #
+  # When set, this overrides the TX notch filter frequency of the radios of
+  # the gateway model (one value per radio, e.g. [0, 0]). This can be used to
+  # mitigate the interference with a colocated (cellular) transmitter. Valid
+  # values are 0 (disabled) or between 126000 and 250000 (SX1257 only).
+  radio_tx_notch_freq=[{{#each gateway.radio_tx_notch_freq}}{{ this }},{{/each}}]
+
   # Gateway ID.
   gateway_id="{{ gateway.gateway_id }}"
 
 
+  # GPIO configuration of the reset pin.
+  [gateway.gpio]
+    # Share the GPIO lines with other processes.
+    #
+    # By default, the lines are requested exclusively by Concentratord, such
+    # that these can not be requested by e.g. a vendor monitoring agent. When
+    # enabled, the lines are only requested for the duration of the reset
+    # sequence (or power down) and released afterwards. Note that most GPIO
+    # drivers keep the value of a released line. This does not apply to I2C
+    # GPIO expander lines.
+    shared={{ gateway.gpio.shared }}
+
+    # Busy timeout.
+    #
+    # When a line is requested by an other process, the request is retried up
+    # to this duration.
+    busy_timeout="{{ gateway.gpio.busy_timeout }}"
+
+
   # LoRa concentrator configuration.
   [gateway.concentrator]
 
@@ -98,11 +796,42 @@ pub fn run(config: &config::Configuration) {
     # Compulsory RFU size.
     compulsory_rfu_size={{ gateway.beacon.compulsory_rfu_size }}
 
+    # Optional RFU size.
+    #
+    # This is the size of the RFU field following the gateway specific field.
+    # The gateway specific field is only included in the beacon when it is
+    # provided by the network server using the 'beacon' command, or when
+    # gw_specific is enabled.
+    optional_rfu_size={{ gateway.beacon.optional_rfu_size }}
+
+    # Include the gateway specific field.
+    #
+    # When enabled, the gateway specific field contains the info descriptor
+    # and the coordinates of the gateway (GPS or static location). A gateway
+    # specific field provided using the 'beacon' command takes precedence.
+    gw_specific={{ gateway.beacon.gw_specific }}
+
+    # Info descriptor.
+    #
+    # Options: 0 - 2 (GPS coordinates of the first, second or third antenna),
+    # 128 - 255 (network specific).
+    info_desc={{ gateway.beacon.info_desc }}
+
     # Beacon frequency / frequencies (Hz).
+    #
+    # When multiple frequencies are configured, the beacon hops over these
+    # frequencies every beacon period.
     frequencies=[{{#each gateway.beacon.frequencies}}
       {{ this }},{{/each}}
     ]
 
+    # Beacon region.
+    #
+    # When set and no frequencies are configured, the regional beacon
+    # frequencies are used (e.g. the US915 and AU915 8-channel hopping).
+    # Valid options: AS923, AU915, CN470, EU433, EU868, IN865, KR920, RU864, US915.
+    region="{{ gateway.beacon.region }}"
+
     # Bandwidth (Hz).
     bandwidth={{ gateway.beacon.bandwidth }}
 
@@ -113,6 +842,61 @@ pub fn run(config: &config::Configuration) {
     tx_power={{ gateway.beacon.tx_power }}
 
 
+  # Status LED.
+  #
+  # When set, the LED indicates the Concentratord status:
+  #
+  #   * solid on: running
+  #   * single flash every 2 seconds: no client connected to the event socket
+  #   * double flash every 2 seconds: GPS time reference not valid
+  #   * slow blink: concentrator is being (re-)initialized
+  #   * fast blink: running with reduced functionality
+  #
+  # The LED is either a GPIO line (chip and line) or a LED under
+  # /sys/class/leds (led, e.g. "user-led"). Changing this setting requires a
+  # restart.
+  [gateway.status_led]
+    # GPIO chip (e.g. /dev/gpiochip0).
+    chip="{{ gateway.status_led.chip }}"
+
+    # GPIO line.
+    line={{ gateway.status_led.line }}
+
+    # LED name (/sys/class/leds), this is used instead of the chip and line
+    # when set.
+    led="{{ gateway.status_led.led }}"
+
+    # The LED is switched on by setting the GPIO line low.
+    active_low={{ gateway.status_led.active_low }}
+
+
+  # First uplink feedback.
+  #
+  # When set, feedback is given on the first valid uplink after startup, such
+  # that installers get an immediate confirmation that the RF path works. The
+  # feedback is a pulse on a GPIO line (e.g. of a buzzer) and / or a command.
+  # Changing this setting requires a restart.
+  [gateway.first_uplink]
+    # GPIO chip (e.g. /dev/gpiochip0).
+    chip="{{ gateway.first_uplink.chip }}"
+
+    # GPIO line.
+    line={{ gateway.first_uplink.line }}
+
+    # The line is asserted by setting it low.
+    active_low={{ gateway.first_uplink.active_low }}
+
+    # Time the line is asserted.
+    duration="{{ gateway.first_uplink.duration }}"
+
+    # Command that is executed. The frequency, RSSI and SNR of the uplink are
+    # passed as UPLINK_FREQUENCY, UPLINK_RSSI and UPLINK_SNR environment
+    # variables. Example:
+    #
+    # command=["/usr/bin/logger", "first uplink received"]
+    command=[{{#each gateway.first_uplink.command}}"{{ this }}",{{/each}}]
+
+
   # Static gateway location.
   [gateway.location]
 
@@ -122,6 +906,382 @@ pub fn run(config: &config::Configuration) {
     latitude={{ gateway.location.latitude }}
     longitude={{ gateway.location.longitude }}
     altitude={{ gateway.location.altitude }}
+
+
+  # Downlink soak test.
+  #
+  # When enabled, downlinks are generated periodically and handled as if these
+  # were received by the downlink command, for thermal / soak testing of new
+  # hardware. The number of enqueued and rejected downlinks is reported in the
+  # stats meta-data (soak_test_enqueued, soak_test_rejected, soak_test_total).
+  #
+  # WARNING: this must not be enabled on gateways in production, as these
+  # downlinks use the airtime of the gateway.
+  [gateway.soak_test]
+
+    # Enable the soak test.
+    enabled={{ gateway.soak_test.enabled }}
+
+    # Interval between two downlinks (min. 1s).
+    interval="{{ gateway.soak_test.interval }}"
+
+    # Payload size (bytes).
+    payload_size={{ gateway.soak_test.payload_size }}
+
+    # TX power (EIRP).
+    tx_power={{ gateway.soak_test.tx_power }}
+
+    # Frequencies (Hz).
+    #
+    # The downlinks are sent round-robin on these frequencies. When empty, the
+    # frequencies of the configured channels are used.
+    frequencies=[{{#each gateway.soak_test.frequencies}}
+      {{ this }},{{/each}}
+    ]
+
+    # Bandwidth (Hz).
+    bandwidth={{ gateway.soak_test.bandwidth }}
+
+    # Spreading factor.
+    spreading_factor={{ gateway.soak_test.spreading_factor }}
+
+
+  # TX power configuration.
+  [gateway.tx_power]
+
+    # Strict mode.
+    #
+    # The TX power of a downlink is rounded down to the closest TX gain table
+    # entry. When strict mode is enabled, downlinks for which the applied TX
+    # power differs more than the configured tolerance from the requested
+    # TX power are rejected (TX_POWER) instead of being rounded.
+    strict={{ gateway.tx_power.strict }}
+
+    # Tolerance (dB).
+    #
+    # When strict mode is disabled, a warning is logged when exceeding the
+    # tolerance.
+    tolerance={{ gateway.tx_power.tolerance }}
+
+    # Enforce regional max. EIRP.
+    #
+    # When enabled, downlinks for which the requested EIRP exceeds the max.
+    # EIRP of the region are rejected (TX_POWER). This requires the region to be
+    # configured or derived from the gateway model name.
+    enforce_max_eirp={{ gateway.tx_power.enforce_max_eirp }}
+
+
+  # Downlink timing configuration.
+  [gateway.downlink_timing]
+
+    # Max. GPS timing offset.
+    #
+    # When set, downlinks using GPS epoch timing which also contain the uplink
+    # context are cross-checked against the concentrator counter of the uplink.
+    # Downlinks scheduled before the uplink are rejected (TOO_LATE), downlinks
+    # scheduled more than the configured offset after the uplink are rejected
+    # (TOO_EARLY). This catches clock mapping issues between the network server
+    # and the gateway. Set this to 0s to disable this check.
+    gps_max_offset="{{ gateway.downlink_timing.gps_max_offset }}"
+
+
+  # Duty-cycle configuration.
+  #
+  # When enabled, the airtime is tracked per duty-cycle limited sub-band of the
+  # region (EU868 and EU433) over a sliding window of one hour. Downlinks that
+  # would exceed the duty-cycle limit of their sub-band are rejected (TOO_EARLY).
+  # The remaining airtime per sub-band is reported in the stats meta-data
+  # (duty_cycle_remaining_ms_<sub-band>). This requires the region to be
+  # configured, or to be derived from the model name.
+  [gateway.duty_cycle]
+    enabled={{ gateway.duty_cycle.enabled }}
+
+
+  # Listen-before-talk configuration.
+  #
+  # When enabled, the channel is sensed before each transmission (e.g. required
+  # in the AS923 JP and KR923 regions). This requires a concentrator FPGA image
+  # with LBT support. Downlinks on a frequency that is not covered by the LBT
+  # channels are rejected (TX_FREQ), downlinks that are not allowed by the LBT
+  # at the time of transmission are reported in the downlink history and the
+  # tx_lbt_rejected stats counter.
+  [gateway.lbt]
+    enabled={{ gateway.lbt.enabled }}
+
+    # RSSI target (dBm).
+    #
+    # The channel is considered busy when the RSSI exceeds this value.
+    rssi_target={{ gateway.lbt.rssi_target }}
+
+    # RSSI offset (dB).
+    #
+    # Offset applied to the RSSI values of the LBT radio by the FPGA.
+    rssi_offset={{ gateway.lbt.rssi_offset }}
+
+    # LBT channels.
+    #
+    # This can be repeated for each channel. The scan time (us) must be 128 or
+    # 5000, e.g.:
+    #
+    # [[gateway.lbt.channels]]
+    #   frequency=920600000
+    #   scan_time_us=5000
+{{#each gateway.lbt.channels}}
+    [[gateway.lbt.channels]]
+      frequency={{ this.frequency }}
+      scan_time_us={{ this.scan_time_us }}
+{{/each}}
+
+
+  # GPS unlocked configuration.
+  #
+  # This defines how GPS epoch timed downlinks (e.g. Class-B) are handled when
+  # the GPS time reference is not valid.
+  [gateway.gps_unlocked]
+
+    # Policy.
+    #
+    # Options:
+    #   REJECT     - Reject the downlink (GPS_UNLOCKED)
+    #   LAST_KNOWN - Schedule the downlink using the last known GPS time
+    #                reference, when the estimated error does not exceed the
+    #                max. error. The estimated error is reported in the txdone
+    #                event (gps_timing_error_us).
+    policy="{{ gateway.gps_unlocked.policy }}"
+
+    # Max. estimated error.
+    #
+    # The error is estimated from the time between the last known GPS time
+    # reference and the downlink, assuming a max. clock drift of 10 ppm (e.g.
+    # 1ms allows scheduling up to 100 seconds from the last known reference).
+    max_error="{{ gateway.gps_unlocked.max_error }}"
+
+
+  # Uplink time configuration.
+  [gateway.uplink_time]
+
+    # Time source.
+    #
+    # This defines how the time field of the uplinks is set.
+    #
+    # Options:
+    #   GNSS   - The GPS time, only when the GPS time reference is valid
+    #            (default)
+    #   SYSTEM - The host system clock, e.g. when the host clock is synchronized
+    #            using NTP
+    #   NONE   - The time field is omitted
+    source="{{ gateway.uplink_time.source }}"
+
+    # GPS epoch policy.
+    #
+    # This defines when the time_since_gps_epoch field of the uplinks is set.
+    #
+    # Options:
+    #   PPS_LOCKED - Only when the GPS time reference is valid (default)
+    #   LAST_KNOWN - Also when the GPS time reference is not valid, using the
+    #                last known GPS time reference. Note that the accuracy
+    #                degrades with the time since the last known reference.
+    #   NEVER      - The time_since_gps_epoch field is omitted
+    gps_epoch="{{ gateway.uplink_time.gps_epoch }}"
+
+    # Timestamp debugging.
+    #
+    # When enabled, the raw concentrator counter and the parameters used to
+    # convert it are added to the uplink meta-data, next to the converted
+    # timestamps. This makes it possible to diagnose time conversion issues
+    # from captured uplinks. The times are formatted as seconds.nanoseconds.
+    #
+    #   ts_count_us         - Raw concentrator counter (us)
+    #   ts_time_source      - Configured time source
+    #   ts_gps_epoch_policy - Configured GPS epoch policy
+    #   ts_ref_valid        - GPS time reference is valid
+    #   ts_ref_count_us     - Concentrator counter of the GPS time reference
+    #   ts_ref_offset_us    - Offset of the uplink to the GPS time reference (us)
+    #   ts_ref_gps_epoch    - GPS epoch of the GPS time reference
+    #   ts_ref_gps_time     - GPS time (Unix epoch) of the GPS time reference
+    #   ts_ref_system_time  - System time (Unix epoch) of the GPS time reference
+    #   ts_xtal_err         - Clock error of the GPS time reference
+    #   ts_time             - Converted uplink time (Unix epoch)
+    #   ts_gps_epoch        - Converted uplink GPS epoch
+    #
+    # The ts_ref_* fields are only added when there is a (last known) GPS time
+    # reference, the converted timestamps only when they are set.
+    debug={{ gateway.uplink_time.debug }}
+
+
+  # Board configuration.
+  #
+  # Multi-board gateways (e.g. 16 or 64 channel gateways) are supported by
+  # running one Concentratord instance per SX1301 board, each with its own
+  # configuration and API binds.
+  [gateway.board]
+
+    # Board index.
+    #
+    # This is reported as board in the uplink meta-data. The channels are
+    # numbered across the boards, e.g. the first IF chain of board 1 is
+    # reported as channel 10. The number of received packets per channel is
+    # reported in the stats meta-data. For the Kerlink iBTS models, this also
+    # selects the SPI device and reset line of the board slot.
+    index={{ gateway.board.index }}
+
+    # SPI device path.
+    #
+    # When set, this overrides the SPI device path of the gateway model.
+    spidev_path="{{ gateway.board.spidev_path }}"
+
+    # RSSI offset (dB).
+    #
+    # This offset is added to the RSSI offset of the radios of the gateway
+    # model, to compensate for RSSI differences between the boards.
+    rssi_offset={{ gateway.board.rssi_offset }}
+
+
+  # GNSS configuration.
+  [gateway.gnss]
+
+    # Source.
+    #
+    # Options:
+    #   TTY   - Read the GNSS module through its tty (or the gpsd source of the
+    #           gateway model). This is the default when empty.
+    #   GPSD  - Read the GNSS module through gpsd, e.g. when the tty is already
+    #           owned by gpsd because it is shared with other services. The
+    #           NMEA and UBX messages are forwarded by gpsd, such that the GPS
+    #           time synchronization and location keep working. The PPS signal
+    #           must still be connected to the concentrator.
+    source="{{ gateway.gnss.source }}"
+
+    # gpsd server.
+    #
+    # The address of the gpsd server, this is used when gpsd is the source.
+    gpsd_server="{{ gateway.gnss.gpsd_server }}"
+
+    # The settings below only apply to GNSS modules that are connected through
+    # a tty.
+
+    # Baud rate.
+    #
+    # The baud rate of the GNSS module (9600, 38400 or 115200). When set to 0,
+    # the default of 9600 baud is used.
+    baud_rate={{ gateway.gnss.baud_rate }}
+
+    # Automatic baud rate detection.
+    #
+    # When enabled, the baud rates 9600, 38400 and 115200 are tried until
+    # valid NMEA sentences are received. This overrides the baud_rate.
+    auto_baud={{ gateway.gnss.auto_baud }}
+
+    # USB vendor and product ID.
+    #
+    # When set (e.g. "1546:01a7"), the tty of the GNSS module is discovered by
+    # the USB vendor and product ID. This overrides the tty of the gateway
+    # model.
+    usb_id="{{ gateway.gnss.usb_id }}"
+
+    # Device-tree alias.
+    #
+    # When set (e.g. "serial1"), the tty of the GNSS module is discovered by
+    # the device-tree alias of its UART. This overrides the tty of the gateway
+    # model.
+    dt_alias="{{ gateway.gnss.dt_alias }}"
+
+    # Assistance data URL.
+    #
+    # When set, assistance data (e.g. u-blox AssistNow Online) is downloaded
+    # from this URL on startup and injected into the GNSS module, to reduce the
+    # time to first fix. The response must contain UBX messages, e.g.:
+    # https://online-live1.services.u-blox.com/GetOnlineData.ashx?token=TOKEN;gnss=gps;datatype=eph,alm,aux
+    assist_url="{{ gateway.gnss.assist_url }}"
+
+
+  # LoRaWAN channel indices.
+  #
+  # When set, the uplinks received on the given frequency are annotated with
+  # the LoRaWAN channel index (lorawan_channel uplink meta-data). This can be
+  # repeated for each channel, e.g.:
+  #
+  # [[gateway.lorawan_channels]]
+  #   frequency=902300000
+  #   index=0
+{{#each gateway.lorawan_channels}}
+  [[gateway.lorawan_channels]]
+    frequency={{ this.frequency }}
+    index={{ this.index }}
+{{/each}}
+
+# Prometheus metrics.
+[metrics]
+
+  # Metrics bind.
+  #
+  # When set (e.g. 0.0.0.0:9100), the metrics are exposed on the /metrics
+  # path of this HTTP listener, such that Concentratord can be scraped by
+  # Prometheus. Unlike the stats, the counters are never reset. Changing this
+  # setting requires a restart.
+  bind="{{ metrics.bind }}"
+
+# Logging.
+#
+# The log level is configured by concentratord.log_level. Changing these
+# settings requires a restart.
+[logging]
+
+  # Output.
+  #
+  # Valid options are:
+  #   * STDOUT
+  #   * SYSLOG
+  #   * FILE: the path file, which is rotated once it exceeds max_size
+  #
+  # When empty, SYSLOG is used when concentratord.log_to_syslog is set, else
+  # STDOUT.
+  output="{{ logging.output }}"
+
+  # Format.
+  #
+  # Valid options are:
+  #   * TEXT
+  #   * JSON: one JSON object per log record, containing the time, level,
+  #     module, gateway_id and message. The "key: value" pairs of the message
+  #     (e.g. freq, dev_addr) are included as fields object.
+  format="{{ logging.format }}"
+
+  # Log file path (FILE output).
+  path="{{ logging.path }}"
+
+  # Max. size (bytes) of the log file before it is rotated (0 = not rotated).
+  max_size={{ logging.max_size }}
+
+  # Number of rotated log files to keep.
+  max_files={{ logging.max_files }}
+
+# Packet-forwarder compatibility.
+[forwarder]
+
+  # Semtech UDP packet-forwarder.
+  #
+  # When a server is configured, the uplinks and stats are also forwarded
+  # using the Semtech UDP protocol and the downlinks received from this server
+  # are enqueued, next to the ZMQ API. This can be used with network servers
+  # that do not provide a ChirpStack MQTT Forwarder or UDP bridge.
+  [forwarder.udp]
+    # Server hostname or IP address.
+    #
+    # When empty, the UDP packet-forwarder is disabled.
+    server="{{ forwarder.udp.server }}"
+
+    # Server port for the uplinks and stats.
+    port_up={{ forwarder.udp.port_up }}
+
+    # Server port for the downlinks.
+    port_down={{ forwarder.udp.port_down }}
+
+    # Keep-alive interval.
+    #
+    # Interval at which the PULL_DATA keep-alive packets are sent. This keeps
+    # the downlink path open through NAT routers and firewalls.
+    keepalive_interval="{{ forwarder.udp.keepalive_interval }}"
 "#;
 
     let reg = Handlebars::new();
@@ -37,7 +37,8 @@ pub fn rxrf_setconf(config: &Configuration) -> Result<()> {
         let rx_rf_config = hal::RxRfConfig {
             enable: *radio_freq > 0,
             freq_hz: *radio_freq,
-            rssi_offset: config.gateway.model_config.radio_rssi_offset[i],
+            rssi_offset: config.gateway.model_config.radio_rssi_offset[i]
+                + config.gateway.board.rssi_offset,
             radio_type: config.gateway.model_config.radio_type[i],
             tx_enable: config.gateway.model_config.radio_tx_enabled[i],
             tx_notch_freq: config.gateway.model_config.radio_tx_notch_freq[i],
@@ -145,6 +146,32 @@ pub fn rxif_setconf(config: &Configuration) -> Result<()> {
     hal::rxif_setconf(9, &rx_if_config)
 }
 
+pub fn lbt_setconf(config: &Configuration) -> Result<()> {
+    let lbt_config = hal::LBTConfig {
+        enable: config.gateway.lbt.enabled,
+        rssi_target: config.gateway.lbt.rssi_target,
+        rssi_offset: config.gateway.lbt.rssi_offset,
+        channels: config
+            .gateway
+            .lbt
+            .channels
+            .iter()
+            .map(|c| hal::LBTChannelConfig {
+                freq_hz: c.frequency,
+                scan_time_us: c.scan_time_us,
+            })
+            .collect(),
+    };
+    info!(
+        "Setting up listen-before-talk, enabled: {}, rssi_target: {}, rssi_offset: {}, channels: {}",
+        lbt_config.enable,
+        lbt_config.rssi_target,
+        lbt_config.rssi_offset,
+        lbt_config.channels.len()
+    );
+    hal::lbt_setconf(&lbt_config)
+}
+
 pub fn start(_config: &Configuration) -> Result<()> {
     info!("Starting the concentrator");
     hal::start()
@@ -85,6 +85,21 @@ pub fn get_radio_frequencies(config: &super::Configuration) -> Result<Vec<u32>>
     return Ok(radios);
 }
 
+/// Validates that the number of configured (non-zero) multi-SF channels does not exceed the
+/// max. number of multi-SF channels of the gateway model (0 = not limited).
+pub fn validate_multi_sf_channel_count(multi_sf_channels: &[u32], max: usize) -> Result<()> {
+    let count = multi_sf_channels.iter().filter(|f| **f != 0).count();
+    if max != 0 && count > max {
+        return Err(anyhow!(
+            "gateway model supports max. {} multi-SF channels, configured: {}",
+            max,
+            count
+        ));
+    }
+
+    Ok(())
+}
+
 pub fn get_radio_for_channel(radios: &[u32], freq_hz: u32, bandwidth: u32) -> Result<usize> {
     let chan_min = freq_hz - (bandwidth / 2);
     let chan_max = freq_hz + (bandwidth / 2);
@@ -101,6 +116,19 @@ pub fn get_radio_for_channel(radios: &[u32], freq_hz: u32, bandwidth: u32) -> Re
     Err(anyhow!("channel does not fit in radio bandwidth"))
 }
 
+/// Returns true when the given TX frequency is allowed by the listen-before-talk channels. A
+/// 250 kHz channel must be centered in between two (200 kHz apart) LBT channels, all other
+/// channels must match an LBT channel.
+pub fn is_lbt_channel(lbt_channels: &[u32], freq_hz: u32, bandwidth: u32) -> bool {
+    match bandwidth {
+        250000 => {
+            lbt_channels.contains(&(freq_hz - 100000)) && lbt_channels.contains(&(freq_hz + 100000))
+        }
+        500000 => false,
+        _ => lbt_channels.contains(&freq_hz),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::vendor::generic;
@@ -186,4 +214,24 @@ mod tests {
             assert_eq!(radio, expected);
         }
     }
+
+    #[test]
+    fn test_validate_multi_sf_channel_count() {
+        let channels = [868100000, 868300000, 868500000, 0, 0, 0, 0, 0];
+
+        assert!(super::validate_multi_sf_channel_count(&channels, 0).is_ok());
+        assert!(super::validate_multi_sf_channel_count(&channels, 3).is_ok());
+        assert!(super::validate_multi_sf_channel_count(&channels, 2).is_err());
+    }
+
+    #[test]
+    fn test_is_lbt_channel() {
+        let channels = [920600000, 920800000, 921000000];
+
+        assert!(super::is_lbt_channel(&channels, 920800000, 125000));
+        assert!(!super::is_lbt_channel(&channels, 920700000, 125000));
+        assert!(super::is_lbt_channel(&channels, 920700000, 250000));
+        assert!(!super::is_lbt_channel(&channels, 921100000, 250000));
+        assert!(!super::is_lbt_channel(&channels, 920800000, 500000));
+    }
 }
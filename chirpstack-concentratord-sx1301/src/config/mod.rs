@@ -1,5 +1,8 @@
+use std::collections::HashMap;
 use std::fs;
+use std::str::FromStr;
 
+use libconcentratord::{gnss, region, reset, shadow};
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 
@@ -8,7 +11,11 @@ pub mod vendor;
 
 pub const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
-#[derive(Default, Serialize, Deserialize, Debug, PartialEq)]
+// Valid range of the SX1257 TX notch filter frequency.
+const MIN_NOTCH_FREQ: u32 = 126000;
+const MAX_NOTCH_FREQ: u32 = 250000;
+
+#[derive(Default, Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Concentrator {
     pub multi_sf_channels: [u32; 8],
     #[serde(default)]
@@ -17,14 +24,14 @@ pub struct Concentrator {
     pub fsk: FSKChannel,
 }
 
-#[derive(Default, Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Default, Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct LoRaStdChannel {
     pub frequency: u32,
     pub bandwidth: u32,
     pub spreading_factor: u8,
 }
 
-#[derive(Default, Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Default, Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct FSKChannel {
     pub frequency: u32,
     pub datarate: u32,
@@ -34,10 +41,57 @@ pub struct FSKChannel {
 #[derive(Default, Serialize, Deserialize, Clone)]
 pub struct Beacon {
     pub compulsory_rfu_size: usize,
+    #[serde(default)]
+    pub optional_rfu_size: usize,
+    #[serde(default)]
     pub frequencies: Vec<u32>,
+    #[serde(default)]
+    pub region: String,
     pub spreading_factor: u32,
     pub bandwidth: u32,
     pub tx_power: u32,
+    #[serde(default)]
+    pub gw_specific: bool,
+    #[serde(default)]
+    pub info_desc: u8,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+pub struct LoRaWANChannel {
+    pub frequency: u32,
+    pub index: u32,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct StatusLed {
+    pub chip: String,
+    pub line: u32,
+    pub led: String,
+    pub active_low: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct FirstUplink {
+    pub chip: String,
+    pub line: u32,
+    pub active_low: bool,
+    #[serde(with = "humantime_serde")]
+    pub duration: Duration,
+    pub command: Vec<String>,
+}
+
+impl Default for FirstUplink {
+    fn default() -> Self {
+        FirstUplink {
+            chip: "".to_string(),
+            line: 0,
+            active_low: false,
+            duration: Duration::from_secs(1),
+            command: Vec::new(),
+        }
+    }
 }
 
 #[derive(Default, Serialize, Deserialize, Clone)]
@@ -47,53 +101,766 @@ pub struct Location {
     pub altitude: i16,
 }
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Lbt {
+    pub enabled: bool,
+    pub rssi_target: i8,
+    pub rssi_offset: i8,
+    pub channels: Vec<LbtChannel>,
+}
+
+impl Default for Lbt {
+    fn default() -> Self {
+        Lbt {
+            enabled: false,
+            rssi_target: -80,
+            rssi_offset: -4,
+            channels: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct LbtChannel {
+    pub frequency: u32,
+    pub scan_time_us: u16,
+}
+
+impl Default for LbtChannel {
+    fn default() -> Self {
+        LbtChannel {
+            frequency: 0,
+            scan_time_us: 128,
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct DutyCycle {
+    pub enabled: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SoakTest {
+    pub enabled: bool,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    pub payload_size: usize,
+    pub tx_power: i32,
+    pub frequencies: Vec<u32>,
+    pub bandwidth: u32,
+    pub spreading_factor: u32,
+}
+
+impl Default for SoakTest {
+    fn default() -> Self {
+        SoakTest {
+            enabled: false,
+            interval: Duration::from_secs(10),
+            payload_size: 51,
+            tx_power: 14,
+            frequencies: Vec::new(),
+            bandwidth: 125000,
+            spreading_factor: 7,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct TxPower {
+    pub strict: bool,
+    pub tolerance: f32,
+    pub enforce_max_eirp: bool,
+}
+
+impl Default for TxPower {
+    fn default() -> Self {
+        TxPower {
+            strict: false,
+            tolerance: 1.5,
+            enforce_max_eirp: false,
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Board {
+    pub index: u32,
+    pub spidev_path: String,
+    pub rssi_offset: f32,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct DownlinkTiming {
+    #[serde(with = "humantime_serde")]
+    pub gps_max_offset: Duration,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct GpsUnlocked {
+    pub policy: String,
+    #[serde(with = "humantime_serde")]
+    pub max_error: Duration,
+}
+
+impl Default for GpsUnlocked {
+    fn default() -> Self {
+        GpsUnlocked {
+            policy: "REJECT".to_string(),
+            max_error: Duration::from_millis(1),
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct UplinkTime {
+    pub source: String,
+    pub gps_epoch: String,
+    pub debug: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Gnss {
+    pub baud_rate: u32,
+    pub auto_baud: bool,
+    pub usb_id: String,
+    pub dt_alias: String,
+    pub assist_url: String,
+    pub source: String,
+    pub gpsd_server: String,
+}
+
+impl Default for Gnss {
+    fn default() -> Self {
+        Gnss {
+            baud_rate: 0,
+            auto_baud: false,
+            usb_id: String::new(),
+            dt_alias: String::new(),
+            assist_url: String::new(),
+            source: String::new(),
+            gpsd_server: "localhost:2947".to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Gpio {
+    pub shared: bool,
+    #[serde(with = "humantime_serde")]
+    pub busy_timeout: Duration,
+}
+
+impl Default for Gpio {
+    fn default() -> Self {
+        Gpio {
+            shared: false,
+            busy_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
 pub struct Gateway {
     #[serde(default)]
     pub antenna_gain: i8,
     #[serde(default)]
+    pub cable_loss: i8,
+    #[serde(default)]
     pub lorawan_public: bool,
     pub model: String,
     #[serde(default)]
+    pub region: String,
+    #[serde(default)]
     pub model_flags: Vec<String>,
     #[serde(default)]
     pub reset_pin: u32,
+    #[serde(default)]
+    pub reset_line: String,
+    #[serde(default)]
+    pub gpio_chip: String,
+    #[serde(default)]
+    pub shutdown_commands: Vec<Vec<String>>,
+    #[serde(default)]
+    pub reset_sequence: Vec<String>,
+    #[serde(default)]
+    pub gpio: Gpio,
+    #[serde(default)]
+    pub radio_tx_notch_freq: Vec<u32>,
     pub gateway_id: String,
     pub concentrator: Concentrator,
     #[serde(default)]
     pub beacon: Beacon,
     #[serde(default)]
     pub location: Location,
+    #[serde(default)]
+    pub status_led: StatusLed,
+    #[serde(default)]
+    pub first_uplink: FirstUplink,
+    #[serde(default)]
+    pub soak_test: SoakTest,
+    #[serde(default)]
+    pub tx_power: TxPower,
+    #[serde(default)]
+    pub downlink_timing: DownlinkTiming,
+    #[serde(default)]
+    pub duty_cycle: DutyCycle,
+    #[serde(default)]
+    pub lbt: Lbt,
+    #[serde(default)]
+    pub gps_unlocked: GpsUnlocked,
+    #[serde(default)]
+    pub uplink_time: UplinkTime,
+    #[serde(default)]
+    pub board: Board,
+    #[serde(default)]
+    pub gnss: Gnss,
+    #[serde(default)]
+    pub lorawan_channels: Vec<LoRaWANChannel>,
 
     #[serde(skip)]
     pub gateway_id_bytes: Vec<u8>,
     #[serde(skip)]
     pub model_config: vendor::Configuration,
     #[serde(skip)]
+    pub region_config: Option<region::Region>,
+    #[serde(skip)]
     pub config_version: String,
 }
 
-#[derive(Default, Serialize, Deserialize)]
+impl Gateway {
+    /// Returns the LoRaWAN channel index by frequency.
+    pub fn get_lorawan_channels(&self) -> HashMap<u32, u32> {
+        self.lorawan_channels
+            .iter()
+            .map(|c| (c.frequency, c.index))
+            .collect()
+    }
+
+    /// Returns the antenna gain minus the cable loss (dB). This is used to convert between
+    /// the EIRP of the downlink and the conducted power of the TX gain table.
+    pub fn get_effective_antenna_gain(&self) -> i8 {
+        self.antenna_gain - self.cable_loss
+    }
+
+    /// Returns the shutdown commands as (command, args).
+    pub fn get_shutdown_commands(&self) -> Option<Vec<(String, Vec<String>)>> {
+        if self.shutdown_commands.is_empty() {
+            return None;
+        }
+
+        Some(
+            self.shutdown_commands
+                .iter()
+                .filter(|v| !v.is_empty())
+                .map(|v| (v[0].clone(), v[1..].to_vec()))
+                .collect(),
+        )
+    }
+
+    /// Returns the reset sequence. The configured sequence overrides the sequence of the model,
+    /// when neither is set the default sequence is used (see reset::Configuration).
+    pub fn get_reset_sequence(&self) -> Option<Vec<reset::Step>> {
+        if self.reset_sequence.is_empty() {
+            return self.model_config.reset_sequence.clone();
+        }
+
+        Some(
+            self.reset_sequence
+                .iter()
+                .map(|v| reset::Step::from_str(v).expect("invalid reset_sequence step"))
+                .collect(),
+        )
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
 pub struct Concentratord {
     pub log_level: String,
     #[serde(default)]
     pub log_to_syslog: bool,
+    #[serde(default)]
+    pub systemd_notify: bool,
     #[serde(with = "humantime_serde")]
     pub stats_interval: Duration,
+    #[serde(default)]
+    pub jit_scheduler: String,
     pub api: API,
+    #[serde(default)]
+    pub remote_config: RemoteConfig,
+    #[serde(default)]
+    pub audit_log: AuditLog,
+    #[serde(default)]
+    pub capture: Capture,
+    #[serde(default)]
+    pub stats_metadata: StatsMetadata,
+    #[serde(default)]
+    pub latency_probe: LatencyProbe,
+    #[serde(default)]
+    pub metered: Metered,
+    #[serde(default)]
+    pub health: Health,
+    #[serde(default)]
+    pub clock_drift: ClockDrift,
+    #[serde(default)]
+    pub time_sync: TimeSync,
+    #[serde(default)]
+    pub shadow: Shadow,
+    #[serde(default)]
+    pub join_flood: JoinFlood,
+    #[serde(default)]
+    pub blacklist: Blacklist,
+    #[serde(default)]
+    pub oversized_uplinks: OversizedUplinks,
+    #[serde(default)]
+    pub uplink_filter: UplinkFilter,
+    #[serde(default)]
+    pub storage: Storage,
+    #[serde(default)]
+    pub downlink_history: DownlinkHistory,
+    #[serde(default)]
+    pub diag_bundle: DiagBundle,
+    // Hash of the configuration file(s), this is set when loading the configuration.
+    #[serde(skip)]
+    pub config_hash: String,
 }
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Default, Serialize, Deserialize, Clone)]
 pub struct API {
     pub event_bind: String,
     pub command_bind: String,
+    #[serde(default)]
+    pub event_sequence: bool,
+    #[serde(default)]
+    pub event_compression: String,
+    #[serde(default)]
+    pub event_encryption_key: String,
+    #[serde(default)]
+    pub curve_secret_key: String,
+    #[serde(default)]
+    pub curve_client_keys: Vec<String>,
+    #[serde(default)]
+    pub allowed_commands: Vec<String>,
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default)]
+    pub log_event_level: String,
+    #[serde(default)]
+    pub uplink_mode: String,
+    #[serde(default)]
+    pub uplink_sampling: u64,
+    #[serde(default)]
+    pub rate_limits: HashMap<String, RateLimit>,
+    #[serde(default)]
+    pub nmea_socket: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RateLimit {
+    pub rate: f64,
+    pub burst: u32,
 }
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct RemoteConfig {
+    pub url: String,
+    pub public_key: String,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+}
+
+impl Default for RemoteConfig {
+    fn default() -> Self {
+        RemoteConfig {
+            url: "".to_string(),
+            public_key: "".to_string(),
+            interval: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct AuditLog {
+    pub path: String,
+    pub max_size: u64,
+    pub max_files: usize,
+}
+
+impl Default for AuditLog {
+    fn default() -> Self {
+        AuditLog {
+            path: "".to_string(),
+            max_size: 10 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Capture {
+    pub path: String,
+    pub max_size: u64,
+    pub max_files: usize,
+}
+
+impl Default for Capture {
+    fn default() -> Self {
+        Capture {
+            path: "".to_string(),
+            max_size: 10 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct StatsMetadata {
+    pub commands: HashMap<String, Vec<String>>,
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+}
+
+impl Default for StatsMetadata {
+    fn default() -> Self {
+        StatsMetadata {
+            commands: HashMap::new(),
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct LatencyProbe {
+    pub method: String,
+    pub target: String,
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+}
+
+impl Default for LatencyProbe {
+    fn default() -> Self {
+        LatencyProbe {
+            method: "TCP".to_string(),
+            target: "".to_string(),
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Metered {
+    pub file: String,
+    pub network_manager: bool,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+}
+
+impl Default for Metered {
+    fn default() -> Self {
+        Metered {
+            file: "".to_string(),
+            network_manager: false,
+            interval: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Health {
+    pub enabled: bool,
+    pub max_hal_errors: u32,
+    #[serde(with = "humantime_serde")]
+    pub rx_timeout: Duration,
+    #[serde(with = "humantime_serde")]
+    pub counter_stall_timeout: Duration,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Health {
+            enabled: false,
+            max_hal_errors: 10,
+            rx_timeout: Duration::ZERO,
+            counter_stall_timeout: Duration::ZERO,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ClockDrift {
+    pub threshold_ppm: f64,
+    #[serde(with = "humantime_serde")]
+    pub baseline: Duration,
+}
+
+impl Default for ClockDrift {
+    fn default() -> Self {
+        ClockDrift {
+            threshold_ppm: 10.0,
+            baseline: Duration::from_secs(3600),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct TimeSync {
+    pub enabled: bool,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    #[serde(with = "humantime_serde")]
+    pub max_rtt: Duration,
+}
+
+impl Default for TimeSync {
+    fn default() -> Self {
+        TimeSync {
+            enabled: false,
+            interval: Duration::from_secs(600),
+            max_rtt: Duration::from_secs(2),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Shadow {
+    pub path: String,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+}
+
+impl Default for Shadow {
+    fn default() -> Self {
+        Shadow {
+            path: "".to_string(),
+            interval: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct JoinFlood {
+    pub enabled: bool,
+    pub key: String,
+    pub prefix_bits: u32,
+    pub max_join_requests: u32,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    pub limit: bool,
+}
+
+impl Default for JoinFlood {
+    fn default() -> Self {
+        JoinFlood {
+            enabled: false,
+            key: "DEV_EUI".to_string(),
+            prefix_bits: 64,
+            max_join_requests: 10,
+            interval: Duration::from_secs(60),
+            limit: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Blacklist {
+    pub enabled: bool,
+    pub max_fcnt_gap: u32,
+    pub max_malformed: u32,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    #[serde(with = "humantime_serde")]
+    pub expiry: Duration,
+    pub limit: bool,
+    pub max_uplinks: u32,
+}
+
+impl Default for Blacklist {
+    fn default() -> Self {
+        Blacklist {
+            enabled: false,
+            max_fcnt_gap: 16384,
+            max_malformed: 10,
+            interval: Duration::from_secs(600),
+            expiry: Duration::from_secs(3600),
+            limit: false,
+            max_uplinks: 0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct OversizedUplinks {
+    pub action: String,
+}
+
+impl Default for OversizedUplinks {
+    fn default() -> Self {
+        OversizedUplinks {
+            action: "FORWARD".to_string(),
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct UplinkFilter {
+    pub net_id_allow: Vec<String>,
+    pub net_id_deny: Vec<String>,
+    pub join_eui_allow: Vec<String>,
+    pub join_eui_deny: Vec<String>,
+    pub thresholds: HashMap<String, UplinkThreshold>,
+    pub chain: Vec<UplinkFilterRule>,
+    pub debug: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UplinkThreshold {
+    pub min_snr: f32,
+    pub min_rssi: i32,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct UplinkFilterRule {
+    pub name: String,
+    pub filter: String,
+    pub action: String,
+    pub values: Vec<String>,
+    pub modulation: String,
+    pub min: f64,
+    pub max: f64,
+    #[serde(with = "humantime_serde")]
+    pub window: Duration,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Storage {
+    pub backend: String,
+    pub path: String,
+    pub fsync: String,
+    pub persist_jit_queue: bool,
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Storage {
+            backend: "".to_string(),
+            path: "".to_string(),
+            fsync: "NEVER".to_string(),
+            persist_jit_queue: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct DownlinkHistory {
+    pub size: usize,
+}
+
+impl Default for DownlinkHistory {
+    fn default() -> Self {
+        DownlinkHistory { size: 256 }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct DiagBundle {
+    pub path: String,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
 #[serde(default = "example_configuration")]
 pub struct Configuration {
     pub concentratord: Concentratord,
     pub gateway: Gateway,
+    #[serde(default)]
+    pub forwarder: Forwarder,
+    #[serde(default)]
+    pub metrics: Metrics,
+    #[serde(default)]
+    pub logging: Logging,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Forwarder {
+    pub udp: ForwarderUdp,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ForwarderUdp {
+    pub server: String,
+    pub port_up: u16,
+    pub port_down: u16,
+    #[serde(with = "humantime_serde")]
+    pub keepalive_interval: Duration,
+}
+
+impl Default for ForwarderUdp {
+    fn default() -> Self {
+        ForwarderUdp {
+            server: "".to_string(),
+            port_up: 1700,
+            port_down: 1700,
+            keepalive_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Metrics {
+    pub bind: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Logging {
+    pub output: String,
+    pub format: String,
+    pub path: String,
+    pub max_size: u64,
+    pub max_files: usize,
+}
+
+impl Default for Logging {
+    fn default() -> Self {
+        Logging {
+            output: "".to_string(),
+            format: "TEXT".to_string(),
+            path: "".to_string(),
+            max_size: 10 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
 }
 
 fn example_configuration() -> Configuration {
@@ -104,6 +871,7 @@ fn example_configuration() -> Configuration {
             api: API {
                 event_bind: "ipc:///tmp/concentratord_event".to_string(),
                 command_bind: "ipc:///tmp/concentratord_command".to_string(),
+                ..Default::default()
             },
             ..Default::default()
         },
@@ -133,9 +901,13 @@ fn example_configuration() -> Configuration {
                 spreading_factor: 9,
                 bandwidth: 125000,
                 tx_power: 14,
+                ..Default::default()
             },
             ..Default::default()
         },
+        forwarder: Default::default(),
+        metrics: Default::default(),
+        logging: Default::default(),
     }
 }
 
@@ -147,6 +919,7 @@ pub fn get(filenames: Vec<String>) -> Configuration {
     }
 
     let mut config: Configuration = toml::from_str(&content).expect("Error parsing config file");
+    config.concentratord.config_hash = shadow::config_hash(&content);
 
     // decode gateway id
     let bytes = hex::decode(&config.gateway.gateway_id).expect("Could not decode gateway_id");
@@ -168,7 +941,11 @@ pub fn get(filenames: Vec<String>) -> Configuration {
         "imst_ic880a_eu868" => vendor::imst::ic880a_eu868::new(&config),
         "imst_ic880a_in865" => vendor::imst::ic880a_in865::new(&config),
         "imst_ic880a_ru864" => vendor::imst::ic880a_ru864::new(&config),
+        "imst_lite_gateway_eu868" => vendor::imst::lite_gateway_eu868::new(&config),
+        "kerlink_ibts_eu868" => vendor::kerlink::ibts_eu868::new(&config),
+        "kerlink_ibts_us915" => vendor::kerlink::ibts_us915::new(&config),
         "kerlink_ifemtocell_eu868" => vendor::kerlink::ifemtocell_eu868::new(),
+        "kerlink_ifemtocell_us915" => vendor::kerlink::ifemtocell_us915::new(),
         "multitech_mtac_lora_h_868_eu868" => vendor::multitech::mtac_lora_h_868_eu868::new(&config),
         "multitech_mtac_lora_h_915_us915" => vendor::multitech::mtac_lora_h_915_us915::new(&config),
         "multitech_mtcap_lora_868_eu868" => vendor::multitech::mtcap_lora_868_eu868::new(),
@@ -229,7 +1006,142 @@ pub fn get(filenames: Vec<String>) -> Configuration {
         _ => panic!("unexpected gateway model: {}", config.gateway.model),
     };
 
+    // validate the number of multi-sf channels against the model
+    helpers::validate_multi_sf_channel_count(
+        &config.gateway.concentrator.multi_sf_channels,
+        config.gateway.model_config.max_multi_sf_channels,
+    )
+    .expect("invalid multi_sf_channels");
+
+    // get the beacon frequencies from the beacon region
+    if config.gateway.beacon.frequencies.is_empty() && !config.gateway.beacon.region.is_empty() {
+        let region: region::Region = config
+            .gateway
+            .beacon
+            .region
+            .parse()
+            .expect("invalid beacon region");
+        config.gateway.beacon.frequencies = region.get_beacon_frequencies();
+    }
+
+    // override the spidev path of the model, in case of a multi-board gateway
+    if !config.gateway.board.spidev_path.is_empty() {
+        config.gateway.model_config.spidev_path = config.gateway.board.spidev_path.clone();
+    }
+
+    // override the tx notch filter frequency of the model radios
+    if !config.gateway.radio_tx_notch_freq.is_empty() {
+        if config.gateway.radio_tx_notch_freq.len() != config.gateway.model_config.radio_count {
+            panic!(
+                "radio_tx_notch_freq must contain {} values",
+                config.gateway.model_config.radio_count
+            );
+        }
+
+        for freq in &config.gateway.radio_tx_notch_freq {
+            if *freq != 0 && !(MIN_NOTCH_FREQ..=MAX_NOTCH_FREQ).contains(freq) {
+                panic!(
+                    "radio_tx_notch_freq must be 0 or between {} and {}",
+                    MIN_NOTCH_FREQ, MAX_NOTCH_FREQ
+                );
+            }
+        }
+
+        config.gateway.model_config.radio_tx_notch_freq =
+            config.gateway.radio_tx_notch_freq.clone();
+    }
+
+    // override the gpio chip of the model reset pin
+    if !config.gateway.gpio_chip.is_empty() {
+        if let Some(reset_pin) = config.gateway.model_config.reset_pin.as_mut() {
+            reset_pin.0 = config.gateway.gpio_chip.clone();
+        }
+    }
+
+    // override the model reset pin by the gpio line name
+    if !config.gateway.reset_line.is_empty() {
+        config.gateway.model_config.reset_pin =
+            Some(reset::find_line(&config.gateway.reset_line).expect("find reset_line error"));
+    }
+
+    // override the model gnss tty by the usb id or device-tree alias
+    if !config.gateway.gnss.usb_id.is_empty() || !config.gateway.gnss.dt_alias.is_empty() {
+        let tty_path = gnss::find_tty(&config.gateway.gnss.usb_id, &config.gateway.gnss.dt_alias)
+            .expect("find gnss tty error");
+        config.gateway.model_config.gps = vendor::Gps::TtyPath(tty_path);
+    }
+
+    // override the model gnss source by gpsd, e.g. when the gnss module is shared with other
+    // services
+    match config.gateway.gnss.source.as_ref() {
+        "" | "TTY" => {}
+        "GPSD" => config.gateway.model_config.gps = vendor::Gps::Gpsd,
+        _ => panic!("invalid gnss source: {}", config.gateway.gnss.source),
+    }
+
+    // validate the lorawan channel indices
+    if config.gateway.get_lorawan_channels().len() != config.gateway.lorawan_channels.len() {
+        panic!("lorawan_channels must not contain duplicate frequencies");
+    }
+
+    // validate the lbt channels
+    if config.gateway.lbt.enabled {
+        if config.gateway.lbt.channels.is_empty() {
+            panic!("lbt requires at least one channel");
+        }
+
+        for channel in &config.gateway.lbt.channels {
+            if channel.scan_time_us != 128 && channel.scan_time_us != 5000 {
+                panic!(
+                    "lbt scan_time_us must be 128 or 5000, frequency: {}, scan_time_us: {}",
+                    channel.frequency, channel.scan_time_us
+                );
+            }
+        }
+    }
+
+    // get region, fallback to the region of the model name
+    config.gateway.region_config = match config.gateway.region.as_ref() {
+        "" => region::Region::from_model(&config.gateway.model),
+        _ => Some(config.gateway.region.parse().expect("invalid region")),
+    };
+
     debug!("Antenna gain {} dBi", config.gateway.antenna_gain);
+    debug!("Cable loss {} dB", config.gateway.cable_loss);
 
     return config;
 }
+
+/// Returns true when the concentrator must be re-initialized to apply the new configuration,
+/// e.g. on a configuration reload. The gateway settings which are applied by the handlers
+/// (e.g. the location and TX power) do not require a restart of the concentrator.
+pub fn requires_restart(current: &Configuration, new: &Configuration) -> bool {
+    match (radio_config(&current.gateway), radio_config(&new.gateway)) {
+        (Some(current), Some(new)) => current != new,
+        _ => true,
+    }
+}
+
+// Returns the gateway configuration, without the settings that are applied by the handlers.
+fn radio_config(gateway: &Gateway) -> Option<toml::Value> {
+    let mut gateway = gateway.clone();
+    gateway.antenna_gain = 0;
+    gateway.cable_loss = 0;
+    gateway.shutdown_commands = Vec::new();
+    gateway.reset_sequence = Vec::new();
+    gateway.gateway_id = String::new();
+    gateway.beacon = Default::default();
+    gateway.location = Default::default();
+    gateway.status_led = Default::default();
+    gateway.first_uplink = Default::default();
+    gateway.soak_test = Default::default();
+    gateway.tx_power = Default::default();
+    gateway.downlink_timing = Default::default();
+    gateway.duty_cycle = Default::default();
+    gateway.gps_unlocked = Default::default();
+    gateway.uplink_time = Default::default();
+    gateway.gnss = Default::default();
+    gateway.lorawan_channels = Vec::new();
+
+    toml::Value::try_from(gateway).ok()
+}
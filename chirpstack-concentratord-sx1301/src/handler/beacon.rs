@@ -4,8 +4,8 @@ use std::thread;
 use std::time::Duration;
 
 use anyhow::Result;
-use libconcentratord::jitqueue;
 use libconcentratord::signals::Signal;
+use libconcentratord::{api, jitqueue};
 use libloragw_sx1301::hal;
 use rand::Rng;
 
@@ -14,6 +14,34 @@ use super::{gps, timersync};
 
 const PERIOD: u64 = 128;
 const MARGIN: Duration = Duration::from_secs(5);
+const GW_SPECIFIC_SIZE: usize = 7;
+
+lazy_static! {
+    static ref PAYLOAD_OVERRIDE: Mutex<api::BeaconPayload> = Mutex::new(Default::default());
+}
+
+pub fn set_payload_override(pl: api::BeaconPayload) -> Result<()> {
+    if !pl.gw_specific.is_empty() && pl.gw_specific.len() != GW_SPECIFIC_SIZE {
+        return Err(anyhow!(
+            "gw_specific must be exactly {} bytes",
+            GW_SPECIFIC_SIZE
+        ));
+    }
+
+    if pl.payload.len() > 255 {
+        return Err(anyhow!("payload must not exceed 255 bytes"));
+    }
+
+    info!(
+        "Beacon payload override set, gw_specific: {}, payload: {}",
+        hex::encode(&pl.gw_specific),
+        hex::encode(&pl.payload)
+    );
+
+    *PAYLOAD_OVERRIDE.lock().unwrap() = pl;
+
+    Ok(())
+}
 
 pub fn beacon_loop(
     conf: &config::Beacon,
@@ -78,15 +106,36 @@ fn send_beacon(
 ) -> Result<()> {
     let mut rng = rand::thread_rng();
 
-    let mut beacon_pl = get_beacon(conf.compulsory_rfu_size, beacon_time);
+    let mut beacon_pl = {
+        let payload_override = PAYLOAD_OVERRIDE.lock().unwrap();
+        if !payload_override.payload.is_empty() {
+            payload_override.payload.clone()
+        } else {
+            let mut b = get_beacon(conf.compulsory_rfu_size, beacon_time);
+            let gw_specific = if !payload_override.gw_specific.is_empty() {
+                Some(payload_override.gw_specific.clone())
+            } else if conf.gw_specific {
+                gps::get_coords()
+                    .map(|v| get_gw_specific_coordinates(conf.info_desc, v.latitude, v.longitude))
+            } else {
+                None
+            };
+            if let Some(gw_specific) = gw_specific {
+                b.extend_from_slice(&get_beacon_gw_specific(
+                    &gw_specific,
+                    conf.optional_rfu_size,
+                ));
+            }
+            b
+        }
+    };
     let data_size = beacon_pl.len();
 
     let mut data: [u8; 256] = [0; 256];
     beacon_pl.resize(data.len(), 0);
     data.copy_from_slice(&beacon_pl);
 
-    let tx_freq = conf.frequencies
-        [((beacon_time.as_secs() % (1 << 32)) % conf.frequencies.len() as u64) as usize];
+    let tx_freq = get_beacon_frequency(&conf.frequencies, beacon_time);
     let tx_packet = hal::TxPacket {
         freq_hz: tx_freq,
         tx_mode: hal::TxMode::OnGPS,
@@ -121,13 +170,19 @@ fn send_beacon(
     match queue
         .lock()
         .unwrap()
-        .enqueue(timersync::get_concentrator_count(), tx_packet)
+        .enqueue_beacon(timersync::get_concentrator_count(), tx_packet)
     {
         Ok(_) => Ok(()),
         Err(status) => Err(anyhow!("{:?}", status)),
     }
 }
 
+// The beacon channel is incremented every beacon period.
+fn get_beacon_frequency(frequencies: &[u32], beacon_time: Duration) -> u32 {
+    let channel = ((beacon_time.as_secs() % (1 << 32)) / PERIOD) % frequencies.len() as u64;
+    frequencies[channel as usize]
+}
+
 fn get_beacon(rfu_size: usize, beacon_time: Duration) -> Vec<u8> {
     // [N: RFU | 4: TIME | 2: CRC]
     let mut b: Vec<u8> = vec![0; rfu_size + 6];
@@ -136,11 +191,43 @@ fn get_beacon(rfu_size: usize, beacon_time: Duration) -> Vec<u8> {
     let time_bytes = ((beacon_time % (1 << 32)) as u32).to_le_bytes();
     b[rfu_size..4 + rfu_size].copy_from_slice(&time_bytes);
 
+    let crc_bytes = crc16(&b[..rfu_size + 4]).to_le_bytes();
+    b[rfu_size + 4..rfu_size + 6].copy_from_slice(&crc_bytes);
+
+    return b;
+}
+
+fn get_beacon_gw_specific(gw_specific: &[u8], rfu_size: usize) -> Vec<u8> {
+    // [7: GwSpecific | N: RFU | 2: CRC]
+    let mut b: Vec<u8> = vec![0; gw_specific.len() + rfu_size + 2];
+    b[..gw_specific.len()].copy_from_slice(gw_specific);
+
+    let crc_bytes = crc16(&b[..gw_specific.len() + rfu_size]).to_le_bytes();
+    b[gw_specific.len() + rfu_size..].copy_from_slice(&crc_bytes);
+
+    b
+}
+
+// Returns the gateway specific field containing the coordinates of the gateway.
+fn get_gw_specific_coordinates(info_desc: u8, latitude: f64, longitude: f64) -> Vec<u8> {
+    // [1: InfoDesc | 3: Lat | 3: Lng]
+    let max = (1 << 23) - 1;
+    let lat = ((latitude / 90.0 * (1 << 23) as f64) as i32).clamp(-max - 1, max);
+    let lng = ((longitude / 180.0 * (1 << 23) as f64) as i32).clamp(-max - 1, max);
+
+    let mut b: Vec<u8> = vec![info_desc];
+    b.extend_from_slice(&lat.to_le_bytes()[..3]);
+    b.extend_from_slice(&lng.to_le_bytes()[..3]);
+
+    b
+}
+
+fn crc16(b: &[u8]) -> u16 {
     let poly: u16 = 0x1021;
     let mut x: u16 = 0;
 
-    for i in 0..b.len() - 2 {
-        x ^= (b[i] as u16) << 8;
+    for v in b {
+        x ^= (*v as u16) << 8;
         for _j in 0..8 {
             if x & 0x8000 != 0 {
                 x = (x << 1) ^ poly;
@@ -150,10 +237,7 @@ fn get_beacon(rfu_size: usize, beacon_time: Duration) -> Vec<u8> {
         }
     }
 
-    let crc_bytes = x.to_le_bytes();
-    b[rfu_size + 4..rfu_size + 6].copy_from_slice(&crc_bytes);
-
-    return b;
+    x
 }
 
 #[cfg(test)]
@@ -167,4 +251,48 @@ mod tests {
 
         assert_eq!(vec![0x00, 0x00, 0x00, 0x00, 0x02, 0xcc, 0xa2, 0x7e], beacon);
     }
+
+    #[test]
+    fn test_get_beacon_gw_specific() {
+        let gw_specific = get_beacon_gw_specific(&[0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06], 0);
+        assert_eq!(9, gw_specific.len());
+        assert_eq!(
+            crc16(&[0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06]).to_le_bytes(),
+            gw_specific[7..9]
+        );
+    }
+
+    #[test]
+    fn test_get_gw_specific_coordinates() {
+        assert_eq!(
+            vec![0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+            get_gw_specific_coordinates(0, 0.0, 0.0)
+        );
+        assert_eq!(
+            vec![0x01, 0xff, 0xff, 0x7f, 0x00, 0x00, 0xc0],
+            get_gw_specific_coordinates(1, 90.0, -90.0)
+        );
+        assert_eq!(
+            vec![0x02, 0x00, 0x00, 0xa0, 0x00, 0x00, 0x40],
+            get_gw_specific_coordinates(2, -67.5, 90.0)
+        );
+    }
+
+    #[test]
+    fn test_get_beacon_frequency() {
+        let frequencies = vec![923300000, 923900000, 924500000];
+
+        assert_eq!(
+            923300000,
+            get_beacon_frequency(&frequencies, Duration::from_secs(0))
+        );
+        assert_eq!(
+            923900000,
+            get_beacon_frequency(&frequencies, Duration::from_secs(128))
+        );
+        assert_eq!(
+            923300000,
+            get_beacon_frequency(&frequencies, Duration::from_secs(3 * 128))
+        );
+    }
 }
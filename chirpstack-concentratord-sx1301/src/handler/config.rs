@@ -1,6 +1,6 @@
 use anyhow::Result;
 
-use super::super::config::{Concentrator, Configuration};
+use super::super::config::{helpers, Concentrator, Configuration};
 
 pub fn update_configuration(
     config: &mut Configuration,
@@ -43,6 +43,11 @@ pub fn update_configuration(
         };
     }
 
+    helpers::validate_multi_sf_channel_count(
+        &concentrator.multi_sf_channels,
+        config.gateway.model_config.max_multi_sf_channels,
+    )?;
+
     // set config
     config.gateway.config_version = new_config.version.clone();
     config.gateway.concentrator = concentrator;
@@ -50,6 +55,74 @@ pub fn update_configuration(
     Ok(())
 }
 
+/// Returns the active channel configuration, in the format accepted by update_configuration.
+pub fn get_configuration(
+    config: &Configuration,
+    gateway_id: &[u8],
+) -> chirpstack_api::gw::GatewayConfiguration {
+    let concentrator = &config.gateway.concentrator;
+    let mut channels = Vec::new();
+
+    for frequency in concentrator.multi_sf_channels.iter() {
+        if *frequency == 0 {
+            continue;
+        }
+
+        channels.push(chirpstack_api::gw::ChannelConfiguration {
+            frequency: *frequency,
+            modulation_config: Some(
+                chirpstack_api::gw::channel_configuration::ModulationConfig::LoraModulationConfig(
+                    chirpstack_api::gw::LoraModulationConfig {
+                        bandwidth: 125000,
+                        spreading_factors: vec![7, 8, 9, 10, 11, 12],
+                        ..Default::default()
+                    },
+                ),
+            ),
+            ..Default::default()
+        });
+    }
+
+    if concentrator.lora_std.frequency != 0 {
+        channels.push(chirpstack_api::gw::ChannelConfiguration {
+            frequency: concentrator.lora_std.frequency,
+            modulation_config: Some(
+                chirpstack_api::gw::channel_configuration::ModulationConfig::LoraModulationConfig(
+                    chirpstack_api::gw::LoraModulationConfig {
+                        bandwidth: concentrator.lora_std.bandwidth,
+                        spreading_factors: vec![concentrator.lora_std.spreading_factor as u32],
+                        ..Default::default()
+                    },
+                ),
+            ),
+            ..Default::default()
+        });
+    }
+
+    if concentrator.fsk.frequency != 0 {
+        channels.push(chirpstack_api::gw::ChannelConfiguration {
+            frequency: concentrator.fsk.frequency,
+            modulation_config: Some(
+                chirpstack_api::gw::channel_configuration::ModulationConfig::FskModulationConfig(
+                    chirpstack_api::gw::FskModulationConfig {
+                        bandwidth: concentrator.fsk.bandwidth,
+                        bitrate: concentrator.fsk.datarate,
+                        ..Default::default()
+                    },
+                ),
+            ),
+            ..Default::default()
+        });
+    }
+
+    chirpstack_api::gw::GatewayConfiguration {
+        gateway_id: hex::encode(gateway_id),
+        version: config.gateway.config_version.clone(),
+        channels,
+        ..Default::default()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::super::config::{FSKChannel, LoRaStdChannel};
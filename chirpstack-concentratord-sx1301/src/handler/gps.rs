@@ -1,14 +1,17 @@
+use std::collections::HashMap;
 use std::io::{BufRead, BufReader, Read};
-use std::sync::mpsc::Receiver;
+use std::sync::mpsc::{Receiver, TryRecvError};
 use std::sync::Mutex;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::Result;
 use chrono::offset::Utc;
 use chrono::DateTime;
 
 use crate::config;
-use libconcentratord::{gpsd, signals::Signal};
+use libconcentratord::{
+    drift, gnss, gpsd, led, metrics, nmea, pps, signals::Signal, state, stats, timesource, timesync,
+};
 use libloragw_sx1301::{gps, hal};
 
 lazy_static! {
@@ -23,6 +26,14 @@ lazy_static! {
     static ref GPS_TIME_REF_VALID: Mutex<bool> = Mutex::new(false);
     static ref XTAL_CORRECT_OK: Mutex<bool> = Mutex::new(false);
     static ref XTAL_CORRECT: Mutex<f64> = Mutex::new(1.0);
+    static ref PPS_LATCH_ERRORS: Mutex<usize> = Mutex::new(0);
+    static ref LAST_KNOWN_MAX_ERROR: Mutex<Option<Duration>> = Mutex::new(None);
+    static ref UPLINK_TIME: Mutex<(timesource::UplinkTime, timesource::UplinkGpsEpoch)> =
+        Mutex::new((
+            timesource::UplinkTime::Gnss,
+            timesource::UplinkGpsEpoch::PpsLocked
+        ));
+    static ref TIMESTAMP_DEBUG: Mutex<bool> = Mutex::new(false);
 }
 
 const XERR_INIT_AVG: isize = 128;
@@ -42,26 +53,85 @@ pub fn set_static_gps_coords(lat: f64, lon: f64, alt: i16) {
     }
 }
 
-pub fn gps_loop(gps_device: config::vendor::Gps, stop_receive: Receiver<Signal>) {
-    debug!("Starting GPS loop");
+/// Invalidates the GPS time reference. This must be called when the concentrator is
+/// re-initialized, as the concentrator counter is reset.
+pub fn reset_time_reference() {
+    *GPS_TIME_REF.lock().unwrap() = Default::default();
+    *GPS_TIME_REF_VALID.lock().unwrap() = false;
+    *PPS_LATCH_ERRORS.lock().unwrap() = 0;
+}
 
-    let mut gps_reader: Box<dyn BufRead> = match gps_device {
+/// Sets up the GPS device and returns its reader. This does not depend on the concentrator,
+/// such that it can run concurrently with the concentrator setup.
+pub fn setup(
+    gps_device: config::vendor::Gps,
+    gnss_config: config::Gnss,
+) -> Option<Box<dyn BufRead + Send>> {
+    Some(match gps_device {
         config::vendor::Gps::TtyPath(tty_path) => {
             info!("Enabling GPS device, tty_path: {}", tty_path);
-            let gps_file = gps::enable(&tty_path, gps::GPSFamily::UBX7, 0)
+            let mut gps_file = gps::enable(&tty_path, gps::GPSFamily::UBX7, 0)
                 .expect("could not open gps tty path for gps sync");
-            Box::new(BufReader::new(gps_file)) as Box<dyn BufRead>
+
+            // the hal always configures the tty at 9600 baud
+            let baud_rate = if gnss_config.auto_baud {
+                gnss::detect_baud_rate(&mut gps_file).expect("detect gps baud rate error")
+            } else if gnss_config.baud_rate != 0 {
+                gnss::set_baud_rate(&gps_file, gnss_config.baud_rate, None)
+                    .expect("set gps baud rate error");
+                gnss_config.baud_rate
+            } else {
+                9600
+            };
+            if baud_rate != 9600 {
+                gnss::enable_ubx_nav_timegps(&mut gps_file).expect("enable ubx nav-timegps error");
+            }
+
+            if !gnss_config.assist_url.is_empty() {
+                if let Err(err) = gnss::assist(&mut gps_file, &gnss_config.assist_url) {
+                    error!("GNSS assistance error, error: {}", err);
+                }
+            }
+
+            Box::new(BufReader::new(gps_file)) as Box<dyn BufRead + Send>
         }
         config::vendor::Gps::Gpsd => {
-            info!("Starting gpsd reader, server: localhost:2947");
-            Box::new(gpsd::get_reader("localhost:2947").expect("could not open gpsd reader"))
-                as Box<dyn BufRead>
+            info!("Starting gpsd reader, server: {}", gnss_config.gpsd_server);
+            Box::new(
+                gpsd::get_reader(&gnss_config.gpsd_server).expect("could not open gpsd reader"),
+            ) as Box<dyn BufRead + Send>
         }
         config::vendor::Gps::None => {
             warn!("No GPS device configured");
+            return None;
+        }
+    })
+}
+
+pub fn gps_loop(
+    ready_receive: Receiver<Option<Box<dyn BufRead + Send>>>,
+    stop_receive: Receiver<Signal>,
+) {
+    debug!("Starting GPS loop");
+
+    // The GPS device is set up concurrently with the concentrator, wait until it is ready.
+    let mut gps_reader = loop {
+        if let Ok(v) = stop_receive.recv_timeout(Duration::from_millis(100)) {
+            debug!("Received stop signal, signal: {}", v);
             return;
         }
+
+        match ready_receive.try_recv() {
+            Ok(Some(v)) => break v,
+            Ok(None) => return,
+            Err(TryRecvError::Empty) => continue,
+            Err(TryRecvError::Disconnected) => {
+                error!("GPS device setup failed");
+                return;
+            }
+        }
     };
+    info!("GPS device ready");
 
     loop {
         match stop_receive.recv_timeout(Duration::from_millis(0)) {
@@ -115,6 +185,7 @@ pub fn gps_loop(gps_device: config::vendor::Gps, stop_receive: Receiver<Signal>)
                 gps_reader
                     .read_until(b'\n', &mut buffer)
                     .expect("read from gps error");
+                nmea::send(&buffer);
 
                 match gps::parse_nmea(&buffer[..buffer.len() - 1]) {
                     Ok(m_type) => {
@@ -175,13 +246,21 @@ pub fn gps_validate_loop(stop_receive: Receiver<Signal>) {
                 }
             };
             if systime_diff > Duration::from_secs(30) {
+                if *gps_ref_valid {
+                    state::degrade("gps time reference lost");
+                }
                 *gps_ref_valid = false;
 
                 warn!("GPS time reference is not valid, age: {:?}", systime_diff);
             } else {
+                if !*gps_ref_valid {
+                    state::recover("gps time reference valid");
+                }
                 *gps_ref_valid = true;
                 trace!("GPS time reference is valid");
             }
+            metrics::set_gauge("gps_fix", &[], if *gps_ref_valid { 1.0 } else { 0.0 });
+            led::set_gps_fix(*gps_ref_valid);
 
             // manage xtal correction
             if *gps_ref_valid == false {
@@ -255,6 +334,173 @@ pub fn epoch2cnt(gps_epoch: &Duration) -> Result<u32> {
     gps::epoch2cnt(&gps_time_ref, gps_epoch)
 }
 
+/// Set the max. estimated error of GPS epoch timed downlinks that are scheduled using the last
+/// known GPS time reference, when the GPS time reference is not valid. When None, these
+/// downlinks are rejected.
+pub fn set_last_known_max_error(max_error: Option<Duration>) {
+    *LAST_KNOWN_MAX_ERROR.lock().unwrap() = max_error;
+}
+
+/// Converts the GPS epoch of a downlink to the concentrator counter and returns the counter and
+/// the estimated error. When the GPS time reference is not valid, the last known GPS time
+/// reference is used if allowed (see set_last_known_max_error). The error is estimated from the
+/// time between the last known GPS time reference and the downlink, assuming the max. expected
+/// concentrator clock drift.
+pub fn epoch2cnt_downlink(gps_epoch: &Duration) -> Result<(u32, Duration)> {
+    let gps_ref_valid = *GPS_TIME_REF_VALID.lock().unwrap();
+    let gps_time_ref = GPS_TIME_REF.lock().unwrap();
+
+    if gps_ref_valid {
+        return Ok((
+            gps::epoch2cnt(&gps_time_ref, gps_epoch)?,
+            Duration::from_secs(0),
+        ));
+    }
+
+    let max_error = match *LAST_KNOWN_MAX_ERROR.lock().unwrap() {
+        Some(v) => v,
+        None => return Err(anyhow!("gps_ref_valid = false")),
+    };
+
+    // The time reference is reset when the concentrator is (re-)initialized.
+    if gps_time_ref.gps_epoch == Duration::from_secs(0) {
+        return Err(anyhow!("no last known gps time reference"));
+    }
+
+    let extrapolated = if *gps_epoch > gps_time_ref.gps_epoch {
+        *gps_epoch - gps_time_ref.gps_epoch
+    } else {
+        gps_time_ref.gps_epoch - *gps_epoch
+    };
+    let error = drift::estimate_error(extrapolated, drift::get_threshold_ppm());
+    if error > max_error {
+        return Err(anyhow!(
+            "gps_ref_valid = false, estimated error of last known time reference exceeds max_error, error: {:?}, max_error: {:?}",
+            error,
+            max_error
+        ));
+    }
+
+    Ok((gps::epoch2cnt(&gps_time_ref, gps_epoch)?, error))
+}
+
+/// Set the source of the uplink time and when the uplink GPS epoch is set.
+pub fn set_uplink_time(time: timesource::UplinkTime, gps_epoch: timesource::UplinkGpsEpoch) {
+    *UPLINK_TIME.lock().unwrap() = (time, gps_epoch);
+}
+
+/// Returns the time of the uplink received at the given concentrator counter, using the
+/// configured uplink time source.
+pub fn uplink_time(count_us: u32) -> Result<SystemTime> {
+    match UPLINK_TIME.lock().unwrap().0 {
+        timesource::UplinkTime::Gnss => cnt2time(count_us),
+        timesource::UplinkTime::System => {
+            // Correct for the time between the reception and now.
+            let elapsed = Duration::from_micros(
+                super::timersync::get_concentrator_count().wrapping_sub(count_us) as u64,
+            );
+            // The host system clock is corrected when the network server assisted time-sync
+            // is enabled.
+            let now = timesync::correct(SystemTime::now());
+            if elapsed > Duration::from_secs(1) {
+                return Ok(now);
+            }
+            Ok(now - elapsed)
+        }
+        timesource::UplinkTime::None => Err(anyhow!("uplink time is disabled")),
+    }
+}
+
+/// Returns the GPS epoch of the uplink received at the given concentrator counter, using the
+/// configured uplink GPS epoch policy.
+pub fn uplink_gps_epoch(count_us: u32) -> Result<Duration> {
+    match UPLINK_TIME.lock().unwrap().1 {
+        timesource::UplinkGpsEpoch::PpsLocked => cnt2epoch(count_us),
+        timesource::UplinkGpsEpoch::LastKnown => {
+            let gps_time_ref = GPS_TIME_REF.lock().unwrap();
+
+            // The time reference is reset when the concentrator is (re-)initialized.
+            if gps_time_ref.gps_epoch == Duration::from_secs(0) {
+                return Err(anyhow!("no last known gps time reference"));
+            }
+
+            gps::cnt2epoch(&gps_time_ref, count_us)
+        }
+        timesource::UplinkGpsEpoch::Never => Err(anyhow!("uplink gps epoch is disabled")),
+    }
+}
+
+/// Enable adding the raw concentrator counter, the time reference and the converted timestamps
+/// to the uplink meta-data.
+pub fn set_timestamp_debug(enabled: bool) {
+    *TIMESTAMP_DEBUG.lock().unwrap() = enabled;
+}
+
+pub fn is_timestamp_debug() -> bool {
+    *TIMESTAMP_DEBUG.lock().unwrap()
+}
+
+/// Returns the raw concentrator counter of the uplink, the GPS time reference used to convert it
+/// and the offset to this time reference as uplink meta-data. The times are formatted as
+/// seconds.nanoseconds (since the Unix epoch, or since the GPS epoch for the GPS epoch).
+pub fn get_timestamp_debug(count_us: u32) -> HashMap<String, String> {
+    let (time, gps_epoch) = *UPLINK_TIME.lock().unwrap();
+    let gps_ref_valid = *GPS_TIME_REF_VALID.lock().unwrap();
+    let gps_time_ref = GPS_TIME_REF.lock().unwrap();
+
+    let mut metadata = HashMap::new();
+    metadata.insert("ts_count_us".to_string(), count_us.to_string());
+    metadata.insert("ts_time_source".to_string(), time.to_string());
+    metadata.insert("ts_gps_epoch_policy".to_string(), gps_epoch.to_string());
+    metadata.insert("ts_ref_valid".to_string(), gps_ref_valid.to_string());
+
+    // The time reference is reset when the concentrator is (re-)initialized.
+    if gps_time_ref.gps_epoch != Duration::from_secs(0) {
+        metadata.insert(
+            "ts_ref_count_us".to_string(),
+            gps_time_ref.count_us.to_string(),
+        );
+        metadata.insert(
+            "ts_ref_offset_us".to_string(),
+            (count_us.wrapping_sub(gps_time_ref.count_us) as i32).to_string(),
+        );
+        metadata.insert(
+            "ts_ref_gps_epoch".to_string(),
+            format_duration(gps_time_ref.gps_epoch),
+        );
+        metadata.insert(
+            "ts_ref_gps_time".to_string(),
+            format_time(gps_time_ref.gps_time),
+        );
+        metadata.insert(
+            "ts_ref_system_time".to_string(),
+            format_time(gps_time_ref.system_time),
+        );
+        metadata.insert(
+            "ts_xtal_err".to_string(),
+            format!("{:.9}", gps_time_ref.xtal_err),
+        );
+    }
+
+    metadata
+}
+
+fn format_time(t: SystemTime) -> String {
+    match t.duration_since(UNIX_EPOCH) {
+        Ok(v) => format_duration(v),
+        Err(_) => "0.000000000".to_string(),
+    }
+}
+
+fn format_duration(d: Duration) -> String {
+    format!("{}.{:09}", d.as_secs(), d.subsec_nanos())
+}
+
+/// Returns true when the GPS time reference is valid.
+pub fn is_time_ref_valid() -> bool {
+    *GPS_TIME_REF_VALID.lock().unwrap()
+}
+
 pub fn get_coords() -> Option<gps::Coordinates> {
     let gps_time_ref_valid = GPS_TIME_REF_VALID.lock().unwrap();
     let coords = GPS_COORDS.lock().unwrap();
@@ -296,6 +542,41 @@ fn gps_process_sync() {
 
     let mut time_reference = GPS_TIME_REF.lock().unwrap();
 
+    // Validate the PPS latched counter against the previous time reference. An outlier is
+    // excluded from the time conversion, unless this happens consecutively (e.g. after a
+    // concentrator reset).
+    if time_reference.count_us != 0
+        && !pps::is_plausible(
+            time_reference.count_us,
+            time_reference.gps_epoch,
+            trig_cnt,
+            gps_epoch,
+        )
+    {
+        let mut pps_latch_errors = PPS_LATCH_ERRORS.lock().unwrap();
+        *pps_latch_errors += 1;
+        stats::inc_counter("pps_latch_errors");
+
+        if *pps_latch_errors < pps::MAX_CONSECUTIVE_ERRORS {
+            warn!(
+                "Implausible PPS latched counter value, ignoring, count_us: {}, prev_count_us: {}, gps_epoch: {:?}, prev_gps_epoch: {:?}",
+                trig_cnt,
+                time_reference.count_us,
+                gps_epoch,
+                time_reference.gps_epoch,
+            );
+            return;
+        }
+
+        warn!(
+            "Implausible PPS latched counter value, accepting after {} consecutive errors, count_us: {}",
+            pps_latch_errors, trig_cnt,
+        );
+        *pps_latch_errors = 0;
+    } else {
+        *PPS_LATCH_ERRORS.lock().unwrap() = 0;
+    }
+
     *time_reference = match gps::sync(&time_reference, &trig_cnt, &gps_time, &gps_epoch) {
         Ok(v) => v,
         Err(err) => {
@@ -334,6 +615,12 @@ fn gps_process_coords() {
     *coords = Some(c);
     *coords_error = ce;
 
+    gnss::set_last_fix(gnss::Fix {
+        latitude: c.latitude,
+        longitude: c.longitude,
+        altitude: c.altitude,
+    });
+
     trace!(
         "GPS coordinates sync completed, coords: {:?}, coords_error: {:?}",
         coords,
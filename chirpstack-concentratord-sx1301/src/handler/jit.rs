@@ -1,22 +1,155 @@
 use std::sync::mpsc::Receiver;
 use std::sync::{Arc, Mutex};
+use std::thread;
 use std::time::Duration;
 
+use anyhow::Result;
+use chirpstack_api::gw;
 use libconcentratord::jitqueue::TxPacket;
 use libconcentratord::signals::Signal;
-use libconcentratord::{jitqueue, stats};
+use libconcentratord::{
+    api, dutycycle, events, history, jitqueue, stats, storage, timesource, txtest,
+};
 use libloragw_sx1301::hal;
+use prost::Message;
 
 use super::super::wrapper;
-use super::timersync;
+use super::{gps, timersync};
+
+// Max. time to wait for a valid GPS time reference, when re-scheduling the pending downlinks
+// after the concentrator has been re-initialized.
+const RESTORE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Storage key of the persisted pending downlinks.
+const PENDING_STORAGE_KEY: &str = "jit_queue";
+
+lazy_static! {
+    static ref PENDING: Mutex<Vec<(wrapper::TxPacket, PendingTime)>> = Mutex::new(Vec::new());
+}
+
+// Scheduled time of a pending downlink, see save_pending.
+#[derive(Clone, Copy)]
+enum PendingTime {
+    GpsEpoch(Duration),
+    Offset(jitqueue::PendingOffset),
+}
+
+/// Saves the pending downlinks, so that these are re-scheduled by the JIT loop once the
+/// concentrator has been re-initialized. As the concentrator counter is reset on
+/// re-initialization, the downlinks are saved with their GPS epoch time. Without valid GPS time
+/// reference, these are saved with their offset relative to the host monotonic time. Downlinks
+/// of which the TX time has passed are dropped.
+pub fn save_pending(queue: &Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>) {
+    let mut pending = PENDING.lock().unwrap();
+    let concentrator_count = timersync::get_concentrator_count();
+    let now = timesource::monotonic();
+
+    for tx_packet in queue.lock().unwrap().drain() {
+        let pending_time = match gps::cnt2epoch(tx_packet.get_count_us()) {
+            Ok(v) => Some(PendingTime::GpsEpoch(v)),
+            Err(_) => {
+                jitqueue::PendingOffset::new(tx_packet.get_count_us(), concentrator_count, now)
+                    .map(PendingTime::Offset)
+            }
+        };
+
+        match pending_time {
+            Some(v) => pending.push((tx_packet, v)),
+            None => drop_pending(&tx_packet, "TX time has passed"),
+        }
+    }
+
+    if !pending.is_empty() {
+        info!(
+            "Saved pending downlinks for re-scheduling, count: {}",
+            pending.len()
+        );
+    }
+}
+
+/// Persists the saved pending downlinks (see save_pending) to the storage, so that these are
+/// restored by load_pending on the next startup. As the host monotonic time does not persist
+/// across a restart, only the downlinks saved with their GPS epoch time are persisted.
+pub fn persist_pending() -> Result<()> {
+    let mut pending = PENDING.lock().unwrap();
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    // Each downlink is encoded as length-delimited DownlinkFrame.
+    let mut b = Vec::new();
+    let mut count = 0;
+    for (tx_packet, pending_time) in pending.drain(..) {
+        let gps_epoch = match pending_time {
+            PendingTime::GpsEpoch(v) => v,
+            PendingTime::Offset(_) => {
+                drop_pending(&tx_packet, "no valid GPS time reference");
+                continue;
+            }
+        };
+        let item = wrapper::pending_to_proto(&tx_packet.tx_packet(), &gps_epoch)?;
+        gw::DownlinkFrame {
+            downlink_id: tx_packet.get_id(),
+            items: vec![item],
+            ..Default::default()
+        }
+        .encode_length_delimited(&mut b)?;
+        count += 1;
+    }
+    if b.is_empty() {
+        return Ok(());
+    }
+    storage::set(PENDING_STORAGE_KEY, &b)?;
+
+    info!("Persisted pending downlinks, count: {}", count);
+
+    Ok(())
+}
+
+/// Loads the downlinks persisted by persist_pending. These are re-scheduled by the JIT loop
+/// once the GPS time reference is valid, downlinks of which the TX time has passed by then are
+/// dropped.
+pub fn load_pending() -> Result<()> {
+    let b = match storage::get(PENDING_STORAGE_KEY)? {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+
+    // The persisted downlinks are only restored once.
+    storage::delete(PENDING_STORAGE_KEY)?;
+
+    let mut buf = b.as_slice();
+    let mut pending = PENDING.lock().unwrap();
+    while !buf.is_empty() {
+        let df = gw::DownlinkFrame::decode_length_delimited(&mut buf)?;
+        let item = df
+            .items
+            .first()
+            .ok_or_else(|| anyhow!("downlink item is missing"))?;
+        let (tx_packet, gps_epoch) = wrapper::pending_from_proto(df.downlink_id, item)?;
+        pending.push((tx_packet, PendingTime::GpsEpoch(gps_epoch)));
+    }
+
+    info!(
+        "Loaded persisted pending downlinks for re-scheduling, count: {}",
+        pending.len()
+    );
+
+    Ok(())
+}
 
 pub fn jit_loop(
+    gateway_id: &[u8],
     queue: Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
     antenna_gain: i8,
     stop_receive: Receiver<Signal>,
 ) {
     debug!("Starting JIT queue loop");
 
+    let mut pending: Vec<(wrapper::TxPacket, PendingTime)> =
+        PENDING.lock().unwrap().drain(..).collect();
+    let restore_deadline = timesource::monotonic() + RESTORE_TIMEOUT;
+
     loop {
         // Instead of a 10ms sleep, we receive from the stop channel with a
         // timeout of 10ms.
@@ -28,33 +161,70 @@ pub fn jit_loop(
             _ => {}
         };
 
+        // The downlinks are suspended during a certification TX test.
+        if txtest::certification_active() {
+            continue;
+        }
+
+        if !pending.is_empty() {
+            restore_pending(&queue, &mut pending, restore_deadline);
+        }
+
         let tx_packet = match get_tx_packet(&queue) {
             Some(v) => v,
             None => continue,
         };
 
         let downlink_id = tx_packet.get_id();
+        let gps_timing_error = tx_packet.gps_timing_error();
         let mut tx_packet = tx_packet.tx_packet();
+        let eirp = tx_packet.rf_power;
         tx_packet.rf_power = tx_packet.rf_power - antenna_gain;
 
-        match hal::send(&tx_packet) {
+        match send(&tx_packet, downlink_id) {
             Ok(_) => {
                 info!(
-                    "Scheduled packet for TX, downlink_id: {}, count_us: {}, freq: {}, bw: {}, mod: {:?}, dr: {:?}",
+                    "Scheduled packet for TX, downlink_id: {}, count_us: {}, freq: {}, bw: {}, mod: {:?}, dr: {:?}, power: {}",
                     downlink_id,
                     tx_packet.count_us,
                     tx_packet.freq_hz,
                     tx_packet.bandwidth,
                     tx_packet.modulation,
-                    tx_packet.datarate
+                    tx_packet.datarate,
+                    eirp,
                 );
 
                 if let Ok(tx_info) = wrapper::downlink_to_tx_info_proto(&tx_packet) {
                     stats::inc_tx_counts(&tx_info);
                 }
+
+                // The airtime is consumed from the duty-cycle budget once the packet has been
+                // handed over to the concentrator (this includes the beacons).
+                if let Ok(time_on_air) = hal::time_on_air(&tx_packet) {
+                    dutycycle::record(tx_packet.freq_hz, time_on_air);
+                }
+
+                events::send_tx_done(&api::TxDone {
+                    gateway_id: hex::encode(gateway_id),
+                    downlink_id,
+                    frequency: tx_packet.freq_hz,
+                    power: eirp as i32,
+                    count_us: tx_packet.count_us,
+                    gps_timing_error_us: gps_timing_error.as_micros() as u32,
+                })
+                .unwrap();
             }
             Err(err) => {
                 error!("Schedule packet for tx error, error: {}", err);
+                if err.is::<hal::LBTError>() {
+                    stats::inc_counter("tx_lbt_rejected");
+                }
+                history::record_tx_failed(
+                    downlink_id,
+                    tx_packet.freq_hz,
+                    tx_packet.count_us,
+                    &err.to_string(),
+                );
             }
         }
     }
@@ -62,10 +232,117 @@ pub fn jit_loop(
     debug!("JIT loop ended");
 }
 
+// Interval between TX attempts when the concentrator is busy.
+const TX_RETRY_INTERVAL: Duration = Duration::from_millis(1);
+
+// TX is no longer retried when the scheduled TX time is within this margin (us).
+const TX_RETRY_MARGIN: u32 = 1500;
+
+// When the concentrator is still busy (scheduled or emitting) at the moment the packet is
+// handed over to the HAL, the TX is retried until the remaining margin is exhausted.
+fn send(tx_packet: &hal::TxPacket, downlink_id: u32) -> Result<()> {
+    loop {
+        let err = match hal::send(tx_packet) {
+            Ok(_) => return Ok(()),
+            Err(err) => err,
+        };
+
+        // the TX was not allowed by LBT (e.g. the channel is busy), this is not retried
+        if err.is::<hal::LBTError>() {
+            return Err(err);
+        }
+
+        match hal::status(hal::StatusSelect::Tx) {
+            Ok(hal::StatusReturn::Tx(hal::TxStatus::Scheduled))
+            | Ok(hal::StatusReturn::Tx(hal::TxStatus::Emitting)) => {}
+            _ => return Err(err),
+        };
+
+        // The remaining time wraps around when the scheduled time has passed.
+        let remaining = tx_packet
+            .count_us
+            .wrapping_sub(timersync::get_concentrator_count());
+        if matches!(tx_packet.tx_mode, hal::TxMode::OnGPS)
+            || remaining < TX_RETRY_MARGIN
+            || remaining > (i32::MAX as u32)
+        {
+            stats::inc_counter("tx_busy_failed");
+            return Err(anyhow!("concentrator busy"));
+        }
+
+        warn!(
+            "Concentrator busy, retrying TX, downlink_id: {}, remaining_us: {}",
+            downlink_id, remaining
+        );
+        stats::inc_counter("tx_busy_retries");
+        thread::sleep(TX_RETRY_INTERVAL);
+    }
+}
+
+// Re-schedules the pending downlinks. The downlinks saved with their offset are re-scheduled
+// directly, the downlinks saved with their GPS epoch time once the GPS time reference is valid.
+// These are dropped when the GPS time reference is not valid before the deadline.
+fn restore_pending(
+    queue: &Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
+    pending: &mut Vec<(wrapper::TxPacket, PendingTime)>,
+    deadline: Duration,
+) {
+    let now = timesource::monotonic();
+    let mut queue = queue.lock().unwrap();
+    let concentrator_count = timersync::get_concentrator_count();
+    let mut waiting = Vec::new();
+
+    for (mut tx_packet, pending_time) in pending.drain(..) {
+        let count_us = match pending_time {
+            PendingTime::Offset(v) => match v.count_us(concentrator_count, now) {
+                Some(v) => v,
+                None => {
+                    drop_pending(&tx_packet, "TX time has passed");
+                    continue;
+                }
+            },
+            PendingTime::GpsEpoch(v) => match gps::epoch2cnt(&v) {
+                Ok(v) => v,
+                Err(_) if now > deadline => {
+                    drop_pending(&tx_packet, "no valid GPS time reference");
+                    continue;
+                }
+                Err(_) => {
+                    waiting.push((tx_packet, pending_time));
+                    continue;
+                }
+            },
+        };
+        tx_packet.set_count_us(count_us);
+
+        match queue.enqueue(concentrator_count, tx_packet) {
+            Ok(_) => {
+                info!(
+                    "Re-scheduled pending downlink, downlink_id: {}, count_us: {}",
+                    tx_packet.get_id(),
+                    count_us
+                );
+            }
+            Err(rejection) => drop_pending(&tx_packet, &rejection.to_string()),
+        }
+    }
+
+    *pending = waiting;
+}
+
+fn drop_pending(tx_packet: &wrapper::TxPacket, reason: &str) {
+    warn!(
+        "Dropping pending downlink, downlink_id: {}, reason: {}",
+        tx_packet.get_id(),
+        reason
+    );
+    stats::inc_counter("jit_restart_dropped");
+}
+
 fn get_tx_packet(
     queue: &Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
 ) -> Option<wrapper::TxPacket> {
     let mut queue = queue.lock().unwrap();
     let concentrator_count = timersync::get_concentrator_count();
-    return queue.pop(concentrator_count);
+    queue.pop(concentrator_count)
 }
@@ -5,4 +5,5 @@ pub mod gps;
 pub mod jit;
 pub mod stats;
 pub mod timersync;
+pub mod txtest;
 pub mod uplink;
@@ -3,15 +3,15 @@ use std::sync::mpsc::Receiver;
 use std::time::Duration;
 
 use libconcentratord::signals::Signal;
-use libconcentratord::stats;
+use libconcentratord::{gnss, stats};
 
-use super::gps;
+use super::{gps, timersync};
 
 pub fn stats_loop(
     gateway_id: &[u8],
     stats_interval: &Duration,
     stop_receive: Receiver<Signal>,
-    metadata: &HashMap<String, String>,
+    mut metadata: HashMap<String, String>,
 ) {
     debug!("Starting stats loop, stats_interval: {:?}", stats_interval);
 
@@ -26,23 +26,41 @@ pub fn stats_loop(
             _ => {}
         };
 
-        // fetch the current gps coordinates
-        let loc = match gps::get_coords() {
-            Some(v) => Some({
-                let mut loc = chirpstack_api::common::Location {
-                    latitude: v.latitude,
-                    longitude: v.longitude,
-                    altitude: v.altitude as f64,
-                    ..Default::default()
-                };
-
-                loc.set_source(chirpstack_api::common::LocationSource::Gps);
-                loc
+        // fetch the current gps coordinates, while waiting for a (new) fix the last known
+        // fix is reported, flagged as stale in the meta-data
+        let coords = match gps::get_coords() {
+            Some(v) => {
+                metadata.remove(&"gnss_fix_stale".to_string());
+                Some((v.latitude, v.longitude, v.altitude))
+            }
+            None => gnss::get_last_fix().map(|v| {
+                metadata.insert("gnss_fix_stale".to_string(), "true".to_string());
+                (v.latitude, v.longitude, v.altitude)
             }),
-            None => None,
         };
+        let loc = coords.map(|(latitude, longitude, altitude)| {
+            let mut loc = chirpstack_api::common::Location {
+                latitude,
+                longitude,
+                altitude: altitude as f64,
+                ..Default::default()
+            };
+
+            loc.set_source(chirpstack_api::common::LocationSource::Gps);
+            loc
+        });
+
+        // the concentrator clock drift is calculated by the timesync loop.
+        match timersync::get_drift_ppm() {
+            Some(v) => {
+                metadata.insert("concentrator_drift_ppm".to_string(), format!("{:.2}", v));
+            }
+            None => {
+                metadata.remove(&"concentrator_drift_ppm".to_string());
+            }
+        }
 
-        stats::send_and_reset(gateway_id, loc, metadata).expect("sending stats failed");
+        stats::send_and_reset(gateway_id, loc, &metadata).expect("sending stats failed");
     }
 
     debug!("Stats loop ended");
@@ -2,6 +2,7 @@ use std::sync::mpsc::Receiver;
 use std::sync::Mutex;
 use std::time::{Duration, SystemTime};
 
+use libconcentratord::drift;
 use libconcentratord::signals::Signal;
 use libloragw_sx1301::{hal, reg, wrapper};
 
@@ -12,15 +13,18 @@ lazy_static! {
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap()
     );
+    static ref DRIFT_PPM: Mutex<Option<f64>> = Mutex::new(None);
 }
 
 pub fn timesync_loop(stop_receive: Receiver<Signal>) {
     debug!("Starting timesync loop");
 
+    let mut clock_drift = drift::Drift::new();
+
     loop {
         // The timesync is in a separate function to make sure that the
         // mutex guard is dereferenced as soon as the function returns.
-        timesync();
+        timesync(&mut clock_drift);
 
         // Instead of a 60s sleep, we receive from the stop channel with a
         // timeout of 60 seconds.
@@ -48,7 +52,11 @@ pub fn get_concentrator_count() -> u32 {
     return prev_concentrator_count.wrapping_add(unix_diff.as_micros() as u32);
 }
 
-fn timesync() {
+pub fn get_drift_ppm() -> Option<f64> {
+    *DRIFT_PPM.lock().unwrap()
+}
+
+fn timesync(clock_drift: &mut drift::Drift) {
     debug!("Disabling GPS mode for concentrator counter");
     reg::reg_w(wrapper::LGW_GPS_EN, 0).unwrap();
 
@@ -82,6 +90,9 @@ fn timesync() {
     debug!("Current concentrator count_us: {}", concentrator_count);
     debug!("Concentrator drift, drift_us: {}", drift);
 
+    // The drift is estimated over the baseline, rather than over the last interval.
+    *DRIFT_PPM.lock().unwrap() = clock_drift.add(unix_time, concentrator_count);
+
     debug!("Enabling GPS mode for concentrator counter");
     reg::reg_w(wrapper::LGW_GPS_EN, 1).unwrap();
 }
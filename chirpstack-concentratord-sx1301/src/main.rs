@@ -7,20 +7,24 @@ extern crate anyhow;
 extern crate simple_logger;
 extern crate syslog;
 
+use std::panic;
 use std::process;
 use std::str::FromStr;
 use std::sync::mpsc::channel;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread;
 
 use clap::{Parser, Subcommand};
-use signal_hook::consts::signal::SIGINT;
+use signal_hook::consts::signal::{SIGHUP, SIGINT, SIGTERM};
 use signal_hook::iterator::Signals;
 use simple_logger::SimpleLogger;
 use syslog::{BasicLogger, Facility, Formatter3164};
 
-use libconcentratord::reset;
 use libconcentratord::signals::Signal;
+use libconcentratord::{
+    audit, capture, curve, diag, feedback, jitqueue, latency, led, logging, metadata, metrics,
+    nmea, reset, state, systemd,
+};
 
 mod cmd;
 mod concentrator;
@@ -34,6 +38,10 @@ struct Cli {
     #[arg(short, long, value_name = "FILE")]
     config: Vec<String>,
 
+    /// Generate a CURVE key pair for the event and command sockets
+    #[arg(long)]
+    generate_keys: bool,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -46,6 +54,15 @@ enum Commands {
 
 fn main() {
     let cli = Cli::parse();
+
+    if cli.generate_keys {
+        let (public_key, secret_key) =
+            curve::generate_keys().expect("generate CURVE key pair error");
+        println!("public_key=\"{}\"", public_key);
+        println!("secret_key=\"{}\"", secret_key);
+        process::exit(0);
+    }
+
     let mut config = config::get(cli.config.clone());
 
     if let Some(Commands::Configfile {}) = &cli.command {
@@ -53,36 +70,147 @@ fn main() {
         process::exit(0);
     }
 
-    if config.concentratord.log_to_syslog {
-        let formatter = Formatter3164 {
-            facility: Facility::LOG_USER,
-            hostname: None,
-            process: "chirpstack-concentratord-sx1301".into(),
-            pid: process::id().into(),
-        };
-        let logger = syslog::unix(formatter).expect("could not connect to syslog");
-        log::set_boxed_logger(Box::new(BasicLogger::new(logger)))
-            .map(|()| {
-                log::set_max_level(
-                    log::Level::from_str(&config.concentratord.log_level)
-                        .unwrap()
-                        .to_level_filter(),
-                )
-            })
-            .unwrap();
-    } else {
-        SimpleLogger::new()
-            .with_utc_timestamps()
-            .with_level(
-                log::Level::from_str(&config.concentratord.log_level)
-                    .unwrap()
-                    .to_level_filter(),
+    let log_level = log::Level::from_str(&config.concentratord.log_level)
+        .unwrap()
+        .to_level_filter();
+    let log_event_level = match config.concentratord.api.log_event_level.as_ref() {
+        "" => log::LevelFilter::Off,
+        v => log::LevelFilter::from_str(v).expect("invalid log_event_level"),
+    };
+    let log_output = match config.logging.output.as_ref() {
+        "" if config.concentratord.log_to_syslog => logging::Output::Syslog,
+        "" => logging::Output::Stdout,
+        v => logging::Output::from_str(v).expect("invalid logging output"),
+    };
+    let log_format =
+        logging::Format::from_str(&config.logging.format).expect("invalid logging format");
+    let logger: Box<dyn log::Log> = match log_output {
+        logging::Output::Syslog => {
+            let formatter = Formatter3164 {
+                facility: Facility::LOG_USER,
+                hostname: None,
+                process: "chirpstack-concentratord-sx1301".into(),
+                pid: process::id().into(),
+            };
+            let logger = syslog::unix(formatter).expect("could not connect to syslog");
+            let logger: Box<dyn log::Log> = Box::new(BasicLogger::new(logger));
+            match log_format {
+                logging::Format::Text => logger,
+                logging::Format::Json => Box::new(logging::JsonLogger::new(logger)),
+            }
+        }
+        // The level is filtered by the logging wrapper, such that it can be changed at
+        // runtime (e.g. by the diag_bundle command).
+        logging::Output::Stdout if log_format == logging::Format::Text => Box::new(
+            SimpleLogger::new()
+                .with_utc_timestamps()
+                .with_level(log::LevelFilter::Trace),
+        ),
+        logging::Output::Stdout => Box::new(logging::LineLogger::stdout(log_format)),
+        logging::Output::File => Box::new(
+            logging::LineLogger::file(
+                &config.logging.path,
+                config.logging.max_size,
+                config.logging.max_files,
+                log_format,
             )
-            .init()
-            .unwrap();
+            .expect("open log file error"),
+        ),
+    };
+    logging::init(logger, log_level, log_event_level).expect("setup logger error");
+
+    // setup curve authentication, before the sockets are bound
+    if !config.concentratord.api.curve_secret_key.is_empty() {
+        curve::setup(&curve::Configuration {
+            secret_key: config.concentratord.api.curve_secret_key.clone(),
+            client_keys: config.concentratord.api.curve_client_keys.clone(),
+        })
+        .expect("setup curve authentication error");
+    }
+
+    // setup audit log
+    if !config.concentratord.audit_log.path.is_empty() {
+        audit::setup(&audit::Configuration {
+            path: config.concentratord.audit_log.path.clone(),
+            max_size: config.concentratord.audit_log.max_size,
+            max_files: config.concentratord.audit_log.max_files,
+        })
+        .expect("setup audit log error");
+    }
+
+    // setup uplink capture file
+    if !config.concentratord.capture.path.is_empty() {
+        capture::setup(&capture::Configuration {
+            path: config.concentratord.capture.path.clone(),
+            max_size: config.concentratord.capture.max_size,
+            max_files: config.concentratord.capture.max_files,
+        })
+        .expect("setup capture file error");
+    }
+
+    // setup diagnostic bundles
+    if !config.concentratord.diag_bundle.path.is_empty() {
+        diag::setup(&config.concentratord.diag_bundle.path);
+    }
+
+    // setup stats meta-data commands
+    metadata::setup(&metadata::Configuration {
+        commands: config.concentratord.stats_metadata.commands.clone(),
+        timeout: config.concentratord.stats_metadata.timeout,
+    });
+
+    // setup backhaul latency probe
+    if !config.concentratord.latency_probe.target.is_empty() {
+        latency::setup(&latency::Configuration {
+            method: latency::Method::from_str(&config.concentratord.latency_probe.method)
+                .expect("invalid latency_probe method"),
+            target: config.concentratord.latency_probe.target.clone(),
+            timeout: config.concentratord.latency_probe.timeout,
+        });
+    }
+
+    // setup systemd notifications
+    if config.concentratord.systemd_notify {
+        systemd::setup().expect("setup systemd notifications error");
+    }
+
+    // setup nmea passthrough socket
+    if !config.concentratord.api.nmea_socket.is_empty() {
+        nmea::setup(&config.concentratord.api.nmea_socket)
+            .expect("setup nmea passthrough socket error");
     }
 
-    let mut signals = Signals::new(&[SIGINT]).expect("error registering channels");
+    // setup prometheus metrics listener
+    if !config.metrics.bind.is_empty() {
+        metrics::setup(&config.metrics.bind).expect("setup metrics listener error");
+    }
+
+    // setup status led
+    if !config.gateway.status_led.chip.is_empty() || !config.gateway.status_led.led.is_empty() {
+        led::setup(&led::Configuration {
+            chip: config.gateway.status_led.chip.clone(),
+            line: config.gateway.status_led.line,
+            led: config.gateway.status_led.led.clone(),
+            active_low: config.gateway.status_led.active_low,
+        })
+        .expect("setup status led error");
+    }
+
+    // setup first uplink feedback
+    if !config.gateway.first_uplink.chip.is_empty()
+        || !config.gateway.first_uplink.command.is_empty()
+    {
+        feedback::setup(&feedback::Configuration {
+            chip: config.gateway.first_uplink.chip.clone(),
+            line: config.gateway.first_uplink.line,
+            active_low: config.gateway.first_uplink.active_low,
+            duration: config.gateway.first_uplink.duration,
+            command: config.gateway.first_uplink.command.clone(),
+        })
+        .expect("setup first uplink feedback error");
+    }
+
+    let mut signals = Signals::new(&[SIGHUP, SIGINT, SIGTERM]).expect("error registering channels");
     let (stop_send, stop_receive) = channel();
     let stop_receive = Arc::new(stop_receive);
 
@@ -90,30 +218,107 @@ fn main() {
         let stop_send = stop_send.clone();
 
         move || {
-            let mut signal_iter = signals.forever();
-            let _ = signal_iter.next();
-            warn!("Received stop signal, stopping Concentratord");
-            stop_send.send(Signal::Stop).unwrap();
-            let _ = signal_iter.next();
-            warn!("Received stop signal, terminating Concentratord immediately");
-            process::exit(0);
+            let mut stopping = false;
+            for signal in signals.forever() {
+                if signal == SIGHUP {
+                    info!("Received SIGHUP, reloading configuration");
+                    stop_send.send(Signal::Reload).unwrap();
+                    continue;
+                }
+
+                if stopping {
+                    warn!("Received stop signal, terminating Concentratord immediately");
+                    process::exit(0);
+                }
+
+                warn!("Received stop signal, stopping Concentratord");
+                stop_send.send(Signal::Stop).unwrap();
+                stopping = true;
+            }
         }
     });
 
     // configure concentrator reset pin
     reset::setup_pins(reset::Configuration {
         sx130x_reset: config.gateway.model_config.reset_pin.clone(),
+        reset_commands: config.gateway.model_config.reset_commands.clone(),
+        shutdown_commands: config.gateway.get_shutdown_commands(),
+        timing: config.gateway.model_config.reset_timing,
+        sequence: config.gateway.get_reset_sequence(),
+        shared: config.gateway.gpio.shared,
+        busy_timeout: config.gateway.gpio.busy_timeout,
         ..Default::default()
     })
     .expect("setup reset pin error");
 
+    // The JIT queue is kept across a configuration reload which does not require
+    // re-initializing the concentrator.
+    let queue: Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>> =
+        Arc::new(Mutex::new(jitqueue::Queue::new(32)));
+
+    let mut reset_reason = Some(reset::Reason::Startup);
     loop {
-        match cmd::root::run(&config, stop_send.clone(), stop_receive.clone()).unwrap() {
-            Signal::Stop => process::exit(0),
+        match cmd::root::run(
+            &config,
+            reset_reason,
+            stop_send.clone(),
+            stop_receive.clone(),
+            &queue,
+        )
+        .unwrap()
+        {
+            Signal::Stop => state::shutdown(),
             Signal::Configuration(new_config) => {
                 handler::config::update_configuration(&mut config, &new_config)
                     .expect("update configuration failed");
+                reset_reason = Some(reset::Reason::Configuration);
             }
+            Signal::Reload => reset_reason = reload(&mut config, &cli.config, &queue),
+            Signal::Restart => reset_reason = Some(reset::Reason::Recovery),
+        }
+    }
+}
+
+// Reloads the configuration files. Returns the reset reason when the concentrator must be
+// re-initialized, in which case the concentrator has been stopped. On an invalid configuration,
+// the current configuration is kept. Note that the reset pins are not re-configured. When the
+// concentrator is re-initialized, the queued downlinks are saved for re-scheduling.
+fn reload(
+    config: &mut config::Configuration,
+    filenames: &[String],
+    queue: &Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
+) -> Option<reset::Reason> {
+    info!("Reloading configuration, files: {:?}", filenames);
+
+    // config::get panics on an invalid configuration, which must not stop Concentratord.
+    let new_config = match panic::catch_unwind(|| config::get(filenames.to_vec())) {
+        Ok(v) => v,
+        Err(_) => {
+            error!("Reload configuration error, keeping the current configuration");
+            return None;
         }
+    };
+
+    let restart = config::requires_restart(config, &new_config);
+    if restart {
+        info!("Radio configuration changed, restarting concentrator");
+        handler::jit::save_pending(queue);
+        concentrator::stop(config).expect("stop concentrator error");
+    }
+
+    // The event and command sockets are re-bound when the CURVE settings changed.
+    if let Err(err) = curve::setup(&curve::Configuration {
+        secret_key: new_config.concentratord.api.curve_secret_key.clone(),
+        client_keys: new_config.concentratord.api.curve_client_keys.clone(),
+    }) {
+        error!("Setup curve authentication error, error: {}", err);
+    }
+
+    *config = new_config;
+
+    if restart {
+        Some(reset::Reason::Configuration)
+    } else {
+        None
     }
 }
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::time::{Duration, UNIX_EPOCH};
 
 use anyhow::Result;
@@ -9,16 +10,27 @@ use rand::Rng;
 use super::handler::gps;
 
 #[derive(Copy, Clone)]
-pub struct TxPacket(hal::TxPacket, u32);
+pub struct TxPacket(hal::TxPacket, u32, Duration);
 
 impl TxPacket {
     pub fn new(id: u32, tx_packet: hal::TxPacket) -> TxPacket {
-        TxPacket(tx_packet, id)
+        TxPacket(tx_packet, id, Duration::from_secs(0))
+    }
+
+    /// Set the estimated timing error, in case of a GPS timed downlink that is scheduled using
+    /// the last known GPS time reference.
+    pub fn with_gps_timing_error(mut self, error: Duration) -> TxPacket {
+        self.2 = error;
+        self
     }
 
     pub fn tx_packet(&self) -> hal::TxPacket {
         self.0
     }
+
+    pub fn gps_timing_error(&self) -> Duration {
+        self.2
+    }
 }
 
 impl jitqueue::TxPacket for TxPacket {
@@ -50,9 +62,21 @@ impl jitqueue::TxPacket for TxPacket {
     fn get_id(&self) -> u32 {
         self.1
     }
+
+    fn get_frequency(&self) -> u32 {
+        self.0.freq_hz
+    }
 }
 
-pub fn uplink_to_proto(gateway_id: &[u8], packet: &hal::RxPacket) -> Result<gw::UplinkFrame> {
+// Number of IF chains per board. In case of a multi-board gateway, the channels are numbered
+// across the boards (e.g. the first IF chain of the second board is channel 10).
+const BOARD_IF_CHAINS: u32 = 10;
+
+pub fn uplink_to_proto(
+    gateway_id: &[u8],
+    board: u32,
+    packet: &hal::RxPacket,
+) -> Result<gw::UplinkFrame> {
     let mut rng = rand::thread_rng();
 
     // tx info
@@ -89,7 +113,7 @@ pub fn uplink_to_proto(gateway_id: &[u8], packet: &hal::RxPacket) -> Result<gw::
             tx_info.modulation = Some(gw::Modulation {
                 parameters: Some(gw::modulation::Parameters::Fsk(gw::FskModulationInfo {
                     datarate: match packet.datarate {
-                        hal::DataRate::FSK(v) => v * 1000,
+                        hal::DataRate::FSK(v) => v,
                         _ => return Err(anyhow!("unexpected datarate")),
                     },
                     ..Default::default()
@@ -109,9 +133,19 @@ pub fn uplink_to_proto(gateway_id: &[u8], packet: &hal::RxPacket) -> Result<gw::
     rx_info.gateway_id = hex::encode(gateway_id);
     rx_info.rssi = packet.rssi as i32;
     rx_info.snr = packet.snr;
-    rx_info.channel = packet.if_chain as u32;
+    rx_info.board = board;
+    rx_info.channel = board * BOARD_IF_CHAINS + packet.if_chain as u32;
     rx_info.rf_chain = packet.rf_chain as u32;
-    match gps::cnt2time(packet.count_us) {
+    rx_info
+        .metadata
+        .insert("board".to_string(), board.to_string());
+    rx_info
+        .metadata
+        .insert("rf_chain".to_string(), packet.rf_chain.to_string());
+    rx_info
+        .metadata
+        .insert("if_chain".to_string(), packet.if_chain.to_string());
+    match gps::uplink_time(packet.count_us) {
         Ok(v) => {
             let v = v.duration_since(UNIX_EPOCH).unwrap();
 
@@ -122,12 +156,12 @@ pub fn uplink_to_proto(gateway_id: &[u8], packet: &hal::RxPacket) -> Result<gw::
         }
         Err(err) => {
             debug!(
-                "Could not get GPS time, uplink_id: {}, error: {}",
+                "Could not get uplink time, uplink_id: {}, error: {}",
                 rx_info.uplink_id, err
             );
         }
     };
-    match gps::cnt2epoch(packet.count_us) {
+    match gps::uplink_gps_epoch(packet.count_us) {
         Ok(v) => {
             rx_info.time_since_gps_epoch = Some(pbjson_types::Duration {
                 seconds: v.as_secs() as i64,
@@ -136,7 +170,7 @@ pub fn uplink_to_proto(gateway_id: &[u8], packet: &hal::RxPacket) -> Result<gw::
         }
         Err(err) => {
             debug!(
-                "Could not get GPS epoch, uplink_id: {}, error: {}",
+                "Could not get uplink GPS epoch, uplink_id: {}, error: {}",
                 rx_info.uplink_id, err
             );
         }
@@ -155,6 +189,10 @@ pub fn uplink_to_proto(gateway_id: &[u8], packet: &hal::RxPacket) -> Result<gw::
         }
         None => {}
     }
+    if gps::is_timestamp_debug() {
+        let metadata = get_timestamp_debug(packet.count_us, &rx_info);
+        rx_info.metadata.extend(metadata);
+    }
 
     let mut pb: gw::UplinkFrame = Default::default();
 
@@ -165,6 +203,25 @@ pub fn uplink_to_proto(gateway_id: &[u8], packet: &hal::RxPacket) -> Result<gw::
     Ok(pb)
 }
 
+// Returns the raw concentrator counter, the time reference and the converted timestamps of the
+// uplink, such that time conversion issues can be diagnosed from the uplinks.
+fn get_timestamp_debug(count_us: u32, rx_info: &gw::UplinkRxInfo) -> HashMap<String, String> {
+    let mut metadata = gps::get_timestamp_debug(count_us);
+    if let Some(v) = &rx_info.time {
+        metadata.insert(
+            "ts_time".to_string(),
+            format!("{}.{:09}", v.seconds, v.nanos),
+        );
+    }
+    if let Some(v) = &rx_info.time_since_gps_epoch {
+        metadata.insert(
+            "ts_gps_epoch".to_string(),
+            format!("{}.{:09}", v.seconds, v.nanos),
+        );
+    }
+    metadata
+}
+
 pub fn downlink_from_proto(df: &gw::DownlinkFrameItem) -> Result<hal::TxPacket> {
     let mut data: [u8; 256] = [0; 256];
     let mut data_slice = df.phy_payload.clone();
@@ -226,8 +283,8 @@ pub fn downlink_from_proto(df: &gw::DownlinkFrameItem) -> Result<hal::TxPacket>
                             let gps_epoch = Duration::from_secs(v.seconds as u64)
                                 + Duration::from_nanos(v.nanos as u64);
 
-                            match gps::epoch2cnt(&gps_epoch) {
-                                Ok(v) => {
+                            match gps::epoch2cnt_downlink(&gps_epoch) {
+                                Ok((v, _)) => {
                                     packet.count_us = v;
                                 }
                                 Err(err) => return Err(err),
@@ -283,6 +340,35 @@ pub fn downlink_from_proto(df: &gw::DownlinkFrameItem) -> Result<hal::TxPacket>
     return Ok(packet);
 }
 
+pub fn gps_epoch_from_proto(df: &gw::DownlinkFrameItem) -> Option<Duration> {
+    let params = df.tx_info.as_ref()?.timing.as_ref()?.parameters.as_ref()?;
+    match params {
+        gw::timing::Parameters::GpsEpoch(v) => {
+            let v = v.time_since_gps_epoch.as_ref()?;
+            Some(Duration::from_secs(v.seconds as u64) + Duration::from_nanos(v.nanos as u64))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the concentrator counter of the uplink context, for downlinks using GPS epoch timing.
+/// This is used to cross-check the GPS epoch timing against the uplink.
+pub fn gps_timing_context_from_proto(df: &gw::DownlinkFrameItem) -> Option<u32> {
+    let tx_info = df.tx_info.as_ref()?;
+    match tx_info.timing.as_ref()?.parameters.as_ref()? {
+        gw::timing::Parameters::GpsEpoch(_) => {}
+        _ => return None,
+    }
+
+    if tx_info.context.len() != 4 {
+        return None;
+    }
+
+    let mut array = [0; 4];
+    array.copy_from_slice(&tx_info.context);
+    Some(u32::from_be_bytes(array))
+}
+
 pub fn downlink_to_tx_info_proto(packet: &hal::TxPacket) -> Result<gw::DownlinkTxInfo> {
     let mut tx_info: gw::DownlinkTxInfo = Default::default();
     tx_info.frequency = packet.freq_hz;
@@ -319,10 +405,10 @@ pub fn downlink_to_tx_info_proto(packet: &hal::TxPacket) -> Result<gw::DownlinkT
             tx_info.modulation = Some(gw::Modulation {
                 parameters: Some(gw::modulation::Parameters::Fsk(gw::FskModulationInfo {
                     datarate: match packet.datarate {
-                        hal::DataRate::FSK(v) => v * 1000,
+                        hal::DataRate::FSK(v) => v,
                         _ => return Err(anyhow!("unexpected datarate")),
                     },
-                    ..Default::default()
+                    frequency_deviation: packet.f_dev as u32 * 1000,
                 })),
             });
         }
@@ -333,3 +419,61 @@ pub fn downlink_to_tx_info_proto(packet: &hal::TxPacket) -> Result<gw::DownlinkT
 
     Ok(tx_info)
 }
+
+/// Returns the downlink item of a pending downlink, using GPS epoch timing. This is used to
+/// persist the pending downlinks, see pending_from_proto.
+pub fn pending_to_proto(
+    packet: &hal::TxPacket,
+    gps_epoch: &Duration,
+) -> Result<gw::DownlinkFrameItem> {
+    let mut tx_info = downlink_to_tx_info_proto(packet)?;
+    tx_info.power = packet.rf_power as i32;
+    tx_info.timing = Some(gw::Timing {
+        parameters: Some(gw::timing::Parameters::GpsEpoch(gw::GpsEpochTimingInfo {
+            time_since_gps_epoch: Some(pbjson_types::Duration {
+                seconds: gps_epoch.as_secs() as i64,
+                nanos: gps_epoch.subsec_nanos() as i32,
+            }),
+        })),
+    });
+
+    // Set the parameters which are not part of the TX info, such that downlink_from_proto
+    // returns the same packet.
+    match tx_info
+        .modulation
+        .as_mut()
+        .and_then(|v| v.parameters.as_mut())
+    {
+        Some(gw::modulation::Parameters::Lora(v)) => {
+            v.polarization_inversion = packet.invert_pol;
+        }
+        Some(gw::modulation::Parameters::Fsk(_)) => {}
+        _ => return Err(anyhow!("unexpected modulation")),
+    }
+
+    Ok(gw::DownlinkFrameItem {
+        phy_payload: packet.payload[..packet.size as usize].to_vec(),
+        tx_info: Some(tx_info),
+        ..Default::default()
+    })
+}
+
+/// Returns the pending downlink and its GPS epoch time, from a downlink item returned by
+/// pending_to_proto. The concentrator counter is set once the downlink is re-scheduled, as
+/// this requires a valid GPS time reference.
+pub fn pending_from_proto(
+    downlink_id: u32,
+    df: &gw::DownlinkFrameItem,
+) -> Result<(TxPacket, Duration)> {
+    let gps_epoch =
+        gps_epoch_from_proto(df).ok_or_else(|| anyhow!("gps epoch timing is missing"))?;
+
+    let mut df = df.clone();
+    if let Some(tx_info) = df.tx_info.as_mut() {
+        tx_info.timing = None;
+    }
+    let mut tx_packet = downlink_from_proto(&df)?;
+    tx_packet.tx_mode = hal::TxMode::Timestamped;
+
+    Ok((TxPacket::new(downlink_id, tx_packet), gps_epoch))
+}
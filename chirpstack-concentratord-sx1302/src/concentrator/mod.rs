@@ -17,8 +17,11 @@ pub fn board_setconf(config: &Configuration) -> Result<()> {
     };
 
     info!(
-        "Setting board configuration, lorawan_public: {}, clock_source: {}",
-        board_config.lorawan_public, board_config.clock_source
+        "Setting board configuration, lorawan_public: {}, clock_source: {}, com_type: {:?}, com_path: {}",
+        board_config.lorawan_public,
+        board_config.clock_source,
+        board_config.com_type,
+        board_config.com_path
     );
     hal::board_setconf(&board_config)
 }
@@ -59,14 +62,43 @@ pub fn txgain_setconf(config: &Configuration) -> Result<()> {
     Ok(())
 }
 
+pub fn sx1261_setconf(config: &Configuration) -> Result<()> {
+    let spectral_scan = &config.gateway.spectral_scan;
+    if !spectral_scan.enabled {
+        return Ok(());
+    }
+
+    // Listen-before-talk is not used, the SX1261 is only configured for the spectral scan.
+    info!(
+        "Setting up SX1261 radio, spi_path: {}, rssi_offset: {}",
+        spectral_scan.spi_path, spectral_scan.rssi_offset
+    );
+    let sx1261_config = hal::SX1261Config {
+        enable: true,
+        spi_path: spectral_scan.spi_path.clone(),
+        rssi_offset: spectral_scan.rssi_offset,
+        lbt_config: hal::LBTConfig {
+            enable: false,
+            rssi_target: 0,
+            channels: Vec::new(),
+        },
+    };
+    hal::sx1261_setconf(&sx1261_config)
+}
+
 pub fn rxrf_setconf(config: &Configuration) -> Result<()> {
     info!("Setting up concentrator channels");
     let radio_freqs = helpers::get_radio_frequencies(&config)?;
     for (i, radio_freq) in radio_freqs.iter().enumerate() {
+        let antenna_rssi_offset = match config.gateway.get_antenna(i as u32) {
+            Some(v) => v.rssi_offset,
+            None => 0.0,
+        };
         let rx_rf_config = hal::RxRfConfig {
             enable: *radio_freq > 0,
             freq_hz: *radio_freq,
-            rssi_offset: config.gateway.model_config.radio_config[i].rssi_offset,
+            rssi_offset: config.gateway.model_config.radio_config[i].rssi_offset
+                + antenna_rssi_offset,
             rssi_temp_compensation: config.gateway.model_config.radio_config[i]
                 .rssi_temp_compensation,
             radio_type: config.gateway.model_config.radio_config[i].radio_type,
@@ -1,5 +1,8 @@
+use std::collections::HashMap;
 use std::fs;
+use std::str::FromStr;
 
+use libconcentratord::{gnss, region, reset, shadow};
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 
@@ -8,38 +11,688 @@ pub mod vendor;
 
 pub const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Default, Serialize, Deserialize, Clone)]
 pub struct Concentratord {
     pub log_level: String,
     #[serde(default)]
     pub log_to_syslog: bool,
+    #[serde(default)]
+    pub systemd_notify: bool,
     #[serde(with = "humantime_serde")]
     pub stats_interval: Duration,
+    #[serde(default)]
+    pub jit_scheduler: String,
     pub api: API,
+    #[serde(default)]
+    pub remote_config: RemoteConfig,
+    #[serde(default)]
+    pub audit_log: AuditLog,
+    #[serde(default)]
+    pub capture: Capture,
+    #[serde(default)]
+    pub stats_metadata: StatsMetadata,
+    #[serde(default)]
+    pub latency_probe: LatencyProbe,
+    #[serde(default)]
+    pub metered: Metered,
+    #[serde(default)]
+    pub health: Health,
+    #[serde(default)]
+    pub clock_drift: ClockDrift,
+    #[serde(default)]
+    pub time_sync: TimeSync,
+    #[serde(default)]
+    pub shadow: Shadow,
+    #[serde(default)]
+    pub join_flood: JoinFlood,
+    #[serde(default)]
+    pub blacklist: Blacklist,
+    #[serde(default)]
+    pub oversized_uplinks: OversizedUplinks,
+    #[serde(default)]
+    pub uplink_filter: UplinkFilter,
+    #[serde(default)]
+    pub storage: Storage,
+    #[serde(default)]
+    pub downlink_history: DownlinkHistory,
+    #[serde(default)]
+    pub diag_bundle: DiagBundle,
+    // Hash of the configuration file(s), this is set when loading the configuration.
+    #[serde(skip)]
+    pub config_hash: String,
 }
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Default, Serialize, Deserialize, Clone)]
 pub struct API {
     pub event_bind: String,
     pub command_bind: String,
+    #[serde(default)]
+    pub event_sequence: bool,
+    #[serde(default)]
+    pub event_compression: String,
+    #[serde(default)]
+    pub event_encryption_key: String,
+    #[serde(default)]
+    pub curve_secret_key: String,
+    #[serde(default)]
+    pub curve_client_keys: Vec<String>,
+    #[serde(default)]
+    pub allowed_commands: Vec<String>,
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default)]
+    pub log_event_level: String,
+    #[serde(default)]
+    pub uplink_mode: String,
+    #[serde(default)]
+    pub uplink_sampling: u64,
+    #[serde(default)]
+    pub rate_limits: HashMap<String, RateLimit>,
+    #[serde(default)]
+    pub nmea_socket: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RateLimit {
+    pub rate: f64,
+    pub burst: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct RemoteConfig {
+    pub url: String,
+    pub public_key: String,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+}
+
+impl Default for RemoteConfig {
+    fn default() -> Self {
+        RemoteConfig {
+            url: "".to_string(),
+            public_key: "".to_string(),
+            interval: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct AuditLog {
+    pub path: String,
+    pub max_size: u64,
+    pub max_files: usize,
+}
+
+impl Default for AuditLog {
+    fn default() -> Self {
+        AuditLog {
+            path: "".to_string(),
+            max_size: 10 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Capture {
+    pub path: String,
+    pub max_size: u64,
+    pub max_files: usize,
+}
+
+impl Default for Capture {
+    fn default() -> Self {
+        Capture {
+            path: "".to_string(),
+            max_size: 10 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct StatsMetadata {
+    pub commands: HashMap<String, Vec<String>>,
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+}
+
+impl Default for StatsMetadata {
+    fn default() -> Self {
+        StatsMetadata {
+            commands: HashMap::new(),
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct LatencyProbe {
+    pub method: String,
+    pub target: String,
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+}
+
+impl Default for LatencyProbe {
+    fn default() -> Self {
+        LatencyProbe {
+            method: "TCP".to_string(),
+            target: "".to_string(),
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Metered {
+    pub file: String,
+    pub network_manager: bool,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+}
+
+impl Default for Metered {
+    fn default() -> Self {
+        Metered {
+            file: "".to_string(),
+            network_manager: false,
+            interval: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Health {
+    pub enabled: bool,
+    pub max_hal_errors: u32,
+    #[serde(with = "humantime_serde")]
+    pub rx_timeout: Duration,
+    #[serde(with = "humantime_serde")]
+    pub counter_stall_timeout: Duration,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Health {
+            enabled: false,
+            max_hal_errors: 10,
+            rx_timeout: Duration::ZERO,
+            counter_stall_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ClockDrift {
+    pub threshold_ppm: f64,
+    #[serde(with = "humantime_serde")]
+    pub baseline: Duration,
+}
+
+impl Default for ClockDrift {
+    fn default() -> Self {
+        ClockDrift {
+            threshold_ppm: 10.0,
+            baseline: Duration::from_secs(3600),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct TimeSync {
+    pub enabled: bool,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    #[serde(with = "humantime_serde")]
+    pub max_rtt: Duration,
+}
+
+impl Default for TimeSync {
+    fn default() -> Self {
+        TimeSync {
+            enabled: false,
+            interval: Duration::from_secs(600),
+            max_rtt: Duration::from_secs(2),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Shadow {
+    pub path: String,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+}
+
+impl Default for Shadow {
+    fn default() -> Self {
+        Shadow {
+            path: "".to_string(),
+            interval: Duration::from_secs(10),
+        }
+    }
 }
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct JoinFlood {
+    pub enabled: bool,
+    pub key: String,
+    pub prefix_bits: u32,
+    pub max_join_requests: u32,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    pub limit: bool,
+}
+
+impl Default for JoinFlood {
+    fn default() -> Self {
+        JoinFlood {
+            enabled: false,
+            key: "DEV_EUI".to_string(),
+            prefix_bits: 64,
+            max_join_requests: 10,
+            interval: Duration::from_secs(60),
+            limit: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Blacklist {
+    pub enabled: bool,
+    pub max_fcnt_gap: u32,
+    pub max_malformed: u32,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    #[serde(with = "humantime_serde")]
+    pub expiry: Duration,
+    pub limit: bool,
+    pub max_uplinks: u32,
+}
+
+impl Default for Blacklist {
+    fn default() -> Self {
+        Blacklist {
+            enabled: false,
+            max_fcnt_gap: 16384,
+            max_malformed: 10,
+            interval: Duration::from_secs(600),
+            expiry: Duration::from_secs(3600),
+            limit: false,
+            max_uplinks: 0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct OversizedUplinks {
+    pub action: String,
+}
+
+impl Default for OversizedUplinks {
+    fn default() -> Self {
+        OversizedUplinks {
+            action: "FORWARD".to_string(),
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct UplinkFilter {
+    pub net_id_allow: Vec<String>,
+    pub net_id_deny: Vec<String>,
+    pub join_eui_allow: Vec<String>,
+    pub join_eui_deny: Vec<String>,
+    pub thresholds: HashMap<String, UplinkThreshold>,
+    pub chain: Vec<UplinkFilterRule>,
+    pub debug: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UplinkThreshold {
+    pub min_snr: f32,
+    pub min_rssi: i32,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct UplinkFilterRule {
+    pub name: String,
+    pub filter: String,
+    pub action: String,
+    pub values: Vec<String>,
+    pub modulation: String,
+    pub min: f64,
+    pub max: f64,
+    #[serde(with = "humantime_serde")]
+    pub window: Duration,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Storage {
+    pub backend: String,
+    pub path: String,
+    pub fsync: String,
+    pub persist_jit_queue: bool,
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Storage {
+            backend: "".to_string(),
+            path: "".to_string(),
+            fsync: "NEVER".to_string(),
+            persist_jit_queue: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct DownlinkHistory {
+    pub size: usize,
+}
+
+impl Default for DownlinkHistory {
+    fn default() -> Self {
+        DownlinkHistory { size: 256 }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct DiagBundle {
+    pub path: String,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+pub struct Beacon {
+    pub compulsory_rfu_size: usize,
+    #[serde(default)]
+    pub optional_rfu_size: usize,
+    #[serde(default)]
+    pub frequencies: Vec<u32>,
+    #[serde(default)]
+    pub region: String,
+    pub spreading_factor: u32,
+    pub bandwidth: u32,
+    pub tx_power: u32,
+    #[serde(default)]
+    pub gw_specific: bool,
+    #[serde(default)]
+    pub info_desc: u8,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+pub struct LoRaWANChannel {
+    pub frequency: u32,
+    pub index: u32,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct StatusLed {
+    pub chip: String,
+    pub line: u32,
+    pub led: String,
+    pub active_low: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct FirstUplink {
+    pub chip: String,
+    pub line: u32,
+    pub active_low: bool,
+    #[serde(with = "humantime_serde")]
+    pub duration: Duration,
+    pub command: Vec<String>,
+}
+
+impl Default for FirstUplink {
+    fn default() -> Self {
+        FirstUplink {
+            chip: "".to_string(),
+            line: 0,
+            active_low: false,
+            duration: Duration::from_secs(1),
+            command: Vec::new(),
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
 pub struct Location {
     pub latitude: f64,
     pub longitude: f64,
     pub altitude: i16,
 }
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct DutyCycle {
+    pub enabled: bool,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Provisioning {
+    pub gateway_id: String,
+    pub mapping_file: String,
+    pub enforce: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SoakTest {
+    pub enabled: bool,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    pub payload_size: usize,
+    pub tx_power: i32,
+    pub frequencies: Vec<u32>,
+    pub bandwidth: u32,
+    pub spreading_factor: u32,
+}
+
+impl Default for SoakTest {
+    fn default() -> Self {
+        SoakTest {
+            enabled: false,
+            interval: Duration::from_secs(10),
+            payload_size: 51,
+            tx_power: 14,
+            frequencies: Vec::new(),
+            bandwidth: 125000,
+            spreading_factor: 7,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SpectralScan {
+    pub enabled: bool,
+    pub spi_path: String,
+    pub rssi_offset: i8,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    pub freq_start: u32,
+    pub freq_stop: u32,
+    pub freq_step: u32,
+    pub nb_scan: u32,
+}
+
+impl Default for SpectralScan {
+    fn default() -> Self {
+        SpectralScan {
+            enabled: false,
+            spi_path: "/dev/spidev0.1".to_string(),
+            rssi_offset: 0,
+            interval: Duration::from_secs(0),
+            freq_start: 0,
+            freq_stop: 0,
+            freq_step: 200000,
+            nb_scan: 2000,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct TxPower {
+    pub strict: bool,
+    pub tolerance: f32,
+    pub enforce_max_eirp: bool,
+    pub temperature_compensation: TemperatureCompensation,
+}
+
+impl Default for TxPower {
+    fn default() -> Self {
+        TxPower {
+            strict: false,
+            tolerance: 1.5,
+            enforce_max_eirp: false,
+            temperature_compensation: Default::default(),
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct TemperatureCompensation {
+    pub enabled: bool,
+    pub table: Vec<TemperatureCorrection>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TemperatureCorrection {
+    pub temperature: f32,
+    pub correction: f32,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Board {
+    pub com_type: String,
+    pub com_path: String,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Antenna {
+    pub radio: u32,
+    pub antenna: u32,
+    pub rssi_offset: f32,
+    pub antenna_gain: i8,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct DownlinkTiming {
+    #[serde(with = "humantime_serde")]
+    pub gps_max_offset: Duration,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct GpsUnlocked {
+    pub policy: String,
+    #[serde(with = "humantime_serde")]
+    pub max_error: Duration,
+}
+
+impl Default for GpsUnlocked {
+    fn default() -> Self {
+        GpsUnlocked {
+            policy: "REJECT".to_string(),
+            max_error: Duration::from_millis(1),
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct UplinkTime {
+    pub source: String,
+    pub gps_epoch: String,
+    pub debug: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Gnss {
+    pub baud_rate: u32,
+    pub auto_baud: bool,
+    pub usb_id: String,
+    pub dt_alias: String,
+    pub assist_url: String,
+    pub source: String,
+    pub gpsd_server: String,
+}
+
+impl Default for Gnss {
+    fn default() -> Self {
+        Gnss {
+            baud_rate: 0,
+            auto_baud: false,
+            usb_id: String::new(),
+            dt_alias: String::new(),
+            assist_url: String::new(),
+            source: String::new(),
+            gpsd_server: "localhost:2947".to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Gpio {
+    pub shared: bool,
+    #[serde(with = "humantime_serde")]
+    pub busy_timeout: Duration,
+}
+
+impl Default for Gpio {
+    fn default() -> Self {
+        Gpio {
+            shared: false,
+            busy_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
 pub struct Gateway {
     #[serde(default)]
     pub antenna_gain: i8,
     #[serde(default)]
+    pub cable_loss: i8,
+    #[serde(default)]
     pub lorawan_public: bool,
+    #[serde(default)]
+    pub uplink_diagnostics: bool,
 
     pub model: String,
     #[serde(default)]
+    pub region: String,
+    #[serde(default)]
     pub model_flags: Vec<String>,
     #[serde(default)]
     pub sx1302_reset_pin: u32,
@@ -47,21 +700,135 @@ pub struct Gateway {
     pub sx1302_power_en_pin: u32,
     #[serde(default)]
     pub sx1261_reset_pin: u32,
+    #[serde(default)]
+    pub sx1302_reset_line: String,
+    #[serde(default)]
+    pub sx1302_power_en_line: String,
+    #[serde(default)]
+    pub sx1261_reset_line: String,
+    #[serde(default)]
+    pub gpio_chip: String,
+    #[serde(default)]
+    pub shutdown_commands: Vec<Vec<String>>,
+    #[serde(default)]
+    pub reset_sequence: Vec<String>,
+    #[serde(default)]
+    pub gpio: Gpio,
     pub concentrator: Concentrator,
     #[serde(default)]
+    pub beacon: Beacon,
+    #[serde(default)]
     pub location: Location,
+    #[serde(default)]
+    pub status_led: StatusLed,
+    #[serde(default)]
+    pub first_uplink: FirstUplink,
+    #[serde(default)]
+    pub soak_test: SoakTest,
+    #[serde(default)]
+    pub spectral_scan: SpectralScan,
+    #[serde(default)]
+    pub tx_power: TxPower,
+    #[serde(default)]
+    pub downlink_timing: DownlinkTiming,
+    #[serde(default)]
+    pub duty_cycle: DutyCycle,
+    #[serde(default)]
+    pub provisioning: Provisioning,
+    #[serde(default)]
+    pub gps_unlocked: GpsUnlocked,
+    #[serde(default)]
+    pub uplink_time: UplinkTime,
+    #[serde(default)]
+    pub board: Board,
+    #[serde(default)]
+    pub gnss: Gnss,
+    #[serde(default)]
+    pub lorawan_channels: Vec<LoRaWANChannel>,
+    #[serde(default)]
+    pub antennas: Vec<Antenna>,
 
     #[serde(default)]
     pub fine_timestamp: FineTimestamp,
 
     #[serde(skip)]
     pub model_config: vendor::Configuration,
+    #[serde(skip)]
+    pub region_config: Option<region::Region>,
 
     #[serde(skip)]
     pub config_version: String,
 }
 
-#[derive(Default, Serialize, Deserialize, Debug, PartialEq)]
+impl Gateway {
+    /// Returns the LoRaWAN channel index by frequency.
+    pub fn get_lorawan_channels(&self) -> HashMap<u32, u32> {
+        self.lorawan_channels
+            .iter()
+            .map(|c| (c.frequency, c.index))
+            .collect()
+    }
+
+    /// Returns the antenna configuration of the given radio (RF chain).
+    pub fn get_antenna(&self, radio: u32) -> Option<&Antenna> {
+        self.antennas.iter().find(|a| a.radio == radio)
+    }
+
+    /// Returns the antenna index by radio (RF chain).
+    pub fn get_antennas(&self) -> HashMap<u32, u32> {
+        self.antennas.iter().map(|a| (a.radio, a.antenna)).collect()
+    }
+
+    /// Returns the antenna gain minus the cable loss (dB) of the given radio (RF chain). This
+    /// is used to convert between the EIRP of the downlink and the conducted power of the TX
+    /// gain table. The antenna gain of the radio overrides the antenna gain of the gateway.
+    pub fn get_effective_antenna_gain(&self, radio: u32) -> i8 {
+        let antenna_gain = match self.get_antenna(radio) {
+            Some(v) => v.antenna_gain,
+            None => self.antenna_gain,
+        };
+        antenna_gain - self.cable_loss
+    }
+
+    /// Returns the effective antenna gain (see get_effective_antenna_gain) per radio.
+    pub fn get_effective_antenna_gains(&self) -> Vec<i8> {
+        (0..self.model_config.radio_count)
+            .map(|radio| self.get_effective_antenna_gain(radio as u32))
+            .collect()
+    }
+
+    /// Returns the shutdown commands as (command, args).
+    pub fn get_shutdown_commands(&self) -> Option<Vec<(String, Vec<String>)>> {
+        if self.shutdown_commands.is_empty() {
+            return None;
+        }
+
+        Some(
+            self.shutdown_commands
+                .iter()
+                .filter(|v| !v.is_empty())
+                .map(|v| (v[0].clone(), v[1..].to_vec()))
+                .collect(),
+        )
+    }
+
+    /// Returns the reset sequence. The configured sequence overrides the sequence of the model,
+    /// when neither is set the default sequence is used (see reset::Configuration).
+    pub fn get_reset_sequence(&self) -> Option<Vec<reset::Step>> {
+        if self.reset_sequence.is_empty() {
+            return self.model_config.reset_sequence.clone();
+        }
+
+        Some(
+            self.reset_sequence
+                .iter()
+                .map(|v| reset::Step::from_str(v).expect("invalid reset_sequence step"))
+                .collect(),
+        )
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Concentrator {
     pub multi_sf_channels: [u32; 8],
     #[serde(default)]
@@ -70,7 +837,7 @@ pub struct Concentrator {
     pub fsk: FSKChannel,
 }
 
-#[derive(Default, Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Default, Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct LoRaStdChannel {
     pub frequency: u32,
     pub bandwidth: u32,
@@ -85,14 +852,15 @@ pub struct LoRaStdChannel {
     pub implicit_coderate: String,
 }
 
-#[derive(Default, Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Default, Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct FSKChannel {
     pub frequency: u32,
     pub bandwidth: u32,
     pub datarate: u32,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct FineTimestamp {
     pub enable: bool,
     pub mode: String, // HIGH_CAPACITY or ALL_SF
@@ -107,11 +875,91 @@ impl Default for FineTimestamp {
     }
 }
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Default, Serialize, Deserialize, Clone)]
 #[serde(default = "example_configuration")]
 pub struct Configuration {
     pub concentratord: Concentratord,
     pub gateway: Gateway,
+    #[serde(default)]
+    pub forwarder: Forwarder,
+    #[serde(default)]
+    pub advanced: Advanced,
+    #[serde(default)]
+    pub metrics: Metrics,
+    #[serde(default)]
+    pub logging: Logging,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Forwarder {
+    pub udp: ForwarderUdp,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ForwarderUdp {
+    pub server: String,
+    pub port_up: u16,
+    pub port_down: u16,
+    #[serde(with = "humantime_serde")]
+    pub keepalive_interval: Duration,
+}
+
+impl Default for ForwarderUdp {
+    fn default() -> Self {
+        ForwarderUdp {
+            server: "".to_string(),
+            port_up: 1700,
+            port_down: 1700,
+            keepalive_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Advanced {
+    pub rx_fetch_size: usize,
+    #[serde(with = "humantime_serde")]
+    pub rx_poll_interval: Duration,
+}
+
+impl Default for Advanced {
+    fn default() -> Self {
+        Advanced {
+            rx_fetch_size: 8,
+            rx_poll_interval: Duration::from_millis(10),
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Metrics {
+    pub bind: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Logging {
+    pub output: String,
+    pub format: String,
+    pub path: String,
+    pub max_size: u64,
+    pub max_files: usize,
+}
+
+impl Default for Logging {
+    fn default() -> Self {
+        Logging {
+            output: "".to_string(),
+            format: "TEXT".to_string(),
+            path: "".to_string(),
+            max_size: 10 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
 }
 
 fn example_configuration() -> Configuration {
@@ -122,6 +970,7 @@ fn example_configuration() -> Configuration {
             api: API {
                 event_bind: "ipc:///tmp/concentratord_event".to_string(),
                 command_bind: "ipc:///tmp/concentratord_command".to_string(),
+                ..Default::default()
             },
             ..Default::default()
         },
@@ -145,12 +994,24 @@ fn example_configuration() -> Configuration {
                     datarate: 50000,
                 },
             },
+            beacon: Beacon {
+                compulsory_rfu_size: 2,
+                frequencies: vec![869525000],
+                spreading_factor: 9,
+                bandwidth: 125000,
+                tx_power: 14,
+                ..Default::default()
+            },
             fine_timestamp: FineTimestamp {
                 enable: false,
                 mode: "ALL_SF".to_string(),
             },
             ..Default::default()
         },
+        forwarder: Default::default(),
+        advanced: Default::default(),
+        metrics: Default::default(),
+        logging: Default::default(),
     }
 }
 
@@ -162,6 +1023,7 @@ pub fn get(filenames: Vec<String>) -> Configuration {
     }
 
     let mut config: Configuration = toml::from_str(&content).expect("Error parsing config file");
+    config.concentratord.config_hash = shadow::config_hash(&content);
 
     // get model configuration
     config.gateway.model_config = match config.gateway.model.as_ref() {
@@ -196,7 +1058,179 @@ pub fn get(filenames: Vec<String>) -> Configuration {
         _ => panic!("unexpected gateway model: {}", config.gateway.model),
     };
 
+    // override the gpio chip of the model reset / power enable pins
+    if !config.gateway.gpio_chip.is_empty() {
+        let gpio_chip = config.gateway.gpio_chip.clone();
+        let set_chip = |pin: &mut Option<(String, u32)>| {
+            if let Some(pin) = pin {
+                pin.0 = gpio_chip.clone();
+            }
+        };
+
+        let model_config = &mut config.gateway.model_config;
+        set_chip(&mut model_config.sx1302_reset_pin);
+        set_chip(&mut model_config.sx1302_power_en_pin);
+        set_chip(&mut model_config.sx1261_reset_pin);
+        set_chip(&mut model_config.ad5338r_reset_pin);
+    }
+
+    // override the model reset / power enable pins by the gpio line names
+    for (line, pin) in [
+        (
+            &config.gateway.sx1302_reset_line,
+            &mut config.gateway.model_config.sx1302_reset_pin,
+        ),
+        (
+            &config.gateway.sx1302_power_en_line,
+            &mut config.gateway.model_config.sx1302_power_en_pin,
+        ),
+        (
+            &config.gateway.sx1261_reset_line,
+            &mut config.gateway.model_config.sx1261_reset_pin,
+        ),
+    ] {
+        if !line.is_empty() {
+            *pin = Some(reset::find_line(line).expect("find gpio line error"));
+        }
+    }
+
+    // override the com type of the model, e.g. for the USB variant of a concentrator module
+    match config.gateway.board.com_type.to_uppercase().as_ref() {
+        "" => {}
+        "SPI" => config.gateway.model_config.com_type = vendor::ComType::SPI,
+        "USB" => config.gateway.model_config.com_type = vendor::ComType::USB,
+        _ => panic!("invalid board com_type: {}", config.gateway.board.com_type),
+    }
+
+    // override the com path of the model, e.g. for the USB variant of a concentrator module
+    if !config.gateway.board.com_path.is_empty() {
+        config.gateway.model_config.com_path = config.gateway.board.com_path.clone();
+    }
+
+    // In case of USB, the SX1302 reset and power enable are driven by the MCU of the module
+    // when the com port is opened. The host gpio pins of the model are only used when these
+    // are configured explicitly.
+    if config.gateway.model_config.com_type == vendor::ComType::USB {
+        if config.gateway.sx1302_reset_pin == 0 && config.gateway.sx1302_reset_line.is_empty() {
+            config.gateway.model_config.sx1302_reset_pin = None;
+        }
+        if config.gateway.sx1302_power_en_pin == 0 && config.gateway.sx1302_power_en_line.is_empty()
+        {
+            config.gateway.model_config.sx1302_power_en_pin = None;
+        }
+    }
+
+    // override the model gnss tty by the usb id or device-tree alias
+    if !config.gateway.gnss.usb_id.is_empty() || !config.gateway.gnss.dt_alias.is_empty() {
+        let tty_path = gnss::find_tty(&config.gateway.gnss.usb_id, &config.gateway.gnss.dt_alias)
+            .expect("find gnss tty error");
+        config.gateway.model_config.gps = vendor::Gps::TtyPath(tty_path);
+    }
+
+    // override the model gnss source by gpsd, e.g. when the gnss module is shared with other
+    // services
+    match config.gateway.gnss.source.as_ref() {
+        "" | "TTY" => {}
+        "GPSD" => config.gateway.model_config.gps = vendor::Gps::Gpsd,
+        _ => panic!("invalid gnss source: {}", config.gateway.gnss.source),
+    }
+
+    // get the beacon frequencies from the beacon region
+    if config.gateway.beacon.frequencies.is_empty() && !config.gateway.beacon.region.is_empty() {
+        let region: region::Region = config
+            .gateway
+            .beacon
+            .region
+            .parse()
+            .expect("invalid beacon region");
+        config.gateway.beacon.frequencies = region.get_beacon_frequencies();
+    }
+
+    // validate the lorawan channel indices
+    if config.gateway.get_lorawan_channels().len() != config.gateway.lorawan_channels.len() {
+        panic!("lorawan_channels must not contain duplicate frequencies");
+    }
+
+    // validate the antennas
+    for antenna in &config.gateway.antennas {
+        if antenna.radio as usize >= config.gateway.model_config.radio_count {
+            panic!("antennas radio {} does not exist", antenna.radio);
+        }
+    }
+    if config.gateway.get_antennas().len() != config.gateway.antennas.len() {
+        panic!("antennas must not contain duplicate radios");
+    }
+
+    // validate the tx power temperature compensation
+    let temperature_compensation = &config.gateway.tx_power.temperature_compensation;
+    if temperature_compensation.enabled && temperature_compensation.table.is_empty() {
+        panic!("tx_power.temperature_compensation.table must not be empty");
+    }
+    if temperature_compensation
+        .table
+        .windows(2)
+        .any(|w| w[0].temperature >= w[1].temperature)
+    {
+        panic!("tx_power.temperature_compensation.table must be sorted by temperature");
+    }
+
+    // validate the advanced settings
+    // the HAL fetches up to 255 frames per receive call
+    if !(1..=255).contains(&config.advanced.rx_fetch_size) {
+        panic!("advanced.rx_fetch_size must be between 1 and 255");
+    }
+
+    // get region, fallback to the region of the model name
+    config.gateway.region_config = match config.gateway.region.as_ref() {
+        "" => region::Region::from_model(&config.gateway.model),
+        _ => Some(config.gateway.region.parse().expect("invalid region")),
+    };
+
     debug!("Antenna gain {} dB", config.gateway.antenna_gain);
+    debug!("Cable loss {} dB", config.gateway.cable_loss);
 
     return config;
 }
+
+/// Returns true when the concentrator must be re-initialized to apply the new configuration,
+/// e.g. on a configuration reload. The gateway settings which are applied by the handlers
+/// (e.g. the location and TX power) do not require a restart of the concentrator.
+pub fn requires_restart(current: &Configuration, new: &Configuration) -> bool {
+    match (radio_config(&current.gateway), radio_config(&new.gateway)) {
+        (Some(current), Some(new)) => current != new,
+        _ => true,
+    }
+}
+
+// Returns the gateway configuration, without the settings that are applied by the handlers.
+fn radio_config(gateway: &Gateway) -> Option<toml::Value> {
+    let mut gateway = gateway.clone();
+    gateway.antenna_gain = 0;
+    gateway.cable_loss = 0;
+    gateway.uplink_diagnostics = false;
+    gateway.shutdown_commands = Vec::new();
+    gateway.reset_sequence = Vec::new();
+    gateway.beacon = Default::default();
+    gateway.location = Default::default();
+    gateway.status_led = Default::default();
+    gateway.first_uplink = Default::default();
+    gateway.soak_test = Default::default();
+    // The SX1261 is configured on concentrator start, the periodic scan is applied by the
+    // handler.
+    gateway.spectral_scan = SpectralScan {
+        enabled: gateway.spectral_scan.enabled,
+        spi_path: gateway.spectral_scan.spi_path.clone(),
+        rssi_offset: gateway.spectral_scan.rssi_offset,
+        ..Default::default()
+    };
+    gateway.tx_power = Default::default();
+    gateway.downlink_timing = Default::default();
+    gateway.duty_cycle = Default::default();
+    gateway.provisioning = Default::default();
+    gateway.gps_unlocked = Default::default();
+    gateway.uplink_time = Default::default();
+    gateway.gnss = Default::default();
+    gateway.lorawan_channels = Vec::new();
+
+    toml::Value::try_from(gateway).ok()
+}
@@ -4,75 +4,308 @@ use std::time::Duration;
 
 use anyhow::Result;
 use libconcentratord::signals::Signal;
-use libconcentratord::{commands, jitqueue, stats};
+use libconcentratord::{
+    api, audit, blacklist, commands, diag, dutycycle, events, history, jitqueue, shadow, stats,
+    timesync, txpower, txtest,
+};
 use libloragw_sx1302::hal;
 use prost::Message;
 
-use super::super::config::vendor;
+use super::super::config;
 use super::super::wrapper;
+use super::{beacon, gps, spectralscan};
+
+// Resolution of the concentrator counter, which is the best achievable accuracy of a
+// synchronized transmission.
+const TX_TIMING_RESOLUTION: Duration = Duration::from_micros(1);
+
+// Options of a synchronized transmission.
+struct SyncTx {
+    max_error: Duration,
+    arm_only: bool,
+}
 
 pub fn handle_loop(
-    vendor_config: &vendor::Configuration,
+    config: &config::Configuration,
     gateway_id: &[u8],
     queue: Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
     rep_sock: zmq::Socket,
     stop_receive: Receiver<Signal>,
     stop_send: Sender<Signal>,
+    txtest_send: Sender<api::TxTestRequest>,
 ) {
     debug!("Starting command handler loop");
 
+    let has_gps = config.gateway.model_config.gps != config::vendor::Gps::None;
+
+    let rate_limits = config
+        .concentratord
+        .api
+        .rate_limits
+        .iter()
+        .map(|(k, v)| {
+            (
+                k.clone(),
+                commands::RateLimit {
+                    rate: v.rate,
+                    burst: v.burst,
+                },
+            )
+        })
+        .collect();
+
     // A timeout is used so that we can consume from the stop signal.
-    let reader = commands::Reader::new(&rep_sock, Duration::from_millis(100));
+    let mut reader = commands::Reader::new(&rep_sock, Duration::from_millis(100))
+        .with_filter(commands::Filter {
+            allowed_commands: config.concentratord.api.allowed_commands.clone(),
+            read_only: config.concentratord.api.read_only,
+        })
+        .with_rate_limits(rate_limits);
 
-    for cmd in reader {
+    while let Some(cmd) = reader.next() {
         match stop_receive.recv_timeout(Duration::from_millis(0)) {
             Ok(v) => {
                 debug!("Received stop signal, signal: {}", v);
+
+                // The command socket is re-used after a configuration reload, a received
+                // command must be replied to, to keep the socket usable.
+                if !matches!(cmd, commands::Command::Timeout) {
+                    let _ = rep_sock.send(Vec::new(), 0);
+                }
                 break;
             }
             _ => {}
         };
 
-        let resp = match cmd {
+        let cmd_name = cmd.to_string();
+        let (resp, outcome) = match cmd {
             commands::Command::Timeout => {
                 continue;
             }
             commands::Command::Downlink(pl) => {
-                match handle_downlink(vendor_config, gateway_id, &queue, &pl) {
-                    Ok(v) => v,
-                    Err(_) => Vec::new(),
+                match handle_downlink(config, gateway_id, &queue, &pl) {
+                    Ok(v) => (v, "OK"),
+                    Err(_) => (Vec::new(), "ERROR"),
                 }
             }
-            commands::Command::GatewayID => gateway_id.to_vec(),
+            commands::Command::GatewayID => (gateway_id.to_vec(), "OK"),
+            commands::Command::GetConfiguration => (
+                super::config::get_configuration(config, gateway_id).encode_to_vec(),
+                "OK",
+            ),
+            commands::Command::Capabilities => (events::get_capabilities().encode_to_vec(), "OK"),
+            commands::Command::Status => (
+                shadow::snapshot(&get_status(has_gps, &queue)).encode_to_vec(),
+                "OK",
+            ),
+            commands::Command::Beacon(pl) => match beacon::set_payload_override(pl) {
+                Ok(_) => (Vec::new(), "OK"),
+                Err(err) => {
+                    error!("Set beacon payload override error, error: {}", err);
+                    (Vec::new(), "ERROR")
+                }
+            },
+            commands::Command::TxTest(pl) => match handle_tx_test(&txtest_send, pl) {
+                Ok(_) => (Vec::new(), "OK"),
+                Err(err) => {
+                    error!("TX test request error, error: {}", err);
+                    (Vec::new(), "ERROR")
+                }
+            },
             commands::Command::Configuration(pl) => {
-                match handle_configuration(stop_send.clone(), pl) {
-                    Ok(v) => v,
-                    Err(_) => Vec::new(),
+                match handle_configuration(config, stop_send.clone(), pl) {
+                    Ok(v) => (v, "OK"),
+                    Err(_) => (Vec::new(), "ERROR"),
+                }
+            }
+            commands::Command::Reload => {
+                stop_send.send(Signal::Reload).unwrap();
+                (Vec::new(), "OK")
+            }
+            commands::Command::TimeSync(pl) => match timesync::handle_answer(&pl) {
+                Ok(_) => (Vec::new(), "OK"),
+                Err(err) => {
+                    error!("Time-sync answer error, error: {}", err);
+                    (Vec::new(), "ERROR")
+                }
+            },
+            commands::Command::SyncTx(pl) => match handle_sync_tx(config, gateway_id, &queue, pl) {
+                Ok(v) => (v, "OK"),
+                Err(err) => {
+                    error!("Synchronized TX request error, error: {}", err);
+                    (Vec::new(), "ERROR")
+                }
+            },
+            commands::Command::SpectralScan(pl) => {
+                match handle_spectral_scan(config, gateway_id, pl) {
+                    Ok(v) => (v, "OK"),
+                    Err(err) => {
+                        error!("Spectral scan request error, error: {}", err);
+                        (Vec::new(), "ERROR")
+                    }
+                }
+            }
+            commands::Command::DownlinkHistory(pl) => {
+                (history::get(gateway_id, &pl).encode_to_vec(), "OK")
+            }
+            commands::Command::Blacklist => (blacklist::get(gateway_id).encode_to_vec(), "OK"),
+            commands::Command::DiagBundle(pl) => {
+                match handle_diag_bundle(config, gateway_id, has_gps, &queue, pl) {
+                    Ok(v) => (v, "OK"),
+                    Err(err) => {
+                        error!("Diagnostic bundle request error, error: {}", err);
+                        (Vec::new(), "ERROR")
+                    }
                 }
             }
+            commands::Command::Tap(pl) => match events::start_tap(&pl) {
+                Ok(_) => (Vec::new(), "OK"),
+                Err(err) => {
+                    error!("Tap request error, error: {}", err);
+                    (Vec::new(), "ERROR")
+                }
+            },
             commands::Command::Error(err) => {
                 error!("Read command error, error: {}", err);
-                Vec::new()
+                (Vec::new(), "ERROR")
+            }
+            commands::Command::Rejected(command) => {
+                warn!("Command rejected, command: {}", command);
+                (Vec::new(), "REJECTED")
+            }
+            commands::Command::Throttled(command) => {
+                // This is not logged as warning, as that would amplify a flood of commands
+                // into a flood of log events.
+                debug!("Command throttled, command: {}", command);
+                (Vec::new(), "THROTTLED")
             }
             commands::Command::Unknown(command, _) => {
                 warn!("Unknown command received, command: {}", command);
-                Vec::new()
+                (Vec::new(), "UNKNOWN")
             }
         };
 
+        audit::log(&cmd_name, reader.user_id(), outcome);
         rep_sock.send(resp, 0).unwrap();
     }
 
+    commands::release_socket(&config.concentratord.api.command_bind, rep_sock);
+
     debug!("Command loop ended");
 }
 
+/// Returns the daemon specific status, as included in the shadow state and the status command.
+pub fn get_status(
+    has_gps: bool,
+    queue: &Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
+) -> shadow::Status {
+    let gps = if has_gps {
+        Some(shadow::Gps {
+            time_valid: gps::is_time_ref_valid(),
+            location: gps::get_coords().map(|v| (v.latitude, v.longitude, v.altitude as f64)),
+        })
+    } else {
+        None
+    };
+    let queue = queue.lock().unwrap();
+    shadow::Status {
+        gps,
+        queue_depth: queue.count(),
+        queue_capacity: queue.size(),
+    }
+}
+
 fn handle_downlink(
-    vendor_config: &vendor::Configuration,
+    config: &config::Configuration,
     gateway_id: &[u8],
     queue: &Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
     pl: &chirpstack_api::gw::DownlinkFrame,
 ) -> Result<Vec<u8>> {
-    stats::inc_tx_packets_received();
+    let (tx_ack, details, _) = schedule_downlink(config, gateway_id, queue, pl, None)?;
+
+    // The rejection details are appended to the TX acknowledgement, see
+    // api::DownlinkTxAckDetails.
+    let mut b = tx_ack.encode_to_vec();
+    details.encode(&mut b)?;
+    Ok(b)
+}
+
+/// Enqueues the downlink and returns the TX acknowledgement. This is also used for the
+/// downlinks received by the UDP packet-forwarder.
+pub fn enqueue_downlink(
+    config: &config::Configuration,
+    gateway_id: &[u8],
+    queue: &Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
+    pl: &chirpstack_api::gw::DownlinkFrame,
+) -> Result<chirpstack_api::gw::DownlinkTxAck> {
+    let (tx_ack, _, _) = schedule_downlink(config, gateway_id, queue, pl, None)?;
+    Ok(tx_ack)
+}
+
+fn handle_sync_tx(
+    config: &config::Configuration,
+    gateway_id: &[u8],
+    queue: &Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
+    pl: api::SyncTxRequest,
+) -> Result<Vec<u8>> {
+    let downlink = pl.downlink.ok_or_else(|| anyhow!("downlink is missing"))?;
+    if downlink
+        .items
+        .iter()
+        .any(|v| wrapper::gps_epoch_from_proto(v).is_none())
+    {
+        return Err(anyhow!("synchronized tx requires gps epoch timing"));
+    }
+    if pl.max_error_us == 0 {
+        return Err(anyhow!("max_error_us must be set"));
+    }
+
+    let sync = SyncTx {
+        max_error: Duration::from_micros(pl.max_error_us.into()),
+        arm_only: pl.arm_only,
+    };
+    let (tx_ack, details, timing_error) =
+        schedule_downlink(config, gateway_id, queue, &downlink, Some(&sync))?;
+    let accepted = tx_ack
+        .items
+        .iter()
+        .any(|v| v.status() == chirpstack_api::gw::TxAckStatus::Ok);
+
+    Ok(api::SyncTxResponse {
+        tx_ack: Some(tx_ack),
+        accuracy_us: if accepted {
+            (timing_error + TX_TIMING_RESOLUTION).as_micros() as u32
+        } else {
+            0
+        },
+        details: Some(details),
+    }
+    .encode_to_vec())
+}
+
+// Validates and enqueues the first possible downlink item. For a synchronized transmission,
+// the items that can not meet the max. error are rejected and on arm_only, the item is only
+// validated against the JIT queue. Returns the TX acknowledgement, the rejection details of the
+// items rejected by the duty-cycle check or the JIT queue and the estimated timing error of
+// the accepted item.
+fn schedule_downlink(
+    config: &config::Configuration,
+    gateway_id: &[u8],
+    queue: &Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
+    pl: &chirpstack_api::gw::DownlinkFrame,
+    sync: Option<&SyncTx>,
+) -> Result<(
+    chirpstack_api::gw::DownlinkTxAck,
+    api::DownlinkTxAckDetails,
+    Duration,
+)> {
+    let arm_only = sync.map(|v| v.arm_only).unwrap_or(false);
+    if !arm_only {
+        stats::inc_tx_packets_received();
+    }
+
+    let vendor_config = &config.gateway.model_config;
+    let tx_power_config = &config.gateway.tx_power;
 
     let mut tx_ack = chirpstack_api::gw::DownlinkTxAck {
         gateway_id: hex::encode(gateway_id),
@@ -80,11 +313,84 @@ fn handle_downlink(
         items: vec![Default::default(); pl.items.len()],
         ..Default::default()
     };
+    let mut details: api::DownlinkTxAckDetails = Default::default();
     let mut stats_tx_status = chirpstack_api::gw::TxAckStatus::Ignored;
+    let mut accepted_timing_error = Duration::from_secs(0);
 
     for (i, item) in pl.items.iter().enumerate() {
+        // the downlinks are suspended during a certification TX test
+        if txtest::certification_active() {
+            warn!(
+                "Rejecting downlink, certification TX test is running, downlink_id: {}",
+                pl.downlink_id
+            );
+            tx_ack.items[i].set_status(chirpstack_api::gw::TxAckStatus::CollisionPacket);
+
+            // try next
+            continue;
+        }
+
+        // validate payload size
+        if let Some(region) = config.gateway.region_config {
+            if let Err(err) = region.validate_phy_payload_size(item) {
+                error!(
+                    "Validate downlink payload size error, downlink_id: {}, error: {}",
+                    pl.downlink_id, err
+                );
+                // There is no payload size status, the PAYLOAD_SIZE rejection details contain the
+                // max. PHYPayload size of the data-rate.
+                if let Some(rejection) = err.to_proto(i) {
+                    details.rejections.push(rejection);
+                }
+                tx_ack.items[i].set_status(chirpstack_api::gw::TxAckStatus::InternalError);
+
+                // try next
+                continue;
+            }
+        }
+
+        // GPS epoch timed downlinks require a valid GPS time reference, or the last known GPS
+        // time reference within the max. error of the gps_unlocked policy
+        let gps_timing_error = match wrapper::gps_epoch_from_proto(item) {
+            Some(gps_epoch) => match gps::epoch2cnt_downlink(&gps_epoch) {
+                Ok((_, error)) => error,
+                Err(err) => {
+                    error!(
+                        "GPS epoch timed downlink error, downlink_id: {}, error: {}",
+                        pl.downlink_id, err
+                    );
+                    tx_ack.items[i].set_status(chirpstack_api::gw::TxAckStatus::GpsUnlocked);
+
+                    // try next
+                    continue;
+                }
+            },
+            None => Duration::from_secs(0),
+        };
+        if !gps_timing_error.is_zero() {
+            warn!(
+                "Scheduling GPS epoch timed downlink using last known GPS time reference, downlink_id: {}, estimated_error: {:?}",
+                pl.downlink_id, gps_timing_error
+            );
+        }
+
+        // a synchronized transmission must be transmitted within the max. error
+        if let Some(sync) = sync {
+            let accuracy = gps_timing_error + TX_TIMING_RESOLUTION;
+            if accuracy > sync.max_error {
+                error!(
+                    "Synchronized TX accuracy exceeds max. error, downlink_id: {}, accuracy: {:?}, max_error: {:?}",
+                    pl.downlink_id, accuracy, sync.max_error
+                );
+                tx_ack.items[i].set_status(chirpstack_api::gw::TxAckStatus::GpsUnlocked);
+
+                // try next
+                continue;
+            }
+        }
+
         // convert protobuf to hal struct
-        let tx_packet = match wrapper::downlink_from_proto(item) {
+        let mut tx_packet = match wrapper::downlink_from_proto(item) {
             Ok(v) => v,
             Err(err) => {
                 error!(
@@ -95,6 +401,48 @@ fn handle_downlink(
             }
         };
 
+        // select the radio of the requested antenna
+        if !config.gateway.antennas.is_empty() {
+            let antenna = item.tx_info.as_ref().map(|v| v.antenna).unwrap_or(0);
+            match get_tx_radio(config, antenna) {
+                Some(v) => tx_packet.rf_chain = v as u8,
+                None => {
+                    error!(
+                        "No TX enabled radio for antenna, downlink_id: {}, antenna: {}",
+                        pl.downlink_id, antenna
+                    );
+                    tx_ack.items[i].set_status(chirpstack_api::gw::TxAckStatus::InternalError);
+
+                    // try next
+                    continue;
+                }
+            }
+        }
+        let antenna_gain = config
+            .gateway
+            .get_effective_antenna_gain(tx_packet.rf_chain as u32);
+
+        // cross-check the GPS epoch timing against the counter of the uplink context
+        let gps_max_offset = config.gateway.downlink_timing.gps_max_offset;
+        if !gps_max_offset.is_zero() {
+            if let Some(context_count_us) = wrapper::gps_timing_context_from_proto(item) {
+                if let Err(status) = jitqueue::validate_gps_timing(
+                    context_count_us,
+                    tx_packet.count_us,
+                    gps_max_offset,
+                ) {
+                    error!(
+                        "GPS epoch timing disagrees with uplink context, downlink_id: {}, context_count_us: {}, count_us: {}, max_offset: {:?}",
+                        pl.downlink_id, context_count_us, tx_packet.count_us, gps_max_offset
+                    );
+                    tx_ack.items[i].set_status(status);
+
+                    // try next
+                    continue;
+                }
+            }
+        }
+
         // validate frequency range
         match vendor_config.radio_config.get(tx_packet.rf_chain as usize) {
             Some(v) => {
@@ -105,6 +453,43 @@ fn handle_downlink(
                     // try next
                     continue;
                 }
+
+                // Set the TX power to the power that will be used by the HAL after
+                // selecting the TX gain table entry. Note that the gain table contains
+                // the conducted power, the downlink contains the EIRP.
+                let gain_table: Vec<i8> = v.tx_gain_table.iter().map(|g| g.rf_power).collect();
+                if let Some(applied_power) =
+                    txpower::get_applied_power(&gain_table, tx_packet.rf_power - antenna_gain)
+                {
+                    let applied_power = applied_power + antenna_gain;
+                    if txpower::exceeds_tolerance(
+                        tx_packet.rf_power,
+                        applied_power,
+                        tx_power_config.tolerance,
+                    ) {
+                        if tx_power_config.strict {
+                            error!(
+                                "TX power exceeds tolerance, downlink_id: {}, requested_power: {}, applied_power: {}, tolerance: {}",
+                                pl.downlink_id, tx_packet.rf_power, applied_power, tx_power_config.tolerance
+                            );
+                            tx_ack.items[i].set_status(chirpstack_api::gw::TxAckStatus::TxPower);
+
+                            // try next
+                            continue;
+                        }
+
+                        warn!(
+                            "TX power adjusted to TX gain table exceeds tolerance, downlink_id: {}, requested_power: {}, applied_power: {}, tolerance: {}",
+                            pl.downlink_id, tx_packet.rf_power, applied_power, tx_power_config.tolerance
+                        );
+                    } else if applied_power != tx_packet.rf_power {
+                        info!(
+                            "TX power adjusted to TX gain table, downlink_id: {}, requested_power: {}, applied_power: {}",
+                            pl.downlink_id, tx_packet.rf_power, applied_power
+                        );
+                    }
+                    tx_packet.rf_power = applied_power;
+                }
             }
             None => {
                 tx_ack.items[i].set_status(chirpstack_api::gw::TxAckStatus::TxFreq);
@@ -114,14 +499,81 @@ fn handle_downlink(
             }
         };
 
-        // try enqueue
-        match queue.lock().unwrap().enqueue(
-            hal::get_instcnt().expect("get concentrator count error"),
-            wrapper::TxPacket::new(pl.downlink_id, tx_packet),
-        ) {
+        // validate the regional max. EIRP
+        if tx_power_config.enforce_max_eirp {
+            if let Some(region) = config.gateway.region_config {
+                let max_eirp = region.get_max_eirp(tx_packet.freq_hz);
+                if tx_packet.rf_power > max_eirp {
+                    error!(
+                        "TX power exceeds regional max. EIRP, downlink_id: {}, power: {}, max_eirp: {}",
+                        pl.downlink_id, tx_packet.rf_power, max_eirp
+                    );
+                    tx_ack.items[i].set_status(chirpstack_api::gw::TxAckStatus::TxPower);
+
+                    // try next
+                    continue;
+                }
+            }
+        }
+
+        // validate the duty-cycle budget of the band
+        let time_on_air = match hal::time_on_air(&tx_packet) {
+            Ok(v) => v,
+            Err(err) => {
+                error!(
+                    "Get time on air for tx packet error, downlink_id: {}, error: {}",
+                    pl.downlink_id, err
+                );
+                tx_ack.items[i].set_status(chirpstack_api::gw::TxAckStatus::InternalError);
+
+                // try next
+                continue;
+            }
+        };
+        let frequency = tx_packet.freq_hz;
+
+        // try enqueue, or only validate on arm_only
+        let concentrator_count = hal::get_instcnt().expect("get concentrator count error");
+        let tx_packet = wrapper::TxPacket::new(pl.downlink_id, tx_packet)
+            .with_gps_epoch(wrapper::gps_epoch_from_proto(item))
+            .with_gps_timing_error(gps_timing_error);
+
+        // The queue lock is held during the duty-cycle check, such that concurrent downlinks
+        // can not exceed the budget. When the scheduler plans the duty-cycle, the immediate
+        // downlinks are delayed until the budget allows these. The airtime is recorded when
+        // transmitted, the airtime of the queued downlinks is taken into account.
+        let mut queue = queue.lock().unwrap();
+        let result = match dutycycle::check(frequency, time_on_air, &queue.queued_airtime()) {
+            Err(err) if !queue.plans_duty_cycle(&tx_packet) => {
+                error!(
+                    "Duty-cycle error, downlink_id: {}, error: {}",
+                    pl.downlink_id, err
+                );
+                details.rejections.push(err.to_proto(i));
+                Err(chirpstack_api::gw::TxAckStatus::TooEarly)
+            }
+            _ => {
+                let result = if arm_only {
+                    queue.validate(concentrator_count, tx_packet)
+                } else {
+                    queue.enqueue(concentrator_count, tx_packet).map(|_| ())
+                };
+
+                result.map_err(|rejection| {
+                    warn!(
+                        "Downlink rejected by JIT queue, downlink_id: {}, reason: {}",
+                        pl.downlink_id, rejection
+                    );
+                    details.rejections.push(rejection.to_proto(i));
+                    rejection.status()
+                })
+            }
+        };
+        match result {
             Ok(_) => {
                 tx_ack.items[i].set_status(chirpstack_api::gw::TxAckStatus::Ok);
                 stats_tx_status = chirpstack_api::gw::TxAckStatus::Ok;
+                accepted_timing_error = gps_timing_error;
 
                 // break out of loop
                 break;
@@ -133,15 +585,93 @@ fn handle_downlink(
         };
     }
 
-    stats::inc_tx_status_count(stats_tx_status);
+    if !arm_only {
+        stats::inc_tx_status_count(stats_tx_status);
+        stats::inc_tx_rejection_counts(&tx_ack);
+        history::record_tx_ack(pl, &tx_ack);
+    }
+
+    Ok((tx_ack, details, accepted_timing_error))
+}
 
-    Ok(tx_ack.encode_to_vec())
+// Returns the TX enabled radio (RF chain) of the given antenna.
+fn get_tx_radio(config: &config::Configuration, antenna: u32) -> Option<u32> {
+    config
+        .gateway
+        .antennas
+        .iter()
+        .filter(|a| a.antenna == antenna)
+        .map(|a| a.radio)
+        .find(|radio| {
+            config
+                .gateway
+                .model_config
+                .radio_config
+                .get(*radio as usize)
+                .map(|v| v.tx_enable)
+                .unwrap_or(false)
+        })
+}
+
+fn handle_diag_bundle(
+    config: &config::Configuration,
+    gateway_id: &[u8],
+    has_gps: bool,
+    queue: &Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
+    pl: api::DiagBundleRequest,
+) -> Result<Vec<u8>> {
+    // The secret keys are not included in the bundle.
+    let mut config = config.clone();
+    config.concentratord.api.event_encryption_key = "".to_string();
+    config.concentratord.api.curve_secret_key = "".to_string();
+
+    let status = format!("{:#?}", shadow::snapshot(&get_status(has_gps, queue)));
+    let queue = diag::format_queue(&queue.lock().unwrap().packets());
+    let history = format!("{:#?}", history::get(gateway_id, &Default::default()));
+
+    let files = vec![
+        (
+            "config.toml".to_string(),
+            toml::Value::try_from(&config)?.to_string().into_bytes(),
+        ),
+        ("status.txt".to_string(), status.into_bytes()),
+        ("queue.txt".to_string(), queue.into_bytes()),
+        ("downlink_history.txt".to_string(), history.into_bytes()),
+    ];
+
+    Ok(diag::start(gateway_id, &pl, files)?.encode_to_vec())
 }
 
 fn handle_configuration(
+    config: &config::Configuration,
     stop_send: Sender<Signal>,
     pl: chirpstack_api::gw::GatewayConfiguration,
 ) -> Result<Vec<u8>> {
+    // Validate the new configuration, so that an invalid channel-plan is rejected instead of
+    // failing the concentrator restart.
+    if let Err(err) = super::config::update_configuration(&mut config.clone(), &pl) {
+        error!("Invalid gateway configuration, error: {}", err);
+        return Err(err);
+    }
+
     stop_send.send(Signal::Configuration(pl)).unwrap();
     Ok(Vec::new())
 }
+
+fn handle_tx_test(txtest_send: &Sender<api::TxTestRequest>, pl: api::TxTestRequest) -> Result<()> {
+    txtest::validate(&pl)?;
+    txtest_send.send(pl)?;
+    Ok(())
+}
+
+fn handle_spectral_scan(
+    config: &config::Configuration,
+    gateway_id: &[u8],
+    pl: api::SpectralScanRequest,
+) -> Result<Vec<u8>> {
+    if !config.gateway.spectral_scan.enabled {
+        return Err(anyhow!("spectral scan is not enabled"));
+    }
+
+    Ok(spectralscan::scan(gateway_id, &pl)?.encode_to_vec())
+}
@@ -50,10 +50,78 @@ pub fn update_configuration(
     Ok(())
 }
 
+/// Returns the active channel configuration, in the format accepted by update_configuration.
+pub fn get_configuration(
+    config: &Configuration,
+    gateway_id: &[u8],
+) -> chirpstack_api::gw::GatewayConfiguration {
+    let concentrator = &config.gateway.concentrator;
+    let mut channels = Vec::new();
+
+    for frequency in concentrator.multi_sf_channels.iter() {
+        if *frequency == 0 {
+            continue;
+        }
+
+        channels.push(chirpstack_api::gw::ChannelConfiguration {
+            frequency: *frequency,
+            modulation_config: Some(
+                chirpstack_api::gw::channel_configuration::ModulationConfig::LoraModulationConfig(
+                    chirpstack_api::gw::LoraModulationConfig {
+                        bandwidth: 125000,
+                        spreading_factors: vec![7, 8, 9, 10, 11, 12],
+                        ..Default::default()
+                    },
+                ),
+            ),
+            ..Default::default()
+        });
+    }
+
+    if concentrator.lora_std.frequency != 0 {
+        channels.push(chirpstack_api::gw::ChannelConfiguration {
+            frequency: concentrator.lora_std.frequency,
+            modulation_config: Some(
+                chirpstack_api::gw::channel_configuration::ModulationConfig::LoraModulationConfig(
+                    chirpstack_api::gw::LoraModulationConfig {
+                        bandwidth: concentrator.lora_std.bandwidth,
+                        spreading_factors: vec![concentrator.lora_std.spreading_factor as u32],
+                        ..Default::default()
+                    },
+                ),
+            ),
+            ..Default::default()
+        });
+    }
+
+    if concentrator.fsk.frequency != 0 {
+        channels.push(chirpstack_api::gw::ChannelConfiguration {
+            frequency: concentrator.fsk.frequency,
+            modulation_config: Some(
+                chirpstack_api::gw::channel_configuration::ModulationConfig::FskModulationConfig(
+                    chirpstack_api::gw::FskModulationConfig {
+                        bandwidth: concentrator.fsk.bandwidth,
+                        bitrate: concentrator.fsk.datarate,
+                        ..Default::default()
+                    },
+                ),
+            ),
+            ..Default::default()
+        });
+    }
+
+    chirpstack_api::gw::GatewayConfiguration {
+        gateway_id: hex::encode(gateway_id),
+        version: config.gateway.config_version.clone(),
+        channels,
+        ..Default::default()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::super::config::{FSKChannel, LoRaStdChannel};
-    use super::{update_configuration, Concentrator, Configuration};
+    use super::{get_configuration, update_configuration, Concentrator, Configuration};
 
     #[test]
     fn test_update_configuration() {
@@ -215,6 +283,11 @@ mod tests {
             let mut config = Configuration::default();
             update_configuration(&mut config, &gw_config).unwrap();
             assert_eq!(config.gateway.concentrator, expected_config);
+
+            // the returned configuration must result in the same concentrator configuration
+            let mut round_trip = Configuration::default();
+            update_configuration(&mut round_trip, &get_configuration(&config, &[0; 8])).unwrap();
+            assert_eq!(round_trip.gateway.concentrator, expected_config);
         }
     }
 }
@@ -1,21 +1,159 @@
-use std::sync::mpsc::Receiver;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
 use std::sync::{Arc, Mutex};
+use std::thread;
 use std::time::Duration;
 
+use anyhow::Result;
+use chirpstack_api::gw;
 use libconcentratord::jitqueue::TxPacket;
 use libconcentratord::signals::Signal;
-use libconcentratord::{jitqueue, stats};
+use libconcentratord::{
+    api, dutycycle, events, history, jitqueue, stats, storage, timesource, txtest,
+};
 use libloragw_sx1302::hal;
+use prost::Message;
 
 use super::super::wrapper;
+use super::{gps, temperature};
+
+// Max. time to wait for a valid GPS time reference, when re-scheduling the pending downlinks
+// after the concentrator has been re-initialized.
+const RESTORE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Storage key of the persisted pending downlinks.
+const PENDING_STORAGE_KEY: &str = "jit_queue";
+
+lazy_static! {
+    static ref PENDING: Mutex<Vec<(wrapper::TxPacket, PendingTime)>> = Mutex::new(Vec::new());
+}
+
+// Scheduled time of a pending downlink, see save_pending.
+#[derive(Clone, Copy)]
+enum PendingTime {
+    GpsEpoch(Duration),
+    Offset(jitqueue::PendingOffset),
+}
+
+/// Saves the pending downlinks, so that these are re-scheduled by the JIT loop once the
+/// concentrator has been re-initialized. As the concentrator counter is reset on
+/// re-initialization, the downlinks are saved with their GPS epoch time. Without valid GPS time
+/// reference, these are saved with their offset relative to the host monotonic time. Downlinks
+/// of which the TX time has passed are dropped.
+pub fn save_pending(queue: &Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>) {
+    let mut pending = PENDING.lock().unwrap();
+    let concentrator_count = hal::get_instcnt().ok();
+    let now = timesource::monotonic();
+
+    for tx_packet in queue.lock().unwrap().drain() {
+        let gps_epoch = match tx_packet.gps_epoch() {
+            Some(v) => Ok(v),
+            None => gps::cnt2epoch(tx_packet.get_count_us()),
+        };
+        let pending_time = match gps_epoch {
+            Ok(v) => Some(PendingTime::GpsEpoch(v)),
+            Err(_) => concentrator_count
+                .and_then(|v| jitqueue::PendingOffset::new(tx_packet.get_count_us(), v, now))
+                .map(PendingTime::Offset),
+        };
+
+        match pending_time {
+            Some(v) => pending.push((tx_packet, v)),
+            None => drop_pending(&tx_packet, "TX time has passed or is unknown"),
+        }
+    }
+
+    if !pending.is_empty() {
+        info!(
+            "Saved pending downlinks for re-scheduling, count: {}",
+            pending.len()
+        );
+    }
+}
+
+/// Persists the saved pending downlinks (see save_pending) to the storage, so that these are
+/// restored by load_pending on the next startup. As the host monotonic time does not persist
+/// across a restart, only the downlinks saved with their GPS epoch time are persisted.
+pub fn persist_pending() -> Result<()> {
+    let mut pending = PENDING.lock().unwrap();
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    // Each downlink is encoded as length-delimited DownlinkFrame.
+    let mut b = Vec::new();
+    let mut count = 0;
+    for (tx_packet, pending_time) in pending.drain(..) {
+        let gps_epoch = match pending_time {
+            PendingTime::GpsEpoch(v) => v,
+            PendingTime::Offset(_) => {
+                drop_pending(&tx_packet, "no valid GPS time reference");
+                continue;
+            }
+        };
+        let item = wrapper::pending_to_proto(&tx_packet.tx_packet(), &gps_epoch)?;
+        gw::DownlinkFrame {
+            downlink_id: tx_packet.get_id(),
+            items: vec![item],
+            ..Default::default()
+        }
+        .encode_length_delimited(&mut b)?;
+        count += 1;
+    }
+    if b.is_empty() {
+        return Ok(());
+    }
+    storage::set(PENDING_STORAGE_KEY, &b)?;
+
+    info!("Persisted pending downlinks, count: {}", count);
+
+    Ok(())
+}
+
+/// Loads the downlinks persisted by persist_pending. These are re-scheduled by the JIT loop
+/// once the GPS time reference is valid, downlinks of which the TX time has passed by then are
+/// dropped.
+pub fn load_pending() -> Result<()> {
+    let b = match storage::get(PENDING_STORAGE_KEY)? {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+
+    // The persisted downlinks are only restored once.
+    storage::delete(PENDING_STORAGE_KEY)?;
+
+    let mut buf = b.as_slice();
+    let mut pending = PENDING.lock().unwrap();
+    while !buf.is_empty() {
+        let df = gw::DownlinkFrame::decode_length_delimited(&mut buf)?;
+        let item = df
+            .items
+            .first()
+            .ok_or_else(|| anyhow!("downlink item is missing"))?;
+        let (tx_packet, gps_epoch) = wrapper::pending_from_proto(df.downlink_id, item)?;
+        pending.push((tx_packet, PendingTime::GpsEpoch(gps_epoch)));
+    }
+
+    info!(
+        "Loaded persisted pending downlinks for re-scheduling, count: {}",
+        pending.len()
+    );
+
+    Ok(())
+}
 
 pub fn jit_loop(
+    gateway_id: &[u8],
     queue: Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
-    antenna_gain: i8,
+    antenna_gains: &[i8],
+    gps_tx_offset_send: Sender<(u32, hal::TxPacket, Duration)>,
     stop_receive: Receiver<Signal>,
 ) {
     debug!("Starting JIT queue loop");
 
+    let mut pending: Vec<(wrapper::TxPacket, PendingTime)> =
+        PENDING.lock().unwrap().drain(..).collect();
+    let restore_deadline = timesource::monotonic() + RESTORE_TIMEOUT;
+
     loop {
         // Instead of a 10ms sleep, we receive from the stop channel with a
         // timeout of 10ms.
@@ -27,33 +165,89 @@ pub fn jit_loop(
             _ => {}
         };
 
+        // The downlinks are suspended during a certification TX test.
+        if txtest::certification_active() {
+            continue;
+        }
+
+        if !pending.is_empty() {
+            restore_pending(&queue, &mut pending, restore_deadline);
+        }
+
         let tx_packet = match get_tx_packet(&queue) {
             Some(v) => v,
             None => continue,
         };
 
         let downlink_id = tx_packet.get_id();
+        let gps_timing_error = tx_packet.gps_timing_error();
+        let gps_epoch = tx_packet.gps_epoch();
         let mut tx_packet = tx_packet.tx_packet();
+        let eirp = tx_packet.rf_power;
+        let antenna_gain = antenna_gains
+            .get(tx_packet.rf_chain as usize)
+            .cloned()
+            .unwrap_or_default();
         tx_packet.rf_power = tx_packet.rf_power - antenna_gain;
 
-        match hal::send(&tx_packet) {
+        // Compensate the temperature dependent output power of the power amplifier.
+        if let Some(correction) = temperature::get_tx_power_correction() {
+            if correction != 0 {
+                debug!(
+                    "Applying TX power temperature correction, downlink_id: {}, correction: {}",
+                    downlink_id, correction
+                );
+                tx_packet.rf_power += correction;
+            }
+        }
+
+        match send(&tx_packet, downlink_id) {
             Ok(_) => {
                 info!(
-                    "Scheduled packet for TX, downlink_id: {}, count_us: {}, freq: {}, bw: {}, mod: {:?}, dr: {:?}",
+                    "Scheduled packet for TX, downlink_id: {}, count_us: {}, freq: {}, bw: {}, mod: {:?}, dr: {:?}, power: {}",
                     downlink_id,
                     tx_packet.count_us,
                     tx_packet.freq_hz,
                     tx_packet.bandwidth,
                     tx_packet.modulation,
-                    tx_packet.datarate
+                    tx_packet.datarate,
+                    eirp,
                 );
 
                 if let Ok(tx_info) = wrapper::downlink_to_tx_info_proto(&tx_packet) {
                     stats::inc_tx_counts(&tx_info);
                 }
+
+                // The airtime is consumed from the duty-cycle budget once the packet has been
+                // handed over to the concentrator (this includes the beacons).
+                if let Ok(time_on_air) = hal::time_on_air(&tx_packet) {
+                    dutycycle::record(tx_packet.freq_hz, time_on_air);
+                }
+
+                events::send_tx_done(&api::TxDone {
+                    gateway_id: hex::encode(gateway_id),
+                    downlink_id,
+                    frequency: tx_packet.freq_hz,
+                    power: eirp as i32,
+                    count_us: tx_packet.count_us,
+                    gps_timing_error_us: gps_timing_error.as_micros() as u32,
+                })
+                .unwrap();
+
+                // The TX start is awaited by the GPS TX offset loop, as this would otherwise
+                // block the JIT loop until the packet is emitted.
+                if let Some(gps_epoch) = gps_epoch {
+                    let _ = gps_tx_offset_send.send((downlink_id, tx_packet, gps_epoch));
+                }
             }
             Err(err) => {
                 error!("Schedule packet for tx error, error: {}", err);
+                history::record_tx_failed(
+                    downlink_id,
+                    tx_packet.freq_hz,
+                    tx_packet.count_us,
+                    &err.to_string(),
+                );
             }
         }
     }
@@ -61,10 +255,200 @@ pub fn jit_loop(
     debug!("JIT loop ended");
 }
 
+// Interval between TX attempts when the concentrator is busy.
+const TX_RETRY_INTERVAL: Duration = Duration::from_millis(1);
+
+// TX is no longer retried when the scheduled TX time is within this margin (us).
+const TX_RETRY_MARGIN: u32 = 1500;
+
+// When the concentrator is still busy (scheduled or emitting) at the moment the packet is
+// handed over to the HAL, the TX is retried until the remaining margin is exhausted.
+fn send(tx_packet: &hal::TxPacket, downlink_id: u32) -> Result<()> {
+    loop {
+        let err = match hal::send(tx_packet) {
+            Ok(_) => return Ok(()),
+            Err(err) => err,
+        };
+
+        match hal::status(tx_packet.rf_chain, hal::StatusSelect::Tx) {
+            Ok(hal::StatusReturn::Tx(hal::TxStatus::Scheduled))
+            | Ok(hal::StatusReturn::Tx(hal::TxStatus::Emitting)) => {}
+            _ => return Err(err),
+        };
+
+        // The remaining time wraps around when the scheduled time has passed.
+        let remaining = tx_packet.count_us.wrapping_sub(hal::get_instcnt()?);
+        if matches!(tx_packet.tx_mode, hal::TxMode::OnGPS)
+            || remaining < TX_RETRY_MARGIN
+            || remaining > (i32::MAX as u32)
+        {
+            stats::inc_counter("tx_busy_failed");
+            return Err(anyhow!("concentrator busy"));
+        }
+
+        warn!(
+            "Concentrator busy, retrying TX, downlink_id: {}, remaining_us: {}",
+            downlink_id, remaining
+        );
+        stats::inc_counter("tx_busy_retries");
+        thread::sleep(TX_RETRY_INTERVAL);
+    }
+}
+
+/// Measures the offset between the requested GPS time and the actual TX start of the GPS timed
+/// downlinks sent by the JIT loop, and reports it as gps_tx_offset_us stats sample.
+pub fn gps_tx_offset_loop(
+    request_receive: Receiver<(u32, hal::TxPacket, Duration)>,
+    stop_receive: Receiver<Signal>,
+) {
+    debug!("Starting GPS TX offset loop");
+
+    loop {
+        if let Ok(v) = stop_receive.try_recv() {
+            debug!("Received stop signal, signal: {}", v);
+            break;
+        }
+
+        // The request is received with a timeout, so that the stop signal is handled.
+        let (downlink_id, tx_packet, gps_epoch) =
+            match request_receive.recv_timeout(Duration::from_millis(100)) {
+                Ok(v) => v,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => {
+                    // The JIT loop has ended, wait for the stop signal.
+                    if let Ok(v) = stop_receive.recv() {
+                        debug!("Received stop signal, signal: {}", v);
+                    }
+                    break;
+                }
+            };
+
+        match get_gps_tx_offset(&tx_packet, &gps_epoch) {
+            Ok(v) => {
+                debug!(
+                    "GPS timed TX offset, downlink_id: {}, offset_us: {}",
+                    downlink_id, v
+                );
+                stats::add_sample("gps_tx_offset_us", v as f64);
+            }
+            Err(err) => {
+                warn!(
+                    "Get GPS timed TX offset error, downlink_id: {}, error: {}",
+                    downlink_id, err
+                );
+            }
+        }
+    }
+
+    debug!("GPS TX offset loop ended");
+}
+
+// Interval for polling the TX status when waiting for the TX to start. Each poll locks the
+// concentrator only briefly, a shorter interval would block the other concentrator access.
+const TX_START_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+// Time (us) before the scheduled TX time at which the polling of the TX status starts.
+const TX_START_POLL_LEAD: i32 = 2000;
+
+// Max. time (us) after the scheduled TX time to wait for the TX to start.
+const TX_START_TIMEOUT: i32 = 10000;
+
+// Returns the offset (us) between the requested GPS time and the actual TX start. As the HAL
+// does not provide a TX start timestamp, the TX status is polled until the concentrator is
+// emitting. The resolution is therefore limited by the polling interval.
+fn get_gps_tx_offset(tx_packet: &hal::TxPacket, gps_epoch: &Duration) -> Result<i64> {
+    // The packet is handed over to the concentrator ahead of the scheduled TX time, there is no
+    // need to poll before.
+    let remaining = tx_packet.count_us.wrapping_sub(hal::get_instcnt()?) as i32;
+    if remaining > TX_START_POLL_LEAD {
+        thread::sleep(Duration::from_micros(
+            (remaining - TX_START_POLL_LEAD) as u64,
+        ));
+    }
+
+    loop {
+        if let hal::StatusReturn::Tx(hal::TxStatus::Emitting) =
+            hal::status(tx_packet.rf_chain, hal::StatusSelect::Tx)?
+        {
+            break;
+        }
+
+        let remaining = tx_packet.count_us.wrapping_sub(hal::get_instcnt()?) as i32;
+        if remaining < -TX_START_TIMEOUT {
+            return Err(anyhow!("timeout waiting for TX start"));
+        }
+
+        thread::sleep(TX_START_POLL_INTERVAL);
+    }
+
+    let tx_start = gps::cnt2epoch(hal::get_instcnt()?)?;
+    Ok(tx_start.as_micros() as i64 - gps_epoch.as_micros() as i64)
+}
+
+// Re-schedules the pending downlinks. The downlinks saved with their offset are re-scheduled
+// directly, the downlinks saved with their GPS epoch time once the GPS time reference is valid.
+// These are dropped when the GPS time reference is not valid before the deadline.
+fn restore_pending(
+    queue: &Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
+    pending: &mut Vec<(wrapper::TxPacket, PendingTime)>,
+    deadline: Duration,
+) {
+    let now = timesource::monotonic();
+    let mut queue = queue.lock().unwrap();
+    let concentrator_count = hal::get_instcnt().expect("get concentrator count error");
+    let mut waiting = Vec::new();
+
+    for (mut tx_packet, pending_time) in pending.drain(..) {
+        let count_us = match pending_time {
+            PendingTime::Offset(v) => match v.count_us(concentrator_count, now) {
+                Some(v) => v,
+                None => {
+                    drop_pending(&tx_packet, "TX time has passed");
+                    continue;
+                }
+            },
+            PendingTime::GpsEpoch(v) => match gps::epoch2cnt(&v) {
+                Ok(v) => v,
+                Err(_) if now > deadline => {
+                    drop_pending(&tx_packet, "no valid GPS time reference");
+                    continue;
+                }
+                Err(_) => {
+                    waiting.push((tx_packet, pending_time));
+                    continue;
+                }
+            },
+        };
+        tx_packet.set_count_us(count_us);
+
+        match queue.enqueue(concentrator_count, tx_packet) {
+            Ok(_) => {
+                info!(
+                    "Re-scheduled pending downlink, downlink_id: {}, count_us: {}",
+                    tx_packet.get_id(),
+                    count_us
+                );
+            }
+            Err(rejection) => drop_pending(&tx_packet, &rejection.to_string()),
+        }
+    }
+
+    *pending = waiting;
+}
+
+fn drop_pending(tx_packet: &wrapper::TxPacket, reason: &str) {
+    warn!(
+        "Dropping pending downlink, downlink_id: {}, reason: {}",
+        tx_packet.get_id(),
+        reason
+    );
+    stats::inc_counter("jit_restart_dropped");
+}
+
 fn get_tx_packet(
     queue: &Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
 ) -> Option<wrapper::TxPacket> {
     let mut queue = queue.lock().unwrap();
     let concentrator_count = hal::get_instcnt().expect("get concentrator count error");
-    return queue.pop(concentrator_count);
+    queue.pop(concentrator_count)
 }
@@ -1,6 +1,10 @@
+pub mod beacon;
 pub mod command;
 pub mod config;
 pub mod gps;
 pub mod jit;
+pub mod spectralscan;
 pub mod stats;
+pub mod temperature;
+pub mod txtest;
 pub mod uplink;
@@ -0,0 +1,229 @@
+use std::sync::mpsc::Receiver;
+use std::sync::Mutex;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use libconcentratord::signals::Signal;
+use libconcentratord::{api, events};
+use libloragw_sx1302::hal;
+
+// Max. number of channels of a single spectral scan.
+const MAX_CHANNELS: usize = 128;
+
+// Max. number of RSSI samples per channel.
+const MAX_NB_SCAN: u32 = 2000;
+
+// Max. duration of the scan of a single channel.
+const CHANNEL_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Interval at which the scan status is polled.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// Number of attempts per channel. The HAL aborts a running scan when it needs the SX1261 for
+// listen-before-talk, in which case the channel is scanned again.
+const CHANNEL_ATTEMPTS: usize = 3;
+
+lazy_static! {
+    // The SX1261 can only run a single scan at a time, this serializes the scans requested by
+    // the command and the periodic scans.
+    static ref SCAN_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Validates the spectral scan request.
+pub fn validate(pl: &api::SpectralScanRequest) -> Result<()> {
+    if pl.freq_start == 0 || pl.freq_stop < pl.freq_start {
+        return Err(anyhow!(
+            "freq_stop must be greater than or equal to freq_start"
+        ));
+    }
+
+    if pl.freq_step == 0 {
+        return Err(anyhow!("freq_step must be greater than 0"));
+    }
+
+    if pl.nb_scan == 0 || pl.nb_scan > MAX_NB_SCAN {
+        return Err(anyhow!("nb_scan must be between 1 and {}", MAX_NB_SCAN));
+    }
+
+    if get_frequencies(pl).len() > MAX_CHANNELS {
+        return Err(anyhow!("number of channels exceeds {}", MAX_CHANNELS));
+    }
+
+    Ok(())
+}
+
+/// Runs the spectral scan and returns the RSSI histogram per channel.
+pub fn scan(gateway_id: &[u8], pl: &api::SpectralScanRequest) -> Result<api::SpectralScan> {
+    validate(pl)?;
+
+    let _guard = SCAN_LOCK.lock().unwrap();
+    let frequencies = get_frequencies(pl);
+
+    info!(
+        "Starting spectral scan, freq_start: {}, freq_stop: {}, freq_step: {}, nb_scan: {}",
+        pl.freq_start, pl.freq_stop, pl.freq_step, pl.nb_scan
+    );
+
+    let mut channels: Vec<api::SpectralScanChannel> = Vec::with_capacity(frequencies.len());
+    for frequency in frequencies {
+        channels.push(api::SpectralScanChannel {
+            frequency,
+            levels: scan_channel(frequency, pl.nb_scan as u16)?,
+        });
+    }
+
+    Ok(api::SpectralScan {
+        gateway_id: hex::encode(gateway_id),
+        channels,
+    })
+}
+
+/// Periodically runs the spectral scan and publishes the results as spectral_scan event, for
+/// noise-floor monitoring.
+pub fn scan_loop(
+    gateway_id: &[u8],
+    pl: &api::SpectralScanRequest,
+    interval: Duration,
+    stop_receive: Receiver<Signal>,
+) {
+    debug!("Starting spectral scan loop, interval: {:?}", interval);
+
+    loop {
+        // Instead of an 'interval' sleep, we receive from the stop channel with a timeout
+        // equal to the 'interval'.
+        if let Ok(v) = stop_receive.recv_timeout(interval) {
+            debug!("Received stop signal, signal: {}", v);
+            break;
+        }
+
+        match scan(gateway_id, pl) {
+            Ok(v) => {
+                if let Err(err) = events::send_spectral_scan(&v) {
+                    error!("Send spectral scan event error, error: {}", err);
+                }
+            }
+            Err(err) => error!("Spectral scan error, error: {}", err),
+        }
+    }
+
+    debug!("Spectral scan loop ended");
+}
+
+fn get_frequencies(pl: &api::SpectralScanRequest) -> Vec<u32> {
+    let mut out: Vec<u32> = Vec::new();
+    let mut freq = pl.freq_start;
+    while freq <= pl.freq_stop && out.len() <= MAX_CHANNELS {
+        out.push(freq);
+        freq = match freq.checked_add(pl.freq_step) {
+            Some(v) => v,
+            None => break,
+        };
+    }
+    out
+}
+
+fn scan_channel(frequency: u32, nb_scan: u16) -> Result<Vec<api::SpectralScanLevel>> {
+    for attempt in 1..=CHANNEL_ATTEMPTS {
+        hal::spectral_scan_start(frequency, nb_scan)?;
+
+        let started = Instant::now();
+        let status = loop {
+            let status = hal::spectral_scan_get_status()?;
+            if status != hal::SpectralScanStatus::OnGoing {
+                break status;
+            }
+
+            if started.elapsed() > CHANNEL_TIMEOUT {
+                hal::spectral_scan_abort()?;
+                return Err(anyhow!("spectral scan timeout, frequency: {}", frequency));
+            }
+
+            sleep(POLL_INTERVAL);
+        };
+
+        match status {
+            hal::SpectralScanStatus::Completed => {
+                return Ok(hal::spectral_scan_get_results()?
+                    .iter()
+                    .map(|v| api::SpectralScanLevel {
+                        rssi: v.dbm_level as i32,
+                        count: v.result as u32,
+                    })
+                    .collect());
+            }
+            hal::SpectralScanStatus::Aborted => {
+                warn!(
+                    "Spectral scan aborted, frequency: {}, attempt: {}",
+                    frequency, attempt
+                );
+            }
+            _ => {
+                return Err(anyhow!(
+                    "unexpected spectral scan status, frequency: {}, status: {:?}",
+                    frequency,
+                    status
+                ));
+            }
+        }
+    }
+
+    Err(anyhow!(
+        "spectral scan aborted, frequency: {}, attempts: {}",
+        frequency,
+        CHANNEL_ATTEMPTS
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_request() -> api::SpectralScanRequest {
+        api::SpectralScanRequest {
+            freq_start: 863000000,
+            freq_stop: 870000000,
+            freq_step: 200000,
+            nb_scan: 2000,
+        }
+    }
+
+    #[test]
+    fn test_get_frequencies() {
+        let frequencies = get_frequencies(&get_request());
+        assert_eq!(36, frequencies.len());
+        assert_eq!(Some(&863000000), frequencies.first());
+        assert_eq!(Some(&870000000), frequencies.last());
+
+        let frequencies = get_frequencies(&api::SpectralScanRequest {
+            freq_stop: 863000000,
+            ..get_request()
+        });
+        assert_eq!(vec![863000000], frequencies);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&get_request()).is_ok());
+        assert!(validate(&api::SpectralScanRequest {
+            freq_stop: 862000000,
+            ..get_request()
+        })
+        .is_err());
+        assert!(validate(&api::SpectralScanRequest {
+            freq_step: 0,
+            ..get_request()
+        })
+        .is_err());
+        assert!(validate(&api::SpectralScanRequest {
+            nb_scan: 2001,
+            ..get_request()
+        })
+        .is_err());
+        assert!(validate(&api::SpectralScanRequest {
+            freq_step: 1000,
+            ..get_request()
+        })
+        .is_err());
+    }
+}
@@ -3,10 +3,10 @@ use std::sync::mpsc::Receiver;
 use std::time::Duration;
 
 use libconcentratord::signals::Signal;
-use libconcentratord::stats;
+use libconcentratord::{drift, gnss, metrics, stats};
 use libloragw_sx1302::hal;
 
-use super::gps;
+use super::{gps, temperature};
 
 pub fn stats_loop(
     gateway_id: &[u8],
@@ -16,6 +16,10 @@ pub fn stats_loop(
 ) {
     debug!("Starting stats loop, stats_interval: {:?}", stats_interval);
 
+    let mut drift = drift::Drift::new();
+    sample_drift(&mut drift, &mut metadata);
+    sample_temperature(&mut metadata);
+
     loop {
         // Instead of a 'stats interval' sleep, we receive from the stop channel with a
         // timeout equal to the 'stats interval'.
@@ -27,35 +31,79 @@ pub fn stats_loop(
             _ => {}
         };
 
-        // fetch the current gps coordinates
-        let loc = match gps::get_coords() {
-            Some(v) => Some({
-                let mut loc = chirpstack_api::common::Location {
-                    latitude: v.latitude,
-                    longitude: v.longitude,
-                    altitude: v.altitude as f64,
-                    ..Default::default()
-                };
-
-                loc.set_source(chirpstack_api::common::LocationSource::Gps);
-                loc
+        // fetch the current gps coordinates, while waiting for a (new) fix the last known
+        // fix is reported, flagged as stale in the meta-data
+        let coords = match gps::get_coords() {
+            Some(v) => {
+                metadata.remove(&"gnss_fix_stale".to_string());
+                Some((v.latitude, v.longitude, v.altitude))
+            }
+            None => gnss::get_last_fix().map(|v| {
+                metadata.insert("gnss_fix_stale".to_string(), "true".to_string());
+                (v.latitude, v.longitude, v.altitude)
             }),
-            None => None,
         };
+        let loc = coords.map(|(latitude, longitude, altitude)| {
+            let mut loc = chirpstack_api::common::Location {
+                latitude,
+                longitude,
+                altitude: altitude as f64,
+                ..Default::default()
+            };
+
+            loc.set_source(chirpstack_api::common::LocationSource::Gps);
+            loc
+        });
 
         // fetch the concentrator temperature.
-        match hal::get_temperature() {
-            Ok(v) => {
-                metadata.insert("concentrator_temp".to_string(), format!("{}", v));
-            }
-            Err(err) => {
-                metadata.remove(&"concentrator_temp".to_string());
-                error!("Get concentrator temperature error, error: {}", err);
-            }
-        }
+        sample_temperature(&mut metadata);
+
+        // sample the concentrator clock drift.
+        sample_drift(&mut drift, &mut metadata);
 
         stats::send_and_reset(gateway_id, loc, &metadata).expect("sending stats failed");
     }
 
     debug!("Stats loop ended");
 }
+
+// The temperature is also used for the TX power temperature compensation, see
+// temperature::get_tx_power_correction.
+fn sample_temperature(metadata: &mut HashMap<String, String>) {
+    match hal::get_temperature() {
+        Ok(v) => {
+            temperature::set(Some(v));
+            metadata.insert("concentrator_temp".to_string(), format!("{}", v));
+            metrics::set_gauge("concentrator_temperature_celsius", &[], v as f64);
+        }
+        Err(err) => {
+            temperature::set(None);
+            metadata.remove(&"concentrator_temp".to_string());
+            metrics::remove_gauge("concentrator_temperature_celsius", &[]);
+            error!("Get concentrator temperature error, error: {}", err);
+        }
+    }
+
+    match temperature::get_tx_power_correction() {
+        Some(v) => {
+            metadata.insert("tx_power_temp_correction".to_string(), v.to_string());
+        }
+        None => {
+            metadata.remove(&"tx_power_temp_correction".to_string());
+        }
+    }
+}
+
+fn sample_drift(drift: &mut drift::Drift, metadata: &mut HashMap<String, String>) {
+    match hal::get_instcnt() {
+        Ok(v) => {
+            if let Some(ppm) = drift.sample(v) {
+                metadata.insert("concentrator_drift_ppm".to_string(), format!("{:.2}", ppm));
+            }
+        }
+        Err(err) => {
+            metadata.remove(&"concentrator_drift_ppm".to_string());
+            error!("Get concentrator count error, error: {}", err);
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use std::sync::Mutex;
+
+use libconcentratord::txpower;
+
+lazy_static! {
+    // Last read concentrator temperature (°C), None when it could not be read.
+    static ref TEMPERATURE: Mutex<Option<f32>> = Mutex::new(None);
+
+    // TX power correction (temperature, correction) points, empty when disabled.
+    static ref CORRECTION_TABLE: Mutex<Vec<(f32, f32)>> = Mutex::new(Vec::new());
+}
+
+/// Set the TX power temperature correction table. When empty, the TX power is not corrected.
+pub fn set_correction_table(table: Vec<(f32, f32)>) {
+    info!(
+        "Setting TX power temperature correction table, table: {:?}",
+        table
+    );
+    *CORRECTION_TABLE.lock().unwrap() = table;
+}
+
+/// Set the last read concentrator temperature.
+pub fn set(temperature: Option<f32>) {
+    *TEMPERATURE.lock().unwrap() = temperature;
+}
+
+/// Returns the TX power correction (dB) for the last read concentrator temperature. This is
+/// rounded to whole dB, as this is the resolution of the rf_power of the HAL. None is returned
+/// when disabled or when the temperature is not known.
+pub fn get_tx_power_correction() -> Option<i8> {
+    let table = CORRECTION_TABLE.lock().unwrap();
+    if table.is_empty() {
+        return None;
+    }
+
+    let temperature = (*TEMPERATURE.lock().unwrap())?;
+    Some(txpower::get_temperature_correction(&table, temperature).round() as i8)
+}
@@ -0,0 +1,203 @@
+use std::str::FromStr;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use libconcentratord::signals::Signal;
+use libconcentratord::{api, events, jitqueue, txtest};
+use libloragw_sx1302::hal;
+use rand::Rng;
+
+use super::super::{config, wrapper};
+
+/// Returns the frequencies of all the configured channels.
+pub fn get_frequencies(conf: &config::Configuration) -> Vec<u32> {
+    let concentrator = &conf.gateway.concentrator;
+    let mut frequencies: Vec<u32> = concentrator.multi_sf_channels.to_vec();
+    frequencies.push(concentrator.lora_std.frequency);
+    frequencies.push(concentrator.fsk.frequency);
+    frequencies.retain(|f| *f != 0);
+    frequencies
+}
+
+pub fn txtest_loop(
+    gateway_id: &[u8],
+    frequencies: &[u32],
+    antenna_gain: i8,
+    queue: Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
+    request_receive: Receiver<api::TxTestRequest>,
+    stop_receive: Receiver<Signal>,
+) {
+    debug!("Starting TX test loop");
+
+    loop {
+        // Instead of a 100ms sleep, we receive from the stop channel with a timeout of 100ms.
+        if let Ok(v) = stop_receive.recv_timeout(Duration::from_millis(100)) {
+            debug!("Received stop signal, signal: {}", v);
+            break;
+        }
+
+        let req = match request_receive.try_recv() {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+
+        let completed = match txtest::Mode::from_str(&req.mode) {
+            _ if req.stop => {
+                warn!("No certification TX test is running");
+                true
+            }
+            Ok(txtest::Mode::Sweep) => {
+                run_test(gateway_id, frequencies, &queue, &req, &stop_receive)
+            }
+            _ => txtest::run_certification(
+                gateway_id,
+                &Transmitter { antenna_gain },
+                &req,
+                &request_receive,
+                &stop_receive,
+            ),
+        };
+
+        if !completed {
+            break;
+        }
+    }
+
+    debug!("TX test loop ended");
+}
+
+// Returns false when the test was aborted by a stop signal.
+fn run_test(
+    gateway_id: &[u8],
+    frequencies: &[u32],
+    queue: &Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
+    req: &api::TxTestRequest,
+    stop_receive: &Receiver<Signal>,
+) -> bool {
+    let schedule = txtest::get_schedule(frequencies, req.repetitions);
+
+    info!(
+        "Starting TX test, tx_power: {}, repetitions: {}, interval_ms: {}, transmissions: {}",
+        req.tx_power,
+        req.repetitions,
+        req.interval_ms,
+        schedule.len()
+    );
+
+    for (i, frequency) in schedule.iter().enumerate() {
+        let mut progress = api::TxTestProgress {
+            gateway_id: hex::encode(gateway_id),
+            frequency: *frequency,
+            sent: i as u32 + 1,
+            total: schedule.len() as u32,
+            ..Default::default()
+        };
+
+        if let Err(err) = send_test_packet(*frequency, req, queue) {
+            warn!(
+                "Enqueue TX test packet failed, frequency: {}, error: {}",
+                frequency, err
+            );
+            progress.error = err.to_string();
+        }
+
+        if let Err(err) = events::send_tx_test_progress(&progress) {
+            error!("Publish TX test progress error, error: {}", err);
+        }
+
+        // Instead of an interval sleep, we receive from the stop channel with a timeout equal
+        // to the interval.
+        if let Ok(v) = stop_receive.recv_timeout(Duration::from_millis(req.interval_ms.into())) {
+            warn!("TX test aborted, signal: {}", v);
+            return false;
+        }
+    }
+
+    info!("TX test completed, transmissions: {}", schedule.len());
+
+    true
+}
+
+fn send_test_packet(
+    frequency: u32,
+    req: &api::TxTestRequest,
+    queue: &Arc<Mutex<jitqueue::Queue<wrapper::TxPacket>>>,
+) -> Result<()> {
+    let mut rng = rand::thread_rng();
+
+    let tx_packet = hal::TxPacket {
+        freq_hz: frequency,
+        tx_mode: hal::TxMode::Immediate,
+        rf_chain: 0,
+        rf_power: req.tx_power as i8,
+        modulation: hal::Modulation::LoRa,
+        bandwidth: 125000,
+        datarate: hal::DataRate::SF7,
+        coderate: hal::CodeRate::LoRa4_5,
+        preamble: 8,
+        size: req.payload_size as u16,
+        ..Default::default()
+    };
+    let tx_packet = wrapper::TxPacket::new(rng.gen(), tx_packet);
+
+    match queue
+        .lock()
+        .unwrap()
+        .enqueue(hal::get_instcnt()?, tx_packet)
+    {
+        Ok(_) => Ok(()),
+        Err(status) => Err(anyhow!("{:?}", status)),
+    }
+}
+
+// Transmits the certification test directly through the HAL, as the JIT queue is suspended
+// during the test.
+struct Transmitter {
+    antenna_gain: i8,
+}
+
+impl txtest::Transmitter for Transmitter {
+    fn start(&self, mode: txtest::Mode, req: &api::TxTestRequest) -> Result<()> {
+        let mut tx_packet = hal::TxPacket {
+            freq_hz: req.frequency,
+            tx_mode: hal::TxMode::Immediate,
+            rf_chain: 0,
+            rf_power: (req.tx_power - self.antenna_gain as i32) as i8,
+            ..Default::default()
+        };
+
+        match mode {
+            txtest::Mode::ContinuousWave => {
+                tx_packet.modulation = hal::Modulation::CW;
+            }
+            _ => {
+                tx_packet.modulation = hal::Modulation::LoRa;
+                tx_packet.bandwidth = 125000;
+                tx_packet.datarate = hal::DataRate::SF7;
+                tx_packet.coderate = hal::CodeRate::LoRa4_5;
+                tx_packet.preamble = 8;
+                tx_packet.size = match req.payload_size {
+                    0 => 255,
+                    v => v as u16,
+                };
+                rand::thread_rng().fill(&mut tx_packet.payload[..]);
+            }
+        }
+
+        hal::send(&tx_packet)
+    }
+
+    fn busy(&self) -> Result<bool> {
+        Ok(matches!(
+            hal::status(0, hal::StatusSelect::Tx)?,
+            hal::StatusReturn::Tx(hal::TxStatus::Scheduled)
+                | hal::StatusReturn::Tx(hal::TxStatus::Emitting)
+        ))
+    }
+
+    fn abort(&self) -> Result<()> {
+        hal::abort_tx(0)
+    }
+}
@@ -1,14 +1,22 @@
+use std::collections::HashMap;
 use std::sync::mpsc::Receiver;
 use std::thread;
 use std::time::Duration;
 
 use libconcentratord::signals::Signal;
-use libconcentratord::{events, stats};
+use libconcentratord::{airtime, capture, events, health, metrics, stats, systemd};
 use libloragw_sx1302::hal;
 
-use super::super::wrapper;
+use super::super::{config, wrapper};
 
-pub fn handle_loop(gateway_id: &[u8], stop_receive: Receiver<Signal>) {
+pub fn handle_loop(
+    gateway_id: &[u8],
+    diagnostics: bool,
+    lorawan_channels: HashMap<u32, u32>,
+    antennas: HashMap<u32, u32>,
+    advanced: &config::Advanced,
+    stop_receive: Receiver<Signal>,
+) {
     debug!("Starting uplink handle loop");
 
     loop {
@@ -20,22 +28,61 @@ pub fn handle_loop(gateway_id: &[u8], stop_receive: Receiver<Signal>) {
             _ => {}
         };
 
-        match hal::receive() {
+        // When a full batch was fetched, more frames might be pending in the concentrator
+        // buffer. In this case these are fetched immediately instead of after the poll interval.
+        let mut pending = false;
+
+        match hal::receive(advanced.rx_fetch_size as u8) {
             Ok(frames) => {
+                // A hanging or failing receive stops the pings, see systemd::watchdog.
+                systemd::watchdog();
+                health::receive_ok(frames.len());
+
+                pending = frames.len() == advanced.rx_fetch_size;
+
                 for frame in frames {
                     stats::inc_rx_packets_received();
+                    metrics::inc_counter(
+                        "rx_packets_total",
+                        &[
+                            ("channel", &frame.if_chain.to_string()),
+                            ("crc", &format!("{:?}", frame.status)),
+                        ],
+                    );
                     if frame.status != hal::CRC::CRCOk {
                         debug!("Frame received with invalid CRC");
+                        if events::tap_active() {
+                            send_tap_invalid_crc(gateway_id, &frame, diagnostics);
+                        }
                         continue;
                     }
 
-                    let proto = match wrapper::uplink_to_proto(gateway_id.clone(), &frame) {
-                        Ok(v) => v,
-                        Err(err) => {
-                            error!("Convert uplink frame to protobuf error, error: {}", err);
-                            continue;
+                    let mut proto =
+                        match wrapper::uplink_to_proto(gateway_id.clone(), &frame, diagnostics) {
+                            Ok(v) => v,
+                            Err(err) => {
+                                error!("Convert uplink frame to protobuf error, error: {}", err);
+                                continue;
+                            }
+                        };
+
+                    // annotate the uplink with the lorawan channel index of its frequency
+                    if let Some(index) = lorawan_channels.get(&frame.freq_hz) {
+                        if let Some(rx_info) = proto.rx_info.as_mut() {
+                            rx_info
+                                .metadata
+                                .insert("lorawan_channel".to_string(), index.to_string());
                         }
-                    };
+                    }
+
+                    // set the antenna of the radio that received the uplink
+                    if let Some(antenna) = antennas.get(&(frame.rf_chain as u32)) {
+                        if let Some(rx_info) = proto.rx_info.as_mut() {
+                            rx_info.antenna = *antenna;
+                        }
+                    }
+
+                    airtime::annotate(&mut proto);
 
                     let rx_info = proto.rx_info.as_ref().unwrap();
 
@@ -50,14 +97,39 @@ pub fn handle_loop(gateway_id: &[u8], stop_receive: Receiver<Signal>) {
                     );
 
                     stats::inc_rx_counts(&proto);
+                    capture::write_uplink(&proto);
                     events::send_uplink(&proto).unwrap();
                 }
             }
-            Err(_) => error!("Receive error"),
+            Err(_) => {
+                error!("Receive error");
+                health::hal_error();
+            }
         };
 
-        thread::sleep(Duration::from_millis(10));
+        if !pending {
+            thread::sleep(advanced.rx_poll_interval);
+        }
     }
 
     debug!("Uplink loop ended");
 }
+
+// Frames with an invalid CRC are only published by the live frame tap.
+fn send_tap_invalid_crc(gateway_id: &[u8], frame: &hal::RxPacket, diagnostics: bool) {
+    let mut proto = match wrapper::uplink_to_proto(gateway_id, frame, diagnostics) {
+        Ok(v) => v,
+        Err(err) => {
+            debug!("Convert tapped frame to protobuf error, error: {}", err);
+            return;
+        }
+    };
+
+    if let Some(rx_info) = proto.rx_info.as_mut() {
+        rx_info
+            .metadata
+            .insert("crc_status".to_string(), format!("{:?}", frame.status));
+    }
+
+    events::send_tap(&proto);
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::time::{Duration, UNIX_EPOCH};
 
 use anyhow::Result;
@@ -9,16 +10,37 @@ use rand::Rng;
 use super::handler::gps;
 
 #[derive(Copy, Clone)]
-pub struct TxPacket(hal::TxPacket, u32);
+pub struct TxPacket(hal::TxPacket, u32, Option<Duration>, Duration);
 
 impl TxPacket {
     pub fn new(id: u32, tx_packet: hal::TxPacket) -> TxPacket {
-        TxPacket(tx_packet, id)
+        TxPacket(tx_packet, id, None, Duration::from_secs(0))
+    }
+
+    /// Set the requested GPS epoch time, in case of a GPS timed downlink.
+    pub fn with_gps_epoch(mut self, gps_epoch: Option<Duration>) -> TxPacket {
+        self.2 = gps_epoch;
+        self
+    }
+
+    /// Set the estimated timing error, in case of a GPS timed downlink that is scheduled using
+    /// the last known GPS time reference.
+    pub fn with_gps_timing_error(mut self, error: Duration) -> TxPacket {
+        self.3 = error;
+        self
     }
 
     pub fn tx_packet(&self) -> hal::TxPacket {
         self.0
     }
+
+    pub fn gps_epoch(&self) -> Option<Duration> {
+        self.2
+    }
+
+    pub fn gps_timing_error(&self) -> Duration {
+        self.3
+    }
 }
 
 impl jitqueue::TxPacket for TxPacket {
@@ -50,13 +72,21 @@ impl jitqueue::TxPacket for TxPacket {
     fn get_id(&self) -> u32 {
         self.1
     }
+
+    fn get_frequency(&self) -> u32 {
+        self.0.freq_hz
+    }
 }
 
-pub fn uplink_to_proto(gateway_id: &[u8], packet: &hal::RxPacket) -> Result<gw::UplinkFrame> {
+pub fn uplink_to_proto(
+    gateway_id: &[u8],
+    packet: &hal::RxPacket,
+    diagnostics: bool,
+) -> Result<gw::UplinkFrame> {
     let mut rng = rand::thread_rng();
     let uplink_id: u32 = rng.gen();
 
-    Ok(gw::UplinkFrame {
+    let mut pb = gw::UplinkFrame {
         phy_payload: packet.payload[..packet.size as usize].to_vec(),
         tx_info: Some(gw::UplinkTxInfo {
             frequency: packet.freq_hz,
@@ -90,13 +120,13 @@ pub fn uplink_to_proto(gateway_id: &[u8], packet: &hal::RxPacket) -> Result<gw::
                     hal::Modulation::FSK => {
                         Some(gw::modulation::Parameters::Fsk(gw::FskModulationInfo {
                             datarate: match packet.datarate {
-                                hal::DataRate::FSK(v) => v * 1000,
+                                hal::DataRate::FSK(v) => v,
                                 _ => return Err(anyhow!("unexpected datarate")),
                             },
                             ..Default::default()
                         }))
                     }
-                    hal::Modulation::Undefined => None,
+                    hal::Modulation::Undefined | hal::Modulation::CW => None,
                 },
             }),
         }),
@@ -108,7 +138,7 @@ pub fn uplink_to_proto(gateway_id: &[u8], packet: &hal::RxPacket) -> Result<gw::
             snr: packet.snr,
             channel: packet.if_chain as u32,
             rf_chain: packet.rf_chain as u32,
-            time: match gps::cnt2time(packet.count_us) {
+            time: match gps::uplink_time(packet.count_us) {
                 Ok(v) => {
                     let v = v.duration_since(UNIX_EPOCH).unwrap();
                     Some(pbjson_types::Timestamp {
@@ -118,29 +148,118 @@ pub fn uplink_to_proto(gateway_id: &[u8], packet: &hal::RxPacket) -> Result<gw::
                 }
                 Err(err) => {
                     debug!(
-                        "Could not get GPS time, uplink_id: {}, error: {}",
+                        "Could not get uplink time, uplink_id: {}, error: {}",
                         uplink_id, err
                     );
                     None
                 }
             },
-            time_since_gps_epoch: match gps::cnt2epoch(packet.count_us) {
+            time_since_gps_epoch: match gps::uplink_gps_epoch(packet.count_us) {
                 Ok(v) => Some(pbjson_types::Duration {
                     seconds: v.as_secs() as i64,
                     nanos: v.subsec_nanos() as i32,
                 }),
                 Err(err) => {
                     debug!(
-                        "Could not get GPS epoch, uplink_id: {}, error: {}",
+                        "Could not get uplink GPS epoch, uplink_id: {}, error: {}",
                         uplink_id, err
                     );
                     None
                 }
             },
+            fine_time_since_gps_epoch: match packet.ftime_received {
+                true => match gps::cnt2epoch(packet.count_us) {
+                    Ok(v) => get_fine_time_since_gps_epoch(v, packet.ftime).map(|v| {
+                        pbjson_types::Duration {
+                            seconds: v.as_secs() as i64,
+                            nanos: v.subsec_nanos() as i32,
+                        }
+                    }),
+                    Err(err) => {
+                        debug!(
+                            "Could not get fine timestamp GPS epoch, uplink_id: {}, error: {}",
+                            uplink_id, err
+                        );
+                        None
+                    }
+                },
+                false => None,
+            },
+            metadata: get_metadata(packet, diagnostics),
             ..Default::default()
         }),
         ..Default::default()
-    })
+    };
+
+    if gps::is_timestamp_debug() {
+        if let Some(rx_info) = pb.rx_info.as_mut() {
+            let metadata = get_timestamp_debug(packet.count_us, rx_info);
+            rx_info.metadata.extend(metadata);
+        }
+    }
+
+    Ok(pb)
+}
+
+// Returns the uplink meta-data. The board, RF chain and IF chain of reception are always
+// included, the per-packet diagnostics only when enabled.
+fn get_metadata(packet: &hal::RxPacket, diagnostics: bool) -> HashMap<String, String> {
+    let mut metadata = HashMap::new();
+    metadata.insert("board".to_string(), "0".to_string());
+    metadata.insert("rf_chain".to_string(), packet.rf_chain.to_string());
+    metadata.insert("if_chain".to_string(), packet.if_chain.to_string());
+    if diagnostics {
+        metadata.extend(get_diagnostics(packet));
+    }
+    metadata
+}
+
+// Returns the per-packet diagnostics of the HAL, which are added to the uplink meta-data.
+fn get_diagnostics(packet: &hal::RxPacket) -> HashMap<String, String> {
+    let mut metadata = HashMap::new();
+    metadata.insert("freq_offset".to_string(), packet.freq_offset.to_string());
+    metadata.insert("modem_id".to_string(), packet.modem_id.to_string());
+    metadata.insert("rssi_channel".to_string(), format!("{:.1}", packet.rssic));
+    metadata
+}
+
+// Returns the raw concentrator counter, the time reference and the converted timestamps of the
+// uplink, such that time conversion issues can be diagnosed from the uplinks.
+fn get_timestamp_debug(count_us: u32, rx_info: &gw::UplinkRxInfo) -> HashMap<String, String> {
+    let mut metadata = gps::get_timestamp_debug(count_us);
+    if let Some(v) = &rx_info.time {
+        metadata.insert(
+            "ts_time".to_string(),
+            format!("{}.{:09}", v.seconds, v.nanos),
+        );
+    }
+    if let Some(v) = &rx_info.time_since_gps_epoch {
+        metadata.insert(
+            "ts_gps_epoch".to_string(),
+            format!("{}.{:09}", v.seconds, v.nanos),
+        );
+    }
+    if let Some(v) = &rx_info.fine_time_since_gps_epoch {
+        metadata.insert(
+            "ts_fine_gps_epoch".to_string(),
+            format!("{}.{:09}", v.seconds, v.nanos),
+        );
+    }
+    metadata
+}
+
+// Returns the GPS epoch of the fine timestamp. The fine timestamp is in nanoseconds since the
+// last PPS, the GPS second of this PPS is derived from the (less accurate) GPS epoch of the
+// uplink. This requires a valid GPS time reference, as it is aligned to the PPS.
+fn get_fine_time_since_gps_epoch(gps_epoch: Duration, ftime: u32) -> Option<Duration> {
+    if ftime >= 1_000_000_000 {
+        return None;
+    }
+
+    let pps = gps_epoch.checked_sub(Duration::from_nanos(ftime.into()))?;
+    let pps_secs = (pps + Duration::from_millis(500)).as_secs();
+
+    Some(Duration::new(pps_secs, ftime))
 }
 
 pub fn downlink_from_proto(df: &gw::DownlinkFrameItem) -> Result<hal::TxPacket> {
@@ -204,8 +323,8 @@ pub fn downlink_from_proto(df: &gw::DownlinkFrameItem) -> Result<hal::TxPacket>
                             let gps_epoch = Duration::from_secs(v.seconds as u64)
                                 + Duration::from_nanos(v.nanos as u64);
 
-                            match gps::epoch2cnt(&gps_epoch) {
-                                Ok(v) => {
+                            match gps::epoch2cnt_downlink(&gps_epoch) {
+                                Ok((v, _)) => {
                                     packet.count_us = v;
                                 }
                                 Err(err) => return Err(err),
@@ -261,6 +380,35 @@ pub fn downlink_from_proto(df: &gw::DownlinkFrameItem) -> Result<hal::TxPacket>
     Ok(packet)
 }
 
+pub fn gps_epoch_from_proto(df: &gw::DownlinkFrameItem) -> Option<Duration> {
+    let params = df.tx_info.as_ref()?.timing.as_ref()?.parameters.as_ref()?;
+    match params {
+        gw::timing::Parameters::GpsEpoch(v) => {
+            let v = v.time_since_gps_epoch.as_ref()?;
+            Some(Duration::from_secs(v.seconds as u64) + Duration::from_nanos(v.nanos as u64))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the concentrator counter of the uplink context, for downlinks using GPS epoch timing.
+/// This is used to cross-check the GPS epoch timing against the uplink.
+pub fn gps_timing_context_from_proto(df: &gw::DownlinkFrameItem) -> Option<u32> {
+    let tx_info = df.tx_info.as_ref()?;
+    match tx_info.timing.as_ref()?.parameters.as_ref()? {
+        gw::timing::Parameters::GpsEpoch(_) => {}
+        _ => return None,
+    }
+
+    if tx_info.context.len() != 4 {
+        return None;
+    }
+
+    let mut array = [0; 4];
+    array.copy_from_slice(&tx_info.context);
+    Some(u32::from_be_bytes(array))
+}
+
 pub fn downlink_to_tx_info_proto(packet: &hal::TxPacket) -> Result<gw::DownlinkTxInfo> {
     Ok(gw::DownlinkTxInfo {
         frequency: packet.freq_hz,
@@ -296,15 +444,129 @@ pub fn downlink_to_tx_info_proto(packet: &hal::TxPacket) -> Result<gw::DownlinkT
                 hal::Modulation::FSK => {
                     Some(gw::modulation::Parameters::Fsk(gw::FskModulationInfo {
                         datarate: match packet.datarate {
-                            hal::DataRate::FSK(v) => v * 1000,
+                            hal::DataRate::FSK(v) => v,
                             _ => return Err(anyhow!("unexpected datarate")),
                         },
-                        ..Default::default()
+                        frequency_deviation: packet.f_dev as u32 * 1000,
                     }))
                 }
-                hal::Modulation::Undefined => None,
+                hal::Modulation::Undefined | hal::Modulation::CW => None,
             },
         }),
         ..Default::default()
     })
 }
+
+/// Returns the downlink item of a pending downlink, using GPS epoch timing. This is used to
+/// persist the pending downlinks, see pending_from_proto.
+pub fn pending_to_proto(
+    packet: &hal::TxPacket,
+    gps_epoch: &Duration,
+) -> Result<gw::DownlinkFrameItem> {
+    let mut tx_info = downlink_to_tx_info_proto(packet)?;
+    tx_info.power = packet.rf_power as i32;
+    tx_info.timing = Some(gw::Timing {
+        parameters: Some(gw::timing::Parameters::GpsEpoch(gw::GpsEpochTimingInfo {
+            time_since_gps_epoch: Some(pbjson_types::Duration {
+                seconds: gps_epoch.as_secs() as i64,
+                nanos: gps_epoch.subsec_nanos() as i32,
+            }),
+        })),
+    });
+
+    // Set the parameters which are not part of the TX info, such that downlink_from_proto
+    // returns the same packet.
+    match tx_info
+        .modulation
+        .as_mut()
+        .and_then(|v| v.parameters.as_mut())
+    {
+        Some(gw::modulation::Parameters::Lora(v)) => {
+            v.polarization_inversion = packet.invert_pol;
+        }
+        Some(gw::modulation::Parameters::Fsk(_)) => {}
+        _ => return Err(anyhow!("unexpected modulation")),
+    }
+
+    Ok(gw::DownlinkFrameItem {
+        phy_payload: packet.payload[..packet.size as usize].to_vec(),
+        tx_info: Some(tx_info),
+        ..Default::default()
+    })
+}
+
+/// Returns the pending downlink and its GPS epoch time, from a downlink item returned by
+/// pending_to_proto. The concentrator counter is set once the downlink is re-scheduled, as
+/// this requires a valid GPS time reference.
+pub fn pending_from_proto(
+    downlink_id: u32,
+    df: &gw::DownlinkFrameItem,
+) -> Result<(TxPacket, Duration)> {
+    let gps_epoch =
+        gps_epoch_from_proto(df).ok_or_else(|| anyhow!("gps epoch timing is missing"))?;
+
+    let mut df = df.clone();
+    if let Some(tx_info) = df.tx_info.as_mut() {
+        tx_info.timing = None;
+    }
+    let mut tx_packet = downlink_from_proto(&df)?;
+    tx_packet.tx_mode = hal::TxMode::Timestamped;
+
+    Ok((
+        TxPacket::new(downlink_id, tx_packet).with_gps_epoch(Some(gps_epoch)),
+        gps_epoch,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_timestamp_debug() {
+        let rx_info = gw::UplinkRxInfo {
+            time: Some(pbjson_types::Timestamp {
+                seconds: 1_600_000_000,
+                nanos: 5_000,
+            }),
+            ..Default::default()
+        };
+
+        let metadata = get_timestamp_debug(1_000, &rx_info);
+        assert_eq!(Some(&"1000".to_string()), metadata.get("ts_count_us"));
+        assert_eq!(
+            Some(&"1600000000.000005000".to_string()),
+            metadata.get("ts_time")
+        );
+        assert_eq!(None, metadata.get("ts_gps_epoch"));
+
+        // there is no time reference yet
+        assert_eq!(Some(&"false".to_string()), metadata.get("ts_ref_valid"));
+        assert_eq!(None, metadata.get("ts_ref_count_us"));
+    }
+
+    #[test]
+    fn test_get_fine_time_since_gps_epoch() {
+        assert_eq!(
+            Some(Duration::new(100, 250_000_000)),
+            get_fine_time_since_gps_epoch(Duration::new(100, 250_010_000), 250_000_000)
+        );
+
+        // the gps epoch of the uplink already passed the next pps
+        assert_eq!(
+            Some(Duration::new(100, 999_999_000)),
+            get_fine_time_since_gps_epoch(Duration::new(101, 5_000), 999_999_000)
+        );
+
+        // the gps epoch of the uplink is slightly before the fine timestamp
+        assert_eq!(
+            Some(Duration::new(100, 1_000)),
+            get_fine_time_since_gps_epoch(Duration::new(99, 999_999_000), 1_000)
+        );
+
+        assert_eq!(
+            None,
+            get_fine_time_since_gps_epoch(Duration::new(100, 0), 1_000_000_000)
+        );
+    }
+}
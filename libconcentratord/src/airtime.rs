@@ -0,0 +1,217 @@
+//! Time on air calculation of the LoRa and FSK frames, see the Semtech SX1276 (sub-GHz) and
+//! SX1280 (2.4 GHz) datasheets.
+use std::time::Duration;
+
+use chirpstack_api::gw;
+
+// Number of preamble symbols of a LoRaWAN frame. At 2.4 GHz, SF5 and SF6 use 12 symbols.
+pub const LORA_PREAMBLE_SYMBOLS: u32 = 8;
+const LORA_2G4_SF5_SF6_PREAMBLE_SYMBOLS: u32 = 12;
+
+// Number of preamble, sync word, length and CRC bytes of a LoRaWAN FSK frame.
+const FSK_OVERHEAD_BYTES: usize = 5 + 3 + 1 + 2;
+
+// Frames above this frequency are sent using the 2.4 GHz (SX1280) modulation.
+const ISM2400_MIN_FREQ: u32 = 2_400_000_000;
+
+/// Returns the time on air of a sub-GHz LoRa frame with explicit header. The code_rate is the
+/// denominator offset (1 = 4/5 ... 4 = 4/8). LoRaWAN uplinks have a payload CRC, downlinks do
+/// not.
+pub fn lora(
+    payload_size: usize,
+    bandwidth: u32,
+    spreading_factor: u32,
+    code_rate: u32,
+    preamble: u32,
+    crc: bool,
+) -> Duration {
+    let sf = spreading_factor as f64;
+    let symbol_time_us = 2f64.powf(sf) * 1_000_000.0 / bandwidth as f64;
+
+    // The low data-rate optimization is enabled when the symbol time exceeds 16 ms.
+    let de = if symbol_time_us >= 16000.0 { 1.0 } else { 0.0 };
+    let crc = if crc { 1.0 } else { 0.0 };
+
+    let payload_symbols = 8.0
+        + (((8.0 * payload_size as f64 - 4.0 * sf + 28.0 + 16.0 * crc) / (4.0 * (sf - 2.0 * de)))
+            .ceil()
+            * (code_rate as f64 + 4.0))
+            .max(0.0);
+
+    Duration::from_nanos(
+        ((preamble as f64 + 4.25 + payload_symbols) * symbol_time_us * 1000.0).round() as u64,
+    )
+}
+
+/// Returns the time on air of a 2.4 GHz LoRa frame with explicit header. The code_rate is the
+/// denominator offset (1 = 4/5 ... 4 = 4/8). With long interleaving, the time on air is
+/// approximated by the one of the equivalent code rate.
+pub fn lora_2g4(
+    payload_size: usize,
+    bandwidth: u32,
+    spreading_factor: u32,
+    code_rate: u32,
+    preamble: u32,
+    crc: bool,
+) -> Duration {
+    let sf = spreading_factor as f64;
+    let symbol_time_us = 2f64.powf(sf) * 1_000_000.0 / bandwidth as f64;
+    let crc = if crc { 16.0 } else { 0.0 };
+
+    // Explicit header.
+    let header = 20.0;
+
+    let (fixed_symbols, bits, bits_per_block) = match spreading_factor {
+        5 | 6 => (
+            6.25,
+            8.0 * payload_size as f64 + crc - 4.0 * sf + header,
+            4.0 * sf,
+        ),
+        7..=10 => (
+            4.25,
+            8.0 * payload_size as f64 + crc - 4.0 * sf + 8.0 + header,
+            4.0 * sf,
+        ),
+        _ => (
+            4.25,
+            8.0 * payload_size as f64 + crc - 4.0 * sf + 8.0 + header,
+            4.0 * (sf - 2.0),
+        ),
+    };
+
+    let payload_symbols = 8.0 + (bits.max(0.0) / bits_per_block).ceil() * (code_rate as f64 + 4.0);
+
+    Duration::from_nanos(
+        ((preamble as f64 + fixed_symbols + payload_symbols) * symbol_time_us * 1000.0).round()
+            as u64,
+    )
+}
+
+/// Returns the time on air of a LoRaWAN FSK frame, given the datarate (bit/s).
+pub fn fsk(payload_size: usize, datarate: u32) -> Duration {
+    Duration::from_nanos(
+        ((payload_size + FSK_OVERHEAD_BYTES) as u64 * 8 * 1_000_000_000) / datarate as u64,
+    )
+}
+
+/// Returns the time on air of the given uplink. Nothing is returned for LR-FHSS or when the
+/// modulation parameters are missing or invalid.
+pub fn uplink(pl: &gw::UplinkFrame) -> Option<Duration> {
+    let tx_info = pl.tx_info.as_ref()?;
+    get(
+        tx_info.frequency,
+        tx_info.modulation.as_ref()?,
+        pl.phy_payload.len(),
+        true,
+    )
+}
+
+/// Returns the time on air of the given downlink item. Nothing is returned for LR-FHSS or when
+/// the modulation parameters are missing or invalid.
+pub fn downlink(item: &gw::DownlinkFrameItem) -> Option<Duration> {
+    let tx_info = item.tx_info.as_ref()?;
+    get(
+        tx_info.frequency,
+        tx_info.modulation.as_ref()?,
+        item.phy_payload.len(),
+        false,
+    )
+}
+
+/// Adds the time on air (us) of the uplink to the uplink meta-data (airtime_us).
+pub fn annotate(pl: &mut gw::UplinkFrame) {
+    let time_on_air = match uplink(pl) {
+        Some(v) => v,
+        None => return,
+    };
+
+    if let Some(rx_info) = pl.rx_info.as_mut() {
+        rx_info.metadata.insert(
+            "airtime_us".to_string(),
+            time_on_air.as_micros().to_string(),
+        );
+    }
+}
+
+fn get(
+    frequency: u32,
+    modulation: &gw::Modulation,
+    payload_size: usize,
+    crc: bool,
+) -> Option<Duration> {
+    match modulation.parameters.as_ref()? {
+        gw::modulation::Parameters::Lora(v) => {
+            let code_rate = get_code_rate(v.code_rate())?;
+            if v.bandwidth == 0 || !(5..=12).contains(&v.spreading_factor) {
+                return None;
+            }
+
+            if frequency >= ISM2400_MIN_FREQ {
+                let preamble = match v.spreading_factor {
+                    5 | 6 => LORA_2G4_SF5_SF6_PREAMBLE_SYMBOLS,
+                    _ => LORA_PREAMBLE_SYMBOLS,
+                };
+
+                Some(lora_2g4(
+                    payload_size,
+                    v.bandwidth,
+                    v.spreading_factor,
+                    code_rate,
+                    preamble,
+                    crc,
+                ))
+            } else {
+                Some(lora(
+                    payload_size,
+                    v.bandwidth,
+                    v.spreading_factor,
+                    code_rate,
+                    LORA_PREAMBLE_SYMBOLS,
+                    crc,
+                ))
+            }
+        }
+        gw::modulation::Parameters::Fsk(v) => {
+            if v.datarate == 0 {
+                return None;
+            }
+            Some(fsk(payload_size, v.datarate))
+        }
+        gw::modulation::Parameters::LrFhss(_) => None,
+    }
+}
+
+// Returns the denominator offset of the LoRa code rate.
+fn get_code_rate(code_rate: gw::CodeRate) -> Option<u32> {
+    match code_rate {
+        gw::CodeRate::Cr45 | gw::CodeRate::CrLi45 => Some(1),
+        gw::CodeRate::Cr46 | gw::CodeRate::CrLi46 => Some(2),
+        gw::CodeRate::Cr47 => Some(3),
+        gw::CodeRate::Cr48 | gw::CodeRate::CrLi48 => Some(4),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_on_air() {
+        assert_eq!(
+            Duration::from_micros(46336),
+            lora(13, 125000, 7, 1, 8, true)
+        );
+        assert_eq!(
+            Duration::from_micros(1155072),
+            lora(13, 125000, 12, 1, 8, true)
+        );
+        assert_eq!(Duration::from_micros(3840), fsk(13, 50000));
+
+        // SF12, 812.5 kHz, 4/5, 8 preamble symbols, 10 bytes, CRC
+        assert_eq!(
+            Duration::from_nanos(152_497_231),
+            lora_2g4(10, 812500, 12, 1, 8, true)
+        );
+    }
+}
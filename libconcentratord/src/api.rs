@@ -0,0 +1,629 @@
+// Concentratord specific API messages, which are not part of the ChirpStack gateway API.
+// These are defined using the prost derive macros so that they are encoded as Protobuf, like
+// all the other messages exposed over the ZeroMQ API.
+
+/// TX done event.
+///
+/// Published once a downlink has been handed over to the concentrator for transmission.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TxDone {
+    /// Gateway ID (HEX encoded).
+    #[prost(string, tag = "1")]
+    pub gateway_id: String,
+    /// Downlink ID.
+    #[prost(uint32, tag = "2")]
+    pub downlink_id: u32,
+    /// TX frequency (Hz).
+    #[prost(uint32, tag = "3")]
+    pub frequency: u32,
+    /// Applied TX power (EIRP in dBm), after TX gain table quantization.
+    #[prost(int32, tag = "4")]
+    pub power: i32,
+    /// Concentrator counter at which the downlink is transmitted.
+    #[prost(uint32, tag = "5")]
+    pub count_us: u32,
+    /// Estimated timing error (us) of a GPS epoch timed downlink that was scheduled using the
+    /// last known GPS time reference, as the GPS was unlocked. Else this is 0.
+    #[prost(uint32, tag = "6")]
+    pub gps_timing_error_us: u32,
+}
+
+/// Capabilities response.
+///
+/// Returned on the capabilities command, so that subscribers can detect how events are
+/// published.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Capabilities {
+    /// Compression applied to the event payloads (NONE or ZSTD).
+    #[prost(string, tag = "1")]
+    pub event_compression: String,
+    /// Compression algorithms supported by this concentratord version.
+    #[prost(string, repeated, tag = "2")]
+    pub supported_event_compression: Vec<String>,
+    /// Events are published with a sequence number frame.
+    #[prost(bool, tag = "3")]
+    pub event_sequence: bool,
+    /// Encryption applied to the event payloads (NONE or AES256_GCM).
+    #[prost(string, tag = "4")]
+    pub event_encryption: String,
+}
+
+/// Beacon payload override.
+///
+/// Sent by the network server using the beacon command. Sending an empty message
+/// removes the override and restores the locally computed beacon.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct BeaconPayload {
+    /// Gateway specific field (InfoDesc + Info, 7 bytes). When set, it is appended
+    /// (followed by the RFU and CRC) to the locally computed beacon.
+    #[prost(bytes = "vec", tag = "1")]
+    pub gw_specific: Vec<u8>,
+    /// Custom beacon payload. When set, it is used as-is instead of the locally computed
+    /// beacon.
+    #[prost(bytes = "vec", tag = "2")]
+    pub payload: Vec<u8>,
+}
+
+/// Remote configuration bundle.
+///
+/// Fetched from the remote configuration endpoint, wrapped in a SignedConfigBundle.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ConfigBundle {
+    /// Bundle version. The bundle is only applied when the version differs from the
+    /// currently applied version.
+    #[prost(string, tag = "1")]
+    pub version: String,
+    /// Log level (e.g. INFO, DEBUG). When empty, the log level is not changed.
+    #[prost(string, tag = "2")]
+    pub log_level: String,
+    /// Channel plan. When not set, the channel plan is not changed.
+    #[prost(message, optional, tag = "3")]
+    pub configuration: Option<chirpstack_api::gw::GatewayConfiguration>,
+}
+
+/// Signed remote configuration bundle.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SignedConfigBundle {
+    /// Protobuf encoded ConfigBundle.
+    #[prost(bytes = "vec", tag = "1")]
+    pub bundle: Vec<u8>,
+    /// Ed25519 signature of the bundle bytes.
+    #[prost(bytes = "vec", tag = "2")]
+    pub signature: Vec<u8>,
+}
+
+/// State event.
+///
+/// Published on every Concentratord state transition.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StateEvent {
+    /// Gateway ID (HEX encoded). This is empty until the gateway ID has been read from the
+    /// concentrator.
+    #[prost(string, tag = "1")]
+    pub gateway_id: String,
+    /// State (INITIALIZING, CALIBRATING, RUNNING, DEGRADED or RESETTING).
+    #[prost(string, tag = "2")]
+    pub state: String,
+    /// Previous state.
+    #[prost(string, tag = "3")]
+    pub previous_state: String,
+    /// Reason of the state transition.
+    #[prost(string, tag = "4")]
+    pub reason: String,
+}
+
+/// Error event.
+///
+/// Published when the concentrator initialization failed, right before Concentratord exits.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ErrorEvent {
+    /// Gateway ID (HEX encoded). This is empty when the gateway ID has not yet been read from
+    /// the concentrator.
+    #[prost(string, tag = "1")]
+    pub gateway_id: String,
+    /// Reason (RESET_FAILED, CONFIGURATION_FAILED, START_FAILED, GATEWAY_ID_FAILED or
+    /// GATEWAY_ID_MISMATCH).
+    #[prost(string, tag = "2")]
+    pub reason: String,
+    /// Exit code of the process.
+    #[prost(int32, tag = "3")]
+    pub exit_code: i32,
+    /// Error message.
+    #[prost(string, tag = "4")]
+    pub error: String,
+}
+
+/// Restart event.
+///
+/// Published once the concentrator has been restarted after a failed health check.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RestartEvent {
+    /// Gateway ID (HEX encoded).
+    #[prost(string, tag = "1")]
+    pub gateway_id: String,
+    /// Trigger of the restart (HAL_ERRORS, RX_TIMEOUT or COUNTER_STALL).
+    #[prost(string, tag = "2")]
+    pub trigger: String,
+    /// Description of the detected failure.
+    #[prost(string, tag = "3")]
+    pub reason: String,
+    /// Total number of health triggered restarts since Concentratord was started.
+    #[prost(uint64, tag = "4")]
+    pub restart_count: u64,
+}
+
+/// Time-sync request event.
+///
+/// Periodically published when the network server assisted time-sync is enabled. The network
+/// server answers this event using the timesync command.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TimeSyncRequest {
+    /// Gateway ID (HEX encoded).
+    #[prost(string, tag = "1")]
+    pub gateway_id: String,
+    /// Request ID, to be returned in the answer.
+    #[prost(uint32, tag = "2")]
+    pub request_id: u32,
+    /// Concentrator counter at which the request was created.
+    #[prost(uint32, tag = "3")]
+    pub count_us: u32,
+    /// Host system time at which the request was created.
+    #[prost(message, optional, tag = "4")]
+    pub host_time: Option<pbjson_types::Timestamp>,
+}
+
+/// Time-sync answer.
+///
+/// Sent by the network server using the timesync command, in response to the last time-sync
+/// request event.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TimeSyncAnswer {
+    /// Request ID of the answered request.
+    #[prost(uint32, tag = "1")]
+    pub request_id: u32,
+    /// Network server time at which the request was received.
+    #[prost(message, optional, tag = "2")]
+    pub receive_time: Option<pbjson_types::Timestamp>,
+    /// Network server time at which the answer was sent.
+    #[prost(message, optional, tag = "3")]
+    pub transmit_time: Option<pbjson_types::Timestamp>,
+}
+
+/// TX test request.
+///
+/// Starts a maintenance TX test, emitting short test transmissions across all the configured
+/// channels (e.g. for VSWR / power measurements), or a certification test, transmitting a
+/// continuous-wave (CW) or modulated carrier on a fixed frequency. During a certification test,
+/// the normal uplink and downlink handling is suspended.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TxTestRequest {
+    /// TX power (EIRP) in dBm.
+    #[prost(int32, tag = "1")]
+    pub tx_power: i32,
+    /// Number of transmissions per channel (SWEEP only).
+    #[prost(uint32, tag = "2")]
+    pub repetitions: u32,
+    /// Interval between two transmissions (milliseconds, SWEEP only).
+    #[prost(uint32, tag = "3")]
+    pub interval_ms: u32,
+    /// Payload size of the test transmissions (bytes, SWEEP and MODULATED only).
+    #[prost(uint32, tag = "4")]
+    pub payload_size: u32,
+    /// Test mode (SWEEP, CW or MODULATED). When empty, SWEEP is used.
+    #[prost(string, tag = "5")]
+    pub mode: String,
+    /// Frequency (Hz) of the certification test (CW and MODULATED only).
+    #[prost(uint32, tag = "6")]
+    pub frequency: u32,
+    /// Duration of the certification test in milliseconds (0 = until stopped).
+    #[prost(uint32, tag = "7")]
+    pub duration_ms: u32,
+    /// Stop the running certification test. All the other fields are ignored.
+    #[prost(bool, tag = "8")]
+    pub stop: bool,
+}
+
+/// TX test progress event.
+///
+/// Published after each test transmission.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TxTestProgress {
+    /// Gateway ID (HEX encoded).
+    #[prost(string, tag = "1")]
+    pub gateway_id: String,
+    /// Frequency (Hz) of the test transmission.
+    #[prost(uint32, tag = "2")]
+    pub frequency: u32,
+    /// Number of test transmissions done.
+    #[prost(uint32, tag = "3")]
+    pub sent: u32,
+    /// Total number of test transmissions.
+    #[prost(uint32, tag = "4")]
+    pub total: u32,
+    /// Error message, in case the test transmission could not be enqueued.
+    #[prost(string, tag = "5")]
+    pub error: String,
+}
+
+/// Log event.
+///
+/// Published for each log record up to the configured log event level.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct LogEvent {
+    /// Gateway ID (HEX encoded). This is empty until the gateway ID has been read from the
+    /// concentrator.
+    #[prost(string, tag = "1")]
+    pub gateway_id: String,
+    /// Log level (ERROR, WARN, INFO, DEBUG or TRACE).
+    #[prost(string, tag = "2")]
+    pub level: String,
+    /// Target (module path) of the log record.
+    #[prost(string, tag = "3")]
+    pub target: String,
+    /// Log message.
+    #[prost(string, tag = "4")]
+    pub message: String,
+}
+
+/// Live frame tap request.
+///
+/// Starts a bounded live tap, publishing every received frame (including the frames with an
+/// invalid CRC) as tap event, regardless of the uplink event mode and sampling. The tap ends
+/// after max_frames frames or duration_ms milliseconds, whichever comes first. A new request
+/// replaces the running tap.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TapRequest {
+    /// Max. number of frames (0 = not limited, duration_ms must be set).
+    #[prost(uint32, tag = "1")]
+    pub max_frames: u32,
+    /// Duration of the tap in milliseconds (0 = max. duration).
+    #[prost(uint32, tag = "2")]
+    pub duration_ms: u32,
+}
+
+/// Status response.
+///
+/// Returned on the status command: a snapshot of the Concentratord status, so that dashboards
+/// can retrieve it in a single round-trip.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Status {
+    /// Gateway ID (HEX encoded).
+    #[prost(string, tag = "1")]
+    pub gateway_id: String,
+    /// Time since Concentratord was started (seconds).
+    #[prost(uint64, tag = "2")]
+    pub uptime_secs: u64,
+    /// State (INITIALIZING, CALIBRATING, RUNNING, DEGRADED or RESETTING).
+    #[prost(string, tag = "3")]
+    pub state: String,
+    /// Time of the last received uplink.
+    #[prost(message, optional, tag = "4")]
+    pub last_uplink: Option<pbjson_types::Timestamp>,
+    /// GNSS status, not set when the gateway does not have a GNSS receiver.
+    #[prost(message, optional, tag = "5")]
+    pub gnss: Option<GnssStatus>,
+    /// Number of downlinks in the JIT queue.
+    #[prost(uint32, tag = "6")]
+    pub queue_depth: u32,
+    /// Capacity of the JIT queue.
+    #[prost(uint32, tag = "7")]
+    pub queue_capacity: u32,
+    /// Concentrator temperature (C) of the last stats interval, not set when this is not
+    /// available.
+    #[prost(float, optional, tag = "8")]
+    pub temperature: Option<f32>,
+    /// Last (up to 5) errors, oldest first.
+    #[prost(message, repeated, tag = "9")]
+    pub errors: Vec<StatusError>,
+    /// Reset status.
+    #[prost(message, optional, tag = "10")]
+    pub reset: Option<ResetStatus>,
+}
+
+/// GNSS status.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GnssStatus {
+    /// The GNSS time reference is valid.
+    #[prost(bool, tag = "1")]
+    pub time_valid: bool,
+    /// Location of the GNSS fix, not set when there is no fix.
+    #[prost(message, optional, tag = "2")]
+    pub location: Option<chirpstack_api::common::Location>,
+}
+
+/// Reset status.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ResetStatus {
+    /// Configured reset and power enable pins.
+    #[prost(message, repeated, tag = "1")]
+    pub pins: Vec<ResetPin>,
+    /// Number of resets since Concentratord was started.
+    #[prost(uint64, tag = "2")]
+    pub count: u64,
+    /// Time of the last reset, not set when the concentrator has not been reset.
+    #[prost(message, optional, tag = "3")]
+    pub last_reset: Option<pbjson_types::Timestamp>,
+    /// Reason of the last reset (STARTUP, CONFIGURATION or RECOVERY).
+    #[prost(string, tag = "4")]
+    pub last_reason: String,
+}
+
+/// Reset or power enable pin.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ResetPin {
+    /// Pin (SX130X_RESET, SX1302_POWER_EN, SX1261_RESET or AD5338R_RESET).
+    #[prost(string, tag = "1")]
+    pub pin: String,
+    /// GPIO chip or I2C GPIO expander.
+    #[prost(string, tag = "2")]
+    pub chip: String,
+    /// Offset of the line.
+    #[prost(uint32, tag = "3")]
+    pub offset: u32,
+    /// The line is only requested while it is driven, see gateway.gpio.shared.
+    #[prost(bool, tag = "4")]
+    pub shared: bool,
+}
+
+/// Error, as logged by Concentratord.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StatusError {
+    /// Time of the error.
+    #[prost(message, optional, tag = "1")]
+    pub time: Option<pbjson_types::Timestamp>,
+    /// Target (module path) of the log record.
+    #[prost(string, tag = "2")]
+    pub target: String,
+    /// Error message.
+    #[prost(string, tag = "3")]
+    pub message: String,
+}
+
+/// Synchronized TX request.
+///
+/// Sent using the sync_tx command, to transmit the same frame at the same GPS time from
+/// multiple gateways (e.g. a synchronized multicast or wake-up broadcast). All the downlink
+/// items must use GPS epoch timing. Using arm_only, the downlink is validated against the
+/// GPS time reference and the JIT queue without enqueueing it (pre-arm), so that the achievable
+/// accuracy of all the gateways can be confirmed before the broadcast is committed.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SyncTxRequest {
+    /// Downlink frame.
+    #[prost(message, optional, tag = "1")]
+    pub downlink: Option<chirpstack_api::gw::DownlinkFrame>,
+    /// Max. timing error (us). Downlink items that can not be transmitted within this
+    /// accuracy are rejected with GPS_UNLOCKED.
+    #[prost(uint32, tag = "2")]
+    pub max_error_us: u32,
+    /// Only validate the downlink, without enqueueing it.
+    #[prost(bool, tag = "3")]
+    pub arm_only: bool,
+}
+
+/// Synchronized TX response.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SyncTxResponse {
+    /// TX acknowledgement, containing the status per downlink item. On arm_only, OK means that
+    /// the item can be scheduled.
+    #[prost(message, optional, tag = "1")]
+    pub tx_ack: Option<chirpstack_api::gw::DownlinkTxAck>,
+    /// Achievable accuracy (us) of the accepted downlink item, 0 when no item was accepted.
+    #[prost(uint32, tag = "2")]
+    pub accuracy_us: u32,
+    /// Rejection details of the rejected downlink items.
+    #[prost(message, optional, tag = "3")]
+    pub details: Option<DownlinkTxAckDetails>,
+}
+
+/// Spectral scan request.
+///
+/// Runs a spectral scan using the SX1261 radio, from freq_start up to and including freq_stop
+/// in steps of freq_step.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SpectralScanRequest {
+    /// Frequency (Hz) of the first channel.
+    #[prost(uint32, tag = "1")]
+    pub freq_start: u32,
+    /// Frequency (Hz) of the last channel.
+    #[prost(uint32, tag = "2")]
+    pub freq_stop: u32,
+    /// Step (Hz) between two channels.
+    #[prost(uint32, tag = "3")]
+    pub freq_step: u32,
+    /// Number of RSSI samples per channel.
+    #[prost(uint32, tag = "4")]
+    pub nb_scan: u32,
+}
+
+/// Spectral scan result.
+///
+/// Returned on the spectral_scan command and published as spectral_scan event by the periodic
+/// spectral scan.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SpectralScan {
+    /// Gateway ID (HEX encoded).
+    #[prost(string, tag = "1")]
+    pub gateway_id: String,
+    /// RSSI histogram per channel.
+    #[prost(message, repeated, tag = "2")]
+    pub channels: Vec<SpectralScanChannel>,
+}
+
+/// Spectral scan channel result.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SpectralScanChannel {
+    /// Channel frequency (Hz).
+    #[prost(uint32, tag = "1")]
+    pub frequency: u32,
+    /// RSSI histogram, ordered by RSSI level.
+    #[prost(message, repeated, tag = "2")]
+    pub levels: Vec<SpectralScanLevel>,
+}
+
+/// Spectral scan RSSI histogram bin.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SpectralScanLevel {
+    /// RSSI level (dBm).
+    #[prost(int32, tag = "1")]
+    pub rssi: i32,
+    /// Number of samples at this RSSI level.
+    #[prost(uint32, tag = "2")]
+    pub count: u32,
+}
+
+/// Blacklist response.
+///
+/// Returned on the blacklist command: the DevAddrs which repeatedly sent malformed uplinks, see
+/// concentratord.blacklist.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Blacklist {
+    /// Gateway ID (HEX encoded).
+    #[prost(string, tag = "1")]
+    pub gateway_id: String,
+    /// Blacklisted DevAddrs, ordered by DevAddr.
+    #[prost(message, repeated, tag = "2")]
+    pub entries: Vec<BlacklistEntry>,
+}
+
+/// Blacklisted DevAddr.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct BlacklistEntry {
+    /// DevAddr (HEX encoded).
+    #[prost(string, tag = "1")]
+    pub dev_addr: String,
+    /// Reason of the last malformed uplink (HEADER, LENGTH, FOPTS or FCNT_JUMP).
+    #[prost(string, tag = "2")]
+    pub reason: String,
+    /// Total number of malformed uplinks.
+    #[prost(uint64, tag = "3")]
+    pub malformed: u64,
+    /// Time at which the DevAddr was blacklisted.
+    #[prost(message, optional, tag = "4")]
+    pub since: Option<pbjson_types::Timestamp>,
+    /// Time at which the DevAddr is removed from the blacklist, unless it sends an other
+    /// malformed uplink.
+    #[prost(message, optional, tag = "5")]
+    pub expires: Option<pbjson_types::Timestamp>,
+}
+
+/// Downlink history request.
+///
+/// Returns the recent downlink scheduling decisions, e.g. to find out why a downlink was
+/// rejected after the fact.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DownlinkHistoryRequest {
+    /// Only return the entries of this downlink ID, 0 for all downlinks.
+    #[prost(uint32, tag = "1")]
+    pub downlink_id: u32,
+    /// Max. number of (most recent) entries to return, 0 for all entries.
+    #[prost(uint32, tag = "2")]
+    pub limit: u32,
+}
+
+/// Downlink history response.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DownlinkHistory {
+    /// Gateway ID (HEX encoded).
+    #[prost(string, tag = "1")]
+    pub gateway_id: String,
+    /// History entries, ordered from oldest to most recent.
+    #[prost(message, repeated, tag = "2")]
+    pub entries: Vec<DownlinkHistoryEntry>,
+}
+
+/// Downlink scheduling decision.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DownlinkHistoryEntry {
+    /// Time of the decision.
+    #[prost(message, optional, tag = "1")]
+    pub time: Option<pbjson_types::Timestamp>,
+    /// Downlink ID.
+    #[prost(uint32, tag = "2")]
+    pub downlink_id: u32,
+    /// Index of the downlink item.
+    #[prost(uint32, tag = "3")]
+    pub item: u32,
+    /// Event (ENQUEUE, TX_DONE, TX_FAILED or DROPPED).
+    #[prost(string, tag = "4")]
+    pub event: String,
+    /// Acknowledgement status of the ENQUEUE event.
+    #[prost(enumeration = "chirpstack_api::gw::TxAckStatus", tag = "5")]
+    pub status: i32,
+    /// TX frequency (Hz).
+    #[prost(uint32, tag = "6")]
+    pub frequency: u32,
+    /// Concentrator counter at which the downlink is (or was) transmitted.
+    #[prost(uint32, tag = "7")]
+    pub count_us: u32,
+    /// Error message of the TX_FAILED event.
+    #[prost(string, tag = "8")]
+    pub error: String,
+}
+
+/// Downlink TX acknowledgement rejection details.
+///
+/// These are appended to the DownlinkTxAck returned on the downlink command, using a field
+/// number that is not used by the DownlinkTxAck. Decoders which are not aware of this message
+/// ignore the details, decoding the same response using this message only returns the
+/// details.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DownlinkTxAckDetails {
+    /// Rejection details of the rejected downlink items.
+    #[prost(message, repeated, tag = "100")]
+    pub rejections: Vec<DownlinkRejection>,
+}
+
+/// Rejection details of a downlink item.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DownlinkRejection {
+    /// Index of the downlink item.
+    #[prost(uint32, tag = "1")]
+    pub item: u32,
+    /// Reason (QUEUE_FULL, INTERNAL_ERROR, COLLISION, TOO_LATE, TOO_EARLY, DUTY_CYCLE,
+    /// LBT_CHANNEL or PAYLOAD_SIZE).
+    #[prost(string, tag = "2")]
+    pub reason: String,
+    /// Downlink ID of the queued downlink the item collides with. This is 0 when the item
+    /// collides with the downlink that is being transmitted.
+    #[prost(uint32, tag = "3")]
+    pub conflict_downlink_id: u32,
+    /// Time (us) by which the item is scheduled too late (TOO_LATE) or too early (TOO_EARLY).
+    #[prost(uint32, tag = "4")]
+    pub offset_us: u32,
+    /// Duty-cycle band of which the budget is exhausted (DUTY_CYCLE).
+    #[prost(string, tag = "5")]
+    pub band: String,
+    /// Remaining duty-cycle budget (ms) of the band (DUTY_CYCLE).
+    #[prost(uint32, tag = "6")]
+    pub remaining_ms: u32,
+    /// Max. PHYPayload size (bytes) of the data-rate of the item (PAYLOAD_SIZE).
+    #[prost(uint32, tag = "7")]
+    pub max_payload_size: u32,
+}
+
+/// Diagnostic bundle request.
+///
+/// Raises the log level for duration_ms milliseconds, capturing the log records and the
+/// received frames. At the end, the captured data is written together with a dump of the
+/// configuration, status, JIT queue and downlink history as a single (zstd compressed tar)
+/// archive. Only one bundle is captured at a time.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DiagBundleRequest {
+    /// Duration of the capture in milliseconds (0 = default duration).
+    #[prost(uint32, tag = "1")]
+    pub duration_ms: u32,
+    /// Log level during the capture (ERROR, WARN, INFO, DEBUG or TRACE, default DEBUG).
+    #[prost(string, tag = "2")]
+    pub log_level: String,
+}
+
+/// Diagnostic bundle response.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DiagBundleResponse {
+    /// Path of the archive, this is written once the capture ends.
+    #[prost(string, tag = "1")]
+    pub path: String,
+    /// Duration of the capture in milliseconds.
+    #[prost(uint32, tag = "2")]
+    pub duration_ms: u32,
+}
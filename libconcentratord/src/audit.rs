@@ -0,0 +1,69 @@
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use anyhow::Result;
+use log::{error, info};
+
+use super::rotate::RotatingFile;
+use super::timesource;
+
+lazy_static! {
+    static ref AUDIT_LOG: Mutex<Option<RotatingFile>> = Mutex::new(None);
+}
+
+#[derive(Clone)]
+pub struct Configuration {
+    // Path of the audit log file.
+    pub path: String,
+    // Max. size (bytes) of the audit log file before it is rotated. When 0, the file is not
+    // rotated.
+    pub max_size: u64,
+    // Number of rotated files to keep (path.1 is the most recent one).
+    pub max_files: usize,
+}
+
+/// Setup the audit log. Once setup, every call to log is written to the audit log file.
+pub fn setup(conf: &Configuration) -> Result<()> {
+    info!(
+        "Setting up audit log, path: {}, max_size: {}, max_files: {}",
+        conf.path, conf.max_size, conf.max_files
+    );
+
+    let mut audit_log = AUDIT_LOG.lock().unwrap();
+    *audit_log = Some(RotatingFile::open(
+        &conf.path,
+        conf.max_size,
+        conf.max_files,
+    )?);
+
+    Ok(())
+}
+
+/// Write the received command, the identity of the requester and the outcome to the audit log.
+/// The identity is only known when CURVE is enabled (see commands::Reader::user_id). This is a
+/// no-op when the audit log has not been setup.
+pub fn log(command: &str, identity: Option<&str>, outcome: &str) {
+    let mut audit_log = AUDIT_LOG.lock().unwrap();
+    let audit_log = match audit_log.as_mut() {
+        Some(v) => v,
+        None => return,
+    };
+
+    let now = timesource::now().duration_since(UNIX_EPOCH).unwrap();
+    let identity = match identity {
+        Some(v) => format!(" identity=\"{}\"", v.escape_default()),
+        None => String::new(),
+    };
+    let line = format!(
+        "time={}.{:03} command=\"{}\"{} outcome={}\n",
+        now.as_secs(),
+        now.subsec_millis(),
+        command.escape_default(),
+        identity,
+        outcome
+    );
+
+    if let Err(err) = audit_log.write(line.as_bytes()) {
+        error!("Write audit log error, error: {}", err);
+    }
+}
@@ -0,0 +1,45 @@
+//! Runs a deterministic simulation of the JIT queue and prints the report.
+//!
+//! Usage: jitqueue-simulator [SEED] [DURATION_SECS] [START_COUNT_US] [STRATEGY]
+use std::env;
+use std::process;
+use std::str::FromStr;
+use std::time::Duration;
+
+use libconcentratord::{jitqueue, jitsim};
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut conf = jitsim::Configuration::default();
+
+    if let Some(v) = args.first() {
+        conf.seed = v.parse().expect("invalid seed");
+    }
+    if let Some(v) = args.get(1) {
+        conf.duration = Duration::from_secs(v.parse().expect("invalid duration"));
+    }
+    if let Some(v) = args.get(2) {
+        conf.start_count_us = v.parse().expect("invalid start count");
+    }
+    if let Some(v) = args.get(3) {
+        conf.strategy = jitqueue::Strategy::from_str(v).expect("invalid strategy");
+    }
+
+    let report = jitsim::run(&conf);
+
+    println!("seed: {}", conf.seed);
+    println!("strategy: {}", conf.strategy);
+    println!("enqueued: {}", report.enqueued);
+    for (status, count) in &report.rejected {
+        println!("rejected ({}): {}", status, count);
+    }
+    println!("transmitted: {}", report.transmitted);
+    println!("dropped: {}", report.dropped);
+    for v in &report.violations {
+        println!("violation: {}", v);
+    }
+
+    if !report.violations.is_empty() || report.dropped != 0 {
+        process::exit(1);
+    }
+}
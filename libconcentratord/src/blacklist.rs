@@ -0,0 +1,517 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use log::{info, warn};
+
+use super::{api, stats, timesource};
+
+// MHDR (1 byte) + DevAddr (4 bytes).
+const DEV_ADDR_END: usize = 5;
+
+// MHDR (1 byte) + FHDR without FOpts (7 bytes) + MIC (4 bytes).
+const MIN_DATA_SIZE: usize = 12;
+
+// Max. number of tracked DevAddrs, before the DevAddrs without recent uplinks are removed.
+const MAX_ENTRIES: usize = 4096;
+
+// Number of DevAddrs which are evicted when the max. number of tracked DevAddrs is reached
+// and none of these has expired.
+const EVICT_ENTRIES: usize = MAX_ENTRIES / 16;
+
+lazy_static! {
+    static ref BLACKLIST: Mutex<Option<Blacklist>> = Mutex::new(None);
+}
+
+/// Reason why an uplink is considered malformed. These checks do not depend on the MIC, as
+/// the gateway does not know the session keys.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Reason {
+    // The MHDR has a non-zero major version or RFU bits.
+    Header,
+    // The frame is too short for the FHDR (including the FOpts) and MIC.
+    Length,
+    // The frame contains both FOpts and a FRMPayload with FPort 0.
+    FOpts,
+    // The FCnt increment since the previous uplink exceeds max_fcnt_gap.
+    FCntJump,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Reason::Header => write!(f, "HEADER"),
+            Reason::Length => write!(f, "LENGTH"),
+            Reason::FOpts => write!(f, "FOPTS"),
+            Reason::FCntJump => write!(f, "FCNT_JUMP"),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Configuration {
+    // Max. FCnt (16 LSB) increment between two uplinks of the same DevAddr.
+    pub max_fcnt_gap: u32,
+    // Number of malformed uplinks within the interval after which the DevAddr is blacklisted.
+    pub max_malformed: u32,
+    pub interval: Duration,
+    // A blacklisted DevAddr is removed from the blacklist when it did not send a malformed
+    // uplink for this duration.
+    pub expiry: Duration,
+    // Rate-limit the uplinks of blacklisted DevAddrs.
+    pub limit: bool,
+    // Max. number of uplinks per interval forwarded for a blacklisted DevAddr (0 = none).
+    pub max_uplinks: u32,
+}
+
+struct Entry {
+    // FCnt (16 LSB) of the last uplink.
+    f_cnt: Option<u16>,
+    // Monotonic time of the last uplink.
+    seen: Duration,
+    // Monotonic start time of the current interval and the number of malformed and forwarded
+    // uplinks within this interval.
+    window_start: Duration,
+    malformed: u32,
+    forwarded: u32,
+    // Total number of malformed uplinks.
+    malformed_total: u64,
+    // Reason and monotonic time of the last malformed uplink.
+    last_malformed: Option<(Reason, Duration)>,
+    // Time at which the DevAddr was blacklisted.
+    blacklisted: Option<SystemTime>,
+}
+
+impl Entry {
+    fn new(now: Duration) -> Entry {
+        Entry {
+            f_cnt: None,
+            seen: now,
+            window_start: now,
+            malformed: 0,
+            forwarded: 0,
+            malformed_total: 0,
+            last_malformed: None,
+            blacklisted: None,
+        }
+    }
+}
+
+struct Blacklist {
+    conf: Configuration,
+    entries: HashMap<u32, Entry>,
+}
+
+impl Blacklist {
+    fn new(conf: Configuration) -> Blacklist {
+        Blacklist {
+            conf,
+            entries: HashMap::new(),
+        }
+    }
+
+    // Returns true when the uplink of the given DevAddr must be forwarded.
+    fn allow(
+        &mut self,
+        dev_addr: u32,
+        f_cnt: Result<u16, Reason>,
+        now: Duration,
+        time: SystemTime,
+    ) -> bool {
+        if self.entries.len() >= MAX_ENTRIES && !self.entries.contains_key(&dev_addr) {
+            self.prune(now);
+        }
+
+        let conf = &self.conf;
+        let entry = self
+            .entries
+            .entry(dev_addr)
+            .or_insert_with(|| Entry::new(now));
+        entry.seen = now;
+
+        if now.saturating_sub(entry.window_start) >= conf.interval {
+            entry.window_start = now;
+            entry.malformed = 0;
+            entry.forwarded = 0;
+        }
+
+        let reason = match f_cnt {
+            Ok(f_cnt) => {
+                let gap = entry.f_cnt.map(|v| f_cnt.wrapping_sub(v));
+                entry.f_cnt = Some(f_cnt);
+
+                match gap {
+                    Some(gap) if gap as u32 > conf.max_fcnt_gap => Some(Reason::FCntJump),
+                    _ => None,
+                }
+            }
+            Err(reason) => Some(reason),
+        };
+
+        if let Some(reason) = reason {
+            stats::inc_counter("uplinks_malformed");
+            entry.malformed += 1;
+            entry.malformed_total += 1;
+            entry.last_malformed = Some((reason, now));
+
+            if entry.blacklisted.is_none() && entry.malformed >= conf.max_malformed {
+                warn!(
+                    "Transmitter blacklisted, dev_addr: {:08x}, reason: {}, malformed: {}, interval: {:?}",
+                    dev_addr, reason, entry.malformed, conf.interval
+                );
+                entry.blacklisted = Some(time);
+            }
+        }
+
+        if entry.blacklisted.is_some() && is_expired(entry, conf.expiry, now) {
+            info!(
+                "Transmitter removed from blacklist, dev_addr: {:08x}",
+                dev_addr
+            );
+            entry.blacklisted = None;
+        }
+
+        if entry.blacklisted.is_none() || !conf.limit {
+            return true;
+        }
+
+        if entry.forwarded < conf.max_uplinks {
+            entry.forwarded += 1;
+            return true;
+        }
+
+        stats::inc_counter("uplinks_blacklisted_dropped");
+        false
+    }
+
+    // Removes the DevAddrs which are not blacklisted and without uplinks within the interval.
+    // When this does not free up an entry, the least recently seen DevAddrs are evicted, the
+    // blacklisted DevAddrs are evicted last.
+    fn prune(&mut self, now: Duration) {
+        let (interval, expiry) = (self.conf.interval, self.conf.expiry);
+        self.entries.retain(|_, v| {
+            if v.blacklisted.is_some() {
+                !is_expired(v, expiry, now)
+            } else {
+                now.saturating_sub(v.seen) < interval
+            }
+        });
+
+        if self.entries.len() < MAX_ENTRIES {
+            return;
+        }
+
+        let mut oldest: Vec<(bool, Duration, u32)> = self
+            .entries
+            .iter()
+            .map(|(dev_addr, v)| (v.blacklisted.is_some(), v.seen, *dev_addr))
+            .collect();
+        oldest.sort_unstable();
+
+        let count = self.entries.len() - MAX_ENTRIES + EVICT_ENTRIES;
+        for (_, _, dev_addr) in oldest.iter().take(count) {
+            self.entries.remove(dev_addr);
+        }
+
+        warn!("Blacklist is full, evicted DevAddrs: {}", count);
+    }
+
+    fn is_blacklisted(&self, entry: &Entry, now: Duration) -> bool {
+        entry.blacklisted.is_some() && !is_expired(entry, self.conf.expiry, now)
+    }
+
+    fn get(&self, now: Duration) -> Vec<api::BlacklistEntry> {
+        let mut out: Vec<api::BlacklistEntry> = self
+            .entries
+            .iter()
+            .filter(|(_, v)| self.is_blacklisted(v, now))
+            .map(|(dev_addr, v)| {
+                let (reason, last_malformed) = v.last_malformed.unwrap();
+
+                api::BlacklistEntry {
+                    dev_addr: format!("{:08x}", dev_addr),
+                    reason: reason.to_string(),
+                    malformed: v.malformed_total,
+                    since: v.blacklisted.map(to_timestamp),
+                    expires: Some(to_timestamp(
+                        timesource::now() + (last_malformed + self.conf.expiry - now),
+                    )),
+                }
+            })
+            .collect();
+
+        out.sort_by(|a, b| a.dev_addr.cmp(&b.dev_addr));
+        out
+    }
+}
+
+/// Validates the blacklist configuration.
+pub fn validate(conf: &Configuration) -> Result<()> {
+    if conf.max_fcnt_gap == 0 || conf.max_fcnt_gap > u16::MAX as u32 {
+        return Err(anyhow!("max_fcnt_gap must be between 1 and 65535"));
+    }
+
+    if conf.max_malformed == 0 {
+        return Err(anyhow!("max_malformed must be greater than 0"));
+    }
+
+    if conf.interval.is_zero() {
+        return Err(anyhow!("interval must be greater than 0"));
+    }
+
+    if conf.expiry.is_zero() {
+        return Err(anyhow!("expiry must be greater than 0"));
+    }
+
+    Ok(())
+}
+
+/// Set the blacklist configuration. When None, the tracking of malformed transmitters is
+/// disabled. The tracked DevAddrs are retained when the blacklist is already set up, such that
+/// these are not forgotten after a configuration reload or restart.
+pub fn setup(conf: Option<Configuration>) {
+    let mut blacklist = BLACKLIST.lock().unwrap();
+
+    let conf = match conf {
+        Some(v) => v,
+        None => {
+            *blacklist = None;
+            return;
+        }
+    };
+
+    if blacklist.as_ref().map(|v| &v.conf) == Some(&conf) {
+        return;
+    }
+
+    info!(
+        "Setting up transmitter blacklist, max_fcnt_gap: {}, max_malformed: {}, interval: {:?}, expiry: {:?}, limit: {}, max_uplinks: {}",
+        conf.max_fcnt_gap, conf.max_malformed, conf.interval, conf.expiry, conf.limit, conf.max_uplinks
+    );
+
+    match blacklist.as_mut() {
+        Some(v) => v.conf = conf,
+        None => *blacklist = Some(Blacklist::new(conf)),
+    }
+}
+
+/// Returns false when the uplink is sent by a blacklisted DevAddr and its rate is exceeded, in
+/// which case it must not be forwarded. Only data uplinks are tracked. As the DevAddr is not
+/// unique, the uplinks of different devices sharing a DevAddr might be counted as FCnt jumps.
+pub fn allow(pl: &chirpstack_api::gw::UplinkFrame) -> bool {
+    let mut blacklist = BLACKLIST.lock().unwrap();
+    let blacklist = match blacklist.as_mut() {
+        Some(v) => v,
+        None => return true,
+    };
+
+    let (dev_addr, f_cnt) = match parse(&pl.phy_payload) {
+        Some(v) => v,
+        None => return true,
+    };
+
+    blacklist.allow(dev_addr, f_cnt, timesource::monotonic(), timesource::now())
+}
+
+/// Returns the blacklisted DevAddrs, as returned on the blacklist command.
+pub fn get(gateway_id: &[u8]) -> api::Blacklist {
+    let blacklist = BLACKLIST.lock().unwrap();
+
+    api::Blacklist {
+        gateway_id: hex::encode(gateway_id),
+        entries: blacklist
+            .as_ref()
+            .map(|v| v.get(timesource::monotonic()))
+            .unwrap_or_default(),
+    }
+}
+
+/// Returns the number of blacklisted DevAddrs as stats meta-data (blacklist_size).
+pub fn get_metadata() -> HashMap<String, String> {
+    let mut out = HashMap::new();
+    if let Some(blacklist) = BLACKLIST.lock().unwrap().as_ref() {
+        let now = timesource::monotonic();
+        let size = blacklist
+            .entries
+            .values()
+            .filter(|v| blacklist.is_blacklisted(v, now))
+            .count();
+        out.insert("blacklist_size".to_string(), size.to_string());
+    }
+    out
+}
+
+// Returns the DevAddr and the FCnt of the given PHYPayload, or the reason why it is
+// malformed. None is returned when this is not a data uplink.
+fn parse(phy_payload: &[u8]) -> Option<(u32, Result<u16, Reason>)> {
+    if phy_payload.len() < DEV_ADDR_END {
+        return None;
+    }
+
+    // Unconfirmed and confirmed data up.
+    let m_type = phy_payload[0] >> 5;
+    if m_type != 0x02 && m_type != 0x04 {
+        return None;
+    }
+
+    // The DevAddr is encoded little endian.
+    let mut b: [u8; 4] = [0; 4];
+    b.copy_from_slice(&phy_payload[1..DEV_ADDR_END]);
+    let dev_addr = u32::from_le_bytes(b);
+
+    if phy_payload[0] & 0x1f != 0 {
+        return Some((dev_addr, Err(Reason::Header)));
+    }
+
+    if phy_payload.len() < MIN_DATA_SIZE {
+        return Some((dev_addr, Err(Reason::Length)));
+    }
+
+    let f_opts_len = (phy_payload[5] & 0x0f) as usize;
+    if phy_payload.len() < MIN_DATA_SIZE + f_opts_len {
+        return Some((dev_addr, Err(Reason::Length)));
+    }
+
+    // MAC-commands must not be sent both as FOpts and as FRMPayload (FPort 0).
+    if phy_payload.len() > MIN_DATA_SIZE + f_opts_len
+        && f_opts_len != 0
+        && phy_payload[8 + f_opts_len] == 0
+    {
+        return Some((dev_addr, Err(Reason::FOpts)));
+    }
+
+    Some((
+        dev_addr,
+        Ok(u16::from_le_bytes([phy_payload[6], phy_payload[7]])),
+    ))
+}
+
+// Returns true when the DevAddr did not send a malformed uplink within the expiry duration.
+fn is_expired(entry: &Entry, expiry: Duration, now: Duration) -> bool {
+    match entry.last_malformed {
+        Some((_, t)) => now.saturating_sub(t) >= expiry,
+        None => true,
+    }
+}
+
+fn to_timestamp(t: SystemTime) -> pbjson_types::Timestamp {
+    let t = t.duration_since(UNIX_EPOCH).unwrap_or_default();
+    pbjson_types::Timestamp {
+        seconds: t.as_secs() as i64,
+        nanos: t.subsec_nanos() as i32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data_up(dev_addr: u32, f_cnt: u16) -> Vec<u8> {
+        let mut b = vec![0x40];
+        b.extend_from_slice(&dev_addr.to_le_bytes());
+        b.push(0x00);
+        b.extend_from_slice(&f_cnt.to_le_bytes());
+        b.extend_from_slice(&[0x01, 0xff, 0, 0, 0, 0]);
+        b
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Some((0x01020304, Ok(10))), parse(&data_up(0x01020304, 10)));
+
+        // join-request
+        assert_eq!(None, parse(&[0x00; 23]));
+
+        // major version 1
+        let mut b = data_up(0x01020304, 10);
+        b[0] |= 0x01;
+        assert_eq!(Some((0x01020304, Err(Reason::Header))), parse(&b));
+
+        // FOpts length exceeds the frame
+        let mut b = data_up(0x01020304, 10);
+        b[5] = 0x0f;
+        assert_eq!(Some((0x01020304, Err(Reason::Length))), parse(&b));
+
+        // FOpts and FPort 0
+        let mut b = data_up(0x01020304, 10);
+        b[5] = 0x01;
+        b[9] = 0x00;
+        b.push(0xff);
+        assert_eq!(Some((0x01020304, Err(Reason::FOpts))), parse(&b));
+    }
+
+    #[test]
+    fn test_blacklist() {
+        let mut blacklist = Blacklist::new(Configuration {
+            max_fcnt_gap: 100,
+            max_malformed: 2,
+            interval: Duration::from_secs(60),
+            expiry: Duration::from_secs(600),
+            limit: true,
+            max_uplinks: 1,
+        });
+        let start = Duration::from_secs(10);
+        let time = UNIX_EPOCH;
+
+        assert!(blacklist.allow(1, Ok(10), start, time));
+        assert!(blacklist.allow(1, Ok(11), start, time));
+
+        // the first FCnt jump does not blacklist the DevAddr
+        assert!(blacklist.allow(1, Ok(1000), start, time));
+        assert!(blacklist.get(start).is_empty());
+
+        // blacklisted, one uplink per interval is forwarded
+        assert!(blacklist.allow(1, Err(Reason::Header), start, time));
+        assert!(!blacklist.allow(1, Ok(1001), start, time));
+        assert_eq!(1, blacklist.get(start).len());
+        assert_eq!("00000001", blacklist.get(start)[0].dev_addr);
+        assert_eq!("HEADER", blacklist.get(start)[0].reason);
+        assert_eq!(2, blacklist.get(start)[0].malformed);
+
+        // the next interval
+        let now = start + Duration::from_secs(60);
+        assert!(blacklist.allow(1, Ok(1002), now, time));
+        assert!(!blacklist.allow(1, Ok(1003), now, time));
+
+        // other DevAddrs are not affected
+        assert!(blacklist.allow(2, Ok(1), now, time));
+        assert!(blacklist.allow(2, Ok(2), now, time));
+
+        // expired
+        let now = start + Duration::from_secs(600);
+        assert!(blacklist.allow(1, Ok(1004), now, time));
+        assert!(blacklist.allow(1, Ok(1005), now, time));
+        assert!(blacklist.get(now).is_empty());
+    }
+
+    #[test]
+    fn test_blacklist_evict() {
+        let mut blacklist = Blacklist::new(Configuration {
+            max_fcnt_gap: 100,
+            max_malformed: 1,
+            interval: Duration::from_secs(60),
+            expiry: Duration::from_secs(600),
+            limit: true,
+            max_uplinks: 0,
+        });
+        let now = Duration::from_secs(10);
+        let time = UNIX_EPOCH;
+
+        // the blacklisted DevAddr is seen first
+        assert!(!blacklist.allow(0, Err(Reason::Header), now, time));
+        for i in 1..MAX_ENTRIES as u32 {
+            assert!(blacklist.allow(i, Ok(1), now + Duration::from_millis(i as u64), time));
+        }
+        assert_eq!(MAX_ENTRIES, blacklist.entries.len());
+
+        // none of the DevAddrs has expired, the least recently seen DevAddrs are evicted
+        let now = now + Duration::from_secs(30);
+        assert!(blacklist.allow(MAX_ENTRIES as u32, Ok(1), now, time));
+        assert_eq!(MAX_ENTRIES - EVICT_ENTRIES + 1, blacklist.entries.len());
+        assert!(blacklist.entries.contains_key(&0));
+        assert!(!blacklist.entries.contains_key(&1));
+        assert!(blacklist.entries.contains_key(&(EVICT_ENTRIES as u32 + 1)));
+    }
+}
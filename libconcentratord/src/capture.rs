@@ -0,0 +1,54 @@
+use std::sync::Mutex;
+
+use anyhow::Result;
+use log::{error, info};
+use prost::Message;
+
+use super::rotate::RotatingFile;
+
+lazy_static! {
+    static ref CAPTURE: Mutex<Option<RotatingFile>> = Mutex::new(None);
+}
+
+#[derive(Clone)]
+pub struct Configuration {
+    // Path of the capture file.
+    pub path: String,
+    // Max. size (bytes) of the capture file before it is rotated. When 0, the file is not
+    // rotated.
+    pub max_size: u64,
+    // Number of rotated files to keep (path.1 is the most recent one).
+    pub max_files: usize,
+}
+
+/// Setup the capture file. Once setup, every call to write_uplink is written to the capture
+/// file.
+pub fn setup(conf: &Configuration) -> Result<()> {
+    info!(
+        "Setting up capture file, path: {}, max_size: {}, max_files: {}",
+        conf.path, conf.max_size, conf.max_files
+    );
+
+    let mut capture = CAPTURE.lock().unwrap();
+    *capture = Some(RotatingFile::open(
+        &conf.path,
+        conf.max_size,
+        conf.max_files,
+    )?);
+
+    Ok(())
+}
+
+/// Write the uplink frame (length-delimited Protobuf) to the capture file. This is a no-op when
+/// the capture file has not been setup.
+pub fn write_uplink(pl: &chirpstack_api::gw::UplinkFrame) {
+    let mut capture = CAPTURE.lock().unwrap();
+    let capture = match capture.as_mut() {
+        Some(v) => v,
+        None => return,
+    };
+
+    if let Err(err) = capture.write(&pl.encode_length_delimited_to_vec()) {
+        error!("Write capture file error, error: {}", err);
+    }
+}
@@ -1,4 +1,7 @@
+use std::collections::HashMap;
+use std::fmt;
 use std::io::Cursor;
+use std::sync::Mutex;
 use std::time::Duration;
 
 use anyhow::Result;
@@ -6,17 +9,43 @@ use chirpstack_api::gw;
 use log::info;
 use prost::Message;
 
+use super::api;
+use super::curve;
 use super::socket::ZMQ_CONTEXT;
+use super::timesource;
 
+lazy_static! {
+    // Released socket, with its endpoint and CURVE secret key.
+    static ref RELEASED_SOCKET: Mutex<Option<(String, Option<Vec<u8>>, zmq::Socket)>> =
+        Mutex::new(None);
+}
+
+/// Returns the socket for receiving commands. When a socket with the same bind and CURVE
+/// settings was released (see release_socket), that socket is returned, so that the clients stay
+/// connected when Concentratord is restarted internally (e.g. on a configuration reload).
 pub fn get_socket(bind: &str) -> Result<zmq::Socket> {
+    if let Some((released_bind, secret_key, sock)) = RELEASED_SOCKET.lock().unwrap().take() {
+        if released_bind == bind && secret_key == curve::secret_key() {
+            info!("Re-using socket for receiving commands, bind: {}", bind);
+            return Ok(sock);
+        }
+    }
+
     info!("Creating socket for receiving commands, bind: {}", bind);
 
     let zmq_ctx = ZMQ_CONTEXT.lock().unwrap();
     let sock = zmq_ctx.socket(zmq::REP)?;
+    curve::apply(&sock)?;
     sock.bind(&bind)?;
     Ok(sock)
 }
 
+/// Release the socket for receiving commands, such that it can be re-used by get_socket. Every
+/// received command must have been replied to.
+pub fn release_socket(bind: &str, sock: zmq::Socket) {
+    *RELEASED_SOCKET.lock().unwrap() = Some((bind.to_string(), curve::secret_key(), sock));
+}
+
 pub enum Command {
     // Reading command timed out.
     Timeout,
@@ -35,11 +64,178 @@ pub enum Command {
 
     // Gateway configuration.
     Configuration(chirpstack_api::gw::GatewayConfiguration),
+
+    // Gateway configuration request.
+    GetConfiguration,
+
+    // Capabilities request.
+    Capabilities,
+
+    // Status request.
+    Status,
+
+    // Beacon payload override.
+    Beacon(api::BeaconPayload),
+
+    // TX test request.
+    TxTest(api::TxTestRequest),
+
+    // Live frame tap request.
+    Tap(api::TapRequest),
+
+    // Synchronized TX request.
+    SyncTx(api::SyncTxRequest),
+
+    // Spectral scan request.
+    SpectralScan(api::SpectralScanRequest),
+
+    // Downlink history request.
+    DownlinkHistory(api::DownlinkHistoryRequest),
+
+    // Diagnostic bundle request.
+    DiagBundle(api::DiagBundleRequest),
+
+    // Blacklist request.
+    Blacklist,
+
+    // Configuration reload request.
+    Reload,
+
+    // Time-sync answer.
+    TimeSync(api::TimeSyncAnswer),
+
+    // Command rejected by the command filter.
+    Rejected(String),
+
+    // Command rejected by the rate limiter.
+    Throttled(String),
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Command::Timeout => write!(f, "timeout"),
+            Command::Error(_) => write!(f, "error"),
+            Command::Unknown(command, _) => write!(f, "{}", command),
+            Command::Downlink(_) => write!(f, "down"),
+            Command::GatewayID => write!(f, "gateway_id"),
+            Command::Configuration(_) => write!(f, "config"),
+            Command::GetConfiguration => write!(f, "get_config"),
+            Command::Capabilities => write!(f, "capabilities"),
+            Command::Status => write!(f, "status"),
+            Command::Beacon(_) => write!(f, "beacon"),
+            Command::TxTest(_) => write!(f, "txtest"),
+            Command::Tap(_) => write!(f, "tap"),
+            Command::SyncTx(_) => write!(f, "sync_tx"),
+            Command::SpectralScan(_) => write!(f, "spectral_scan"),
+            Command::DownlinkHistory(_) => write!(f, "downlink_history"),
+            Command::DiagBundle(_) => write!(f, "diag_bundle"),
+            Command::Blacklist => write!(f, "blacklist"),
+            Command::Reload => write!(f, "reload"),
+            Command::TimeSync(_) => write!(f, "timesync"),
+            Command::Rejected(command) => write!(f, "{}", command),
+            Command::Throttled(command) => write!(f, "{}", command),
+        }
+    }
+}
+
+// Commands changing the state of the concentrator, these are rejected in read-only mode.
+const WRITE_COMMANDS: [&str; 9] = [
+    "down",
+    "config",
+    "beacon",
+    "txtest",
+    "sync_tx",
+    "reload",
+    "timesync",
+    "diag_bundle",
+    "spectral_scan",
+];
+
+#[derive(Default, Clone)]
+pub struct Filter {
+    // Allowed commands. When empty, all commands are allowed.
+    pub allowed_commands: Vec<String>,
+    // Reject the commands changing the state of the concentrator.
+    pub read_only: bool,
+}
+
+impl Filter {
+    pub fn is_allowed(&self, command: &str) -> bool {
+        if self.read_only && WRITE_COMMANDS.contains(&command) {
+            return false;
+        }
+
+        self.allowed_commands.is_empty() || self.allowed_commands.iter().any(|v| v == command)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    // Number of commands per second.
+    pub rate: f64,
+    // Max. number of commands that can be received in a burst.
+    pub burst: u32,
+}
+
+/// Token-bucket rate limiter, with a bucket per command. Commands without a rate limit are
+/// never throttled.
+#[derive(Default)]
+pub struct RateLimiter {
+    limits: HashMap<String, RateLimit>,
+    buckets: HashMap<String, Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    // Monotonic time of the last update.
+    updated: Duration,
+}
+
+impl RateLimiter {
+    pub fn new(limits: HashMap<String, RateLimit>) -> Self {
+        RateLimiter {
+            limits,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Returns true when the command is allowed at the given (monotonic) time, in which case
+    /// a token is consumed from the bucket of the command.
+    pub fn allow(&mut self, command: &str, now: Duration) -> bool {
+        let limit = match self.limits.get(command) {
+            Some(v) => v,
+            None => return true,
+        };
+
+        let bucket = self
+            .buckets
+            .entry(command.to_string())
+            .or_insert_with(|| Bucket {
+                tokens: limit.burst as f64,
+                updated: now,
+            });
+
+        let elapsed = now.saturating_sub(bucket.updated);
+        bucket.tokens =
+            (bucket.tokens + elapsed.as_secs_f64() * limit.rate).min(limit.burst as f64);
+        bucket.updated = now;
+
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+
+        bucket.tokens -= 1.0;
+        true
+    }
 }
 
 pub struct Reader<'a> {
     rep_sock: &'a zmq::Socket,
     timeout: Duration,
+    filter: Filter,
+    rate_limiter: RateLimiter,
+    user_id: Option<String>,
 }
 
 impl<'a> Reader<'a> {
@@ -47,6 +243,46 @@ impl<'a> Reader<'a> {
         Reader {
             rep_sock: sock,
             timeout: timeout,
+            filter: Default::default(),
+            rate_limiter: Default::default(),
+            user_id: None,
+        }
+    }
+
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_rate_limits(mut self, limits: HashMap<String, RateLimit>) -> Self {
+        self.rate_limiter = RateLimiter::new(limits);
+        self
+    }
+
+    /// Returns the identity of the sender of the last received command. When CURVE is enabled,
+    /// this is the Z85 encoded public key of the client (see curve), else this is None.
+    pub fn user_id(&self) -> Option<&str> {
+        self.user_id.as_deref()
+    }
+
+    fn recv(&mut self) -> Vec<Vec<u8>> {
+        let mut msg = Vec::new();
+        self.user_id = None;
+
+        loop {
+            let mut part = zmq::Message::new();
+            self.rep_sock.recv(&mut part, 0).unwrap();
+            if self.user_id.is_none() {
+                self.user_id = part
+                    .gets("User-Id")
+                    .filter(|v| !v.is_empty())
+                    .map(|v| v.to_string());
+            }
+
+            msg.push(part.to_vec());
+            if !part.get_more() {
+                return msg;
+            }
         }
     }
 }
@@ -62,20 +298,30 @@ impl Iterator for Reader<'_> {
             return Some(Command::Timeout);
         }
 
-        let msg = self.rep_sock.recv_multipart(0).unwrap();
-        match handle_message(msg) {
+        let msg = self.recv();
+        match handle_message(msg, &self.filter, &mut self.rate_limiter) {
             Ok(v) => Some(v),
             Err(err) => Some(Command::Error(err.to_string())),
         }
     }
 }
 
-fn handle_message(msg: Vec<Vec<u8>>) -> Result<Command> {
+fn handle_message(
+    msg: Vec<Vec<u8>>,
+    filter: &Filter,
+    rate_limiter: &mut RateLimiter,
+) -> Result<Command> {
     if msg.len() != 2 {
         return Err(anyhow!("Command must have two frames"));
     }
 
     let command = String::from_utf8(msg[0].clone())?;
+    if !filter.is_allowed(&command) {
+        return Ok(Command::Rejected(command));
+    }
+    if !rate_limiter.allow(&command, timesource::monotonic()) {
+        return Ok(Command::Throttled(command));
+    }
 
     Ok(match command.as_str() {
         "down" => match gw::DownlinkFrame::decode(&mut Cursor::new(&msg[1])) {
@@ -86,7 +332,122 @@ fn handle_message(msg: Vec<Vec<u8>>) -> Result<Command> {
             Ok(v) => Command::Configuration(v),
             Err(err) => Command::Error(err.to_string()),
         },
+        "beacon" => match api::BeaconPayload::decode(&mut Cursor::new(&msg[1])) {
+            Ok(v) => Command::Beacon(v),
+            Err(err) => Command::Error(err.to_string()),
+        },
+        "txtest" => match api::TxTestRequest::decode(&mut Cursor::new(&msg[1])) {
+            Ok(v) => Command::TxTest(v),
+            Err(err) => Command::Error(err.to_string()),
+        },
+        "tap" => match api::TapRequest::decode(&mut Cursor::new(&msg[1])) {
+            Ok(v) => Command::Tap(v),
+            Err(err) => Command::Error(err.to_string()),
+        },
+        "sync_tx" => match api::SyncTxRequest::decode(&mut Cursor::new(&msg[1])) {
+            Ok(v) => Command::SyncTx(v),
+            Err(err) => Command::Error(err.to_string()),
+        },
+        "spectral_scan" => match api::SpectralScanRequest::decode(&mut Cursor::new(&msg[1])) {
+            Ok(v) => Command::SpectralScan(v),
+            Err(err) => Command::Error(err.to_string()),
+        },
+        "downlink_history" => {
+            match api::DownlinkHistoryRequest::decode(&mut Cursor::new(&msg[1])) {
+                Ok(v) => Command::DownlinkHistory(v),
+                Err(err) => Command::Error(err.to_string()),
+            }
+        }
+        "diag_bundle" => match api::DiagBundleRequest::decode(&mut Cursor::new(&msg[1])) {
+            Ok(v) => Command::DiagBundle(v),
+            Err(err) => Command::Error(err.to_string()),
+        },
+        "timesync" => match api::TimeSyncAnswer::decode(&mut Cursor::new(&msg[1])) {
+            Ok(v) => Command::TimeSync(v),
+            Err(err) => Command::Error(err.to_string()),
+        },
         "gateway_id" => Command::GatewayID,
+        "get_config" => Command::GetConfiguration,
+        "capabilities" => Command::Capabilities,
+        "status" => Command::Status,
+        "blacklist" => Command::Blacklist,
+        "reload" => Command::Reload,
         _ => Command::Unknown(command, msg[1].clone()),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_is_allowed() {
+        let filter = Filter::default();
+        assert!(filter.is_allowed("down"));
+        assert!(filter.is_allowed("gateway_id"));
+
+        let filter = Filter {
+            read_only: true,
+            ..Default::default()
+        };
+        assert!(!filter.is_allowed("down"));
+        assert!(!filter.is_allowed("config"));
+        assert!(!filter.is_allowed("beacon"));
+        assert!(!filter.is_allowed("txtest"));
+        assert!(!filter.is_allowed("diag_bundle"));
+        assert!(!filter.is_allowed("spectral_scan"));
+        assert!(filter.is_allowed("gateway_id"));
+
+        let filter = Filter {
+            allowed_commands: vec!["gateway_id".to_string(), "down".to_string()],
+            ..Default::default()
+        };
+        assert!(filter.is_allowed("down"));
+        assert!(filter.is_allowed("gateway_id"));
+        assert!(!filter.is_allowed("config"));
+
+        let filter = Filter {
+            allowed_commands: vec!["gateway_id".to_string(), "down".to_string()],
+            read_only: true,
+        };
+        assert!(!filter.is_allowed("down"));
+        assert!(filter.is_allowed("gateway_id"));
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut limits = HashMap::new();
+        limits.insert(
+            "down".to_string(),
+            RateLimit {
+                rate: 2.0,
+                burst: 3,
+            },
+        );
+        let mut rate_limiter = RateLimiter::new(limits);
+        let start = Duration::from_secs(10);
+
+        // burst
+        for _ in 0..3 {
+            assert!(rate_limiter.allow("down", start));
+        }
+        assert!(!rate_limiter.allow("down", start));
+
+        // commands without rate limit
+        for _ in 0..10 {
+            assert!(rate_limiter.allow("gateway_id", start));
+        }
+
+        // refill at 2 commands per second
+        assert!(!rate_limiter.allow("down", start + Duration::from_millis(400)));
+        assert!(rate_limiter.allow("down", start + Duration::from_millis(600)));
+        assert!(!rate_limiter.allow("down", start + Duration::from_millis(600)));
+
+        // the bucket does not exceed the burst size
+        let later = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(rate_limiter.allow("down", later));
+        }
+        assert!(!rate_limiter.allow("down", later));
+    }
+}
@@ -0,0 +1,228 @@
+use std::sync::Mutex;
+use std::thread;
+
+use anyhow::Result;
+use log::{error, info, warn};
+
+use super::socket::ZMQ_CONTEXT;
+
+// ZAP (ZeroMQ Authentication Protocol) handler endpoint, as defined by ZMQ RFC 27.
+const ZAP_ENDPOINT: &str = "inproc://zeromq.zap.01";
+
+// ZAP domain of the CURVE sockets.
+const ZAP_DOMAIN: &str = "concentratord";
+
+lazy_static! {
+    // Z85 decoded server secret key, None when CURVE is disabled.
+    static ref SECRET_KEY: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+
+    // Z85 decoded client public keys, see Configuration.client_keys.
+    static ref CLIENT_KEYS: Mutex<Vec<Vec<u8>>> = Mutex::new(Vec::new());
+
+    // Set once the ZAP handler is running, the handler is kept across a re-setup.
+    static ref ZAP_RUNNING: Mutex<bool> = Mutex::new(false);
+}
+
+#[derive(Clone, Default)]
+pub struct Configuration {
+    // Z85 encoded server secret key. When empty, CURVE is disabled.
+    pub secret_key: String,
+    // Z85 encoded public keys of the clients that are allowed to connect. When empty, every
+    // client that knows the server public key is allowed to connect.
+    pub client_keys: Vec<String>,
+}
+
+/// Setup CURVE authentication and encryption. Once setup, the event and command sockets are
+/// bound as CURVE server. This must be called before the sockets are bound. On a configuration
+/// reload, this can be called again (an empty secret key disables CURVE), in which case the
+/// sockets are re-bound when the secret key changed (see secret_key).
+pub fn setup(conf: &Configuration) -> Result<()> {
+    if conf.secret_key.is_empty() {
+        if SECRET_KEY.lock().unwrap().take().is_some() {
+            info!("Disabling CURVE authentication");
+        }
+        CLIENT_KEYS.lock().unwrap().clear();
+        return Ok(());
+    }
+
+    info!(
+        "Setting up CURVE authentication, client_keys: {}",
+        conf.client_keys.len()
+    );
+
+    if zmq::has("curve") != Some(true) {
+        return Err(anyhow!("libzmq has been built without CURVE support"));
+    }
+
+    let secret_key = decode_key(&conf.secret_key)?;
+    let client_keys = conf
+        .client_keys
+        .iter()
+        .map(|v| decode_key(v))
+        .collect::<Result<Vec<Vec<u8>>>>()?;
+
+    // The ZAP handler allows every client when there are no client keys. It is also needed in
+    // that case, as it sets the client public key as User-Id of the received messages.
+    let mut zap_running = ZAP_RUNNING.lock().unwrap();
+    if !*zap_running {
+        let zmq_ctx = ZMQ_CONTEXT.lock().unwrap();
+        let sock = zmq_ctx.socket(zmq::REP)?;
+        sock.bind(ZAP_ENDPOINT)?;
+
+        thread::spawn(move || zap_loop(sock));
+        *zap_running = true;
+    }
+
+    *CLIENT_KEYS.lock().unwrap() = client_keys;
+    *SECRET_KEY.lock().unwrap() = Some(secret_key);
+
+    Ok(())
+}
+
+/// Generates a new CURVE key pair, returning the Z85 encoded (public key, secret key).
+pub fn generate_keys() -> Result<(String, String)> {
+    let key_pair = zmq::CurveKeyPair::new()?;
+
+    Ok((
+        zmq::z85_encode(&key_pair.public_key).map_err(|_| anyhow!("encode public key error"))?,
+        zmq::z85_encode(&key_pair.secret_key).map_err(|_| anyhow!("encode secret key error"))?,
+    ))
+}
+
+/// Returns the server secret key, None when CURVE is disabled. A socket must be re-bound when
+/// this changes, as the CURVE options are applied before binding.
+pub(crate) fn secret_key() -> Option<Vec<u8>> {
+    SECRET_KEY.lock().unwrap().clone()
+}
+
+/// Configure the socket as CURVE server, this is a no-op when CURVE has not been setup. This
+/// must be called before the socket is bound.
+pub(crate) fn apply(sock: &zmq::Socket) -> Result<()> {
+    if let Some(secret_key) = SECRET_KEY.lock().unwrap().as_ref() {
+        sock.set_curve_server(true)?;
+        sock.set_curve_secretkey(secret_key)?;
+        sock.set_zap_domain(ZAP_DOMAIN)?;
+    }
+
+    Ok(())
+}
+
+fn decode_key(key: &str) -> Result<Vec<u8>> {
+    let b = zmq::z85_decode(key).map_err(|_| anyhow!("invalid Z85 key: {}", key))?;
+    if b.len() != 32 {
+        return Err(anyhow!("CURVE key must be 32 bytes, key: {}", key));
+    }
+
+    Ok(b)
+}
+
+// Handles the ZAP requests, accepting the CURVE clients of which the public key is in the
+// list of allowed keys. The Z85 encoded client public key is returned as user id, this is set
+// as User-Id property of the messages received from the client (see commands::Reader).
+fn zap_loop(sock: zmq::Socket) {
+    loop {
+        let msg = match sock.recv_multipart(0) {
+            Ok(v) => v,
+            Err(err) => {
+                error!("Receive ZAP request error, error: {}", err);
+                return;
+            }
+        };
+
+        let client_keys = CLIENT_KEYS.lock().unwrap().clone();
+        let (status, text, user_id) = match zap_status(&msg, &client_keys) {
+            Ok(v) => ("200", "OK", v),
+            Err(err) => {
+                let address = msg.get(3).map(|v| v.as_slice()).unwrap_or_default();
+                warn!(
+                    "CURVE client rejected, address: {}, error: {}",
+                    String::from_utf8_lossy(address),
+                    err
+                );
+                ("400", "client key not allowed", String::new())
+            }
+        };
+
+        // version, request id, status code, status text, user id and metadata
+        let reply: Vec<&[u8]> = vec![
+            msg.first().map(|v| v.as_slice()).unwrap_or_default(),
+            msg.get(1).map(|v| v.as_slice()).unwrap_or_default(),
+            status.as_bytes(),
+            text.as_bytes(),
+            user_id.as_bytes(),
+            "".as_bytes(),
+        ];
+        if let Err(err) = sock.send_multipart(reply, 0) {
+            error!("Send ZAP reply error, error: {}", err);
+        }
+    }
+}
+
+// The ZAP request frames are: version, request id, domain, address, identity, mechanism and
+// the credentials (the client public key for CURVE). Every client is allowed when there are no
+// client keys. The Z85 encoded client public key is returned.
+fn zap_status(msg: &[Vec<u8>], client_keys: &[Vec<u8>]) -> Result<String> {
+    if msg.len() != 7 || msg[5] != b"CURVE" {
+        return Err(anyhow!("unexpected ZAP request"));
+    }
+
+    let client_key = zmq::z85_encode(&msg[6]).map_err(|_| anyhow!("encode client key error"))?;
+    if !client_keys.is_empty() && !client_keys.contains(&msg[6]) {
+        return Err(anyhow!("unknown client key: {}", client_key));
+    }
+
+    Ok(client_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zap_status() {
+        let client_key = vec![1; 32];
+        let request = |mechanism: &str, key: Vec<u8>| -> Vec<Vec<u8>> {
+            vec![
+                b"1.0".to_vec(),
+                b"1".to_vec(),
+                ZAP_DOMAIN.as_bytes().to_vec(),
+                b"127.0.0.1".to_vec(),
+                b"".to_vec(),
+                mechanism.as_bytes().to_vec(),
+                key,
+            ]
+        };
+
+        assert!(zap_status(&request("CURVE", client_key.clone()), &[client_key.clone()]).is_ok());
+        assert!(zap_status(&request("CURVE", vec![2; 32]), &[client_key.clone()]).is_err());
+        assert!(zap_status(&request("NULL", client_key.clone()), &[client_key.clone()]).is_err());
+        assert!(zap_status(
+            &request("CURVE", client_key.clone())[..6],
+            &[client_key.clone()]
+        )
+        .is_err());
+        assert!(zap_status(&request("CURVE", vec![2; 32]), &[]).is_ok());
+
+        // the Z85 encoded client key is returned as user id
+        assert_eq!(
+            zmq::z85_encode(&client_key).unwrap(),
+            zap_status(&request("CURVE", client_key.clone()), &[]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_decode_key() {
+        // server public key of the ZMQ RFC 26 example
+        assert_eq!(
+            32,
+            decode_key("rq:rM>}U?@Lns47E1%kR.o@n%FcmmsL/@{H8]yf7")
+                .unwrap()
+                .len()
+        );
+
+        // Z85 encoded, but not 32 bytes
+        assert!(decode_key("HelloWorld").is_err());
+        // not Z85 encoded
+        assert!(decode_key("invalid").is_err());
+    }
+}
@@ -0,0 +1,290 @@
+use std::cmp;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use chrono::Utc;
+use log::{error, info, LevelFilter, Record};
+use prost::Message;
+
+use super::api;
+use super::jitqueue::TxPacket;
+use super::logging;
+
+// Duration of the capture when not set in the request.
+const DEFAULT_DURATION: Duration = Duration::from_secs(60);
+
+// Max. duration of the capture, such that the raised log level is always restored.
+const MAX_DURATION: Duration = Duration::from_secs(600);
+
+// Max. number of captured log records and frames, such that the memory usage is bounded.
+const MAX_LOG_RECORDS: usize = 10000;
+const MAX_FRAMES: usize = 1000;
+
+// Block size of the tar archive.
+const BLOCK_SIZE: usize = 512;
+
+lazy_static! {
+    // Directory to which the bundles are written, empty when not configured.
+    static ref PATH: Mutex<String> = Mutex::new(String::new());
+    static ref CAPTURE: Mutex<Option<Capture>> = Mutex::new(None);
+}
+
+struct Capture {
+    level: LevelFilter,
+    log: Vec<String>,
+    log_dropped: usize,
+    // Length-delimited Protobuf encoded frames, like the capture file.
+    frames: Vec<u8>,
+    frame_count: usize,
+}
+
+/// Setup the directory to which the diagnostic bundles are written. Until setup, the
+/// diag_bundle command returns an error.
+pub fn setup(path: &str) {
+    info!("Setting up diagnostic bundles, path: {}", path);
+    *PATH.lock().unwrap() = path.to_string();
+}
+
+/// Start capturing a diagnostic bundle. Until the capture ends, the log level is raised to
+/// the requested level and the log records and the received frames are captured. At the end,
+/// the log level is restored and the archive is written, containing the given files (name,
+/// content), the captured log records (log.txt) and the captured frames (uplinks.bin, using
+/// the capture file format).
+pub fn start(
+    gateway_id: &[u8],
+    req: &api::DiagBundleRequest,
+    files: Vec<(String, Vec<u8>)>,
+) -> Result<api::DiagBundleResponse> {
+    let dir = PATH.lock().unwrap().clone();
+    if dir.is_empty() {
+        return Err(anyhow!("diagnostic bundle path is not configured"));
+    }
+
+    let duration = get_duration(req);
+    let level = match req.log_level.as_ref() {
+        "" => LevelFilter::Debug,
+        v => LevelFilter::from_str(v).map_err(|_| anyhow!("invalid log_level: {}", v))?,
+    };
+    let path = Path::new(&dir).join(format!(
+        "diag_{}_{}.tar.zst",
+        hex::encode(gateway_id),
+        Utc::now().format("%Y%m%dT%H%M%SZ")
+    ));
+
+    {
+        let mut capture = CAPTURE.lock().unwrap();
+        if capture.is_some() {
+            return Err(anyhow!("diagnostic bundle capture is already running"));
+        }
+
+        *capture = Some(Capture {
+            level,
+            log: Vec::new(),
+            log_dropped: 0,
+            frames: Vec::new(),
+            frame_count: 0,
+        });
+    }
+
+    // The log level is only raised, a more verbose configured level is kept.
+    let previous_level = logging::get_level();
+    logging::set_level(cmp::max(previous_level, level));
+
+    info!(
+        "Starting diagnostic bundle capture, path: {}, duration: {:?}, log_level: {}",
+        path.display(),
+        duration,
+        level
+    );
+
+    thread::spawn({
+        let path = path.clone();
+
+        move || {
+            thread::sleep(duration);
+            finish(path, previous_level, files);
+        }
+    });
+
+    Ok(api::DiagBundleResponse {
+        path: path.display().to_string(),
+        duration_ms: duration.as_millis() as u32,
+    })
+}
+
+/// Captures the log record, when a diagnostic bundle capture is running.
+pub fn capture_log(record: &Record) {
+    let mut capture = CAPTURE.lock().unwrap();
+    let capture = match capture.as_mut() {
+        Some(v) if record.level() <= v.level => v,
+        _ => return,
+    };
+
+    if capture.log.len() == MAX_LOG_RECORDS {
+        capture.log_dropped += 1;
+        return;
+    }
+
+    capture.log.push(logging::format_text(record));
+}
+
+/// Captures the received frame, when a diagnostic bundle capture is running.
+pub fn capture_uplink(pl: &chirpstack_api::gw::UplinkFrame) {
+    let mut capture = CAPTURE.lock().unwrap();
+    let capture = match capture.as_mut() {
+        Some(v) if v.frame_count < MAX_FRAMES => v,
+        _ => return,
+    };
+
+    capture
+        .frames
+        .extend_from_slice(&pl.encode_length_delimited_to_vec());
+    capture.frame_count += 1;
+}
+
+/// Formats the queued packets (see jitqueue::Queue::packets), one line per packet.
+pub fn format_queue<T: TxPacket>(packets: &[T]) -> String {
+    packets
+        .iter()
+        .map(|v| {
+            format!(
+                "downlink_id: {}, tx_mode: {:?}, count_us: {}\n",
+                v.get_id(),
+                v.get_tx_mode(),
+                v.get_count_us()
+            )
+        })
+        .collect()
+}
+
+fn finish(path: PathBuf, previous_level: LevelFilter, mut files: Vec<(String, Vec<u8>)>) {
+    let capture = CAPTURE.lock().unwrap().take();
+    logging::set_level(previous_level);
+
+    let capture = match capture {
+        Some(v) => v,
+        None => return,
+    };
+
+    let mut log = capture.log.join("\n");
+    if capture.log_dropped != 0 {
+        log.push_str(&format!(
+            "\n{} log records dropped, max. number of records reached",
+            capture.log_dropped
+        ));
+    }
+    files.push(("log.txt".to_string(), log.into_bytes()));
+    files.push(("uplinks.bin".to_string(), capture.frames));
+
+    match write_archive(&path, &files) {
+        Ok(_) => info!(
+            "Diagnostic bundle written, path: {}, log_records: {}, frames: {}",
+            path.display(),
+            capture.log.len(),
+            capture.frame_count
+        ),
+        Err(err) => error!(
+            "Write diagnostic bundle error, path: {}, error: {}",
+            path.display(),
+            err
+        ),
+    }
+}
+
+fn get_duration(req: &api::DiagBundleRequest) -> Duration {
+    match req.duration_ms {
+        0 => DEFAULT_DURATION,
+        v => cmp::min(Duration::from_millis(v.into()), MAX_DURATION),
+    }
+}
+
+fn write_archive(path: &Path, files: &[(String, Vec<u8>)]) -> Result<()> {
+    let mtime = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+    let mut tar = Vec::new();
+    for (name, data) in files {
+        tar_append(&mut tar, name, data, mtime)?;
+    }
+    // The end of the archive is marked by two empty blocks.
+    tar.resize(tar.len() + 2 * BLOCK_SIZE, 0);
+
+    fs::write(path, zstd::encode_all(&tar[..], 0)?)?;
+    Ok(())
+}
+
+// Appends the file to the (ustar format) tar archive.
+fn tar_append(tar: &mut Vec<u8>, name: &str, data: &[u8], mtime: u64) -> Result<()> {
+    if name.len() >= 100 {
+        return Err(anyhow!("file name too long: {}", name));
+    }
+
+    let mut header = [0; BLOCK_SIZE];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[100..108].copy_from_slice(b"0000644\0");
+    header[108..116].copy_from_slice(b"0000000\0");
+    header[116..124].copy_from_slice(b"0000000\0");
+    header[124..136].copy_from_slice(format!("{:011o}\0", data.len()).as_bytes());
+    header[136..148].copy_from_slice(format!("{:011o}\0", mtime).as_bytes());
+    header[156] = b'0';
+    header[257..265].copy_from_slice(b"ustar\x0000");
+
+    // The checksum is calculated with the checksum field set to spaces.
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u32 = header.iter().map(|v| *v as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+
+    tar.extend_from_slice(&header);
+    tar.extend_from_slice(data);
+    let padding = (BLOCK_SIZE - data.len() % BLOCK_SIZE) % BLOCK_SIZE;
+    tar.resize(tar.len() + padding, 0);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_duration() {
+        let tests = vec![
+            (0, DEFAULT_DURATION),
+            (5000, Duration::from_secs(5)),
+            (3_600_000, MAX_DURATION),
+        ];
+
+        for (duration_ms, expected) in tests {
+            let req = api::DiagBundleRequest {
+                duration_ms,
+                ..Default::default()
+            };
+            assert_eq!(expected, get_duration(&req));
+        }
+    }
+
+    #[test]
+    fn test_tar_append() {
+        let mut tar = Vec::new();
+        tar_append(&mut tar, "status.txt", b"hello", 0).unwrap();
+
+        // header + data padded to the block size
+        assert_eq!(2 * BLOCK_SIZE, tar.len());
+        assert_eq!(b"status.txt\0", &tar[..11]);
+        assert_eq!(b"00000000005\0", &tar[124..136]);
+        assert_eq!(b"ustar\x0000", &tar[257..265]);
+        assert_eq!(b"hello\0", &tar[BLOCK_SIZE..BLOCK_SIZE + 6]);
+
+        // the checksum is the sum of the header bytes, with the checksum field as spaces
+        let mut header = tar[..BLOCK_SIZE].to_vec();
+        header[148..156].copy_from_slice(b"        ");
+        let checksum: u32 = header.iter().map(|v| *v as u32).sum();
+        assert_eq!(format!("{:06o}\0 ", checksum).as_bytes(), &tar[148..156]);
+
+        assert!(tar_append(&mut tar, &"a".repeat(100), b"", 0).is_err());
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use super::timesource::{self, TimeSource};
+
+// Max. number of samples used for the drift estimate.
+const MAX_SAMPLES: usize = 1024;
+
+lazy_static! {
+    static ref CONFIG: Mutex<Configuration> = Mutex::new(Default::default());
+}
+
+#[derive(Clone)]
+pub struct Configuration {
+    // Drift (ppm) above which a warning is logged. A failing TCXO typically shows up as a
+    // slowly increasing drift between the concentrator counter and the host clock.
+    pub threshold_ppm: f64,
+    // Duration over which the drift is estimated. A short baseline is dominated by the jitter
+    // of reading the concentrator counter and the host clock.
+    pub baseline: Duration,
+}
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Configuration {
+            threshold_ppm: 10.0,
+            baseline: Duration::from_secs(3600),
+        }
+    }
+}
+
+/// Set the drift configuration.
+pub fn setup(conf: Configuration) {
+    *CONFIG.lock().unwrap() = conf;
+}
+
+/// Returns the drift (ppm) above which a warning is logged.
+pub fn get_threshold_ppm() -> f64 {
+    CONFIG.lock().unwrap().threshold_ppm
+}
+
+/// Drift keeps track of the concentrator counter against the host monotonic clock. The drift
+/// is estimated using a linear regression over the samples within the baseline.
+pub struct Drift {
+    time_source: Arc<dyn TimeSource>,
+    // Host time and linear (not wrapping) concentrator counter of each sample.
+    samples: VecDeque<(Duration, u64)>,
+}
+
+impl Drift {
+    pub fn new() -> Drift {
+        Drift::with_time_source(timesource::get())
+    }
+
+    pub fn with_time_source(time_source: Arc<dyn TimeSource>) -> Drift {
+        Drift {
+            time_source,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Adds a new concentrator counter sample, using the host monotonic clock, and returns the
+    /// estimated drift (ppm). See add.
+    pub fn sample(&mut self, concentrator_count: u32) -> Option<f64> {
+        let now = self.time_source.monotonic();
+        self.add(now, concentrator_count)
+    }
+
+    /// Adds a new concentrator counter sample, taken at the given host time, and returns the
+    /// estimated drift (ppm). The first sample returns None. Note that the interval between two
+    /// samples must be shorter than the concentrator counter rollover (~71 minutes).
+    pub fn add(&mut self, time: Duration, concentrator_count: u32) -> Option<f64> {
+        let conf = CONFIG.lock().unwrap().clone();
+
+        let count = match self.samples.back() {
+            Some((_, prev)) => prev + concentrator_count.wrapping_sub(*prev as u32) as u64,
+            None => concentrator_count as u64,
+        };
+        self.samples.push_back((time, count));
+
+        while self.samples.len() > MAX_SAMPLES
+            || (self.samples.len() > 2 && time.saturating_sub(self.samples[1].0) >= conf.baseline)
+        {
+            self.samples.pop_front();
+        }
+
+        let ppm = estimate_ppm(&self.samples)?;
+
+        debug!(
+            "Concentrator clock drift, drift_ppm: {:.2}, samples: {}",
+            ppm,
+            self.samples.len()
+        );
+        if ppm.abs() > conf.threshold_ppm {
+            warn!(
+                "Concentrator clock drift exceeds threshold, drift_ppm: {:.2}, threshold_ppm: {}",
+                ppm, conf.threshold_ppm
+            );
+        }
+
+        Some(ppm)
+    }
+}
+
+impl Default for Drift {
+    fn default() -> Self {
+        Drift::new()
+    }
+}
+
+// Returns the drift (ppm), using the slope of the least-squares fit of the concentrator counter
+// against the host time. None is returned when there are less than two samples with a
+// different host time.
+fn estimate_ppm(samples: &VecDeque<(Duration, u64)>) -> Option<f64> {
+    let (t0, c0) = *samples.front()?;
+    let points: Vec<(f64, f64)> = samples
+        .iter()
+        .map(|(t, c)| ((*t - t0).as_micros() as f64, (c - c0) as f64))
+        .collect();
+
+    let n = points.len() as f64;
+    let mean_t = points.iter().map(|(t, _)| t).sum::<f64>() / n;
+    let mean_c = points.iter().map(|(_, c)| c).sum::<f64>() / n;
+
+    let (mut cov, mut var) = (0.0, 0.0);
+    for (t, c) in &points {
+        cov += (t - mean_t) * (c - mean_c);
+        var += (t - mean_t) * (t - mean_t);
+    }
+
+    if var == 0.0 {
+        return None;
+    }
+
+    Some((cov / var - 1.0) * 1_000_000.0)
+}
+
+/// Returns the drift (ppm) of the concentrator counter compared to the host clock. A positive
+/// value means that the concentrator counter runs faster than the host clock.
+pub fn calculate_ppm(host_diff: Duration, concentrator_diff: u32) -> Option<f64> {
+    let host_us = host_diff.as_micros() as f64;
+    if host_us == 0.0 {
+        return None;
+    }
+
+    Some((concentrator_diff as f64 - host_us) * 1_000_000.0 / host_us)
+}
+
+/// Returns the estimated error of a time that is extrapolated over the given duration, using a
+/// clock with the given drift (ppm).
+pub fn estimate_error(extrapolated: Duration, drift_ppm: f64) -> Duration {
+    Duration::from_secs_f64(extrapolated.as_secs_f64() * drift_ppm.abs() / 1_000_000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calculate_ppm() {
+        let tests = vec![
+            (Duration::from_secs(1), 1_000_000, Some(0.0)),
+            (Duration::from_secs(1), 1_000_010, Some(10.0)),
+            (Duration::from_secs(10), 9_999_900, Some(-10.0)),
+            (Duration::from_secs(0), 0, None),
+        ];
+
+        for (host_diff, concentrator_diff, expected) in tests {
+            assert_eq!(expected, calculate_ppm(host_diff, concentrator_diff));
+        }
+    }
+
+    #[test]
+    fn test_estimate_error() {
+        assert_eq!(
+            Duration::from_micros(100),
+            estimate_error(Duration::from_secs(10), 10.0)
+        );
+        assert_eq!(
+            Duration::from_micros(100),
+            estimate_error(Duration::from_secs(10), -10.0)
+        );
+        assert_eq!(
+            Duration::from_secs(0),
+            estimate_error(Duration::from_secs(0), 10.0)
+        );
+    }
+
+    #[test]
+    fn test_sample() {
+        let clock = Arc::new(timesource::MockClock::new(std::time::UNIX_EPOCH));
+        let mut drift = Drift::with_time_source(clock.clone());
+
+        assert_eq!(None, drift.sample(u32::MAX - 5));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(Some(10.0), drift.sample(1_000_004));
+    }
+
+    #[test]
+    fn test_add() {
+        let mut drift = Drift::with_time_source(Arc::new(timesource::SystemClock));
+
+        // the jitter of the individual samples is averaged out
+        let tests = vec![(0, 0), (30, 30_000_150), (60, 59_999_850), (90, 90_000_150)];
+        let mut ppm = None;
+        for (t, count) in tests {
+            ppm = drift.add(Duration::from_secs(t), count);
+        }
+        assert!(ppm.unwrap().abs() < 1.0);
+
+        // the samples outside the baseline are removed
+        let baseline = Configuration::default().baseline;
+        drift.add(baseline + Duration::from_secs(60), 0);
+        assert_eq!(3, drift.samples.len());
+    }
+}
@@ -0,0 +1,389 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use log::info;
+
+use super::{api, stats, timesource};
+
+// Sliding window over which the duty-cycle is calculated.
+pub const WINDOW: Duration = Duration::from_secs(3600);
+
+lazy_static! {
+    static ref TRACKER: Mutex<Tracker> = Mutex::new(Default::default());
+}
+
+/// Sub-band with a duty-cycle limit (e.g. the ETSI EN 300 220 sub-bands of EU868).
+#[derive(Clone, PartialEq, Debug)]
+pub struct Band {
+    pub name: String,
+    pub min_freq: u32,
+    pub max_freq: u32,
+    // Duty-cycle (e.g. 0.01 for 1%).
+    pub duty_cycle: f64,
+}
+
+impl Band {
+    pub fn new(min_freq: u32, max_freq: u32, duty_cycle: f64) -> Band {
+        Band {
+            name: format!(
+                "{:.2}-{:.2}",
+                min_freq as f64 / 1_000_000.0,
+                max_freq as f64 / 1_000_000.0
+            ),
+            min_freq,
+            max_freq,
+            duty_cycle,
+        }
+    }
+
+    fn contains(&self, frequency: u32) -> bool {
+        frequency >= self.min_freq && frequency < self.max_freq
+    }
+
+    fn budget(&self) -> Duration {
+        WINDOW.mul_f64(self.duty_cycle)
+    }
+}
+
+/// The duty-cycle budget of a band does not allow the transmission.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Exhausted {
+    pub band: String,
+    pub remaining: Duration,
+    pub time_on_air: Duration,
+}
+
+impl Exhausted {
+    /// Returns the rejection details of the given downlink item.
+    pub fn to_proto(&self, item: usize) -> api::DownlinkRejection {
+        api::DownlinkRejection {
+            item: item as u32,
+            reason: "DUTY_CYCLE".to_string(),
+            band: self.band.clone(),
+            remaining_ms: self.remaining.as_millis() as u32,
+            ..Default::default()
+        }
+    }
+}
+
+impl fmt::Display for Exhausted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "duty-cycle budget exceeded, band: {}, remaining: {:?}, time_on_air: {:?}",
+            self.band, self.remaining, self.time_on_air
+        )
+    }
+}
+
+struct Record {
+    // Monotonic time of the transmission.
+    time: Duration,
+    frequency: u32,
+    time_on_air: Duration,
+}
+
+#[derive(Default)]
+struct Tracker {
+    bands: Vec<Band>,
+    records: VecDeque<Record>,
+}
+
+impl Tracker {
+    fn check(
+        &mut self,
+        frequency: u32,
+        time_on_air: Duration,
+        queued: &[(u32, Duration)],
+        now: Duration,
+    ) -> Result<(), Exhausted> {
+        self.prune(now);
+
+        let band = match self.bands.iter().find(|v| v.contains(frequency)) {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+
+        let remaining = self
+            .remaining(band)
+            .saturating_sub(queued_airtime(band, queued));
+        if time_on_air > remaining {
+            return Err(Exhausted {
+                band: band.name.clone(),
+                remaining,
+                time_on_air,
+            });
+        }
+
+        Ok(())
+    }
+
+    // Returns the time after which the budget of the band allows a transmission of the given
+    // airtime, None when the airtime exceeds the budget of the band. The queued airtime is not
+    // released within the window, as it is recorded once transmitted.
+    fn available_in(
+        &mut self,
+        frequency: u32,
+        time_on_air: Duration,
+        queued: &[(u32, Duration)],
+        now: Duration,
+    ) -> Option<Duration> {
+        self.prune(now);
+
+        let band = match self.bands.iter().find(|v| v.contains(frequency)) {
+            Some(v) => v,
+            None => return Some(Duration::ZERO),
+        };
+
+        let budget = band.budget().checked_sub(queued_airtime(band, queued))?;
+        if time_on_air > budget {
+            return None;
+        }
+
+        let mut records: Vec<&Record> = self
+            .records
+            .iter()
+            .filter(|v| band.contains(v.frequency))
+            .collect();
+        records.sort_by_key(|v| v.time);
+
+        let mut used: Duration = records.iter().map(|v| v.time_on_air).sum();
+        if used + time_on_air <= budget {
+            return Some(Duration::ZERO);
+        }
+
+        // The airtime of a record is released once it is no longer within the window.
+        for r in records {
+            used = used.saturating_sub(r.time_on_air);
+            if used + time_on_air <= budget {
+                return Some((r.time + WINDOW).saturating_sub(now));
+            }
+        }
+
+        None
+    }
+
+    // Records the airtime and returns the band of the given frequency.
+    fn record(&mut self, frequency: u32, time_on_air: Duration, now: Duration) -> Option<&Band> {
+        let band = self.bands.iter().find(|v| v.contains(frequency))?;
+
+        self.records.push_back(Record {
+            time: now,
+            frequency,
+            time_on_air,
+        });
+
+        Some(band)
+    }
+
+    fn remaining(&self, band: &Band) -> Duration {
+        let used: Duration = self
+            .records
+            .iter()
+            .filter(|v| band.contains(v.frequency))
+            .map(|v| v.time_on_air)
+            .sum();
+
+        band.budget().saturating_sub(used)
+    }
+
+    // Removes the records which are no longer within the window. All records are tested, as
+    // the records are not guaranteed to be ordered by time.
+    fn prune(&mut self, now: Duration) {
+        self.records.retain(|v| now.saturating_sub(v.time) < WINDOW);
+    }
+}
+
+// Returns the airtime of the queued packets within the band.
+fn queued_airtime(band: &Band, queued: &[(u32, Duration)]) -> Duration {
+    queued
+        .iter()
+        .filter(|(frequency, _)| band.contains(*frequency))
+        .map(|(_, time_on_air)| *time_on_air)
+        .sum()
+}
+
+/// Set the duty-cycle bands. When empty, the duty-cycle is not tracked. The already recorded
+/// airtime is retained, such that it is still taken into account after a configuration reload.
+pub fn set_bands(bands: Vec<Band>) {
+    let mut tracker = TRACKER.lock().unwrap();
+    if tracker.bands != bands {
+        info!(
+            "Configuring duty-cycle bands, bands: {:?}",
+            bands.iter().map(|v| &v.name).collect::<Vec<&String>>()
+        );
+        tracker.bands = bands;
+    }
+}
+
+/// Returns an error when a transmission of the given airtime on the given frequency would
+/// exceed the duty-cycle budget of its band. The queued argument contains the frequency and
+/// airtime of the packets which are queued, but not yet transmitted.
+pub fn check(
+    frequency: u32,
+    time_on_air: Duration,
+    queued: &[(u32, Duration)],
+) -> Result<(), Exhausted> {
+    TRACKER
+        .lock()
+        .unwrap()
+        .check(frequency, time_on_air, queued, timesource::monotonic())
+}
+
+/// Returns the time after which the duty-cycle budget of the band of the given frequency allows
+/// a transmission of the given airtime. None is returned when the airtime exceeds the budget.
+pub fn available_in(
+    frequency: u32,
+    time_on_air: Duration,
+    queued: &[(u32, Duration)],
+) -> Option<Duration> {
+    TRACKER
+        .lock()
+        .unwrap()
+        .available_in(frequency, time_on_air, queued, timesource::monotonic())
+}
+
+/// Records the airtime of a transmission on the given frequency. This must be called once the
+/// packet has been transmitted, such that the airtime of evicted or failed packets is not
+/// consumed. The transmitted airtime per band is reported as stats counter (tx_airtime_us_BAND).
+pub fn record(frequency: u32, time_on_air: Duration) {
+    let mut tracker = TRACKER.lock().unwrap();
+    if let Some(band) = tracker.record(frequency, time_on_air, timesource::monotonic()) {
+        stats::add_counter(
+            &format!("tx_airtime_us_{}", band.name),
+            time_on_air.as_micros() as u64,
+        );
+    }
+}
+
+/// Returns the remaining duty-cycle budget (ms) per band, as stats meta-data.
+pub fn get_metadata() -> HashMap<String, String> {
+    let mut tracker = TRACKER.lock().unwrap();
+    tracker.prune(timesource::monotonic());
+
+    tracker
+        .bands
+        .iter()
+        .map(|v| {
+            (
+                format!("duty_cycle_remaining_ms_{}", v.name),
+                tracker.remaining(v).as_millis().to_string(),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_band() {
+        let band = Band::new(868000000, 868600000, 0.01);
+        assert_eq!("868.00-868.60", band.name);
+        assert_eq!(Duration::from_secs(36), band.budget());
+        assert!(band.contains(868100000));
+        assert!(!band.contains(868600000));
+    }
+
+    #[test]
+    fn test_tracker() {
+        let mut tracker = Tracker {
+            bands: vec![Band::new(868000000, 868600000, 0.01)],
+            records: VecDeque::new(),
+        };
+        let now = Duration::from_secs(10);
+
+        // frequencies outside the bands are not limited
+        assert!(tracker
+            .check(869525000, Duration::from_secs(60), &[], now)
+            .is_ok());
+        tracker.record(869525000, Duration::from_secs(60), now);
+        assert!(tracker.records.is_empty());
+
+        tracker.record(868100000, Duration::from_secs(30), now);
+        assert!(tracker
+            .check(868300000, Duration::from_secs(6), &[], now)
+            .is_ok());
+        assert_eq!(
+            Err(Exhausted {
+                band: "868.00-868.60".to_string(),
+                remaining: Duration::from_secs(6),
+                time_on_air: Duration::from_secs(7),
+            }),
+            tracker.check(868300000, Duration::from_secs(7), &[], now)
+        );
+
+        // the queued airtime is taken into account
+        let queued = [
+            (868500000, Duration::from_secs(1)),
+            (869525000, Duration::from_secs(1)),
+        ];
+        assert!(tracker
+            .check(868300000, Duration::from_secs(6), &queued, now)
+            .is_err());
+        assert!(tracker
+            .check(868300000, Duration::from_secs(5), &queued, now)
+            .is_ok());
+
+        // records which are out of order are pruned too
+        tracker.record(
+            868100000,
+            Duration::from_secs(1),
+            now + Duration::from_secs(60),
+        );
+        tracker.record(868100000, Duration::from_secs(1), now);
+        tracker.prune(now + WINDOW);
+        assert_eq!(1, tracker.records.len());
+
+        // the airtime expires after the window
+        let later = now + WINDOW + Duration::from_secs(60);
+        assert!(tracker
+            .check(868300000, Duration::from_secs(7), &[], later)
+            .is_ok());
+        assert!(tracker.records.is_empty());
+    }
+
+    #[test]
+    fn test_tracker_available_in() {
+        let mut tracker = Tracker {
+            bands: vec![Band::new(868000000, 868600000, 0.01)],
+            records: VecDeque::new(),
+        };
+        let now = Duration::from_secs(10);
+
+        assert_eq!(
+            Some(Duration::ZERO),
+            tracker.available_in(869525000, Duration::from_secs(60), &[], now)
+        );
+        assert_eq!(
+            None,
+            tracker.available_in(868100000, Duration::from_secs(37), &[], now)
+        );
+
+        tracker.record(868100000, Duration::from_secs(20), now);
+        tracker.record(
+            868100000,
+            Duration::from_secs(10),
+            now + Duration::from_secs(60),
+        );
+        assert_eq!(
+            Some(Duration::ZERO),
+            tracker.available_in(868100000, Duration::from_secs(6), &[], now)
+        );
+
+        // the airtime of the first record is released after the window
+        assert_eq!(
+            Some(WINDOW),
+            tracker.available_in(868100000, Duration::from_secs(7), &[], now)
+        );
+
+        // both records must be released
+        assert_eq!(
+            Some(WINDOW + Duration::from_secs(60)),
+            tracker.available_in(868100000, Duration::from_secs(30), &[], now)
+        );
+    }
+}
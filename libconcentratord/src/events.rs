@@ -1,49 +1,644 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
 
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
 use anyhow::Result;
-use log::info;
+use log::{error, info};
 use prost::Message;
 
+use super::api;
+use super::blacklist;
+use super::curve;
+use super::diag;
+use super::forwarder;
+use super::history;
+use super::joinflood;
+use super::metered;
+use super::metrics;
+use super::oversize;
 use super::socket::ZMQ_CONTEXT;
+use super::timesource;
+use super::txtest;
+use super::uplinkfilter;
+
+// Size of the AES-GCM nonce, which is prepended to the encrypted event payloads.
+const NONCE_SIZE: usize = 12;
+
+// Max. duration of a live frame tap, such that a forgotten tap does not run forever.
+const TAP_MAX_DURATION: Duration = Duration::from_secs(600);
 
 lazy_static! {
     static ref ZMQ_PUB: Mutex<Option<zmq::Socket>> = Mutex::new(None);
+    // Endpoint and CURVE secret key of the bound socket.
+    static ref PUB_BIND: Mutex<(String, Option<Vec<u8>>)> = Mutex::new((String::new(), None));
+    static ref COMPRESSION: Mutex<Compression> = Mutex::new(Compression::None);
+    static ref ENCRYPTION: Mutex<Option<Aes256Gcm>> = Mutex::new(None);
+    static ref UPLINK_MODE: Mutex<UplinkMode> = Mutex::new(UplinkMode::Full);
+    static ref TAP: Mutex<Option<Tap>> = Mutex::new(None);
+}
+
+struct Tap {
+    // Remaining number of frames, None when not limited.
+    remaining: Option<u32>,
+    // Monotonic time at which the tap ends.
+    deadline: Duration,
+}
+
+/// Compression applied to the event payloads.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Compression {
+    None,
+    Zstd,
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Compression::None => write!(f, "NONE"),
+            Compression::Zstd => write!(f, "ZSTD"),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "" | "NONE" => Compression::None,
+            "ZSTD" => Compression::Zstd,
+            _ => return Err(anyhow!("unexpected compression: {}", s)),
+        })
+    }
+}
+
+/// Content of the published uplink events.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UplinkMode {
+    // The complete uplink frame is published.
+    Full,
+    // The uplink frame is published without PHYPayload.
+    MetadataOnly,
+}
+
+impl fmt::Display for UplinkMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UplinkMode::Full => write!(f, "FULL"),
+            UplinkMode::MetadataOnly => write!(f, "METADATA_ONLY"),
+        }
+    }
+}
+
+impl FromStr for UplinkMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "" | "FULL" => UplinkMode::Full,
+            "METADATA_ONLY" => UplinkMode::MetadataOnly,
+            _ => return Err(anyhow!("unexpected uplink mode: {}", s)),
+        })
+    }
+}
+
+// Only one out of every UPLINK_SAMPLING uplinks is published (0 and 1 publish all uplinks).
+static UPLINK_SAMPLING: AtomicU64 = AtomicU64::new(0);
+static UPLINK_COUNT: AtomicU64 = AtomicU64::new(0);
+
+// The sequence number is not reset when the socket is re-created (e.g. after a configuration
+// update), so that it is monotonically increasing for the lifetime of the process.
+static SEQUENCE_ENABLED: AtomicBool = AtomicBool::new(false);
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+// Identifies the monitor of the current event socket, see monitor_subscribers.
+static MONITOR_ID: AtomicU64 = AtomicU64::new(0);
+static SUBSCRIBERS: AtomicU64 = AtomicU64::new(0);
+
+/// Bind the event PUB socket.
+///
+/// When sequence is set, every event is published with a third frame containing a
+/// monotonically increasing sequence number (u64, big endian). This makes it possible for
+/// subscribers to detect dropped events (e.g. on HWM overflow).
+///
+/// When compression is set, the event payloads are compressed before publishing. Subscribers
+/// can retrieve the used compression using the capabilities command.
+///
+/// When encryption_key is set (32 bytes), the (compressed) event payloads are encrypted using
+/// AES-256-GCM with the topic as associated data, see decrypt. This is intended for deployments
+/// where the event socket crosses an untrusted network segment.
+pub fn bind_socket(
+    bind: &str,
+    sequence: bool,
+    compression: Compression,
+    encryption_key: &[u8],
+) -> Result<()> {
+    info!(
+        "Creating socket for publishing events, bind: {}, sequence: {}, compression: {}, encryption: {}",
+        bind,
+        sequence,
+        compression,
+        !encryption_key.is_empty()
+    );
+
+    SEQUENCE_ENABLED.store(sequence, Ordering::SeqCst);
+    *COMPRESSION.lock().unwrap() = compression;
+    *ENCRYPTION.lock().unwrap() = if encryption_key.is_empty() {
+        None
+    } else {
+        Some(
+            Aes256Gcm::new_from_slice(encryption_key)
+                .map_err(|_| anyhow!("encryption key must be exactly 32 bytes"))?,
+        )
+    };
+
+    // The error is logged after the locks are released, as the log messages might be
+    // published as events (see log_event_level).
+    let monitor_result = {
+        let zmq_ctx = ZMQ_CONTEXT.lock().unwrap();
+        let mut zmq_pub = ZMQ_PUB.lock().unwrap();
+        let mut pub_bind = PUB_BIND.lock().unwrap();
+
+        // Keep the existing socket when it is already bound to the same endpoint using the same
+        // CURVE settings (e.g. on a configuration reload), such that the subscribers stay
+        // connected.
+        let secret_key = curve::secret_key();
+        if zmq_pub.is_some() && pub_bind.0 == bind && pub_bind.1 == secret_key {
+            return Ok(());
+        }
+
+        // Close the previous socket first, as it might be bound to the same endpoint.
+        *zmq_pub = None;
+
+        let sock = zmq_ctx.socket(zmq::PUB)?;
+        curve::apply(&sock)?;
+        sock.bind(&bind)?;
+        let monitor_result = monitor_subscribers(&zmq_ctx, &sock);
+
+        *zmq_pub = Some(sock);
+        *pub_bind = (bind.to_string(), secret_key);
+
+        monitor_result
+    };
+
+    if let Err(err) = monitor_result {
+        error!("Setup event socket monitor error, error: {}", err);
+    }
+
+    Ok(())
+}
+
+// Tracks the number of clients connected to the event socket, which is exposed as metric. The
+// monitor thread ends when the socket is closed.
+fn monitor_subscribers(zmq_ctx: &zmq::Context, sock: &zmq::Socket) -> Result<()> {
+    let id = MONITOR_ID.fetch_add(1, Ordering::SeqCst) + 1;
+    let endpoint = format!("inproc://event-monitor-{}", id);
+    sock.monitor(
+        &endpoint,
+        (zmq::SocketEvent::ACCEPTED.to_raw()
+            | zmq::SocketEvent::DISCONNECTED.to_raw()
+            | zmq::SocketEvent::MONITOR_STOPPED.to_raw()) as i32,
+    )?;
+
+    let monitor = zmq_ctx.socket(zmq::PAIR)?;
+    monitor.connect(&endpoint)?;
+    SUBSCRIBERS.store(0, Ordering::SeqCst);
+    metrics::set_gauge("event_subscribers", &[], 0.0);
+
+    thread::spawn(move || {
+        let mut subscribers: u64 = 0;
+
+        loop {
+            // The first frame contains the event (u16) and the event value (u32).
+            let msg = match monitor.recv_multipart(0) {
+                Ok(v) => v,
+                Err(_) => break,
+            };
+            let event = match msg.first() {
+                Some(v) if v.len() >= 2 => {
+                    zmq::SocketEvent::from_raw(u16::from_ne_bytes([v[0], v[1]]))
+                }
+                _ => continue,
+            };
+
+            match event {
+                zmq::SocketEvent::ACCEPTED => subscribers += 1,
+                zmq::SocketEvent::DISCONNECTED => subscribers = subscribers.saturating_sub(1),
+                zmq::SocketEvent::MONITOR_STOPPED => break,
+                _ => continue,
+            }
+
+            // The monitor of a previous socket must not overwrite the value of the current one.
+            if MONITOR_ID.load(Ordering::SeqCst) == id {
+                SUBSCRIBERS.store(subscribers, Ordering::SeqCst);
+                metrics::set_gauge("event_subscribers", &[], subscribers as f64);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Returns the number of clients connected to the event socket.
+pub fn get_subscribers() -> u64 {
+    SUBSCRIBERS.load(Ordering::SeqCst)
 }
 
-pub fn bind_socket(bind: &str) -> Result<()> {
-    info!("Creating socket for publishing events, bind: {}", bind);
+/// Set the uplink mode and sampling of the published uplink events. When sampling is set to
+/// N (> 1), only one out of every N uplinks is published. This is intended for gateways with
+/// a constrained backhaul, the uplinks can be retained locally using the capture file.
+pub fn set_uplink_filter(mode: UplinkMode, sampling: u64) {
+    info!(
+        "Setting uplink event filter, mode: {}, sampling: {}",
+        mode, sampling
+    );
+
+    *UPLINK_MODE.lock().unwrap() = mode;
+    UPLINK_SAMPLING.store(sampling, Ordering::SeqCst);
+}
 
-    let zmq_ctx = ZMQ_CONTEXT.lock().unwrap();
-    let mut zmq_pub = ZMQ_PUB.lock().unwrap();
+/// Start a live frame tap. Until the tap ends, every received frame is published as tap event
+/// (including the frames with an invalid CRC), regardless of the uplink mode and sampling. A
+/// running tap is replaced.
+pub fn start_tap(pl: &api::TapRequest) -> Result<()> {
+    let duration = get_tap_duration(pl)?;
 
-    let sock = zmq_ctx.socket(zmq::PUB)?;
-    sock.bind(&bind)?;
+    info!(
+        "Starting live frame tap, max_frames: {}, duration: {:?}",
+        pl.max_frames, duration
+    );
 
-    *zmq_pub = Some(sock);
+    let mut tap = TAP.lock().unwrap();
+    *tap = Some(Tap {
+        remaining: if pl.max_frames == 0 {
+            None
+        } else {
+            Some(pl.max_frames)
+        },
+        deadline: timesource::monotonic() + duration,
+    });
 
     Ok(())
 }
 
+/// Returns true when a live frame tap is running.
+pub fn tap_active() -> bool {
+    TAP.lock().unwrap().is_some()
+}
+
+/// Publish the given frame as tap event, when a live frame tap is running.
+pub fn send_tap(pl: &chirpstack_api::gw::UplinkFrame) {
+    // A running diagnostic bundle captures the same frames.
+    diag::capture_uplink(pl);
+
+    let ended = {
+        let mut tap = TAP.lock().unwrap();
+        let t = match tap.as_mut() {
+            Some(v) => v,
+            None => return,
+        };
+
+        let ended = if timesource::monotonic() >= t.deadline {
+            Some("duration")
+        } else {
+            if let Some(pub_guard) = ZMQ_PUB.lock().unwrap().as_ref() {
+                send_event(pub_guard, "tap", pl.encode_to_vec());
+            }
+
+            match t.remaining.as_mut() {
+                Some(v) => {
+                    *v -= 1;
+                    if *v == 0 {
+                        Some("max_frames")
+                    } else {
+                        None
+                    }
+                }
+                None => None,
+            }
+        };
+
+        if ended.is_some() {
+            *tap = None;
+        }
+
+        ended
+    };
+
+    // This is logged after releasing the ZMQ_PUB lock, as the log record might be published as
+    // log event.
+    if let Some(reason) = ended {
+        info!("Live frame tap ended, reason: {}", reason);
+    }
+}
+
+// Returns the duration of the tap. If only max_frames is set, the max. duration is used.
+fn get_tap_duration(pl: &api::TapRequest) -> Result<Duration> {
+    if pl.max_frames == 0 && pl.duration_ms == 0 {
+        return Err(anyhow!("max_frames and / or duration_ms must be set"));
+    }
+
+    let duration = Duration::from_millis(pl.duration_ms.into());
+    if pl.duration_ms == 0 || duration > TAP_MAX_DURATION {
+        return Ok(TAP_MAX_DURATION);
+    }
+
+    Ok(duration)
+}
+
 pub fn send_uplink(pl: &chirpstack_api::gw::UplinkFrame) -> Result<()> {
+    // The uplinks are suspended during a certification TX test.
+    if txtest::certification_active() {
+        return Ok(());
+    }
+
+    send_tap(pl);
+
+    let truncated;
+    let pl = match oversize::apply(pl) {
+        oversize::Outcome::Forward => pl,
+        oversize::Outcome::Drop => return Ok(()),
+        oversize::Outcome::Truncate(size) => {
+            truncated = oversize::truncate(pl, size);
+            &truncated
+        }
+    };
+
+    let annotated;
+    let pl = match uplinkfilter::apply(pl) {
+        uplinkfilter::Outcome::Forward => pl,
+        uplinkfilter::Outcome::Drop => return Ok(()),
+        uplinkfilter::Outcome::Annotate(name) => {
+            annotated = uplinkfilter::annotate(pl, &name);
+            &annotated
+        }
+    };
+
+    if !joinflood::allow(pl) {
+        return Ok(());
+    }
+
+    if !blacklist::allow(pl) {
+        return Ok(());
+    }
+
+    forwarder::send_uplink(pl);
+
+    let sampling = UPLINK_SAMPLING.load(Ordering::SeqCst);
+    if sampling > 1 && UPLINK_COUNT.fetch_add(1, Ordering::SeqCst) % sampling != 0 {
+        return Ok(());
+    }
+
+    let b = match *UPLINK_MODE.lock().unwrap() {
+        UplinkMode::Full => pl.encode_to_vec(),
+        UplinkMode::MetadataOnly => chirpstack_api::gw::UplinkFrame {
+            phy_payload: Vec::new(),
+            ..pl.clone()
+        }
+        .encode_to_vec(),
+    };
+
+    let pub_guard = ZMQ_PUB.lock().unwrap();
+    let publisher = pub_guard.as_ref().unwrap();
+    send_event(publisher, "up", b);
+
+    Ok(())
+}
+
+pub fn send_stats(stats: &chirpstack_api::gw::GatewayStats) -> Result<()> {
+    // This is logged before acquiring the ZMQ_PUB lock, as the log record might be published
+    // as log event.
+    info!("Publishing stats event, rx_received: {}, rx_received_ok: {}, tx_received: {}, tx_emitted: {}", stats.rx_packets_received, stats.rx_packets_received_ok, stats.tx_packets_received, stats.tx_packets_emitted);
+    forwarder::send_stats(stats);
+
+    let pub_guard = ZMQ_PUB.lock().unwrap();
+    let publisher = pub_guard.as_ref().unwrap();
+
+    let b = stats.encode_to_vec();
+    send_event(publisher, "stats", b);
+
+    Ok(())
+}
+
+pub fn send_tx_done(pl: &api::TxDone) -> Result<()> {
+    history::record_tx_done(pl);
+
     let pub_guard = ZMQ_PUB.lock().unwrap();
     let publisher = pub_guard.as_ref().unwrap();
 
     let b = pl.encode_to_vec();
-    publisher.send("up", zmq::SNDMORE).unwrap();
-    publisher.send(b, 0).unwrap();
+    send_event(publisher, "txdone", b);
 
     Ok(())
 }
 
-pub fn send_stats(stats: &chirpstack_api::gw::GatewayStats) -> Result<()> {
+pub fn send_state(pl: &api::StateEvent) -> Result<()> {
+    let pub_guard = ZMQ_PUB.lock().unwrap();
+    let publisher = match pub_guard.as_ref() {
+        Some(v) => v,
+        None => return Err(anyhow!("event socket is not bound")),
+    };
+
+    let b = pl.encode_to_vec();
+    send_event(publisher, "state", b);
+
+    Ok(())
+}
+
+pub fn send_tx_test_progress(pl: &api::TxTestProgress) -> Result<()> {
     let pub_guard = ZMQ_PUB.lock().unwrap();
     let publisher = pub_guard.as_ref().unwrap();
 
-    info!("Publishing stats event, rx_received: {}, rx_received_ok: {}, tx_received: {}, tx_emitted: {}", stats.rx_packets_received, stats.rx_packets_received_ok, stats.tx_packets_received, stats.tx_packets_emitted);
+    let b = pl.encode_to_vec();
+    send_event(publisher, "txtest", b);
 
-    let b = stats.encode_to_vec();
-    publisher.send("stats", zmq::SNDMORE).unwrap();
-    publisher.send(b, 0).unwrap();
+    Ok(())
+}
+
+pub fn send_spectral_scan(pl: &api::SpectralScan) -> Result<()> {
+    let pub_guard = ZMQ_PUB.lock().unwrap();
+    let publisher = pub_guard.as_ref().unwrap();
+
+    let b = pl.encode_to_vec();
+    send_event(publisher, "spectral_scan", b);
+
+    Ok(())
+}
+
+pub fn send_error(pl: &api::ErrorEvent) -> Result<()> {
+    let pub_guard = ZMQ_PUB.lock().unwrap();
+    let publisher = match pub_guard.as_ref() {
+        Some(v) => v,
+        None => return Err(anyhow!("event socket is not bound")),
+    };
+
+    let b = pl.encode_to_vec();
+    send_event(publisher, "error", b);
+
+    Ok(())
+}
+
+pub fn send_restart(pl: &api::RestartEvent) -> Result<()> {
+    let pub_guard = ZMQ_PUB.lock().unwrap();
+    let publisher = match pub_guard.as_ref() {
+        Some(v) => v,
+        None => return Err(anyhow!("event socket is not bound")),
+    };
+
+    let b = pl.encode_to_vec();
+    send_event(publisher, "restart", b);
+
+    Ok(())
+}
+
+pub fn send_timesync_request(pl: &api::TimeSyncRequest) -> Result<()> {
+    let pub_guard = ZMQ_PUB.lock().unwrap();
+    let publisher = match pub_guard.as_ref() {
+        Some(v) => v,
+        None => return Err(anyhow!("event socket is not bound")),
+    };
+
+    let b = pl.encode_to_vec();
+    send_event(publisher, "timesync", b);
+
+    Ok(())
+}
+
+pub fn send_log(pl: &api::LogEvent) -> Result<()> {
+    // Log events are optional and suppressed on a metered backhaul.
+    if metered::is_metered() {
+        return Ok(());
+    }
+
+    let pub_guard = ZMQ_PUB.lock().unwrap();
+    let publisher = match pub_guard.as_ref() {
+        Some(v) => v,
+        None => return Err(anyhow!("event socket is not bound")),
+    };
+
+    let b = pl.encode_to_vec();
+    send_event(publisher, "log", b);
 
     Ok(())
 }
+
+pub fn get_capabilities() -> api::Capabilities {
+    api::Capabilities {
+        event_compression: COMPRESSION.lock().unwrap().to_string(),
+        supported_event_compression: vec![
+            Compression::None.to_string(),
+            Compression::Zstd.to_string(),
+        ],
+        event_sequence: SEQUENCE_ENABLED.load(Ordering::SeqCst),
+        event_encryption: match *ENCRYPTION.lock().unwrap() {
+            Some(_) => "AES256_GCM".to_string(),
+            None => "NONE".to_string(),
+        },
+    }
+}
+
+/// Decrypts an event payload that was encrypted using the given key. This is the reverse of the
+/// encryption applied before publishing, the returned payload might still be compressed.
+pub fn decrypt(encryption_key: &[u8], topic: &str, b: &[u8]) -> Result<Vec<u8>> {
+    let cipher = Aes256Gcm::new_from_slice(encryption_key)
+        .map_err(|_| anyhow!("encryption key must be exactly 32 bytes"))?;
+    if b.len() < NONCE_SIZE {
+        return Err(anyhow!("payload is too short"));
+    }
+
+    let (nonce, msg) = b.split_at(NONCE_SIZE);
+    cipher
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg,
+                aad: topic.as_bytes(),
+            },
+        )
+        .map_err(|_| anyhow!("decrypt event payload error"))
+}
+
+// Encrypts the event payload. The returned payload contains the random nonce, followed by the
+// ciphertext and authentication tag.
+fn encrypt(cipher: &Aes256Gcm, topic: &str, b: &[u8]) -> Vec<u8> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let mut out = nonce.to_vec();
+    out.extend(
+        cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: b,
+                    aad: topic.as_bytes(),
+                },
+            )
+            .unwrap(),
+    );
+    out
+}
+
+// This must be called while holding the ZMQ_PUB lock, which guarantees that the events are
+// published in the order of their sequence number.
+fn send_event(publisher: &zmq::Socket, topic: &str, b: Vec<u8>) {
+    let b = match *COMPRESSION.lock().unwrap() {
+        Compression::None => b,
+        Compression::Zstd => zstd::encode_all(&b[..], 0).unwrap(),
+    };
+    let b = match ENCRYPTION.lock().unwrap().as_ref() {
+        Some(cipher) => encrypt(cipher, topic, &b),
+        None => b,
+    };
+
+    publisher.send(topic, zmq::SNDMORE).unwrap();
+
+    if SEQUENCE_ENABLED.load(Ordering::SeqCst) {
+        let seq = SEQUENCE.fetch_add(1, Ordering::SeqCst);
+        publisher.send(b, zmq::SNDMORE).unwrap();
+        publisher.send(&seq.to_be_bytes()[..], 0).unwrap();
+    } else {
+        publisher.send(b, 0).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let key = [1; 32];
+        let cipher = Aes256Gcm::new_from_slice(&key).unwrap();
+
+        let b = encrypt(&cipher, "up", b"hello");
+        assert_eq!(NONCE_SIZE + 5 + 16, b.len());
+        assert_eq!(b"hello".to_vec(), decrypt(&key, "up", &b).unwrap());
+
+        // the topic is authenticated
+        assert!(decrypt(&key, "stats", &b).is_err());
+        assert!(decrypt(&[2; 32], "up", &b).is_err());
+        assert!(decrypt(&key[..16], "up", &b).is_err());
+    }
+
+    #[test]
+    fn test_get_tap_duration() {
+        let tests = vec![
+            (0, 0, None),
+            (10, 0, Some(TAP_MAX_DURATION)),
+            (0, 5000, Some(Duration::from_secs(5))),
+            (10, 3_600_000, Some(TAP_MAX_DURATION)),
+        ];
+
+        for (max_frames, duration_ms, expected) in tests {
+            let pl = api::TapRequest {
+                max_frames,
+                duration_ms,
+            };
+            assert_eq!(expected, get_tap_duration(&pl).ok());
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use log::{error, info};
+
+use super::reset;
+
+lazy_static! {
+    static ref FEEDBACK: Mutex<Option<Feedback>> = Mutex::new(None);
+}
+
+// Set once the first uplink has been received, such that the feedback is only given once
+// after startup.
+static TRIGGERED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone)]
+pub struct Configuration {
+    // GPIO chip (or I2C GPIO expander, see reset::find_line) and line of e.g. a buzzer. Not
+    // used when the chip is empty.
+    pub chip: String,
+    pub line: u32,
+    // The line is asserted by setting it low.
+    pub active_low: bool,
+    // Time the line is asserted.
+    pub duration: Duration,
+    // Command (argv) that is executed, not used when empty. The frequency, RSSI and SNR of the
+    // uplink are passed as UPLINK_FREQUENCY, UPLINK_RSSI and UPLINK_SNR environment variables.
+    pub command: Vec<String>,
+}
+
+struct Feedback {
+    line: Option<reset::Line>,
+    active_low: bool,
+    duration: Duration,
+    command: Vec<String>,
+}
+
+/// Setup the installer feedback, which is given on the first valid uplink after startup to
+/// confirm that the RF path works.
+pub fn setup(conf: &Configuration) -> Result<()> {
+    info!(
+        "Setting up first uplink feedback, chip: {}, line: {}, active_low: {}, duration: {:?}, command: {:?}",
+        conf.chip, conf.line, conf.active_low, conf.duration, conf.command
+    );
+
+    let line = if conf.chip.is_empty() {
+        None
+    } else {
+        let line = reset::Line::request(&conf.chip, conf.line, "first_uplink")?;
+        line.set_value(conf.active_low as u8)?;
+        Some(line)
+    };
+
+    *FEEDBACK.lock().unwrap() = Some(Feedback {
+        line,
+        active_low: conf.active_low,
+        duration: conf.duration,
+        command: conf.command.clone(),
+    });
+
+    Ok(())
+}
+
+/// Gives the feedback when this is the first valid uplink after startup. This is a no-op
+/// when the feedback has not been setup.
+pub fn uplink_received(pl: &chirpstack_api::gw::UplinkFrame) {
+    if TRIGGERED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let feedback = match FEEDBACK.lock().unwrap().take() {
+        Some(v) => v,
+        None => return,
+    };
+
+    let frequency = pl.tx_info.as_ref().map(|v| v.frequency).unwrap_or_default();
+    let (rssi, snr) = pl
+        .rx_info
+        .as_ref()
+        .map(|v| (v.rssi, v.snr))
+        .unwrap_or_default();
+
+    info!(
+        "First uplink received, frequency: {}, rssi: {}, snr: {}",
+        frequency, rssi, snr
+    );
+
+    // The feedback is given from a separate thread, as this is called from the uplink loop.
+    thread::spawn(move || {
+        if let Some(line) = &feedback.line {
+            if let Err(err) = pulse(line, feedback.active_low, feedback.duration) {
+                error!("First uplink feedback line error, error: {}", err);
+            }
+        }
+
+        if !feedback.command.is_empty() {
+            if let Err(err) = execute(&feedback.command, frequency, rssi, snr) {
+                error!("First uplink feedback command error, error: {}", err);
+            }
+        }
+    });
+}
+
+fn pulse(line: &reset::Line, active_low: bool, duration: Duration) -> Result<()> {
+    line.set_value(!active_low as u8)?;
+    thread::sleep(duration);
+    line.set_value(active_low as u8)
+}
+
+fn execute(argv: &[String], frequency: u32, rssi: i32, snr: f32) -> Result<()> {
+    let status = Command::new(&argv[0])
+        .args(&argv[1..])
+        .env("UPLINK_FREQUENCY", frequency.to_string())
+        .env("UPLINK_RSSI", rssi.to_string())
+        .env("UPLINK_SNR", snr.to_string())
+        .stdin(Stdio::null())
+        .status()?;
+
+    if !status.success() {
+        return Err(anyhow!("command exited with {}", status));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,620 @@
+//! Semtech UDP packet-forwarder compatibility, such that Concentratord can be used with network
+//! servers implementing the (legacy) Semtech UDP Gateway Messaging Protocol. The uplinks and
+//! stats are sent as PUSH_DATA, the downlinks are received as PULL_RESP on the socket that is
+//! kept alive by PULL_DATA. This runs alongside the ZMQ API.
+use std::io::ErrorKind;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicU16, AtomicU32, AtomicU64, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use anyhow::Result;
+use chirpstack_api::gw;
+use chrono::{DateTime, Utc};
+use log::{debug, error, info, warn};
+use serde_json::{json, Value};
+
+use super::signals::Signal;
+use super::timesource;
+
+const PROTOCOL_VERSION: u8 = 0x02;
+const PUSH_DATA: u8 = 0x00;
+const PUSH_ACK: u8 = 0x01;
+const PULL_DATA: u8 = 0x02;
+const PULL_RESP: u8 = 0x03;
+const PULL_ACK: u8 = 0x04;
+const TX_ACK: u8 = 0x05;
+
+// Timeout for reading from the socket, such that we can consume from the stop signal.
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+// Interval between two attempts to resolve the server and connect the sockets, e.g. when DNS or
+// the network is not yet ready at boot.
+const CONNECT_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    static ref FORWARDER: Mutex<Option<Forwarder>> = Mutex::new(None);
+}
+
+static TOKEN: AtomicU16 = AtomicU16::new(0);
+static DOWNLINK_ID: AtomicU32 = AtomicU32::new(0);
+static PUSH_COUNT: AtomicU64 = AtomicU64::new(0);
+static PUSH_ACK_COUNT: AtomicU64 = AtomicU64::new(0);
+
+struct Forwarder {
+    gateway_id: Vec<u8>,
+    socket: UdpSocket,
+}
+
+#[derive(Clone)]
+pub struct Configuration {
+    // Hostname or IP address of the server.
+    pub server: String,
+    // Server port for the uplinks and stats (PUSH_DATA).
+    pub port_up: u16,
+    // Server port for the downlinks (PULL_DATA).
+    pub port_down: u16,
+    // Interval between two PULL_DATA keep-alive packets.
+    pub keepalive_interval: Duration,
+}
+
+/// Close the socket for sending the uplinks and stats, e.g. when the forwarder has been disabled
+/// by a configuration reload.
+pub fn close() {
+    *FORWARDER.lock().unwrap() = None;
+}
+
+/// Forward the uplink as PUSH_DATA rxpk.
+pub fn send_uplink(pl: &gw::UplinkFrame) {
+    if let Err(err) = push(|| Ok(json!({ "rxpk": [rxpk_from_proto(pl)?] }))) {
+        error!("Forward uplink error, error: {}", err);
+    }
+}
+
+/// Forward the stats as PUSH_DATA stat.
+pub fn send_stats(stats: &gw::GatewayStats) {
+    if let Err(err) = push(|| Ok(json!({ "stat": stat_from_proto(stats) }))) {
+        error!("Forward stats error, error: {}", err);
+    }
+}
+
+fn push<F>(data: F) -> Result<()>
+where
+    F: FnOnce() -> Result<Value>,
+{
+    let forwarder = FORWARDER.lock().unwrap();
+    let forwarder = match forwarder.as_ref() {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+
+    // Drain the PUSH_ACK packets of the previous PUSH_DATA packets.
+    let mut b = [0; 12];
+    while let Ok(size) = forwarder.socket.recv(&mut b) {
+        if size >= 4 && b[3] == PUSH_ACK {
+            PUSH_ACK_COUNT.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    let b = packet(
+        PUSH_DATA,
+        next_token(),
+        &forwarder.gateway_id,
+        Some(&data()?),
+    );
+    forwarder.socket.send(&b)?;
+    PUSH_COUNT.fetch_add(1, Ordering::Relaxed);
+
+    Ok(())
+}
+
+/// Keeps the downlink path alive using PULL_DATA and handles the received PULL_RESP downlinks.
+/// The given function must enqueue the downlink and return its TX acknowledgement.
+///
+/// The server is resolved and the sockets are connected by this loop, this is retried every
+/// CONNECT_RETRY_INTERVAL as DNS or the network might not be ready yet. Until connected,
+/// send_uplink and send_stats are a no-op.
+pub fn pull_loop<F>(
+    conf: &Configuration,
+    gateway_id: &[u8],
+    handle_downlink: F,
+    stop_receive: Receiver<Signal>,
+) where
+    F: Fn(&gw::DownlinkFrame) -> Result<gw::DownlinkTxAck>,
+{
+    info!(
+        "Starting UDP packet-forwarder pull loop, server: {}, port_down: {}, keepalive_interval: {:?}",
+        conf.server, conf.port_down, conf.keepalive_interval
+    );
+
+    // The socket of the previous loop (e.g. before a configuration reload) is closed, as the
+    // server might have changed.
+    close();
+
+    let mut pull_socket: Option<UdpSocket> = None;
+    let mut last_connect: Option<Instant> = None;
+    let mut last_pull: Option<Instant> = None;
+    let mut b = [0; 65535];
+
+    loop {
+        // When not connected, the stop channel timeout is the delay between the retries.
+        let timeout = match pull_socket {
+            Some(_) => Duration::from_millis(0),
+            None => READ_TIMEOUT,
+        };
+        match stop_receive.recv_timeout(timeout) {
+            Ok(v) => {
+                debug!("Received stop signal, signal: {}", v);
+                break;
+            }
+            _ => {}
+        };
+
+        if pull_socket.is_none()
+            && last_connect.map_or(true, |v| v.elapsed() >= CONNECT_RETRY_INTERVAL)
+        {
+            last_connect = Some(Instant::now());
+            match connect_forwarder(conf, gateway_id) {
+                Ok(v) => pull_socket = Some(v),
+                Err(err) => error!(
+                    "Setup UDP packet-forwarder error, retry_interval: {:?}, error: {}",
+                    CONNECT_RETRY_INTERVAL, err
+                ),
+            }
+        }
+
+        let socket = match pull_socket.as_ref() {
+            Some(v) => v,
+            None => continue,
+        };
+
+        if last_pull.map_or(true, |v| v.elapsed() >= conf.keepalive_interval) {
+            if let Err(err) = socket.send(&packet(PULL_DATA, next_token(), gateway_id, None)) {
+                error!("Send PULL_DATA error, error: {}", err);
+            }
+            last_pull = Some(Instant::now());
+        }
+
+        let size = match socket.recv(&mut b) {
+            Ok(v) => v,
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                continue;
+            }
+            Err(err) => {
+                // E.g. connection refused, when the server is not (yet) listening.
+                debug!("Receive from UDP socket error, error: {}", err);
+                continue;
+            }
+        };
+
+        if size < 4 || b[0] != PROTOCOL_VERSION {
+            warn!("Invalid UDP packet received, size: {}", size);
+            continue;
+        }
+
+        match b[3] {
+            PULL_ACK => debug!("PULL_ACK received"),
+            PULL_RESP => {
+                let token = [b[1], b[2]];
+                let error = match handle_pull_resp(gateway_id, &b[4..size], &handle_downlink) {
+                    Ok(v) => v,
+                    Err(err) => {
+                        error!("Handle PULL_RESP error, error: {}", err);
+                        "INTERNAL_ERROR"
+                    }
+                };
+
+                let tx_ack = json!({ "txpk_ack": { "error": error } });
+                if let Err(err) = socket.send(&packet(TX_ACK, token, gateway_id, Some(&tx_ack))) {
+                    error!("Send TX_ACK error, error: {}", err);
+                }
+            }
+            v => warn!("Unexpected UDP packet received, identifier: {}", v),
+        }
+    }
+
+    debug!("UDP packet-forwarder pull loop ended");
+}
+
+// Enqueues the PULL_RESP downlink and returns the TX_ACK error.
+fn handle_pull_resp<F>(gateway_id: &[u8], b: &[u8], handle_downlink: &F) -> Result<&'static str>
+where
+    F: Fn(&gw::DownlinkFrame) -> Result<gw::DownlinkTxAck>,
+{
+    let v: Value = serde_json::from_slice(b)?;
+    let pl = downlink_from_txpk(
+        gateway_id,
+        v.get("txpk").ok_or_else(|| anyhow!("txpk is missing"))?,
+    )?;
+
+    info!("PULL_RESP received, downlink_id: {}", pl.downlink_id);
+
+    let tx_ack = handle_downlink(&pl)?;
+    Ok(match tx_ack.items.first() {
+        Some(v) => tx_ack_error(v.status()),
+        None => "INTERNAL_ERROR",
+    })
+}
+
+// Connects the socket for sending the uplinks and stats (PUSH_DATA) and returns the socket for
+// the downlinks (PULL_DATA).
+fn connect_forwarder(conf: &Configuration, gateway_id: &[u8]) -> Result<UdpSocket> {
+    let push_socket = connect(&conf.server, conf.port_up)?;
+    push_socket.set_nonblocking(true)?;
+
+    let pull_socket = connect(&conf.server, conf.port_down)?;
+    pull_socket.set_read_timeout(Some(READ_TIMEOUT))?;
+
+    *FORWARDER.lock().unwrap() = Some(Forwarder {
+        gateway_id: gateway_id.to_vec(),
+        socket: push_socket,
+    });
+
+    info!(
+        "UDP packet-forwarder connected, server: {}, port_up: {}, port_down: {}",
+        conf.server, conf.port_up, conf.port_down
+    );
+
+    Ok(pull_socket)
+}
+
+fn connect(server: &str, port: u16) -> Result<UdpSocket> {
+    let addr = (server, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| anyhow!("resolve server error, server: {}", server))?;
+
+    let socket = if addr.is_ipv4() {
+        UdpSocket::bind("0.0.0.0:0")?
+    } else {
+        UdpSocket::bind("[::]:0")?
+    };
+    socket.connect(addr)?;
+
+    Ok(socket)
+}
+
+fn next_token() -> [u8; 2] {
+    TOKEN.fetch_add(1, Ordering::Relaxed).to_be_bytes()
+}
+
+// [1: version | 2: token | 1: identifier | 8: gateway id | N: JSON]
+fn packet(identifier: u8, token: [u8; 2], gateway_id: &[u8], data: Option<&Value>) -> Vec<u8> {
+    let mut b = vec![PROTOCOL_VERSION, token[0], token[1], identifier];
+    b.extend_from_slice(gateway_id);
+    if let Some(v) = data {
+        b.extend_from_slice(v.to_string().as_bytes());
+    }
+    b
+}
+
+fn rxpk_from_proto(pl: &gw::UplinkFrame) -> Result<Value> {
+    let tx_info = pl
+        .tx_info
+        .as_ref()
+        .ok_or_else(|| anyhow!("tx_info is missing"))?;
+    let rx_info = pl
+        .rx_info
+        .as_ref()
+        .ok_or_else(|| anyhow!("rx_info is missing"))?;
+
+    if rx_info.context.len() != 4 {
+        return Err(anyhow!("context must be exactly 4 bytes"));
+    }
+    let mut tmst = [0; 4];
+    tmst.copy_from_slice(&rx_info.context);
+
+    let mut rxpk = json!({
+        "tmst": u32::from_be_bytes(tmst),
+        "chan": rx_info.channel,
+        "rfch": rx_info.rf_chain,
+        "freq": tx_info.frequency as f64 / 1_000_000.0,
+        "stat": 1,
+        "rssi": rx_info.rssi,
+        "lsnr": rx_info.snr,
+        "size": pl.phy_payload.len(),
+        "data": base64::encode(&pl.phy_payload),
+    });
+
+    if let Some(v) = &rx_info.time {
+        let t: DateTime<Utc> =
+            (UNIX_EPOCH + Duration::new(v.seconds as u64, v.nanos as u32)).into();
+        rxpk["time"] = json!(t.format("%Y-%m-%dT%H:%M:%S%.6fZ").to_string());
+    }
+
+    if let Some(v) = &rx_info.time_since_gps_epoch {
+        let v = Duration::new(v.seconds as u64, v.nanos as u32);
+        rxpk["tmms"] = json!(v.as_millis() as u64);
+    }
+
+    // nanoseconds since the last PPS
+    if let Some(v) = &rx_info.fine_time_since_gps_epoch {
+        rxpk["ftime"] = json!(v.nanos);
+    }
+
+    match tx_info
+        .modulation
+        .as_ref()
+        .and_then(|v| v.parameters.as_ref())
+    {
+        Some(gw::modulation::Parameters::Lora(v)) => {
+            rxpk["modu"] = json!("LORA");
+            rxpk["datr"] = json!(format!("SF{}BW{}", v.spreading_factor, v.bandwidth / 1000));
+            rxpk["codr"] = json!(match v.code_rate() {
+                gw::CodeRate::Cr45 => "4/5",
+                gw::CodeRate::Cr46 => "4/6",
+                gw::CodeRate::Cr47 => "4/7",
+                gw::CodeRate::Cr48 => "4/8",
+                gw::CodeRate::CrLi45 => "4/5LI",
+                gw::CodeRate::CrLi46 => "4/6LI",
+                gw::CodeRate::CrLi48 => "4/8LI",
+                _ => "OFF",
+            });
+        }
+        Some(gw::modulation::Parameters::Fsk(v)) => {
+            rxpk["modu"] = json!("FSK");
+            rxpk["datr"] = json!(v.datarate);
+        }
+        _ => return Err(anyhow!("modulation is not supported")),
+    }
+
+    Ok(rxpk)
+}
+
+fn stat_from_proto(stats: &gw::GatewayStats) -> Value {
+    let now: DateTime<Utc> = timesource::now().into();
+
+    // The acknowledged percentage of the PUSH_DATA packets since the previous stats.
+    let push_count = PUSH_COUNT.swap(0, Ordering::Relaxed);
+    let push_ack_count = PUSH_ACK_COUNT.swap(0, Ordering::Relaxed);
+    let ackr = if push_count == 0 {
+        0.0
+    } else {
+        push_ack_count as f64 / push_count as f64 * 100.0
+    };
+
+    let mut stat = json!({
+        "time": now.format("%Y-%m-%d %H:%M:%S GMT").to_string(),
+        "rxnb": stats.rx_packets_received,
+        "rxok": stats.rx_packets_received_ok,
+        "rxfw": stats.rx_packets_received_ok,
+        "ackr": ackr,
+        "dwnb": stats.tx_packets_received,
+        "txnb": stats.tx_packets_emitted,
+    });
+
+    if let Some(v) = &stats.location {
+        stat["lati"] = json!(v.latitude);
+        stat["long"] = json!(v.longitude);
+        stat["alti"] = json!(v.altitude as i64);
+    }
+
+    stat
+}
+
+fn downlink_from_txpk(gateway_id: &[u8], txpk: &Value) -> Result<gw::DownlinkFrame> {
+    let data = base64::decode(
+        txpk["data"]
+            .as_str()
+            .ok_or_else(|| anyhow!("data is missing"))?,
+    )?;
+    let frequency = txpk["freq"]
+        .as_f64()
+        .ok_or_else(|| anyhow!("freq is missing"))?;
+    let power = txpk["powe"]
+        .as_i64()
+        .ok_or_else(|| anyhow!("powe is missing"))?;
+
+    let mut context = Vec::new();
+    let timing = if txpk["imme"].as_bool().unwrap_or(false) {
+        gw::timing::Parameters::Immediately(gw::ImmediatelyTimingInfo {})
+    } else if let Some(tmms) = txpk["tmms"].as_u64() {
+        gw::timing::Parameters::GpsEpoch(gw::GpsEpochTimingInfo {
+            time_since_gps_epoch: Some(pbjson_types::Duration {
+                seconds: (tmms / 1000) as i64,
+                nanos: ((tmms % 1000) * 1_000_000) as i32,
+            }),
+        })
+    } else if let Some(tmst) = txpk["tmst"].as_u64() {
+        // The tmst is the concentrator counter, this is sent as delay of zero relative to
+        // the context.
+        context = (tmst as u32).to_be_bytes().to_vec();
+        gw::timing::Parameters::Delay(gw::DelayTimingInfo {
+            delay: Some(Default::default()),
+        })
+    } else {
+        return Err(anyhow!("imme, tmms or tmst must be set"));
+    };
+
+    let modulation = match txpk["modu"].as_str() {
+        Some("LORA") => {
+            let (spreading_factor, bandwidth) = parse_lora_datr(
+                txpk["datr"]
+                    .as_str()
+                    .ok_or_else(|| anyhow!("datr is missing"))?,
+            )?;
+            let code_rate = match txpk["codr"].as_str() {
+                Some("4/5") => gw::CodeRate::Cr45,
+                Some("4/6") => gw::CodeRate::Cr46,
+                Some("4/7") => gw::CodeRate::Cr47,
+                Some("4/8") => gw::CodeRate::Cr48,
+                Some("4/5LI") => gw::CodeRate::CrLi45,
+                Some("4/6LI") => gw::CodeRate::CrLi46,
+                Some("4/8LI") => gw::CodeRate::CrLi48,
+                _ => return Err(anyhow!("invalid codr: {}", txpk["codr"])),
+            };
+
+            gw::modulation::Parameters::Lora(gw::LoraModulationInfo {
+                bandwidth,
+                spreading_factor,
+                code_rate: code_rate.into(),
+                polarization_inversion: txpk["ipol"].as_bool().unwrap_or(false),
+                ..Default::default()
+            })
+        }
+        Some("FSK") => gw::modulation::Parameters::Fsk(gw::FskModulationInfo {
+            datarate: txpk["datr"]
+                .as_u64()
+                .ok_or_else(|| anyhow!("datr is missing"))? as u32,
+            frequency_deviation: txpk["fdev"].as_u64().unwrap_or(0) as u32,
+        }),
+        _ => return Err(anyhow!("invalid modu: {}", txpk["modu"])),
+    };
+
+    Ok(gw::DownlinkFrame {
+        downlink_id: DOWNLINK_ID.fetch_add(1, Ordering::Relaxed),
+        gateway_id: hex::encode(gateway_id),
+        items: vec![gw::DownlinkFrameItem {
+            phy_payload: data,
+            tx_info: Some(gw::DownlinkTxInfo {
+                frequency: (frequency * 1_000_000.0).round() as u32,
+                power: power as i32,
+                modulation: Some(gw::Modulation {
+                    parameters: Some(modulation),
+                }),
+                timing: Some(gw::Timing {
+                    parameters: Some(timing),
+                }),
+                context,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }],
+        ..Default::default()
+    })
+}
+
+// Parses the LoRa datarate (e.g. SF7BW125) into the spreading factor and bandwidth (Hz).
+fn parse_lora_datr(datr: &str) -> Result<(u32, u32)> {
+    let (sf, bw) = datr
+        .strip_prefix("SF")
+        .and_then(|v| v.split_once("BW"))
+        .ok_or_else(|| anyhow!("invalid datr: {}", datr))?;
+
+    Ok((
+        sf.parse().map_err(|_| anyhow!("invalid datr: {}", datr))?,
+        bw.parse::<u32>()
+            .map_err(|_| anyhow!("invalid datr: {}", datr))?
+            * 1000,
+    ))
+}
+
+fn tx_ack_error(status: gw::TxAckStatus) -> &'static str {
+    match status {
+        gw::TxAckStatus::Ok => "NONE",
+        gw::TxAckStatus::Ignored => "IGNORED",
+        gw::TxAckStatus::TooLate => "TOO_LATE",
+        gw::TxAckStatus::TooEarly => "TOO_EARLY",
+        gw::TxAckStatus::CollisionPacket => "COLLISION_PACKET",
+        gw::TxAckStatus::CollisionBeacon => "COLLISION_BEACON",
+        gw::TxAckStatus::TxFreq => "TX_FREQ",
+        gw::TxAckStatus::TxPower => "TX_POWER",
+        gw::TxAckStatus::GpsUnlocked => "GPS_UNLOCKED",
+        gw::TxAckStatus::QueueFull => "QUEUE_FULL",
+        gw::TxAckStatus::InternalError => "INTERNAL_ERROR",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_lora_datr() {
+        assert_eq!((7, 125000), parse_lora_datr("SF7BW125").unwrap());
+        assert_eq!((12, 812000), parse_lora_datr("SF12BW812").unwrap());
+        assert!(parse_lora_datr("SF7").is_err());
+        assert!(parse_lora_datr("50000").is_err());
+    }
+
+    #[test]
+    fn test_rxpk_from_proto() {
+        let pl = gw::UplinkFrame {
+            phy_payload: vec![0x01, 0x02, 0x03],
+            tx_info: Some(gw::UplinkTxInfo {
+                frequency: 868100000,
+                modulation: Some(gw::Modulation {
+                    parameters: Some(gw::modulation::Parameters::Lora(gw::LoraModulationInfo {
+                        bandwidth: 125000,
+                        spreading_factor: 7,
+                        code_rate: gw::CodeRate::Cr45.into(),
+                        ..Default::default()
+                    })),
+                }),
+            }),
+            rx_info: Some(gw::UplinkRxInfo {
+                context: vec![0x00, 0x00, 0x04, 0xd2],
+                rssi: -60,
+                snr: 5.5,
+                channel: 2,
+                time: Some(pbjson_types::Timestamp {
+                    seconds: 1577836800,
+                    nanos: 500_000_000,
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let rxpk = rxpk_from_proto(&pl).unwrap();
+        assert_eq!(1234, rxpk["tmst"]);
+        assert_eq!(868.1, rxpk["freq"]);
+        assert_eq!(2, rxpk["chan"]);
+        assert_eq!("LORA", rxpk["modu"]);
+        assert_eq!("SF7BW125", rxpk["datr"]);
+        assert_eq!("4/5", rxpk["codr"]);
+        assert_eq!(-60, rxpk["rssi"]);
+        assert_eq!(3, rxpk["size"]);
+        assert_eq!("AQID", rxpk["data"]);
+        assert_eq!("2020-01-01T00:00:00.500000Z", rxpk["time"]);
+    }
+
+    #[test]
+    fn test_downlink_from_txpk() {
+        let txpk = json!({
+            "tmst": 1234,
+            "freq": 869.525,
+            "rfch": 0,
+            "powe": 14,
+            "modu": "LORA",
+            "datr": "SF9BW125",
+            "codr": "4/5",
+            "ipol": true,
+            "size": 3,
+            "data": "AQID",
+        });
+
+        let pl = downlink_from_txpk(&[1, 2, 3, 4, 5, 6, 7, 8], &txpk).unwrap();
+        assert_eq!("0102030405060708", pl.gateway_id);
+
+        let item = &pl.items[0];
+        assert_eq!(vec![0x01, 0x02, 0x03], item.phy_payload);
+
+        let tx_info = item.tx_info.as_ref().unwrap();
+        assert_eq!(869525000, tx_info.frequency);
+        assert_eq!(14, tx_info.power);
+        assert_eq!(vec![0x00, 0x00, 0x04, 0xd2], tx_info.context);
+        assert_eq!(
+            Some(gw::modulation::Parameters::Lora(gw::LoraModulationInfo {
+                bandwidth: 125000,
+                spreading_factor: 9,
+                code_rate: gw::CodeRate::Cr45.into(),
+                polarization_inversion: true,
+                ..Default::default()
+            })),
+            tx_info.modulation.as_ref().unwrap().parameters
+        );
+
+        let txpk = json!({
+            "imme": true,
+            "freq": 869.525,
+            "powe": 14,
+            "modu": "LORA",
+            "datr": "SF9BW125",
+            "codr": "4/4",
+            "data": "AQID",
+        });
+        assert!(downlink_from_txpk(&[0; 8], &txpk).is_err());
+    }
+}
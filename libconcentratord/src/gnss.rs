@@ -0,0 +1,463 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::Mutex;
+use std::thread::sleep;
+use std::time::Duration;
+
+use anyhow::Result;
+use log::{debug, error, info};
+
+use super::{storage, timesource};
+
+/// Baud rates that are tried (in this order) by the baud rate detection.
+pub const BAUD_RATES: [u32; 3] = [9600, 38400, 115200];
+
+// Time to listen for NMEA sentences on each baud rate.
+const DETECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+// Max. number of bytes to read on each baud rate.
+const DETECT_MAX_BYTES: usize = 2048;
+
+// Max. size of the downloaded assistance data.
+const MAX_ASSISTANCE_SIZE: u64 = 64 * 1024;
+
+// Timeout of the assistance data request.
+const ASSISTANCE_TIMEOUT: Duration = Duration::from_secs(15);
+
+// Pause between two injected messages, so that the input buffer of the receiver does not
+// overflow.
+const INJECT_INTERVAL: Duration = Duration::from_millis(10);
+
+// Storage key of the last known fix.
+const LAST_FIX_STORAGE_KEY: &str = "gnss_last_fix";
+
+// Min. change of the latitude or longitude (in degrees, about 11 meters) or altitude (in
+// meters), before the last known fix is updated. This avoids a storage write on every fix
+// because of the position noise.
+const LAST_FIX_MIN_DEGREES: f64 = 0.0001;
+const LAST_FIX_MIN_METERS: i16 = 10;
+
+lazy_static! {
+    static ref LAST_FIX: Mutex<Option<Fix>> = Mutex::new(None);
+}
+
+/// GNSS position fix.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Fix {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: i16,
+}
+
+impl Fix {
+    fn has_moved(&self, other: &Fix) -> bool {
+        (self.latitude - other.latitude).abs() >= LAST_FIX_MIN_DEGREES
+            || (self.longitude - other.longitude).abs() >= LAST_FIX_MIN_DEGREES
+            || (self.altitude - other.altitude).abs() >= LAST_FIX_MIN_METERS
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        serde_json::json!({
+            "latitude": self.latitude,
+            "longitude": self.longitude,
+            "altitude": self.altitude,
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    fn from_bytes(b: &[u8]) -> Result<Fix> {
+        let v: serde_json::Value = serde_json::from_slice(b)?;
+        let get = |k: &str| {
+            v[k].as_f64()
+                .ok_or_else(|| anyhow!("{} is missing in persisted fix", k))
+        };
+
+        Ok(Fix {
+            latitude: get("latitude")?,
+            longitude: get("longitude")?,
+            altitude: get("altitude")? as i16,
+        })
+    }
+}
+
+/// Loads the last known fix from the storage, such that it can be reported (as stale) after a
+/// restart while waiting for a new fix. This must be called after the storage has been setup.
+pub fn load_last_fix() -> Result<()> {
+    let fix = match storage::get(LAST_FIX_STORAGE_KEY)? {
+        Some(v) => Fix::from_bytes(&v)?,
+        None => return Ok(()),
+    };
+
+    info!(
+        "Loaded last known GNSS fix, latitude: {}, longitude: {}, altitude: {}",
+        fix.latitude, fix.longitude, fix.altitude
+    );
+
+    *LAST_FIX.lock().unwrap() = Some(fix);
+    Ok(())
+}
+
+/// Set the last known fix. When the fix has moved, it is persisted in the storage (when
+/// enabled).
+pub fn set_last_fix(fix: Fix) {
+    let mut last_fix = LAST_FIX.lock().unwrap();
+    if let Some(v) = last_fix.as_ref() {
+        if !v.has_moved(&fix) {
+            return;
+        }
+    }
+
+    *last_fix = Some(fix);
+
+    if let Err(err) = storage::set(LAST_FIX_STORAGE_KEY, &fix.to_bytes()) {
+        error!("Persist last known GNSS fix error, error: {}", err);
+    }
+}
+
+/// Returns the last known fix.
+pub fn get_last_fix() -> Option<Fix> {
+    *LAST_FIX.lock().unwrap()
+}
+
+/// Set the baud rate of the given tty. When read_timeout is set, reads return after the given
+/// timeout (max. 25.5 seconds) when no data is available, else reads block until at least one
+/// byte is available.
+pub fn set_baud_rate(file: &File, baud_rate: u32, read_timeout: Option<Duration>) -> Result<()> {
+    let speed = match baud_rate {
+        9600 => libc::B9600,
+        38400 => libc::B38400,
+        115200 => libc::B115200,
+        _ => return Err(anyhow!("unsupported baud rate: {}", baud_rate)),
+    };
+
+    let fd = file.as_raw_fd();
+    let mut tio: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut tio) } != 0 {
+        return Err(anyhow!("tcgetattr error: {}", io::Error::last_os_error()));
+    }
+
+    unsafe {
+        libc::cfsetispeed(&mut tio, speed);
+        libc::cfsetospeed(&mut tio, speed);
+    }
+
+    match read_timeout {
+        Some(v) => {
+            tio.c_cc[libc::VMIN] = 0;
+            tio.c_cc[libc::VTIME] = (v.as_millis() / 100).clamp(1, 255) as u8;
+        }
+        None => {
+            tio.c_cc[libc::VMIN] = 1;
+            tio.c_cc[libc::VTIME] = 0;
+        }
+    }
+
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &tio) } != 0 {
+        return Err(anyhow!("tcsetattr error: {}", io::Error::last_os_error()));
+    }
+
+    // discard the data that was received using the previous settings
+    unsafe {
+        libc::tcflush(fd, libc::TCIFLUSH);
+    }
+
+    Ok(())
+}
+
+/// Detect the baud rate of the GNSS module by listening for valid NMEA sentences on each of
+/// the BAUD_RATES. On success, the tty is left configured with the detected baud rate.
+pub fn detect_baud_rate(file: &mut File) -> Result<u32> {
+    for baud_rate in BAUD_RATES {
+        debug!("Trying GNSS baud rate, baud_rate: {}", baud_rate);
+        set_baud_rate(file, baud_rate, Some(Duration::from_millis(500)))?;
+
+        let start = timesource::monotonic();
+        let mut b: Vec<u8> = Vec::new();
+        let mut buffer = [0; 256];
+
+        while timesource::monotonic() - start < DETECT_TIMEOUT && b.len() < DETECT_MAX_BYTES {
+            let size = file.read(&mut buffer)?;
+            b.extend_from_slice(&buffer[..size]);
+
+            if has_valid_nmea(&b) {
+                info!("Detected GNSS baud rate, baud_rate: {}", baud_rate);
+                set_baud_rate(file, baud_rate, None)?;
+                return Ok(baud_rate);
+            }
+        }
+    }
+
+    Err(anyhow!(
+        "no valid NMEA sentences received, baud_rates: {:?}",
+        BAUD_RATES
+    ))
+}
+
+/// Enable the UBX NAV-TIMEGPS message on the UART and USB ports of the GNSS module. The HAL
+/// sends this command when enabling the GPS, but always at 9600 baud. It must be sent again
+/// when the module is using a different baud rate.
+pub fn enable_ubx_nav_timegps(file: &mut File) -> Result<()> {
+    // CFG-MSG: msgClass, msgID, rates (I2C, UART1, UART2, USB, SPI, reserved)
+    let mut b: Vec<u8> = vec![0xb5, 0x62, 0x06, 0x01, 0x08, 0x00];
+    b.extend_from_slice(&[0x01, 0x20, 0x00, 0x01, 0x01, 0x01, 0x00, 0x00]);
+    let (ck_a, ck_b) = ubx_checksum(&b[2..]);
+    b.extend_from_slice(&[ck_a, ck_b]);
+
+    file.write_all(&b)?;
+    Ok(())
+}
+
+/// Download the assistance data (e.g. u-blox AssistNow Online) from the given url and inject it
+/// into the GNSS module. The response must contain UBX messages (e.g. UBX-MGA), which are
+/// written unmodified to the module.
+pub fn assist(file: &mut File, url: &str) -> Result<()> {
+    info!("Downloading GNSS assistance data");
+
+    let resp = ureq::get(url).timeout(ASSISTANCE_TIMEOUT).call()?;
+    let mut b: Vec<u8> = Vec::new();
+    resp.into_reader()
+        .take(MAX_ASSISTANCE_SIZE + 1)
+        .read_to_end(&mut b)?;
+
+    if b.len() as u64 > MAX_ASSISTANCE_SIZE {
+        return Err(anyhow!(
+            "assistance data exceeds max size, max_size: {}",
+            MAX_ASSISTANCE_SIZE
+        ));
+    }
+
+    let messages = split_ubx(&b)?;
+    for msg in &messages {
+        file.write_all(msg)?;
+        sleep(INJECT_INTERVAL);
+    }
+
+    info!(
+        "GNSS assistance data injected, messages: {}, size: {}",
+        messages.len(),
+        b.len()
+    );
+
+    Ok(())
+}
+
+/// Find the tty of the GNSS module by its USB vendor and product ID (e.g. 1546:01a7) or by its
+/// device-tree alias (e.g. serial1). Empty values are ignored.
+pub fn find_tty(usb_id: &str, dt_alias: &str) -> Result<String> {
+    let dt_node = if dt_alias.is_empty() {
+        None
+    } else {
+        let node = fs::read_to_string(Path::new("/proc/device-tree/aliases").join(dt_alias))
+            .map_err(|e| anyhow!("read device-tree alias {} error: {}", dt_alias, e))?;
+        Some(
+            node.trim_end_matches('\0')
+                .trim_start_matches('/')
+                .to_string(),
+        )
+    };
+
+    for entry in fs::read_dir("/sys/class/tty")? {
+        let entry = entry?;
+        let device = match fs::canonicalize(entry.path().join("device")) {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        let tty_path = format!("/dev/{}", entry.file_name().to_string_lossy());
+
+        if !usb_id.is_empty() {
+            if let Some(id) = get_usb_id(&device) {
+                if id.eq_ignore_ascii_case(usb_id) {
+                    info!("Found GNSS tty, usb_id: {}, tty_path: {}", usb_id, tty_path);
+                    return Ok(tty_path);
+                }
+            }
+        }
+
+        if let Some(dt_node) = &dt_node {
+            if let Ok(of_node) = fs::canonicalize(device.join("of_node")) {
+                if of_node.ends_with(dt_node) {
+                    info!(
+                        "Found GNSS tty, dt_alias: {}, tty_path: {}",
+                        dt_alias, tty_path
+                    );
+                    return Ok(tty_path);
+                }
+            }
+        }
+    }
+
+    Err(anyhow!(
+        "no GNSS tty found, usb_id: {}, dt_alias: {}",
+        usb_id,
+        dt_alias
+    ))
+}
+
+// The device of a USB serial tty is the USB interface (or a child of it), the vendor and
+// product ID are attributes of the USB device further up the tree.
+fn get_usb_id(device: &Path) -> Option<String> {
+    for dir in device.ancestors().take(4) {
+        if let (Ok(vendor), Ok(product)) = (
+            fs::read_to_string(dir.join("idVendor")),
+            fs::read_to_string(dir.join("idProduct")),
+        ) {
+            return Some(format!("{}:{}", vendor.trim(), product.trim()));
+        }
+    }
+
+    None
+}
+
+// Split the given bytes into UBX messages, validating the sync chars and checksum of each
+// message.
+fn split_ubx(mut b: &[u8]) -> Result<Vec<&[u8]>> {
+    let mut out = Vec::new();
+
+    while !b.is_empty() {
+        if b.len() < 8 || b[0..2] != [0xb5, 0x62] {
+            return Err(anyhow!("invalid ubx message, index: {}", out.len()));
+        }
+
+        let len = 8 + u16::from_le_bytes([b[4], b[5]]) as usize;
+        if b.len() < len {
+            return Err(anyhow!("truncated ubx message, index: {}", out.len()));
+        }
+
+        let (msg, rest) = b.split_at(len);
+        if ubx_checksum(&msg[2..len - 2]) != (msg[len - 2], msg[len - 1]) {
+            return Err(anyhow!("invalid ubx checksum, index: {}", out.len()));
+        }
+
+        out.push(msg);
+        b = rest;
+    }
+
+    Ok(out)
+}
+
+// Fletcher checksum over the class, ID, length and payload of an UBX message.
+fn ubx_checksum(b: &[u8]) -> (u8, u8) {
+    b.iter().fold((0u8, 0u8), |(ck_a, ck_b), c| {
+        let ck_a = ck_a.wrapping_add(*c);
+        (ck_a, ck_b.wrapping_add(ck_a))
+    })
+}
+
+// Returns true when the given bytes contain at least one complete NMEA sentence with a valid
+// checksum.
+fn has_valid_nmea(b: &[u8]) -> bool {
+    b.split(|c| *c == b'\n')
+        .filter_map(|line| line.strip_suffix(b"\r"))
+        .any(is_valid_nmea)
+}
+
+fn is_valid_nmea(line: &[u8]) -> bool {
+    let pos = match line.iter().position(|c| *c == b'$') {
+        Some(v) => v,
+        None => return false,
+    };
+    let line = &line[pos + 1..];
+
+    if line.len() < 3 || line[line.len() - 3] != b'*' {
+        return false;
+    }
+
+    let checksum = match std::str::from_utf8(&line[line.len() - 2..])
+        .ok()
+        .and_then(|v| u8::from_str_radix(v, 16).ok())
+    {
+        Some(v) => v,
+        None => return false,
+    };
+
+    line[..line.len() - 3].iter().fold(0, |acc, c| acc ^ c) == checksum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ubx_checksum() {
+        // CFG-MSG enabling NAV-TIMEGPS on UART1
+        assert_eq!(
+            (0x31, 0x90),
+            ubx_checksum(&[0x06, 0x01, 0x08, 0x00, 0x01, 0x20, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00])
+        );
+    }
+
+    #[test]
+    fn test_split_ubx() {
+        let msg: Vec<u8> = vec![
+            0xb5, 0x62, 0x06, 0x01, 0x08, 0x00, 0x01, 0x20, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+            0x31, 0x90,
+        ];
+        let mut b = msg.clone();
+        b.extend_from_slice(&msg);
+
+        assert_eq!(vec![&msg[..], &msg[..]], split_ubx(&b).unwrap());
+        assert!(split_ubx(&[]).unwrap().is_empty());
+
+        // truncated
+        assert!(split_ubx(&b[..20]).is_err());
+
+        // invalid checksum
+        b[15] ^= 0xff;
+        assert!(split_ubx(&b).is_err());
+
+        // invalid sync chars
+        assert!(split_ubx(&b[1..]).is_err());
+    }
+
+    #[test]
+    fn test_has_valid_nmea() {
+        assert!(has_valid_nmea(
+            b"\x12\xfe$GPGGA,092750.000,5321.6802,N,00630.3372,W,1,8,1.03,61.7,M,55.2,M,,*76\r\n"
+        ));
+        assert!(has_valid_nmea(
+            b"$GPGSA,A,3,10,07,05,02,29,04,08,13,,,,,1.72,1.03,1.38*0A\r\n"
+        ));
+
+        // invalid checksum
+        assert!(!has_valid_nmea(
+            b"$GPGSA,A,3,10,07,05,02,29,04,08,13,,,,,1.72,1.03,1.38*0B\r\n"
+        ));
+
+        // incomplete sentence
+        assert!(!has_valid_nmea(
+            b"$GPGSA,A,3,10,07,05,02,29,04,08,13,,,,,1.72,1.03,1.38*0A"
+        ));
+
+        // garbage (wrong baud rate)
+        assert!(!has_valid_nmea(b"\x80\xf8\x00\x78\x80\x80\xf8\r\n\x00"));
+    }
+
+    #[test]
+    fn test_fix() {
+        let fix = Fix {
+            latitude: 52.3676,
+            longitude: 4.9041,
+            altitude: 12,
+        };
+
+        assert_eq!(fix, Fix::from_bytes(&fix.to_bytes()).unwrap());
+        assert!(Fix::from_bytes(b"{}").is_err());
+
+        assert!(!fix.has_moved(&Fix {
+            latitude: 52.36765,
+            longitude: 4.90405,
+            altitude: 15,
+        }));
+        assert!(fix.has_moved(&Fix {
+            latitude: 52.3678,
+            ..fix
+        }));
+        assert!(fix.has_moved(&Fix {
+            altitude: 22,
+            ..fix
+        }));
+    }
+}
@@ -0,0 +1,277 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use log::{debug, error, info};
+
+use super::signals::Signal;
+use super::{api, events, state, stats};
+
+// Interval between two health checks.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    static ref HEALTH: Mutex<Health> = Mutex::new(Health::new(Instant::now()));
+    static ref LAST_RESTART: Mutex<Option<(Trigger, String)>> = Mutex::new(None);
+}
+
+// Total number of health triggered restarts since Concentratord was started.
+static RESTART_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Condition which triggered the restart of the concentrator.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Trigger {
+    // The max. number of consecutive HAL errors was exceeded.
+    HalErrors,
+    // No frame was received within the RX timeout.
+    RxTimeout,
+    // The concentrator counter did not advance within the stall timeout.
+    CounterStall,
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Trigger::HalErrors => write!(f, "HAL_ERRORS"),
+            Trigger::RxTimeout => write!(f, "RX_TIMEOUT"),
+            Trigger::CounterStall => write!(f, "COUNTER_STALL"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Configuration {
+    // Max. number of consecutive HAL errors. 0 = disabled.
+    pub max_hal_errors: u32,
+    // Max. duration without any received frame (e.g. in a busy band). 0 = disabled.
+    pub rx_timeout: Duration,
+    // Max. duration without the concentrator counter advancing. 0 = disabled.
+    pub counter_stall_timeout: Duration,
+}
+
+struct Health {
+    hal_errors: u32,
+    last_rx: Instant,
+    counter: Option<u32>,
+    counter_changed: Instant,
+}
+
+impl Health {
+    fn new(now: Instant) -> Self {
+        Health {
+            hal_errors: 0,
+            last_rx: now,
+            counter: None,
+            counter_changed: now,
+        }
+    }
+
+    fn set_counter(&mut self, counter: u32, now: Instant) {
+        if self.counter != Some(counter) {
+            self.counter = Some(counter);
+            self.counter_changed = now;
+        }
+    }
+
+    // Returns the trigger and a description of the failure when the concentrator must be
+    // restarted.
+    fn check(&self, conf: &Configuration, now: Instant) -> Option<(Trigger, String)> {
+        if conf.max_hal_errors != 0 && self.hal_errors >= conf.max_hal_errors {
+            return Some((
+                Trigger::HalErrors,
+                format!("{} consecutive HAL errors", self.hal_errors),
+            ));
+        }
+
+        if !conf.rx_timeout.is_zero() && now.duration_since(self.last_rx) >= conf.rx_timeout {
+            return Some((
+                Trigger::RxTimeout,
+                format!(
+                    "no frame received for {:?}",
+                    now.duration_since(self.last_rx)
+                ),
+            ));
+        }
+
+        if !conf.counter_stall_timeout.is_zero()
+            && now.duration_since(self.counter_changed) >= conf.counter_stall_timeout
+        {
+            return Some((
+                Trigger::CounterStall,
+                format!(
+                    "counter stalled for {:?}, counter: {:?}",
+                    now.duration_since(self.counter_changed),
+                    self.counter
+                ),
+            ));
+        }
+
+        None
+    }
+}
+
+/// Report a successful receive call, returning the given number of frames (including frames
+/// with an invalid CRC).
+pub fn receive_ok(frames: usize) {
+    let mut health = HEALTH.lock().unwrap();
+    health.hal_errors = 0;
+    if frames != 0 {
+        health.last_rx = Instant::now();
+    }
+}
+
+/// Report a failed HAL call.
+pub fn hal_error() {
+    let mut health = HEALTH.lock().unwrap();
+    health.hal_errors += 1;
+}
+
+/// Monitors the concentrator health and restarts the concentrator by sending the Restart
+/// signal when one of the configured thresholds is exceeded. The number of restarts per trigger
+/// is reported as stats counter (health_restart_hal_errors, health_restart_rx_timeout,
+/// health_restart_counter_stall), the total number of restarts as stats meta-data
+/// (health_restart_count).
+pub fn monitor_loop<F>(
+    conf: &Configuration,
+    get_counter: F,
+    stop_send: Sender<Signal>,
+    stop_receive: Receiver<Signal>,
+) where
+    F: Fn() -> Result<u32>,
+{
+    info!(
+        "Starting health monitor loop, max_hal_errors: {}, rx_timeout: {:?}, counter_stall_timeout: {:?}",
+        conf.max_hal_errors, conf.rx_timeout, conf.counter_stall_timeout
+    );
+
+    // The thresholds are evaluated from the (re)start of the concentrator.
+    *HEALTH.lock().unwrap() = Health::new(Instant::now());
+
+    // Once the restart has been triggered, the loop waits for the stop signal.
+    let mut restarting = false;
+
+    loop {
+        if !restarting {
+            let counter = get_counter();
+
+            let mut health = HEALTH.lock().unwrap();
+            match counter {
+                Ok(v) => health.set_counter(v, Instant::now()),
+                Err(err) => {
+                    error!("Get concentrator counter error, error: {}", err);
+                    health.hal_errors += 1;
+                }
+            }
+
+            if let Some((trigger, reason)) = health.check(conf, Instant::now()) {
+                error!(
+                    "Concentrator health check failed, restarting concentrator, trigger: {}, reason: {}",
+                    trigger, reason
+                );
+
+                let count = RESTART_COUNT.fetch_add(1, Ordering::SeqCst) + 1;
+                stats::inc_counter(&format!(
+                    "health_restart_{}",
+                    trigger.to_string().to_lowercase()
+                ));
+                stats::set_metadata("health_restart_count", &count.to_string());
+
+                *LAST_RESTART.lock().unwrap() = Some((trigger, reason));
+                stop_send.send(Signal::Restart).unwrap();
+                restarting = true;
+            }
+        }
+
+        if let Ok(v) = stop_receive.recv_timeout(CHECK_INTERVAL) {
+            debug!("Received stop signal, signal: {}", v);
+            break;
+        }
+    }
+
+    debug!("Health monitor loop ended");
+}
+
+/// Publishes the concentrator restarted event, once the concentrator has been restarted after
+/// a failed health check.
+pub fn send_restarted() {
+    let (trigger, reason) = match LAST_RESTART.lock().unwrap().take() {
+        Some(v) => v,
+        None => return,
+    };
+
+    info!(
+        "Concentrator restarted after failed health check, trigger: {}",
+        trigger
+    );
+
+    if let Err(err) = events::send_restart(&api::RestartEvent {
+        gateway_id: hex::encode(state::get_gateway_id()),
+        trigger: trigger.to_string(),
+        reason,
+        restart_count: RESTART_COUNT.load(Ordering::SeqCst),
+    }) {
+        error!("Send restart event error, error: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let conf = Configuration {
+            max_hal_errors: 3,
+            rx_timeout: Duration::from_secs(60),
+            counter_stall_timeout: Duration::from_secs(10),
+        };
+        let start = Instant::now();
+        let mut health = Health::new(start);
+
+        health.set_counter(1, start);
+        health.hal_errors = 2;
+        assert_eq!(None, health.check(&conf, start + Duration::from_secs(5)));
+
+        health.hal_errors = 3;
+        assert_eq!(
+            Some(Trigger::HalErrors),
+            health
+                .check(&conf, start + Duration::from_secs(5))
+                .map(|v| v.0)
+        );
+
+        // the counter advanced
+        health.hal_errors = 0;
+        health.set_counter(2, start + Duration::from_secs(9));
+        assert_eq!(None, health.check(&conf, start + Duration::from_secs(15)));
+
+        // the counter did not advance
+        health.set_counter(2, start + Duration::from_secs(15));
+        assert_eq!(
+            Some(Trigger::CounterStall),
+            health
+                .check(&conf, start + Duration::from_secs(19))
+                .map(|v| v.0)
+        );
+
+        health.set_counter(3, start + Duration::from_secs(60));
+        assert_eq!(
+            Some(Trigger::RxTimeout),
+            health
+                .check(&conf, start + Duration::from_secs(60))
+                .map(|v| v.0)
+        );
+
+        // disabled thresholds
+        let conf = Configuration {
+            max_hal_errors: 0,
+            rx_timeout: Duration::ZERO,
+            counter_stall_timeout: Duration::ZERO,
+        };
+        health.hal_errors = 100;
+        assert_eq!(None, health.check(&conf, start + Duration::from_secs(600)));
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use chirpstack_api::gw;
+use log::info;
+
+use super::{api, timesource};
+
+// Default number of retained entries.
+const DEFAULT_SIZE: usize = 256;
+
+lazy_static! {
+    static ref HISTORY: Mutex<History> = Mutex::new(History::new(DEFAULT_SIZE));
+}
+
+/// Downlink scheduling event.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Event {
+    // The downlink item was enqueued or rejected.
+    Enqueue,
+    // The downlink was handed over to the concentrator.
+    TxDone,
+    // The downlink could not be handed over to the concentrator.
+    TxFailed,
+    // The downlink was dropped from the queue, as its TX time had passed.
+    Dropped,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Enqueue => write!(f, "ENQUEUE"),
+            Event::TxDone => write!(f, "TX_DONE"),
+            Event::TxFailed => write!(f, "TX_FAILED"),
+            Event::Dropped => write!(f, "DROPPED"),
+        }
+    }
+}
+
+struct History {
+    size: usize,
+    entries: VecDeque<api::DownlinkHistoryEntry>,
+}
+
+impl History {
+    fn new(size: usize) -> History {
+        History {
+            size,
+            entries: VecDeque::with_capacity(size),
+        }
+    }
+
+    fn push(&mut self, entry: api::DownlinkHistoryEntry) {
+        if self.size == 0 {
+            return;
+        }
+
+        while self.entries.len() >= self.size {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    fn get(&self, req: &api::DownlinkHistoryRequest) -> Vec<api::DownlinkHistoryEntry> {
+        let mut out: Vec<api::DownlinkHistoryEntry> = self
+            .entries
+            .iter()
+            .filter(|v| req.downlink_id == 0 || v.downlink_id == req.downlink_id)
+            .cloned()
+            .collect();
+
+        if req.limit != 0 && out.len() > req.limit as usize {
+            out.drain(..out.len() - req.limit as usize);
+        }
+
+        out
+    }
+}
+
+/// Set the number of retained entries. When 0, the history is disabled.
+pub fn set_size(size: usize) {
+    let mut history = HISTORY.lock().unwrap();
+    if history.size != size {
+        info!("Setting downlink history size, size: {}", size);
+        history.size = size;
+        while history.entries.len() > size {
+            history.entries.pop_front();
+        }
+    }
+}
+
+/// Records the enqueue status of each downlink item. The items that were not tried (e.g.
+/// because a previous item was enqueued) are not recorded.
+pub fn record_tx_ack(pl: &gw::DownlinkFrame, tx_ack: &gw::DownlinkTxAck) {
+    for (i, item) in tx_ack.items.iter().enumerate() {
+        if item.status() == gw::TxAckStatus::Ignored {
+            continue;
+        }
+
+        let mut entry = new_entry(pl.downlink_id, Event::Enqueue);
+        entry.item = i as u32;
+        entry.status = item.status;
+        entry.frequency = pl
+            .items
+            .get(i)
+            .and_then(|v| v.tx_info.as_ref())
+            .map(|v| v.frequency)
+            .unwrap_or_default();
+        record(entry);
+    }
+}
+
+/// Records the downlink that was handed over to the concentrator.
+pub fn record_tx_done(pl: &api::TxDone) {
+    let mut entry = new_entry(pl.downlink_id, Event::TxDone);
+    entry.frequency = pl.frequency;
+    entry.count_us = pl.count_us;
+    record(entry);
+}
+
+/// Records a downlink that could not be handed over to the concentrator.
+pub fn record_tx_failed(downlink_id: u32, frequency: u32, count_us: u32, error: &str) {
+    let mut entry = new_entry(downlink_id, Event::TxFailed);
+    entry.frequency = frequency;
+    entry.count_us = count_us;
+    entry.error = error.to_string();
+    record(entry);
+}
+
+/// Records a downlink that was dropped from the queue.
+pub fn record_dropped(downlink_id: u32, count_us: u32) {
+    let mut entry = new_entry(downlink_id, Event::Dropped);
+    entry.count_us = count_us;
+    record(entry);
+}
+
+/// Returns the history entries matching the request.
+pub fn get(gateway_id: &[u8], req: &api::DownlinkHistoryRequest) -> api::DownlinkHistory {
+    api::DownlinkHistory {
+        gateway_id: hex::encode(gateway_id),
+        entries: HISTORY.lock().unwrap().get(req),
+    }
+}
+
+fn record(entry: api::DownlinkHistoryEntry) {
+    HISTORY.lock().unwrap().push(entry);
+}
+
+fn new_entry(downlink_id: u32, event: Event) -> api::DownlinkHistoryEntry {
+    let now = timesource::now().duration_since(UNIX_EPOCH).unwrap();
+
+    api::DownlinkHistoryEntry {
+        time: Some(pbjson_types::Timestamp {
+            seconds: now.as_secs() as i64,
+            nanos: now.subsec_nanos() as i32,
+        }),
+        downlink_id,
+        event: event.to_string(),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(downlink_id: u32) -> api::DownlinkHistoryEntry {
+        new_entry(downlink_id, Event::Enqueue)
+    }
+
+    #[test]
+    fn test_history() {
+        let mut history = History::new(3);
+        for id in 1..=4 {
+            history.push(entry(id));
+        }
+        history.push(new_entry(3, Event::TxDone));
+
+        // the oldest entries are removed
+        let ids: Vec<u32> = history
+            .get(&Default::default())
+            .iter()
+            .map(|v| v.downlink_id)
+            .collect();
+        assert_eq!(vec![3, 4, 3], ids);
+
+        let entries = history.get(&api::DownlinkHistoryRequest {
+            downlink_id: 3,
+            ..Default::default()
+        });
+        assert_eq!(2, entries.len());
+        assert_eq!("TX_DONE", entries[1].event);
+
+        let entries = history.get(&api::DownlinkHistoryRequest {
+            limit: 1,
+            ..Default::default()
+        });
+        assert_eq!(1, entries.len());
+        assert_eq!("TX_DONE", entries[0].event);
+
+        // disabled
+        let mut history = History::new(0);
+        history.push(entry(1));
+        assert!(history.get(&Default::default()).is_empty());
+    }
+}
@@ -0,0 +1,223 @@
+//! Output lines of I2C GPIO expanders, which are used by some gateway boards to route the
+//! reset and power enable lines of the concentrator.
+//!
+//! An expander is identified as <model>:<bus>:<address> (e.g. pca9555:1:0x20), a line as
+//! <model>:<bus>:<address>:<pin>. Supported models are pca9555 (also i2c, for PCA9555
+//! compatible expanders like the PCA9535 and TCA9555) and mcp23017. Pins 0 - 7 are on the
+//! first port, pins 8 - 15 on the second port.
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+
+use anyhow::Result;
+use log::info;
+
+// ioctl to set the address of the I2C device (see linux/i2c-dev.h).
+const I2C_SLAVE: u32 = 0x0703;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Model {
+    PCA9555,
+    MCP23017,
+}
+
+impl Model {
+    // Returns the direction and output register of the given port. Note that the MCP23017
+    // registers assume the default IOCON.BANK = 0 addressing.
+    fn registers(&self, port: u8) -> (u8, u8) {
+        match self {
+            Model::PCA9555 => (0x06 + port, 0x02 + port),
+            Model::MCP23017 => (port, 0x14 + port),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Chip {
+    pub model: Model,
+    pub bus: u32,
+    pub address: u16,
+}
+
+/// Parses the expander chip. Returns None when the chip is not an I2C GPIO expander (e.g. a
+/// /dev/gpiochipN path).
+pub fn parse_chip(chip: &str) -> Option<Result<Chip>> {
+    let parts: Vec<&str> = chip.split(':').collect();
+    let model = match parts[0] {
+        "i2c" | "pca9555" => Model::PCA9555,
+        "mcp23017" => Model::MCP23017,
+        _ => return None,
+    };
+
+    if parts.len() != 3 {
+        return Some(Err(anyhow!(
+            "invalid i2c gpio expander, expected <model>:<bus>:<address>: {}",
+            chip
+        )));
+    }
+
+    let (bus, address) = match parse_bus_address(parts[1], parts[2]) {
+        Ok(v) => v,
+        Err(err) => return Some(Err(err)),
+    };
+
+    Some(Ok(Chip {
+        model,
+        bus,
+        address,
+    }))
+}
+
+/// Parses an expander line into the expander chip and pin, such that it can be used as reset
+/// pin. Returns None when the line is not an I2C GPIO expander line.
+pub fn parse_line(line: &str) -> Option<Result<(String, u32)>> {
+    let (chip, pin) = match line.rsplit_once(':') {
+        Some(v) => v,
+        None => return None,
+    };
+    if let Err(err) = parse_chip(chip)? {
+        return Some(Err(err));
+    }
+
+    Some(
+        pin.parse()
+            .map(|pin| (chip.to_string(), pin))
+            .map_err(|_| anyhow!("invalid i2c gpio expander pin: {}", line)),
+    )
+}
+
+fn parse_bus_address(bus: &str, address: &str) -> Result<(u32, u16)> {
+    let bus = bus
+        .parse()
+        .map_err(|_| anyhow!("invalid i2c bus: {}", bus))?;
+    let address = match address.strip_prefix("0x") {
+        Some(v) => u16::from_str_radix(v, 16),
+        None => address.parse(),
+    }
+    .map_err(|_| anyhow!("invalid i2c address: {}", address))?;
+
+    Ok((bus, address))
+}
+
+/// Output line of an I2C GPIO expander.
+pub struct Line {
+    file: File,
+    model: Model,
+    pin: u8,
+}
+
+impl Line {
+    /// Configure the given pin of the expander as output, with the given default value.
+    pub fn request(chip: &Chip, pin: u32, default: u8) -> Result<Line> {
+        if pin > 15 {
+            return Err(anyhow!("expander pin must be 0 - 15, pin: {}", pin));
+        }
+
+        let path = format!("/dev/i2c-{}", chip.bus);
+        info!(
+            "Configuring i2c gpio expander line, model: {:?}, dev: {}, address: 0x{:02x}, pin: {}",
+            chip.model, path, chip.address, pin
+        );
+
+        let file = OpenOptions::new().read(true).write(true).open(&path)?;
+        let address = chip.address as libc::c_ulong;
+        if unsafe { libc::ioctl(file.as_raw_fd(), I2C_SLAVE as _, address) } < 0 {
+            return Err(anyhow!(
+                "set i2c address error: {}",
+                io::Error::last_os_error()
+            ));
+        }
+
+        let line = Line {
+            file,
+            model: chip.model,
+            pin: pin as u8,
+        };
+
+        // The output value is set before changing the direction, so that the line does not
+        // glitch when it is switched to output.
+        line.set_value(default)?;
+        let (direction_reg, _) = line.model.registers(line.port());
+        let direction = line.read_register(direction_reg)?;
+        line.write_register(direction_reg, direction & !line.mask())?;
+
+        Ok(line)
+    }
+
+    pub fn set_value(&self, value: u8) -> Result<()> {
+        let (_, output_reg) = self.model.registers(self.port());
+        let output = self.read_register(output_reg)?;
+        let output = if value == 0 {
+            output & !self.mask()
+        } else {
+            output | self.mask()
+        };
+
+        self.write_register(output_reg, output)
+    }
+
+    fn port(&self) -> u8 {
+        self.pin / 8
+    }
+
+    fn mask(&self) -> u8 {
+        1 << (self.pin % 8)
+    }
+
+    fn read_register(&self, reg: u8) -> Result<u8> {
+        let mut b = [0; 1];
+        (&self.file).write_all(&[reg])?;
+        (&self.file).read_exact(&mut b)?;
+        Ok(b[0])
+    }
+
+    fn write_register(&self, reg: u8, value: u8) -> Result<()> {
+        (&self.file).write_all(&[reg, value])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_chip() {
+        assert!(parse_chip("/dev/gpiochip0").is_none());
+        assert!(parse_chip("pinctrl-rp1").is_none());
+        assert_eq!(
+            Chip {
+                model: Model::PCA9555,
+                bus: 1,
+                address: 0x20
+            },
+            parse_chip("i2c:1:0x20").unwrap().unwrap()
+        );
+        assert_eq!(
+            Chip {
+                model: Model::MCP23017,
+                bus: 0,
+                address: 39
+            },
+            parse_chip("mcp23017:0:39").unwrap().unwrap()
+        );
+        assert!(parse_chip("pca9555:1").unwrap().is_err());
+        assert!(parse_chip("pca9555:a:0x20").unwrap().is_err());
+    }
+
+    #[test]
+    fn test_parse_line() {
+        assert!(parse_line("SX1302_RESET").is_none());
+        assert!(parse_line("/dev/gpiochip0:17").is_none());
+        assert_eq!(
+            ("i2c:1:0x20".to_string(), 3),
+            parse_line("i2c:1:0x20:3").unwrap().unwrap()
+        );
+        assert_eq!(
+            ("mcp23017:1:0x21".to_string(), 12),
+            parse_line("mcp23017:1:0x21:12").unwrap().unwrap()
+        );
+        assert!(parse_line("i2c:1:0x20:x").unwrap().is_err());
+        assert!(parse_line("i2c:1:0x20").unwrap().is_err());
+    }
+}
@@ -1,7 +1,11 @@
+use std::fmt;
+use std::str::FromStr;
 use std::time::Duration;
 
 use anyhow::Result;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
+
+use super::{api, dutycycle, history, metrics};
 
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
 pub enum TxMode {
@@ -17,6 +21,230 @@ pub trait TxPacket {
     fn set_tx_mode(&mut self, tx_mode: TxMode);
     fn get_count_us(&self) -> u32;
     fn set_count_us(&mut self, count_us: u32);
+    fn get_frequency(&self) -> u32;
+}
+
+// Min. delay of an immediate packet.
+const IMMEDIATE_DELAY: Duration = Duration::from_secs(1);
+
+// Max. delay of a packet.
+const TX_MAX_ADVANCE_DELAY: Duration = Duration::from_secs((3 + 1) * 128);
+
+/// Scheduling policy of the queue. The queue validates the timing of every packet (collisions,
+/// too late, too early), the scheduler decides when an immediate packet is planned at the
+/// earliest and which queued packets make way for a new packet.
+pub trait Scheduler<T>: Send {
+    /// Returns the min. delay, relative to the current counter, of the immediate packet. The
+    /// queued argument contains the frequency and airtime of the queued packets.
+    fn delay(&self, packet: &T, queued: &[(u32, Duration)]) -> Result<Duration, Rejection>;
+
+    /// Returns true when the queued packet, colliding with a new packet with a fixed time, is
+    /// evicted. The immediate argument is set when the queued packet was enqueued as immediate
+    /// packet. Evicted immediate packets are re-planned, the other evicted packets are dropped.
+    fn evict(&self, queued: &T, immediate: bool) -> bool;
+
+    /// Returns true when the scheduler delays the immediate packets until the duty-cycle budget
+    /// allows these, in which case these must not be rejected when the budget is exhausted. The
+    /// scheduler rejects these when the budget does not allow these within the max. advance
+    /// delay.
+    fn plans_duty_cycle(&self) -> bool;
+}
+
+/// Scheduling strategy, see Scheduler.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Strategy {
+    // Packets are scheduled in the order in which these are received, colliding packets are
+    // rejected.
+    Fifo,
+    // Packets with a fixed time (timestamped, GPS) have priority over immediate packets, the
+    // colliding immediate packets are re-planned.
+    PriorityEvict,
+    // Immediate packets are planned at the time the duty-cycle budget of their band allows
+    // these.
+    DutyCycle,
+}
+
+impl Strategy {
+    pub fn scheduler<T: TxPacket>(&self) -> Box<dyn Scheduler<T>> {
+        match self {
+            Strategy::Fifo => Box::new(Fifo),
+            Strategy::PriorityEvict => Box::new(PriorityEvict),
+            Strategy::DutyCycle => Box::new(DutyCyclePlanner),
+        }
+    }
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Strategy::Fifo => write!(f, "FIFO"),
+            Strategy::PriorityEvict => write!(f, "PRIORITY_EVICT"),
+            Strategy::DutyCycle => write!(f, "DUTY_CYCLE"),
+        }
+    }
+}
+
+impl FromStr for Strategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "" | "FIFO" => Strategy::Fifo,
+            "PRIORITY_EVICT" => Strategy::PriorityEvict,
+            "DUTY_CYCLE" => Strategy::DutyCycle,
+            _ => return Err(anyhow!("unexpected jit scheduler: {}", s)),
+        })
+    }
+}
+
+pub struct Fifo;
+
+impl<T: TxPacket> Scheduler<T> for Fifo {
+    fn delay(&self, _: &T, _: &[(u32, Duration)]) -> Result<Duration, Rejection> {
+        Ok(IMMEDIATE_DELAY)
+    }
+
+    fn evict(&self, _: &T, _: bool) -> bool {
+        false
+    }
+
+    fn plans_duty_cycle(&self) -> bool {
+        false
+    }
+}
+
+pub struct PriorityEvict;
+
+impl<T: TxPacket> Scheduler<T> for PriorityEvict {
+    fn delay(&self, _: &T, _: &[(u32, Duration)]) -> Result<Duration, Rejection> {
+        Ok(IMMEDIATE_DELAY)
+    }
+
+    fn evict(&self, _: &T, immediate: bool) -> bool {
+        immediate
+    }
+
+    fn plans_duty_cycle(&self) -> bool {
+        false
+    }
+}
+
+pub struct DutyCyclePlanner;
+
+impl<T: TxPacket> Scheduler<T> for DutyCyclePlanner {
+    fn delay(&self, packet: &T, queued: &[(u32, Duration)]) -> Result<Duration, Rejection> {
+        let time_on_air = match packet.get_time_on_air() {
+            Ok(v) => v,
+            Err(_) => return Ok(IMMEDIATE_DELAY),
+        };
+
+        let exhausted = match dutycycle::check(packet.get_frequency(), time_on_air, queued) {
+            Ok(_) => return Ok(IMMEDIATE_DELAY),
+            Err(v) => v,
+        };
+
+        // The packet is rejected when the budget of the band does not allow the airtime within
+        // the max. advance delay.
+        match dutycycle::available_in(packet.get_frequency(), time_on_air, queued) {
+            Some(v) if v + IMMEDIATE_DELAY <= TX_MAX_ADVANCE_DELAY => Ok(v.max(IMMEDIATE_DELAY)),
+            _ => Err(Rejection::DutyCycle(exhausted)),
+        }
+    }
+
+    fn evict(&self, _: &T, _: bool) -> bool {
+        false
+    }
+
+    fn plans_duty_cycle(&self) -> bool {
+        true
+    }
+}
+
+/// Packet with which a new packet collides.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Conflict {
+    // The packet that is being transmitted.
+    Running,
+    // The queued packet with the given downlink ID.
+    Queued(u32),
+}
+
+/// Reason why a packet can not be enqueued, this is returned as rejection details to the
+/// network server.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum Rejection {
+    QueueFull,
+    InternalError,
+    Collision(Conflict),
+    // The packet is scheduled too late by the given duration.
+    TooLate(Duration),
+    // The packet is scheduled too early by the given duration.
+    TooEarly(Duration),
+    // The duty-cycle budget does not allow the packet (DUTY_CYCLE scheduler).
+    DutyCycle(dutycycle::Exhausted),
+}
+
+impl Rejection {
+    pub fn status(&self) -> chirpstack_api::gw::TxAckStatus {
+        match self {
+            Rejection::QueueFull => chirpstack_api::gw::TxAckStatus::QueueFull,
+            Rejection::InternalError => chirpstack_api::gw::TxAckStatus::InternalError,
+            Rejection::Collision(_) => chirpstack_api::gw::TxAckStatus::CollisionPacket,
+            Rejection::TooLate(_) => chirpstack_api::gw::TxAckStatus::TooLate,
+            Rejection::TooEarly(_) => chirpstack_api::gw::TxAckStatus::TooEarly,
+            // There is no duty-cycle status, this is reported as TOO_EARLY with the DUTY_CYCLE
+            // rejection reason.
+            Rejection::DutyCycle(_) => chirpstack_api::gw::TxAckStatus::TooEarly,
+        }
+    }
+
+    /// Returns the rejection details of the given downlink item.
+    pub fn to_proto(&self, item: usize) -> api::DownlinkRejection {
+        let mut out = api::DownlinkRejection {
+            item: item as u32,
+            ..Default::default()
+        };
+
+        match self {
+            Rejection::QueueFull => out.reason = "QUEUE_FULL".to_string(),
+            Rejection::InternalError => out.reason = "INTERNAL_ERROR".to_string(),
+            Rejection::Collision(conflict) => {
+                out.reason = "COLLISION".to_string();
+                if let Conflict::Queued(downlink_id) = conflict {
+                    out.conflict_downlink_id = *downlink_id;
+                }
+            }
+            Rejection::TooLate(v) => {
+                out.reason = "TOO_LATE".to_string();
+                out.offset_us = v.as_micros() as u32;
+            }
+            Rejection::TooEarly(v) => {
+                out.reason = "TOO_EARLY".to_string();
+                out.offset_us = v.as_micros() as u32;
+            }
+            Rejection::DutyCycle(v) => out = v.to_proto(item),
+        }
+
+        out
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rejection::QueueFull => write!(f, "queue is full"),
+            Rejection::InternalError => write!(f, "internal error"),
+            Rejection::Collision(Conflict::Running) => {
+                write!(f, "collision with the running transmission")
+            }
+            Rejection::Collision(Conflict::Queued(v)) => {
+                write!(f, "collision with downlink_id {}", v)
+            }
+            Rejection::TooLate(v) => write!(f, "too late by {:?}", v),
+            Rejection::TooEarly(v) => write!(f, "too early by {:?}", v),
+            Rejection::DutyCycle(v) => write!(f, "{}", v),
+        }
+    }
 }
 
 pub struct Item<T> {
@@ -25,11 +253,14 @@ pub struct Item<T> {
     linear_count: Duration,
     pre_delay: Duration,
     post_delay: Duration,
+    // The packet was enqueued as immediate packet.
+    immediate: bool,
     packet: T,
 }
 
 pub struct Queue<T> {
     items: Vec<Item<T>>,
+    scheduler: Box<dyn Scheduler<T>>,
 
     tx_start_delay: Duration,
     tx_margin_delay: Duration,
@@ -55,11 +286,12 @@ impl<T: TxPacket + Copy> Queue<T> {
 
         Queue {
             items: Vec::with_capacity(capacity),
+            scheduler: Strategy::Fifo.scheduler(),
 
             tx_start_delay: Duration::from_micros(1500),
             tx_margin_delay: Duration::from_micros(1000),
             tx_jit_delay: Duration::from_micros(30000),
-            tx_max_advance_delay: Duration::from_secs((3 + 1) * 128),
+            tx_max_advance_delay: TX_MAX_ADVANCE_DELAY,
 
             concentrator_count_last: 0,
             linear_count_last: Duration::from_secs(0),
@@ -67,10 +299,33 @@ impl<T: TxPacket + Copy> Queue<T> {
         }
     }
 
+    /// Set the scheduling strategy of the queue.
+    pub fn with_strategy(mut self, strategy: Strategy) -> Queue<T> {
+        self.set_strategy(strategy);
+        self
+    }
+
+    /// Set the scheduling strategy of the queue. The queued packets are kept, e.g. on a
+    /// configuration reload.
+    pub fn set_strategy(&mut self, strategy: Strategy) {
+        info!("Setting JIT queue scheduler, strategy: {}", strategy);
+        self.scheduler = strategy.scheduler();
+    }
+
+    /// Returns true when the duty-cycle of the packet is planned by the scheduler, see
+    /// Scheduler::plans_duty_cycle.
+    pub fn plans_duty_cycle(&self, packet: &T) -> bool {
+        packet.get_tx_mode() == TxMode::Immediate && self.scheduler.plans_duty_cycle()
+    }
+
     pub fn size(&self) -> usize {
         self.items.capacity()
     }
 
+    pub fn count(&self) -> usize {
+        self.items.len()
+    }
+
     pub fn empty(&self) -> bool {
         self.items.len() == 0
     }
@@ -79,6 +334,40 @@ impl<T: TxPacket + Copy> Queue<T> {
         self.items.len() == self.size()
     }
 
+    /// Removes the queued packets and resets the counter tracking. This must be called when the
+    /// concentrator has been re-initialized, as this resets the concentrator counter.
+    pub fn reset(&mut self) {
+        self.items.clear();
+        self.concentrator_count_last = 0;
+        self.linear_count_last = Duration::from_secs(0);
+        self.tx_linear_count_finished = Duration::from_secs(0);
+        self.update_depth();
+    }
+
+    /// Removes all items from the queue and returns the packets, ordered by their scheduled
+    /// time. This is used to re-schedule the pending packets after the concentrator has been
+    /// re-initialized.
+    pub fn drain(&mut self) -> Vec<T> {
+        let packets = self.items.drain(..).map(|v| v.packet).collect();
+        self.update_depth();
+        packets
+    }
+
+    /// Returns the frequency and airtime of the queued packets. As the airtime is recorded once
+    /// transmitted, this must be taken into account by the duty-cycle check (see dutycycle).
+    pub fn queued_airtime(&self) -> Vec<(u32, Duration)> {
+        self.items
+            .iter()
+            .map(|v| (v.packet.get_frequency(), v.post_delay))
+            .collect()
+    }
+
+    /// Returns the queued packets, ordered by their scheduled time, without removing these from
+    /// the queue.
+    pub fn packets(&self) -> Vec<T> {
+        self.items.iter().map(|v| v.packet).collect()
+    }
+
     pub fn pop(&mut self, concentrator_count: u32) -> Option<T> {
         let linear_count = self.get_linear_count(concentrator_count);
 
@@ -94,7 +383,9 @@ impl<T: TxPacket + Copy> Queue<T> {
                     error!("Scheduled packet is too old, dropped: count_us: {}, current_counter_us: {}", 
                         v.packet.get_count_us(),
                         concentrator_count);
+                    history::record_dropped(v.packet.get_id(), v.packet.get_count_us());
                     self.items.remove(0);
+                    self.update_depth();
                     return None;
                 }
 
@@ -106,19 +397,17 @@ impl<T: TxPacket + Copy> Queue<T> {
         };
 
         let item = self.items.remove(0);
+        self.update_depth();
 
         // This value holds the counter when the concentrator is done transmitting the packet. This
         // is needed to detect possible collisions if enqueueing new packets.
         self.tx_linear_count_finished = item.linear_count + item.post_delay;
 
-        return Some(item.packet);
+        Some(item.packet)
     }
 
-    pub fn enqueue(
-        &mut self,
-        concentrator_count: u32,
-        packet: T,
-    ) -> Result<(), chirpstack_api::gw::TxAckStatus> {
+    /// Enqueues the packet and returns the concentrator counter at which it is scheduled.
+    pub fn enqueue(&mut self, concentrator_count: u32, packet: T) -> Result<u32, Rejection> {
         let linear_count = self.get_linear_count(concentrator_count);
 
         match packet.get_tx_mode() {
@@ -147,15 +436,183 @@ impl<T: TxPacket + Copy> Queue<T> {
             }
         }
 
+        let (item, evicted) = match self.schedule(linear_count, packet) {
+            Ok(v) => (v, Vec::new()),
+            Err(Rejection::Collision(Conflict::Queued(_))) => {
+                self.schedule_evicting(linear_count, packet)?
+            }
+            Err(rejection) => return Err(rejection),
+        };
+
+        debug!(
+            "Packet enqueued, downlink_id: {}, count_us: {}",
+            item.packet.get_id(),
+            item.packet.get_count_us()
+        );
+
+        let count_us = item.packet.get_count_us();
+        self.items.push(item);
+        self.sort();
+
+        for item in evicted {
+            self.replan(linear_count, item);
+        }
+        self.update_depth();
+
+        Ok(count_us)
+    }
+
+    /// Enqueues a beacon. Beacons have priority over the other packets: packets colliding with
+    /// the beacon are removed from the queue. These are restored when the beacon can not be
+    /// enqueued.
+    pub fn enqueue_beacon(&mut self, concentrator_count: u32, packet: T) -> Result<(), Rejection> {
+        let linear_count = self.get_linear_count(concentrator_count);
+        let beacon_count = self.concentrator_count_to_linear_count(packet.get_count_us());
+        let pre_delay = self.tx_start_delay + self.tx_jit_delay;
+        let post_delay = match packet.get_time_on_air() {
+            Ok(v) => v,
+            Err(err) => {
+                error!("Get time on air for tx packet error, error: {}", err);
+                return Err(Rejection::InternalError);
+            }
+        };
+
+        let mut removed: Vec<Item<T>> = Vec::new();
+        let mut i = 0;
+        while i < self.items.len() {
+            if self.overlaps(&self.items[i], beacon_count, pre_delay, post_delay) {
+                removed.push(self.items.remove(i));
+            } else {
+                i += 1;
+            }
+        }
+
+        let item = match self.schedule(linear_count, packet) {
+            Ok(v) => v,
+            Err(rejection) => {
+                self.items.extend(removed);
+                self.sort();
+                return Err(rejection);
+            }
+        };
+
+        for r in &removed {
+            warn!(
+                "Packet colliding with beacon, dropped: downlink_id: {}, count_us: {}",
+                r.packet.get_id(),
+                r.packet.get_count_us()
+            );
+        }
+
+        debug!(
+            "Beacon packet enqueued, downlink_id: {}, count_us: {}, current_counter_us: {}",
+            item.packet.get_id(),
+            item.packet.get_count_us(),
+            concentrator_count,
+        );
+
+        self.items.push(item);
+        self.sort();
+        self.update_depth();
+
+        Ok(())
+    }
+
+    /// Validates that the packet can be enqueued, without enqueueing it. This is used to
+    /// pre-arm a synchronized transmission.
+    pub fn validate(&mut self, concentrator_count: u32, packet: T) -> Result<(), Rejection> {
+        let linear_count = self.get_linear_count(concentrator_count);
+        self.schedule(linear_count, packet).map(|_| ())
+    }
+
+    // Removes the queued packets colliding with the packet which are evicted by the scheduler
+    // and returns the queue item of the packet, together with the evicted items. Nothing is
+    // removed when the packet can not be scheduled.
+    fn schedule_evicting(
+        &mut self,
+        linear_count: Duration,
+        packet: T,
+    ) -> Result<(Item<T>, Vec<Item<T>>), Rejection> {
+        let count = self.concentrator_count_to_linear_count(packet.get_count_us());
+        let pre_delay = self.tx_start_delay + self.tx_jit_delay;
+        let post_delay = match packet.get_time_on_air() {
+            Ok(v) => v,
+            Err(err) => {
+                error!("Get time on air for tx packet error, error: {}", err);
+                return Err(Rejection::InternalError);
+            }
+        };
+
+        let mut evicted: Vec<Item<T>> = Vec::new();
+        let mut i = 0;
+        while i < self.items.len() {
+            let p = &self.items[i];
+            if self.overlaps(p, count, pre_delay, post_delay)
+                && self.scheduler.evict(&p.packet, p.immediate)
+            {
+                evicted.push(self.items.remove(i));
+            } else {
+                i += 1;
+            }
+        }
+
+        match self.schedule(linear_count, packet) {
+            Ok(v) => Ok((v, evicted)),
+            Err(rejection) => {
+                self.items.extend(evicted);
+                self.sort();
+                Err(rejection)
+            }
+        }
+    }
+
+    // Re-plans the evicted item in the first available slot when it was enqueued as immediate
+    // packet, else it is dropped.
+    fn replan(&mut self, linear_count: Duration, item: Item<T>) {
+        let mut packet = item.packet;
+
+        if item.immediate {
+            packet.set_tx_mode(TxMode::Immediate);
+            match self.schedule(linear_count, packet) {
+                Ok(v) => {
+                    info!(
+                        "Evicted packet re-planned, downlink_id: {}, count_us: {}",
+                        v.packet.get_id(),
+                        v.packet.get_count_us()
+                    );
+                    self.items.push(v);
+                    self.sort();
+                    return;
+                }
+                Err(rejection) => {
+                    warn!(
+                        "Re-plan evicted packet error, downlink_id: {}, reason: {}",
+                        packet.get_id(),
+                        rejection
+                    );
+                }
+            }
+        }
+
+        warn!(
+            "Evicted packet dropped, downlink_id: {}, count_us: {}",
+            item.packet.get_id(),
+            item.packet.get_count_us()
+        );
+        history::record_dropped(item.packet.get_id(), item.packet.get_count_us());
+    }
+
+    // Returns the queue item of the packet, after checking that it can be scheduled.
+    fn schedule(&self, linear_count: Duration, packet: T) -> Result<Item<T>, Rejection> {
         if self.full() {
-            return Err(chirpstack_api::gw::TxAckStatus::QueueFull);
+            return Err(Rejection::QueueFull);
         }
 
         let time_on_air = match packet.get_time_on_air() {
             Ok(v) => v,
             Err(err) => {
                 error!("Get time on air for tx packet error, error: {}", err);
-                return Err(chirpstack_api::gw::TxAckStatus::InternalError);
+                return Err(Rejection::InternalError);
             }
         };
 
@@ -164,6 +621,7 @@ impl<T: TxPacket + Copy> Queue<T> {
             linear_count: Duration::from_micros(0),
             pre_delay: self.tx_start_delay + self.tx_jit_delay,
             post_delay: time_on_air,
+            immediate: packet.get_tx_mode() == TxMode::Immediate,
             packet: packet,
         };
 
@@ -172,8 +630,9 @@ impl<T: TxPacket + Copy> Queue<T> {
         if item.packet.get_tx_mode() == TxMode::Immediate {
             item.packet.set_tx_mode(TxMode::Timestamped);
 
-            // use now + 1 sec
-            let mut asap_count = linear_count + Duration::from_secs(1);
+            // use now + the delay of the scheduler (1 sec by default)
+            let mut asap_count =
+                linear_count + self.scheduler.delay(&item.packet, &self.queued_airtime())?;
 
             // eventual collision with currently running packet
             // not anymore in queue but still there
@@ -201,39 +660,36 @@ impl<T: TxPacket + Copy> Queue<T> {
         } else {
             item.linear_count = self.concentrator_count_to_linear_count(item.packet.get_count_us());
             if item.packet.get_tx_mode() == TxMode::Timestamped {
-                if self.collision_test(item.linear_count, item.pre_delay, item.post_delay) {
-                    return Err(chirpstack_api::gw::TxAckStatus::CollisionPacket);
+                if let Some(conflict) =
+                    self.find_collision(item.linear_count, item.pre_delay, item.post_delay)
+                {
+                    return Err(Rejection::Collision(conflict));
                 }
             } else if item.packet.get_tx_mode() == TxMode::OnGPS {
-                if self.collision_test(item.linear_count, item.pre_delay, item.post_delay) {
-                    return Err(chirpstack_api::gw::TxAckStatus::CollisionPacket);
+                if let Some(conflict) =
+                    self.find_collision(item.linear_count, item.pre_delay, item.post_delay)
+                {
+                    return Err(Rejection::Collision(conflict));
                 }
             }
         }
 
         // Is it too late to send this packet?
-        if item.linear_count < linear_count
-            || item.linear_count - linear_count
-                < self.tx_start_delay + self.tx_margin_delay + self.tx_jit_delay
-        {
-            return Err(chirpstack_api::gw::TxAckStatus::TooLate);
+        let min_delay = self.tx_start_delay + self.tx_margin_delay + self.tx_jit_delay;
+        if item.linear_count < linear_count + min_delay {
+            return Err(Rejection::TooLate(
+                linear_count + min_delay - item.linear_count,
+            ));
         }
 
         // Is it too early to send this packet?
         if item.linear_count - linear_count > self.tx_max_advance_delay {
-            return Err(chirpstack_api::gw::TxAckStatus::TooEarly);
+            return Err(Rejection::TooEarly(
+                item.linear_count - linear_count - self.tx_max_advance_delay,
+            ));
         }
 
-        debug!(
-            "Packet enqueued, downlink_id: {}, count_us: {}",
-            item.packet.get_id(),
-            item.packet.get_count_us()
-        );
-
-        self.items.push(item);
-        self.sort();
-
-        return Ok(());
+        Ok(item)
     }
 
     fn get_linear_count(&mut self, concentrator_count: u32) -> Duration {
@@ -262,30 +718,115 @@ impl<T: TxPacket + Copy> Queue<T> {
         })
     }
 
+    fn update_depth(&self) {
+        metrics::set_gauge("jit_queue_depth", &[], self.items.len() as f64);
+    }
+
     fn collision_test(&self, count: Duration, pre_delay: Duration, post_delay: Duration) -> bool {
+        self.find_collision(count, pre_delay, post_delay).is_some()
+    }
+
+    fn find_collision(
+        &self,
+        count: Duration,
+        pre_delay: Duration,
+        post_delay: Duration,
+    ) -> Option<Conflict> {
         if count < self.tx_linear_count_finished + pre_delay + self.tx_margin_delay {
             // a packet is currently running, then we need to take it into account
-            return true;
+            return Some(Conflict::Running);
         }
 
         for p2 in self.items.iter() {
-            if count > p2.linear_count {
-                if count - p2.linear_count <= pre_delay + p2.post_delay + self.tx_margin_delay {
-                    return true;
-                }
-            } else {
-                if p2.linear_count - count <= p2.pre_delay + post_delay + self.tx_margin_delay {
-                    return true;
-                }
+            if self.overlaps(p2, count, pre_delay, post_delay) {
+                return Some(Conflict::Queued(p2.packet.get_id()));
             }
         }
-        return false;
+        return None;
+    }
+
+    fn overlaps(
+        &self,
+        p2: &Item<T>,
+        count: Duration,
+        pre_delay: Duration,
+        post_delay: Duration,
+    ) -> bool {
+        if count > p2.linear_count {
+            count - p2.linear_count <= pre_delay + p2.post_delay + self.tx_margin_delay
+        } else {
+            p2.linear_count - count <= p2.pre_delay + post_delay + self.tx_margin_delay
+        }
+    }
+}
+
+/// Scheduled time of a pending packet (see Queue::drain), relative to the host monotonic time
+/// (see timesource::monotonic). This is used to re-schedule the pending packets without GPS
+/// time reference, as the concentrator counter is reset when the concentrator is
+/// re-initialized.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PendingOffset {
+    remaining: Duration,
+    saved_at: Duration,
+}
+
+impl PendingOffset {
+    /// Returns the offset of the packet, scheduled at count_us, relative to the current
+    /// concentrator counter and monotonic time. None is returned when the scheduled time has
+    /// passed.
+    pub fn new(count_us: u32, concentrator_count: u32, now: Duration) -> Option<PendingOffset> {
+        let remaining = count_us.wrapping_sub(concentrator_count) as i32;
+        if remaining <= 0 {
+            return None;
+        }
+
+        Some(PendingOffset {
+            remaining: Duration::from_micros(remaining as u64),
+            saved_at: now,
+        })
+    }
+
+    /// Returns the concentrator counter at which the packet must be scheduled, given the
+    /// counter and monotonic time after re-initialization. None is returned when the scheduled
+    /// time has passed in the meantime. Note that the packet must still be validated by
+    /// enqueueing it, e.g. it might be too late by now.
+    pub fn count_us(&self, concentrator_count: u32, now: Duration) -> Option<u32> {
+        let remaining = self
+            .remaining
+            .checked_sub(now.saturating_sub(self.saved_at))?;
+        if remaining == Duration::from_secs(0) {
+            return None;
+        }
+
+        Some(concentrator_count.wrapping_add(remaining.as_micros() as u32))
     }
 }
 
+/// Cross-checks the concentrator counter derived from the GPS epoch timing of a downlink
+/// against the counter of the uplink context. The downlink must be scheduled after the uplink
+/// and within max_offset, else the counter to GPS time mapping of the network server and the
+/// gateway disagree. TOO_LATE is returned when the downlink is scheduled before the uplink,
+/// TOO_EARLY when it exceeds max_offset.
+pub fn validate_gps_timing(
+    context_count_us: u32,
+    count_us: u32,
+    max_offset: Duration,
+) -> std::result::Result<(), chirpstack_api::gw::TxAckStatus> {
+    let offset = count_us.wrapping_sub(context_count_us) as i32;
+    if offset < 0 {
+        return Err(chirpstack_api::gw::TxAckStatus::TooLate);
+    }
+    if Duration::from_micros(offset as u64) > max_offset {
+        return Err(chirpstack_api::gw::TxAckStatus::TooEarly);
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[derive(Copy, Clone)]
     struct TxPacketMock {
@@ -318,6 +859,39 @@ mod tests {
         fn set_count_us(&mut self, count_us: u32) {
             self.count_us = count_us;
         }
+
+        fn get_frequency(&self) -> u32 {
+            868100000
+        }
+    }
+
+    #[test]
+    fn test_validate_gps_timing() {
+        let max_offset = Duration::from_secs(10);
+
+        assert!(validate_gps_timing(1000, 1_001_000, max_offset).is_ok());
+        // counter rollover
+        assert!(validate_gps_timing(u32::MAX - 1000, 1_000_000, max_offset).is_ok());
+        assert_eq!(
+            Err(chirpstack_api::gw::TxAckStatus::TooLate),
+            validate_gps_timing(2_000_000, 1_000_000, max_offset)
+        );
+        assert_eq!(
+            Err(chirpstack_api::gw::TxAckStatus::TooEarly),
+            validate_gps_timing(0, 10_000_001, max_offset)
+        );
+    }
+
+    #[test]
+    fn test_rejection_to_proto() {
+        let rejection = Rejection::Collision(Conflict::Queued(123)).to_proto(1);
+        assert_eq!(1, rejection.item);
+        assert_eq!("COLLISION", rejection.reason);
+        assert_eq!(123, rejection.conflict_downlink_id);
+
+        let rejection = Rejection::TooLate(Duration::from_millis(12)).to_proto(0);
+        assert_eq!("TOO_LATE", rejection.reason);
+        assert_eq!(12000, rejection.offset_us);
     }
 
     #[test]
@@ -412,6 +986,66 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_enqueue_priority_evict() {
+        let immediate = TxPacketMock {
+            time_on_air: Duration::from_millis(100),
+            tx_mode: TxMode::Immediate,
+            count_us: 0,
+        };
+        let timestamped = TxPacketMock {
+            time_on_air: Duration::from_millis(100),
+            tx_mode: TxMode::Timestamped,
+            count_us: 1_000_100,
+        };
+
+        // the timestamped packet collides with the immediate packet
+        let mut q: Queue<TxPacketMock> = Queue::new(2);
+        assert_eq!(Ok(1_000_100), q.enqueue(100, immediate));
+        assert_eq!(
+            Err(Rejection::Collision(Conflict::Queued(0))),
+            q.enqueue(100, timestamped)
+        );
+
+        // the immediate packet is evicted and re-planned after the timestamped packet
+        let mut q: Queue<TxPacketMock> = Queue::new(2).with_strategy(Strategy::PriorityEvict);
+        q.enqueue(100, immediate).unwrap();
+        assert_eq!(Ok(1_000_100), q.enqueue(100, timestamped));
+        assert_eq!(
+            vec![1_000_100, 1_000_100 + 100_000 + 1500 + 30000 + 1000],
+            q.drain().iter().map(|v| v.count_us).collect::<Vec<u32>>()
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut q: Queue<TxPacketMock> = Queue::new(2);
+        let packet = TxPacketMock {
+            time_on_air: Duration::from_millis(100),
+            tx_mode: TxMode::OnGPS,
+            count_us: 2_000_000,
+        };
+
+        assert!(q.validate(100, packet).is_ok());
+        assert!(q.empty(), "validate must not enqueue");
+
+        q.enqueue(100, packet).unwrap();
+        assert_eq!(
+            Err(Rejection::Collision(Conflict::Queued(0))),
+            q.validate(100, packet)
+        );
+        assert_eq!(
+            Err(Rejection::TooEarly(Duration::from_micros(87_999_900))),
+            q.validate(
+                100,
+                TxPacketMock {
+                    count_us: 600_000_000,
+                    ..packet
+                }
+            )
+        );
+    }
+
     #[test]
     fn test_enqueue_immediate_u32_wrapping() {
         let mut q: Queue<TxPacketMock> = Queue::new(2);
@@ -449,6 +1083,73 @@ mod tests {
         assert_eq!(1000, item.packet.get_count_us());
     }
 
+    #[test]
+    fn test_enqueue_beacon() {
+        let mut q: Queue<TxPacketMock> = Queue::new(3);
+        let concentrator_count = Duration::from_secs(1).as_micros() as u32;
+
+        for count_us in [2_000_000, 4_000_000] {
+            q.enqueue(
+                concentrator_count,
+                TxPacketMock {
+                    time_on_air: Duration::from_millis(100),
+                    tx_mode: TxMode::Timestamped,
+                    count_us,
+                },
+            )
+            .unwrap();
+        }
+
+        let beacon = TxPacketMock {
+            time_on_air: Duration::from_millis(100),
+            tx_mode: TxMode::OnGPS,
+            count_us: 2_050_000,
+        };
+
+        // a regular packet collides with the first packet
+        assert_eq!(
+            Err(Rejection::Collision(Conflict::Queued(0))),
+            q.enqueue(concentrator_count, beacon)
+        );
+
+        // the beacon replaces the first packet
+        q.enqueue_beacon(concentrator_count, beacon).unwrap();
+        assert_eq!(
+            vec![2_050_000, 4_000_000],
+            q.drain().iter().map(|v| v.count_us).collect::<Vec<u32>>()
+        );
+    }
+
+    #[test]
+    fn test_enqueue_beacon_too_late() {
+        let mut q: Queue<TxPacketMock> = Queue::new(2);
+        let concentrator_count = Duration::from_secs(2).as_micros() as u32;
+
+        q.enqueue(
+            concentrator_count,
+            TxPacketMock {
+                time_on_air: Duration::from_millis(100),
+                tx_mode: TxMode::Timestamped,
+                count_us: 2_100_000,
+            },
+        )
+        .unwrap();
+
+        // the beacon can not be enqueued, the colliding packet is restored
+        assert_eq!(
+            Err(Rejection::TooLate(Duration::from_micros(22_500))),
+            q.enqueue_beacon(
+                concentrator_count,
+                TxPacketMock {
+                    time_on_air: Duration::from_millis(100),
+                    tx_mode: TxMode::OnGPS,
+                    count_us: 2_010_000,
+                },
+            )
+        );
+        assert_eq!(1, q.count());
+    }
+
     #[test]
     fn test_pop_empty() {
         let mut q: Queue<TxPacketMock> = Queue::new(2);
@@ -476,6 +1177,100 @@ mod tests {
         assert_eq!(false, item.is_none());
     }
 
+    #[test]
+    fn test_drain() {
+        let mut q: Queue<TxPacketMock> = Queue::new(2);
+        let concentrator_count = Duration::from_secs(1).as_micros() as u32;
+
+        for count_us in [3, 2] {
+            q.enqueue(
+                concentrator_count,
+                TxPacketMock {
+                    time_on_air: Duration::from_millis(100),
+                    tx_mode: TxMode::Timestamped,
+                    count_us: Duration::from_secs(count_us).as_micros() as u32,
+                },
+            )
+            .unwrap();
+        }
+
+        let packets = q.drain();
+        assert!(q.empty());
+        assert_eq!(
+            vec![2_000_000, 3_000_000],
+            packets.iter().map(|v| v.count_us).collect::<Vec<u32>>()
+        );
+    }
+
+    #[test]
+    fn test_pending_offset() {
+        let concentrator_count = u32::MAX - 500_000;
+        let now = Duration::from_secs(100);
+
+        // scheduled time has passed
+        assert_eq!(
+            None,
+            PendingOffset::new(concentrator_count, concentrator_count, now)
+        );
+        assert_eq!(
+            None,
+            PendingOffset::new(concentrator_count - 1, concentrator_count, now)
+        );
+
+        // scheduled in 2 seconds, wrapping the counter
+        let offset = PendingOffset::new(
+            concentrator_count.wrapping_add(2_000_000),
+            concentrator_count,
+            now,
+        )
+        .unwrap();
+
+        // re-initialized after 500ms, with the counter reset to 1 second
+        assert_eq!(
+            Some(2_500_000),
+            offset.count_us(1_000_000, now + Duration::from_millis(500))
+        );
+
+        // re-initialized after the scheduled time
+        assert_eq!(
+            None,
+            offset.count_us(1_000_000, now + Duration::from_secs(2))
+        );
+        assert_eq!(
+            None,
+            offset.count_us(1_000_000, now + Duration::from_secs(3))
+        );
+    }
+
+    #[test]
+    fn test_pending_offset_enqueue() {
+        let mut q: Queue<TxPacketMock> = Queue::new(2);
+        let now = Duration::from_secs(100);
+        let offset = PendingOffset::new(3_000_000, 1_000_000, now).unwrap();
+
+        // feasible: the packet is re-scheduled at the same time relative to the host
+        let mut packet = TxPacketMock {
+            time_on_air: Duration::from_millis(100),
+            tx_mode: TxMode::Timestamped,
+            count_us: 0,
+        };
+        packet.count_us = offset
+            .count_us(500_000, now + Duration::from_millis(500))
+            .unwrap();
+        assert_eq!(2_000_000, packet.count_us);
+        assert!(q.enqueue(500_000, packet).is_ok());
+
+        // expired: the re-initialization took too long for the TX to be prepared in time
+        q.reset();
+        packet.count_us = offset
+            .count_us(500_000, now + Duration::from_micros(1_999_999))
+            .unwrap();
+        assert!(matches!(
+            q.enqueue(500_000, packet),
+            Err(Rejection::TooLate(_))
+        ));
+    }
+
     #[test]
     fn test_pop_too_far_in_future() {
         let mut q: Queue<TxPacketMock> = Queue::new(2);
@@ -513,4 +1308,72 @@ mod tests {
         let item = q.pop(0_u32.wrapping_sub(100));
         assert_eq!(true, item.is_some());
     }
+
+    proptest! {
+        #[test]
+        fn prop_enqueue_no_overlap(
+            concentrator_count in any::<u32>(),
+            packets in prop::collection::vec(
+                (0_u8..3, 0_u32..5_000_000, 1_u64..2_000_000),
+                1..32,
+            ),
+        ) {
+            let mut q: Queue<TxPacketMock> = Queue::new(32);
+
+            for (tx_mode, offset_us, time_on_air_us) in packets {
+                let _ = q.enqueue(
+                    concentrator_count,
+                    TxPacketMock {
+                        time_on_air: Duration::from_micros(time_on_air_us),
+                        tx_mode: match tx_mode {
+                            0 => TxMode::Immediate,
+                            1 => TxMode::Timestamped,
+                            _ => TxMode::OnGPS,
+                        },
+                        count_us: concentrator_count.wrapping_add(offset_us),
+                    },
+                );
+            }
+
+            // The items are ordered and each item starts after the previous one has finished.
+            for w in q.items.windows(2) {
+                prop_assert!(
+                    w[1].linear_count >= w[0].linear_count + w[0].post_delay + q.tx_margin_delay
+                );
+            }
+        }
+
+        #[test]
+        fn prop_pop_in_window(
+            concentrator_count in any::<u32>(),
+            offsets in prop::collection::vec(0_u32..5_000_000, 1..32),
+        ) {
+            let mut q: Queue<TxPacketMock> = Queue::new(32);
+
+            for offset_us in offsets {
+                let _ = q.enqueue(
+                    concentrator_count,
+                    TxPacketMock {
+                        time_on_air: Duration::from_millis(50),
+                        tx_mode: TxMode::Timestamped,
+                        count_us: concentrator_count.wrapping_add(offset_us),
+                    },
+                );
+            }
+            let enqueued = q.items.len();
+
+            // Poll the queue every 10ms, every packet must be popped before its scheduled time
+            // and within the pre-delay.
+            let mut popped = 0;
+            for i in 0..600 {
+                let now = concentrator_count.wrapping_add(i * 10_000);
+                if let Some(p) = q.pop(now) {
+                    let ahead = Duration::from_micros(p.get_count_us().wrapping_sub(now) as u64);
+                    prop_assert!(ahead <= q.tx_start_delay + q.tx_jit_delay);
+                    popped += 1;
+                }
+            }
+            prop_assert_eq!(enqueued, popped);
+        }
+    }
 }
@@ -0,0 +1,320 @@
+//! Deterministic simulation of the JIT queue.
+//!
+//! The simulation polls the queue the same way as the JIT loop of the Concentratord daemons, but
+//! driven by a simulated clock and a seeded stream of downlinks. Every transmission that is
+//! popped from the queue is validated, so that scheduling issues can be found without hardware.
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashSet};
+use std::time::Duration;
+
+use anyhow::Result;
+
+use super::jitqueue::{Queue, Strategy, TxMode, TxPacket};
+
+// Margin between two transmissions, this must match the tx_margin_delay of the queue.
+const TX_MARGIN: Duration = Duration::from_micros(1000);
+
+// Frequency of the simulated downlinks.
+const FREQUENCY: u32 = 868100000;
+
+// Max. time a packet is popped before its scheduled time, this must match the
+// tx_start_delay + tx_jit_delay of the queue.
+const TX_PRE_DELAY: Duration = Duration::from_micros(1500 + 30000);
+
+/// Clock which only advances when instructed to. Like the concentrator counter, it wraps around
+/// every ~71 minutes.
+#[derive(Default)]
+pub struct SimulatedClock {
+    count_us: Cell<u32>,
+}
+
+impl SimulatedClock {
+    pub fn new(count_us: u32) -> SimulatedClock {
+        SimulatedClock {
+            count_us: Cell::new(count_us),
+        }
+    }
+
+    pub fn advance(&self, d: Duration) {
+        self.count_us
+            .set(self.count_us.get().wrapping_add(d.as_micros() as u32));
+    }
+
+    pub fn count_us(&self) -> u32 {
+        self.count_us.get()
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Packet {
+    id: u32,
+    tx_mode: TxMode,
+    count_us: u32,
+    time_on_air: Duration,
+}
+
+impl TxPacket for Packet {
+    fn get_time_on_air(&self) -> Result<Duration> {
+        Ok(self.time_on_air)
+    }
+
+    fn get_tx_mode(&self) -> TxMode {
+        self.tx_mode
+    }
+
+    fn get_id(&self) -> u32 {
+        self.id
+    }
+
+    fn set_tx_mode(&mut self, tx_mode: TxMode) {
+        self.tx_mode = tx_mode;
+    }
+
+    fn get_count_us(&self) -> u32 {
+        self.count_us
+    }
+
+    fn set_count_us(&mut self, count_us: u32) {
+        self.count_us = count_us;
+    }
+
+    fn get_frequency(&self) -> u32 {
+        FREQUENCY
+    }
+}
+
+pub struct Configuration {
+    // Seed of the downlink generator. The same seed always results in the same simulation.
+    pub seed: u64,
+    // Capacity of the queue.
+    pub capacity: usize,
+    // Scheduling strategy of the queue.
+    pub strategy: Strategy,
+    // Start value of the clock (e.g. close to u32::MAX to test the counter rollover).
+    pub start_count_us: u32,
+    // Simulated time.
+    pub duration: Duration,
+    // Interval by which the queue is polled. This must be smaller than the jit delay of the
+    // queue (30ms), else packets are dropped as too old.
+    pub poll_interval: Duration,
+    // Max. interval between two downlinks. The interval is uniformly distributed between 0 and
+    // this value.
+    pub max_downlink_interval: Duration,
+}
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Configuration {
+            seed: 1,
+            capacity: 32,
+            strategy: Strategy::Fifo,
+            start_count_us: 0,
+            duration: Duration::from_secs(3600),
+            poll_interval: Duration::from_millis(10),
+            max_downlink_interval: Duration::from_secs(2),
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct Report {
+    // Number of downlinks that were accepted by the queue.
+    pub enqueued: usize,
+    // Number of rejected downlinks per status.
+    pub rejected: BTreeMap<String, usize>,
+    // Number of downlinks popped for transmission.
+    pub transmitted: usize,
+    // Number of accepted downlinks that were never transmitted.
+    pub dropped: usize,
+    // Detected scheduling violations (e.g. overlapping transmissions).
+    pub violations: Vec<String>,
+}
+
+/// Run the simulation.
+pub fn run(conf: &Configuration) -> Report {
+    let clock = SimulatedClock::new(conf.start_count_us);
+    let mut queue: Queue<Packet> = Queue::new(conf.capacity).with_strategy(conf.strategy);
+    let mut rng = Rng::new(conf.seed);
+    let mut report = Report::default();
+
+    let mut accepted: HashSet<u32> = HashSet::new();
+    let mut transmitted: HashSet<u32> = HashSet::new();
+    let mut next_id: u32 = 0;
+
+    // As the clock wraps around, the validation is done using the elapsed time since the start
+    // of the simulation.
+    let mut elapsed = Duration::from_secs(0);
+    let mut next_downlink = Duration::from_secs(0);
+    let mut tx_finished: Option<Duration> = None;
+
+    while elapsed < conf.duration {
+        while next_downlink <= elapsed {
+            let packet = random_packet(&mut rng, next_id, clock.count_us());
+            next_id += 1;
+
+            match queue.enqueue(clock.count_us(), packet) {
+                Ok(_) => {
+                    report.enqueued += 1;
+                    accepted.insert(packet.id);
+                }
+                Err(rejection) => {
+                    *report
+                        .rejected
+                        .entry(format!("{:?}", rejection.status()))
+                        .or_insert(0) += 1;
+                }
+            }
+
+            next_downlink +=
+                Duration::from_micros(rng.range(0, conf.max_downlink_interval.as_micros() as u64));
+        }
+
+        if let Some(packet) = queue.pop(clock.count_us()) {
+            report.transmitted += 1;
+
+            let ahead = packet.count_us.wrapping_sub(clock.count_us());
+            if ahead > (i32::MAX as u32) {
+                report.violations.push(format!(
+                    "packet popped after its scheduled time, id: {}, late_us: {}",
+                    packet.id,
+                    ahead.wrapping_neg()
+                ));
+            } else if Duration::from_micros(ahead as u64) > TX_PRE_DELAY {
+                report.violations.push(format!(
+                    "packet popped too early, id: {}, ahead_us: {}",
+                    packet.id, ahead
+                ));
+            }
+
+            let tx_start = elapsed + Duration::from_micros(ahead as u64);
+            if let Some(finished) = tx_finished {
+                if tx_start < finished + TX_MARGIN {
+                    report.violations.push(format!(
+                        "overlapping transmission, id: {}, start: {:?}, previous_finished: {:?}",
+                        packet.id, tx_start, finished
+                    ));
+                }
+            }
+            tx_finished = Some(tx_start + packet.time_on_air);
+
+            if !accepted.contains(&packet.id) {
+                report.violations.push(format!(
+                    "transmitted packet was never accepted, id: {}",
+                    packet.id
+                ));
+            }
+            if !transmitted.insert(packet.id) {
+                report
+                    .violations
+                    .push(format!("packet transmitted twice, id: {}", packet.id));
+            }
+        }
+
+        clock.advance(conf.poll_interval);
+        elapsed += conf.poll_interval;
+    }
+
+    let pending = queue.drain().len();
+    report.dropped = report.enqueued - report.transmitted - pending;
+
+    report
+}
+
+// Returns a packet with a random TX mode, time on air and (for timestamped and GPS packets)
+// scheduled time. The scheduled time can be in the past or too far in the future, so that the
+// rejection paths are exercised too.
+fn random_packet(rng: &mut Rng, id: u32, count_us: u32) -> Packet {
+    let tx_mode = match rng.range(0, 3) {
+        0 => TxMode::Immediate,
+        1 => TxMode::Timestamped,
+        _ => TxMode::OnGPS,
+    };
+
+    let offset_us = match rng.range(0, 20) {
+        0 => rng.range(600_000_000, 700_000_000),
+        _ => rng.range(0, 5_000_000),
+    };
+
+    Packet {
+        id,
+        tx_mode,
+        count_us: count_us.wrapping_add(offset_us as u32),
+        time_on_air: Duration::from_micros(rng.range(10_000, 2_000_000)),
+    }
+}
+
+// xorshift64* pseudo-random number generator. A dedicated implementation is used so that a
+// seed gives the same simulation on every platform and version.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        // The state must be non-zero.
+        Rng(seed | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // Returns a value in the range [min, max).
+    fn range(&mut self, min: u64, max: u64) -> u64 {
+        if max <= min {
+            return min;
+        }
+        min + self.next() % (max - min)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_deterministic() {
+        let conf = Configuration {
+            duration: Duration::from_secs(600),
+            ..Default::default()
+        };
+
+        let a = run(&conf);
+        let b = run(&conf);
+        assert_eq!(a.enqueued, b.enqueued);
+        assert_eq!(a.rejected, b.rejected);
+        assert_eq!(a.transmitted, b.transmitted);
+        assert!(a.enqueued > 0);
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn prop_no_violations(
+            seed in any::<u64>(),
+            start_count_us in any::<u32>(),
+            capacity in 1_usize..64,
+            max_downlink_interval_ms in 1_u64..5000,
+            priority_evict in any::<bool>(),
+        ) {
+            let report = run(&Configuration {
+                seed,
+                capacity,
+                strategy: match priority_evict {
+                    true => Strategy::PriorityEvict,
+                    false => Strategy::Fifo,
+                },
+                start_count_us,
+                duration: Duration::from_secs(900),
+                poll_interval: Duration::from_millis(10),
+                max_downlink_interval: Duration::from_millis(max_downlink_interval_ms),
+            });
+
+            prop_assert!(report.violations.is_empty(), "{:?}", report.violations);
+            prop_assert_eq!(0, report.dropped);
+        }
+    }
+}
@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Result;
+use log::{info, warn};
+
+use super::{stats, timesource};
+
+// MHDR (1 byte) + JoinEUI (8 bytes) + DevEUI (8 bytes) + DevNonce (2 bytes) + MIC (4 bytes).
+const JOIN_REQUEST_SIZE: usize = 23;
+
+// Max. number of tracked prefixes, before the prefixes without recent join-requests are
+// removed.
+const MAX_BUCKETS: usize = 1024;
+
+lazy_static! {
+    static ref DETECTOR: Mutex<Option<Detector>> = Mutex::new(None);
+}
+
+static FLOOD: AtomicBool = AtomicBool::new(false);
+
+/// EUI by which the join-requests are grouped.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Key {
+    JoinEui,
+    DevEui,
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Key::JoinEui => write!(f, "JOIN_EUI"),
+            Key::DevEui => write!(f, "DEV_EUI"),
+        }
+    }
+}
+
+impl FromStr for Key {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "JOIN_EUI" => Key::JoinEui,
+            "" | "DEV_EUI" => Key::DevEui,
+            _ => return Err(anyhow!("unexpected join flood key: {}", s)),
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct Configuration {
+    // EUI by which the join-requests are grouped.
+    pub key: Key,
+    // Number of (most significant) bits of the EUI prefix, 64 for the complete EUI.
+    pub prefix_bits: u32,
+    // Max. number of join-requests per prefix within the interval.
+    pub max_join_requests: u32,
+    pub interval: Duration,
+    // Do not forward the join-requests exceeding the rate.
+    pub limit: bool,
+}
+
+struct Bucket {
+    tokens: f64,
+    // Monotonic time of the last update.
+    updated: Duration,
+    // The rate is exceeded.
+    flooding: bool,
+}
+
+struct Detector {
+    conf: Configuration,
+    buckets: HashMap<u64, Bucket>,
+}
+
+impl Detector {
+    fn new(conf: Configuration) -> Detector {
+        Detector {
+            conf,
+            buckets: HashMap::new(),
+        }
+    }
+
+    fn rate(&self) -> f64 {
+        self.conf.max_join_requests as f64 / self.conf.interval.as_secs_f64()
+    }
+
+    // Returns true when the join-request with the given prefix is within the rate.
+    fn allow(&mut self, prefix: u64, now: Duration) -> bool {
+        if self.buckets.len() >= MAX_BUCKETS {
+            self.prune(now);
+        }
+
+        let rate = self.rate();
+        let burst = self.conf.max_join_requests as f64;
+        let bucket = self.buckets.entry(prefix).or_insert_with(|| Bucket {
+            tokens: burst,
+            updated: now,
+            flooding: false,
+        });
+
+        let elapsed = now.saturating_sub(bucket.updated);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * rate).min(burst);
+        bucket.updated = now;
+
+        if bucket.tokens < 1.0 {
+            if !bucket.flooding {
+                warn!(
+                    "Join-request flood detected, key: {}, prefix: {:016x}/{}, max_join_requests: {}, interval: {:?}",
+                    self.conf.key, prefix << (64 - self.conf.prefix_bits), self.conf.prefix_bits, self.conf.max_join_requests, self.conf.interval
+                );
+                bucket.flooding = true;
+            }
+            return false;
+        }
+
+        if bucket.flooding {
+            info!(
+                "Join-request flood ended, key: {}, prefix: {:016x}/{}",
+                self.conf.key,
+                prefix << (64 - self.conf.prefix_bits),
+                self.conf.prefix_bits
+            );
+            bucket.flooding = false;
+        }
+
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    // Removes the buckets which are full, these do not have recent join-requests.
+    fn prune(&mut self, now: Duration) {
+        let rate = self.rate();
+        let burst = self.conf.max_join_requests as f64;
+        self.buckets
+            .retain(|_, v| v.tokens + now.saturating_sub(v.updated).as_secs_f64() * rate < burst);
+    }
+
+    fn get_prefix(&self, phy_payload: &[u8]) -> Option<u64> {
+        let eui = get_join_request_eui(phy_payload, self.conf.key)?;
+        Some(eui >> (64 - self.conf.prefix_bits))
+    }
+}
+
+/// Validates the join flood configuration.
+pub fn validate(conf: &Configuration) -> Result<()> {
+    if conf.prefix_bits == 0 || conf.prefix_bits > 64 {
+        return Err(anyhow!("prefix_bits must be between 1 and 64"));
+    }
+
+    if conf.max_join_requests == 0 {
+        return Err(anyhow!("max_join_requests must be greater than 0"));
+    }
+
+    if conf.interval.is_zero() {
+        return Err(anyhow!("interval must be greater than 0"));
+    }
+
+    Ok(())
+}
+
+/// Set the join flood detection configuration. When None, join flood detection is disabled.
+pub fn setup(conf: Option<Configuration>) {
+    if let Some(conf) = &conf {
+        info!(
+            "Setting up join-request flood detection, key: {}, prefix_bits: {}, max_join_requests: {}, interval: {:?}, limit: {}",
+            conf.key, conf.prefix_bits, conf.max_join_requests, conf.interval, conf.limit
+        );
+    }
+
+    *DETECTOR.lock().unwrap() = conf.map(Detector::new);
+}
+
+/// Returns false when the uplink is a join-request exceeding the rate and limiting is
+/// enabled, in which case it must not be forwarded.
+pub fn allow(pl: &chirpstack_api::gw::UplinkFrame) -> bool {
+    let mut detector = DETECTOR.lock().unwrap();
+    let detector = match detector.as_mut() {
+        Some(v) => v,
+        None => return true,
+    };
+
+    let prefix = match detector.get_prefix(&pl.phy_payload) {
+        Some(v) => v,
+        None => return true,
+    };
+
+    if detector.allow(prefix, timesource::monotonic()) {
+        return true;
+    }
+
+    FLOOD.store(true, Ordering::Relaxed);
+    if detector.conf.limit {
+        stats::inc_counter("join_requests_dropped");
+        false
+    } else {
+        stats::inc_counter("join_requests_excess");
+        true
+    }
+}
+
+/// Returns the join flood flag as stats meta-data. The flag is set when the rate was exceeded
+/// since the previous stats.
+pub fn get_metadata() -> HashMap<String, String> {
+    let mut out = HashMap::new();
+    if DETECTOR.lock().unwrap().is_some() {
+        out.insert(
+            "join_flood".to_string(),
+            FLOOD.swap(false, Ordering::Relaxed).to_string(),
+        );
+    }
+    out
+}
+
+// Returns the JoinEUI or DevEUI of the given PHYPayload, None when this is not a join-request.
+fn get_join_request_eui(phy_payload: &[u8], key: Key) -> Option<u64> {
+    if phy_payload.len() != JOIN_REQUEST_SIZE || phy_payload[0] >> 5 != 0 {
+        return None;
+    }
+
+    // The EUIs are encoded little endian.
+    let mut b: [u8; 8] = [0; 8];
+    match key {
+        Key::JoinEui => b.copy_from_slice(&phy_payload[1..9]),
+        Key::DevEui => b.copy_from_slice(&phy_payload[9..17]),
+    }
+
+    Some(u64::from_le_bytes(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn join_request(join_eui: u64, dev_eui: u64) -> Vec<u8> {
+        let mut b = vec![0x00];
+        b.extend_from_slice(&join_eui.to_le_bytes());
+        b.extend_from_slice(&dev_eui.to_le_bytes());
+        b.extend_from_slice(&[0; 6]);
+        b
+    }
+
+    #[test]
+    fn test_get_join_request_eui() {
+        let b = join_request(0x0102030405060708, 0x1112131415161718);
+        assert_eq!(
+            Some(0x0102030405060708),
+            get_join_request_eui(&b, Key::JoinEui)
+        );
+        assert_eq!(
+            Some(0x1112131415161718),
+            get_join_request_eui(&b, Key::DevEui)
+        );
+
+        // unconfirmed data-up
+        let mut b = b.clone();
+        b[0] = 0x40;
+        assert_eq!(None, get_join_request_eui(&b, Key::DevEui));
+        assert_eq!(None, get_join_request_eui(&[0x00], Key::DevEui));
+    }
+
+    #[test]
+    fn test_detector() {
+        let mut detector = Detector::new(Configuration {
+            key: Key::DevEui,
+            prefix_bits: 32,
+            max_join_requests: 2,
+            interval: Duration::from_secs(60),
+            limit: true,
+        });
+        let start = Duration::from_secs(100);
+
+        // the devices share the same prefix
+        let a = detector
+            .get_prefix(&join_request(0, 0x0102030400000001))
+            .unwrap();
+        let b = detector
+            .get_prefix(&join_request(0, 0x0102030400000002))
+            .unwrap();
+        assert_eq!(a, b);
+
+        assert!(detector.allow(a, start));
+        assert!(detector.allow(b, start));
+        assert!(!detector.allow(a, start));
+
+        // a different prefix has its own bucket
+        assert!(detector.allow(0x01020305, start));
+
+        // one join-request per 30s is restored
+        assert!(detector.allow(a, start + Duration::from_secs(30)));
+        assert!(!detector.allow(a, start + Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn test_validate() {
+        let conf = Configuration {
+            key: Key::DevEui,
+            prefix_bits: 64,
+            max_join_requests: 10,
+            interval: Duration::from_secs(60),
+            limit: false,
+        };
+        assert!(validate(&conf).is_ok());
+        assert!(validate(&Configuration {
+            prefix_bits: 0,
+            ..conf.clone()
+        })
+        .is_err());
+        assert!(validate(&Configuration {
+            max_join_requests: 0,
+            ..conf.clone()
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use log::{debug, info, warn};
+
+lazy_static! {
+    static ref CONFIGURATION: Mutex<Option<Configuration>> = Mutex::new(None);
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Method {
+    // TCP connect to host:port.
+    Tcp,
+    // ICMP echo request using the system ping command.
+    Icmp,
+}
+
+impl FromStr for Method {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "TCP" => Method::Tcp,
+            "ICMP" => Method::Icmp,
+            _ => return Err(anyhow!("unexpected latency probe method: {}", s)),
+        })
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Method::Tcp => write!(f, "TCP"),
+            Method::Icmp => write!(f, "ICMP"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Configuration {
+    pub method: Method,
+    // Target of the probe, host:port for TCP and host for ICMP.
+    pub target: String,
+    // Max. time to wait for the probe to complete.
+    pub timeout: Duration,
+}
+
+/// Setup the backhaul latency probe. The probe is executed by the stats on every stats
+/// interval.
+pub fn setup(conf: &Configuration) {
+    info!(
+        "Setting up backhaul latency probe, method: {}, target: {}, timeout: {:?}",
+        conf.method, conf.target, conf.timeout
+    );
+
+    *CONFIGURATION.lock().unwrap() = Some(conf.clone());
+}
+
+/// Execute the probe and return the measured round-trip latency (ms) as meta-data. When the
+/// probe fails, the error is returned as meta-data instead. Nothing is returned when the probe
+/// has not been setup.
+pub fn get_metadata() -> HashMap<String, String> {
+    let conf = match CONFIGURATION.lock().unwrap().clone() {
+        Some(v) => v,
+        None => return HashMap::new(),
+    };
+
+    let res = match conf.method {
+        Method::Tcp => probe_tcp(&conf.target, conf.timeout),
+        Method::Icmp => probe_icmp(&conf.target, conf.timeout),
+    };
+
+    let mut out = HashMap::new();
+
+    match res {
+        Ok(latency_ms) => {
+            debug!(
+                "Backhaul latency probed, target: {}, latency_ms: {:.2}",
+                conf.target, latency_ms
+            );
+            out.insert(
+                "backhaul_latency_ms".to_string(),
+                format!("{:.2}", latency_ms),
+            );
+        }
+        Err(err) => {
+            warn!(
+                "Backhaul latency probe error, target: {}, error: {}",
+                conf.target, err
+            );
+            out.insert("backhaul_latency_error".to_string(), err.to_string());
+        }
+    }
+
+    out
+}
+
+fn probe_tcp(target: &str, timeout: Duration) -> Result<f64> {
+    // The name is resolved before starting the timer, such that only the connect is measured.
+    let addr = target
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| anyhow!("no address for target: {}", target))?;
+
+    let start = Instant::now();
+    let _stream = TcpStream::connect_timeout(&addr, timeout)?;
+    Ok(start.elapsed().as_secs_f64() * 1000.0)
+}
+
+fn probe_icmp(target: &str, timeout: Duration) -> Result<f64> {
+    // The ping timeout is in seconds.
+    let timeout_secs = std::cmp::max(1, (timeout.as_millis() + 999) / 1000);
+
+    let output = Command::new("ping")
+        .args(&["-c", "1", "-W", &timeout_secs.to_string(), target])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()?;
+
+    if !output.status.success() {
+        return Err(anyhow!("ping exited with {}", output.status));
+    }
+
+    parse_ping_output(&String::from_utf8_lossy(&output.stdout))
+}
+
+// Parses the round-trip time (ms) of the echo reply from the ping output, e.g.:
+// 64 bytes from 192.0.2.1: seq=0 ttl=56 time=12.345 ms
+fn parse_ping_output(output: &str) -> Result<f64> {
+    let time = output
+        .split_whitespace()
+        .find_map(|v| v.strip_prefix("time="))
+        .ok_or_else(|| anyhow!("no round-trip time in ping output"))?;
+
+    Ok(time.trim_end_matches("ms").parse()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ping_output() {
+        // iputils
+        let output = "PING 192.0.2.1 (192.0.2.1) 56(84) bytes of data.\n64 bytes from 192.0.2.1: icmp_seq=1 ttl=56 time=12.5 ms\n";
+        assert_eq!(12.5, parse_ping_output(output).unwrap());
+
+        // busybox
+        let output = "PING 192.0.2.1 (192.0.2.1): 56 data bytes\n64 bytes from 192.0.2.1: seq=0 ttl=56 time=8.000 ms\n";
+        assert_eq!(8.0, parse_ping_output(output).unwrap());
+
+        // no reply
+        let output = "PING 192.0.2.1 (192.0.2.1): 56 data bytes\n";
+        assert!(parse_ping_output(output).is_err());
+    }
+}
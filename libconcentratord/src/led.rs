@@ -0,0 +1,230 @@
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use log::{error, info};
+
+use super::{events, reset, state};
+
+// Duration of a single step of a blink pattern.
+const TICK: Duration = Duration::from_millis(100);
+
+// Number of steps of a blink pattern (2 seconds).
+const TICKS: u32 = 20;
+
+lazy_static! {
+    // GPS time reference state, None when no GPS is used.
+    static ref GPS_FIX: Mutex<Option<bool>> = Mutex::new(None);
+}
+
+#[derive(Clone)]
+pub struct Configuration {
+    // GPIO chip (or I2C GPIO expander, see reset::find_line) and line of the LED.
+    pub chip: String,
+    pub line: u32,
+    // Name of the LED under /sys/class/leds, this is used instead of the chip and line when
+    // set.
+    pub led: String,
+    // The LED is switched on by setting the line low.
+    pub active_low: bool,
+}
+
+/// Blink pattern of the status LED.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Pattern {
+    // Solid on, Concentratord is running.
+    Running,
+    // Single flash every 2 seconds, there are no clients connected to the event socket.
+    NoSubscriber,
+    // Double flash every 2 seconds, the GPS time reference is not valid.
+    NoGpsFix,
+    // Slow blink, the concentrator is being (re-)initialized.
+    Starting,
+    // Fast blink, Concentratord is running with reduced functionality.
+    Error,
+}
+
+impl Pattern {
+    fn is_on(&self, tick: u32) -> bool {
+        match self {
+            Pattern::Running => true,
+            Pattern::NoSubscriber => tick == 0,
+            Pattern::NoGpsFix => tick == 0 || tick == 2,
+            Pattern::Starting => tick % 10 < 5,
+            Pattern::Error => tick % 2 == 0,
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pattern::Running => write!(f, "RUNNING"),
+            Pattern::NoSubscriber => write!(f, "NO_SUBSCRIBER"),
+            Pattern::NoGpsFix => write!(f, "NO_GPS_FIX"),
+            Pattern::Starting => write!(f, "STARTING"),
+            Pattern::Error => write!(f, "ERROR"),
+        }
+    }
+}
+
+enum Output {
+    Line(reset::Line),
+    Sysfs {
+        brightness: PathBuf,
+        max_brightness: String,
+    },
+}
+
+impl Output {
+    fn open(conf: &Configuration) -> Result<Output> {
+        if conf.led.is_empty() {
+            return Ok(Output::Line(reset::Line::request(
+                &conf.chip,
+                conf.line,
+                "status_led",
+            )?));
+        }
+
+        // Disable the kernel trigger, such that the LED is only driven by Concentratord.
+        let dir = PathBuf::from("/sys/class/leds").join(&conf.led);
+        fs::write(dir.join("trigger"), "none")?;
+
+        Ok(Output::Sysfs {
+            brightness: dir.join("brightness"),
+            max_brightness: fs::read_to_string(dir.join("max_brightness"))?
+                .trim()
+                .to_string(),
+        })
+    }
+
+    fn set(&self, on: bool) -> Result<()> {
+        match self {
+            Output::Line(v) => v.set_value(on as u8)?,
+            Output::Sysfs {
+                brightness,
+                max_brightness,
+            } => fs::write(brightness, if on { max_brightness.as_str() } else { "0" })?,
+        }
+
+        Ok(())
+    }
+}
+
+/// Setup the status LED. Once setup, the LED indicates the Concentratord status using the
+/// blink patterns, see Pattern.
+pub fn setup(conf: &Configuration) -> Result<()> {
+    info!(
+        "Setting up status LED, chip: {}, line: {}, led: {}, active_low: {}",
+        conf.chip, conf.line, conf.led, conf.active_low
+    );
+
+    let output = Output::open(conf)?;
+    let active_low = conf.active_low;
+
+    thread::spawn(move || {
+        let mut pattern = get_pattern();
+        let mut tick: u32 = 0;
+        let mut failed = false;
+
+        loop {
+            // The pattern is only changed at the start of a cycle.
+            if tick == 0 {
+                let p = get_pattern();
+                if p != pattern {
+                    info!("Status LED pattern changed, pattern: {}", p);
+                    pattern = p;
+                }
+            }
+
+            match output.set(pattern.is_on(tick) != active_low) {
+                Ok(_) => failed = false,
+                Err(err) => {
+                    // Log this only once, as this is called every tick.
+                    if !failed {
+                        error!("Set status LED error, error: {}", err);
+                    }
+                    failed = true;
+                }
+            }
+
+            tick = (tick + 1) % TICKS;
+            thread::sleep(TICK);
+        }
+    });
+
+    Ok(())
+}
+
+/// Set the GPS time reference state, which is indicated by the NoGpsFix pattern.
+pub fn set_gps_fix(valid: bool) {
+    *GPS_FIX.lock().unwrap() = Some(valid);
+}
+
+fn get_pattern() -> Pattern {
+    pattern_for(
+        state::get(),
+        events::get_subscribers() > 0,
+        *GPS_FIX.lock().unwrap(),
+    )
+}
+
+fn pattern_for(state: state::State, subscribers: bool, gps_fix: Option<bool>) -> Pattern {
+    match state {
+        state::State::Initializing | state::State::Calibrating | state::State::Resetting => {
+            Pattern::Starting
+        }
+        state::State::Degraded if gps_fix == Some(false) => Pattern::NoGpsFix,
+        state::State::Degraded => Pattern::Error,
+        state::State::Running if !subscribers => Pattern::NoSubscriber,
+        state::State::Running if gps_fix == Some(false) => Pattern::NoGpsFix,
+        state::State::Running => Pattern::Running,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern_for() {
+        assert_eq!(
+            Pattern::Starting,
+            pattern_for(state::State::Calibrating, true, None)
+        );
+        assert_eq!(
+            Pattern::Running,
+            pattern_for(state::State::Running, true, None)
+        );
+        assert_eq!(
+            Pattern::Running,
+            pattern_for(state::State::Running, true, Some(true))
+        );
+        assert_eq!(
+            Pattern::NoSubscriber,
+            pattern_for(state::State::Running, false, Some(false))
+        );
+        assert_eq!(
+            Pattern::NoGpsFix,
+            pattern_for(state::State::Degraded, true, Some(false))
+        );
+        assert_eq!(
+            Pattern::Error,
+            pattern_for(state::State::Degraded, true, Some(true))
+        );
+    }
+
+    #[test]
+    fn test_pattern_is_on() {
+        let on = |p: Pattern| (0..TICKS).filter(|t| p.is_on(*t)).count();
+        assert_eq!(20, on(Pattern::Running));
+        assert_eq!(1, on(Pattern::NoSubscriber));
+        assert_eq!(2, on(Pattern::NoGpsFix));
+        assert_eq!(10, on(Pattern::Starting));
+        assert_eq!(10, on(Pattern::Error));
+    }
+}
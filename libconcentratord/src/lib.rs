@@ -3,11 +3,51 @@ extern crate lazy_static;
 #[macro_use]
 extern crate anyhow;
 
+pub mod airtime;
+pub mod api;
+pub mod audit;
+pub mod blacklist;
+pub mod capture;
 pub mod commands;
+pub mod curve;
+pub mod diag;
+pub mod drift;
+pub mod dutycycle;
 pub mod events;
+pub mod feedback;
+pub mod forwarder;
+pub mod gnss;
 pub mod gpsd;
+pub mod health;
+pub mod history;
+mod i2cgpio;
 pub mod jitqueue;
+pub mod jitsim;
+pub mod joinflood;
+pub mod latency;
+pub mod led;
+pub mod logging;
+pub mod metadata;
+pub mod metered;
+pub mod metrics;
+pub mod nmea;
+pub mod oversize;
+pub mod pps;
+pub mod provisioning;
+pub mod region;
+pub mod remoteconfig;
 pub mod reset;
+mod rotate;
+pub mod shadow;
 pub mod signals;
+pub mod soaktest;
 mod socket;
+pub mod state;
 pub mod stats;
+pub mod storage;
+pub mod systemd;
+pub mod timesource;
+pub mod timesync;
+pub mod txpower;
+pub mod txtest;
+pub mod uplinkfilter;
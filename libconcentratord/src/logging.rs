@@ -0,0 +1,396 @@
+use std::cmp;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use chrono::{SecondsFormat, Utc};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde_json::{Map, Value};
+
+use super::api;
+use super::diag;
+use super::events;
+use super::rotate::RotatingFile;
+use super::state;
+
+lazy_static! {
+    static ref LEVEL: Mutex<LevelFilter> = Mutex::new(LevelFilter::Info);
+    static ref EVENT_LEVEL: Mutex<LevelFilter> = Mutex::new(LevelFilter::Off);
+    static ref ERRORS: Mutex<VecDeque<api::StatusError>> = Mutex::new(VecDeque::new());
+}
+
+// Number of errors that are kept for the status command.
+const ERROR_HISTORY: usize = 5;
+
+/// Log output.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Output {
+    Stdout,
+    Syslog,
+    // Rotated log file, see LineLogger::file.
+    File,
+}
+
+impl fmt::Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Output::Stdout => write!(f, "STDOUT"),
+            Output::Syslog => write!(f, "SYSLOG"),
+            Output::File => write!(f, "FILE"),
+        }
+    }
+}
+
+impl FromStr for Output {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "STDOUT" => Output::Stdout,
+            "SYSLOG" => Output::Syslog,
+            "FILE" => Output::File,
+            _ => return Err(anyhow!("unexpected log output: {}", s)),
+        })
+    }
+}
+
+/// Log format.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    // Plain text: time, level, module and message.
+    Text,
+    // One JSON object per log record, see format_json.
+    Json,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Format::Text => write!(f, "TEXT"),
+            Format::Json => write!(f, "JSON"),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "" | "TEXT" => Format::Text,
+            "JSON" => Format::Json,
+            _ => return Err(anyhow!("unexpected log format: {}", s)),
+        })
+    }
+}
+
+/// Logger writing a line per log record to stdout or to a rotated file. The records are not
+/// filtered, this is done by the logger setup by init.
+pub struct LineLogger {
+    format: Format,
+    // When None, the lines are written to stdout.
+    file: Option<Mutex<RotatingFile>>,
+}
+
+impl LineLogger {
+    pub fn stdout(format: Format) -> LineLogger {
+        LineLogger { format, file: None }
+    }
+
+    pub fn file(path: &str, max_size: u64, max_files: usize, format: Format) -> Result<LineLogger> {
+        Ok(LineLogger {
+            format,
+            file: Some(Mutex::new(RotatingFile::open(path, max_size, max_files)?)),
+        })
+    }
+}
+
+impl Log for LineLogger {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let line = match self.format {
+            Format::Text => format_text(record),
+            Format::Json => format_json(record),
+        } + "\n";
+
+        // The errors can not be logged, as this would call this logger again.
+        match &self.file {
+            Some(file) => {
+                if let Err(err) = file.lock().unwrap().write(line.as_bytes()) {
+                    eprintln!("Write log file error, error: {}", err);
+                }
+            }
+            None => {
+                let _ = io::stdout().lock().write_all(line.as_bytes());
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if self.file.is_none() {
+            let _ = io::stdout().flush();
+        }
+    }
+}
+
+/// Logger wrapping e.g. the syslog logger, which replaces the message of each log record by
+/// its JSON representation (see format_json).
+pub struct JsonLogger {
+    inner: Box<dyn Log>,
+}
+
+impl JsonLogger {
+    pub fn new(inner: Box<dyn Log>) -> JsonLogger {
+        JsonLogger { inner }
+    }
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        let line = format_json(record);
+        self.inner.log(
+            &Record::builder()
+                .metadata(record.metadata().clone())
+                .args(format_args!("{}", line))
+                .module_path(record.module_path())
+                .file(record.file())
+                .line(record.line())
+                .build(),
+        );
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+// Logger wrapping the stdout or syslog logger, which additionally publishes the log records
+// up to the event level as log events.
+struct EventLogger {
+    inner: Box<dyn Log>,
+}
+
+impl Log for EventLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if record.level() <= *LEVEL.lock().unwrap() {
+            self.inner.log(record);
+        }
+
+        if record.level() == Level::Error {
+            add_error(record);
+        }
+
+        diag::capture_log(record);
+
+        if record.level() <= *EVENT_LEVEL.lock().unwrap() {
+            // Before the event socket is bound, this returns an error and the record is only
+            // written to the wrapped logger.
+            let _ = events::send_log(&api::LogEvent {
+                gateway_id: hex::encode(state::get_gateway_id()),
+                level: record.level().to_string(),
+                target: record.target().to_string(),
+                message: record.args().to_string(),
+            });
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Setup the global logger. The log records up to level are written to the given (stdout or
+/// syslog) logger, the log records up to event_level are published as log events.
+pub fn init(inner: Box<dyn Log>, level: LevelFilter, event_level: LevelFilter) -> Result<()> {
+    // This is called on startup, so this is used as the start of the uptime.
+    state::uptime();
+
+    *EVENT_LEVEL.lock().unwrap() = event_level;
+    set_level(level);
+    log::set_boxed_logger(Box::new(EventLogger { inner }))?;
+
+    Ok(())
+}
+
+/// Set the level of the log records that are written to the wrapped logger. This does not
+/// change the log event level.
+pub fn set_level(level: LevelFilter) {
+    *LEVEL.lock().unwrap() = level;
+    log::set_max_level(cmp::max(level, *EVENT_LEVEL.lock().unwrap()));
+}
+
+/// Formats the log record as plain text line (without newline).
+pub fn format_text(record: &Record) -> String {
+    format!(
+        "{} {:<5} [{}] {}",
+        Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        record.level(),
+        record.target(),
+        record.args()
+    )
+}
+
+/// Formats the log record as JSON object (without newline). Besides the time, level, module
+/// (target), gateway_id and message, this contains the fields of the message. By convention,
+/// the log messages are formatted as "Message, key: value, key: value", these key / value
+/// pairs are included as (string) fields, e.g. the frequency and dev_addr of a frame.
+pub fn format_json(record: &Record) -> String {
+    let message = record.args().to_string();
+    let (message, fields) = parse_fields(&message);
+    let gateway_id = state::get_gateway_id();
+
+    let mut obj = Map::new();
+    obj.insert(
+        "time".to_string(),
+        Value::String(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)),
+    );
+    obj.insert(
+        "level".to_string(),
+        Value::String(record.level().to_string()),
+    );
+    obj.insert(
+        "module".to_string(),
+        Value::String(record.target().to_string()),
+    );
+    if !gateway_id.is_empty() {
+        obj.insert(
+            "gateway_id".to_string(),
+            Value::String(hex::encode(gateway_id)),
+        );
+    }
+    obj.insert("message".to_string(), Value::String(message.to_string()));
+    if !fields.is_empty() {
+        obj.insert("fields".to_string(), Value::Object(fields));
+    }
+
+    Value::Object(obj).to_string()
+}
+
+// Splits the "Message, key: value, key: value" formatted log message into the message and the
+// fields. A value containing ", " (e.g. an error) is kept as a single value, as long as the
+// part after it is not formatted as "key: ". When the message does not contain fields, the
+// complete message is returned.
+fn parse_fields(message: &str) -> (&str, Map<String, Value>) {
+    let mut fields: Vec<(&str, String)> = Vec::new();
+    let mut parts = message.split(", ");
+    let head = parts.next().unwrap_or_default();
+
+    for part in parts {
+        match split_field(part) {
+            Some((k, v)) => fields.push((k, v.to_string())),
+            None => match fields.last_mut() {
+                Some((_, v)) => {
+                    v.push_str(", ");
+                    v.push_str(part);
+                }
+                None => return (message, Map::new()),
+            },
+        }
+    }
+
+    if fields.is_empty() {
+        return (message, Map::new());
+    }
+
+    (
+        head,
+        fields
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), Value::String(v)))
+            .collect(),
+    )
+}
+
+fn split_field(part: &str) -> Option<(&str, &str)> {
+    let (key, value) = part.split_once(": ")?;
+    let valid = key.starts_with(|c: char| c.is_ascii_lowercase())
+        && key
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+
+    if valid {
+        Some((key, value))
+    } else {
+        None
+    }
+}
+
+/// Returns the level of the log records that are written to the wrapped logger.
+pub fn get_level() -> LevelFilter {
+    *LEVEL.lock().unwrap()
+}
+
+/// Returns the last logged errors, oldest first.
+pub fn get_errors() -> Vec<api::StatusError> {
+    ERRORS.lock().unwrap().iter().cloned().collect()
+}
+
+fn add_error(record: &Record) {
+    // The system clock is used instead of the time source, as the GPS time source locks the
+    // GPS time reference, which might be locked by the caller.
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    let mut errors = ERRORS.lock().unwrap();
+    if errors.len() == ERROR_HISTORY {
+        errors.pop_front();
+    }
+    errors.push_back(api::StatusError {
+        time: Some(pbjson_types::Timestamp {
+            seconds: now.as_secs() as i64,
+            nanos: now.subsec_nanos() as i32,
+        }),
+        target: record.target().to_string(),
+        message: record.args().to_string(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_fields() {
+        let (message, fields) = parse_fields("Concentrator started");
+        assert_eq!("Concentrator started", message);
+        assert!(fields.is_empty());
+
+        let (message, fields) =
+            parse_fields("Frame received, freq: 868100000, snr: 9.5, dev_addr: 26011234");
+        assert_eq!("Frame received", message);
+        assert_eq!(Value::from("868100000"), fields["freq"]);
+        assert_eq!(Value::from("9.5"), fields["snr"]);
+        assert_eq!(Value::from("26011234"), fields["dev_addr"]);
+
+        // values containing ", "
+        let (message, fields) =
+            parse_fields("Read command error, error: invalid wire type, expected 2, command: down");
+        assert_eq!("Read command error", message);
+        assert_eq!(
+            Value::from("invalid wire type, expected 2"),
+            fields["error"]
+        );
+        assert_eq!(Value::from("down"), fields["command"]);
+
+        // not formatted as fields
+        let (message, fields) = parse_fields("Hello, world");
+        assert_eq!("Hello, world", message);
+        assert!(fields.is_empty());
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use log::{debug, error};
+
+use super::metered;
+
+// Interval for polling if the command has exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+lazy_static! {
+    static ref CONFIGURATION: Mutex<Option<Configuration>> = Mutex::new(None);
+}
+
+#[derive(Clone)]
+pub struct Configuration {
+    // Commands (name => argv) of which the output is merged into the stats meta-data.
+    pub commands: HashMap<String, Vec<String>>,
+    // Max. time to wait for a command to complete, before it is killed.
+    pub timeout: Duration,
+}
+
+/// Setup the meta-data commands. These are executed by the stats on every stats interval.
+pub fn setup(conf: &Configuration) {
+    let mut configuration = CONFIGURATION.lock().unwrap();
+    *configuration = if conf.commands.is_empty() {
+        None
+    } else {
+        Some(conf.clone())
+    };
+}
+
+/// Execute the configured commands and return the merged meta-data. The keys are prefixed by
+/// the command name. Failing commands are logged and skipped. Nothing is returned when the
+/// backhaul is metered.
+pub fn collect() -> HashMap<String, String> {
+    let conf = match CONFIGURATION.lock().unwrap().clone() {
+        Some(v) => v,
+        None => return HashMap::new(),
+    };
+
+    // The command output is optional and suppressed on a metered backhaul.
+    if metered::is_metered() {
+        return HashMap::new();
+    }
+
+    let mut out = HashMap::new();
+
+    for (name, argv) in &conf.commands {
+        match execute(argv, conf.timeout) {
+            Ok(v) => {
+                debug!("Executed stats meta-data command, name: {}", name);
+                out.extend(parse_output(name, &v));
+            }
+            Err(err) => {
+                error!(
+                    "Execute stats meta-data command error, name: {}, error: {}",
+                    name, err
+                );
+            }
+        }
+    }
+
+    out
+}
+
+fn execute(argv: &[String], timeout: Duration) -> Result<String> {
+    if argv.is_empty() {
+        return Err(anyhow!("command is empty"));
+    }
+
+    let mut child = Command::new(&argv[0])
+        .args(&argv[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    // The output is read by a separate thread, so that a command with a large output does not
+    // block on a full pipe.
+    let mut stdout = child.stdout.take().unwrap();
+    let reader = thread::spawn(move || -> Result<String> {
+        let mut b = String::new();
+        stdout.read_to_string(&mut b)?;
+        Ok(b)
+    });
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(v) = child.try_wait()? {
+            break v;
+        }
+
+        if Instant::now() > deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(anyhow!("timeout after {:?}", timeout));
+        }
+
+        sleep(POLL_INTERVAL);
+    };
+
+    if !status.success() {
+        return Err(anyhow!("command exited with {}", status));
+    }
+
+    reader
+        .join()
+        .map_err(|_| anyhow!("read command output error"))?
+}
+
+// Parses the command output, which is either a JSON object or key=value lines. JSON values that
+// are not a string are added in their JSON representation.
+fn parse_output(name: &str, output: &str) -> HashMap<String, String> {
+    let mut out = HashMap::new();
+
+    if let Ok(serde_json::Value::Object(obj)) = serde_json::from_str(output) {
+        for (k, v) in obj {
+            let v = match v {
+                serde_json::Value::String(v) => v,
+                _ => v.to_string(),
+            };
+            out.insert(format!("{}_{}", name, k), v);
+        }
+
+        return out;
+    }
+
+    for line in output.lines() {
+        if let Some((k, v)) = line.split_once('=') {
+            let k = k.trim();
+            if !k.is_empty() {
+                out.insert(format!("{}_{}", name, k), v.trim().to_string());
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_output() {
+        let tests = vec![
+            (
+                "rssi=-71\ntech = lte\n\ninvalid\n",
+                vec![("modem_rssi", "-71"), ("modem_tech", "lte")],
+            ),
+            (
+                r#"{"rssi": -71, "tech": "lte", "roaming": false}"#,
+                vec![
+                    ("modem_rssi", "-71"),
+                    ("modem_tech", "lte"),
+                    ("modem_roaming", "false"),
+                ],
+            ),
+            ("", vec![]),
+        ];
+
+        for (output, expected) in tests {
+            let expected: HashMap<String, String> = expected
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            assert_eq!(expected, parse_output("modem", output));
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::fs;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+use anyhow::Result;
+use log::{debug, error, info};
+
+use super::signals::Signal;
+use super::stats;
+
+static METERED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone)]
+pub struct Configuration {
+    // File containing the metered flag (1, true or yes). When empty, this is not used.
+    pub file: String,
+    // Read the metered state from NetworkManager.
+    pub network_manager: bool,
+    // Interval between two checks.
+    pub interval: Duration,
+}
+
+/// Returns true when the backhaul link is metered. In this case, the optional events (e.g. the
+/// log events and the stats meta-data commands) are suppressed. Uplinks are always forwarded.
+pub fn is_metered() -> bool {
+    METERED.load(Ordering::Relaxed)
+}
+
+/// Periodically checks if the backhaul link is metered.
+pub fn monitor_loop(conf: &Configuration, stop_receive: Receiver<Signal>) {
+    info!(
+        "Starting metered backhaul monitor loop, file: {}, network_manager: {}, interval: {:?}",
+        conf.file, conf.network_manager, conf.interval
+    );
+
+    loop {
+        match get_metered(conf) {
+            Ok(v) => {
+                if METERED.swap(v, Ordering::Relaxed) != v {
+                    info!("Metered backhaul state changed, metered: {}", v);
+                }
+                stats::set_metadata("backhaul_metered", &v.to_string());
+            }
+            Err(err) => {
+                error!("Get metered backhaul state error, error: {}", err);
+            }
+        }
+
+        // Instead of an 'interval' sleep, we receive from the stop channel with a timeout
+        // equal to the 'interval'.
+        if let Ok(v) = stop_receive.recv_timeout(conf.interval) {
+            debug!("Received stop signal, signal: {}", v);
+            break;
+        }
+    }
+
+    debug!("Metered backhaul monitor loop ended");
+}
+
+fn get_metered(conf: &Configuration) -> Result<bool> {
+    if !conf.file.is_empty() {
+        // A missing file means not metered, e.g. when the file is removed by the connection
+        // manager once the link switches to ethernet.
+        match fs::read_to_string(&conf.file) {
+            Ok(v) => {
+                if parse_flag(&v) {
+                    return Ok(true);
+                }
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    if conf.network_manager {
+        let out = Command::new("busctl")
+            .args([
+                "get-property",
+                "org.freedesktop.NetworkManager",
+                "/org/freedesktop/NetworkManager",
+                "org.freedesktop.NetworkManager",
+                "Metered",
+            ])
+            .output()?;
+        if !out.status.success() {
+            return Err(anyhow!(
+                "busctl error: {}",
+                String::from_utf8_lossy(&out.stderr).trim()
+            ));
+        }
+
+        return parse_network_manager(&String::from_utf8_lossy(&out.stdout));
+    }
+
+    Ok(false)
+}
+
+fn parse_flag(s: &str) -> bool {
+    matches!(s.trim().to_lowercase().as_ref(), "1" | "true" | "yes")
+}
+
+// Parses the NetworkManager Metered property (e.g. "u 1"). The values are defined by
+// NMMetered: 0 = unknown, 1 = yes, 2 = no, 3 = guess yes, 4 = guess no.
+fn parse_network_manager(s: &str) -> Result<bool> {
+    let v: u32 = s
+        .trim()
+        .strip_prefix("u ")
+        .ok_or_else(|| anyhow!("unexpected Metered property: {}", s.trim()))?
+        .parse()?;
+
+    Ok(v == 1 || v == 3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_flag() {
+        assert!(parse_flag("1\n"));
+        assert!(parse_flag("True"));
+        assert!(parse_flag(" yes "));
+        assert!(!parse_flag("0"));
+        assert!(!parse_flag(""));
+    }
+
+    #[test]
+    fn test_parse_network_manager() {
+        assert!(parse_network_manager("u 1\n").unwrap());
+        assert!(parse_network_manager("u 3").unwrap());
+        assert!(!parse_network_manager("u 2").unwrap());
+        assert!(!parse_network_manager("u 0").unwrap());
+        assert!(parse_network_manager("s \"yes\"").is_err());
+    }
+}
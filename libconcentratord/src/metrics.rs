@@ -0,0 +1,225 @@
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use log::{debug, error, info};
+
+// Prefix of the exposed metric names.
+const PREFIX: &str = "concentratord_";
+
+// Read and write timeout of a scrape request, such that a stalled client does not block the
+// listener.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Name, type and help of the exposed metrics. Only the metrics for which a value has been
+// recorded are exposed.
+const METRICS: [(&str, &str, &str); 7] = [
+    (
+        "rx_packets_total",
+        "counter",
+        "Number of received frames, per channel and CRC status.",
+    ),
+    (
+        "tx_packets_total",
+        "counter",
+        "Number of downlink items, per enqueue status.",
+    ),
+    (
+        "tx_emitted_total",
+        "counter",
+        "Number of downlinks handed over to the concentrator.",
+    ),
+    (
+        "jit_queue_depth",
+        "gauge",
+        "Number of downlinks in the JIT queue.",
+    ),
+    (
+        "concentrator_temperature_celsius",
+        "gauge",
+        "Concentrator temperature.",
+    ),
+    (
+        "gps_fix",
+        "gauge",
+        "1 when the GPS time reference is valid, 0 otherwise.",
+    ),
+    (
+        "event_subscribers",
+        "gauge",
+        "Number of clients connected to the event socket.",
+    ),
+];
+
+lazy_static! {
+    // Metric name -> formatted labels -> value.
+    static ref VALUES: Mutex<BTreeMap<String, BTreeMap<String, f64>>> =
+        Mutex::new(BTreeMap::new());
+}
+
+/// Setup the Prometheus metrics listener. Once setup, the metrics are served on the /metrics
+/// path of the given bind (e.g. 0.0.0.0:9100).
+pub fn setup(bind: &str) -> Result<()> {
+    info!("Setting up metrics listener, bind: {}", bind);
+
+    let listener = TcpListener::bind(bind)?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(v) => {
+                    if let Err(err) = handle_request(v) {
+                        debug!("Handle metrics request error, error: {}", err);
+                    }
+                }
+                Err(err) => error!("Accept metrics connection error, error: {}", err),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Increment a counter. Unlike the stats counters, these are never reset.
+pub fn inc_counter(name: &str, labels: &[(&str, &str)]) {
+    let mut values = VALUES.lock().unwrap();
+    *values
+        .entry(name.to_string())
+        .or_insert_with(BTreeMap::new)
+        .entry(format_labels(labels))
+        .or_insert(0.0) += 1.0;
+}
+
+/// Set a gauge to the given value.
+pub fn set_gauge(name: &str, labels: &[(&str, &str)], value: f64) {
+    let mut values = VALUES.lock().unwrap();
+    values
+        .entry(name.to_string())
+        .or_insert_with(BTreeMap::new)
+        .insert(format_labels(labels), value);
+}
+
+/// Remove a gauge, e.g. when its value could not be read. Removed gauges are not exposed.
+pub fn remove_gauge(name: &str, labels: &[(&str, &str)]) {
+    let mut values = VALUES.lock().unwrap();
+    if let Some(v) = values.get_mut(name) {
+        v.remove(&format_labels(labels));
+    }
+}
+
+fn handle_request(stream: TcpStream) -> Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // the request headers are not used
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", render(&VALUES.lock().unwrap())),
+        (Some("GET"), _) => ("404 Not Found", "Not Found\n".to_string()),
+        _ => ("405 Method Not Allowed", "Method Not Allowed\n".to_string()),
+    };
+
+    write!(
+        &stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+
+    Ok(())
+}
+
+// Renders the metrics using the Prometheus text exposition format.
+fn render(values: &BTreeMap<String, BTreeMap<String, f64>>) -> String {
+    let mut out = String::new();
+
+    for (name, kind, help) in METRICS.iter() {
+        let samples = match values.get(*name) {
+            Some(v) if !v.is_empty() => v,
+            _ => continue,
+        };
+
+        out.push_str(&format!("# HELP {}{} {}\n", PREFIX, name, help));
+        out.push_str(&format!("# TYPE {}{} {}\n", PREFIX, name, kind));
+        for (labels, value) in samples {
+            out.push_str(&format!("{}{}{} {}\n", PREFIX, name, labels, value));
+        }
+    }
+
+    out
+}
+
+fn format_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return "".to_string();
+    }
+
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| {
+            format!(
+                "{}=\"{}\"",
+                k,
+                v.replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('\n', "\\n")
+            )
+        })
+        .collect();
+
+    format!("{{{}}}", labels.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_labels() {
+        assert_eq!("", format_labels(&[]));
+        assert_eq!(
+            "{channel=\"0\",crc=\"CRCOk\"}",
+            format_labels(&[("channel", "0"), ("crc", "CRCOk")])
+        );
+        assert_eq!("{status=\"a\\\"b\"}", format_labels(&[("status", "a\"b")]));
+    }
+
+    #[test]
+    fn test_render() {
+        let mut values: BTreeMap<String, BTreeMap<String, f64>> = BTreeMap::new();
+        values
+            .entry("rx_packets_total".to_string())
+            .or_default()
+            .insert(format_labels(&[("channel", "1"), ("crc", "CRCOk")]), 3.0);
+        values
+            .entry("concentrator_temperature_celsius".to_string())
+            .or_default()
+            .insert(format_labels(&[]), 34.5);
+        values.entry("gps_fix".to_string()).or_default().clear();
+
+        assert_eq!(
+            "# HELP concentratord_rx_packets_total Number of received frames, per channel and CRC status.\n\
+            # TYPE concentratord_rx_packets_total counter\n\
+            concentratord_rx_packets_total{channel=\"1\",crc=\"CRCOk\"} 3\n\
+            # HELP concentratord_concentrator_temperature_celsius Concentrator temperature.\n\
+            # TYPE concentratord_concentrator_temperature_celsius gauge\n\
+            concentratord_concentrator_temperature_celsius 34.5\n",
+            render(&values)
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use log::{debug, error, info};
+
+lazy_static! {
+    static ref SERVER: Mutex<Option<Server>> = Mutex::new(None);
+}
+
+struct Server {
+    listener: UnixListener,
+    clients: Vec<UnixStream>,
+}
+
+/// Setup the NMEA passthrough socket. Once setup, every NMEA sentence read from the GPS is
+/// re-served to the clients connected to the Unix socket.
+pub fn setup(path: &str) -> Result<()> {
+    info!("Setting up NMEA passthrough socket, path: {}", path);
+
+    // remove the socket file of a previous run
+    if Path::new(path).exists() {
+        fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+    listener.set_nonblocking(true)?;
+
+    let mut server = SERVER.lock().unwrap();
+    *server = Some(Server {
+        listener,
+        clients: Vec::new(),
+    });
+
+    Ok(())
+}
+
+/// Send the NMEA sentence to the connected clients. Clients that are not able to keep up are
+/// disconnected, so that a slow client never blocks the GPS loop. This is a no-op when the
+/// socket has not been setup.
+pub fn send(sentence: &[u8]) {
+    let mut server = SERVER.lock().unwrap();
+    let server = match server.as_mut() {
+        Some(v) => v,
+        None => return,
+    };
+
+    // accept the pending connections
+    loop {
+        match server.listener.accept() {
+            Ok((stream, _)) => {
+                if let Err(err) = stream.set_nonblocking(true) {
+                    error!("Set NMEA client non-blocking error, error: {}", err);
+                    continue;
+                }
+                debug!("NMEA passthrough client connected");
+                server.clients.push(stream);
+            }
+            Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+            Err(err) => {
+                error!("Accept NMEA client error, error: {}", err);
+                break;
+            }
+        }
+    }
+
+    server
+        .clients
+        .retain_mut(|client| match client.write_all(sentence) {
+            Ok(_) => true,
+            Err(err) => {
+                debug!("NMEA passthrough client disconnected, error: {}", err);
+                false
+            }
+        });
+}
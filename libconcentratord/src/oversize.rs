@@ -0,0 +1,188 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use chirpstack_api::gw;
+use log::warn;
+
+use super::{region, stats};
+
+// LoRaWAN max. PHYPayload size, used when the max. size of the region is not known.
+const MAX_PHY_PAYLOAD_SIZE: usize = 255;
+
+lazy_static! {
+    static ref CONFIG: Mutex<Configuration> = Mutex::new(Default::default());
+}
+
+/// Handling of uplinks which exceed the max. PHYPayload size, e.g. interference artifacts
+/// reported by the HAL.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    // The uplink is forwarded as-is.
+    Forward,
+    // The PHYPayload is truncated to the max. size and the uplink is flagged.
+    Truncate,
+    // The uplink is dropped.
+    Drop,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Action::Forward => write!(f, "FORWARD"),
+            Action::Truncate => write!(f, "TRUNCATE"),
+            Action::Drop => write!(f, "DROP"),
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "" | "FORWARD" => Action::Forward,
+            "TRUNCATE" => Action::Truncate,
+            "DROP" => Action::Drop,
+            _ => return Err(anyhow!("unexpected oversized uplink action: {}", s)),
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct Configuration {
+    pub action: Action,
+    // Region of which the max. PHYPayload size per data-rate is used. When None, the
+    // LoRaWAN max. PHYPayload size is used.
+    pub region: Option<region::Region>,
+}
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Configuration {
+            action: Action::Forward,
+            region: None,
+        }
+    }
+}
+
+/// Outcome of the oversized uplink handling.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Outcome {
+    Forward,
+    Drop,
+    // The uplink is forwarded with a PHYPayload truncated to the given size.
+    Truncate(usize),
+}
+
+/// Set the oversized uplink handling.
+pub fn setup(conf: Configuration) {
+    *CONFIG.lock().unwrap() = conf;
+}
+
+/// Applies the oversized uplink handling to the given uplink. Oversized uplinks are counted
+/// using the rx_oversized stats counter, the truncated and dropped uplinks using the
+/// rx_oversized_truncated and rx_oversized_dropped counters.
+pub fn apply(pl: &gw::UplinkFrame) -> Outcome {
+    let conf = CONFIG.lock().unwrap();
+
+    let max_size = get_max_size(conf.region, pl);
+    let size = pl.phy_payload.len();
+    if size <= max_size {
+        return Outcome::Forward;
+    }
+
+    warn!(
+        "Uplink exceeds max. PHYPayload size, uplink_id: {}, size: {}, max_size: {}, action: {}",
+        pl.rx_info.as_ref().map(|v| v.uplink_id).unwrap_or_default(),
+        size,
+        max_size,
+        conf.action
+    );
+    stats::inc_counter("rx_oversized");
+
+    match conf.action {
+        Action::Forward => Outcome::Forward,
+        Action::Truncate => {
+            stats::inc_counter("rx_oversized_truncated");
+            Outcome::Truncate(max_size)
+        }
+        Action::Drop => {
+            stats::inc_counter("rx_oversized_dropped");
+            Outcome::Drop
+        }
+    }
+}
+
+/// Returns a copy of the uplink with the PHYPayload truncated to the given size. The uplink is
+/// flagged with the original size (phy_payload_truncated meta-data).
+pub fn truncate(pl: &gw::UplinkFrame, size: usize) -> gw::UplinkFrame {
+    let mut pl = pl.clone();
+    let original_size = pl.phy_payload.len();
+    pl.phy_payload.truncate(size);
+    if let Some(rx_info) = pl.rx_info.as_mut() {
+        rx_info.metadata.insert(
+            "phy_payload_truncated".to_string(),
+            original_size.to_string(),
+        );
+    }
+    pl
+}
+
+// Returns the max. PHYPayload size for the modulation of the uplink.
+fn get_max_size(region: Option<region::Region>, pl: &gw::UplinkFrame) -> usize {
+    let params = pl
+        .tx_info
+        .as_ref()
+        .and_then(|v| v.modulation.as_ref())
+        .and_then(|v| v.parameters.as_ref());
+
+    match (region, params) {
+        (Some(region), Some(params)) => region
+            .get_max_phy_payload_size(params)
+            .unwrap_or(MAX_PHY_PAYLOAD_SIZE),
+        _ => MAX_PHY_PAYLOAD_SIZE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uplink(spreading_factor: u32, size: usize) -> gw::UplinkFrame {
+        gw::UplinkFrame {
+            phy_payload: vec![0; size],
+            tx_info: Some(gw::UplinkTxInfo {
+                modulation: Some(gw::Modulation {
+                    parameters: Some(gw::modulation::Parameters::Lora(gw::LoraModulationInfo {
+                        bandwidth: 125000,
+                        spreading_factor,
+                        ..Default::default()
+                    })),
+                }),
+                ..Default::default()
+            }),
+            rx_info: Some(Default::default()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_get_max_size() {
+        let region = Some(region::Region::EU868);
+        assert_eq!(64, get_max_size(region, &uplink(12, 10)));
+        assert_eq!(255, get_max_size(region, &uplink(7, 10)));
+        assert_eq!(255, get_max_size(None, &uplink(12, 10)));
+    }
+
+    #[test]
+    fn test_truncate() {
+        let pl = truncate(&uplink(12, 100), 64);
+        assert_eq!(64, pl.phy_payload.len());
+        assert_eq!(
+            Some(&"100".to_string()),
+            pl.rx_info.unwrap().metadata.get("phy_payload_truncated")
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+// Max. clock error (ppm) between two PPS latched counter values.
+const MAX_DRIFT_PPM: f64 = 50.0;
+
+// Max. jitter (us) of the PPS latched counter value.
+const MAX_JITTER_US: f64 = 20.0;
+
+// Above this interval between two references, the latched counter value can not be validated
+// as it might have rolled over.
+const MAX_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Number of consecutive implausible latched counter values after which the new value is
+/// accepted anyway. This happens for example when the concentrator has been reset.
+pub const MAX_CONSECUTIVE_ERRORS: usize = 3;
+
+/// Returns true when the PPS latched counter value is plausible, compared to the previous
+/// time reference. A glitch on the PPS signal results in a counter value that does not match
+/// the elapsed GPS time.
+pub fn is_plausible(
+    prev_count_us: u32,
+    prev_gps_epoch: Duration,
+    count_us: u32,
+    gps_epoch: Duration,
+) -> bool {
+    let epoch_diff = match gps_epoch.checked_sub(prev_gps_epoch) {
+        Some(v) => v,
+        None => return false,
+    };
+
+    if epoch_diff > MAX_INTERVAL {
+        return true;
+    }
+
+    let expected = epoch_diff.as_micros() as f64;
+    let count_diff = count_us.wrapping_sub(prev_count_us) as f64;
+
+    (count_diff - expected).abs() <= expected * MAX_DRIFT_PPM / 1_000_000.0 + MAX_JITTER_US
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_plausible() {
+        let tests = vec![
+            ("one second", 1000, 1_000_000, 1, true),
+            ("drift", 1000, 1_000_030, 1, true),
+            ("ten seconds", 1000, 10_000_000, 10, true),
+            ("rollover", u32::MAX - 10, 1_000_000, 1, true),
+            ("glitch", 1000, 1_500_000, 1, false),
+            ("missed pps", 1000, 1_000_000, 2, false),
+        ];
+
+        for (name, prev_count_us, count_diff, epoch_diff, expected) in tests {
+            let prev_gps_epoch = Duration::from_secs(1000);
+
+            assert_eq!(
+                expected,
+                is_plausible(
+                    prev_count_us,
+                    prev_gps_epoch,
+                    prev_count_us.wrapping_add(count_diff),
+                    prev_gps_epoch + Duration::from_secs(epoch_diff)
+                ),
+                "{}",
+                name
+            );
+        }
+
+        // gps time going backwards
+        assert!(!is_plausible(
+            1000,
+            Duration::from_secs(1000),
+            1_001_000,
+            Duration::from_secs(999)
+        ));
+    }
+}
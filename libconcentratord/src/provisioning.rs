@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::fs;
+
+use anyhow::Result;
+use log::info;
+
+#[derive(Clone, Default)]
+pub struct Configuration {
+    // Expected gateway ID (hex encoded).
+    pub gateway_id: String,
+    // Provisioning mapping file, mapping the hostname to the expected gateway ID. This is used
+    // when the gateway_id is not set.
+    pub mapping_file: String,
+}
+
+/// Returns the gateway ID which is provisioned for this gateway. None when neither the
+/// gateway ID nor the mapping file is configured.
+pub fn get_expected_gateway_id(conf: &Configuration) -> Result<Option<Vec<u8>>> {
+    if !conf.gateway_id.is_empty() {
+        return Ok(Some(decode_gateway_id(&conf.gateway_id)?));
+    }
+
+    if conf.mapping_file.is_empty() {
+        return Ok(None);
+    }
+
+    let hostname = get_hostname()?;
+    let mapping = parse_mapping(&fs::read_to_string(&conf.mapping_file)?)?;
+    match mapping.get(&hostname) {
+        Some(v) => Ok(Some(v.clone())),
+        None => Err(anyhow!(
+            "hostname not found in provisioning mapping file, hostname: {}, mapping_file: {}",
+            hostname,
+            conf.mapping_file
+        )),
+    }
+}
+
+/// Verifies that the concentrator EUI matches the provisioned gateway ID. This catches e.g.
+/// concentrator cards which have been swapped between gateways.
+pub fn verify(conf: &Configuration, eui: &[u8]) -> Result<()> {
+    let expected = match get_expected_gateway_id(conf)? {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+
+    if expected != eui {
+        return Err(anyhow!(
+            "concentrator EUI does not match the provisioned gateway ID, concentrator_eui: {}, gateway_id: {}",
+            hex::encode(eui),
+            hex::encode(&expected)
+        ));
+    }
+
+    info!(
+        "Concentrator EUI matches the provisioned gateway ID, gateway_id: {}",
+        hex::encode(eui)
+    );
+
+    Ok(())
+}
+
+// Each line contains the hostname and the gateway ID, separated by whitespace. Empty lines and
+// lines starting with # are ignored.
+fn parse_mapping(s: &str) -> Result<HashMap<String, Vec<u8>>> {
+    let mut out = HashMap::new();
+
+    for (i, line) in s.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 2 {
+            return Err(anyhow!(
+                "invalid provisioning mapping, line: {}, expected: <hostname> <gateway_id>",
+                i + 1
+            ));
+        }
+
+        out.insert(fields[0].to_string(), decode_gateway_id(fields[1])?);
+    }
+
+    Ok(out)
+}
+
+fn decode_gateway_id(s: &str) -> Result<Vec<u8>> {
+    let b = hex::decode(s)?;
+    if b.len() != 8 {
+        return Err(anyhow!("gateway_id must be exactly 8 bytes: {}", s));
+    }
+    Ok(b)
+}
+
+fn get_hostname() -> Result<String> {
+    let mut buf = [0 as libc::c_char; 256];
+    let ret = unsafe { libc::gethostname(buf.as_mut_ptr(), buf.len()) };
+    if ret != 0 {
+        return Err(anyhow!("gethostname failed"));
+    }
+
+    let hostname = unsafe { CStr::from_ptr(buf.as_ptr()) };
+    Ok(hostname.to_str()?.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mapping() {
+        let mapping = parse_mapping(
+            "# hostname gateway_id\n\ngw-01 0102030405060708\n  gw-02\t1112131415161718\n",
+        )
+        .unwrap();
+        assert_eq!(2, mapping.len());
+        assert_eq!(Some(&vec![1, 2, 3, 4, 5, 6, 7, 8]), mapping.get("gw-01"));
+        assert_eq!(
+            Some(&vec![0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18]),
+            mapping.get("gw-02")
+        );
+
+        assert!(parse_mapping("gw-01").is_err());
+        assert!(parse_mapping("gw-01 010203").is_err());
+    }
+
+    #[test]
+    fn test_verify() {
+        let conf = Configuration {
+            gateway_id: "0102030405060708".to_string(),
+            ..Default::default()
+        };
+        assert!(verify(&conf, &[1, 2, 3, 4, 5, 6, 7, 8]).is_ok());
+        assert!(verify(&conf, &[1, 2, 3, 4, 5, 6, 7, 9]).is_err());
+
+        // nothing is provisioned
+        assert!(verify(&Default::default(), &[1, 2, 3, 4, 5, 6, 7, 9]).is_ok());
+    }
+}
@@ -0,0 +1,347 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::Result;
+use chirpstack_api::gw;
+
+use super::{api, dutycycle};
+
+// MHDR (1 byte) + MIC (4 bytes).
+const PHY_PAYLOAD_OVERHEAD: usize = 5;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Region {
+    AS923,
+    AU915,
+    CN470,
+    EU433,
+    EU868,
+    IN865,
+    ISM2400,
+    KR920,
+    RU864,
+    US915,
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl FromStr for Region {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.to_uppercase().as_ref() {
+            "AS923" => Region::AS923,
+            "AU915" => Region::AU915,
+            "CN470" => Region::CN470,
+            "EU433" => Region::EU433,
+            "EU868" => Region::EU868,
+            "IN865" => Region::IN865,
+            "ISM2400" => Region::ISM2400,
+            "KR920" => Region::KR920,
+            "RU864" => Region::RU864,
+            "US915" => Region::US915,
+            _ => return Err(anyhow!("unexpected region: {}", s)),
+        })
+    }
+}
+
+impl Region {
+    /// Returns the region from the model name suffix (e.g. rak_2287_eu868).
+    pub fn from_model(model: &str) -> Option<Region> {
+        model.rsplit('_').next()?.parse().ok()
+    }
+
+    /// Returns the max. PHYPayload size for the given modulation parameters. None is returned
+    /// when the parameters are not valid for the region.
+    ///
+    /// Note that the AS923 values assume that the downlink dwell-time limitation is disabled.
+    pub fn get_max_phy_payload_size(&self, params: &gw::modulation::Parameters) -> Option<usize> {
+        let mac_payload_size = match params {
+            gw::modulation::Parameters::Lora(v) => {
+                self.get_max_mac_payload_size_lora(v.bandwidth, v.spreading_factor)?
+            }
+            gw::modulation::Parameters::Fsk(_) => 250,
+            gw::modulation::Parameters::LrFhss(_) => return None,
+        };
+
+        Some(mac_payload_size + PHY_PAYLOAD_OVERHEAD)
+    }
+
+    /// Returns the beacon frequencies (Hz) of the region. When multiple frequencies are
+    /// returned, the beacon hops over these frequencies every beacon period.
+    ///
+    /// Note that the AS923 value is the AS923-1 beacon frequency.
+    pub fn get_beacon_frequencies(&self) -> Vec<u32> {
+        match self {
+            Region::AS923 => vec![923400000],
+            Region::AU915 | Region::US915 => (0..8).map(|i| 923300000 + i * 600000).collect(),
+            Region::CN470 => (0..8).map(|i| 508300000 + i * 200000).collect(),
+            Region::EU433 => vec![434665000],
+            Region::EU868 => vec![869525000],
+            Region::IN865 => vec![866550000],
+            Region::ISM2400 => vec![2424000000],
+            Region::KR920 => vec![923100000],
+            Region::RU864 => vec![869100000],
+        }
+    }
+
+    /// Returns the max. EIRP (dBm) for the given frequency (Hz).
+    pub fn get_max_eirp(&self, frequency: u32) -> i8 {
+        match self {
+            Region::AS923 => 16,
+            Region::AU915 => 30,
+            Region::CN470 => 19,
+            Region::EU433 => 12,
+            Region::EU868 => match frequency {
+                // g3 sub-band (500mW ERP)
+                869400000..=869650000 => 29,
+                _ => 16,
+            },
+            Region::IN865 => 30,
+            Region::ISM2400 => 10,
+            Region::KR920 => 14,
+            Region::RU864 => 16,
+            Region::US915 => 30,
+        }
+    }
+
+    /// Returns the sub-bands with a duty-cycle limit. An empty list is returned when the
+    /// region does not have duty-cycle limited sub-bands.
+    pub fn get_duty_cycle_bands(&self) -> Vec<dutycycle::Band> {
+        match self {
+            Region::EU433 => vec![dutycycle::Band::new(433050000, 434790000, 0.1)],
+            Region::EU868 => vec![
+                dutycycle::Band::new(863000000, 865000000, 0.001),
+                dutycycle::Band::new(865000000, 868000000, 0.01),
+                dutycycle::Band::new(868000000, 868600000, 0.01),
+                dutycycle::Band::new(868700000, 869200000, 0.001),
+                dutycycle::Band::new(869400000, 869650000, 0.1),
+                dutycycle::Band::new(869700000, 870000000, 0.01),
+            ],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Validates the PHYPayload size of the given downlink item.
+    pub fn validate_phy_payload_size(
+        &self,
+        item: &gw::DownlinkFrameItem,
+    ) -> Result<(), PayloadSizeError> {
+        let params = match item
+            .tx_info
+            .as_ref()
+            .and_then(|v| v.modulation.as_ref())
+            .and_then(|v| v.parameters.as_ref())
+        {
+            Some(v) => v,
+            None => {
+                return Err(PayloadSizeError::Modulation(
+                    "modulation parameters must not be blank".to_string(),
+                ))
+            }
+        };
+
+        let max_size = match self.get_max_phy_payload_size(params) {
+            Some(v) => v,
+            None => {
+                return Err(PayloadSizeError::Modulation(format!(
+                    "modulation parameters are not valid for region, region: {}",
+                    self
+                )))
+            }
+        };
+
+        if item.phy_payload.len() > max_size {
+            return Err(PayloadSizeError::Exceeded {
+                size: item.phy_payload.len(),
+                max_size,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn get_max_mac_payload_size_lora(
+        &self,
+        bandwidth: u32,
+        spreading_factor: u32,
+    ) -> Option<usize> {
+        match self {
+            Region::US915 => match (bandwidth, spreading_factor) {
+                (125000, 10) => Some(19),
+                (125000, 9) => Some(61),
+                (125000, 8) => Some(133),
+                (125000, 7) => Some(250),
+                (500000, 12) => Some(61),
+                (500000, 11) => Some(137),
+                (500000, 7..=10) => Some(250),
+                _ => None,
+            },
+            Region::AU915 => match (bandwidth, spreading_factor) {
+                (125000, 10..=12) => Some(59),
+                (125000, 9) => Some(123),
+                (125000, 7..=8) => Some(250),
+                (500000, 12) => Some(61),
+                (500000, 11) => Some(137),
+                (500000, 7..=10) => Some(250),
+                _ => None,
+            },
+            Region::ISM2400 => match (bandwidth, spreading_factor) {
+                (_, 12) => Some(59),
+                (_, 11) => Some(123),
+                (_, 5..=10) => Some(248),
+                _ => None,
+            },
+            _ => match (bandwidth, spreading_factor) {
+                (125000, 10..=12) => Some(59),
+                (125000, 9) => Some(123),
+                (125000, 7..=8) => Some(250),
+                (250000, 7) => Some(250),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// Reason why the PHYPayload size of a downlink item can not be validated or is not valid.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum PayloadSizeError {
+    // The modulation parameters are blank or not valid for the region.
+    Modulation(String),
+    // The PHYPayload exceeds the max. PHYPayload size of the data-rate.
+    Exceeded { size: usize, max_size: usize },
+}
+
+impl PayloadSizeError {
+    /// Returns the rejection details of the given downlink item. None is returned when the
+    /// modulation parameters are not valid, as this is not a rejection of the payload size.
+    pub fn to_proto(&self, item: usize) -> Option<api::DownlinkRejection> {
+        match self {
+            PayloadSizeError::Modulation(_) => None,
+            PayloadSizeError::Exceeded { max_size, .. } => Some(api::DownlinkRejection {
+                item: item as u32,
+                reason: "PAYLOAD_SIZE".to_string(),
+                max_payload_size: *max_size as u32,
+                ..Default::default()
+            }),
+        }
+    }
+}
+
+impl fmt::Display for PayloadSizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PayloadSizeError::Modulation(v) => write!(f, "{}", v),
+            PayloadSizeError::Exceeded { size, max_size } => write!(
+                f,
+                "PHYPayload exceeds max size, size: {}, max_size: {}",
+                size, max_size
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lora(bandwidth: u32, spreading_factor: u32) -> gw::modulation::Parameters {
+        gw::modulation::Parameters::Lora(gw::LoraModulationInfo {
+            bandwidth,
+            spreading_factor,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_from_model() {
+        assert_eq!(Some(Region::EU868), Region::from_model("rak_2287_eu868"));
+        assert_eq!(
+            Some(Region::US915),
+            Region::from_model("multitech_mtac_lora_h_915_us915")
+        );
+        assert_eq!(None, Region::from_model("semtech_sx1280z3dsfgw1"));
+    }
+
+    #[test]
+    fn test_get_max_phy_payload_size() {
+        let tests = vec![
+            (Region::EU868, lora(125000, 12), Some(64)),
+            (Region::EU868, lora(125000, 9), Some(128)),
+            (Region::EU868, lora(125000, 7), Some(255)),
+            (Region::EU868, lora(500000, 12), None),
+            (Region::US915, lora(125000, 10), Some(24)),
+            (Region::US915, lora(125000, 7), Some(255)),
+            (Region::US915, lora(500000, 12), Some(66)),
+            (Region::US915, lora(500000, 11), Some(142)),
+            (Region::ISM2400, lora(812000, 12), Some(64)),
+            (Region::ISM2400, lora(812000, 7), Some(253)),
+        ];
+
+        for (region, params, expected) in tests {
+            assert_eq!(expected, region.get_max_phy_payload_size(&params));
+        }
+    }
+
+    #[test]
+    fn test_get_beacon_frequencies() {
+        assert_eq!(vec![869525000], Region::EU868.get_beacon_frequencies());
+
+        let freqs = Region::US915.get_beacon_frequencies();
+        assert_eq!(8, freqs.len());
+        assert_eq!(923300000, freqs[0]);
+        assert_eq!(927500000, freqs[7]);
+    }
+
+    #[test]
+    fn test_get_max_eirp() {
+        assert_eq!(16, Region::EU868.get_max_eirp(868100000));
+        assert_eq!(29, Region::EU868.get_max_eirp(869525000));
+        assert_eq!(30, Region::US915.get_max_eirp(923300000));
+    }
+
+    #[test]
+    fn test_get_duty_cycle_bands() {
+        let bands = Region::EU868.get_duty_cycle_bands();
+        assert_eq!(6, bands.len());
+        assert_eq!("869.40-869.65", bands[4].name);
+        assert_eq!(0.1, bands[4].duty_cycle);
+
+        assert!(Region::US915.get_duty_cycle_bands().is_empty());
+    }
+
+    #[test]
+    fn test_validate_phy_payload_size() {
+        let mut item = gw::DownlinkFrameItem {
+            phy_payload: vec![0; 64],
+            tx_info: Some(gw::DownlinkTxInfo {
+                modulation: Some(gw::Modulation {
+                    parameters: Some(lora(125000, 12)),
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        assert!(Region::EU868.validate_phy_payload_size(&item).is_ok());
+
+        item.phy_payload = vec![0; 65];
+        let err = Region::EU868.validate_phy_payload_size(&item).unwrap_err();
+        assert_eq!(
+            PayloadSizeError::Exceeded {
+                size: 65,
+                max_size: 64
+            },
+            err
+        );
+
+        let rejection = err.to_proto(1).unwrap();
+        assert_eq!(1, rejection.item);
+        assert_eq!("PAYLOAD_SIZE", rejection.reason);
+        assert_eq!(64, rejection.max_payload_size);
+    }
+}
@@ -0,0 +1,184 @@
+use std::convert::TryFrom;
+use std::io::Read;
+use std::str::FromStr;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Result;
+use ed25519_dalek::{PublicKey, Signature, Verifier};
+use log::{debug, error, info};
+use prost::Message;
+
+use super::api;
+use super::logging;
+use super::signals::Signal;
+use super::stats;
+
+// Max. size of the fetched configuration bundle.
+const MAX_BUNDLE_SIZE: u64 = 64 * 1024;
+
+// Timeout of the configuration bundle request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    static ref VERSION: Mutex<String> = Mutex::new(String::new());
+}
+
+#[derive(Clone)]
+pub struct Configuration {
+    // HTTPS endpoint from which the signed configuration bundle is fetched.
+    pub url: String,
+    // Ed25519 public key (32 bytes) used to validate the bundle signature.
+    pub public_key: Vec<u8>,
+    // Interval between two fetches.
+    pub interval: Duration,
+}
+
+/// Periodically fetches the configuration bundle. On a new version, the log level is applied
+/// directly and the channel plan is applied through the same path as the configuration
+/// command, which re-initializes the concentrator.
+pub fn fetch_loop(
+    conf: &Configuration,
+    gateway_id: &[u8],
+    stop_send: Sender<Signal>,
+    stop_receive: Receiver<Signal>,
+) {
+    info!(
+        "Starting remote configuration loop, url: {}, interval: {:?}",
+        conf.url, conf.interval
+    );
+
+    loop {
+        if let Err(err) = fetch_and_apply(conf, gateway_id, &stop_send) {
+            error!("Remote configuration error, error: {}", err);
+        }
+
+        // Instead of an 'interval' sleep, we receive from the stop channel with a timeout
+        // equal to the 'interval'.
+        if let Ok(v) = stop_receive.recv_timeout(conf.interval) {
+            debug!("Received stop signal, signal: {}", v);
+            break;
+        }
+    }
+
+    debug!("Remote configuration loop ended");
+}
+
+fn fetch_and_apply(
+    conf: &Configuration,
+    gateway_id: &[u8],
+    stop_send: &Sender<Signal>,
+) -> Result<()> {
+    let b = fetch(&conf.url, gateway_id)?;
+    let bundle = verify(&conf.public_key, &b)?;
+
+    let mut version = VERSION.lock().unwrap();
+    if bundle.version == *version {
+        debug!(
+            "Remote configuration is up-to-date, version: {}",
+            bundle.version
+        );
+        return Ok(());
+    }
+
+    info!(
+        "Applying remote configuration, version: {}, previous_version: {}",
+        bundle.version, version
+    );
+
+    if !bundle.log_level.is_empty() {
+        let level = log::Level::from_str(&bundle.log_level)
+            .map_err(|_| anyhow!("invalid log_level: {}", bundle.log_level))?;
+        logging::set_level(level.to_level_filter());
+    }
+
+    *version = bundle.version.clone();
+    stats::set_metadata("remote_config_version", &bundle.version);
+
+    if let Some(mut gw_conf) = bundle.configuration {
+        gw_conf.version = bundle.version;
+        stop_send.send(Signal::Configuration(gw_conf))?;
+    }
+
+    Ok(())
+}
+
+fn fetch(url: &str, gateway_id: &[u8]) -> Result<Vec<u8>> {
+    debug!("Fetching remote configuration, url: {}", url);
+
+    let resp = ureq::get(url)
+        .query("gateway_id", &hex::encode(gateway_id))
+        .timeout(REQUEST_TIMEOUT)
+        .call()?;
+
+    let mut b: Vec<u8> = Vec::new();
+    resp.into_reader()
+        .take(MAX_BUNDLE_SIZE + 1)
+        .read_to_end(&mut b)?;
+
+    if b.len() as u64 > MAX_BUNDLE_SIZE {
+        return Err(anyhow!(
+            "remote configuration exceeds max size, max_size: {}",
+            MAX_BUNDLE_SIZE
+        ));
+    }
+
+    Ok(b)
+}
+
+/// Validates the signature of the given signed configuration bundle and returns the decoded
+/// bundle.
+pub fn verify(public_key: &[u8], b: &[u8]) -> Result<api::ConfigBundle> {
+    let signed = api::SignedConfigBundle::decode(b)?;
+
+    let public_key =
+        PublicKey::from_bytes(public_key).map_err(|e| anyhow!("invalid public_key: {}", e))?;
+    let signature = Signature::try_from(signed.signature.as_slice())
+        .map_err(|e| anyhow!("invalid signature: {}", e))?;
+
+    public_key
+        .verify(&signed.bundle, &signature)
+        .map_err(|e| anyhow!("signature validation failed: {}", e))?;
+
+    Ok(api::ConfigBundle::decode(signed.bundle.as_slice())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{ExpandedSecretKey, SecretKey};
+
+    #[test]
+    fn test_verify() {
+        let secret_key = SecretKey::from_bytes(&[1; 32]).unwrap();
+        let public_key: PublicKey = (&secret_key).into();
+
+        let bundle = api::ConfigBundle {
+            version: "1.0.0".to_string(),
+            log_level: "DEBUG".to_string(),
+            ..Default::default()
+        };
+        let bundle_b = bundle.encode_to_vec();
+
+        let mut signed = api::SignedConfigBundle {
+            signature: ExpandedSecretKey::from(&secret_key)
+                .sign(&bundle_b, &public_key)
+                .to_bytes()
+                .to_vec(),
+            bundle: bundle_b,
+        };
+
+        assert_eq!(
+            bundle,
+            verify(public_key.as_bytes(), &signed.encode_to_vec()).unwrap()
+        );
+
+        // invalid public key
+        assert!(verify(&[2; 32], &signed.encode_to_vec()).is_err());
+
+        // tampered bundle
+        signed.bundle[0] ^= 0xff;
+        assert!(verify(public_key.as_bytes(), &signed.encode_to_vec()).is_err());
+    }
+}
@@ -1,20 +1,358 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
 use std::process::Command;
+use std::str::FromStr;
 use std::sync::Mutex;
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::Result;
-use gpio_cdev::{Chip, LineHandle, LineRequestFlags};
-use log::info;
+use gpio_cdev::{chips, Chip, LineHandle, LineRequestFlags};
+use log::{debug, info, warn};
+
+use super::{api, i2cgpio, stats};
 
 lazy_static! {
-    static ref SX1302_RESET: Mutex<Option<LineHandle>> = Mutex::new(None);
-    static ref SX1302_POWER_EN: Mutex<Option<LineHandle>> = Mutex::new(None);
-    static ref SX1261_RESET: Mutex<Option<LineHandle>> = Mutex::new(None);
-    static ref AD5338R_RESET: Mutex<Option<LineHandle>> = Mutex::new(None);
-    static ref RESET_COMMANDS: Mutex<Option<Vec<(String, Vec<String>)>>> = Mutex::new(None);
+    static ref SX1302_RESET: Mutex<Option<Line>> = Mutex::new(None);
+    static ref SX1302_POWER_EN: Mutex<Option<Line>> = Mutex::new(None);
+    static ref SX1261_RESET: Mutex<Option<Line>> = Mutex::new(None);
+    static ref AD5338R_RESET: Mutex<Option<Line>> = Mutex::new(None);
+    static ref STATE: Mutex<State> = Mutex::new(Default::default());
+    static ref SHUTDOWN_COMMANDS: Mutex<Option<Vec<(String, Vec<String>)>>> = Mutex::new(None);
+    static ref TIMING: Mutex<Timing> = Mutex::new(Default::default());
+}
+
+// Pins, in the order of the default reset sequence.
+const PINS: [Pin; 4] = [
+    Pin::Sx1302PowerEn,
+    Pin::Sx130xReset,
+    Pin::Sx1261Reset,
+    Pin::Ad5338rReset,
+];
+
+// Interval between two requests of a busy GPIO line.
+const BUSY_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Reason why the reset sequence is executed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Reason {
+    // Concentratord was started.
+    Startup,
+    // The concentrator is re-initialized to apply a configuration update.
+    Configuration,
+    // The concentrator is re-initialized after a failed health check.
+    Recovery,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Reason::Startup => write!(f, "STARTUP"),
+            Reason::Configuration => write!(f, "CONFIGURATION"),
+            Reason::Recovery => write!(f, "RECOVERY"),
+        }
+    }
+}
+
+/// State of the reset module, see get_state.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct State {
+    // Configured reset and power enable pins.
+    pub pins: Vec<PinState>,
+    // Reset sequence.
+    pub sequence: Vec<Step>,
+    // Number of resets since Concentratord was started.
+    pub reset_count: u64,
+    // Time and reason of the last reset.
+    pub last_reset: Option<(SystemTime, Reason)>,
+}
+
+impl State {
+    /// Returns the reset status, as included in the status command.
+    pub fn to_proto(&self) -> api::ResetStatus {
+        api::ResetStatus {
+            pins: self
+                .pins
+                .iter()
+                .map(|v| api::ResetPin {
+                    pin: v.pin.to_string(),
+                    chip: v.chip.clone(),
+                    offset: v.offset,
+                    shared: v.shared,
+                })
+                .collect(),
+            count: self.reset_count,
+            last_reset: self.last_reset.map(|(t, _)| {
+                let t = t.duration_since(UNIX_EPOCH).unwrap_or_default();
+                pbjson_types::Timestamp {
+                    seconds: t.as_secs() as i64,
+                    nanos: t.subsec_nanos() as i32,
+                }
+            }),
+            last_reason: self
+                .last_reset
+                .map(|(_, reason)| reason.to_string())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Configured reset or power enable pin.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PinState {
+    pub pin: Pin,
+    // GPIO chip or I2C GPIO expander.
+    pub chip: String,
+    pub offset: u32,
+    // The native GPIO line is only requested while it is driven.
+    pub shared: bool,
 }
 
+// Output line, either a native GPIO line or a line of an I2C GPIO expander.
+pub(crate) enum Line {
+    Gpio(LineHandle),
+    I2c(i2cgpio::Line),
+    // Native GPIO line which is only requested while it is driven, such that it can be shared
+    // with other processes.
+    Shared(SharedLine),
+}
+
+pub(crate) struct SharedLine {
+    chip: String,
+    pin: u32,
+    consumer: String,
+    busy_timeout: Duration,
+}
+
+impl SharedLine {
+    // Request the line, driving it to the given value. The value is passed as default value of
+    // the request, such that the line is not driven low first.
+    fn request(&self, value: u8) -> Result<Line> {
+        Ok(Line::Gpio(request_gpio(
+            &self.chip,
+            self.pin,
+            &self.consumer,
+            value,
+            self.busy_timeout,
+        )?))
+    }
+}
+
+impl Line {
+    // Request the given pin of the chip as output. The chip is either a GPIO chip or an I2C
+    // GPIO expander (see i2cgpio).
+    pub(crate) fn request(chip: &str, pin: u32, consumer: &str) -> Result<Line> {
+        Line::request_with_timeout(chip, pin, consumer, Duration::ZERO)
+    }
+
+    // Request the given pin of the chip as output. When the native GPIO line is busy (e.g.
+    // requested by an other process), the request is retried until the busy timeout.
+    fn request_with_timeout(
+        chip: &str,
+        pin: u32,
+        consumer: &str,
+        busy_timeout: Duration,
+    ) -> Result<Line> {
+        if let Some(expander) = i2cgpio::parse_chip(chip) {
+            return Ok(Line::I2c(i2cgpio::Line::request(&expander?, pin, 0)?));
+        }
+
+        Ok(Line::Gpio(request_gpio(
+            chip,
+            pin,
+            consumer,
+            0,
+            busy_timeout,
+        )?))
+    }
+
+    // Returns a shared line for the given pin of the chip, which is requested on every use and
+    // released afterwards. I2C GPIO expander lines can not be requested by other processes,
+    // these are requested immediately.
+    fn shared(chip: &str, pin: u32, consumer: &str, busy_timeout: Duration) -> Result<Line> {
+        if i2cgpio::parse_chip(chip).is_some() {
+            return Line::request(chip, pin, consumer);
+        }
+
+        // validate the line
+        get_chip(chip)?.get_line(pin)?;
+
+        Ok(Line::Shared(SharedLine {
+            chip: chip.to_string(),
+            pin,
+            consumer: consumer.to_string(),
+            busy_timeout,
+        }))
+    }
+
+    pub(crate) fn set_value(&self, value: u8) -> Result<()> {
+        match self {
+            Line::Gpio(v) => v.set_value(value)?,
+            Line::I2c(v) => v.set_value(value)?,
+            Line::Shared(v) => {
+                v.request(value)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Request a shared line driven to the given value, such that it is held until the returned
+    // line is dropped. Nothing is returned for the other lines, as these are held already.
+    fn acquire(&self, value: u8) -> Result<Option<Line>> {
+        match self {
+            Line::Shared(v) => Ok(Some(v.request(value)?)),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Reset or power enable pin.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Pin {
+    Sx130xReset,
+    Sx1302PowerEn,
+    Sx1261Reset,
+    Ad5338rReset,
+}
+
+impl fmt::Display for Pin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pin::Sx130xReset => write!(f, "SX130X_RESET"),
+            Pin::Sx1302PowerEn => write!(f, "SX1302_POWER_EN"),
+            Pin::Sx1261Reset => write!(f, "SX1261_RESET"),
+            Pin::Ad5338rReset => write!(f, "AD5338R_RESET"),
+        }
+    }
+}
+
+impl FromStr for Pin {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "SX130X_RESET" => Pin::Sx130xReset,
+            "SX1302_POWER_EN" => Pin::Sx1302PowerEn,
+            "SX1261_RESET" => Pin::Sx1261Reset,
+            "AD5338R_RESET" => Pin::Ad5338rReset,
+            _ => return Err(anyhow!("unexpected pin: {}", s)),
+        })
+    }
+}
+
+/// Step of the reset sequence. A step is given as SET <PIN> <VALUE>, WAIT <MS> or
+/// COMMAND <COMMAND> [ARGS...], e.g. SET SX130X_RESET 1.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Step {
+    // Set the pin to the given value (0 or 1).
+    Set(Pin, u8),
+    // Wait for the given duration.
+    Wait(Duration),
+    // Execute the command with the given arguments.
+    Command(String, Vec<String>),
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Step::Set(pin, value) => write!(f, "SET {} {}", pin, value),
+            Step::Wait(d) => write!(f, "WAIT {}", d.as_millis()),
+            Step::Command(cmd, args) if args.is_empty() => write!(f, "COMMAND {}", cmd),
+            Step::Command(cmd, args) => write!(f, "COMMAND {} {}", cmd, args.join(" ")),
+        }
+    }
+}
+
+impl FromStr for Step {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split_whitespace().collect();
+
+        Ok(match parts.as_slice() {
+            ["SET", pin, value] => Step::Set(
+                Pin::from_str(pin)?,
+                match *value {
+                    "0" => 0,
+                    "1" => 1,
+                    _ => return Err(anyhow!("value must be 0 or 1, step: {}", s)),
+                },
+            ),
+            ["WAIT", ms] => Step::Wait(Duration::from_millis(ms.parse()?)),
+            ["COMMAND", cmd, args @ ..] => Step::Command(
+                cmd.to_string(),
+                args.iter().map(|v| v.to_string()).collect(),
+            ),
+            _ => return Err(anyhow!("unexpected reset step: {}", s)),
+        })
+    }
+}
+
+/// Reset waveform of a pin.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PinTiming {
+    // The pin is asserted by setting it low.
+    pub active_low: bool,
+    // Time the pin is asserted. For the power enable pin, this is the time to let the power
+    // settle.
+    pub assert: Duration,
+    // Time to wait after the pin is deasserted.
+    pub deassert: Duration,
+    // Additional delay after the reset of the pin, e.g. for a slow boot of the chip.
+    pub post_reset: Duration,
+}
+
+impl PinTiming {
+    /// Returns the timing of an active-high pin, using the given assert and deassert duration.
+    pub fn active_high(d: Duration) -> PinTiming {
+        PinTiming {
+            active_low: false,
+            assert: d,
+            deassert: d,
+            post_reset: Duration::from_secs(0),
+        }
+    }
+
+    /// Returns the timing of an active-low pin, using the given assert and deassert duration.
+    pub fn active_low(d: Duration) -> PinTiming {
+        PinTiming {
+            active_low: true,
+            ..PinTiming::active_high(d)
+        }
+    }
+
+    // Returns the line value for the asserted or deasserted state.
+    fn value(&self, asserted: bool) -> u8 {
+        (asserted != self.active_low) as u8
+    }
+}
+
+/// Reset waveforms of the reset pins. The defaults match the reference designs: an active-high
+/// SX1302 (SX1301) reset and power enable and an active-low SX1261 and AD5338R reset, each
+/// using 100ms delays.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Timing {
+    pub sx130x_reset: PinTiming,
+    pub sx1302_power_en: PinTiming,
+    pub sx1261_reset: PinTiming,
+    pub ad5338r_reset: PinTiming,
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Timing {
+            sx130x_reset: PinTiming::active_high(Duration::from_millis(100)),
+            sx1302_power_en: PinTiming::active_high(Duration::from_millis(100)),
+            sx1261_reset: PinTiming::active_low(Duration::from_millis(100)),
+            ad5338r_reset: PinTiming::active_low(Duration::from_millis(100)),
+        }
+    }
+}
+
+/// The pins are set as (chip, pin). The chip is either a GPIO chip path or label, or an I2C
+/// GPIO expander (e.g. pca9555:1:0x20, see i2cgpio).
 #[derive(Default)]
 pub struct Configuration {
     pub sx130x_reset: Option<(String, u32)>,
@@ -22,19 +360,32 @@ pub struct Configuration {
     pub sx1261_reset: Option<(String, u32)>,
     pub ad5338r_reset: Option<(String, u32)>,
     pub reset_commands: Option<Vec<(String, Vec<String>)>>,
+    pub shutdown_commands: Option<Vec<(String, Vec<String>)>>,
+    pub timing: Timing,
+    // Reset sequence, overriding the default sequence which is derived from the configured pins,
+    // the timing and the reset commands.
+    pub sequence: Option<Vec<Step>>,
+    // Only request the native GPIO lines for the duration of the reset pulse (or power enable /
+    // disable), such that these can be shared with other processes.
+    pub shared: bool,
+    // Duration for which a busy native GPIO line request is retried.
+    pub busy_timeout: Duration,
 }
 
 pub fn setup_pins(config: Configuration) -> Result<()> {
+    let (shared, busy_timeout) = (config.shared, config.busy_timeout);
+    let mut pins: Vec<PinState> = Vec::new();
+
     if let Some(sx1302_reset) = config.sx130x_reset {
         info!(
             "Configuring reset pin, dev: {}, pin: {}",
             sx1302_reset.0, sx1302_reset.1
         );
 
-        let mut chip = Chip::new(sx1302_reset.0)?;
-        let line = chip.get_line(sx1302_reset.1)?;
+        let line = request_line(&sx1302_reset, "sx130x_reset", shared, busy_timeout)?;
+        pins.push(pin_state(Pin::Sx130xReset, &sx1302_reset, shared));
         let mut sx1302_reset = SX1302_RESET.lock().unwrap();
-        *sx1302_reset = Some(line.request(LineRequestFlags::OUTPUT, 0, "sx130x_reset")?);
+        *sx1302_reset = Some(line);
     }
 
     if let Some(sx1302_power_en) = config.sx1302_power_en {
@@ -43,10 +394,10 @@ pub fn setup_pins(config: Configuration) -> Result<()> {
             sx1302_power_en.0, sx1302_power_en.1
         );
 
-        let mut chip = Chip::new(sx1302_power_en.0)?;
-        let line = chip.get_line(sx1302_power_en.1)?;
+        let line = request_line(&sx1302_power_en, "sx1302_power_en", shared, busy_timeout)?;
+        pins.push(pin_state(Pin::Sx1302PowerEn, &sx1302_power_en, shared));
         let mut sx1302_power_en = SX1302_POWER_EN.lock().unwrap();
-        *sx1302_power_en = Some(line.request(LineRequestFlags::OUTPUT, 0, "sx1302_power_en")?);
+        *sx1302_power_en = Some(line);
     }
 
     if let Some(sx1261_reset) = config.sx1261_reset {
@@ -55,10 +406,10 @@ pub fn setup_pins(config: Configuration) -> Result<()> {
             sx1261_reset.0, sx1261_reset.1
         );
 
-        let mut chip = Chip::new(sx1261_reset.0)?;
-        let line = chip.get_line(sx1261_reset.1)?;
+        let line = request_line(&sx1261_reset, "sx1261_reset", shared, busy_timeout)?;
+        pins.push(pin_state(Pin::Sx1261Reset, &sx1261_reset, shared));
         let mut sx1261_reset = SX1261_RESET.lock().unwrap();
-        *sx1261_reset = Some(line.request(LineRequestFlags::OUTPUT, 0, "sx1261_reset")?);
+        *sx1261_reset = Some(line);
     }
 
     if let Some(ad5338r_reset) = config.ad5338r_reset {
@@ -67,82 +418,550 @@ pub fn setup_pins(config: Configuration) -> Result<()> {
             ad5338r_reset.0, ad5338r_reset.1
         );
 
-        let mut chip = Chip::new(ad5338r_reset.0)?;
-        let line = chip.get_line(ad5338r_reset.1)?;
+        let line = request_line(&ad5338r_reset, "ad5338r_reset", shared, busy_timeout)?;
+        pins.push(pin_state(Pin::Ad5338rReset, &ad5338r_reset, shared));
         let mut ad5338r_reset = AD5338R_RESET.lock().unwrap();
-        *ad5338r_reset = Some(line.request(LineRequestFlags::OUTPUT, 0, "ad5338r_reset")?);
+        *ad5338r_reset = Some(line);
     }
 
-    if let Some(reset_commands) = config.reset_commands {
-        info!("Configuring raw reset commands");
+    let mut timing = TIMING.lock().unwrap();
+    *timing = config.timing;
+    drop(timing);
 
-        let mut reset_commands_m = RESET_COMMANDS.lock().unwrap();
-        *reset_commands_m = Some(reset_commands);
+    let sequence = match config.sequence {
+        Some(v) => {
+            info!("Configuring reset sequence");
+            v
+        }
+        None => {
+            let pins: Vec<Pin> = PINS
+                .iter()
+                .copied()
+                .filter(|v| get_line(*v).lock().unwrap().is_some())
+                .collect();
+
+            default_sequence(
+                &config.timing,
+                &pins,
+                config.reset_commands.unwrap_or_default(),
+            )
+        }
+    };
+
+    for step in &sequence {
+        if let Step::Set(pin, _) = step {
+            if get_line(*pin).lock().unwrap().is_none() {
+                return Err(anyhow!(
+                    "reset sequence pin is not configured, pin: {}",
+                    pin
+                ));
+            }
+        }
+    }
+
+    let mut state = STATE.lock().unwrap();
+    state.pins = pins;
+    state.sequence = sequence;
+    drop(state);
+
+    if let Some(shutdown_commands) = config.shutdown_commands {
+        info!("Configuring shutdown commands");
+
+        let mut shutdown_commands_m = SHUTDOWN_COMMANDS.lock().unwrap();
+        *shutdown_commands_m = Some(shutdown_commands);
     }
 
     Ok(())
 }
 
-pub fn reset() -> Result<()> {
-    let sx1302_power_en = SX1302_POWER_EN.lock().unwrap();
-    if sx1302_power_en.is_some() {
-        let sx1302_power_en = sx1302_power_en.as_ref().unwrap();
+fn pin_state(pin: Pin, line: &(String, u32), shared: bool) -> PinState {
+    PinState {
+        pin,
+        chip: line.0.clone(),
+        offset: line.1,
+        shared: shared && i2cgpio::parse_chip(&line.0).is_none(),
+    }
+}
 
-        info!("Enabling concentrator power");
+// Request the line used for a reset or power enable pin.
+fn request_line(
+    pin: &(String, u32),
+    consumer: &str,
+    shared: bool,
+    busy_timeout: Duration,
+) -> Result<Line> {
+    if i2cgpio::parse_chip(&pin.0).is_none() {
+        verify_line(&pin.0, pin.1, shared);
+    }
 
-        sx1302_power_en.set_value(1)?;
-        sleep(Duration::from_millis(100));
+    match shared {
+        true => Line::shared(&pin.0, pin.1, consumer, busy_timeout),
+        false => Line::request_with_timeout(&pin.0, pin.1, consumer, busy_timeout),
     }
+}
 
-    let sx1302 = SX1302_RESET.lock().unwrap();
-    if sx1302.is_some() {
-        let sx1302 = sx1302.as_ref().unwrap();
+// Request the native GPIO line as output, driven to the given default value. The request is
+// retried while the line is busy, until the busy timeout.
+fn request_gpio(
+    chip: &str,
+    pin: u32,
+    consumer: &str,
+    default: u8,
+    busy_timeout: Duration,
+) -> Result<LineHandle> {
+    let start = Instant::now();
+
+    loop {
+        let res = get_chip(chip).and_then(|mut chip| {
+            Ok(chip
+                .get_line(pin)?
+                .request(LineRequestFlags::OUTPUT, default, consumer)?)
+        });
 
-        info!("Triggering sx1302 reset");
+        match res {
+            Ok(v) => return Ok(v),
+            Err(err) => {
+                if !is_busy(&err) {
+                    return Err(err);
+                }
 
-        sx1302.set_value(1)?;
-        sleep(Duration::from_millis(100));
-        sx1302.set_value(0)?;
-        sleep(Duration::from_millis(100));
+                if start.elapsed() >= busy_timeout {
+                    return Err(match get_conflict(chip, pin) {
+                        Ok(conflict) => anyhow!(
+                            "gpio line is busy, chip: {}, pin: {}, {}",
+                            chip,
+                            pin,
+                            conflict
+                        ),
+                        Err(_) => err,
+                    });
+                }
+
+                debug!("GPIO line is busy, retrying, chip: {}, pin: {}", chip, pin);
+                sleep(BUSY_RETRY_INTERVAL);
+            }
+        }
     }
+}
 
-    let sx1261_reset = SX1261_RESET.lock().unwrap();
-    if sx1261_reset.is_some() {
-        let sx1261_reset = sx1261_reset.as_ref().unwrap();
+// Returns true when the line request failed because the line is requested by an other
+// consumer.
+fn is_busy(err: &anyhow::Error) -> bool {
+    match err.downcast_ref::<gpio_cdev::Error>().map(|v| v.kind()) {
+        Some(gpio_cdev::errors::ErrorKind::Ioctl { cause, .. }) => *cause as i32 == libc::EBUSY,
+        _ => false,
+    }
+}
 
-        info!("Triggering sx1261 reset");
+// Logs the state of the native GPIO line before it is requested, reporting the consumer and the
+// processes holding the line when it is already requested.
+fn verify_line(chip: &str, pin: u32, shared: bool) {
+    let res = get_chip(chip).and_then(|mut chip| Ok(chip.get_line(pin)?.info()?));
+    let info = match res {
+        Ok(v) => v,
+        Err(err) => {
+            warn!(
+                "Get gpio line info error, chip: {}, pin: {}, error: {}",
+                chip, pin, err
+            );
+            return;
+        }
+    };
+
+    debug!(
+        "GPIO line state, chip: {}, pin: {}, name: {:?}, direction: {:?}, used: {}",
+        chip,
+        pin,
+        info.name(),
+        info.direction(),
+        info.is_used()
+    );
 
-        sx1261_reset.set_value(0)?;
-        sleep(Duration::from_millis(100));
-        sx1261_reset.set_value(1)?;
-        sleep(Duration::from_millis(100));
+    // A shared line is expected to be requested by an other process from time to time.
+    if !info.is_used() || shared {
+        return;
     }
 
-    let ad5338r_reset = AD5338R_RESET.lock().unwrap();
-    if ad5338r_reset.is_some() {
-        let ad5338r_reset = ad5338r_reset.as_ref().unwrap();
+    match get_conflict(chip, pin) {
+        Ok(conflict) => warn!(
+            "GPIO line is already in use, chip: {}, pin: {}, {}",
+            chip, pin, conflict
+        ),
+        Err(err) => warn!(
+            "Get gpio line conflict error, chip: {}, pin: {}, error: {}",
+            chip, pin, err
+        ),
+    }
+}
+
+// Consumer and processes holding a requested GPIO line.
+struct Conflict {
+    consumer: Option<String>,
+    kernel: bool,
+    processes: Vec<(u32, String)>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "consumer: {}, processes: [{}], ",
+            self.consumer.as_deref().unwrap_or("unknown"),
+            self.processes
+                .iter()
+                .map(|(pid, name)| format!("{} ({})", pid, name))
+                .collect::<Vec<String>>()
+                .join(", ")
+        )?;
+
+        if self.consumer.as_deref() == Some("sysfs") {
+            write!(
+                f,
+                "the line is exported through /sys/class/gpio, unexport it"
+            )
+        } else if self.kernel && self.processes.is_empty() {
+            write!(
+                f,
+                "the line is used by a kernel driver, remove it from the device-tree (overlay)"
+            )
+        } else if !self.processes.is_empty() {
+            write!(
+                f,
+                "stop the process holding the line, or enable gateway.gpio.shared when the line must be shared"
+            )
+        } else {
+            write!(
+                f,
+                "the line is held by a process which could not be determined, see gpioinfo"
+            )
+        }
+    }
+}
+
+// Returns the consumer of the GPIO line and the other processes which have the GPIO chip open.
+// A line request is held through a file descriptor of the chip, the processes are found by the
+// file descriptors listed in /proc. Processes of other users are only visible to root.
+fn get_conflict(chip: &str, pin: u32) -> Result<Conflict> {
+    let mut chip = get_chip(chip)?;
+    let info = chip.get_line(pin)?.info()?;
+    let chip_path = chip.path().to_path_buf();
+    let own_pid = std::process::id();
+
+    let mut processes = Vec::new();
+    for entry in fs::read_dir("/proc")? {
+        let entry = entry?;
+        let pid = match entry.file_name().to_string_lossy().parse::<u32>() {
+            Ok(v) if v != own_pid => v,
+            _ => continue,
+        };
+
+        let fds = match fs::read_dir(entry.path().join("fd")) {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+
+        let holds_chip = fds
+            .filter_map(|fd| fd.ok())
+            .any(|fd| fs::read_link(fd.path()).ok().as_deref() == Some(chip_path.as_path()));
+
+        if holds_chip {
+            let name = fs::read_to_string(entry.path().join("comm")).unwrap_or_default();
+            processes.push((pid, name.trim().to_string()));
+        }
+    }
+
+    Ok(Conflict {
+        consumer: info.consumer().map(|v| v.to_string()),
+        kernel: info.is_kernel(),
+        processes,
+    })
+}
+
+// Returns the GPIO chip by its path (e.g. /dev/gpiochip0) or by its label / name
+// (e.g. pinctrl-rp1), as the chip numbering is not stable across boards and kernel versions.
+fn get_chip(chip: &str) -> Result<Chip> {
+    if chip.starts_with("/dev/") {
+        return Ok(Chip::new(chip)?);
+    }
+
+    for c in chips()? {
+        let c = c?;
+        if c.label() == chip || c.name() == chip {
+            info!(
+                "Found gpio chip, label: {}, path: {}",
+                chip,
+                c.path().display()
+            );
+            return Ok(c);
+        }
+    }
+
+    Err(anyhow!("gpio chip not found, label: {}", chip))
+}
+
+/// Returns the GPIO chip path and line offset of the line with the given name (as shown by
+/// gpioinfo), by scanning all the GPIO chips. A line of an I2C GPIO expander is given as
+/// <model>:<bus>:<address>:<pin> (e.g. pca9555:1:0x20:3, see i2cgpio).
+pub fn find_line(name: &str) -> Result<(String, u32)> {
+    if let Some(line) = i2cgpio::parse_line(name) {
+        return line;
+    }
+
+    for chip in chips()? {
+        let mut chip = chip?;
+
+        for offset in 0..chip.num_lines() {
+            let line_info = chip.get_line(offset)?.info()?;
+            if line_info.name() == Some(name) {
+                info!(
+                    "Found gpio line, name: {}, chip: {}, offset: {}",
+                    name,
+                    chip.path().display(),
+                    offset
+                );
+                return Ok((chip.path().to_string_lossy().to_string(), offset));
+            }
+        }
+    }
+
+    Err(anyhow!("gpio line not found, name: {}", name))
+}
+
+/// Execute the reset sequence. The number of resets per reason is reported as stats counter
+/// (reset_startup, reset_configuration, reset_recovery), the total number of resets and the
+/// last reset reason are reported as stats meta-data (reset_count, reset_last_reason).
+pub fn reset(reason: Reason) -> Result<()> {
+    info!("Executing reset sequence, reason: {}", reason);
+
+    let sequence = {
+        let mut state = STATE.lock().unwrap();
+        state.reset_count += 1;
+        state.last_reset = Some((SystemTime::now(), reason));
+
+        stats::inc_counter(&format!("reset_{}", reason.to_string().to_lowercase()));
+        stats::set_metadata("reset_count", &state.reset_count.to_string());
+        stats::set_metadata("reset_last_reason", &reason.to_string());
+
+        state.sequence.clone()
+    };
+
+    // The shared lines are requested on their first use and held until the end of the sequence.
+    let mut held: HashMap<Pin, Line> = HashMap::new();
+
+    for step in &sequence {
+        debug!("Executing reset step, step: {}", step);
+
+        match step {
+            Step::Set(pin, value) => {
+                if let Some(line) = held.get(pin) {
+                    line.set_value(*value)?;
+                    continue;
+                }
+
+                let line = get_line(*pin).lock().unwrap();
+                let line = line
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("reset sequence pin is not configured, pin: {}", pin))?;
 
-        info!("Triggering AD5338R reset");
-        ad5338r_reset.set_value(0)?;
-        sleep(Duration::from_millis(100));
-        ad5338r_reset.set_value(1)?;
-        sleep(Duration::from_millis(100));
+                match line.acquire(*value)? {
+                    Some(acquired) => {
+                        held.insert(*pin, acquired);
+                    }
+                    None => line.set_value(*value)?,
+                }
+            }
+            Step::Wait(d) => sleep(*d),
+            Step::Command(cmd, args) => {
+                info!(
+                    "Executing reset command, command: {}, args: {:?}",
+                    cmd, args
+                );
+
+                Command::new(cmd).args(args).output()?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Returns the default reset sequence: the power is enabled, after which the reset pins are
+// pulsed and the reset commands are executed.
+fn default_sequence(
+    timing: &Timing,
+    pins: &[Pin],
+    reset_commands: Vec<(String, Vec<String>)>,
+) -> Vec<Step> {
+    let mut sequence = Vec::new();
+
+    for pin in pins {
+        let t = match pin {
+            Pin::Sx1302PowerEn => {
+                let t = &timing.sx1302_power_en;
+                sequence.push(Step::Set(*pin, t.value(true)));
+                sequence.push(Step::Wait(t.assert + t.post_reset));
+                continue;
+            }
+            Pin::Sx130xReset => &timing.sx130x_reset,
+            Pin::Sx1261Reset => &timing.sx1261_reset,
+            Pin::Ad5338rReset => &timing.ad5338r_reset,
+        };
+
+        sequence.push(Step::Set(*pin, t.value(true)));
+        sequence.push(Step::Wait(t.assert));
+        sequence.push(Step::Set(*pin, t.value(false)));
+        sequence.push(Step::Wait(t.deassert + t.post_reset));
+    }
+
+    for (cmd, args) in reset_commands {
+        sequence.push(Step::Command(cmd, args));
+        sequence.push(Step::Wait(Duration::from_millis(100)));
+    }
+
+    sequence
+}
+
+// Returns the line of the given pin.
+fn get_line(pin: Pin) -> &'static Mutex<Option<Line>> {
+    match pin {
+        Pin::Sx130xReset => &*SX1302_RESET,
+        Pin::Sx1302PowerEn => &*SX1302_POWER_EN,
+        Pin::Sx1261Reset => &*SX1261_RESET,
+        Pin::Ad5338rReset => &*AD5338R_RESET,
+    }
+}
+
+/// Returns the current state: the configured pins, the reset sequence and the number, time and
+/// reason of the resets.
+pub fn get_state() -> State {
+    STATE.lock().unwrap().clone()
+}
+
+/// Execute the power down sequence, e.g. on shutdown. The reset pins and the power enable pin
+/// are deasserted, after which the shutdown commands are executed.
+pub fn power_down() -> Result<()> {
+    info!("Executing power down sequence");
+
+    let timing = *TIMING.lock().unwrap();
+
+    let sx1302 = SX1302_RESET.lock().unwrap();
+    if let Some(sx1302) = sx1302.as_ref() {
+        sx1302.set_value(timing.sx130x_reset.value(false))?;
+    }
+
+    let sx1261_reset = SX1261_RESET.lock().unwrap();
+    if let Some(sx1261_reset) = sx1261_reset.as_ref() {
+        sx1261_reset.set_value(timing.sx1261_reset.value(false))?;
     }
 
-    let reset_commands = RESET_COMMANDS.lock().unwrap();
-    if reset_commands.is_some() {
-        let reset_commands = reset_commands.as_ref().unwrap();
+    let ad5338r_reset = AD5338R_RESET.lock().unwrap();
+    if let Some(ad5338r_reset) = ad5338r_reset.as_ref() {
+        ad5338r_reset.set_value(timing.ad5338r_reset.value(false))?;
+    }
 
-        for (cmd, args) in reset_commands {
+    let sx1302_power_en = SX1302_POWER_EN.lock().unwrap();
+    if let Some(sx1302_power_en) = sx1302_power_en.as_ref() {
+        info!("Disabling concentrator power");
+        sx1302_power_en.set_value(timing.sx1302_power_en.value(false))?;
+    }
+
+    let shutdown_commands = SHUTDOWN_COMMANDS.lock().unwrap();
+    if let Some(shutdown_commands) = shutdown_commands.as_ref() {
+        for (cmd, args) in shutdown_commands {
             info!(
-                "Executing reset command, command: {}, args: {:?}",
+                "Executing shutdown command, command: {}, args: {:?}",
                 cmd, args
             );
 
             Command::new(cmd).args(args).output()?;
-            sleep(Duration::from_millis(100));
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pin_timing_value() {
+        let t = PinTiming::active_high(Duration::from_millis(100));
+        assert_eq!(1, t.value(true));
+        assert_eq!(0, t.value(false));
+
+        let t = PinTiming::active_low(Duration::from_millis(100));
+        assert_eq!(0, t.value(true));
+        assert_eq!(1, t.value(false));
+    }
+
+    #[test]
+    fn test_step_from_str() {
+        assert_eq!(
+            Step::Set(Pin::Sx1302PowerEn, 1),
+            Step::from_str("SET SX1302_POWER_EN 1").unwrap()
+        );
+        assert_eq!(
+            Step::Wait(Duration::from_millis(250)),
+            Step::from_str("WAIT 250").unwrap()
+        );
+        assert_eq!(
+            Step::Command(
+                "/usr/bin/reset_lgw.sh".to_string(),
+                vec!["start".to_string()]
+            ),
+            Step::from_str("COMMAND /usr/bin/reset_lgw.sh start").unwrap()
+        );
+        assert!(Step::from_str("SET SX130X_RESET 2").is_err());
+        assert!(Step::from_str("SET GPS_RESET 1").is_err());
+        assert!(Step::from_str("WAIT").is_err());
+
+        let step = Step::from_str("COMMAND /usr/bin/reset_lgw.sh start").unwrap();
+        assert_eq!("COMMAND /usr/bin/reset_lgw.sh start", step.to_string());
+    }
+
+    #[test]
+    fn test_default_sequence() {
+        let timing = Timing::default();
+        assert_eq!(
+            vec![
+                Step::Set(Pin::Sx1302PowerEn, 1),
+                Step::Wait(Duration::from_millis(100)),
+                Step::Set(Pin::Sx130xReset, 1),
+                Step::Wait(Duration::from_millis(100)),
+                Step::Set(Pin::Sx130xReset, 0),
+                Step::Wait(Duration::from_millis(100)),
+                Step::Command("reset.sh".to_string(), vec![]),
+                Step::Wait(Duration::from_millis(100)),
+            ],
+            default_sequence(
+                &timing,
+                &[Pin::Sx1302PowerEn, Pin::Sx130xReset],
+                vec![("reset.sh".to_string(), vec![])]
+            )
+        );
+    }
+
+    #[test]
+    fn test_conflict_display() {
+        let conflict = Conflict {
+            consumer: Some("reset".to_string()),
+            kernel: false,
+            processes: vec![(123, "lora_pkt_fwd".to_string())],
+        };
+        assert_eq!(
+            "consumer: reset, processes: [123 (lora_pkt_fwd)], stop the process holding the line, or enable gateway.gpio.shared when the line must be shared",
+            conflict.to_string()
+        );
+
+        let conflict = Conflict {
+            consumer: Some("sysfs".to_string()),
+            kernel: true,
+            processes: vec![],
+        };
+        assert_eq!(
+            "consumer: sysfs, processes: [], the line is exported through /sys/class/gpio, unexport it",
+            conflict.to_string()
+        );
+    }
+}
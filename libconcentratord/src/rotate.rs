@@ -0,0 +1,93 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::Result;
+
+/// Append-only file which is rotated once it exceeds the max. size.
+pub struct RotatingFile {
+    // Path of the file.
+    path: String,
+    // Max. size (bytes) of the file before it is rotated. When 0, the file is not rotated.
+    max_size: u64,
+    // Number of rotated files to keep (path.1 is the most recent one).
+    max_files: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    pub fn open(path: &str, max_size: u64, max_files: usize) -> Result<RotatingFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+
+        Ok(RotatingFile {
+            path: path.to_string(),
+            max_size,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    pub fn write(&mut self, b: &[u8]) -> Result<()> {
+        if self.max_size != 0 && self.size != 0 && self.size + b.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+
+        self.file.write_all(b)?;
+        self.size += b.len() as u64;
+
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        let path = &self.path;
+
+        if self.max_files == 0 {
+            fs::remove_file(path)?;
+        } else {
+            for i in (1..self.max_files).rev() {
+                let from = format!("{}.{}", path, i);
+                if Path::new(&from).exists() {
+                    fs::rename(&from, format!("{}.{}", path, i + 1))?;
+                }
+            }
+            fs::rename(path, format!("{}.1", path))?;
+        }
+
+        *self = RotatingFile::open(&self.path, self.max_size, self.max_files)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotate() {
+        let dir = std::env::temp_dir().join(format!("concentratord-rotate-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.log").to_str().unwrap().to_string();
+
+        let mut file = RotatingFile::open(&path, 10, 2).unwrap();
+
+        for line in ["aaaaaaaa\n", "bbbbbbbb\n", "cccccccc\n", "dddddddd\n"] {
+            file.write(line.as_bytes()).unwrap();
+        }
+
+        assert_eq!("dddddddd\n", fs::read_to_string(&path).unwrap());
+        assert_eq!(
+            "cccccccc\n",
+            fs::read_to_string(format!("{}.1", path)).unwrap()
+        );
+        assert_eq!(
+            "bbbbbbbb\n",
+            fs::read_to_string(format!("{}.2", path)).unwrap()
+        );
+        assert!(!Path::new(&format!("{}.3", path)).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
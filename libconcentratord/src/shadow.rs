@@ -0,0 +1,176 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use log::{debug, error, info};
+use serde_json::json;
+
+use super::signals::Signal;
+use super::{api, logging, reset, state, stats};
+
+#[derive(Clone)]
+pub struct Configuration {
+    // Path of the shadow state file (e.g. on tmpfs).
+    pub path: String,
+    // Interval at which the shadow state file is written.
+    pub interval: Duration,
+    // Hash of the configuration file(s).
+    pub config_hash: String,
+    // Version of the applied channel configuration.
+    pub config_version: String,
+}
+
+/// Daemon specific status, included in the shadow state.
+#[derive(Default)]
+pub struct Status {
+    // GPS status, None when the gateway does not have a GPS.
+    pub gps: Option<Gps>,
+    // Number of downlinks in the JIT queue.
+    pub queue_depth: usize,
+    // Capacity of the JIT queue.
+    pub queue_capacity: usize,
+}
+
+#[derive(Default)]
+pub struct Gps {
+    // The GPS time reference is valid.
+    pub time_valid: bool,
+    // Latitude, longitude and altitude of the GPS fix.
+    pub location: Option<(f64, f64, f64)>,
+}
+
+/// Returns the (HEX encoded) hash of the given configuration content.
+pub fn config_hash(content: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Periodically writes the shadow state file: a compact JSON document containing the current
+/// state of the gateway, such that it can be inspected without access to the API.
+pub fn shadow_loop<F>(conf: &Configuration, status: F, stop_receive: Receiver<Signal>)
+where
+    F: Fn() -> Status,
+{
+    info!(
+        "Starting shadow state loop, path: {}, interval: {:?}",
+        conf.path, conf.interval
+    );
+
+    loop {
+        if let Err(err) = write(conf, &status()) {
+            error!("Write shadow state file error, error: {}", err);
+        }
+
+        // Instead of an 'interval' sleep, we receive from the stop channel with a timeout
+        // equal to the 'interval'.
+        if let Ok(v) = stop_receive.recv_timeout(conf.interval) {
+            debug!("Received stop signal, signal: {}", v);
+            break;
+        }
+    }
+
+    debug!("Shadow state loop ended");
+}
+
+fn write(conf: &Configuration, status: &Status) -> Result<()> {
+    let now = SystemTime::now();
+    let last_stats = stats::get_last();
+    let last_uplink = stats::get_last_uplink();
+
+    let doc = json!({
+        "time": unix_secs(now),
+        "gateway_id": hex::encode(state::get_gateway_id()),
+        "state": state::get().to_string(),
+        "config_hash": conf.config_hash,
+        "config_version": conf.config_version,
+        "last_uplink": last_uplink.map(unix_secs),
+        "last_uplink_age_secs": last_uplink
+            .and_then(|v| now.duration_since(v).ok())
+            .map(|v| v.as_secs()),
+        "gps": status.gps.as_ref().map(|v| json!({
+            "time_valid": v.time_valid,
+            "location": v.location.map(|(lat, lon, alt)| json!({
+                "latitude": lat,
+                "longitude": lon,
+                "altitude": alt,
+            })),
+        })),
+        "queue": {
+            "depth": status.queue_depth,
+            "capacity": status.queue_capacity,
+        },
+        "stats": last_stats.map(|v| json!({
+            "rx_received": v.rx_packets_received,
+            "rx_received_ok": v.rx_packets_received_ok,
+            "tx_received": v.tx_packets_received,
+            "tx_emitted": v.tx_packets_emitted,
+            "meta_data": v.meta_data,
+        })),
+    });
+
+    // Write to a temporary file first, so that readers never see a partially written file.
+    let tmp_path = format!("{}.tmp", conf.path);
+    fs::write(&tmp_path, serde_json::to_vec(&doc)?)?;
+    fs::rename(&tmp_path, &conf.path)?;
+
+    Ok(())
+}
+
+/// Returns the status snapshot, as returned on the status command. The temperature is read
+/// from the concentrator_temp meta-data of the last stats, the reset status from the reset
+/// module.
+pub fn snapshot(status: &Status) -> api::Status {
+    let temperature = stats::get_last()
+        .and_then(|v| v.meta_data.get("concentrator_temp").cloned())
+        .and_then(|v| v.parse().ok());
+
+    api::Status {
+        gateway_id: hex::encode(state::get_gateway_id()),
+        uptime_secs: state::uptime().as_secs(),
+        state: state::get().to_string(),
+        last_uplink: stats::get_last_uplink().map(|v| {
+            let v = v.duration_since(UNIX_EPOCH).unwrap_or_default();
+            pbjson_types::Timestamp {
+                seconds: v.as_secs() as i64,
+                nanos: v.subsec_nanos() as i32,
+            }
+        }),
+        gnss: status.gps.as_ref().map(|v| api::GnssStatus {
+            time_valid: v.time_valid,
+            location: v
+                .location
+                .map(|(lat, lon, alt)| chirpstack_api::common::Location {
+                    latitude: lat,
+                    longitude: lon,
+                    altitude: alt,
+                    source: chirpstack_api::common::LocationSource::Gps as i32,
+                    ..Default::default()
+                }),
+        }),
+        queue_depth: status.queue_depth as u32,
+        queue_capacity: status.queue_capacity as u32,
+        temperature,
+        errors: logging::get_errors(),
+        reset: Some(reset::get_state().to_proto()),
+    }
+}
+
+fn unix_secs(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_hash() {
+        assert_eq!(config_hash("a = 1"), config_hash("a = 1"));
+        assert_ne!(config_hash("a = 1"), config_hash("a = 2"));
+        assert_eq!(16, config_hash("").len());
+    }
+}
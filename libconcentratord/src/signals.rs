@@ -5,6 +5,10 @@ use std::sync::mpsc::{channel, Receiver, Sender};
 pub enum Signal {
     Stop,
     Configuration(chirpstack_api::gw::GatewayConfiguration),
+    // Re-read the configuration files (SIGHUP or reload command).
+    Reload,
+    // Re-initialize the concentrator after a failed health check (see health).
+    Restart,
 }
 
 impl fmt::Display for Signal {
@@ -12,6 +16,8 @@ impl fmt::Display for Signal {
         match *self {
             Signal::Stop => write!(f, "Stop"),
             Signal::Configuration(_) => write!(f, "Configuration"),
+            Signal::Reload => write!(f, "Reload"),
+            Signal::Restart => write!(f, "Restart"),
         }
     }
 }
@@ -0,0 +1,187 @@
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+use anyhow::Result;
+use chirpstack_api::gw;
+use log::{debug, error, info, warn};
+
+use super::signals::Signal;
+use super::{stats, txtest};
+
+#[derive(Clone)]
+pub struct Configuration {
+    // Interval between two downlinks.
+    pub interval: Duration,
+    // Payload size of the downlinks.
+    pub payload_size: usize,
+    // TX power (EIRP) of the downlinks.
+    pub tx_power: i32,
+    // Frequencies of the downlinks, these are iterated round-robin.
+    pub frequencies: Vec<u32>,
+    // LoRa modulation parameters.
+    pub bandwidth: u32,
+    pub spreading_factor: u32,
+}
+
+/// Validates the soak test configuration.
+pub fn validate(conf: &Configuration) -> Result<()> {
+    if conf.interval < txtest::MIN_INTERVAL {
+        return Err(anyhow!(
+            "interval must be at least {:?}",
+            txtest::MIN_INTERVAL
+        ));
+    }
+
+    if conf.payload_size > 255 {
+        return Err(anyhow!("payload_size must not exceed 255 bytes"));
+    }
+
+    if conf.frequencies.is_empty() {
+        return Err(anyhow!("no frequencies configured"));
+    }
+
+    Ok(())
+}
+
+/// Periodically generates downlinks, which are handled by the given function as if these were
+/// received by the downlink command, for thermal / soak testing of the hardware. The results
+/// are reported as part of the stats meta-data.
+pub fn soak_loop<F>(
+    conf: &Configuration,
+    gateway_id: &[u8],
+    handle_downlink: F,
+    stop_receive: Receiver<Signal>,
+) where
+    F: Fn(&gw::DownlinkFrame) -> Result<gw::DownlinkTxAck>,
+{
+    warn!(
+        "Starting downlink soak test loop, interval: {:?}, payload_size: {}, tx_power: {}, frequencies: {:?}",
+        conf.interval, conf.payload_size, conf.tx_power, conf.frequencies
+    );
+
+    let mut seq: u32 = 0;
+
+    loop {
+        // Instead of an 'interval' sleep, we receive from the stop channel with a timeout
+        // equal to the 'interval'.
+        if let Ok(v) = stop_receive.recv_timeout(conf.interval) {
+            debug!("Received stop signal, signal: {}", v);
+            break;
+        }
+
+        let frame = get_frame(conf, gateway_id, seq);
+        seq = seq.wrapping_add(1);
+
+        let status = match handle_downlink(&frame) {
+            Ok(v) => v
+                .items
+                .first()
+                .map(|v| v.status())
+                .unwrap_or(gw::TxAckStatus::InternalError),
+            Err(err) => {
+                error!("Handle soak test downlink error, error: {}", err);
+                gw::TxAckStatus::InternalError
+            }
+        };
+
+        if status == gw::TxAckStatus::Ok {
+            stats::inc_counter("soak_test_enqueued");
+        } else {
+            warn!(
+                "Soak test downlink rejected, downlink_id: {}, status: {:?}",
+                frame.downlink_id, status
+            );
+            stats::inc_counter("soak_test_rejected");
+        }
+        stats::set_metadata("soak_test_total", &seq.to_string());
+    }
+
+    info!("Downlink soak test loop ended, total: {}", seq);
+}
+
+fn get_frame(conf: &Configuration, gateway_id: &[u8], seq: u32) -> gw::DownlinkFrame {
+    let frequency = conf.frequencies[seq as usize % conf.frequencies.len()];
+
+    // The payload starts with the sequence number, such that the downlinks can be identified
+    // by a receiver.
+    let mut phy_payload: Vec<u8> = seq.to_be_bytes().to_vec();
+    phy_payload.resize(conf.payload_size, 0);
+
+    gw::DownlinkFrame {
+        downlink_id: seq,
+        gateway_id: hex::encode(gateway_id),
+        items: vec![gw::DownlinkFrameItem {
+            phy_payload,
+            tx_info: Some(gw::DownlinkTxInfo {
+                frequency,
+                power: conf.tx_power,
+                modulation: Some(gw::Modulation {
+                    parameters: Some(gw::modulation::Parameters::Lora(gw::LoraModulationInfo {
+                        bandwidth: conf.bandwidth,
+                        spreading_factor: conf.spreading_factor,
+                        code_rate: gw::CodeRate::Cr45.into(),
+                        polarization_inversion: true,
+                        ..Default::default()
+                    })),
+                }),
+                timing: Some(gw::Timing {
+                    parameters: Some(gw::timing::Parameters::Immediately(
+                        gw::ImmediatelyTimingInfo {},
+                    )),
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }],
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_configuration() -> Configuration {
+        Configuration {
+            interval: Duration::from_secs(5),
+            payload_size: 8,
+            tx_power: 14,
+            frequencies: vec![868100000, 868300000],
+            bandwidth: 125000,
+            spreading_factor: 7,
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&get_configuration()).is_ok());
+
+        let mut conf = get_configuration();
+        conf.interval = Duration::from_millis(100);
+        assert!(validate(&conf).is_err());
+
+        let mut conf = get_configuration();
+        conf.payload_size = 256;
+        assert!(validate(&conf).is_err());
+
+        let mut conf = get_configuration();
+        conf.frequencies = Vec::new();
+        assert!(validate(&conf).is_err());
+    }
+
+    #[test]
+    fn test_get_frame() {
+        let conf = get_configuration();
+
+        let frame = get_frame(&conf, &[1, 2, 3, 4, 5, 6, 7, 8], 3);
+        assert_eq!(3, frame.downlink_id);
+        assert_eq!("0102030405060708", frame.gateway_id);
+
+        let item = &frame.items[0];
+        assert_eq!(vec![0, 0, 0, 3, 0, 0, 0, 0], item.phy_payload);
+
+        let tx_info = item.tx_info.as_ref().unwrap();
+        assert_eq!(868300000, tx_info.frequency);
+        assert_eq!(14, tx_info.power);
+    }
+}
@@ -0,0 +1,191 @@
+use std::fmt;
+use std::process;
+use std::sync::Mutex;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use log::{error, info};
+
+use super::api;
+use super::events;
+use super::reset;
+
+// Time to wait after publishing the last events, before exiting the process, so that the
+// subscribers have the chance to receive them.
+const EVENT_LINGER: Duration = Duration::from_millis(500);
+
+lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State::Initializing);
+    static ref GATEWAY_ID: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+    static ref STARTED: Instant = Instant::now();
+}
+
+/// Concentratord state.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum State {
+    // The concentrator is being configured.
+    Initializing,
+    // The concentrator is being started (which includes the radio calibration).
+    Calibrating,
+    // The concentrator is running.
+    Running,
+    // The concentrator is running, but with reduced functionality (e.g. GPS time lost).
+    Degraded,
+    // The concentrator is being stopped, e.g. to apply a configuration update.
+    Resetting,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            State::Initializing => write!(f, "INITIALIZING"),
+            State::Calibrating => write!(f, "CALIBRATING"),
+            State::Running => write!(f, "RUNNING"),
+            State::Degraded => write!(f, "DEGRADED"),
+            State::Resetting => write!(f, "RESETTING"),
+        }
+    }
+}
+
+/// Class of a concentrator initialization failure.
+///
+/// Each class exits the process with a distinct exit code, so that supervisors can apply a
+/// different restart policy per class.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Failure {
+    // The concentrator reset failed.
+    Reset,
+    // Configuring the concentrator failed.
+    Configuration,
+    // Starting the concentrator failed.
+    Start,
+    // Reading the gateway ID from the concentrator failed.
+    GatewayId,
+    // The gateway ID does not match the provisioned gateway ID.
+    GatewayIdMismatch,
+}
+
+impl Failure {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Failure::Reset => 3,
+            Failure::Configuration => 4,
+            Failure::Start => 5,
+            Failure::GatewayId => 6,
+            Failure::GatewayIdMismatch => 7,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Failure::Reset => write!(f, "RESET_FAILED"),
+            Failure::Configuration => write!(f, "CONFIGURATION_FAILED"),
+            Failure::Start => write!(f, "START_FAILED"),
+            Failure::GatewayId => write!(f, "GATEWAY_ID_FAILED"),
+            Failure::GatewayIdMismatch => write!(f, "GATEWAY_ID_MISMATCH"),
+        }
+    }
+}
+
+/// Set the gateway ID that is included in the state events.
+pub fn set_gateway_id(gateway_id: &[u8]) {
+    let mut gw_id = GATEWAY_ID.lock().unwrap();
+    *gw_id = gateway_id.to_vec();
+}
+
+pub(crate) fn get_gateway_id() -> Vec<u8> {
+    GATEWAY_ID.lock().unwrap().clone()
+}
+
+/// Returns the time since Concentratord was started.
+pub fn uptime() -> Duration {
+    STARTED.elapsed()
+}
+
+/// Returns the current state.
+pub fn get() -> State {
+    *STATE.lock().unwrap()
+}
+
+/// Set the state and publish the state transition.
+pub fn set(state: State, reason: &str) {
+    let mut current = STATE.lock().unwrap();
+    transition(&mut current, state, reason);
+}
+
+/// Set the state to Degraded. This is ignored when the state is not Running.
+pub fn degrade(reason: &str) {
+    let mut current = STATE.lock().unwrap();
+    if *current == State::Running {
+        transition(&mut current, State::Degraded, reason);
+    }
+}
+
+/// Set the state back to Running. This is ignored when the state is not Degraded.
+pub fn recover(reason: &str) {
+    let mut current = STATE.lock().unwrap();
+    if *current == State::Degraded {
+        transition(&mut current, State::Running, reason);
+    }
+}
+
+/// Publish the error event and exit the process with the exit code of the failure class.
+pub fn fail(failure: Failure, err: &anyhow::Error) -> ! {
+    error!(
+        "Concentrator initialization failed, reason: {}, exit_code: {}, error: {}",
+        failure,
+        failure.exit_code(),
+        err
+    );
+
+    let pl = api::ErrorEvent {
+        gateway_id: hex::encode(&*GATEWAY_ID.lock().unwrap()),
+        reason: failure.to_string(),
+        exit_code: failure.exit_code(),
+        error: err.to_string(),
+    };
+
+    if let Err(err) = events::send_error(&pl) {
+        error!("Publish error event error, error: {}", err);
+    } else {
+        sleep(EVENT_LINGER);
+    }
+
+    process::exit(failure.exit_code());
+}
+
+/// Power down the concentrator and exit the process. This must be called after the
+/// concentrator has been stopped (which also stops the JIT queue).
+pub fn shutdown() -> ! {
+    info!("Shutting down Concentratord");
+
+    if let Err(err) = reset::power_down() {
+        error!("Power down concentrator error, error: {}", err);
+    }
+
+    // Flush the pending events (e.g. the last state event).
+    sleep(EVENT_LINGER);
+
+    process::exit(0);
+}
+
+fn transition(current: &mut State, state: State, reason: &str) {
+    info!(
+        "Concentratord state transition, state: {}, previous_state: {}, reason: {}",
+        state, current, reason
+    );
+
+    let pl = api::StateEvent {
+        gateway_id: hex::encode(&*GATEWAY_ID.lock().unwrap()),
+        state: state.to_string(),
+        previous_state: current.to_string(),
+        reason: reason.to_string(),
+    };
+    *current = state;
+
+    if let Err(err) = events::send_state(&pl) {
+        error!("Publish state event error, error: {}", err);
+    }
+}
@@ -3,14 +3,51 @@ use std::sync::Mutex;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use anyhow::Result;
+use log::error;
 
+use super::blacklist;
+use super::dutycycle;
 use super::events;
+use super::feedback;
+use super::joinflood;
+use super::latency;
+use super::metadata;
+use super::metrics;
+use super::storage;
+use super::timesource;
+
+// Storage key of the lifetime counters.
+const LIFETIME_COUNTERS_KEY: &str = "lifetime_counters";
 
 lazy_static! {
     static ref STATS: Mutex<chirpstack_api::gw::GatewayStats> = Mutex::new(Default::default());
+    static ref COUNTERS: Mutex<HashMap<String, u64>> = Mutex::new(HashMap::new());
+    static ref SAMPLES: Mutex<HashMap<String, Vec<f64>>> = Mutex::new(HashMap::new());
+    static ref METADATA: Mutex<HashMap<String, String>> = Mutex::new(HashMap::new());
+    // Received signal quality per frequency: count, RSSI sum and SNR sum.
+    static ref RX_SIGNAL: Mutex<HashMap<u32, (u64, f64, f64)>> = Mutex::new(HashMap::new());
+    static ref LAST_STATS: Mutex<Option<chirpstack_api::gw::GatewayStats>> = Mutex::new(None);
+    static ref LAST_UPLINK: Mutex<Option<SystemTime>> = Mutex::new(None);
 }
 
 pub fn inc_rx_counts(pl: &chirpstack_api::gw::UplinkFrame) {
+    *LAST_UPLINK.lock().unwrap() = Some(SystemTime::now());
+    feedback::uplink_received(pl);
+
+    if let Some(tx_info) = &pl.tx_info {
+        if let Some(sf) = get_spreading_factor(&tx_info.modulation) {
+            inc_counter(&format!("rx_sf{}", sf));
+        }
+
+        if let Some(rx_info) = &pl.rx_info {
+            let mut rx_signal = RX_SIGNAL.lock().unwrap();
+            let v = rx_signal.entry(tx_info.frequency).or_insert((0, 0.0, 0.0));
+            v.0 += 1;
+            v.1 += rx_info.rssi as f64;
+            v.2 += rx_info.snr as f64;
+        }
+    }
+
     let mut stats = STATS.lock().unwrap();
     stats.rx_packets_received_ok += 1;
 
@@ -41,6 +78,12 @@ pub fn inc_rx_counts(pl: &chirpstack_api::gw::UplinkFrame) {
 }
 
 pub fn inc_tx_counts(tx_info: &chirpstack_api::gw::DownlinkTxInfo) {
+    metrics::inc_counter("tx_emitted_total", &[]);
+
+    if let Some(sf) = get_spreading_factor(&tx_info.modulation) {
+        inc_counter(&format!("tx_sf{}", sf));
+    }
+
     let mut stats = STATS.lock().unwrap();
     stats.tx_packets_emitted += 1;
 
@@ -69,20 +112,9 @@ pub fn inc_tx_counts(tx_info: &chirpstack_api::gw::DownlinkTxInfo) {
 }
 
 pub fn inc_tx_status_count(status: chirpstack_api::gw::TxAckStatus) {
-    let s = match status {
-        chirpstack_api::gw::TxAckStatus::Ignored => "IGNORED",
-        chirpstack_api::gw::TxAckStatus::Ok => "OK",
-        chirpstack_api::gw::TxAckStatus::TooLate => "TOO_LATE",
-        chirpstack_api::gw::TxAckStatus::TooEarly => "TOO_EARLY",
-        chirpstack_api::gw::TxAckStatus::CollisionPacket => "COLLISION_PACKET",
-        chirpstack_api::gw::TxAckStatus::CollisionBeacon => "COLLISION_BEACON",
-        chirpstack_api::gw::TxAckStatus::TxFreq => "TX_FREQ",
-        chirpstack_api::gw::TxAckStatus::TxPower => "TX_POWER",
-        chirpstack_api::gw::TxAckStatus::GpsUnlocked => "GPS_UNLOCKED",
-        chirpstack_api::gw::TxAckStatus::QueueFull => "QUEUE_FULL",
-        chirpstack_api::gw::TxAckStatus::InternalError => "InternalError",
-    }
-    .to_string();
+    let s = get_tx_status_str(status).to_string();
+
+    metrics::inc_counter("tx_packets_total", &[("status", &s)]);
 
     let mut stats = STATS.lock().unwrap();
     stats
@@ -92,6 +124,18 @@ pub fn inc_tx_status_count(status: chirpstack_api::gw::TxAckStatus) {
         .or_insert(1);
 }
 
+/// Increment the tx rejection counters (tx_rejected_STATUS) for each item of the ack that
+/// was rejected. Unlike the tx status count, which only counts the status of the downlink,
+/// this counts the rejection of every item that was tried.
+pub fn inc_tx_rejection_counts(tx_ack: &chirpstack_api::gw::DownlinkTxAck) {
+    for item in &tx_ack.items {
+        match item.status() {
+            chirpstack_api::gw::TxAckStatus::Ok | chirpstack_api::gw::TxAckStatus::Ignored => {}
+            status => inc_counter(&format!("tx_rejected_{}", get_tx_status_str(status))),
+        }
+    }
+}
+
 pub fn inc_rx_packets_received() {
     let mut stats = STATS.lock().unwrap();
     stats.rx_packets_received += 1;
@@ -102,14 +146,68 @@ pub fn inc_tx_packets_received() {
     stats.tx_packets_received += 1;
 }
 
+/// Increment a concentratord specific counter. Counters are reported as part of the
+/// stats meta-data and are reset after each stats interval.
+pub fn inc_counter(name: &str) {
+    let mut counters = COUNTERS.lock().unwrap();
+    counters
+        .entry(name.to_string())
+        .and_modify(|v| *v += 1)
+        .or_insert(1);
+}
+
+/// Add the given value to a concentratord specific counter, see inc_counter.
+pub fn add_counter(name: &str, value: u64) {
+    let mut counters = COUNTERS.lock().unwrap();
+    counters
+        .entry(name.to_string())
+        .and_modify(|v| *v += value)
+        .or_insert(value);
+}
+
+/// Add a sample to a concentratord specific distribution. For each distribution, the count,
+/// min, max and average are reported as part of the stats meta-data. Samples are reset after
+/// each stats interval.
+pub fn add_sample(name: &str, value: f64) {
+    let mut samples = SAMPLES.lock().unwrap();
+    samples
+        .entry(name.to_string())
+        .or_insert_with(Vec::new)
+        .push(value);
+}
+
+/// Set a concentratord specific meta-data value. Unlike counters and samples, meta-data values
+/// are not reset after each stats interval.
+pub fn set_metadata(name: &str, value: &str) {
+    let mut metadata = METADATA.lock().unwrap();
+    metadata.insert(name.to_string(), value.to_string());
+}
+
+/// Returns the last published stats.
+pub fn get_last() -> Option<chirpstack_api::gw::GatewayStats> {
+    LAST_STATS.lock().unwrap().clone()
+}
+
+/// Returns the time of the last received uplink.
+pub fn get_last_uplink() -> Option<SystemTime> {
+    *LAST_UPLINK.lock().unwrap()
+}
+
 pub fn send_and_reset(
     gateway_id: &[u8],
     location: Option<chirpstack_api::common::Location>,
     metadata: &HashMap<String, String>,
 ) -> Result<()> {
-    let mut stats = STATS.lock().unwrap();
+    // The time is read before taking the lock, as a GPS-disciplined time source needs to lock
+    // the GPS time reference.
+    let now_since_unix = timesource::now().duration_since(UNIX_EPOCH).unwrap();
 
-    let now_since_unix = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    // The meta-data commands and the latency probe are executed before taking the lock, as these
+    // can take some time.
+    let command_metadata = metadata::collect();
+    let latency_metadata = latency::get_metadata();
+
+    let mut stats = STATS.lock().unwrap();
 
     stats.gateway_id = hex::encode(gateway_id);
     stats.time = Some(pbjson_types::Timestamp {
@@ -119,10 +217,122 @@ pub fn send_and_reset(
     stats.location = location;
     stats.meta_data = metadata.clone();
 
+    for (k, v) in METADATA.lock().unwrap().iter() {
+        stats.meta_data.insert(k.clone(), v.clone());
+    }
+    stats.meta_data.extend(command_metadata);
+    stats.meta_data.extend(latency_metadata);
+    stats.meta_data.extend(dutycycle::get_metadata());
+    stats.meta_data.extend(joinflood::get_metadata());
+    stats.meta_data.extend(blacklist::get_metadata());
+
+    let mut rx_signal = RX_SIGNAL.lock().unwrap();
+    for (frequency, (count, rssi, snr)) in rx_signal.iter() {
+        stats.meta_data.insert(
+            format!("rx_rssi_avg_{}", frequency),
+            format!("{:.2}", rssi / *count as f64),
+        );
+        stats.meta_data.insert(
+            format!("rx_snr_avg_{}", frequency),
+            format!("{:.2}", snr / *count as f64),
+        );
+    }
+    rx_signal.clear();
+
+    let mut counters = COUNTERS.lock().unwrap();
+    for (k, v) in counters.iter() {
+        stats.meta_data.insert(k.clone(), v.to_string());
+    }
+    counters.clear();
+
+    let mut samples = SAMPLES.lock().unwrap();
+    for (k, v) in samples.iter() {
+        if v.is_empty() {
+            continue;
+        }
+
+        let min = v.iter().cloned().fold(f64::INFINITY, f64::min);
+        let max = v.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        let avg = v.iter().sum::<f64>() / v.len() as f64;
+
+        stats
+            .meta_data
+            .insert(format!("{}_count", k), v.len().to_string());
+        stats
+            .meta_data
+            .insert(format!("{}_min", k), format!("{:.2}", min));
+        stats
+            .meta_data
+            .insert(format!("{}_max", k), format!("{:.2}", max));
+        stats
+            .meta_data
+            .insert(format!("{}_avg", k), format!("{:.2}", avg));
+    }
+    samples.clear();
+
+    if storage::is_enabled() {
+        match update_lifetime_counters(&stats) {
+            Ok(v) => stats.meta_data.extend(v),
+            Err(err) => error!("Update lifetime counters error, error: {}", err),
+        }
+    }
+
     events::send_stats(&stats).unwrap();
+    *LAST_STATS.lock().unwrap() = Some(stats.clone());
 
     // reset stats
     *stats = Default::default();
 
     Ok(())
 }
+
+fn get_tx_status_str(status: chirpstack_api::gw::TxAckStatus) -> &'static str {
+    match status {
+        chirpstack_api::gw::TxAckStatus::Ignored => "IGNORED",
+        chirpstack_api::gw::TxAckStatus::Ok => "OK",
+        chirpstack_api::gw::TxAckStatus::TooLate => "TOO_LATE",
+        chirpstack_api::gw::TxAckStatus::TooEarly => "TOO_EARLY",
+        chirpstack_api::gw::TxAckStatus::CollisionPacket => "COLLISION_PACKET",
+        chirpstack_api::gw::TxAckStatus::CollisionBeacon => "COLLISION_BEACON",
+        chirpstack_api::gw::TxAckStatus::TxFreq => "TX_FREQ",
+        chirpstack_api::gw::TxAckStatus::TxPower => "TX_POWER",
+        chirpstack_api::gw::TxAckStatus::GpsUnlocked => "GPS_UNLOCKED",
+        chirpstack_api::gw::TxAckStatus::QueueFull => "QUEUE_FULL",
+        chirpstack_api::gw::TxAckStatus::InternalError => "InternalError",
+    }
+}
+
+// Returns the spreading-factor, or None when the modulation is not LoRa.
+fn get_spreading_factor(modulation: &Option<chirpstack_api::gw::Modulation>) -> Option<u32> {
+    match modulation.as_ref().and_then(|v| v.parameters.as_ref()) {
+        Some(chirpstack_api::gw::modulation::Parameters::Lora(v)) => Some(v.spreading_factor),
+        _ => None,
+    }
+}
+
+// Adds the counts of the given stats to the lifetime counters, which are persisted in the
+// storage, and returns these as stats meta-data.
+fn update_lifetime_counters(
+    stats: &chirpstack_api::gw::GatewayStats,
+) -> Result<HashMap<String, String>> {
+    let mut counters: HashMap<String, u64> = match storage::get(LIFETIME_COUNTERS_KEY)? {
+        Some(v) => serde_json::from_slice(&v)?,
+        None => HashMap::new(),
+    };
+
+    for (k, v) in [
+        ("rx_received", stats.rx_packets_received),
+        ("rx_received_ok", stats.rx_packets_received_ok),
+        ("tx_received", stats.tx_packets_received),
+        ("tx_emitted", stats.tx_packets_emitted),
+    ] {
+        *counters.entry(k.to_string()).or_insert(0) += v as u64;
+    }
+
+    storage::set(LIFETIME_COUNTERS_KEY, &serde_json::to_vec(&counters)?)?;
+
+    Ok(counters
+        .iter()
+        .map(|(k, v)| (format!("lifetime_{}", k), v.to_string()))
+        .collect())
+}
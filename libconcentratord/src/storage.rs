@@ -0,0 +1,314 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use log::info;
+
+lazy_static! {
+    static ref STORAGE: Mutex<Option<Box<dyn Backend>>> = Mutex::new(None);
+}
+
+/// Storage backend type.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BackendType {
+    // Each key is stored as a file within the storage directory.
+    File,
+    // The keys are stored in a SQLite database.
+    Sqlite,
+}
+
+impl fmt::Display for BackendType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BackendType::File => write!(f, "FILE"),
+            BackendType::Sqlite => write!(f, "SQLITE"),
+        }
+    }
+}
+
+impl FromStr for BackendType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "FILE" => BackendType::File,
+            "SQLITE" => BackendType::Sqlite,
+            _ => return Err(anyhow!("unexpected storage backend: {}", s)),
+        })
+    }
+}
+
+/// Defines when written data is flushed to the storage device.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FsyncPolicy {
+    // Flush on every write, this survives a power loss at the cost of flash wear.
+    Always,
+    // Leave flushing to the OS.
+    Never,
+}
+
+impl fmt::Display for FsyncPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FsyncPolicy::Always => write!(f, "ALWAYS"),
+            FsyncPolicy::Never => write!(f, "NEVER"),
+        }
+    }
+}
+
+impl FromStr for FsyncPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "" | "NEVER" => FsyncPolicy::Never,
+            "ALWAYS" => FsyncPolicy::Always,
+            _ => return Err(anyhow!("unexpected fsync policy: {}", s)),
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct Configuration {
+    pub backend: BackendType,
+    // Directory (FILE) or database file (SQLITE).
+    pub path: String,
+    pub fsync: FsyncPolicy,
+}
+
+/// Key-value storage backend, used to persist state across restarts.
+pub trait Backend: Send {
+    /// Returns the value of the given key, None when the key does not exist.
+    fn get(&mut self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Sets the value of the given key.
+    fn set(&mut self, key: &str, value: &[u8]) -> Result<()>;
+
+    /// Deletes the given key. Deleting a key that does not exist is not an error.
+    fn delete(&mut self, key: &str) -> Result<()>;
+}
+
+/// Flat-file backend, storing each key as a file within a directory.
+pub struct FileBackend {
+    dir: PathBuf,
+    fsync: FsyncPolicy,
+}
+
+impl FileBackend {
+    pub fn open(dir: &str, fsync: FsyncPolicy) -> Result<FileBackend> {
+        fs::create_dir_all(dir)?;
+
+        Ok(FileBackend {
+            dir: PathBuf::from(dir),
+            fsync,
+        })
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf> {
+        validate_key(key)?;
+        Ok(self.dir.join(key))
+    }
+}
+
+impl Backend for FileBackend {
+    fn get(&mut self, key: &str) -> Result<Option<Vec<u8>>> {
+        match fs::read(self.path(key)?) {
+            Ok(v) => Ok(Some(v)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &[u8]) -> Result<()> {
+        let path = self.path(key)?;
+
+        // Write to a temporary file first, so that a power loss never leaves a partially
+        // written value behind.
+        let tmp_path = path.with_extension("tmp");
+        let mut f = File::create(&tmp_path)?;
+        f.write_all(value)?;
+        if self.fsync == FsyncPolicy::Always {
+            f.sync_all()?;
+        }
+        fs::rename(&tmp_path, &path)?;
+
+        if self.fsync == FsyncPolicy::Always {
+            File::open(&self.dir)?.sync_all()?;
+        }
+
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &str) -> Result<()> {
+        match fs::remove_file(self.path(key)?) {
+            Ok(_) => Ok(()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// SQLite backend, storing the keys in a single table.
+#[cfg(feature = "sqlite")]
+pub struct SqliteBackend {
+    conn: rusqlite::Connection,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteBackend {
+    pub fn open(path: &str, fsync: FsyncPolicy) -> Result<SqliteBackend> {
+        let conn = rusqlite::Connection::open(path)?;
+        conn.pragma_update(
+            None,
+            "synchronous",
+            match fsync {
+                FsyncPolicy::Always => "FULL",
+                FsyncPolicy::Never => "OFF",
+            },
+        )?;
+        conn.execute(
+            "create table if not exists storage (key text primary key, value blob not null)",
+            [],
+        )?;
+
+        Ok(SqliteBackend { conn })
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Backend for SqliteBackend {
+    fn get(&mut self, key: &str) -> Result<Option<Vec<u8>>> {
+        use rusqlite::OptionalExtension;
+
+        Ok(self
+            .conn
+            .query_row("select value from storage where key = ?1", [key], |row| {
+                row.get(0)
+            })
+            .optional()?)
+    }
+
+    fn set(&mut self, key: &str, value: &[u8]) -> Result<()> {
+        validate_key(key)?;
+        self.conn.execute(
+            "insert or replace into storage (key, value) values (?1, ?2)",
+            rusqlite::params![key, value],
+        )?;
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &str) -> Result<()> {
+        self.conn
+            .execute("delete from storage where key = ?1", [key])?;
+        Ok(())
+    }
+}
+
+/// Setup the storage backend. When None, the storage is disabled and nothing is persisted.
+pub fn setup(conf: Option<&Configuration>) -> Result<()> {
+    let backend: Option<Box<dyn Backend>> = match conf {
+        Some(conf) => {
+            info!(
+                "Setting up storage, backend: {}, path: {}, fsync: {}",
+                conf.backend, conf.path, conf.fsync
+            );
+            Some(open(conf)?)
+        }
+        None => None,
+    };
+
+    *STORAGE.lock().unwrap() = backend;
+    Ok(())
+}
+
+/// Returns true when the storage is enabled.
+pub fn is_enabled() -> bool {
+    STORAGE.lock().unwrap().is_some()
+}
+
+/// Returns the value of the given key, None when the key does not exist or when the storage
+/// is disabled.
+pub fn get(key: &str) -> Result<Option<Vec<u8>>> {
+    match STORAGE.lock().unwrap().as_mut() {
+        Some(v) => v.get(key),
+        None => Ok(None),
+    }
+}
+
+/// Sets the value of the given key. This is a no-op when the storage is disabled.
+pub fn set(key: &str, value: &[u8]) -> Result<()> {
+    match STORAGE.lock().unwrap().as_mut() {
+        Some(v) => v.set(key, value),
+        None => Ok(()),
+    }
+}
+
+/// Deletes the given key. This is a no-op when the storage is disabled.
+pub fn delete(key: &str) -> Result<()> {
+    match STORAGE.lock().unwrap().as_mut() {
+        Some(v) => v.delete(key),
+        None => Ok(()),
+    }
+}
+
+fn open(conf: &Configuration) -> Result<Box<dyn Backend>> {
+    Ok(match conf.backend {
+        BackendType::File => Box::new(FileBackend::open(&conf.path, conf.fsync)?),
+        #[cfg(feature = "sqlite")]
+        BackendType::Sqlite => Box::new(SqliteBackend::open(&conf.path, conf.fsync)?),
+        #[cfg(not(feature = "sqlite"))]
+        BackendType::Sqlite => {
+            return Err(anyhow!(
+                "SQLITE storage backend requires the sqlite feature"
+            ))
+        }
+    })
+}
+
+// The keys are used as file names by the FILE backend.
+fn validate_key(key: &str) -> Result<()> {
+    if key.is_empty()
+        || !key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err(anyhow!("invalid storage key: {}", key));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_key() {
+        assert!(validate_key("lifetime_counters").is_ok());
+        assert!(validate_key("").is_err());
+        assert!(validate_key("../etc/passwd").is_err());
+        assert!(validate_key("a.tmp").is_err());
+    }
+
+    #[test]
+    fn test_file_backend() {
+        let dir =
+            std::env::temp_dir().join(format!("concentratord-storage-{}", std::process::id()));
+        let mut backend = FileBackend::open(dir.to_str().unwrap(), FsyncPolicy::Always).unwrap();
+
+        assert_eq!(None, backend.get("foo").unwrap());
+        backend.set("foo", b"bar").unwrap();
+        assert_eq!(Some(b"bar".to_vec()), backend.get("foo").unwrap());
+        backend.set("foo", b"baz").unwrap();
+        assert_eq!(Some(b"baz".to_vec()), backend.get("foo").unwrap());
+        backend.delete("foo").unwrap();
+        backend.delete("foo").unwrap();
+        assert_eq!(None, backend.get("foo").unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
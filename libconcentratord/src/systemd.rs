@@ -0,0 +1,149 @@
+use std::env;
+use std::os::unix::net::UnixDatagram;
+use std::process;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use log::{debug, error, info, warn};
+
+lazy_static! {
+    static ref NOTIFY: Mutex<Option<Notify>> = Mutex::new(None);
+}
+
+struct Notify {
+    socket: UnixDatagram,
+    path: String,
+    // Interval of the watchdog pings, None when the watchdog is not enabled for this process.
+    watchdog_interval: Option<Duration>,
+    last_watchdog: Option<Instant>,
+}
+
+/// Setup the systemd service notifications. The notification socket and watchdog timeout are
+/// read from the environment set by systemd (NOTIFY_SOCKET and WATCHDOG_USEC). When not
+/// started by systemd (or without Type=notify), the notifications are disabled.
+pub fn setup() -> Result<()> {
+    let path = match env::var("NOTIFY_SOCKET") {
+        Ok(v) if !v.is_empty() => v,
+        _ => {
+            warn!("NOTIFY_SOCKET is not set, systemd notifications are disabled");
+            return Ok(());
+        }
+    };
+
+    if path.starts_with('@') {
+        return Err(anyhow!(
+            "abstract notify socket is not supported, notify_socket: {}",
+            path
+        ));
+    }
+
+    let watchdog_interval = get_watchdog_interval(
+        env::var("WATCHDOG_USEC").ok().as_deref(),
+        env::var("WATCHDOG_PID").ok().as_deref(),
+        process::id(),
+    );
+
+    info!(
+        "Setting up systemd notifications, notify_socket: {}, watchdog_interval: {:?}",
+        path, watchdog_interval
+    );
+
+    *NOTIFY.lock().unwrap() = Some(Notify {
+        socket: UnixDatagram::unbound()?,
+        path,
+        watchdog_interval,
+        last_watchdog: None,
+    });
+
+    Ok(())
+}
+
+/// Notify systemd that Concentratord is ready. This must be called once the concentrator is
+/// started and the API sockets are bound.
+pub fn ready() {
+    let mut notify = NOTIFY.lock().unwrap();
+    if let Some(notify) = notify.as_mut() {
+        info!("Notifying systemd that Concentratord is ready");
+        notify.send("READY=1");
+    }
+}
+
+/// Ping the systemd watchdog. This is called from the uplink loop, such that a hanging
+/// concentrator results in a restart by systemd. The pings are rate-limited to half the
+/// watchdog timeout.
+pub fn watchdog() {
+    let mut notify = NOTIFY.lock().unwrap();
+    if let Some(notify) = notify.as_mut() {
+        let interval = match notify.watchdog_interval {
+            Some(v) => v,
+            None => return,
+        };
+
+        if let Some(last) = notify.last_watchdog {
+            if last.elapsed() < interval {
+                return;
+            }
+        }
+
+        debug!("Pinging systemd watchdog");
+        notify.send("WATCHDOG=1");
+        notify.last_watchdog = Some(Instant::now());
+    }
+}
+
+impl Notify {
+    fn send(&self, state: &str) {
+        if let Err(err) = self.socket.send_to(state.as_bytes(), &self.path) {
+            error!(
+                "Send systemd notification error, state: {}, error: {}",
+                state, err
+            );
+        }
+    }
+}
+
+// Returns the watchdog ping interval (half the watchdog timeout), or None when the watchdog is
+// not enabled or is enabled for a different process.
+fn get_watchdog_interval(usec: Option<&str>, pid: Option<&str>, own_pid: u32) -> Option<Duration> {
+    if let Some(pid) = pid {
+        if pid.parse::<u32>().ok()? != own_pid {
+            return None;
+        }
+    }
+
+    let usec: u64 = usec?.parse().ok()?;
+    if usec == 0 {
+        return None;
+    }
+
+    Some(Duration::from_micros(usec / 2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_watchdog_interval() {
+        assert_eq!(
+            Some(Duration::from_secs(15)),
+            get_watchdog_interval(Some("30000000"), None, 10)
+        );
+        assert_eq!(
+            Some(Duration::from_secs(15)),
+            get_watchdog_interval(Some("30000000"), Some("10"), 10)
+        );
+
+        // watchdog of a different process
+        assert_eq!(
+            None,
+            get_watchdog_interval(Some("30000000"), Some("11"), 10)
+        );
+
+        // watchdog not enabled
+        assert_eq!(None, get_watchdog_interval(None, None, 10));
+        assert_eq!(None, get_watchdog_interval(Some("0"), None, 10));
+        assert_eq!(None, get_watchdog_interval(Some("invalid"), None, 10));
+    }
+}
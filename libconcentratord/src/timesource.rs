@@ -0,0 +1,217 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::Result;
+
+lazy_static! {
+    static ref START: Instant = Instant::now();
+    static ref TIME_SOURCE: RwLock<Arc<dyn TimeSource>> = RwLock::new(Arc::new(SystemClock));
+}
+
+/// Source of the wall-clock and monotonic time.
+pub trait TimeSource: Send + Sync {
+    /// Returns the current (wall-clock) time.
+    fn now(&self) -> SystemTime;
+
+    /// Returns the monotonic time, since an arbitrary but fixed point in time.
+    fn monotonic(&self) -> Duration;
+}
+
+/// Host system clock.
+pub struct SystemClock;
+
+impl TimeSource for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn monotonic(&self) -> Duration {
+        START.elapsed()
+    }
+}
+
+/// GPS-disciplined clock. The wall-clock time is derived from the GPS time reference and falls
+/// back to the host system clock when there is no valid GPS time reference. The monotonic time
+/// is always provided by the host system clock.
+pub struct GpsClock {
+    gps_time: Box<dyn Fn() -> Result<SystemTime> + Send + Sync>,
+}
+
+impl GpsClock {
+    /// Create a new GPS clock. The given function must return the current time using the GPS
+    /// time reference, or an error when there is no valid GPS time reference.
+    pub fn new<F>(gps_time: F) -> GpsClock
+    where
+        F: Fn() -> Result<SystemTime> + Send + Sync + 'static,
+    {
+        GpsClock {
+            gps_time: Box::new(gps_time),
+        }
+    }
+}
+
+impl TimeSource for GpsClock {
+    fn now(&self) -> SystemTime {
+        (self.gps_time)().unwrap_or_else(|_| SystemTime::now())
+    }
+
+    fn monotonic(&self) -> Duration {
+        START.elapsed()
+    }
+}
+
+/// Clock which only advances when instructed to, for testing.
+pub struct MockClock {
+    now: Mutex<SystemTime>,
+    monotonic: Mutex<Duration>,
+}
+
+impl MockClock {
+    pub fn new(now: SystemTime) -> MockClock {
+        MockClock {
+            now: Mutex::new(now),
+            monotonic: Mutex::new(Duration::from_secs(0)),
+        }
+    }
+
+    /// Advance both the wall-clock and monotonic time.
+    pub fn advance(&self, d: Duration) {
+        *self.now.lock().unwrap() += d;
+        *self.monotonic.lock().unwrap() += d;
+    }
+
+    /// Set the wall-clock time, e.g. to simulate a clock step. The monotonic time is not
+    /// affected.
+    pub fn set(&self, now: SystemTime) {
+        *self.now.lock().unwrap() = now;
+    }
+}
+
+impl TimeSource for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().unwrap()
+    }
+
+    fn monotonic(&self) -> Duration {
+        *self.monotonic.lock().unwrap()
+    }
+}
+
+/// Source of the uplink time field.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UplinkTime {
+    // The GPS time, only when the GPS time reference is valid.
+    Gnss,
+    // The host system clock.
+    System,
+    // The time field is omitted.
+    None,
+}
+
+impl fmt::Display for UplinkTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UplinkTime::Gnss => write!(f, "GNSS"),
+            UplinkTime::System => write!(f, "SYSTEM"),
+            UplinkTime::None => write!(f, "NONE"),
+        }
+    }
+}
+
+impl FromStr for UplinkTime {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "" | "GNSS" => UplinkTime::Gnss,
+            "SYSTEM" => UplinkTime::System,
+            "NONE" => UplinkTime::None,
+            _ => return Err(anyhow!("unexpected uplink time source: {}", s)),
+        })
+    }
+}
+
+/// Defines when the uplink time_since_gps_epoch field is set.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UplinkGpsEpoch {
+    // Only when the GPS time reference is valid (PPS locked).
+    PpsLocked,
+    // Also when the GPS time reference is not valid, using the last known GPS time reference.
+    LastKnown,
+    // The time_since_gps_epoch field is omitted.
+    Never,
+}
+
+impl fmt::Display for UplinkGpsEpoch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UplinkGpsEpoch::PpsLocked => write!(f, "PPS_LOCKED"),
+            UplinkGpsEpoch::LastKnown => write!(f, "LAST_KNOWN"),
+            UplinkGpsEpoch::Never => write!(f, "NEVER"),
+        }
+    }
+}
+
+impl FromStr for UplinkGpsEpoch {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "" | "PPS_LOCKED" => UplinkGpsEpoch::PpsLocked,
+            "LAST_KNOWN" => UplinkGpsEpoch::LastKnown,
+            "NEVER" => UplinkGpsEpoch::Never,
+            _ => return Err(anyhow!("unexpected uplink gps epoch policy: {}", s)),
+        })
+    }
+}
+
+/// Set the time source used by the Concentratord modules. By default this is the SystemClock.
+pub fn set(source: Arc<dyn TimeSource>) {
+    let mut time_source = TIME_SOURCE.write().unwrap();
+    *time_source = source;
+}
+
+/// Returns the time source used by the Concentratord modules.
+pub fn get() -> Arc<dyn TimeSource> {
+    TIME_SOURCE.read().unwrap().clone()
+}
+
+/// Returns the current time of the time source.
+pub fn now() -> SystemTime {
+    get().now()
+}
+
+/// Returns the monotonic time of the time source.
+pub fn monotonic() -> Duration {
+    get().monotonic()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn test_gps_clock() {
+        let gps = GpsClock::new(|| Ok(UNIX_EPOCH + Duration::from_secs(10)));
+        assert_eq!(UNIX_EPOCH + Duration::from_secs(10), gps.now());
+
+        // falls back to the system clock
+        let gps = GpsClock::new(|| Err(anyhow!("gps_ref_valid = false")));
+        assert!(gps.now() > UNIX_EPOCH + Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_mock_clock() {
+        let clock = MockClock::new(UNIX_EPOCH);
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(UNIX_EPOCH + Duration::from_secs(5), clock.now());
+        assert_eq!(Duration::from_secs(5), clock.monotonic());
+
+        clock.set(UNIX_EPOCH);
+        assert_eq!(UNIX_EPOCH, clock.now());
+        assert_eq!(Duration::from_secs(5), clock.monotonic());
+    }
+}
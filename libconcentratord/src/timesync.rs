@@ -0,0 +1,230 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use log::{debug, error, info};
+
+use super::signals::Signal;
+use super::timesource::{self, TimeSource};
+use super::{api, events, stats};
+
+lazy_static! {
+    static ref MAX_RTT: Mutex<Duration> = Mutex::new(Duration::from_secs(2));
+    static ref PENDING: Mutex<Option<Request>> = Mutex::new(None);
+    // Offset (ns) between the network server time and the host system clock.
+    static ref OFFSET: Mutex<Option<i64>> = Mutex::new(None);
+}
+
+static REQUEST_ID: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Clone)]
+pub struct Configuration {
+    // Interval between two time-sync requests.
+    pub interval: Duration,
+    // Max. round-trip time of an answer, answers with a larger round-trip time are rejected as
+    // the accuracy of the offset is half the round-trip time.
+    pub max_rtt: Duration,
+}
+
+// Last published time-sync request.
+struct Request {
+    request_id: u32,
+    host_time: SystemTime,
+    monotonic: Duration,
+}
+
+/// Host system clock, corrected using the offset obtained from the network server. Before the
+/// first answer has been received, this is the uncorrected host system clock.
+pub struct NetworkClock;
+
+impl TimeSource for NetworkClock {
+    fn now(&self) -> SystemTime {
+        correct(SystemTime::now())
+    }
+
+    fn monotonic(&self) -> Duration {
+        timesource::SystemClock.monotonic()
+    }
+}
+
+/// Periodically publishes a time-sync request event, containing the concentrator counter and
+/// the host system clock. The network server answers this request using the timesync command,
+/// see handle_answer.
+pub fn request_loop<F>(
+    conf: &Configuration,
+    gateway_id: &[u8],
+    get_counter: F,
+    stop_receive: Receiver<Signal>,
+) where
+    F: Fn() -> Result<u32>,
+{
+    info!(
+        "Starting time-sync request loop, interval: {:?}, max_rtt: {:?}",
+        conf.interval, conf.max_rtt
+    );
+
+    *MAX_RTT.lock().unwrap() = conf.max_rtt;
+
+    loop {
+        if let Err(err) = send_request(gateway_id, &get_counter) {
+            error!("Send time-sync request error, error: {}", err);
+        }
+
+        // Instead of an 'interval' sleep, we receive from the stop channel with a timeout
+        // equal to the 'interval'.
+        if let Ok(v) = stop_receive.recv_timeout(conf.interval) {
+            debug!("Received stop signal, signal: {}", v);
+            break;
+        }
+    }
+
+    debug!("Time-sync request loop ended");
+}
+
+/// Handles the answer of the network server to the last time-sync request. The offset is
+/// reported as stats meta-data (timesync_offset_us), the round-trip time as stats sample
+/// (timesync_rtt_ms).
+pub fn handle_answer(pl: &api::TimeSyncAnswer) -> Result<()> {
+    let monotonic = timesource::monotonic();
+
+    let req = {
+        let mut pending = PENDING.lock().unwrap();
+        match pending.as_ref() {
+            Some(v) if v.request_id == pl.request_id => pending.take().unwrap(),
+            _ => {
+                return Err(anyhow!(
+                    "unknown or expired time-sync request, request_id: {}",
+                    pl.request_id
+                ))
+            }
+        }
+    };
+
+    let receive_time = to_system_time(pl.receive_time.as_ref())?;
+    let transmit_time = to_system_time(pl.transmit_time.as_ref())?;
+
+    // The monotonic time is used, as the host system clock might be stepped in between.
+    let host_receive_time = req.host_time + monotonic.saturating_sub(req.monotonic);
+
+    let (offset, rtt) = get_offset(
+        req.host_time,
+        receive_time,
+        transmit_time,
+        host_receive_time,
+    );
+
+    let max_rtt = *MAX_RTT.lock().unwrap();
+    if rtt > max_rtt {
+        return Err(anyhow!(
+            "round-trip time exceeds max_rtt, rtt: {:?}, max_rtt: {:?}",
+            rtt,
+            max_rtt
+        ));
+    }
+
+    info!(
+        "Time-sync answer received, request_id: {}, offset_us: {}, rtt: {:?}",
+        pl.request_id,
+        offset / 1000,
+        rtt
+    );
+
+    *OFFSET.lock().unwrap() = Some(offset);
+    stats::set_metadata("timesync_offset_us", &(offset / 1000).to_string());
+    stats::add_sample("timesync_rtt_ms", rtt.as_secs_f64() * 1000.0);
+
+    Ok(())
+}
+
+/// Corrects the given host system time using the offset obtained from the network server.
+/// When there is no offset, the given time is returned.
+pub fn correct(t: SystemTime) -> SystemTime {
+    match *OFFSET.lock().unwrap() {
+        Some(offset) if offset >= 0 => t + Duration::from_nanos(offset as u64),
+        Some(offset) => t - Duration::from_nanos(offset.unsigned_abs()),
+        None => t,
+    }
+}
+
+fn send_request<F>(gateway_id: &[u8], get_counter: &F) -> Result<()>
+where
+    F: Fn() -> Result<u32>,
+{
+    let count_us = get_counter()?;
+    let request_id = REQUEST_ID.fetch_add(1, Ordering::SeqCst) + 1;
+    let host_time = SystemTime::now();
+
+    // An answer to a previous request is no longer accepted.
+    *PENDING.lock().unwrap() = Some(Request {
+        request_id,
+        host_time,
+        monotonic: timesource::monotonic(),
+    });
+
+    let host_time = host_time.duration_since(UNIX_EPOCH)?;
+    events::send_timesync_request(&api::TimeSyncRequest {
+        gateway_id: hex::encode(gateway_id),
+        request_id,
+        count_us,
+        host_time: Some(pbjson_types::Timestamp {
+            seconds: host_time.as_secs() as i64,
+            nanos: host_time.subsec_nanos() as i32,
+        }),
+    })
+}
+
+// Returns the offset (ns) and the round-trip time, excluding the processing time of the
+// network server, given the host time of the request (t0), the network server receive (t1)
+// and transmit (t2) time and the host time of the answer (t3).
+fn get_offset(t0: SystemTime, t1: SystemTime, t2: SystemTime, t3: SystemTime) -> (i64, Duration) {
+    let offset = (diff_ns(t1, t0) + diff_ns(t2, t3)) / 2;
+    let rtt = diff_ns(t3, t0) - diff_ns(t2, t1);
+
+    (offset, Duration::from_nanos(rtt.max(0) as u64))
+}
+
+// Returns a - b in ns.
+fn diff_ns(a: SystemTime, b: SystemTime) -> i64 {
+    match a.duration_since(b) {
+        Ok(v) => v.as_nanos() as i64,
+        Err(err) => -(err.duration().as_nanos() as i64),
+    }
+}
+
+fn to_system_time(ts: Option<&pbjson_types::Timestamp>) -> Result<SystemTime> {
+    let ts = ts.ok_or_else(|| anyhow!("timestamp is not set"))?;
+    if ts.seconds < 0 || ts.nanos < 0 {
+        return Err(anyhow!("invalid timestamp"));
+    }
+
+    Ok(UNIX_EPOCH + Duration::new(ts.seconds as u64, ts.nanos as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_offset() {
+        let t0 = UNIX_EPOCH + Duration::from_secs(1000);
+
+        // network server is 10s ahead, 100ms one-way delay, 20ms processing time
+        let t1 = t0 + Duration::from_millis(10100);
+        let t2 = t1 + Duration::from_millis(20);
+        let t3 = t0 + Duration::from_millis(220);
+        assert_eq!(
+            (10_000_000_000, Duration::from_millis(200)),
+            get_offset(t0, t1, t2, t3)
+        );
+
+        // network server is 10s behind
+        let t1 = t0 - Duration::from_millis(9900);
+        let t2 = t1 + Duration::from_millis(20);
+        assert_eq!(
+            (-10_000_000_000, Duration::from_millis(200)),
+            get_offset(t0, t1, t2, t3)
+        );
+    }
+}
@@ -0,0 +1,108 @@
+/// Returns the TX power (dBm) that will actually be used by the HAL.
+///
+/// The HAL selects the highest TX gain table entry with a power less than or equal to the
+/// requested power. When the requested power is below the lowest entry, the first entry of
+/// the table is used. This replicates that selection so that the applied power can be
+/// reported back. None is returned when the gain table is empty.
+pub fn get_applied_power(gain_table: &[i8], requested: i8) -> Option<i8> {
+    if gain_table.is_empty() {
+        return None;
+    }
+
+    let mut index = gain_table.len() - 1;
+    while index > 0 {
+        if gain_table[index] <= requested {
+            break;
+        }
+        index -= 1;
+    }
+
+    Some(gain_table[index])
+}
+
+/// Returns true when the difference between the requested and applied TX power (dB) is
+/// greater than the given tolerance.
+pub fn exceeds_tolerance(requested: i8, applied: i8, tolerance: f32) -> bool {
+    (requested as f32 - applied as f32).abs() > tolerance
+}
+
+/// Returns the TX power correction (dB) for the given temperature (°C).
+///
+/// The table contains (temperature, correction) points, sorted by temperature. Between two
+/// points the correction is linearly interpolated, outside the table the correction of the
+/// first or last point is used. 0.0 is returned when the table is empty.
+pub fn get_temperature_correction(table: &[(f32, f32)], temperature: f32) -> f32 {
+    let (first, last) = match (table.first(), table.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return 0.0,
+    };
+
+    if temperature <= first.0 {
+        return first.1;
+    }
+    if temperature >= last.0 {
+        return last.1;
+    }
+
+    for w in table.windows(2) {
+        let ((t0, c0), (t1, c1)) = (w[0], w[1]);
+        if temperature <= t1 {
+            return c0 + (c1 - c0) * (temperature - t0) / (t1 - t0);
+        }
+    }
+
+    last.1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_applied_power() {
+        let gain_table = vec![12, 13, 14, 16, 20, 27];
+
+        let tests = vec![
+            ("exact match", 14, Some(14)),
+            ("rounded down", 15, Some(14)),
+            ("below table", 5, Some(12)),
+            ("above table", 30, Some(27)),
+        ];
+
+        for (_, requested, expected) in tests {
+            assert_eq!(expected, get_applied_power(&gain_table, requested));
+        }
+
+        assert_eq!(None, get_applied_power(&[], 14));
+    }
+
+    #[test]
+    fn test_exceeds_tolerance() {
+        assert!(!exceeds_tolerance(14, 14, 1.5));
+        assert!(!exceeds_tolerance(15, 14, 1.5));
+        assert!(exceeds_tolerance(19, 16, 1.5));
+        assert!(exceeds_tolerance(5, 12, 1.5));
+        assert!(exceeds_tolerance(15, 14, 0.0));
+    }
+
+    #[test]
+    fn test_get_temperature_correction() {
+        let table = vec![(-20.0, -1.0), (25.0, 0.0), (60.0, 1.5)];
+
+        let tests = vec![
+            ("below table", -40.0, -1.0),
+            ("first point", -20.0, -1.0),
+            ("interpolated", 2.5, -0.5),
+            ("exact point", 25.0, 0.0),
+            ("interpolated upper", 39.0, 0.6),
+            ("above table", 85.0, 1.5),
+        ];
+
+        for (name, temperature, expected) in tests {
+            let correction = get_temperature_correction(&table, temperature);
+            assert!((expected - correction).abs() < 0.001, "{}", name);
+        }
+
+        assert_eq!(0.0, get_temperature_correction(&[], 25.0));
+    }
+}
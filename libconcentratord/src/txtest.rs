@@ -0,0 +1,290 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+use anyhow::Result;
+use log::{debug, error, info, warn};
+
+use super::signals::Signal;
+use super::{api, events, timesource};
+
+// Min. interval between two test transmissions.
+pub const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+// Max. number of test transmissions per channel.
+const MAX_REPETITIONS: u32 = 100;
+
+// Max. payload size of the test transmissions.
+const MAX_PAYLOAD_SIZE: u32 = 255;
+
+// Interval at which the TX status and the stop requests are polled during a certification test.
+const CERTIFICATION_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// A certification test is running.
+static CERTIFICATION_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// TX test mode.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Mode {
+    // Short test transmissions across all the configured channels.
+    Sweep,
+    // Continuous-wave (unmodulated) carrier on a fixed frequency.
+    ContinuousWave,
+    // Back-to-back modulated transmissions on a fixed frequency.
+    Modulated,
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mode::Sweep => write!(f, "SWEEP"),
+            Mode::ContinuousWave => write!(f, "CW"),
+            Mode::Modulated => write!(f, "MODULATED"),
+        }
+    }
+}
+
+impl FromStr for Mode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "" | "SWEEP" => Mode::Sweep,
+            "CW" => Mode::ContinuousWave,
+            "MODULATED" => Mode::Modulated,
+            _ => return Err(anyhow!("unexpected tx test mode: {}", s)),
+        })
+    }
+}
+
+/// Transmitter of the certification test, implemented by the concentratord daemons.
+pub trait Transmitter {
+    /// Starts a transmission on the given frequency, this is called again once the previous
+    /// transmission has ended.
+    fn start(&self, mode: Mode, req: &api::TxTestRequest) -> Result<()>;
+
+    /// Returns true while the transmission is scheduled or emitting.
+    fn busy(&self) -> Result<bool>;
+
+    /// Aborts the ongoing transmission.
+    fn abort(&self) -> Result<()>;
+}
+
+/// Validates the TX test request.
+pub fn validate(req: &api::TxTestRequest) -> Result<()> {
+    if req.stop {
+        return Ok(());
+    }
+
+    match Mode::from_str(&req.mode)? {
+        Mode::Sweep => {}
+        Mode::ContinuousWave | Mode::Modulated => {
+            if req.frequency == 0 {
+                return Err(anyhow!("frequency must be set"));
+            }
+
+            if req.payload_size > MAX_PAYLOAD_SIZE {
+                return Err(anyhow!(
+                    "payload_size must not exceed {} bytes",
+                    MAX_PAYLOAD_SIZE
+                ));
+            }
+
+            return Ok(());
+        }
+    }
+
+    if req.repetitions == 0 || req.repetitions > MAX_REPETITIONS {
+        return Err(anyhow!(
+            "repetitions must be between 1 and {}",
+            MAX_REPETITIONS
+        ));
+    }
+
+    if Duration::from_millis(req.interval_ms.into()) < MIN_INTERVAL {
+        return Err(anyhow!(
+            "interval_ms must be at least {}",
+            MIN_INTERVAL.as_millis()
+        ));
+    }
+
+    if req.payload_size > MAX_PAYLOAD_SIZE {
+        return Err(anyhow!(
+            "payload_size must not exceed {} bytes",
+            MAX_PAYLOAD_SIZE
+        ));
+    }
+
+    Ok(())
+}
+
+/// Returns the frequencies of the test transmissions. The channels are iterated round-robin, so
+/// that consecutive test transmissions are spread over the channels.
+pub fn get_schedule(frequencies: &[u32], repetitions: u32) -> Vec<u32> {
+    let mut out = Vec::with_capacity(frequencies.len() * repetitions as usize);
+    for _ in 0..repetitions {
+        out.extend_from_slice(frequencies);
+    }
+    out
+}
+
+/// Returns true while a certification test is running, during which the normal uplink and
+/// downlink handling is suspended.
+pub fn certification_active() -> bool {
+    CERTIFICATION_ACTIVE.load(Ordering::SeqCst)
+}
+
+/// Runs the certification test (CW or modulated carrier) until the duration has elapsed, a
+/// stop request is received or a stop signal is received. Returns false when the test was
+/// aborted by a stop signal.
+pub fn run_certification<T: Transmitter>(
+    gateway_id: &[u8],
+    transmitter: &T,
+    req: &api::TxTestRequest,
+    request_receive: &Receiver<api::TxTestRequest>,
+    stop_receive: &Receiver<Signal>,
+) -> bool {
+    let mode = Mode::from_str(&req.mode).unwrap_or(Mode::Modulated);
+    let deadline = match req.duration_ms {
+        0 => None,
+        v => Some(timesource::monotonic() + Duration::from_millis(v.into())),
+    };
+
+    warn!(
+        "Starting certification TX test, suspending uplink and downlink handling, mode: {}, frequency: {}, tx_power: {}, duration_ms: {}",
+        mode, req.frequency, req.tx_power, req.duration_ms
+    );
+    CERTIFICATION_ACTIVE.store(true, Ordering::SeqCst);
+
+    let mut sent: u32 = 0;
+    let mut result: Result<()> = Ok(());
+    let mut stopped = false;
+
+    loop {
+        // Instead of a sleep, we receive from the stop channel with a timeout equal to the
+        // poll interval.
+        if let Ok(v) = stop_receive.recv_timeout(CERTIFICATION_POLL_INTERVAL) {
+            debug!("Received stop signal, signal: {}", v);
+            stopped = true;
+            break;
+        }
+
+        if let Ok(v) = request_receive.try_recv() {
+            if v.stop {
+                info!("Certification TX test stopped");
+                break;
+            }
+            warn!("Certification TX test is running, ignoring TX test request");
+        }
+
+        if let Some(deadline) = deadline {
+            if timesource::monotonic() >= deadline {
+                break;
+            }
+        }
+
+        result = match transmitter.busy() {
+            Ok(true) => continue,
+            Ok(false) => transmitter.start(mode, req),
+            Err(err) => Err(err),
+        };
+        if result.is_err() {
+            break;
+        }
+        sent += 1;
+    }
+
+    if let Err(err) = transmitter.abort() {
+        error!("Abort certification TX test error, error: {}", err);
+    }
+    CERTIFICATION_ACTIVE.store(false, Ordering::SeqCst);
+
+    let mut progress = api::TxTestProgress {
+        gateway_id: hex::encode(gateway_id),
+        frequency: req.frequency,
+        sent,
+        total: sent,
+        ..Default::default()
+    };
+    if let Err(err) = result {
+        error!("Certification TX test error, error: {}", err);
+        progress.error = err.to_string();
+    }
+    if let Err(err) = events::send_tx_test_progress(&progress) {
+        error!("Publish TX test progress error, error: {}", err);
+    }
+
+    info!(
+        "Certification TX test ended, resuming uplink and downlink handling, transmissions: {}",
+        sent
+    );
+
+    !stopped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let req = api::TxTestRequest {
+            tx_power: 14,
+            repetitions: 3,
+            interval_ms: 1000,
+            payload_size: 16,
+            ..Default::default()
+        };
+        assert!(validate(&req).is_ok());
+
+        assert!(validate(&api::TxTestRequest {
+            repetitions: 0,
+            ..req.clone()
+        })
+        .is_err());
+        assert!(validate(&api::TxTestRequest {
+            interval_ms: 100,
+            ..req.clone()
+        })
+        .is_err());
+        assert!(validate(&api::TxTestRequest {
+            payload_size: 256,
+            ..req.clone()
+        })
+        .is_err());
+
+        // certification test
+        let req = api::TxTestRequest {
+            tx_power: 14,
+            mode: "CW".to_string(),
+            frequency: 868100000,
+            ..Default::default()
+        };
+        assert!(validate(&req).is_ok());
+        assert!(validate(&api::TxTestRequest {
+            frequency: 0,
+            ..req.clone()
+        })
+        .is_err());
+        assert!(validate(&api::TxTestRequest {
+            mode: "FOO".to_string(),
+            ..req.clone()
+        })
+        .is_err());
+        assert!(validate(&api::TxTestRequest {
+            stop: true,
+            ..Default::default()
+        })
+        .is_ok());
+    }
+
+    #[test]
+    fn test_get_schedule() {
+        assert_eq!(
+            vec![868100000, 868300000, 868100000, 868300000],
+            get_schedule(&[868100000, 868300000], 2)
+        );
+    }
+}
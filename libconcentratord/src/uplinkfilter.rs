@@ -0,0 +1,848 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use chirpstack_api::gw;
+use log::info;
+
+use super::stats;
+
+// MHDR (1 byte) + JoinEUI (8 bytes) + DevEUI (8 bytes) + DevNonce (2 bytes) + MIC (4 bytes).
+const JOIN_REQUEST_SIZE: usize = 23;
+
+// MHDR (1 byte) + FHDR (min. 7 bytes) + MIC (4 bytes).
+const MIN_DATA_SIZE: usize = 12;
+
+// Number of NwkID bits of the DevAddr, per NetID type.
+const NWK_ID_BITS: [u32; 8] = [6, 6, 9, 11, 12, 13, 15, 17];
+
+lazy_static! {
+    static ref FILTER: Mutex<Configuration> = Mutex::new(Default::default());
+}
+
+/// LoRaWAN NetID (24 bits), encoded as hex string (e.g. 000013).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct NetId(u32);
+
+impl NetId {
+    // Returns true when the DevAddr belongs to this NetID. For NetID types 3 - 7, the DevAddr
+    // only contains the least significant bits of the NetID.
+    fn matches(&self, dev_addr: u32) -> bool {
+        let net_id_type = self.0 >> 21;
+        if get_dev_addr_type(dev_addr) != Some(net_id_type) {
+            return false;
+        }
+
+        let bits = NWK_ID_BITS[net_id_type as usize];
+        let nwk_id = (dev_addr >> (32 - (net_id_type + 1) - bits)) & ((1 << bits) - 1);
+        nwk_id == self.0 & ((1 << bits) - 1)
+    }
+}
+
+impl fmt::Display for NetId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:06x}", self.0)
+    }
+}
+
+impl FromStr for NetId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let b = hex::decode(s)?;
+        if b.len() != 3 {
+            return Err(anyhow!("net_id must be exactly 3 bytes: {}", s));
+        }
+
+        Ok(NetId(u32::from_be_bytes([0, b[0], b[1], b[2]])))
+    }
+}
+
+/// Inclusive range of JoinEUIs, encoded as start-end (e.g.
+/// 70b3d50000000000-70b3d5ffffffffff) or as single JoinEUI.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct JoinEuiRange {
+    start: u64,
+    end: u64,
+}
+
+impl JoinEuiRange {
+    fn contains(&self, join_eui: u64) -> bool {
+        join_eui >= self.start && join_eui <= self.end
+    }
+}
+
+impl fmt::Display for JoinEuiRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x}-{:016x}", self.start, self.end)
+    }
+}
+
+impl FromStr for JoinEuiRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (start, end) = match s.split_once('-') {
+            Some((start, end)) => (decode_eui(start)?, decode_eui(end)?),
+            None => (decode_eui(s)?, decode_eui(s)?),
+        };
+
+        if end < start {
+            return Err(anyhow!("join_eui range end is before start: {}", s));
+        }
+
+        Ok(JoinEuiRange { start, end })
+    }
+}
+
+/// DevAddr prefix, encoded as DevAddr/bits (e.g. 26000000/7).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DevAddrPrefix {
+    prefix: u32,
+    bits: u32,
+}
+
+impl DevAddrPrefix {
+    fn matches(&self, dev_addr: u32) -> bool {
+        self.bits == 0 || (dev_addr ^ self.prefix) >> (32 - self.bits) == 0
+    }
+}
+
+impl fmt::Display for DevAddrPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:08x}/{}", self.prefix, self.bits)
+    }
+}
+
+impl FromStr for DevAddrPrefix {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (prefix, bits) = match s.split_once('/') {
+            Some((prefix, bits)) => (prefix, bits.parse()?),
+            None => (s, 32),
+        };
+
+        let b = hex::decode(prefix)?;
+        if b.len() != 4 {
+            return Err(anyhow!("dev_addr must be exactly 4 bytes: {}", s));
+        }
+        if bits > 32 {
+            return Err(anyhow!("dev_addr prefix must be at most 32 bits: {}", s));
+        }
+
+        Ok(DevAddrPrefix {
+            prefix: u32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+            bits,
+        })
+    }
+}
+
+/// Modulation of an uplink, used as key of the publish thresholds.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Modulation {
+    LoRa,
+    Fsk,
+    LrFhss,
+}
+
+impl fmt::Display for Modulation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Modulation::LoRa => write!(f, "LORA"),
+            Modulation::Fsk => write!(f, "FSK"),
+            Modulation::LrFhss => write!(f, "LR_FHSS"),
+        }
+    }
+}
+
+impl FromStr for Modulation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "LORA" => Modulation::LoRa,
+            "FSK" => Modulation::Fsk,
+            "LR_FHSS" => Modulation::LrFhss,
+            _ => return Err(anyhow!("unexpected modulation: {}", s)),
+        })
+    }
+}
+
+/// Publish threshold of a modulation. Frames with a lower SNR (dB) or RSSI (dBm) are counted,
+/// but not published.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Threshold {
+    pub min_snr: f32,
+    pub min_rssi: i32,
+}
+
+/// Action of a filter of the filter chain.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    // The frames to which the filter applies, but which do not match the filter, are dropped.
+    Allow,
+    // The frames matching the filter are dropped.
+    Deny,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Action::Allow => write!(f, "ALLOW"),
+            Action::Deny => write!(f, "DENY"),
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "ALLOW" => Action::Allow,
+            "DENY" => Action::Deny,
+            _ => return Err(anyhow!("unexpected action: {}", s)),
+        })
+    }
+}
+
+/// Filter of the filter chain.
+#[derive(Clone, Debug)]
+pub enum Kind {
+    // Matches the data frames of which the DevAddr belongs to one of the NetIDs.
+    NetId(Vec<NetId>),
+    // Matches the data frames of which the DevAddr has one of the prefixes.
+    DevAddr(Vec<DevAddrPrefix>),
+    // Matches the join-requests of which the JoinEUI is within one of the ranges.
+    JoinEui(Vec<JoinEuiRange>),
+    // Matches the frames of which the PHYPayload size is within the range. The max. is not
+    // used when 0.
+    Size {
+        min: usize,
+        max: usize,
+    },
+    // Matches the frames with at least the given SNR (dB). When set, this only applies to the
+    // frames of the given modulation.
+    Snr {
+        modulation: Option<Modulation>,
+        min: f32,
+    },
+    // Matches the frames with at least the given RSSI (dBm). When set, this only applies to
+    // the frames of the given modulation.
+    Rssi {
+        modulation: Option<Modulation>,
+        min: i32,
+    },
+    // Matches the frames of which the same PHYPayload was received within the window, e.g.
+    // the same frame received on overlapping channels.
+    Dedup {
+        window: Duration,
+    },
+}
+
+/// Filter chain entry, as read from the configuration. The used fields depend on the filter
+/// (NET_ID, DEV_ADDR and JOIN_EUI use the values, SIZE, SNR and RSSI the min. and max., DEDUP
+/// the window).
+#[derive(Clone, Default)]
+pub struct FilterConfig {
+    pub name: String,
+    pub filter: String,
+    pub action: String,
+    pub values: Vec<String>,
+    pub modulation: String,
+    pub min: f64,
+    pub max: f64,
+    pub window: Duration,
+}
+
+/// Named filter of the filter chain. The frames dropped by the filter are counted using the
+/// uplink_filtered_<name> stats counter.
+#[derive(Clone, Debug)]
+pub struct Filter {
+    name: String,
+    kind: Kind,
+    action: Action,
+    // Hash of the PHYPayload -> time of the first reception, used by the Dedup filter.
+    seen: HashMap<u64, Instant>,
+}
+
+impl Filter {
+    pub fn new(name: &str, kind: Kind, action: Action) -> Filter {
+        Filter {
+            name: name.to_string(),
+            kind,
+            action,
+            seen: HashMap::new(),
+        }
+    }
+
+    pub fn from_config(conf: &FilterConfig) -> Result<Filter> {
+        if conf.name.is_empty() {
+            return Err(anyhow!("filter name must not be empty"));
+        }
+
+        let modulation = match conf.modulation.as_ref() {
+            "" => None,
+            _ => Some(conf.modulation.parse()?),
+        };
+
+        let kind = match conf.filter.as_ref() {
+            "NET_ID" => Kind::NetId(parse_list(&conf.values)?),
+            "DEV_ADDR" => Kind::DevAddr(parse_list(&conf.values)?),
+            "JOIN_EUI" => Kind::JoinEui(parse_list(&conf.values)?),
+            "SIZE" => Kind::Size {
+                min: conf.min as usize,
+                max: conf.max as usize,
+            },
+            "SNR" => Kind::Snr {
+                modulation,
+                min: conf.min as f32,
+            },
+            "RSSI" => Kind::Rssi {
+                modulation,
+                min: conf.min as i32,
+            },
+            "DEDUP" => Kind::Dedup {
+                window: conf.window,
+            },
+            _ => return Err(anyhow!("unexpected filter: {}", conf.filter)),
+        };
+
+        Ok(Filter::new(&conf.name, kind, conf.action.parse()?))
+    }
+
+    fn counter(&self) -> String {
+        format!("uplink_filtered_{}", self.name)
+    }
+
+    // Returns true when the frame must be dropped by this filter.
+    fn drops(&mut self, pl: &gw::UplinkFrame, now: Instant) -> bool {
+        match (self.action, self.matches(pl, now)) {
+            (_, None) => false,
+            (Action::Allow, Some(matches)) => !matches,
+            (Action::Deny, Some(matches)) => matches,
+        }
+    }
+
+    // Returns None when the filter does not apply to the frame (e.g. a NetID filter and a
+    // join-request), else if the frame matches the filter.
+    fn matches(&mut self, pl: &gw::UplinkFrame, now: Instant) -> Option<bool> {
+        let rx_info = pl.rx_info.as_ref();
+        let applies = |modulation: &Option<Modulation>| {
+            modulation.is_none() || get_modulation(pl) == *modulation
+        };
+
+        match &self.kind {
+            Kind::NetId(v) => {
+                get_dev_addr(&pl.phy_payload).map(|dev_addr| v.iter().any(|v| v.matches(dev_addr)))
+            }
+            Kind::DevAddr(v) => {
+                get_dev_addr(&pl.phy_payload).map(|dev_addr| v.iter().any(|v| v.matches(dev_addr)))
+            }
+            Kind::JoinEui(v) => {
+                get_join_eui(&pl.phy_payload).map(|join_eui| v.iter().any(|v| v.contains(join_eui)))
+            }
+            Kind::Size { min, max } => {
+                let size = pl.phy_payload.len();
+                Some(size >= *min && (*max == 0 || size <= *max))
+            }
+            Kind::Snr { modulation, min } if applies(modulation) => rx_info.map(|v| v.snr >= *min),
+            Kind::Rssi { modulation, min } if applies(modulation) => {
+                rx_info.map(|v| v.rssi >= *min)
+            }
+            Kind::Snr { .. } | Kind::Rssi { .. } => None,
+            Kind::Dedup { window } => {
+                let window = *window;
+                self.seen
+                    .retain(|_, received| now.duration_since(*received) < window);
+
+                let mut hasher = DefaultHasher::new();
+                pl.phy_payload.hash(&mut hasher);
+                let hash = hasher.finish();
+
+                if self.seen.contains_key(&hash) {
+                    return Some(true);
+                }
+                self.seen.insert(hash, now);
+                Some(false)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Configuration {
+    // When not empty, only the data frames of these NetIDs are forwarded.
+    pub net_id_allow: Vec<NetId>,
+    // The data frames of these NetIDs are not forwarded.
+    pub net_id_deny: Vec<NetId>,
+    // When not empty, only the join-requests within these ranges are forwarded.
+    pub join_eui_allow: Vec<JoinEuiRange>,
+    // The join-requests within these ranges are not forwarded.
+    pub join_eui_deny: Vec<JoinEuiRange>,
+    // Publish thresholds per modulation.
+    pub thresholds: HashMap<Modulation, Threshold>,
+    // Ordered filter chain, this is applied after the above lists and thresholds.
+    pub chain: Vec<Filter>,
+    // When set, the frames are annotated with the name of the filter by which they would have
+    // been dropped (uplink_filter meta-data) instead of being dropped.
+    pub debug: bool,
+}
+
+impl Configuration {
+    fn is_empty(&self) -> bool {
+        self.net_id_allow.is_empty()
+            && self.net_id_deny.is_empty()
+            && self.join_eui_allow.is_empty()
+            && self.join_eui_deny.is_empty()
+            && self.thresholds.is_empty()
+            && self.chain.is_empty()
+    }
+
+    // Returns the name and stats counter of the filter by which the frame is dropped, None
+    // when the frame passes all filters. The lists and thresholds are named after their
+    // configuration.
+    fn evaluate(&mut self, pl: &gw::UplinkFrame, now: Instant) -> Option<(String, String)> {
+        if let Some(counter) = self.below_threshold(pl) {
+            return Some(("thresholds".to_string(), counter.to_string()));
+        }
+
+        if let Some(dev_addr) = get_dev_addr(&pl.phy_payload) {
+            if !self.allow_net_id(dev_addr) {
+                return Some(("net_id".to_string(), "uplink_filtered_net_id".to_string()));
+            }
+        }
+
+        if let Some(join_eui) = get_join_eui(&pl.phy_payload) {
+            if !self.allow_join_eui(join_eui) {
+                return Some((
+                    "join_eui".to_string(),
+                    "uplink_filtered_join_eui".to_string(),
+                ));
+            }
+        }
+
+        self.chain
+            .iter_mut()
+            .find(|v| v.drops(pl, now))
+            .map(|v| (v.name.clone(), v.counter()))
+    }
+
+    // Returns the stats counter of the threshold that is not met, None when the frame meets
+    // the threshold of its modulation (or there is no threshold).
+    fn below_threshold(&self, pl: &gw::UplinkFrame) -> Option<&'static str> {
+        let threshold = get_modulation(pl).and_then(|v| self.thresholds.get(&v))?;
+        let rx_info = pl.rx_info.as_ref()?;
+
+        if rx_info.snr < threshold.min_snr {
+            return Some("uplink_suppressed_snr");
+        }
+        if rx_info.rssi < threshold.min_rssi {
+            return Some("uplink_suppressed_rssi");
+        }
+
+        None
+    }
+
+    fn allow_net_id(&self, dev_addr: u32) -> bool {
+        (self.net_id_allow.is_empty() || self.net_id_allow.iter().any(|v| v.matches(dev_addr)))
+            && !self.net_id_deny.iter().any(|v| v.matches(dev_addr))
+    }
+
+    fn allow_join_eui(&self, join_eui: u64) -> bool {
+        (self.join_eui_allow.is_empty() || self.join_eui_allow.iter().any(|v| v.contains(join_eui)))
+            && !self.join_eui_deny.iter().any(|v| v.contains(join_eui))
+    }
+}
+
+/// Parses the given list of NetIDs or JoinEUI ranges.
+pub fn parse_list<T: FromStr<Err = anyhow::Error>>(values: &[String]) -> Result<Vec<T>> {
+    values.iter().map(|v| v.parse()).collect()
+}
+
+/// Parses the given filter chain. The filter names must be unique.
+pub fn parse_chain(values: &[FilterConfig]) -> Result<Vec<Filter>> {
+    let mut names: HashSet<&str> = HashSet::new();
+    for v in values {
+        if !names.insert(v.name.as_str()) {
+            return Err(anyhow!("duplicate filter name: {}", v.name));
+        }
+    }
+
+    values.iter().map(Filter::from_config).collect()
+}
+
+/// Outcome of the uplink filter.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Outcome {
+    Forward,
+    Drop,
+    // Debug mode, the frame is forwarded but annotated with the name of the filter by which
+    // it would have been dropped.
+    Annotate(String),
+}
+
+/// Set the uplink filter configuration. When all lists are empty, all uplinks are forwarded.
+pub fn setup(conf: Configuration) {
+    if !conf.is_empty() {
+        info!(
+            "Setting up uplink filter, net_id_allow: {:?}, net_id_deny: {:?}, join_eui_allow: {:?}, join_eui_deny: {:?}, thresholds: {:?}, chain: {:?}, debug: {}",
+            conf.net_id_allow.iter().map(|v| v.to_string()).collect::<Vec<String>>(),
+            conf.net_id_deny.iter().map(|v| v.to_string()).collect::<Vec<String>>(),
+            conf.join_eui_allow.iter().map(|v| v.to_string()).collect::<Vec<String>>(),
+            conf.join_eui_deny.iter().map(|v| v.to_string()).collect::<Vec<String>>(),
+            conf.thresholds
+                .iter()
+                .map(|(k, v)| format!("{}: min_snr={}, min_rssi={}", k, v.min_snr, v.min_rssi))
+                .collect::<Vec<String>>(),
+            conf.chain
+                .iter()
+                .map(|v| format!("{}: {} {:?}", v.name, v.action, v.kind))
+                .collect::<Vec<String>>(),
+            conf.debug,
+        );
+    }
+
+    *FILTER.lock().unwrap() = conf;
+}
+
+/// Applies the uplink filter to the given uplink. Frames below the publish threshold of their
+/// modulation are dropped, the data frames are filtered by the NetID of their DevAddr and the
+/// join-requests by their JoinEUI. Then the filter chain is applied in order, the frame is
+/// dropped by the first filter that does not allow it.
+pub fn apply(pl: &gw::UplinkFrame) -> Outcome {
+    let mut filter = FILTER.lock().unwrap();
+    if filter.is_empty() {
+        return Outcome::Forward;
+    }
+
+    match filter.evaluate(pl, Instant::now()) {
+        None => Outcome::Forward,
+        Some((name, counter)) => {
+            stats::inc_counter(&counter);
+            if filter.debug {
+                Outcome::Annotate(name)
+            } else {
+                Outcome::Drop
+            }
+        }
+    }
+}
+
+/// Returns a copy of the uplink, annotated with the name of the filter (uplink_filter
+/// meta-data).
+pub fn annotate(pl: &gw::UplinkFrame, name: &str) -> gw::UplinkFrame {
+    let mut pl = pl.clone();
+    if let Some(rx_info) = pl.rx_info.as_mut() {
+        rx_info
+            .metadata
+            .insert("uplink_filter".to_string(), name.to_string());
+    }
+    pl
+}
+
+fn get_modulation(pl: &gw::UplinkFrame) -> Option<Modulation> {
+    let params = pl
+        .tx_info
+        .as_ref()?
+        .modulation
+        .as_ref()?
+        .parameters
+        .as_ref()?;
+    Some(match params {
+        gw::modulation::Parameters::Lora(_) => Modulation::LoRa,
+        gw::modulation::Parameters::Fsk(_) => Modulation::Fsk,
+        gw::modulation::Parameters::LrFhss(_) => Modulation::LrFhss,
+    })
+}
+
+// Returns the DevAddr of the given PHYPayload, None when this is not an uplink data frame.
+fn get_dev_addr(phy_payload: &[u8]) -> Option<u32> {
+    // unconfirmed or confirmed data up
+    if phy_payload.len() < MIN_DATA_SIZE || !matches!(phy_payload[0] >> 5, 2 | 4) {
+        return None;
+    }
+
+    // The DevAddr is encoded little endian.
+    Some(u32::from_le_bytes([
+        phy_payload[1],
+        phy_payload[2],
+        phy_payload[3],
+        phy_payload[4],
+    ]))
+}
+
+// Returns the JoinEUI of the given PHYPayload, None when this is not a join-request.
+fn get_join_eui(phy_payload: &[u8]) -> Option<u64> {
+    if phy_payload.len() != JOIN_REQUEST_SIZE || phy_payload[0] >> 5 != 0 {
+        return None;
+    }
+
+    // The JoinEUI is encoded little endian.
+    let mut b: [u8; 8] = [0; 8];
+    b.copy_from_slice(&phy_payload[1..9]);
+    Some(u64::from_le_bytes(b))
+}
+
+// Returns the type of the DevAddr (the number of leading 1 bits), None for the reserved
+// prefix.
+fn get_dev_addr_type(dev_addr: u32) -> Option<u32> {
+    match dev_addr.leading_ones() {
+        v if v < 8 => Some(v),
+        _ => None,
+    }
+}
+
+fn decode_eui(s: &str) -> Result<u64> {
+    let b = hex::decode(s)?;
+    if b.len() != 8 {
+        return Err(anyhow!("join_eui must be exactly 8 bytes: {}", s));
+    }
+
+    let mut eui: [u8; 8] = [0; 8];
+    eui.copy_from_slice(&b);
+    Ok(u64::from_be_bytes(eui))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_net_id_matches() {
+        // type 0, NwkID 0x13
+        let net_id = NetId::from_str("000013").unwrap();
+        assert!(net_id.matches(0x26012345));
+        assert!(!net_id.matches(0x28012345));
+
+        // type 3, NwkID 11 LSB
+        let net_id = NetId::from_str("600002").unwrap();
+        assert!(net_id.matches(0xe0040000 | 0x1234));
+        assert!(!net_id.matches(0x26012345));
+
+        // type 7, NwkID 17 LSB
+        let net_id = NetId::from_str("e00001").unwrap();
+        assert!(net_id.matches(0xfe000080 | 0x12));
+
+        assert!(NetId::from_str("0013").is_err());
+    }
+
+    #[test]
+    fn test_join_eui_range() {
+        let range = JoinEuiRange::from_str("70b3d50000000000-70b3d5ffffffffff").unwrap();
+        assert!(range.contains(0x70b3d57ed0000000));
+        assert!(!range.contains(0x70b3d60000000000));
+
+        let range = JoinEuiRange::from_str("0102030405060708").unwrap();
+        assert!(range.contains(0x0102030405060708));
+        assert!(!range.contains(0x0102030405060709));
+
+        assert!(JoinEuiRange::from_str("0000000000000002-0000000000000001").is_err());
+        assert!(JoinEuiRange::from_str("01020304").is_err());
+    }
+
+    #[test]
+    fn test_configuration() {
+        let conf = Configuration {
+            net_id_deny: vec![NetId::from_str("000013").unwrap()],
+            join_eui_allow: vec![
+                JoinEuiRange::from_str("70b3d50000000000-70b3d5ffffffffff").unwrap()
+            ],
+            ..Default::default()
+        };
+
+        assert!(!conf.allow_net_id(0x26012345));
+        assert!(conf.allow_net_id(0x28012345));
+        assert!(conf.allow_join_eui(0x70b3d57ed0000000));
+        assert!(!conf.allow_join_eui(0x0102030405060708));
+    }
+
+    #[test]
+    fn test_below_threshold() {
+        let mut conf: Configuration = Default::default();
+        conf.thresholds.insert(
+            Modulation::Fsk,
+            Threshold {
+                min_snr: 0.0,
+                min_rssi: -110,
+            },
+        );
+
+        let frame = |fsk: bool, rssi: i32, snr: f32| gw::UplinkFrame {
+            tx_info: Some(gw::UplinkTxInfo {
+                modulation: Some(gw::Modulation {
+                    parameters: Some(if fsk {
+                        gw::modulation::Parameters::Fsk(Default::default())
+                    } else {
+                        gw::modulation::Parameters::Lora(Default::default())
+                    }),
+                }),
+                ..Default::default()
+            }),
+            rx_info: Some(gw::UplinkRxInfo {
+                rssi,
+                snr,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        assert_eq!(None, conf.below_threshold(&frame(true, -100, 5.0)));
+        assert_eq!(
+            Some("uplink_suppressed_snr"),
+            conf.below_threshold(&frame(true, -100, -1.0))
+        );
+        assert_eq!(
+            Some("uplink_suppressed_rssi"),
+            conf.below_threshold(&frame(true, -120, 5.0))
+        );
+        // no threshold for LoRa
+        assert_eq!(None, conf.below_threshold(&frame(false, -120, -10.0)));
+
+        assert_eq!(Modulation::LrFhss, Modulation::from_str("LR_FHSS").unwrap());
+        assert!(Modulation::from_str("GFSK").is_err());
+    }
+
+    #[test]
+    fn test_dev_addr_prefix() {
+        let prefix = DevAddrPrefix::from_str("26000000/7").unwrap();
+        assert!(prefix.matches(0x26012345));
+        assert!(prefix.matches(0x27012345));
+        assert!(!prefix.matches(0x28012345));
+
+        let prefix = DevAddrPrefix::from_str("26012345").unwrap();
+        assert!(prefix.matches(0x26012345));
+        assert!(!prefix.matches(0x26012346));
+
+        assert!(DevAddrPrefix::from_str("00000000/0")
+            .unwrap()
+            .matches(0xffffffff));
+        assert!(DevAddrPrefix::from_str("26000000/33").is_err());
+        assert!(DevAddrPrefix::from_str("2600").is_err());
+    }
+
+    #[test]
+    fn test_chain() {
+        let mut data = vec![0x40, 0x45, 0x23, 0x01, 0x26];
+        data.extend_from_slice(&[0; 7]);
+        let frame = |phy_payload: &[u8]| gw::UplinkFrame {
+            phy_payload: phy_payload.to_vec(),
+            rx_info: Some(Default::default()),
+            ..Default::default()
+        };
+
+        let mut conf = Configuration {
+            chain: parse_chain(&[
+                FilterConfig {
+                    name: "own_devices".to_string(),
+                    filter: "DEV_ADDR".to_string(),
+                    action: "ALLOW".to_string(),
+                    values: vec!["26000000/7".to_string()],
+                    ..Default::default()
+                },
+                FilterConfig {
+                    name: "max_size".to_string(),
+                    filter: "SIZE".to_string(),
+                    action: "ALLOW".to_string(),
+                    max: 20.0,
+                    ..Default::default()
+                },
+                FilterConfig {
+                    name: "duplicates".to_string(),
+                    filter: "DEDUP".to_string(),
+                    action: "DENY".to_string(),
+                    window: Duration::from_secs(1),
+                    ..Default::default()
+                },
+            ])
+            .unwrap(),
+            ..Default::default()
+        };
+
+        let now = Instant::now();
+        assert_eq!(None, conf.evaluate(&frame(&data), now));
+        assert_eq!(
+            Some((
+                "duplicates".to_string(),
+                "uplink_filtered_duplicates".to_string()
+            )),
+            conf.evaluate(&frame(&data), now + Duration::from_millis(500))
+        );
+        // the window has passed
+        assert_eq!(
+            None,
+            conf.evaluate(&frame(&data), now + Duration::from_secs(2))
+        );
+
+        // the DevAddr filter does not apply to a join-request
+        let mut join_request = vec![0x00];
+        join_request.extend_from_slice(&[0; 22]);
+        assert_eq!(None, conf.evaluate(&frame(&join_request), now));
+
+        // other DevAddr
+        data[4] = 0x28;
+        assert_eq!(
+            Some((
+                "own_devices".to_string(),
+                "uplink_filtered_own_devices".to_string()
+            )),
+            conf.evaluate(&frame(&data), now)
+        );
+
+        // size exceeds max
+        assert_eq!(
+            Some((
+                "max_size".to_string(),
+                "uplink_filtered_max_size".to_string()
+            )),
+            conf.evaluate(&frame(&[0xe0; 21]), now)
+        );
+
+        let duplicate = FilterConfig {
+            name: "size".to_string(),
+            filter: "SIZE".to_string(),
+            action: "ALLOW".to_string(),
+            ..Default::default()
+        };
+        assert!(parse_chain(&[duplicate.clone(), duplicate]).is_err());
+        assert!(Filter::from_config(&FilterConfig {
+            name: "size".to_string(),
+            filter: "SIZE".to_string(),
+            action: "DROP".to_string(),
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_annotate() {
+        let pl = annotate(
+            &gw::UplinkFrame {
+                rx_info: Some(Default::default()),
+                ..Default::default()
+            },
+            "max_size",
+        );
+        assert_eq!(
+            Some(&"max_size".to_string()),
+            pl.rx_info.unwrap().metadata.get("uplink_filter")
+        );
+    }
+
+    #[test]
+    fn test_get_dev_addr() {
+        let mut phy_payload = vec![0x40, 0x45, 0x23, 0x01, 0x26];
+        phy_payload.extend_from_slice(&[0; 7]);
+        assert_eq!(Some(0x26012345), get_dev_addr(&phy_payload));
+
+        // join-request
+        phy_payload[0] = 0x00;
+        assert_eq!(None, get_dev_addr(&phy_payload));
+    }
+}
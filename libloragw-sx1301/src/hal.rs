@@ -1,5 +1,6 @@
 use std::convert::TryInto;
 use std::ffi::CStr;
+use std::fmt;
 use std::time::Duration;
 
 use anyhow::Result;
@@ -382,6 +383,72 @@ impl TxGainConfig {
     }
 }
 
+/// Structure containing a Listen-Before-Talk channel configuration.
+#[derive(Clone)]
+pub struct LBTChannelConfig {
+    /// LBT channel frequency.
+    pub freq_hz: u32,
+    /// LBT channel carrier sense time (128 or 5000 us).
+    pub scan_time_us: u16,
+}
+
+impl LBTChannelConfig {
+    fn to_hal(&self) -> wrapper::lgw_conf_lbt_chan_s {
+        wrapper::lgw_conf_lbt_chan_s {
+            freq_hz: self.freq_hz,
+            scan_time_us: self.scan_time_us,
+        }
+    }
+}
+
+/// Configuration structure for listen-before-talk.
+#[derive(Clone)]
+pub struct LBTConfig {
+    /// Enable or disable LBT.
+    pub enable: bool,
+    /// RSSI threshold to detect if channel is busy or not (dBm).
+    pub rssi_target: i8,
+    /// RSSI offset to be applied to the SX127x RSSI values of the LBT FPGA.
+    pub rssi_offset: i8,
+    /// LBT channels configuration.
+    pub channels: Vec<LBTChannelConfig>,
+}
+
+impl LBTConfig {
+    fn to_hal(&self) -> Result<wrapper::lgw_conf_lbt_s> {
+        if self.channels.len() > wrapper::LBT_CHANNEL_FREQ_NB as usize {
+            return Err(anyhow!("channels exceeds LBT_CHANNEL_FREQ_NB"));
+        }
+
+        let mut conf = wrapper::lgw_conf_lbt_s {
+            enable: self.enable,
+            rssi_target: self.rssi_target,
+            rssi_offset: self.rssi_offset,
+            nb_channel: self.channels.len() as u8,
+            ..Default::default()
+        };
+
+        for (i, c) in self.channels.iter().enumerate() {
+            conf.channels[i] = c.to_hal();
+        }
+
+        Ok(conf)
+    }
+}
+
+/// Error returned by send when the transmission was not allowed by listen-before-talk, e.g.
+/// because the channel was busy.
+#[derive(Debug)]
+pub struct LBTError;
+
+impl fmt::Display for LBTError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "lgw_send failed, tx not allowed by lbt")
+    }
+}
+
+impl std::error::Error for LBTError {}
+
 /// Structure containing the metadata of a packet that was received and a pointer to the payload.
 pub struct RxPacket {
     /// Central frequency of the IF chain.
@@ -590,6 +657,20 @@ pub fn txgain_setconf(txgain: &[TxGainConfig]) -> Result<()> {
     Ok(())
 }
 
+/// Configure the listen-before-talk (must configure before start). This requires a
+/// concentrator FPGA image with LBT support.
+pub fn lbt_setconf(conf: &LBTConfig) -> Result<()> {
+    let conf = conf.to_hal()?;
+
+    let _guard = mutex::CONCENTATOR.lock().unwrap();
+    let ret = unsafe { wrapper::lgw_lbt_setconf(conf) };
+    if ret != 0 {
+        return Err(anyhow!("lgw_lbt_setconf failed"));
+    }
+
+    Ok(())
+}
+
 /// Connect to the LoRa concentrator, reset it and configure it according to previously set
 /// parameters.
 pub fn start() -> Result<()> {
@@ -641,6 +722,9 @@ pub fn send(pkt: &TxPacket) -> Result<()> {
     let pkt = pkt.to_hal();
 
     let ret = unsafe { wrapper::lgw_send(pkt) };
+    if ret == wrapper::LGW_LBT_ISSUE as i32 {
+        return Err(LBTError.into());
+    }
     if ret != 0 {
         return Err(anyhow!("lgw_send failed"));
     }
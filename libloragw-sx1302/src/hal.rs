@@ -2,12 +2,18 @@ use std::convert::TryInto;
 use std::ffi::{CStr, CString};
 use std::mem::transmute;
 use std::os::raw::c_char;
+use std::sync::Mutex;
 use std::time::Duration;
 
 use anyhow::Result;
 
 use super::{mutex, wrapper};
 
+lazy_static! {
+    // Buffer for the packets fetched by receive, which is re-used by every call.
+    static ref RX_BUFFER: Mutex<Vec<wrapper::lgw_pkt_rx_s>> = Mutex::new(Vec::new());
+}
+
 // ConvertBandwidth is a trait to convert the bandwidth from / to the HAL
 // enum constants. A trait is needed as it is the only way to add methods to
 // a type alias.
@@ -84,6 +90,8 @@ impl CRC {
 #[derive(Debug, Copy, Clone)]
 pub enum Modulation {
     Undefined,
+    /// Continuous-wave (TX only).
+    CW,
     LoRa,
     FSK,
 }
@@ -92,6 +100,7 @@ impl Modulation {
     fn to_hal(&self) -> u8 {
         return match self {
             Modulation::Undefined => wrapper::MOD_UNDEFINED,
+            Modulation::CW => wrapper::MOD_CW,
             Modulation::LoRa => wrapper::MOD_LORA,
             Modulation::FSK => wrapper::MOD_FSK,
         } as u8;
@@ -99,6 +108,7 @@ impl Modulation {
 
     fn from_hal(modulation: u8) -> Self {
         match modulation as u32 {
+            wrapper::MOD_CW => Modulation::CW,
             wrapper::MOD_LORA => Modulation::LoRa,
             wrapper::MOD_FSK => Modulation::FSK,
             _ => Modulation::Undefined,
@@ -678,6 +688,35 @@ impl TimestampConfig {
     }
 }
 
+#[derive(Debug, PartialEq, Eq)]
+pub enum SpectralScanStatus {
+    None,
+    OnGoing,
+    Aborted,
+    Completed,
+    Unknown,
+}
+
+impl SpectralScanStatus {
+    fn from_hal(status: wrapper::lgw_spectral_scan_status_t) -> Self {
+        match status {
+            wrapper::lgw_spectral_scan_status_t_LGW_SPECTRAL_SCAN_STATUS_NONE => {
+                SpectralScanStatus::None
+            }
+            wrapper::lgw_spectral_scan_status_t_LGW_SPECTRAL_SCAN_STATUS_ON_GOING => {
+                SpectralScanStatus::OnGoing
+            }
+            wrapper::lgw_spectral_scan_status_t_LGW_SPECTRAL_SCAN_STATUS_ABORTED => {
+                SpectralScanStatus::Aborted
+            }
+            wrapper::lgw_spectral_scan_status_t_LGW_SPECTRAL_SCAN_STATUS_COMPLETED => {
+                SpectralScanStatus::Completed
+            }
+            _ => SpectralScanStatus::Unknown,
+        }
+    }
+}
+
 /// Structure containing a Listen-Before-Talk channel configuration.
 pub struct LBTChannelConfig {
     /// LBT channel frequency.
@@ -779,8 +818,6 @@ pub struct SpectralScanResult {
     pub result: u16,
 }
 
-const MAX_PKT: usize = 8;
-
 /// Configure the gateway board.
 pub fn board_setconf(conf: &BoardConfig) -> Result<()> {
     let mut conf = conf.to_hal()?;
@@ -903,11 +940,12 @@ pub fn get_eui() -> Result<[u8; 8]> {
 
 /// A non-blocking function that will fetch up to 'max_pkt' packets from the LoRa concentrator FIFO
 /// and data buffer.
-pub fn receive() -> Result<Vec<RxPacket>> {
-    let mut packets: [wrapper::lgw_pkt_rx_s; MAX_PKT] = [Default::default(); MAX_PKT];
+pub fn receive(max_pkt: u8) -> Result<Vec<RxPacket>> {
+    let mut packets = RX_BUFFER.lock().unwrap();
+    packets.resize(max_pkt as usize, Default::default());
 
     let _guard = mutex::CONCENTATOR.lock().unwrap();
-    let ret = unsafe { wrapper::lgw_receive(MAX_PKT.try_into().unwrap(), packets.as_mut_ptr()) };
+    let ret = unsafe { wrapper::lgw_receive(max_pkt, packets.as_mut_ptr()) };
     if ret == -1 {
         return Err(anyhow!("lgw_receive failed"));
     }
@@ -1077,3 +1115,27 @@ pub fn spectral_scan_get_results() -> Result<Vec<SpectralScanResult>> {
 
     Ok(v)
 }
+
+/// Get the status of the channel scan.
+pub fn spectral_scan_get_status() -> Result<SpectralScanStatus> {
+    let mut status: wrapper::lgw_spectral_scan_status_t = Default::default();
+
+    let _guard = mutex::CONCENTATOR.lock().unwrap();
+    let ret = unsafe { wrapper::lgw_spectral_scan_get_status(&mut status) };
+    if ret != 0 {
+        return Err(anyhow!("lgw_spectral_scan_get_status failed"));
+    }
+
+    Ok(SpectralScanStatus::from_hal(status))
+}
+
+/// Abort the channel scan.
+pub fn spectral_scan_abort() -> Result<()> {
+    let _guard = mutex::CONCENTATOR.lock().unwrap();
+    let ret = unsafe { wrapper::lgw_spectral_scan_abort() };
+    if ret != 0 {
+        return Err(anyhow!("lgw_spectral_scan_abort failed"));
+    }
+
+    Ok(())
+}